@@ -0,0 +1,93 @@
+//! Lists the entries of a directory on disk and of a compound file with the
+//! same generic code, written against a small "archive entry" trait of the
+//! kind that archive-handling code often defines.
+//!
+//! Usage: `cargo run --example archive_entry -- <directory> <compound file>`
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::time::SystemTime;
+
+//===========================================================================//
+
+/// The little bit of metadata that the listing code needs about an entry.
+trait ArchiveEntry {
+    fn file_name(&self) -> String;
+    fn is_dir(&self) -> bool;
+    fn len(&self) -> u64;
+    fn modified(&self) -> Option<SystemTime>;
+}
+
+impl ArchiveEntry for fs::DirEntry {
+    fn file_name(&self) -> String {
+        self.file_name().to_string_lossy().into_owned()
+    }
+    fn is_dir(&self) -> bool {
+        self.metadata().map(|metadata| metadata.is_dir()).unwrap_or(false)
+    }
+    fn len(&self) -> u64 {
+        self.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+    }
+    fn modified(&self) -> Option<SystemTime> {
+        self.metadata().and_then(|metadata| metadata.modified()).ok()
+    }
+}
+
+// Each method is a one-liner, since cfb::Entry mirrors the std::fs API.
+impl ArchiveEntry for cfb::Entry {
+    fn file_name(&self) -> String {
+        self.name().to_string()
+    }
+    fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+    fn len(&self) -> u64 {
+        self.metadata().len()
+    }
+    fn modified(&self) -> Option<SystemTime> {
+        self.metadata().modified().ok()
+    }
+}
+
+/// Generic code that works with any kind of entry.
+fn print_listing<E: ArchiveEntry>(entries: impl IntoIterator<Item = E>) {
+    for entry in entries {
+        let age = entry
+            .modified()
+            .and_then(|time| SystemTime::now().duration_since(time).ok())
+            .map(|age| format!("{}s ago", age.as_secs()))
+            .unwrap_or_else(|| "-".to_string());
+        let name = entry.file_name();
+        if entry.is_dir() {
+            println!("{:>10}  {:>16}  {}/", "", age, name);
+        } else {
+            println!("{:>10}  {:>16}  {}", entry.len(), age, name);
+        }
+    }
+}
+
+//===========================================================================//
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [dir_path, cfb_path] = match <[String; 2]>::try_from(args) {
+        Ok(paths) => paths,
+        Err(_) => {
+            eprintln!("Usage: archive_entry <directory> <compound file>");
+            std::process::exit(1);
+        }
+    };
+
+    println!("{}:", dir_path);
+    let dir_entries =
+        fs::read_dir(&dir_path)?.collect::<io::Result<Vec<_>>>()?;
+    print_listing(dir_entries);
+
+    println!("{}:", cfb_path);
+    let comp = cfb::open(&cfb_path)?;
+    print_listing(comp.read_root_storage());
+    Ok(())
+}
+
+//===========================================================================//
@@ -0,0 +1,322 @@
+//! Serves the streams of a compound file from several reader threads while
+//! a writer thread updates them once a second and a backup thread copies
+//! the whole file every few seconds, all sharing one `CompoundFile` behind
+//! an `RwLock`.
+//!
+//! Readers and backups only need a shared reference to the compound file
+//! (through `read_stream_at` and `export_storage_as_cfb`), so they take the
+//! read lock and run alongside each other.  The writer takes the write lock
+//! for a whole batch of updates, and drops its stream handles and flushes
+//! before letting go, so nobody else ever sees a half-applied batch.
+//!
+//! Each batch writes its batch number throughout every stream.  With
+//! `--check`, every read and every backup asserts that all of the streams
+//! held a single batch (no torn state), and that batches never went
+//! backwards (no stale reads).  The file is created (or overwritten), and
+//! backups are written next to it, with `.backup` appended to its name.
+//!
+//! Usage: `cargo run --example server -- [--check] [--seconds <n>]
+//! [--backup-every <n>] <compound file>`
+
+use cfb::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//===========================================================================//
+
+/// The streams served, and their lengths.  The first two are short enough
+/// to live in the mini stream.
+const STREAMS: [(&str, usize); 4] = [
+    ("/small", 64),
+    ("/medium", 2_000),
+    ("/storage/large", 10_000),
+    ("/storage/huge", 100_000),
+];
+
+const NUM_READERS: usize = 4;
+
+struct Options {
+    check: bool,
+    seconds: u64,
+    backup_every: u64,
+    path: PathBuf,
+}
+
+#[derive(Default)]
+struct Stats {
+    batches: u64,
+    reads: u64,
+    bytes_read: u64,
+    backups: u64,
+}
+
+//===========================================================================//
+
+fn main() -> io::Result<()> {
+    let options = parse_args();
+    let comp = RwLock::new(create(&options.path)?);
+    let stop = AtomicBool::new(false);
+    let mut backup_path = options.path.clone().into_os_string();
+    backup_path.push(".backup");
+    let backup_path = PathBuf::from(backup_path);
+
+    let stats = thread::scope(|scope| -> io::Result<Stats> {
+        let readers: Vec<_> = (0..NUM_READERS)
+            .map(|_| scope.spawn(|| reader(&comp, &stop, options.check)))
+            .collect();
+        let writer = scope.spawn(|| writer(&comp, &stop));
+        let backups =
+            scope.spawn(|| backup(&comp, &stop, &options, &backup_path));
+        thread::sleep(Duration::from_secs(options.seconds));
+        stop.store(true, Ordering::SeqCst);
+
+        let mut stats = Stats {
+            batches: writer.join().expect("writer panicked")?,
+            backups: backups.join().expect("backup thread panicked")?,
+            ..Stats::default()
+        };
+        for reader in readers {
+            let (reads, bytes_read) =
+                reader.join().expect("reader panicked")?;
+            stats.reads += reads;
+            stats.bytes_read += bytes_read;
+        }
+        Ok(stats)
+    })?;
+
+    // Once everyone else is done, the file must hold the last batch.
+    let mut comp = comp.into_inner().expect("lock poisoned");
+    comp.flush()?;
+    if options.check {
+        let comp = cfb::open(&options.path)?;
+        assert_eq!(read_consistent(&comp)?.0, stats.batches);
+    }
+    println!(
+        "{} batches written, {} reads ({} bytes) served by {} readers, {} \
+         backups taken{}",
+        stats.batches,
+        stats.reads,
+        stats.bytes_read,
+        NUM_READERS,
+        stats.backups,
+        if options.check { ", all consistent" } else { "" }
+    );
+    Ok(())
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        check: false,
+        seconds: 10,
+        backup_every: 3,
+        path: PathBuf::new(),
+    };
+    let mut args = std::env::args().skip(1);
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => options.check = true,
+            "--seconds" => options.seconds = number(args.next()),
+            "--backup-every" => options.backup_every = number(args.next()),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 1 || options.backup_every == 0 {
+        usage();
+    }
+    options.path = PathBuf::from(paths.remove(0));
+    options
+}
+
+fn number(arg: Option<String>) -> u64 {
+    arg.and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: server [--check] [--seconds <n>] [--backup-every <n>] \
+         <compound file>"
+    );
+    std::process::exit(1);
+}
+
+//===========================================================================//
+
+/// Creates the compound file, with every stream holding batch zero.
+fn create(path: &Path) -> io::Result<CompoundFile<File>> {
+    let mut comp = cfb::create(path)?;
+    comp.create_storage("/storage")?;
+    for (path, len) in STREAMS {
+        comp.create_stream(path)?.write_all(&batch_data(0, len))?;
+    }
+    comp.flush()?;
+    Ok(comp)
+}
+
+/// Returns the contents of a stream of the given length for a batch: the
+/// batch number, over and over.
+fn batch_data(batch: u64, len: usize) -> Vec<u8> {
+    batch.to_le_bytes().iter().copied().cycle().take(len).collect()
+}
+
+/// Returns the batch that a stream's contents are from, or an error if
+/// they are from more than one.
+fn batch_of(path: &str, data: &[u8]) -> io::Result<u64> {
+    let mut chunks = data.chunks(8);
+    let first = chunks.next().unwrap_or(&[]);
+    if first.len() != 8 || chunks.any(|chunk| chunk != &first[..chunk.len()]) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} holds more than one batch", path),
+        ));
+    }
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(first);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads every stream through `read_stream_at`, which only needs a shared
+/// reference, and returns the one batch they are all from, along with the
+/// number of bytes read.
+fn read_consistent(comp: &CompoundFile<File>) -> io::Result<(u64, u64)> {
+    let mut batches = Vec::with_capacity(STREAMS.len());
+    let mut bytes_read = 0;
+    for (path, _) in STREAMS {
+        let mut data = vec![0; comp.entry(path)?.len() as usize];
+        let mut filled = 0;
+        while filled < data.len() {
+            match comp.read_stream_at(
+                path,
+                filled as u64,
+                &mut data[filled..],
+            )? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                num_bytes => filled += num_bytes,
+            }
+        }
+        bytes_read += data.len() as u64;
+        batches.push(batch_of(path, &data)?);
+    }
+    if batches.iter().any(|&batch| batch != batches[0]) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Streams hold different batches: {:?}", batches),
+        ));
+    }
+    Ok((batches[0], bytes_read))
+}
+
+//===========================================================================//
+
+/// Reads every stream over and over until told to stop, and returns how
+/// many times it did, and how many bytes it read.
+fn reader(
+    comp: &RwLock<CompoundFile<File>>,
+    stop: &AtomicBool,
+    check: bool,
+) -> io::Result<(u64, u64)> {
+    let mut reads = 0;
+    let mut total_bytes = 0;
+    let mut last_batch = 0;
+    while !stop.load(Ordering::SeqCst) {
+        // The guard is held only for one pass over the streams, so the
+        // writer gets its turn in between.
+        let result = read_consistent(&comp.read().expect("lock poisoned"));
+        match result {
+            Ok((batch, bytes_read)) => {
+                if check {
+                    assert!(batch >= last_batch, "read a stale batch");
+                }
+                last_batch = batch;
+                reads += 1;
+                total_bytes += bytes_read;
+            }
+            Err(error) if check => panic!("torn read: {}", error),
+            Err(_) => {}
+        }
+    }
+    Ok((reads, total_bytes))
+}
+
+/// Applies a batch of updates once a second until told to stop, and
+/// returns the number of the last batch applied.
+fn writer(
+    comp: &RwLock<CompoundFile<File>>,
+    stop: &AtomicBool,
+) -> io::Result<u64> {
+    let mut batch = 0;
+    let start = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        let next = start + Duration::from_secs(batch + 1);
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        batch += 1;
+        let mut comp = comp.write().expect("lock poisoned");
+        for (path, len) in STREAMS {
+            comp.open_stream(path)?.write_all(&batch_data(batch, len))?;
+        }
+        // Every handle has been dropped (and so has passed on its writes)
+        // by now, so this can't fail with `StreamsStillOpen`, and once the
+        // lock is released, readers see the whole batch.
+        comp.flush()?;
+    }
+    Ok(batch)
+}
+
+/// Copies the whole compound file into memory every few seconds, under the
+/// read lock, and writes the copy out as a backup.  Returns the number of
+/// backups taken.
+fn backup(
+    comp: &RwLock<CompoundFile<File>>,
+    stop: &AtomicBool,
+    options: &Options,
+    backup_path: &Path,
+) -> io::Result<u64> {
+    let mut backups = 0;
+    let mut last_batch = 0;
+    let start = Instant::now();
+    loop {
+        let next =
+            start + Duration::from_secs(options.backup_every * (backups + 1));
+        while Instant::now() < next {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(backups);
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let copy = comp
+            .read()
+            .expect("lock poisoned")
+            .export_storage_as_cfb("/", Cursor::new(Vec::new()))?;
+        // The copy is in memory, so it is written out without holding the
+        // lock.
+        let data = copy.into_inner().into_inner();
+        fs::write(backup_path, &data)?;
+        backups += 1;
+        if options.check {
+            let mut copy = CompoundFile::open_strict(Cursor::new(data))?;
+            let mut batches = Vec::with_capacity(STREAMS.len());
+            for (path, len) in STREAMS {
+                let data = copy.read_stream_to_vec(path)?;
+                assert_eq!(data.len(), len);
+                batches.push(batch_of(path, &data)?);
+            }
+            assert!(
+                batches.iter().all(|&batch| batch == batches[0]),
+                "torn backup: {:?}",
+                batches
+            );
+            assert!(batches[0] >= last_batch, "stale backup");
+            last_batch = batches[0];
+        }
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,65 @@
+//! Mirrors a directory on disk into a storage within a compound file,
+//! rewriting only the streams whose files have changed since the last sync,
+//! and prints what was changed.  The compound file is created if it doesn't
+//! exist yet.
+//!
+//! Usage: `cargo run --example sync -- [--prune] [--contents] <directory>
+//! <compound file> [<storage path>]`
+
+use cfb::SyncOptions;
+use std::io;
+use std::path::Path;
+
+//===========================================================================//
+
+fn main() -> io::Result<()> {
+    let mut options = SyncOptions::new();
+    let mut args = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--prune" => options = options.remove_missing(true),
+            "--contents" => options = options.compare_contents(true),
+            _ => args.push(arg),
+        }
+    }
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!(
+            "Usage: sync [--prune] [--contents] <directory> <compound file> \
+             [<storage path>]"
+        );
+        std::process::exit(1);
+    }
+    let dir_path = &args[0];
+    let cfb_path = &args[1];
+    let inner_path = args.get(2).map(String::as_str).unwrap_or("/");
+
+    let mut comp = if Path::new(cfb_path).exists() {
+        cfb::open_rw(cfb_path)?
+    } else {
+        cfb::create(cfb_path)?
+    };
+    let report = comp.sync_from_dir(dir_path, inner_path, options)?;
+    // If nothing changed, this doesn't write anything, so the file's
+    // modification time is left alone too.
+    comp.flush()?;
+
+    for path in report.created() {
+        println!("created  {}", path.display());
+    }
+    for path in report.updated() {
+        println!("updated  {}", path.display());
+    }
+    for path in report.removed() {
+        println!("removed  {}", path.display());
+    }
+    println!(
+        "{} changed, {} unchanged",
+        report.created().len()
+            + report.updated().len()
+            + report.removed().len(),
+        report.num_unchanged()
+    );
+    Ok(())
+}
+
+//===========================================================================//
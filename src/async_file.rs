@@ -0,0 +1,548 @@
+//! Read-only access to compound files through Tokio's asynchronous I/O
+//! traits (enabled by the `async` feature).
+//!
+//! Opening a file this way runs the very same parser as
+//! [`CompoundFile::open`], so the two can't disagree about what a file
+//! contains.  The parser doesn't read the file itself: it reads an in-memory
+//! [`MetadataImage`], which starts out holding only the header.  Whatever
+//! the parser asks for that the image doesn't yet hold (DIFAT, FAT,
+//! directory, and MiniFAT sectors) is noted and read in as zeros; the
+//! missing blocks are then fetched asynchronously, all at once, and the
+//! parse is run again, until it completes without needing anything more.
+//! Each round can only find out about sectors that the previous round's
+//! data pointed to, so a file needs only a handful of rounds (plus one for
+//! each DIFAT sector, which only very large files have).
+//!
+//! Stream data is never part of the image.  An [`AsyncStream`] reads it
+//! straight from the underlying reader, using the sector layout that the
+//! parser worked out.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
+
+use crate::internal::{self, consts, Validation};
+use crate::{CompoundFile, Entries, Entry, ObjType, ValidationIssue, Version};
+
+//===========================================================================//
+
+/// The granularity with which the image is fetched from the underlying
+/// reader.  This is the length of the header and of the smallest sectors,
+/// so nothing beyond the sectors the parser needs is read; larger sectors
+/// span several adjacent blocks, which are fetched with a single read.
+const BLOCK_LEN: u64 = consts::HEADER_LEN as u64;
+
+//===========================================================================//
+
+/// An in-memory copy of the parts of a compound file that are needed to
+/// parse it (its header, DIFAT, FAT, directory, and MiniFAT), as used by
+/// [`AsyncCompoundFile`].
+///
+/// This implements `Read` and `Seek` only so that the parser can read it;
+/// it has no public methods of its own.
+pub struct MetadataImage {
+    len: u64,
+    position: u64,
+    blocks: Arc<BTreeMap<u64, Vec<u8>>>,
+    state: Arc<Mutex<ImageState>>,
+}
+
+struct ImageState {
+    /// Blocks that the parser tried to read but that weren't in the image.
+    missing: Vec<u64>,
+    /// Once the file has been opened, reads of missing blocks are errors
+    /// rather than zeros.
+    sealed: bool,
+}
+
+impl MetadataImage {
+    fn new(len: u64) -> MetadataImage {
+        MetadataImage {
+            len,
+            position: 0,
+            blocks: Arc::new(BTreeMap::new()),
+            state: Arc::new(Mutex::new(ImageState {
+                missing: Vec::new(),
+                sealed: false,
+            })),
+        }
+    }
+
+    /// Returns a fresh view of the image for another parse, with its own
+    /// position.
+    fn view(&self) -> MetadataImage {
+        MetadataImage {
+            len: self.len,
+            position: 0,
+            blocks: self.blocks.clone(),
+            state: self.state.clone(),
+        }
+    }
+
+    fn block_len(&self, block: u64) -> usize {
+        (self.len - block * BLOCK_LEN).min(BLOCK_LEN) as usize
+    }
+
+    /// Returns (and forgets) the blocks that were found missing since the
+    /// last call, sorted and without duplicates.
+    fn take_missing(&self) -> Vec<u64> {
+        let mut missing =
+            std::mem::take(&mut self.state.lock().unwrap().missing);
+        missing.sort_unstable();
+        missing.dedup();
+        missing
+    }
+
+    fn seal(&self) {
+        self.state.lock().unwrap().sealed = true;
+    }
+
+    /// Reads the given blocks (sorted, and not yet in the image) from the
+    /// underlying reader, with one read per run of adjacent blocks.
+    async fn fetch<F: AsyncRead + AsyncSeek + Unpin>(
+        &mut self,
+        inner: &mut F,
+        missing: &[u64],
+    ) -> io::Result<()> {
+        // Every view of the image from a finished parse has been dropped by
+        // now, so this doesn't copy the blocks.
+        let blocks = Arc::make_mut(&mut self.blocks);
+        let mut start = 0;
+        while start < missing.len() {
+            let mut end = start + 1;
+            while end < missing.len() && missing[end] == missing[end - 1] + 1 {
+                end += 1;
+            }
+            let first = missing[start];
+            let offset = first * BLOCK_LEN;
+            let len =
+                (self.len - offset).min((end - start) as u64 * BLOCK_LEN);
+            let mut buffer = vec![0; len as usize];
+            inner.seek(SeekFrom::Start(offset)).await?;
+            inner.read_exact(&mut buffer).await?;
+            for (index, chunk) in buffer.chunks(BLOCK_LEN as usize).enumerate()
+            {
+                blocks.insert(first + index as u64, chunk.to_vec());
+            }
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+impl Read for MetadataImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let block = self.position / BLOCK_LEN;
+        let offset = (self.position % BLOCK_LEN) as usize;
+        let num_read = buf.len().min(self.block_len(block) - offset);
+        match self.blocks.get(&block) {
+            Some(data) => {
+                buf[..num_read]
+                    .copy_from_slice(&data[offset..offset + num_read]);
+            }
+            None => {
+                let mut state = self.state.lock().unwrap();
+                if state.sealed {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "Only the metadata of a compound file opened with \
+                         AsyncCompoundFile is held in memory; read streams \
+                         with AsyncCompoundFile::open_stream",
+                    ));
+                }
+                state.missing.push(block);
+                buf[..num_read].fill(0);
+            }
+        }
+        self.position += num_read as u64;
+        Ok(num_read)
+    }
+}
+
+impl Seek for MetadataImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(delta) => Some(delta),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => {
+                self.position.checked_add_signed(delta)
+            }
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => invalid_input!("Cannot seek to a negative position"),
+        }
+    }
+}
+
+//===========================================================================//
+
+/// A compound file, opened for reading through Tokio's asynchronous I/O
+/// traits.
+///
+/// Opening the file reads only what's needed to parse its structure: the
+/// header, DIFAT, FAT, directory, and MiniFAT, which are kept in memory as
+/// a [`MetadataImage`] and parsed by the same code as
+/// [`CompoundFile::open`](struct.CompoundFile.html#method.open).  After
+/// that, listing and looking up entries needs no I/O at all, and stream
+/// data is read as it is requested through
+/// [`open_stream`](#method.open_stream), without ever blocking.  Write
+/// support isn't available yet.
+///
+/// ```no_run
+/// # async fn example() -> std::io::Result<()> {
+/// use tokio::io::AsyncReadExt;
+///
+/// let file = tokio::fs::File::open("path/to/cfb/file").await?;
+/// let mut comp = cfb::AsyncCompoundFile::open(file).await?;
+/// let paths: Vec<_> = comp.walk().map(|entry| entry.path().to_owned()).collect();
+/// let mut data = Vec::new();
+/// comp.open_stream("/foo/bar")?.read_to_end(&mut data).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncCompoundFile<F> {
+    comp: CompoundFile<MetadataImage>,
+    inner: F,
+}
+
+impl<F> AsyncCompoundFile<F> {
+    /// Returns the CFB format version used for this compound file.
+    pub fn version(&self) -> Version {
+        self.comp.version()
+    }
+
+    /// Returns the sector length used for this compound file, in bytes.
+    pub fn sector_len(&self) -> usize {
+        self.comp.sector_len()
+    }
+
+    /// Returns the spec violations that were tolerated when the file was
+    /// opened (see
+    /// [`CompoundFile::open_warnings`](struct.CompoundFile.html#method.open_warnings)).
+    pub fn open_warnings(&self) -> &[ValidationIssue] {
+        self.comp.open_warnings()
+    }
+
+    /// Returns information about the root storage object.
+    pub fn root_entry(&self) -> Entry {
+        self.comp.root_entry()
+    }
+
+    /// Given a path within the compound file, get information about that
+    /// stream or storage object.
+    pub fn entry<P: AsRef<Path>>(&self, path: P) -> io::Result<Entry> {
+        self.comp.entry(path)
+    }
+
+    /// Returns an iterator over the entries within the root storage object.
+    pub fn read_root_storage(&self) -> Entries<'_, MetadataImage> {
+        self.comp.read_root_storage()
+    }
+
+    /// Returns an iterator over the entries within a storage object.
+    pub fn read_storage<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<Entries<'_, MetadataImage>> {
+        self.comp.read_storage(path)
+    }
+
+    /// Returns an iterator over all entries within the compound file, in
+    /// preorder.
+    pub fn walk(&self) -> Entries<'_, MetadataImage> {
+        self.comp.walk()
+    }
+
+    /// Returns an iterator over all entries under a storage subtree, in
+    /// preorder.
+    pub fn walk_storage<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<Entries<'_, MetadataImage>> {
+        self.comp.walk_storage(path)
+    }
+
+    /// Returns true if there is an existing stream or storage at the given
+    /// path, or false if there is nothing at that path.
+    pub fn exists<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.comp.exists(path)
+    }
+
+    /// Returns true if there is an existing stream at the given path, or
+    /// false if there is a storage or nothing at that path.
+    pub fn is_stream<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.comp.is_stream(path)
+    }
+
+    /// Returns true if there is an existing storage at the given path, or
+    /// false if there is a stream or nothing at that path.
+    pub fn is_storage<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.comp.is_storage(path)
+    }
+
+    /// Consumes the `AsyncCompoundFile`, returning the underlying reader.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: AsyncRead + AsyncSeek + Unpin> AsyncCompoundFile<F> {
+    /// Opens an existing compound file, using the underlying reader.  Like
+    /// [`CompoundFile::open`](struct.CompoundFile.html#method.open), this
+    /// tolerates the spec violations that many files in the wild have.
+    pub async fn open(inner: F) -> io::Result<AsyncCompoundFile<F>> {
+        AsyncCompoundFile::open_internal(inner, Validation::Permissive).await
+    }
+
+    /// Like `open()`, but returns an error if the file violates the CFB spec
+    /// in any way (see
+    /// [`CompoundFile::open_strict`](struct.CompoundFile.html#method.open_strict)).
+    pub async fn open_strict(inner: F) -> io::Result<AsyncCompoundFile<F>> {
+        AsyncCompoundFile::open_internal(inner, Validation::Strict).await
+    }
+
+    async fn open_internal(
+        mut inner: F,
+        validation: Validation,
+    ) -> io::Result<AsyncCompoundFile<F>> {
+        let len = inner.seek(SeekFrom::End(0)).await?;
+        let mut image = MetadataImage::new(len);
+        if len > 0 {
+            image.fetch(&mut inner, &[0]).await?;
+        }
+        loop {
+            let result =
+                CompoundFile::open_internal(image.view(), validation, None);
+            let missing = image.take_missing();
+            if missing.is_empty() {
+                let comp = result?;
+                image.seal();
+                return Ok(AsyncCompoundFile { comp, inner });
+            }
+            // Whatever the parse concluded, it was working from zeros where
+            // these blocks should have been, so fetch them and start over.
+            drop(result);
+            image.fetch(&mut inner, &missing).await?;
+        }
+    }
+
+    /// Opens an existing stream in the compound file for reading.  This
+    /// doesn't need any I/O (the stream's layout is already known), so it
+    /// isn't `async`; the returned stream reads from the underlying reader
+    /// as it is polled.
+    pub fn open_stream<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<AsyncStream<'_, F>> {
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        let stream_id = self.comp.resolve_name_chain(&names, "stream")?;
+        let minialloc = self.comp.minialloc();
+        if minialloc.dir_entry(stream_id).obj_type != ObjType::Stream {
+            invalid_input!(
+                "Not a stream: {:?}",
+                internal::path::path_from_name_chain(&names)
+            );
+        }
+        // Merge sectors that happen to be adjacent in the file, so that
+        // they can be read without seeking in between.
+        let mut runs = Vec::<Run>::new();
+        let mut len = 0;
+        for (file_offset, piece_len) in
+            minialloc.stream_pieces(stream_id, &mut None)?
+        {
+            match runs.last_mut() {
+                Some(run) if run.file_offset + run.len == file_offset => {
+                    run.len += piece_len as u64;
+                }
+                _ => runs.push(Run {
+                    start: len,
+                    file_offset,
+                    len: piece_len as u64,
+                }),
+            }
+            len += piece_len as u64;
+        }
+        drop(minialloc);
+        Ok(AsyncStream {
+            inner: &mut self.inner,
+            runs,
+            len,
+            position: 0,
+            file_position: None,
+            seeking: None,
+        })
+    }
+}
+
+//===========================================================================//
+
+/// A contiguous piece of a stream's data in the underlying file.
+struct Run {
+    /// The offset of the run within the stream.
+    start: u64,
+    /// The offset of the run within the underlying file.
+    file_offset: u64,
+    len: u64,
+}
+
+/// A read-only stream in a compound file, as returned by
+/// [`AsyncCompoundFile::open_stream`].  It borrows the file's underlying
+/// reader, so only one can be open at a time.
+pub struct AsyncStream<'a, F> {
+    inner: &'a mut F,
+    runs: Vec<Run>,
+    len: u64,
+    position: u64,
+    /// Where the underlying reader is positioned, if known.
+    file_position: Option<u64>,
+    /// The target of a seek of the underlying reader that is in progress.
+    seeking: Option<u64>,
+}
+
+impl<'a, F> AsyncStream<'a, F> {
+    /// Returns the current length of the stream, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the stream is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the offset in the underlying file of the current position,
+    /// and how many bytes of the stream follow it contiguously there.
+    fn locate(&self) -> (u64, u64) {
+        let index = self
+            .runs
+            .partition_point(|run| run.start + run.len <= self.position);
+        let run = &self.runs[index];
+        let skip = self.position - run.start;
+        (run.file_offset + skip, run.len - skip)
+    }
+}
+
+impl<'a, F: AsyncRead + AsyncSeek + Unpin> AsyncRead for AsyncStream<'a, F> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(target) = this.seeking {
+                let position =
+                    ready!(Pin::new(&mut *this.inner).poll_complete(cx))?;
+                this.seeking = None;
+                if position != target {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Underlying reader sought to {} instead of {}",
+                            position, target
+                        ),
+                    )));
+                }
+                this.file_position = Some(position);
+            }
+            if this.position >= this.len || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            let (file_offset, available) = this.locate();
+            if this.file_position != Some(file_offset) {
+                // Let any operation that an earlier, dropped stream left
+                // pending finish before starting the seek.
+                ready!(Pin::new(&mut *this.inner).poll_complete(cx))?;
+                this.file_position = None;
+                Pin::new(&mut *this.inner)
+                    .start_seek(SeekFrom::Start(file_offset))?;
+                this.seeking = Some(file_offset);
+                continue;
+            }
+            let max_len = available.min(buf.remaining() as u64) as usize;
+            let mut target = ReadBuf::new(buf.initialize_unfilled_to(max_len));
+            ready!(Pin::new(&mut *this.inner).poll_read(cx, &mut target))?;
+            let num_read = target.filled().len();
+            if num_read == 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Underlying file ended within a stream's data",
+                )));
+            }
+            buf.advance(num_read);
+            this.position += num_read as u64;
+            this.file_position = Some(file_offset + num_read as u64);
+            return Poll::Ready(Ok(()));
+        }
+    }
+}
+
+impl<'a, F> AsyncSeek for AsyncStream<'a, F> {
+    fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        this.position = match pos {
+            SeekFrom::Start(delta) => {
+                if delta > this.len {
+                    invalid_input!(
+                        "Cannot seek to {} bytes from start, because stream \
+                         length is only {} bytes",
+                        delta,
+                        this.len
+                    );
+                }
+                delta
+            }
+            SeekFrom::End(delta) => {
+                if delta > 0 {
+                    invalid_input!(
+                        "Cannot seek to {} bytes past the end of the stream",
+                        delta
+                    );
+                }
+                match this.len.checked_sub(delta.unsigned_abs()) {
+                    Some(position) => position,
+                    None => invalid_input!(
+                        "Cannot seek to {} bytes before end, because stream \
+                         length is only {} bytes",
+                        delta.unsigned_abs(),
+                        this.len
+                    ),
+                }
+            }
+            SeekFrom::Current(delta) => {
+                match this.position.checked_add_signed(delta) {
+                    Some(position) if position <= this.len => position,
+                    _ => invalid_input!(
+                        "Cannot seek {} bytes from current position {}, \
+                         because stream length is {} bytes",
+                        delta,
+                        this.position,
+                        this.len
+                    ),
+                }
+            }
+        };
+        Ok(())
+    }
+
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,620 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs, process, thread};
+
+use cfb::prelude::*;
+use cfb::tool::{self, escape_name, escape_path, split_path, NameStyle};
+use cfb::{ImportOptions, SanitizeOptions};
+use clap::{Parser, Subcommand};
+use uuid::Uuid;
+
+/// How often `watch` checks whether the file has changed when it can't use
+/// file change notifications, and how often it checks whether a changed file
+/// has settled.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The exit status of `chcls --get` when a CLSID is null.
+const EXIT_NULL_CLSID: i32 = 3;
+
+/// The exit status of `chcls --if-match` when a CLSID doesn't match.
+const EXIT_CLSID_MISMATCH: i32 = 4;
+
+/// The exit status of `verify` when the file has problems.
+const EXIT_VERIFY_FAILED: i32 = 5;
+
+/// The exit status of `pack` when some files couldn't be copied.
+const EXIT_PACK_INCOMPLETE: i32 = 6;
+
+#[derive(Parser, Debug)]
+#[clap(author, about, long_about = None)]
+struct Cli {
+    #[clap(long, global = true)]
+    /// Prints object names exactly as stored, rather than escaping control
+    /// characters as \u{...}
+    raw: bool,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Concatenates and prints streams
+    Cat { path: Vec<String> },
+
+    /// Changes or prints storage CLSIDs
+    Chcls {
+        #[clap(long, conflicts_with_all = ["clear", "if_match"])]
+        /// Prints each storage's CLSID instead of changing it (exits with
+        /// status 3 if any is null)
+        get: bool,
+
+        #[clap(long)]
+        /// Sets each storage's CLSID to the null GUID
+        clear: bool,
+
+        #[clap(long, value_name = "UUID")]
+        /// Only changes anything if every storage's CLSID is currently UUID
+        /// (otherwise exits with status 4)
+        if_match: Option<Uuid>,
+
+        #[clap(value_name = "[CLSID] PATH", required = true)]
+        /// The new CLSID (unless --get or --clear is given), followed by the
+        /// storages to change, as FILE:PATH
+        args: Vec<String>,
+    },
+
+    /// Lists storage contents
+    Ls {
+        #[clap(short, long)]
+        /// Lists in long format
+        long: bool,
+
+        #[clap(short, long)]
+        /// Includes . in output
+        all: bool,
+
+        path: Vec<String>,
+    },
+
+    /// Summarizes the total size of each storage, like du
+    Du {
+        #[clap(short, long)]
+        /// Only reports storages at most N levels below the given path
+        depth: Option<usize>,
+
+        #[clap(short, long, value_parser = tool::parse_size)]
+        /// Hides storages smaller than SIZE (e.g. 4096, 64K, 2M)
+        threshold: Option<u64>,
+
+        #[clap(short, long)]
+        /// Prints sizes in bytes rather than human-readable units
+        bytes: bool,
+
+        #[clap(long)]
+        /// Prints the summary as JSON
+        json: bool,
+
+        path: Vec<String>,
+    },
+
+    /// Dump a given stream by navigating to it from Root Storage.
+    Dump {
+        #[clap(short, long)]
+        /// Dump all streams found in CFB file.
+        all: bool,
+        /// Path to dump destination
+        path: String,
+    },
+
+    /// Copies a local file (or stdin, given as -) into a stream
+    Put {
+        /// The local file to read from
+        source: PathBuf,
+        /// The stream to write to, as FILE:PATH
+        dest: String,
+    },
+
+    /// Copies a directory (such as one written by dump --all) into a
+    /// storage, restoring exact names and metadata from its manifest if it
+    /// has one (exits with status 6 if anything couldn't be copied)
+    Pack {
+        #[clap(long)]
+        /// Follows symbolic links, rather than skipping them
+        follow_symlinks: bool,
+
+        #[clap(long)]
+        /// Gives each storage the modification time of its directory
+        preserve_times: bool,
+
+        /// The local directory to read from
+        source: PathBuf,
+        /// The storage to write to, as FILE:PATH (FILE is created if it
+        /// doesn't exist)
+        dest: String,
+    },
+
+    /// Strips timestamps, identifying properties, and leftover data from
+    /// removed streams, before a file is shared
+    Sanitize {
+        #[clap(long)]
+        /// Keeps storage timestamps and summary information times
+        keep_times: bool,
+
+        #[clap(long)]
+        /// Leaves summary information property sets alone
+        keep_properties: bool,
+
+        /// The compound file to sanitize
+        file: PathBuf,
+    },
+
+    /// Prints everything known about a single object
+    Stat {
+        #[clap(long)]
+        /// Prints the facts as JSON
+        json: bool,
+
+        /// The object to describe, as FILE:PATH
+        path: String,
+    },
+
+    /// Checks a compound file for problems (exits with status 5 if any are
+    /// found)
+    Verify {
+        #[clap(long)]
+        /// Also reads every stream to its end and cross-checks the FAT,
+        /// MiniFAT, and directory
+        deep: bool,
+
+        #[clap(long, requires = "deep")]
+        /// Prints a hash of each stream's contents
+        hash: bool,
+
+        /// The compound file to verify
+        file: PathBuf,
+    },
+
+    /// Extracts streams into a directory, then re-extracts whichever streams
+    /// change each time the file is modified
+    Watch {
+        /// The compound file to watch
+        file: PathBuf,
+
+        #[clap(short, long)]
+        /// The directory to extract streams into
+        output: PathBuf,
+
+        #[clap(short, long)]
+        /// Only extracts streams whose paths match GLOB (e.g. "/dir/*")
+        filter: Option<String>,
+
+        #[clap(long, default_value_t = 500)]
+        /// How long, in milliseconds, the file must go unmodified before it
+        /// is re-read
+        debounce: u64,
+
+        #[clap(long)]
+        /// Checks the file for changes every 100ms, instead of relying on
+        /// file change notifications (which some file systems, such as
+        /// network shares, don't deliver)
+        poll: bool,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match run(cli.command, NameStyle::for_stdout(cli.raw)) {
+        Ok(0) => {}
+        Ok(status) => process::exit(status),
+        Err(error) => {
+            eprintln!("cfbtool: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+/// Groups `FILE:PATH` arguments by file, keeping the files in the order they
+/// first appear, so that each file need only be opened once.
+fn group_paths(args: &[String]) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    for arg in args {
+        let (comp_path, inner_path) = split_path(arg);
+        match groups.iter_mut().find(|(path, _)| *path == comp_path) {
+            Some((_, inner_paths)) => inner_paths.push(inner_path),
+            None => groups.push((comp_path, vec![inner_path])),
+        }
+    }
+    groups
+}
+
+fn storage_clsid<F>(comp: &CompoundFile<F>, path: &Path) -> io::Result<Uuid> {
+    let entry = comp.entry(path)?;
+    if entry.is_stream() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Not a storage: {:?}", entry.path()),
+        ));
+    }
+    Ok(*entry.clsid())
+}
+
+fn chcls(
+    get: bool,
+    clear: bool,
+    if_match: Option<Uuid>,
+    mut args: Vec<String>,
+    style: NameStyle,
+) -> io::Result<i32> {
+    let clsid = if get || clear {
+        Uuid::nil()
+    } else {
+        let clsid = args.remove(0);
+        if args.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No storage paths given",
+            ));
+        }
+        Uuid::parse_str(&clsid).map_err(|error| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid CLSID {:?}: {}", clsid, error),
+            )
+        })?
+    };
+    let groups = group_paths(&args);
+    if get {
+        let mut status = 0;
+        for (comp_path, inner_paths) in groups {
+            let comp = cfb::open(&comp_path)?;
+            for inner_path in inner_paths {
+                let clsid = storage_clsid(&comp, &inner_path)?;
+                println!("{}", clsid.hyphenated());
+                if clsid.is_nil() {
+                    status = EXIT_NULL_CLSID;
+                }
+            }
+        }
+        return Ok(status);
+    }
+    // Check every precondition before changing anything, so that a mismatch
+    // leaves all of the files as they were.
+    let mut comps = Vec::with_capacity(groups.len());
+    for (comp_path, inner_paths) in groups {
+        let comp = cfb::open_rw(&comp_path)?;
+        if let Some(expected) = if_match {
+            for inner_path in inner_paths.iter() {
+                let actual = storage_clsid(&comp, inner_path)?;
+                if actual != expected {
+                    eprintln!(
+                        "cfbtool: {}:{}: CLSID is {}, not {}",
+                        comp_path.display(),
+                        escape_path(inner_path, style),
+                        actual.hyphenated(),
+                        expected.hyphenated()
+                    );
+                    return Ok(EXIT_CLSID_MISMATCH);
+                }
+            }
+        }
+        comps.push((comp, inner_paths));
+    }
+    for (mut comp, inner_paths) in comps {
+        for inner_path in inner_paths {
+            comp.set_storage_clsid(inner_path, clsid)?;
+        }
+        comp.flush()?;
+    }
+    Ok(0)
+}
+
+fn verify(
+    file: &Path,
+    deep: bool,
+    hash: bool,
+    style: NameStyle,
+) -> io::Result<i32> {
+    let mut comp = cfb::open(file)?;
+    let mut ok = true;
+    for warning in comp.open_warnings() {
+        println!("warning: {}", warning);
+        ok = false;
+    }
+    if deep {
+        let report = comp.verify_deep(VerifyOptions::new().hash_streams(hash));
+        for problem in report.structure_problems() {
+            println!("problem: {}", problem);
+        }
+        for stream in report.streams() {
+            match (stream.error(), stream.hash()) {
+                (Some(error), _) => println!(
+                    "FAILED {} ({} of {} bytes read): {}",
+                    escape_path(stream.path(), style),
+                    stream.bytes_read(),
+                    stream.len(),
+                    error
+                ),
+                (None, Some(hash)) => println!(
+                    "{:016x} {}",
+                    hash,
+                    escape_path(stream.path(), style)
+                ),
+                (None, None) => {}
+            }
+        }
+        println!(
+            "{} streams, {} bytes read in {:.3}s ({:.1} MB/s)",
+            report.streams().len(),
+            report.bytes_read(),
+            report.elapsed().as_secs_f64(),
+            report.throughput() / 1e6
+        );
+        ok &= report.is_ok();
+    }
+    Ok(if ok { 0 } else { EXIT_VERIFY_FAILED })
+}
+
+fn run(command: Command, style: NameStyle) -> io::Result<i32> {
+    match command {
+        Command::Cat { path } => {
+            for path in path {
+                let (comp_path, inner_path) = split_path(&path);
+                let mut comp = cfb::open(&comp_path)?;
+                let mut stream = comp.open_stream(inner_path)?;
+                io::copy(&mut stream, &mut io::stdout())?;
+            }
+        }
+        Command::Chcls { get, clear, if_match, args } => {
+            let status = chcls(get, clear, if_match, args, style)?;
+            io::stdout().flush()?;
+            return Ok(status);
+        }
+        Command::Ls { long, all, path } => {
+            for path in path {
+                let (comp_path, inner_path) = split_path(&path);
+                let comp = cfb::open(&comp_path)?;
+                let entry = comp.entry(&inner_path)?;
+                if entry.is_stream() {
+                    let name = escape_name(entry.name(), style);
+                    println!("{}", tool::format_entry(&name, &entry, long));
+                } else {
+                    if all {
+                        println!("{}", tool::format_entry(".", &entry, long));
+                    }
+                    for subentry in comp.read_storage(&inner_path)? {
+                        let name = escape_name(subentry.name(), style);
+                        println!(
+                            "{}",
+                            tool::format_entry(&name, &subentry, long)
+                        );
+                    }
+                }
+            }
+        }
+        Command::Du { depth, threshold, bytes, json, path } => {
+            for path in path {
+                let (comp_path, inner_path) = split_path(&path);
+                let comp = cfb::open(&comp_path)?;
+                let mut rows = tool::disk_usage(&comp, &inner_path)?;
+                rows.retain(|row| {
+                    depth.map_or(true, |depth| row.depth <= depth)
+                        && threshold.map_or(true, |min| row.len >= min)
+                });
+                let mut stdout = io::stdout();
+                if json {
+                    tool::write_disk_usage_json(&mut stdout, &rows)?;
+                } else {
+                    tool::write_disk_usage(&mut stdout, &rows, bytes, style)?;
+                }
+            }
+        }
+        Command::Dump { path, all } => {
+            let mut comp = cfb::open(&path)?;
+            if all {
+                let output_dir = env::current_dir()?.join("root");
+                fs::create_dir(&output_dir)?;
+                for written in
+                    tool::extract_all(&mut comp, "/".as_ref(), &output_dir)?
+                {
+                    println!("Dumped stream to [{}]", written.display());
+                }
+                return Ok(0);
+            }
+
+            let mut entries = comp.read_root_storage().collect::<Vec<_>>();
+            loop {
+                for (index, subentry) in entries.iter().enumerate() {
+                    let (name, _) = tool::decode_msi_name(subentry.name());
+                    println!("[{index}] {}", escape_name(&name, style));
+                }
+                println!("Inspect?: ");
+                let mut input = String::new();
+                io::stdin().read_line(&mut input)?;
+                let input = input.trim();
+                if input == "q" {
+                    return Ok(0);
+                }
+
+                let selection = match input
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| entries.get(index))
+                {
+                    Some(selection) => selection.clone(),
+                    None => {
+                        println!("Enter a listed number, or 'q' to quit.");
+                        continue;
+                    }
+                };
+                if selection.is_storage() {
+                    entries = comp.read_storage(selection.path())?.collect();
+                } else {
+                    let mut stream = comp.open_stream(selection.path())?;
+                    println!("Stream dump location: ");
+                    let mut input = String::new();
+                    io::stdin().read_line(&mut input)?;
+                    let input = input.trim();
+                    println!(
+                        "Dumping stream [{}] to [{}]",
+                        escape_path(selection.path(), style),
+                        input
+                    );
+                    let mut new_file = fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(input)?;
+                    io::copy(&mut stream, &mut new_file)?;
+                    return Ok(0);
+                }
+            }
+        }
+        Command::Put { source, dest } => {
+            let (comp_path, inner_path) = split_path(&dest);
+            let mut comp = cfb::open_rw(&comp_path)?;
+            if source.as_os_str() == "-" {
+                tool::put_stream(&mut comp, &inner_path, &mut io::stdin())?;
+            } else {
+                let mut file = fs::File::open(&source)?;
+                tool::put_stream(&mut comp, &inner_path, &mut file)?;
+            }
+            comp.flush()?;
+        }
+        Command::Pack { follow_symlinks, preserve_times, source, dest } => {
+            let (comp_path, inner_path) = split_path(&dest);
+            let mut comp = if comp_path.exists() {
+                cfb::open_rw(&comp_path)?
+            } else {
+                cfb::create(&comp_path)?
+            };
+            if source.join(tool::MANIFEST_FILE_NAME).is_file() {
+                tool::insert_all(&mut comp, &inner_path, &source)?;
+                comp.flush()?;
+                return Ok(0);
+            }
+            // Undo the .dump extension that dump --all gives streams.
+            let options = ImportOptions::new()
+                .follow_symlinks(follow_symlinks)
+                .preserve_times(preserve_times)
+                .map_names(|name, is_storage| {
+                    match name.strip_suffix(".dump") {
+                        Some(stem) if !is_storage => stem.to_string(),
+                        _ => name.to_string(),
+                    }
+                });
+            let report =
+                comp.create_storage_from_dir(&source, &inner_path, options)?;
+            comp.flush()?;
+            for failure in report.failures() {
+                eprintln!("cfbtool: skipped {}", failure);
+            }
+            if !report.is_complete() {
+                return Ok(EXIT_PACK_INCOMPLETE);
+            }
+        }
+        Command::Sanitize { keep_times, keep_properties, file } => {
+            let mut comp = cfb::open_rw(&file)?;
+            let options = SanitizeOptions::new()
+                .keep_times(keep_times)
+                .keep_properties(keep_properties);
+            let report = comp.sanitize(options)?;
+            println!("timestamps cleared: {}", report.num_times_cleared());
+            println!(
+                "properties scrubbed: {}",
+                report.num_properties_scrubbed()
+            );
+            for path in report.removed_property_sets() {
+                println!("property set removed: {}", escape_path(path, style));
+            }
+            println!("bytes wiped: {}", report.num_bytes_wiped());
+            if report.header_reset() {
+                println!("header fields reset");
+            }
+        }
+        Command::Stat { json, path } => {
+            let (comp_path, inner_path) = split_path(&path);
+            let mut comp = cfb::open(&comp_path)?;
+            let stat = tool::stat_entry(&mut comp, &inner_path)?;
+            let mut stdout = io::stdout();
+            if json {
+                tool::write_stat_json(&mut stdout, &stat)?;
+            } else {
+                tool::write_stat(&mut stdout, &stat, style)?;
+            }
+        }
+        Command::Verify { deep, hash, file } => {
+            let status = verify(&file, deep, hash, style)?;
+            io::stdout().flush()?;
+            return Ok(status);
+        }
+        Command::Watch { file, output, filter, debounce, poll } => {
+            fs::create_dir_all(&output)?;
+            let debounce = Duration::from_millis(debounce);
+            let mut watcher =
+                tool::Watcher::new(&file, &output, filter, debounce);
+            // Fall back to polling if notifications aren't available.
+            let events = if poll {
+                None
+            } else {
+                match tool::FileEvents::new(&file) {
+                    Ok(events) => Some(events),
+                    Err(error) => {
+                        eprintln!(
+                            "cfbtool: {}: can't watch for changes ({}); \
+                             polling instead",
+                            file.display(),
+                            error
+                        );
+                        None
+                    }
+                }
+            };
+            let mut stdout = io::stdout();
+            let mut reported_unreadable = false;
+            loop {
+                let status = watcher.poll()?;
+                // Until a changed file has settled and been read, it has to
+                // be checked again even if nothing more happens to it.
+                let settled = matches!(
+                    status,
+                    tool::WatchStatus::Unchanged
+                        | tool::WatchStatus::Extracted(_)
+                );
+                match status {
+                    tool::WatchStatus::Extracted(changes) => {
+                        reported_unreadable = false;
+                        tool::write_watch_changes(
+                            &mut stdout,
+                            &changes,
+                            style,
+                        )?;
+                        stdout.flush()?;
+                    }
+                    tool::WatchStatus::Unreadable(error)
+                        if !reported_unreadable =>
+                    {
+                        eprintln!(
+                            "cfbtool: {}: {} (will retry)",
+                            file.display(),
+                            error
+                        );
+                        reported_unreadable = true;
+                    }
+                    _ => {}
+                }
+                match events {
+                    Some(ref events) if settled => {
+                        events.wait(None);
+                    }
+                    Some(ref events) => {
+                        events.wait(Some(WATCH_POLL_INTERVAL));
+                    }
+                    None => thread::sleep(WATCH_POLL_INTERVAL),
+                }
+            }
+        }
+    }
+    io::stdout().flush()?;
+    Ok(0)
+}
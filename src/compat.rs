@@ -0,0 +1,209 @@
+//! Transitional adapters for code written against other structured storage
+//! crates.
+//!
+//! Several other crates for reading OLE/structured storage files expose
+//! slightly different call shapes than [`CompoundFile`]: navigation through
+//! storage handles rather than full paths, entry iterators that yield
+//! `io::Result` items, listings of owned child names, and paths given only as
+//! `&str`.  The types in this module map those call shapes onto a
+//! `CompoundFile`, so that a codebase can switch one call site at a time
+//! instead of all at once.
+//!
+//! These adapters are meant as a stepping stone, not as a second API: they
+//! add no functionality of their own, and new code should use `CompoundFile`
+//! directly.  [`CompatFile::inner_mut`] gives access to the full API at any
+//! point during a migration.
+//!
+//! This module is only available with the `compat` feature.
+//!
+//! ```
+//! use cfb::compat::CompatFile;
+//! use std::io::{Cursor, Write};
+//!
+//! let mut comp = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+//! comp.create_storage("/foo").unwrap();
+//! comp.create_stream("/foo/bar").unwrap().write_all(b"baz").unwrap();
+//!
+//! let mut file = CompatFile::new(comp);
+//! let mut storage = file.open_storage("/foo").unwrap();
+//! assert_eq!(storage.list_names().unwrap(), vec!["bar".to_string()]);
+//! assert_eq!(storage.read_stream("bar").unwrap(), b"baz");
+//! ```
+
+use std::io::{self, Read, Seek};
+use std::iter::FusedIterator;
+use std::path::{Path, PathBuf};
+
+use crate::internal;
+use crate::{CompoundFile, Entries, Entry, Stream};
+
+//===========================================================================//
+
+/// A compound file with the call shapes of other structured storage crates.
+///
+/// All paths are given as `&str`, and are interpreted the same way as by
+/// `CompoundFile` (i.e. relative paths are relative to the root storage).
+pub struct CompatFile<F> {
+    comp: CompoundFile<F>,
+}
+
+impl<F> CompatFile<F> {
+    /// Wraps the given compound file.
+    pub fn new(comp: CompoundFile<F>) -> CompatFile<F> {
+        CompatFile { comp }
+    }
+
+    /// Returns a reference to the wrapped compound file.
+    pub fn inner(&self) -> &CompoundFile<F> {
+        &self.comp
+    }
+
+    /// Returns a mutable reference to the wrapped compound file.
+    pub fn inner_mut(&mut self) -> &mut CompoundFile<F> {
+        &mut self.comp
+    }
+
+    /// Unwraps the compound file.
+    pub fn into_inner(self) -> CompoundFile<F> {
+        self.comp
+    }
+
+    /// Returns a handle to the root storage.
+    pub fn root_storage(&mut self) -> Storage<'_, F> {
+        Storage { comp: &mut self.comp, path: PathBuf::from("/") }
+    }
+
+    /// Returns a handle to the storage at the given path.  Fails if there is
+    /// no such storage.
+    pub fn open_storage(&mut self, path: &str) -> io::Result<Storage<'_, F>> {
+        Storage::open(&mut self.comp, Path::new(path))
+    }
+
+    /// Returns an iterator over every entry in the compound file (including
+    /// the root), in preorder.
+    pub fn entries(&self) -> ResultEntries<'_, F> {
+        ResultEntries { entries: self.comp.walk() }
+    }
+
+    /// Returns the names of the children of the storage at the given path.
+    pub fn list_names(&self, path: &str) -> io::Result<Vec<String>> {
+        list_names(&self.comp, Path::new(path))
+    }
+}
+
+impl<F: Seek> CompatFile<F> {
+    /// Opens the stream at the given path.
+    pub fn open_stream(&mut self, path: &str) -> io::Result<Stream<F>> {
+        self.comp.open_stream(path)
+    }
+}
+
+impl<F: Read + Seek> CompatFile<F> {
+    /// Reads the whole contents of the stream at the given path.
+    pub fn read_stream(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        self.comp.read_stream_to_vec(path)
+    }
+}
+
+//===========================================================================//
+
+/// A handle to one storage within a [`CompatFile`], through which its
+/// children are opened by name.
+pub struct Storage<'a, F> {
+    comp: &'a mut CompoundFile<F>,
+    path: PathBuf,
+}
+
+impl<'a, F> Storage<'a, F> {
+    fn open(comp: &'a mut CompoundFile<F>, path: &Path) -> io::Result<Self> {
+        // Fail early, with the usual errors, for a missing object or for a
+        // stream.
+        comp.read_storage(path)?;
+        let entry = comp.entry(path)?;
+        Ok(Storage { comp, path: entry.path().to_path_buf() })
+    }
+
+    fn child_path(&self, name: &str) -> io::Result<PathBuf> {
+        internal::path::validate_name(name)?;
+        Ok(self.path.join(name))
+    }
+
+    /// Returns the path of this storage within the compound file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the directory entry for this storage.
+    pub fn entry(&self) -> io::Result<Entry> {
+        self.comp.entry(&self.path)
+    }
+
+    /// Returns a handle to the child storage with the given name.
+    pub fn open_storage(&mut self, name: &str) -> io::Result<Storage<'_, F>> {
+        let path = self.child_path(name)?;
+        Storage::open(self.comp, &path)
+    }
+
+    /// Returns an iterator over the children of this storage.
+    pub fn entries(&self) -> io::Result<ResultEntries<'_, F>> {
+        let entries = self.comp.read_storage(&self.path)?;
+        Ok(ResultEntries { entries })
+    }
+
+    /// Returns the names of the children of this storage.
+    pub fn list_names(&self) -> io::Result<Vec<String>> {
+        list_names(self.comp, &self.path)
+    }
+}
+
+impl<'a, F: Seek> Storage<'a, F> {
+    /// Opens the child stream with the given name.
+    pub fn open_stream(&mut self, name: &str) -> io::Result<Stream<F>> {
+        let path = self.child_path(name)?;
+        self.comp.open_stream(path)
+    }
+}
+
+impl<'a, F: Read + Seek> Storage<'a, F> {
+    /// Reads the whole contents of the child stream with the given name.
+    pub fn read_stream(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let path = self.child_path(name)?;
+        self.comp.read_stream_to_vec(path)
+    }
+}
+
+//===========================================================================//
+
+/// An iterator over entries that yields `io::Result` items, for code that
+/// expects fallible iteration.  Created by [`CompatFile::entries`] and
+/// [`Storage::entries`].
+///
+/// Since the directory is validated when a compound file is opened, this
+/// iterator never actually yields an error.
+pub struct ResultEntries<'a, F: 'a> {
+    entries: Entries<'a, F>,
+}
+
+impl<'a, F> Iterator for ResultEntries<'a, F> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        self.entries.next().map(Ok)
+    }
+}
+
+impl<'a, F> FusedIterator for ResultEntries<'a, F> {}
+
+//===========================================================================//
+
+fn list_names<F>(
+    comp: &CompoundFile<F>,
+    path: &Path,
+) -> io::Result<Vec<String>> {
+    Ok(comp
+        .read_storage(path)?
+        .map(|entry| entry.name().to_string())
+        .collect())
+}
+
+//===========================================================================//
@@ -0,0 +1,178 @@
+use crate::internal::try_zeroed_vec;
+use std::fmt;
+use std::io;
+use std::ops::{Deref, DerefMut};
+
+//===========================================================================//
+
+/// An owned byte buffer whose start is aligned to a given power of two, as
+/// returned by
+/// [`CompoundFile::read_stream_aligned`](../struct.CompoundFile.html#method.read_stream_aligned).
+/// This is useful for zero-copy formats (such as FlatBuffers or rkyv
+/// archives) that need their data aligned in memory.
+///
+/// The buffer derefs to a slice of exactly the length it was created with.
+/// It is allocated with room to spare, so that an aligned slice of that
+/// length can be carved out of it; the alignment holds for as long as the
+/// buffer lives, even if the `AlignedBytes` value itself is moved.
+pub struct AlignedBytes {
+    buffer: Vec<u8>,
+    offset: usize,
+    len: usize,
+    align: usize,
+}
+
+impl AlignedBytes {
+    /// Returns a buffer of `len` zero bytes, aligned to `align` bytes.
+    /// Fails with an `InvalidInput` error if `align` isn't a power of two,
+    /// or with an `OutOfMemory` error if the buffer can't be allocated.
+    pub fn zeroed(len: usize, align: usize) -> io::Result<AlignedBytes> {
+        if !align.is_power_of_two() {
+            invalid_input!("Alignment {} is not a power of two", align);
+        }
+        let Some(capacity) = len.checked_add(align - 1) else {
+            out_of_memory!("Cannot allocate {} aligned bytes", len);
+        };
+        let buffer = try_zeroed_vec(capacity, "aligned data")?;
+        let offset = (align - buffer.as_ptr() as usize % align) % align;
+        Ok(AlignedBytes { buffer, offset, len, align })
+    }
+
+    /// Returns a copy of `data`, aligned to `align` bytes (see
+    /// [`zeroed`](#method.zeroed)).  For example, copying an entire compound
+    /// file into an aligned buffer lets
+    /// [`CompoundFile::stream_slice_aligned`](../struct.CompoundFile.html#method.stream_slice_aligned)
+    /// borrow suitably placed streams directly from it.
+    pub fn copy_from_slice(
+        data: &[u8],
+        align: usize,
+    ) -> io::Result<AlignedBytes> {
+        let mut bytes = AlignedBytes::zeroed(data.len(), align)?;
+        bytes[..].copy_from_slice(data);
+        Ok(bytes)
+    }
+
+    /// Returns the alignment that the buffer was created with.  (The data
+    /// may happen to be more strictly aligned than this.)
+    pub fn align(&self) -> usize {
+        self.align
+    }
+}
+
+impl Deref for AlignedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[self.offset..self.offset + self.len]
+    }
+}
+
+impl DerefMut for AlignedBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer[self.offset..self.offset + self.len]
+    }
+}
+
+impl AsRef<[u8]> for AlignedBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for AlignedBytes {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("AlignedBytes")
+            .field("len", &self.len)
+            .field("align", &self.align)
+            .finish()
+    }
+}
+
+//===========================================================================//
+
+/// The contents of a stream, aligned in memory, as returned by
+/// [`CompoundFile::stream_slice_aligned`](../struct.CompoundFile.html#method.stream_slice_aligned):
+/// either borrowed directly from the in-memory file, or copied out of it.
+/// Either way, it derefs to a suitably aligned slice holding exactly the
+/// stream's data.
+#[derive(Debug)]
+pub enum AlignedSlice<'a> {
+    /// The stream's data is contiguous within the file, and was already
+    /// aligned.
+    Borrowed(&'a [u8]),
+    /// The stream's data had to be copied to align it (or to gather it from
+    /// sectors that weren't contiguous).
+    Owned(AlignedBytes),
+}
+
+impl AlignedSlice<'_> {
+    /// Returns true if the data was borrowed from the file without copying.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self, AlignedSlice::Borrowed(_))
+    }
+}
+
+impl Deref for AlignedSlice<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            AlignedSlice::Borrowed(slice) => slice,
+            AlignedSlice::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for AlignedSlice<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+//===========================================================================//
+
+/// Returns true if the given slice starts at a multiple of `align` bytes.
+pub fn is_aligned(slice: &[u8], align: usize) -> bool {
+    slice.as_ptr() as usize % align == 0
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{is_aligned, AlignedBytes};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn zeroed_is_aligned() {
+        for align in [1, 2, 8, 64, 4096] {
+            for len in [0, 1, 100, 5000] {
+                let bytes = AlignedBytes::zeroed(len, align).unwrap();
+                assert_eq!(bytes.len(), len);
+                assert_eq!(bytes.align(), align);
+                assert!(is_aligned(&bytes, align));
+                assert!(bytes.iter().all(|&byte| byte == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn alignment_survives_moves() {
+        let data: Vec<u8> = (0..200).collect();
+        let bytes = AlignedBytes::copy_from_slice(&data, 64).unwrap();
+        let moved = Box::new(bytes);
+        assert!(is_aligned(&moved, 64));
+        assert_eq!(&moved[..], &data[..]);
+    }
+
+    #[test]
+    fn alignment_must_be_power_of_two() {
+        for align in [0, 3, 48] {
+            let error = AlignedBytes::zeroed(10, align).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
+
+//===========================================================================//
@@ -1,10 +1,14 @@
 use crate::internal::{
-    consts, Chain, Sector, SectorInit, Sectors, Validation, Version,
+    consts, next_in_chain, recover, AllocContext, Chain, ChainName,
+    FileTooLarge, FirstFree, Metrics, ReadOnly, RecoveryWarning, Sector,
+    SectorAllocator, SectorId, SectorInit, SectorPurpose, Sectors, Validation,
+    ValidationIssue, ValidationIssueKind, Version,
 };
 use crate::WriteLeNumber;
 use fnv::FnvHashSet;
-use std::io::{self, Seek, Write};
-use std::mem::size_of;
+use std::collections::BTreeSet;
+use std::io::{self, Read, Seek, Write};
+use std::path::PathBuf;
 
 //===========================================================================//
 
@@ -24,6 +28,48 @@ pub struct Allocator<F> {
     difat_sector_ids: Vec<u32>,
     difat: Vec<u32>,
     fat: Vec<u32>,
+    free_sectors: BTreeSet<u32>,
+    policy: Option<Box<dyn SectorAllocator>>,
+    stream_path: Option<PathBuf>,
+    /// The FAT entries changed since the last call to
+    /// `take_touched_sectors`, if changes are being tracked.
+    touched_sectors: Option<FnvHashSet<u32>>,
+    /// The writes held back while allocating a run of sectors (see
+    /// `extend_chain_by`).
+    pending: Option<PendingWrites>,
+    /// Incremented whenever the FAT changes, so that `chain_cache` can tell
+    /// whether it is still accurate.
+    fat_generation: u64,
+    /// The sectors of the chain most recently handed back with
+    /// `cache_chain`, and the `fat_generation` at the time.
+    chain_cache: Option<(u64, Vec<u32>)>,
+    /// Incremented whenever the FAT changes other than by allocating a free
+    /// sector or extending a chain past its end, so that a cached chain is
+    /// still accurate as long as this hasn't changed (though it may since
+    /// have been extended).
+    link_generation: u64,
+    /// True if sectors are overwritten with zeros as they are freed.
+    zero_freed_sectors: bool,
+}
+
+/// Writes held back while allocating a run of sectors, so that they can be
+/// made in as few pieces as possible once the run is complete.
+#[derive(Default)]
+struct PendingWrites {
+    /// FAT entries that have been changed in memory, but not yet in the
+    /// file.
+    fat_entries: BTreeSet<u32>,
+    /// A run of consecutive sectors (as its first sector and length) that
+    /// have been allocated, but not yet zero-filled.
+    zero_run: Option<(u32, u32)>,
+}
+
+/// What a sector is being allocated for; see `Allocator::choose_sector`.
+#[derive(Clone, Copy)]
+enum SectorUse {
+    Fat,
+    Difat,
+    Chain(SectorInit),
 }
 
 impl<F> Allocator<F> {
@@ -33,12 +79,36 @@ impl<F> Allocator<F> {
         difat: Vec<u32>,
         fat: Vec<u32>,
         validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
     ) -> io::Result<Allocator<F>> {
-        let mut alloc = Allocator { sectors, difat_sector_ids, difat, fat };
-        alloc.validate(validation)?;
+        let mut alloc = Allocator {
+            sectors,
+            difat_sector_ids,
+            difat,
+            fat,
+            free_sectors: BTreeSet::new(),
+            policy: None,
+            stream_path: None,
+            touched_sectors: None,
+            pending: None,
+            fat_generation: 0,
+            chain_cache: None,
+            link_generation: 0,
+            zero_freed_sectors: false,
+        };
+        alloc.validate(validation, issues)?;
+        alloc.free_sectors = free_indices(&alloc.fat);
         Ok(alloc)
     }
 
+    pub fn zero_freed_sectors(&self) -> bool {
+        self.zero_freed_sectors
+    }
+
+    pub fn set_zero_freed_sectors(&mut self, zero: bool) {
+        self.zero_freed_sectors = zero;
+    }
+
     pub fn version(&self) -> Version {
         self.sectors.version()
     }
@@ -47,11 +117,74 @@ impl<F> Allocator<F> {
         self.sectors.inner()
     }
 
+    pub fn metrics(&self) -> &Metrics {
+        self.sectors.metrics()
+    }
+
+    pub fn metrics_mut(&mut self) -> &mut Metrics {
+        self.sectors.metrics_mut()
+    }
+
+    /// Installs a policy for choosing where new sectors are allocated,
+    /// replacing the default (`FirstFree`) behavior.
+    pub fn set_policy(&mut self, policy: Box<dyn SectorAllocator>) {
+        self.policy = Some(policy);
+    }
+
+    /// Returns true if a policy other than the default has been installed.
+    pub fn has_policy(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Sets the path of the stream whose data is being written, which is
+    /// reported to the policy for sectors allocated with `SectorInit::Zero`
+    /// (with no path set, those are taken to be for the mini stream).
+    /// Returns the previously set path.
+    pub fn set_stream_path(
+        &mut self,
+        path: Option<PathBuf>,
+    ) -> Option<PathBuf> {
+        std::mem::replace(&mut self.stream_path, path)
+    }
+
     pub fn sector_len(&self) -> usize {
         self.sectors.sector_len()
     }
 
-    pub fn next(&self, sector_id: u32) -> io::Result<u32> {
+    /// Sets whether to keep track of which FAT entries are changed, for
+    /// `take_touched_sectors`.
+    pub fn set_track_changes(&mut self, track: bool) {
+        self.touched_sectors =
+            if track { Some(FnvHashSet::default()) } else { None };
+    }
+
+    /// Returns the FAT entries changed since the last call (if changes are
+    /// being tracked), in order.
+    pub fn take_touched_sectors(&mut self) -> Vec<u32> {
+        let mut sector_ids: Vec<u32> = match self.touched_sectors.as_mut() {
+            Some(touched) => touched.drain().collect(),
+            None => Vec::new(),
+        };
+        sector_ids.sort_unstable();
+        sector_ids
+    }
+
+    /// Returns a description of each way in which the given FAT entries (or
+    /// all of them, if `None`) are inconsistent with each other or with the
+    /// free sector index.
+    pub fn fat_problems(&self, sector_ids: Option<&[u32]>) -> Vec<String> {
+        table_problems("FAT", &self.fat, &self.free_sectors, sector_ids)
+    }
+
+    /// Returns the sector following the given one, which is at the given
+    /// position within the named chain (or `END_OF_CHAIN` if the chain ends
+    /// there).
+    pub fn next(
+        &self,
+        sector_id: u32,
+        chain: ChainName<'_>,
+        position: usize,
+    ) -> io::Result<u32> {
         let index = sector_id as usize;
         if index >= self.fat.len() {
             invalid_data!(
@@ -60,20 +193,55 @@ impl<F> Allocator<F> {
                 self.fat.len()
             );
         }
-        let next_id = self.fat[index];
-        if next_id != consts::END_OF_CHAIN
-            && (next_id > consts::MAX_REGULAR_SECTOR
-                || next_id as usize >= self.fat.len())
-        {
-            invalid_data!("next_id ({}) is invalid", next_id);
-        }
-        Ok(next_id)
+        next_in_chain(chain, position + 1, self.fat[index], self.fat.len())
     }
 
     pub fn into_inner(self) -> F {
         self.sectors.into_inner()
     }
 
+    pub fn fat(&self) -> &[u32] {
+        &self.fat
+    }
+
+    /// Salvages the chain starting at the given sector (see
+    /// `recover::salvage_chain`), ending it early in the in-memory FAT if it
+    /// is damaged.
+    pub fn salvage_chain(
+        &mut self,
+        start_sector_id: u32,
+        chain: ChainName<'_>,
+    ) -> (Vec<u32>, Option<RecoveryWarning>) {
+        self.fat_generation += 1;
+        self.link_generation += 1;
+        recover::salvage_chain(&mut self.fat, start_sector_id, chain)
+    }
+
+    pub fn set_read_only(&mut self, reason: ReadOnly) {
+        self.sectors.set_read_only(reason);
+    }
+
+    /// Returns the IDs of the sectors in the chain starting at the given
+    /// sector, in order.
+    pub fn chain_sector_ids(
+        &self,
+        start_sector_id: u32,
+        chain: ChainName<'_>,
+    ) -> io::Result<Vec<u32>> {
+        let mut sector_ids = Vec::new();
+        let mut current_sector_id = start_sector_id;
+        while current_sector_id != consts::END_OF_CHAIN {
+            if sector_ids.len() > self.fat.len() {
+                invalid_data!("The {} contains a loop", chain);
+            }
+            let position = sector_ids.len();
+            sector_ids.push(current_sector_id);
+            current_sector_id =
+                self.next(current_sector_id, chain, position)?;
+        }
+        Ok(sector_ids)
+    }
+
     pub fn open_chain(
         &mut self,
         start_sector_id: u32,
@@ -82,7 +250,45 @@ impl<F> Allocator<F> {
         Chain::new(self, start_sector_id, init)
     }
 
-    fn validate(&mut self, validation: Validation) -> io::Result<()> {
+    /// Returns a counter that changes whenever an existing link in the FAT
+    /// is changed or removed, but not when a chain is extended past its end
+    /// or a free sector is allocated.
+    pub fn link_generation(&self) -> u64 {
+        self.link_generation
+    }
+
+    /// Remembers the sectors of a chain (as last seen by a `Chain`), so
+    /// that reopening the chain doesn't have to follow it through the FAT
+    /// again, as long as the FAT hasn't changed in the meantime.
+    pub fn cache_chain(&mut self, sector_ids: Vec<u32>) {
+        if !sector_ids.is_empty() {
+            self.chain_cache = Some((self.fat_generation, sector_ids));
+        }
+    }
+
+    /// Returns the sectors of the chain starting at the given sector, if
+    /// that is the chain most recently passed to `cache_chain` and the FAT
+    /// hasn't changed since.
+    pub fn take_cached_chain(
+        &mut self,
+        start_sector_id: u32,
+    ) -> Option<Vec<u32>> {
+        match self.chain_cache {
+            Some((generation, ref sector_ids))
+                if generation == self.fat_generation
+                    && sector_ids.first() == Some(&start_sector_id) =>
+            {
+                self.chain_cache.take().map(|(_, sector_ids)| sector_ids)
+            }
+            _ => None,
+        }
+    }
+
+    fn validate(
+        &mut self,
+        validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
+    ) -> io::Result<()> {
         if self.fat.len() > self.sectors.num_sectors() as usize {
             malformed!(
                 "FAT has {} entries, but file has only {} sectors",
@@ -90,6 +296,16 @@ impl<F> Allocator<F> {
                 self.sectors.num_sectors()
             );
         }
+        let fat_capacity =
+            self.difat.len() * self.version().fat_entries_per_sector();
+        if self.fat.len() > fat_capacity {
+            malformed!(
+                "FAT has {} entries, but its {} sectors can only hold {}",
+                self.fat.len(),
+                self.difat.len(),
+                fat_capacity
+            );
+        }
         for &difat_sector in self.difat_sector_ids.iter() {
             let difat_sector_index = difat_sector as usize;
             let Some(sector) = self.fat.get_mut(difat_sector_index) else {
@@ -99,10 +315,22 @@ impl<F> Allocator<F> {
                     difat_sector
                 );
             };
-            if *sector != consts::DIFAT_SECTOR && validation.is_strict() {
-                malformed!(
-                    "DIFAT sector {} is not marked as such in the FAT",
-                    difat_sector
+            if *sector != consts::DIFAT_SECTOR {
+                if validation.is_strict() {
+                    malformed!(
+                        "DIFAT sector {} is not marked as such in the FAT",
+                        difat_sector
+                    );
+                }
+                issues.push(
+                    ValidationIssue::new(
+                        ValidationIssueKind::SectorNotMarked,
+                        format!(
+                            "DIFAT sector {} is not marked as such in the FAT",
+                            difat_sector
+                        ),
+                    )
+                    .with_sector_id(difat_sector),
                 );
             }
             *sector = consts::DIFAT_SECTOR;
@@ -116,10 +344,22 @@ impl<F> Allocator<F> {
                     fat_sector
                 );
             };
-            if *sector != consts::FAT_SECTOR && validation.is_strict() {
-                malformed!(
-                    "FAT sector {} is not marked as such in the FAT",
-                    fat_sector
+            if *sector != consts::FAT_SECTOR {
+                if validation.is_strict() {
+                    malformed!(
+                        "FAT sector {} is not marked as such in the FAT",
+                        fat_sector
+                    );
+                }
+                issues.push(
+                    ValidationIssue::new(
+                        ValidationIssueKind::SectorNotMarked,
+                        format!(
+                            "FAT sector {} is not marked as such in the FAT",
+                            fat_sector
+                        ),
+                    )
+                    .with_sector_id(fat_sector),
                 );
             }
             *sector = consts::FAT_SECTOR;
@@ -148,6 +388,18 @@ impl<F> Allocator<F> {
 }
 
 impl<F: Seek> Allocator<F> {
+    pub fn check_backing_len(&mut self) -> io::Result<()> {
+        self.sectors.check_backing_len()
+    }
+
+    pub fn backing_len(&mut self) -> io::Result<u64> {
+        self.sectors.backing_len()
+    }
+
+    pub fn diagnose_read_error(&mut self, error: io::Error) -> io::Error {
+        self.sectors.diagnose_read_error(error)
+    }
+
     pub fn seek_within_header(
         &mut self,
         offset_within_header: u64,
@@ -188,7 +440,95 @@ impl<F: Seek> Allocator<F> {
     }
 }
 
+impl<F: Read + Seek> Allocator<F> {
+    pub fn read_span(
+        &mut self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        self.sectors.read_span(offset, buf)
+    }
+}
+
 impl<F: Write + Seek> Allocator<F> {
+    pub fn repair_backing_len(&mut self) -> io::Result<u64> {
+        self.sectors.repair_backing_len()
+    }
+
+    pub fn truncate_backing(
+        &mut self,
+        set_len: fn(&mut F, u64) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        self.sectors.truncate_backing(set_len)
+    }
+
+    /// Replaces the underlying file's contents with those of `other`, a
+    /// compacted copy of this file (see `CompactLayout`), using `install`
+    /// (see `Sectors::adopt_compacted`), and takes on its FAT and DIFAT,
+    /// keeping this allocator's policy.
+    pub fn adopt_compacted<G, I>(
+        &mut self,
+        other: Allocator<G>,
+        install: I,
+    ) -> io::Result<()>
+    where
+        I: FnOnce(&mut F, G) -> io::Result<()>,
+    {
+        self.sectors.adopt_compacted(other.sectors, install)?;
+        self.difat_sector_ids = other.difat_sector_ids;
+        self.difat = other.difat;
+        self.fat = other.fat;
+        self.fat_generation += 1;
+        self.link_generation += 1;
+        self.free_sectors = other.free_sectors;
+        if let Some(touched) = self.touched_sectors.as_mut() {
+            touched.clear();
+        }
+        Ok(())
+    }
+
+    /// Overwrites every free sector with zeros, and returns the number of
+    /// bytes overwritten.
+    pub fn wipe_free_sectors(&mut self) -> io::Result<u64> {
+        let num_sectors = self.sectors.num_sectors();
+        let free_sectors: Vec<u32> = self
+            .free_sectors
+            .iter()
+            .copied()
+            .filter(|&sector_id| sector_id < num_sectors)
+            .collect();
+        for &sector_id in free_sectors.iter() {
+            self.sectors.init_sector(sector_id, SectorInit::Zero)?;
+        }
+        Ok(free_sectors.len() as u64 * self.sector_len() as u64)
+    }
+
+    /// Overwrites everything in the chain starting at the given sector past
+    /// its first `len` bytes with zeros, and returns the number of bytes
+    /// overwritten.
+    pub fn wipe_chain_slack(
+        &mut self,
+        start_sector_id: u32,
+        len: u64,
+        chain: ChainName<'_>,
+    ) -> io::Result<u64> {
+        let sector_len = self.sector_len();
+        let zeros = vec![0u8; sector_len];
+        let mut num_bytes = 0;
+        let sector_ids = self.chain_sector_ids(start_sector_id, chain)?;
+        for (index, &sector_id) in sector_ids.iter().enumerate() {
+            let start = index as u64 * sector_len as u64;
+            let used = len.saturating_sub(start).min(sector_len as u64);
+            if used < sector_len as u64 {
+                let mut sector =
+                    self.sectors.seek_within_sector(sector_id, used)?;
+                sector.write_all(&zeros[used as usize..])?;
+                num_bytes += sector_len as u64 - used;
+            }
+        }
+        Ok(num_bytes)
+    }
+
     /// Allocates a new chain with one sector, and returns the starting sector
     /// number.
     pub fn begin_chain(&mut self, init: SectorInit) -> io::Result<u32> {
@@ -217,44 +557,189 @@ impl<F: Write + Seek> Allocator<F> {
         Ok(new_sector_id)
     }
 
+    /// Extends the chain made up of the given sectors (or begins a new
+    /// chain, if there are none) by `count` sectors, appending the new
+    /// sectors to `sector_ids`.  This is equivalent to calling
+    /// `extend_chain` (or `begin_chain`) `count` times, but zero-fills each
+    /// run of consecutive new sectors with a single write, and writes each
+    /// changed stretch of each FAT sector only once, after the sectors it
+    /// describes.  If this fails partway through, `sector_ids` still
+    /// reflects the sectors that were added.
+    pub fn extend_chain_by(
+        &mut self,
+        sector_ids: &mut Vec<u32>,
+        count: usize,
+        init: SectorInit,
+    ) -> io::Result<()> {
+        debug_assert!(self.pending.is_none());
+        self.pending = Some(PendingWrites::default());
+        let mut result = Ok(());
+        for _ in 0..count {
+            let last_sector_id = sector_ids.last().copied();
+            result = self.allocate_sector(init).and_then(|new_sector_id| {
+                sector_ids.push(new_sector_id);
+                match last_sector_id {
+                    Some(last_sector_id) => {
+                        debug_assert_eq!(
+                            self.fat[last_sector_id as usize],
+                            consts::END_OF_CHAIN
+                        );
+                        self.set_fat(last_sector_id, new_sector_id)
+                    }
+                    None => Ok(()),
+                }
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+        // Whatever happened, make the file match the in-memory FAT.
+        let written = self.write_pending();
+        result.and(written)
+    }
+
     /// Allocates a new entry in the FAT, sets its value to `END_OF_CHAIN`, and
     /// returns the new sector number.
     fn allocate_sector(&mut self, init: SectorInit) -> io::Result<u32> {
-        // If there's an existing free sector, use that.
-        for sector_id in 0..self.fat.len() {
-            if self.fat[sector_id] == consts::FREE_SECTOR {
-                let sector_id = sector_id as u32;
+        let fat_entries_per_sector = self.version().fat_entries_per_sector();
+        loop {
+            // If the policy chose an existing free sector, use that.
+            let sector_id = self.choose_sector(SectorUse::Chain(init))?;
+            if (sector_id as usize) < self.fat.len() {
                 self.set_fat(sector_id, consts::END_OF_CHAIN)?;
-                self.sectors.init_sector(sector_id, init)?;
+                self.init_sector(sector_id, init)?;
                 return Ok(sector_id);
             }
+            self.check_growth(sector_id)?;
+            // Otherwise, grow the file up to the chosen sector, marking any
+            // sectors skipped over as free.
+            while self.fat.len() < sector_id as usize {
+                if self.fat.len() >= self.difat.len() * fat_entries_per_sector
+                {
+                    self.append_fat_sector()?;
+                    continue;
+                }
+                let skipped = self.fat.len() as u32;
+                self.init_sector(skipped, SectorInit::Zero)?;
+                self.set_fat(skipped, consts::FREE_SECTOR)?;
+            }
+            // If there's not room in the FAT to add the new sector, then
+            // first we need to allocate a new FAT sector.  That may use up
+            // the chosen sector, so then ask the policy again.
+            if self.fat.len() >= self.difat.len() * fat_entries_per_sector {
+                self.append_fat_sector()?;
+                continue;
+            }
+            // Add a new sector to the end of the file and return it.  The
+            // sector is written before the FAT mentions it, so that the FAT
+            // never describes more sectors than the file has.
+            let new_sector = self.fat.len() as u32;
+            self.init_sector(new_sector, init)?;
+            self.set_fat(new_sector, consts::END_OF_CHAIN)?;
+            return Ok(new_sector);
+        }
+    }
+
+    /// Returns a `FileTooLarge` error if growing the file up to the given
+    /// sector (along with any FAT and DIFAT sectors needed to describe it)
+    /// would make the file longer than its version allows.  This assumes
+    /// that any new FAT and DIFAT sectors go at the end of the file, which
+    /// they do unless there are free sectors for them.
+    fn check_growth(&self, sector_id: u32) -> io::Result<()> {
+        let version = self.version();
+        let num_sectors = self.num_sectors_after_growth(sector_id);
+        if num_sectors > version.max_num_sectors() {
+            let sector_len = version.sector_len() as u64;
+            let required = (num_sectors + 1) * sector_len;
+            return Err(FileTooLarge::new(version, required).into());
         }
-        // Otherwise, we need a new sector; if there's not room in the FAT to
-        // add it, then first we need to allocate a new FAT sector.
+        Ok(())
+    }
+
+    /// Returns the number of sectors that the file will have once it has
+    /// grown up to the given sector, counting any new FAT and DIFAT sectors
+    /// needed to describe it as appended to the end.
+    fn num_sectors_after_growth(&self, sector_id: u32) -> u64 {
         let fat_entries_per_sector =
-            self.sectors.sector_len() / size_of::<u32>();
-        if self.fat.len() % fat_entries_per_sector == 0 {
-            self.append_fat_sector()?;
+            self.version().fat_entries_per_sector() as u64;
+        let difat_entries_per_sector =
+            self.version().difat_entries_per_sector() as u64;
+        let old_num_fat_sectors = self.difat.len() as u64;
+        let old_num_difat_sectors = self.difat_sector_ids.len() as u64;
+        let mut num_fat_sectors = old_num_fat_sectors;
+        let mut num_difat_sectors = old_num_difat_sectors;
+        loop {
+            let num_sectors = sector_id as u64
+                + 1
+                + (num_fat_sectors - old_num_fat_sectors)
+                + (num_difat_sectors - old_num_difat_sectors);
+            let needed_fat = num_sectors.div_ceil(fat_entries_per_sector);
+            let needed_difat = needed_fat
+                .saturating_sub(consts::NUM_DIFAT_ENTRIES_IN_HEADER as u64)
+                .div_ceil(difat_entries_per_sector);
+            if needed_fat <= num_fat_sectors
+                && needed_difat <= num_difat_sectors
+            {
+                return num_sectors;
+            }
+            num_fat_sectors = num_fat_sectors.max(needed_fat);
+            num_difat_sectors = num_difat_sectors.max(needed_difat);
         }
-        // Add a new sector to the end of the file and return it.
-        let new_sector = self.fat.len() as u32;
-        self.set_fat(new_sector, consts::END_OF_CHAIN)?;
-        self.sectors.init_sector(new_sector, init)?;
-        Ok(new_sector)
+    }
+
+    /// Asks the allocation policy where to put a new sector, and checks that
+    /// the answer is either a free sector or within the growth limit past the
+    /// end of the file.
+    fn choose_sector(&mut self, sector_use: SectorUse) -> io::Result<u32> {
+        let purpose = match sector_use {
+            SectorUse::Fat => SectorPurpose::Fat,
+            SectorUse::Difat => SectorPurpose::Difat,
+            SectorUse::Chain(SectorInit::Dir) => SectorPurpose::Directory,
+            SectorUse::Chain(SectorInit::Fat) => SectorPurpose::MiniFat,
+            SectorUse::Chain(SectorInit::Difat) => SectorPurpose::Difat,
+            SectorUse::Chain(SectorInit::Zero) => match self.stream_path {
+                Some(ref path) => SectorPurpose::Stream(path),
+                None => SectorPurpose::MiniStream,
+            },
+        };
+        let num_sectors = self.fat.len() as u32;
+        let ctx = AllocContext::new(purpose, &self.free_sectors, num_sectors);
+        let sector_id = match self.policy {
+            Some(ref mut policy) => policy.alloc(ctx),
+            None => FirstFree.alloc(ctx),
+        };
+        if sector_id.value() < num_sectors && !ctx.is_free(sector_id) {
+            invalid_input!(
+                "Sector allocator chose sector {} for {:?}, but that sector \
+                 is already in use",
+                sector_id,
+                purpose
+            );
+        } else if sector_id > ctx.max_sector() {
+            invalid_input!(
+                "Sector allocator chose sector {} for {:?}, but the file has \
+                 {} sectors and may only grow up to sector {}",
+                sector_id,
+                purpose,
+                num_sectors,
+                ctx.max_sector()
+            );
+        }
+        Ok(sector_id.value())
     }
 
     /// Adds a new sector to the FAT chain at the end of the file, and updates
     /// the FAT and DIFAT accordingly.
     fn append_fat_sector(&mut self) -> io::Result<()> {
-        // Add a new FAT sector to the end of the file.
-        let new_fat_sector_id = self.fat.len() as u32;
-        self.sectors.init_sector(new_fat_sector_id, SectorInit::Fat)?;
+        // Add a new FAT sector, normally to the end of the file (in which
+        // case it will describe itself).
+        let new_fat_sector_id = self.choose_sector(SectorUse::Fat)?;
+        self.init_sector(new_fat_sector_id, SectorInit::Fat)?;
 
         // Record this new FAT sector in the DIFAT and in the FAT itself.
         let difat_index = self.difat.len();
         self.difat.push(new_fat_sector_id);
         self.set_fat(new_fat_sector_id, consts::FAT_SECTOR)?;
-        debug_assert_eq!(self.fat.len(), new_fat_sector_id as usize + 1);
 
         // Write DIFAT changes to file.
         if difat_index < consts::NUM_DIFAT_ENTRIES_IN_HEADER {
@@ -264,15 +749,16 @@ impl<F: Write + Seek> Allocator<F> {
             header.write_le_u32(new_fat_sector_id)?;
         } else {
             // This DIFAT entry goes in a DIFAT sector.
-            let difat_entries_per_sector = (self.sector_len() - 4) / 4;
+            let difat_entries_per_sector =
+                self.version().difat_entries_per_sector();
             let difat_sector_index = (difat_index
                 - consts::NUM_DIFAT_ENTRIES_IN_HEADER)
                 / difat_entries_per_sector;
             if difat_sector_index >= self.difat_sector_ids.len() {
-                // Add a new DIFAT sector to the end of the file.
-                let new_difat_sector_id = self.fat.len() as u32;
-                self.sectors
-                    .init_sector(new_difat_sector_id, SectorInit::Difat)?;
+                // Add a new DIFAT sector, normally to the end of the file.
+                let new_difat_sector_id =
+                    self.choose_sector(SectorUse::Difat)?;
+                self.init_sector(new_difat_sector_id, SectorInit::Difat)?;
                 // Record this new DIFAT sector in the FAT.
                 self.set_fat(new_difat_sector_id, consts::DIFAT_SECTOR)?;
                 // Add this sector to the end of the DIFAT chain.
@@ -310,7 +796,8 @@ impl<F: Write + Seek> Allocator<F> {
     /// Sets the given sector to point to `END_OF_CHAIN`, and deallocates all
     /// subsequent sectors in the chain.
     pub fn free_chain_after(&mut self, sector_id: u32) -> io::Result<()> {
-        let next = self.next(sector_id)?;
+        let next =
+            self.next(sector_id, ChainName::StartingAt(sector_id), 0)?;
         self.set_fat(sector_id, consts::END_OF_CHAIN)?;
         self.free_chain(next)?;
         Ok(())
@@ -318,55 +805,445 @@ impl<F: Write + Seek> Allocator<F> {
 
     /// Given the start sector of a chain, deallocates the entire chain.
     pub fn free_chain(&mut self, start_sector_id: u32) -> io::Result<()> {
+        let chain = ChainName::StartingAt(start_sector_id);
         let mut sector_id = start_sector_id;
+        let mut position = 0;
         while sector_id != consts::END_OF_CHAIN {
-            let next = self.next(sector_id)?;
+            let next = self.next(sector_id, chain, position)?;
             self.free_sector(sector_id)?;
             sector_id = next;
+            position += 1;
         }
         Ok(())
     }
 
-    /// Deallocates the specified sector.
+    /// Deallocates the specified sector, first overwriting it with zeros if
+    /// `zero_freed_sectors` is set.
     fn free_sector(&mut self, sector_id: u32) -> io::Result<()> {
+        if self.zero_freed_sectors && sector_id < self.sectors.num_sectors() {
+            self.sectors.init_sector(sector_id, SectorInit::Zero)?;
+        }
         self.set_fat(sector_id, consts::FREE_SECTOR)?;
         // TODO: Truncate FAT if last FAT sector is now all free.
         Ok(())
     }
 
+    /// Removes FAT sectors (and any DIFAT sectors that become empty as a
+    /// result) that only describe sectors beyond the end of the file, marking
+    /// them as free.  This is used to release FAT capacity that was reserved
+    /// when the file was created but never needed.
+    pub fn release_unused_fat_sectors(&mut self) -> io::Result<()> {
+        let fat_entries_per_sector = self.version().fat_entries_per_sector();
+        let num_needed =
+            self.fat.len().div_ceil(fat_entries_per_sector).max(1);
+        if self.difat.len() <= num_needed {
+            return Ok(());
+        }
+        self.truncate_difat(num_needed)
+    }
+
+    /// Drops the free sectors at the end of the file, along with any FAT and
+    /// DIFAT sectors that would then only describe dropped sectors, updating
+    /// the header to match; returns the new number of sectors.  The
+    /// underlying file isn't truncated (since `F` may not support that), so
+    /// the caller is responsible for discarding everything past the new end.
+    pub fn release_free_tail(&mut self) -> io::Result<u32> {
+        let fat_entries_per_sector = self.version().fat_entries_per_sector();
+        let difat_entries_per_sector = fat_entries_per_sector - 1;
+        // Which FAT and DIFAT sectors are still needed depends on where the
+        // file ends, but where the file ends also depends on where the needed
+        // FAT and DIFAT sectors are (they're usually near the end, since
+        // they're appended as the file grows, and the last FAT sector often
+        // describes itself).  So start from the end of the last sector that
+        // holds actual data, and move the end out until it covers all the
+        // FAT and DIFAT sectors needed to describe a file of that length.
+        let data_end = self
+            .fat
+            .iter()
+            .rposition(|&next| {
+                next != consts::FREE_SECTOR
+                    && next != consts::FAT_SECTOR
+                    && next != consts::DIFAT_SECTOR
+            })
+            .map_or(0, |sector_id| sector_id + 1);
+        let mut num_sectors = data_end;
+        let num_fat_sectors = loop {
+            let num_fat_sectors = num_sectors
+                .div_ceil(fat_entries_per_sector)
+                .clamp(1, self.difat.len());
+            let num_difat_sectors = num_fat_sectors
+                .saturating_sub(consts::NUM_DIFAT_ENTRIES_IN_HEADER)
+                .div_ceil(difat_entries_per_sector);
+            let new_num_sectors = self.difat[..num_fat_sectors]
+                .iter()
+                .chain(&self.difat_sector_ids[..num_difat_sectors])
+                .map(|&sector_id| sector_id as usize + 1)
+                .fold(data_end, usize::max);
+            if new_num_sectors == num_sectors {
+                break num_fat_sectors;
+            }
+            num_sectors = new_num_sectors;
+        };
+        if num_sectors == self.fat.len() && num_fat_sectors == self.difat.len()
+        {
+            return Ok(num_sectors as u32);
+        }
+        self.fat.truncate(num_sectors);
+        self.fat_generation += 1;
+        self.link_generation += 1;
+        self.free_sectors.split_off(&(num_sectors as u32));
+        self.truncate_difat(num_fat_sectors)?;
+        // The rest of the last remaining FAT sector must be padded with
+        // FREE_SECTOR entries (see MS-CFB section 2.3).
+        let padding = num_fat_sectors * fat_entries_per_sector - num_sectors;
+        if padding > 0 {
+            let offset = 4 * (num_sectors % fat_entries_per_sector) as u64;
+            let last_fat_sector_id = self.difat[num_fat_sectors - 1];
+            let mut sector =
+                self.sectors.seek_within_sector(last_fat_sector_id, offset)?;
+            for _ in 0..padding {
+                sector.write_le_u32(consts::FREE_SECTOR)?;
+            }
+        }
+        self.sectors.truncate(num_sectors as u32);
+        Ok(num_sectors as u32)
+    }
+
+    /// Shortens the DIFAT to the given number of FAT sectors, removing any
+    /// DIFAT sectors that become empty as a result, and marks the removed
+    /// FAT and DIFAT sectors as free (if they're still within the FAT).
+    fn truncate_difat(&mut self, num_fat_sectors: usize) -> io::Result<()> {
+        let fat_entries_per_sector = self.version().fat_entries_per_sector();
+        let difat_entries_per_sector = fat_entries_per_sector - 1;
+        while self.difat.len() > num_fat_sectors {
+            let difat_index = self.difat.len() - 1;
+            let Some(fat_sector_id) = self.difat.pop() else {
+                break;
+            };
+            if difat_index < consts::NUM_DIFAT_ENTRIES_IN_HEADER {
+                let offset = 76 + 4 * difat_index as u64;
+                let mut header = self.sectors.seek_within_header(offset)?;
+                header.write_le_u32(consts::FREE_SECTOR)?;
+            } else {
+                let index = difat_index - consts::NUM_DIFAT_ENTRIES_IN_HEADER;
+                let difat_sector_id =
+                    self.difat_sector_ids[index / difat_entries_per_sector];
+                let offset = 4 * (index % difat_entries_per_sector) as u64;
+                let mut sector = self
+                    .sectors
+                    .seek_within_sector(difat_sector_id, offset)?;
+                sector.write_le_u32(consts::FREE_SECTOR)?;
+            }
+            if (fat_sector_id as usize) < self.fat.len() {
+                self.set_fat(fat_sector_id, consts::FREE_SECTOR)?;
+            }
+        }
+        let num_difat_sectors_needed = self
+            .difat
+            .len()
+            .saturating_sub(consts::NUM_DIFAT_ENTRIES_IN_HEADER)
+            .div_ceil(difat_entries_per_sector);
+        if self.difat_sector_ids.len() > num_difat_sectors_needed {
+            while self.difat_sector_ids.len() > num_difat_sectors_needed {
+                let Some(difat_sector_id) = self.difat_sector_ids.pop() else {
+                    break;
+                };
+                if (difat_sector_id as usize) < self.fat.len() {
+                    self.set_fat(difat_sector_id, consts::FREE_SECTOR)?;
+                }
+            }
+            if let Some(&last_sector_id) = self.difat_sector_ids.last() {
+                let offset = self.sector_len() as u64 - 4;
+                let mut sector =
+                    self.sectors.seek_within_sector(last_sector_id, offset)?;
+                sector.write_le_u32(consts::END_OF_CHAIN)?;
+            }
+            let mut header = self.sectors.seek_within_header(68)?;
+            header.write_le_u32(
+                self.difat_sector_ids
+                    .first()
+                    .copied()
+                    .unwrap_or(consts::END_OF_CHAIN),
+            )?;
+            header.write_le_u32(self.difat_sector_ids.len() as u32)?;
+        }
+        let mut header = self.sectors.seek_within_header(44)?;
+        header.write_le_u32(self.difat.len() as u32)?;
+        Ok(())
+    }
+
     /// Sets `self.fat[index] = value`, and also writes that change to the
     /// underlying file.  The `index` must be <= `self.fat.len()`.
     fn set_fat(&mut self, index: u32, value: u32) -> io::Result<()> {
         let index = index as usize;
         debug_assert!(index <= self.fat.len());
-        let fat_entries_per_sector =
-            self.sectors.sector_len() / size_of::<u32>();
-        let fat_sector_id = self.difat[index / fat_entries_per_sector];
-        let offset_within_sector = 4 * (index % fat_entries_per_sector) as u64;
-        let mut sector = self
-            .sectors
-            .seek_within_sector(fat_sector_id, offset_within_sector)?;
-        sector.write_le_u32(value)?;
+        let fat_sector_id = self.fat_sector_for(index)?;
+        if let Some(pending) = self.pending.as_mut() {
+            pending.fat_entries.insert(index as u32);
+        } else {
+            let fat_entries_per_sector =
+                self.version().fat_entries_per_sector();
+            let offset_within_sector =
+                4 * (index % fat_entries_per_sector) as u64;
+            let mut sector = self
+                .sectors
+                .seek_within_sector(fat_sector_id, offset_within_sector)?;
+            sector.write_le_u32(value)?;
+        }
+        self.fat_generation += 1;
+        let old_value =
+            self.fat.get(index).copied().unwrap_or(consts::FREE_SECTOR);
+        let is_extension = old_value == consts::END_OF_CHAIN
+            && value <= consts::MAX_REGULAR_SECTOR;
+        if old_value != consts::FREE_SECTOR && !is_extension {
+            self.link_generation += 1;
+        }
         if index == self.fat.len() {
             self.fat.push(value);
         } else {
             self.fat[index] = value;
         }
+        if let Some(touched) = self.touched_sectors.as_mut() {
+            touched.insert(index as u32);
+        }
+        if value == consts::FREE_SECTOR {
+            let was_free = !self.free_sectors.insert(index as u32);
+            if let (false, Some(policy)) = (was_free, self.policy.as_mut()) {
+                policy.free(SectorId::new(index as u32));
+            }
+        } else {
+            self.free_sectors.remove(&(index as u32));
+        }
         Ok(())
     }
 
+    /// Returns the ID of the FAT sector that holds the given FAT entry, or
+    /// an error if the entry is past the end of the last FAT sector.
+    fn fat_sector_for(&self, index: usize) -> io::Result<u32> {
+        let fat_entries_per_sector = self.version().fat_entries_per_sector();
+        match self.difat.get(index / fat_entries_per_sector) {
+            Some(&fat_sector_id) => Ok(fat_sector_id),
+            None => malformed!(
+                "FAT entry {} is past the end of its {} sectors",
+                index,
+                self.difat.len()
+            ),
+        }
+    }
+
+    /// Initializes the given sector, or, while allocating a run of sectors
+    /// (see `extend_chain_by`), holds back zero-filling it if it continues
+    /// the run of sectors waiting to be zero-filled.
+    fn init_sector(
+        &mut self,
+        sector_id: u32,
+        init: SectorInit,
+    ) -> io::Result<()> {
+        if let (Some(pending), SectorInit::Zero) =
+            (self.pending.as_mut(), init)
+        {
+            if let Some((first, ref mut count)) = pending.zero_run {
+                if first + *count == sector_id {
+                    *count += 1;
+                    return Ok(());
+                }
+            }
+        }
+        self.write_pending_zeros()?;
+        let within_file = sector_id <= self.sectors.num_sectors();
+        match self.pending.as_mut() {
+            Some(pending)
+                if matches!(init, SectorInit::Zero) && within_file =>
+            {
+                pending.zero_run = Some((sector_id, 1));
+                Ok(())
+            }
+            _ => self.sectors.init_sector(sector_id, init),
+        }
+    }
+
+    /// Zero-fills the run of sectors held back by `init_sector`, if any.
+    fn write_pending_zeros(&mut self) -> io::Result<()> {
+        let zero_run = self.pending.as_mut().and_then(|p| p.zero_run.take());
+        match zero_run {
+            Some((first, count)) => self.sectors.zero_sectors(first, count),
+            None => Ok(()),
+        }
+    }
+
+    /// Makes all writes held back while allocating a run of sectors: first
+    /// the sectors themselves, then the FAT entries describing them, with
+    /// one write for each FAT sector, covering all of its changed entries.
+    fn write_pending(&mut self) -> io::Result<()> {
+        self.write_pending_zeros()?;
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        let fat_entries_per_sector = self.version().fat_entries_per_sector();
+        let mut indices = pending.fat_entries.into_iter().peekable();
+        while let Some(first) = indices.next() {
+            let fat_sector_index = first as usize / fat_entries_per_sector;
+            let mut last = first;
+            while let Some(&next) = indices.peek() {
+                if next as usize / fat_entries_per_sector != fat_sector_index {
+                    break;
+                }
+                last = next;
+                indices.next();
+            }
+            let mut data = Vec::with_capacity(4 * (last - first + 1) as usize);
+            for &value in &self.fat[first as usize..=last as usize] {
+                data.write_le_u32(value)?;
+            }
+            let fat_sector_id = self.fat_sector_for(first as usize)?;
+            let offset_within_sector =
+                4 * (first as usize % fat_entries_per_sector) as u64;
+            let mut sector = self
+                .sectors
+                .seek_within_sector(fat_sector_id, offset_within_sector)?;
+            sector.write_all(&data)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `buf` to the file starting at the given offset within the
+    /// given sector, continuing on into the sectors that physically follow
+    /// it.  All of the sectors must already exist.
+    pub fn write_span(
+        &mut self,
+        sector_id: u32,
+        offset_within_sector: u64,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        let sector_len = self.sector_len() as u64;
+        let offset =
+            (sector_id as u64 + 1) * sector_len + offset_within_sector;
+        self.sectors.write_span(offset, buf)
+    }
+
     /// Flushes all changes to the underlying file.
     pub fn flush(&mut self) -> io::Result<()> {
         self.sectors.flush()
     }
 }
 
+impl<F: Read + Write + Seek> Allocator<F> {
+    /// Zeroes the header's CLSID, reserved field, and transaction signature,
+    /// none of which this crate uses, and returns true if any of them were
+    /// nonzero.
+    pub fn reset_unused_header_fields(&mut self) -> io::Result<bool> {
+        let mut was_nonzero = false;
+        for &(offset, len) in [(8, 16), (34, 6), (52, 4)].iter() {
+            let mut buffer = [0u8; 16];
+            let buffer = &mut buffer[..len];
+            self.sectors.seek_within_header(offset)?.read_exact(buffer)?;
+            if buffer.iter().any(|&byte| byte != 0) {
+                was_nonzero = true;
+                buffer.fill(0);
+                self.sectors.seek_within_header(offset)?.write_all(buffer)?;
+            }
+        }
+        Ok(was_nonzero)
+    }
+}
+
+//===========================================================================//
+
+/// Returns the set of indices of all `FREE_SECTOR` entries in the given FAT
+/// or MiniFAT.
+pub(crate) fn free_indices(table: &[u32]) -> BTreeSet<u32> {
+    table
+        .iter()
+        .enumerate()
+        .filter(|&(_, &next)| next == consts::FREE_SECTOR)
+        .map(|(index, _)| index as u32)
+        .collect()
+}
+
+/// Returns a description of each way in which the given entries (or all of
+/// them, if `indices` is `None`) of a FAT or MiniFAT are inconsistent with
+/// the rest of the table or with its index of free entries.  Checking all
+/// entries also checks that no entry is pointed to twice.
+pub(crate) fn table_problems(
+    name: &str,
+    table: &[u32],
+    free: &BTreeSet<u32>,
+    indices: Option<&[u32]>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    let all_indices: Vec<u32>;
+    let indices = match indices {
+        Some(indices) => indices,
+        None => {
+            all_indices = (0..table.len() as u32)
+                .chain(free.range(table.len() as u32..).copied())
+                .collect();
+            &all_indices
+        }
+    };
+    for &index in indices {
+        let Some(&next) = table.get(index as usize) else {
+            if free.contains(&index) {
+                problems.push(format!(
+                    "{} has {} entries, but entry {} is listed as free",
+                    name,
+                    table.len(),
+                    index
+                ));
+            }
+            continue;
+        };
+        let is_free = next == consts::FREE_SECTOR;
+        if is_free != free.contains(&index) {
+            problems.push(format!(
+                "{} entry {} is 0x{:08X}, but is {}listed as free",
+                name,
+                index,
+                next,
+                if is_free { "not " } else { "" }
+            ));
+        }
+        if next == consts::INVALID_SECTOR {
+            problems.push(format!(
+                "{} entry {} is the invalid value 0x{:08X}",
+                name, index, next
+            ));
+        } else if next <= consts::MAX_REGULAR_SECTOR {
+            match table.get(next as usize) {
+                None => problems.push(format!(
+                    "{} entry {} points to {}, past the end of the table",
+                    name, index, next
+                )),
+                Some(&consts::FREE_SECTOR) => problems.push(format!(
+                    "{} entry {} points to free entry {}",
+                    name, index, next
+                )),
+                Some(_) => {}
+            }
+        }
+    }
+    if indices.len() == table.len() + free.range(table.len() as u32..).count()
+    {
+        let mut pointees = FnvHashSet::default();
+        for (index, &next) in table.iter().enumerate() {
+            if next <= consts::MAX_REGULAR_SECTOR && !pointees.insert(next) {
+                problems.push(format!(
+                    "{} entry {} points to {}, which another entry also \
+                     points to",
+                    name, index, next
+                ));
+            }
+        }
+    }
+    problems
+}
+
 //===========================================================================//
 
 #[cfg(test)]
 mod tests {
-    use super::Allocator;
-    use crate::internal::{consts, Sectors, Validation, Version};
+    use super::{free_indices, Allocator};
+    use crate::internal::{consts, SectorInit, Sectors, Validation, Version};
     use std::io::Cursor;
 
     fn make_sectors(
@@ -388,6 +1265,7 @@ mod tests {
             difat,
             fat,
             validation,
+            &mut Vec::new(),
         )
         .unwrap()
     }
@@ -401,8 +1279,27 @@ mod tests {
         let difat = vec![0];
         let fat = vec![consts::FAT_SECTOR, 2, consts::END_OF_CHAIN];
         let sectors = make_sectors(Version::V3, 2);
-        Allocator::new(sectors, vec![], difat, fat, Validation::Strict)
-            .unwrap();
+        Allocator::new(
+            sectors,
+            vec![],
+            difat,
+            fat,
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Malformed FAT (FAT has 130 entries, but its 1 sectors \
+                    can only hold 128)"
+    )]
+    fn fat_longer_than_fat_sectors() {
+        let difat = vec![0];
+        let mut fat = vec![consts::FREE_SECTOR; 130];
+        fat[0] = consts::FAT_SECTOR;
+        make_allocator(difat, fat, Validation::Permissive);
     }
 
     #[test]
@@ -415,8 +1312,15 @@ mod tests {
         let difat = vec![0];
         let fat = vec![consts::FAT_SECTOR, consts::END_OF_CHAIN];
         let sectors = make_sectors(Version::V3, fat.len());
-        Allocator::new(sectors, difat_sectors, difat, fat, Validation::Strict)
-            .unwrap();
+        Allocator::new(
+            sectors,
+            difat_sectors,
+            difat,
+            fat,
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -429,8 +1333,15 @@ mod tests {
         let difat = vec![0];
         let fat = vec![consts::FAT_SECTOR, consts::END_OF_CHAIN];
         let sectors = make_sectors(Version::V3, fat.len());
-        Allocator::new(sectors, difat_sectors, difat, fat, Validation::Strict)
-            .unwrap();
+        Allocator::new(
+            sectors,
+            difat_sectors,
+            difat,
+            fat,
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -447,12 +1358,13 @@ mod tests {
             difat,
             fat,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         // We should repair the FAT entry, and the resulting Allocator should
         // now pass Strict validation.
         assert_eq!(allocator.fat[1], consts::DIFAT_SECTOR);
-        allocator.validate(Validation::Strict).unwrap();
+        allocator.validate(Validation::Strict, &mut Vec::new()).unwrap();
     }
 
     #[test]
@@ -488,7 +1400,7 @@ mod tests {
         // We should repair the FAT entry, and the resulting Allocator should
         // now pass Strict validation.
         assert_eq!(allocator.fat[1], consts::FAT_SECTOR);
-        allocator.validate(Validation::Strict).unwrap();
+        allocator.validate(Validation::Strict, &mut Vec::new()).unwrap();
     }
 
     #[test]
@@ -519,6 +1431,49 @@ mod tests {
         let fat = vec![consts::FAT_SECTOR, consts::INVALID_SECTOR];
         make_allocator(difat, fat, Validation::Permissive);
     }
+
+    #[test]
+    fn growth_counts_new_fat_sectors() {
+        // One FAT sector, describing itself and 127 data sectors.
+        let mut fat = vec![consts::FAT_SECTOR];
+        fat.resize(128, consts::END_OF_CHAIN);
+        let allocator = make_allocator(vec![0], fat, Validation::Strict);
+        assert_eq!(allocator.num_sectors_after_growth(127), 128);
+        // Sector 128 can't be described without a second FAT sector.
+        assert_eq!(allocator.num_sectors_after_growth(128), 130);
+        assert_eq!(allocator.num_sectors_after_growth(300), 303);
+        // Past 109 FAT sectors, DIFAT sectors are needed too.
+        assert_eq!(allocator.num_sectors_after_growth(109 * 128), 14063);
+    }
+
+    #[test]
+    fn free_sector_index_tracks_fat() {
+        let difat = vec![0];
+        let fat = vec![
+            consts::FAT_SECTOR,
+            consts::FREE_SECTOR,
+            consts::END_OF_CHAIN,
+            consts::FREE_SECTOR,
+        ];
+        let mut allocator = make_allocator(difat, fat, Validation::Strict);
+        assert_eq!(allocator.free_sectors.len(), 2);
+        let start = allocator.begin_chain(SectorInit::Zero).unwrap();
+        assert_eq!(start, 1);
+        assert_eq!(
+            allocator.extend_chain(start, SectorInit::Zero).unwrap(),
+            3
+        );
+        assert_eq!(
+            allocator.extend_chain(start, SectorInit::Zero).unwrap(),
+            4
+        );
+        assert_eq!(allocator.free_sectors.len(), 0);
+        allocator.free_chain_after(start).unwrap();
+        assert_eq!(allocator.free_sectors, free_indices(&allocator.fat));
+        assert_eq!(allocator.free_sectors.len(), 2);
+        assert_eq!(allocator.begin_chain(SectorInit::Zero).unwrap(), 3);
+        assert_eq!(allocator.free_sectors, free_indices(&allocator.fat));
+    }
 }
 
 //===========================================================================//
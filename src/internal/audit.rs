@@ -0,0 +1,413 @@
+use crate::internal::path::{
+    compare_names, name_chain_from_path, path_from_name_chain,
+};
+use crate::internal::{ioutil, Timestamp};
+use crate::{ReadLeNumber, WriteLeNumber};
+use fnv::FnvHashMap;
+use std::cmp::Ordering;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+//===========================================================================//
+
+/// The format version written at the start of each audit record.  Readers
+/// reject records with any other version, so this must be bumped whenever
+/// the record layout changes.
+const RECORD_VERSION: u8 = 1;
+
+//===========================================================================//
+
+/// A kind of modification recorded in an audit trail (see
+/// [`CompoundFile::enable_audit_trail`](crate::CompoundFile::enable_audit_trail)).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum AuditOp {
+    /// A storage was created.
+    CreateStorage,
+    /// A storage was removed.
+    RemoveStorage,
+    /// A stream was created, or an existing stream was replaced by a new,
+    /// empty one (in which case the old size is that of the replaced
+    /// stream).
+    CreateStream,
+    /// A stream was removed.
+    RemoveStream,
+    /// A stream was written to or resized.  All such changes to a stream
+    /// between two flushes are covered by a single record.
+    ModifyStream,
+    /// An object's metadata (its CLSID, state bits, or timestamps) was
+    /// changed.
+    SetMetadata,
+    /// An object was renamed or moved away from this path.  This record is
+    /// always immediately followed by a `RenameTo` record with the new path.
+    RenameFrom,
+    /// An object was renamed or moved to this path, from the path in the
+    /// `RenameFrom` record just before this one.
+    RenameTo,
+}
+
+impl AuditOp {
+    fn code(self) -> u8 {
+        match self {
+            AuditOp::CreateStorage => 1,
+            AuditOp::RemoveStorage => 2,
+            AuditOp::CreateStream => 3,
+            AuditOp::RemoveStream => 4,
+            AuditOp::ModifyStream => 5,
+            AuditOp::SetMetadata => 6,
+            AuditOp::RenameFrom => 7,
+            AuditOp::RenameTo => 8,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<AuditOp> {
+        match code {
+            1 => Some(AuditOp::CreateStorage),
+            2 => Some(AuditOp::RemoveStorage),
+            3 => Some(AuditOp::CreateStream),
+            4 => Some(AuditOp::RemoveStream),
+            5 => Some(AuditOp::ModifyStream),
+            6 => Some(AuditOp::SetMetadata),
+            7 => Some(AuditOp::RenameFrom),
+            8 => Some(AuditOp::RenameTo),
+            _ => None,
+        }
+    }
+}
+
+//===========================================================================//
+
+/// One modification recorded in an audit trail, as returned by
+/// [`CompoundFile::read_audit_trail`](crate::CompoundFile::read_audit_trail).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuditRecord {
+    timestamp: Timestamp,
+    op: AuditOp,
+    path: PathBuf,
+    old_len: u64,
+    new_len: u64,
+}
+
+impl AuditRecord {
+    fn new(op: AuditOp, path: PathBuf, old_len: u64, new_len: u64) -> Self {
+        AuditRecord { timestamp: Timestamp::now(), op, path, old_len, new_len }
+    }
+
+    /// Returns when the modification was made (for `ModifyStream`, when the
+    /// stream was first changed since the previous flush).  This has the
+    /// 100-nanosecond precision of CFB timestamps.
+    pub fn time(&self) -> SystemTime {
+        self.timestamp.to_system_time()
+    }
+
+    /// Returns what kind of modification this was.
+    pub fn op(&self) -> AuditOp {
+        self.op
+    }
+
+    /// Returns the path of the object that was modified.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the size of the object, in bytes, before the modification.
+    /// This is always zero for storages.
+    pub fn old_len(&self) -> u64 {
+        self.old_len
+    }
+
+    /// Returns the size of the object, in bytes, after the modification.
+    /// This is always zero for storages and for removed streams.
+    pub fn new_len(&self) -> u64 {
+        self.new_len
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let names = name_chain_from_path(&self.path)?;
+        let path = format!("/{}", names.join("/"));
+        writer.write_all(&[RECORD_VERSION, self.op.code()])?;
+        self.timestamp.write_to(writer)?;
+        writer.write_le_u64(self.old_len)?;
+        writer.write_le_u64(self.new_len)?;
+        writer.write_le_u32(path.len() as u32)?;
+        writer.write_all(path.as_bytes())
+    }
+
+    /// Reads the next record, or returns `None` if the reader is already at
+    /// its end.
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<AuditRecord>> {
+        let mut version = [0u8; 1];
+        if ioutil::read_retrying(reader, &mut version)? == 0 {
+            return Ok(None);
+        }
+        if version[0] != RECORD_VERSION {
+            invalid_data!(
+                "Unsupported audit record version {} (expected {})",
+                version[0],
+                RECORD_VERSION
+            );
+        }
+        let record = AuditRecord::read_fields(reader).map_err(|error| {
+            if error.kind() == io::ErrorKind::UnexpectedEof {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Truncated audit record",
+                )
+            } else {
+                error
+            }
+        })?;
+        Ok(Some(record))
+    }
+
+    fn read_fields<R: Read>(reader: &mut R) -> io::Result<AuditRecord> {
+        let mut code = [0u8; 1];
+        reader.read_exact(&mut code)?;
+        let op = match AuditOp::from_code(code[0]) {
+            Some(op) => op,
+            None => invalid_data!("Unknown audit operation code {}", code[0]),
+        };
+        let timestamp = Timestamp::read_from(reader)?;
+        let old_len = reader.read_le_u64()?;
+        let new_len = reader.read_le_u64()?;
+        let path_len = reader.read_le_u32()?;
+        let mut path = Vec::new();
+        reader.take(path_len as u64).read_to_end(&mut path)?;
+        if path.len() != path_len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let path = match String::from_utf8(path) {
+            Ok(path) => path,
+            Err(_) => invalid_data!("Audit record path is not UTF-8"),
+        };
+        let names: Vec<&str> =
+            path.split('/').filter(|name| !name.is_empty()).collect();
+        Ok(AuditRecord {
+            timestamp,
+            op,
+            path: path_from_name_chain(&names),
+            old_len,
+            new_len,
+        })
+    }
+}
+
+/// Parses all of the records in an audit trail stream.
+pub fn read_audit_records<R: Read>(
+    mut reader: R,
+) -> io::Result<Vec<AuditRecord>> {
+    let mut records = Vec::new();
+    while let Some(record) = AuditRecord::read_from(&mut reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+//===========================================================================//
+
+/// The modifications made to a compound file since its audit trail was last
+/// written out.
+pub struct AuditLog {
+    path: PathBuf,
+    records: Vec<AuditRecord>,
+    /// For each stream modified since the last flush, the index of its
+    /// still-open `ModifyStream` record, or `None` if the stream is the
+    /// audit trail itself.
+    modified: FnvHashMap<u32, Option<usize>>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> AuditLog {
+        AuditLog { path, records: Vec::new(), modified: FnvHashMap::default() }
+    }
+
+    /// Returns the path of the stream that the audit trail is written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Changes the stream that the audit trail is written to.
+    pub fn set_path(&mut self, path: PathBuf) {
+        self.path = path;
+        self.modified.clear();
+    }
+
+    /// Returns true if `path` names the audit trail stream itself (comparing
+    /// names the way CFB does, i.e. case-insensitively).
+    fn is_audit_trail(&self, path: &Path) -> bool {
+        match (name_chain_from_path(path), name_chain_from_path(&self.path)) {
+            (Ok(names), Ok(audit_names)) => {
+                names.len() == audit_names.len()
+                    && names.iter().zip(audit_names.iter()).all(
+                        |(name, audit_name)| {
+                            compare_names(name, audit_name) == Ordering::Equal
+                        },
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    /// Records a modification, unless it was made to the audit trail itself.
+    pub fn record(
+        &mut self,
+        op: AuditOp,
+        path: &Path,
+        old_len: u64,
+        new_len: u64,
+    ) {
+        if !self.is_audit_trail(path) {
+            let path = path.to_path_buf();
+            self.records.push(AuditRecord::new(op, path, old_len, new_len));
+        }
+    }
+
+    /// Records a rename as a `RenameFrom` record followed by a `RenameTo`
+    /// record, unless the object was renamed to or from the audit trail's
+    /// path (since that is a change to the audit trail itself).
+    pub fn record_rename(
+        &mut self,
+        old_path: &Path,
+        new_path: &Path,
+        len: u64,
+    ) {
+        if !self.is_audit_trail(old_path) && !self.is_audit_trail(new_path) {
+            self.records.push(AuditRecord::new(
+                AuditOp::RenameFrom,
+                old_path.to_path_buf(),
+                len,
+                len,
+            ));
+            self.records.push(AuditRecord::new(
+                AuditOp::RenameTo,
+                new_path.to_path_buf(),
+                len,
+                len,
+            ));
+        }
+    }
+
+    /// Returns true if the given stream has already been seen to be modified
+    /// since the last flush (so that `begin_modify` need not be called).
+    pub fn is_modifying(&self, stream_id: u32) -> bool {
+        self.modified.contains_key(&stream_id)
+    }
+
+    /// Opens a `ModifyStream` record for the given stream, which is about to
+    /// be changed for the first time since the last flush.
+    pub fn begin_modify(&mut self, stream_id: u32, path: &Path, old_len: u64) {
+        let index = if self.is_audit_trail(path) {
+            None
+        } else {
+            let record = AuditRecord::new(
+                AuditOp::ModifyStream,
+                path.to_path_buf(),
+                old_len,
+                old_len,
+            );
+            self.records.push(record);
+            Some(self.records.len() - 1)
+        };
+        self.modified.insert(stream_id, index);
+    }
+
+    /// Closes the given stream's `ModifyStream` record (if it has one), with
+    /// the stream's current length.
+    pub fn end_modify(&mut self, stream_id: u32, new_len: u64) {
+        if let Some(Some(index)) = self.modified.remove(&stream_id) {
+            self.records[index].new_len = new_len;
+        }
+    }
+
+    /// Drops the given stream's `ModifyStream` record (if it has one),
+    /// because its changes are already covered by another record.
+    pub fn discard_modify(&mut self, stream_id: u32) {
+        if let Some(Some(index)) = self.modified.remove(&stream_id) {
+            self.records.remove(index);
+            for other in self.modified.values_mut().flatten() {
+                if *other > index {
+                    *other -= 1;
+                }
+            }
+        }
+    }
+
+    /// Returns the IDs of streams whose `ModifyStream` records are open.
+    pub fn modified_streams(&self) -> Vec<u32> {
+        self.modified.keys().copied().collect()
+    }
+
+    /// Encodes the records made so far, returning them along with how many
+    /// there are.  Call `end_modify` for every modified stream first.
+    pub fn encode(&self) -> io::Result<(Vec<u8>, usize)> {
+        let mut data = Vec::new();
+        for record in self.records.iter() {
+            record.write_to(&mut data)?;
+        }
+        Ok((data, self.records.len()))
+    }
+
+    /// Forgets the first `count` records, once they have been written out.
+    pub fn clear(&mut self, count: usize) {
+        debug_assert!(self.modified.values().all(|index| index.is_none()));
+        self.records.drain(..count);
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{read_audit_records, AuditLog, AuditOp};
+    use std::io::ErrorKind;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn records_round_trip() {
+        let mut log = AuditLog::new(PathBuf::from("/\u{5}Trail"));
+        log.record(AuditOp::CreateStream, Path::new("/foo/bar"), 0, 0);
+        log.begin_modify(3, Path::new("/foo/bar"), 0);
+        log.begin_modify(4, Path::new("/\u{5}TRAIL"), 10);
+        log.end_modify(3, 1234);
+        log.end_modify(4, 20);
+        let (data, count) = log.encode().unwrap();
+        assert_eq!(count, 2);
+        let records = read_audit_records(data.as_slice()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].op(), AuditOp::CreateStream);
+        assert_eq!(records[1].op(), AuditOp::ModifyStream);
+        assert_eq!(records[1].path(), Path::new("/foo/bar"));
+        assert_eq!((records[1].old_len(), records[1].new_len()), (0, 1234));
+    }
+
+    #[test]
+    fn discarding_shifts_open_records() {
+        let mut log = AuditLog::new(PathBuf::from("/trail"));
+        log.begin_modify(1, Path::new("/one"), 0);
+        log.begin_modify(2, Path::new("/two"), 0);
+        log.discard_modify(1);
+        log.end_modify(2, 5);
+        let (data, _) = log.encode().unwrap();
+        let records = read_audit_records(data.as_slice()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].path(), Path::new("/two"));
+        assert_eq!(records[0].new_len(), 5);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let mut log = AuditLog::new(PathBuf::from("/trail"));
+        log.record(AuditOp::RemoveStorage, Path::new("/foo"), 0, 0);
+        let (mut data, _) = log.encode().unwrap();
+        data[0] = 2;
+        let error = read_audit_records(data.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        data[0] = 1;
+        data.pop();
+        let error = read_audit_records(data.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
+
+//===========================================================================//
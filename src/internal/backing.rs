@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+//===========================================================================//
+
+/// The error payload reported when the underlying file has become shorter
+/// than the compound file's sectors require, for example because another
+/// process truncated it.
+///
+/// This is returned wrapped in an `io::Error` (of kind `UnexpectedEof`); use
+/// [`from_io_error`](#method.from_io_error) to recognize it.  Once this has
+/// been detected, [`CompoundFile::flush`](../struct.CompoundFile.html#method.flush)
+/// refuses to write anything until
+/// [`reload`](../struct.CompoundFile.html#method.reload) or
+/// [`repair`](../struct.CompoundFile.html#method.repair) is called, since
+/// writing a FAT that describes sectors past the end of the file would only
+/// compound the damage.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BackingFileShrunk {
+    expected: u64,
+    actual: u64,
+}
+
+impl BackingFileShrunk {
+    pub(crate) fn new(expected: u64, actual: u64) -> BackingFileShrunk {
+        debug_assert!(actual < expected);
+        BackingFileShrunk { expected, actual }
+    }
+
+    /// Returns the length, in bytes, that the compound file's sectors
+    /// require the underlying file to have.
+    pub fn expected(&self) -> u64 {
+        self.expected
+    }
+
+    /// Returns the actual length, in bytes, of the underlying file.
+    pub fn actual(&self) -> u64 {
+        self.actual
+    }
+
+    /// Returns the `BackingFileShrunk` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&BackingFileShrunk> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for BackingFileShrunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Underlying file shrank to {} bytes, but the compound file \
+             requires {} bytes",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl Error for BackingFileShrunk {}
+
+impl From<BackingFileShrunk> for io::Error {
+    fn from(shrunk: BackingFileShrunk) -> io::Error {
+        io::Error::new(io::ErrorKind::UnexpectedEof, shrunk)
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::BackingFileShrunk;
+    use std::io;
+
+    #[test]
+    fn round_trip_through_io_error() {
+        let error = io::Error::from(BackingFileShrunk::new(2048, 1000));
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let shrunk = BackingFileShrunk::from_io_error(&error).unwrap();
+        assert_eq!(shrunk.expected(), 2048);
+        assert_eq!(shrunk.actual(), 1000);
+        assert!(error.to_string().contains("1000 bytes"));
+
+        let other = io::Error::new(io::ErrorKind::UnexpectedEof, "eof");
+        assert!(BackingFileShrunk::from_io_error(&other).is_none());
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,320 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+use std::path::PathBuf;
+
+//===========================================================================//
+
+/// A set of optional capabilities of the reader/writer underlying a
+/// compound file, as returned by
+/// [`CompoundFile::capabilities`](../struct.CompoundFile.html#method.capabilities).
+/// Sets can be combined with `|`.
+///
+/// Capabilities are recorded by the constructor that opened or created the
+/// file, from what it knows about the underlying reader/writer.  For
+/// example, [`cfb::open_rw`](../fn.open_rw.html) knows that it opened a
+/// writable file at a path, but
+/// [`CompoundFile::open`](../struct.CompoundFile.html#method.open), given an
+/// arbitrary reader, knows nothing beyond its type.
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    /// The underlying file can be written to.
+    pub const WRITABLE: Capabilities = Capabilities(1 << 0);
+    /// The underlying file can be truncated, so
+    /// [`flush_and_truncate`](../struct.CompoundFile.html#method.flush_and_truncate)
+    /// can drop the space that
+    /// [`shrink_to_fit`](../struct.CompoundFile.html#method.shrink_to_fit)
+    /// frees.
+    pub const TRUNCATABLE: Capabilities = Capabilities(1 << 1);
+    /// The underlying file can be read at a given offset through a shared
+    /// reference, so
+    /// [`read_stream_at`](../struct.CompoundFile.html#method.read_stream_at)
+    /// can be called from several threads at once.
+    pub const POSITIONAL_READ: Capabilities = Capabilities(1 << 2);
+    /// The underlying file has a path on disk (see
+    /// [`CompoundFile::path`](../struct.CompoundFile.html#method.path)).
+    pub const PERSISTENT_PATH: Capabilities = Capabilities(1 << 3);
+    /// A new version of the file can be written beside it and renamed over
+    /// it (see
+    /// [`save_atomic`](../struct.CompoundFile.html#method.save_atomic)).
+    pub const ATOMIC_SAVE: Capabilities = Capabilities(1 << 4);
+    /// Every capability.
+    pub const ALL: Capabilities = Capabilities(0b11111);
+
+    const NAMES: [(Capabilities, &'static str); 5] = [
+        (Capabilities::WRITABLE, "WRITABLE"),
+        (Capabilities::TRUNCATABLE, "TRUNCATABLE"),
+        (Capabilities::POSITIONAL_READ, "POSITIONAL_READ"),
+        (Capabilities::PERSISTENT_PATH, "PERSISTENT_PATH"),
+        (Capabilities::ATOMIC_SAVE, "ATOMIC_SAVE"),
+    ];
+
+    /// Returns the empty set.
+    pub const fn empty() -> Capabilities {
+        Capabilities(0)
+    }
+
+    /// Returns true if no capabilities are included.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if every capability in `other` is also in `self`.
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the capabilities in `other` that aren't in `self`.
+    pub const fn missing(self, other: Capabilities) -> Capabilities {
+        Capabilities(other.0 & !self.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, other: Capabilities) {
+        self.0 |= other.0;
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = Capabilities::NAMES
+            .iter()
+            .filter(|&&(capability, _)| self.contains(capability))
+            .map(|&(_, name)| name)
+            .collect();
+        if names.is_empty() {
+            f.write_str("(empty)")
+        } else {
+            f.write_str(&names.join(" | "))
+        }
+    }
+}
+
+//===========================================================================//
+
+/// The error payload reported when an operation needs a capability (see
+/// [`Capabilities`]) that the compound file's underlying reader/writer
+/// doesn't have.
+///
+/// This is returned wrapped in an `io::Error` (of kind `Unsupported`); use
+/// [`from_io_error`](#method.from_io_error) to recognize it.  Nothing is
+/// changed by an operation that fails this way, so generic code can check
+/// for it and fall back to another approach.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Unsupported {
+    operation: &'static str,
+    missing: Capabilities,
+}
+
+impl Unsupported {
+    pub(crate) fn new(
+        operation: &'static str,
+        missing: Capabilities,
+    ) -> Unsupported {
+        debug_assert!(!missing.is_empty());
+        Unsupported { operation, missing }
+    }
+
+    /// Returns the name of the operation that was refused (e.g.
+    /// `"save_atomic"`).
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// Returns the capabilities that the operation needed but that the
+    /// underlying file doesn't have.
+    pub fn missing(&self) -> Capabilities {
+        self.missing
+    }
+
+    /// Returns the `Unsupported` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&Unsupported> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} requires the {:?} capability, which the underlying file \
+             doesn't have",
+            self.operation, self.missing
+        )
+    }
+}
+
+impl Error for Unsupported {}
+
+impl From<Unsupported> for io::Error {
+    fn from(unsupported: Unsupported) -> io::Error {
+        io::Error::new(io::ErrorKind::Unsupported, unsupported)
+    }
+}
+
+//===========================================================================//
+
+/// Reads from the given offset of a file into a buffer, through a shared
+/// reference, returning the number of bytes read.
+pub type ReadAt<F> = fn(&F, &mut [u8], u64) -> io::Result<usize>;
+
+/// What a constructor knows about the reader/writer underlying a compound
+/// file, along with the operations that its type supports beyond
+/// `Read + Write + Seek`, captured as function pointers while the concrete
+/// type was still known.
+pub struct Backing<F> {
+    /// True if the file is known to be writable.
+    pub writable: bool,
+    /// True if the file is known not to be writable, so that writes should
+    /// be refused up front rather than failing part-way through.
+    pub read_only: bool,
+    pub path: Option<PathBuf>,
+    pub set_len: Option<fn(&mut F, u64) -> io::Result<()>>,
+    pub read_at: Option<ReadAt<F>>,
+    pub from_file: Option<fn(fs::File) -> F>,
+}
+
+impl<F> Backing<F> {
+    /// Returns a description of a reader/writer that nothing is known
+    /// about.
+    pub fn unknown() -> Backing<F> {
+        Backing {
+            writable: false,
+            read_only: false,
+            path: None,
+            set_len: None,
+            read_at: None,
+            from_file: None,
+        }
+    }
+
+    /// Returns a description of a reader/writer that is known to be
+    /// writable, but nothing else.
+    pub fn writable() -> Backing<F> {
+        Backing { writable: true, ..Backing::unknown() }
+    }
+
+    /// Returns the function for truncating the file, if it has the
+    /// `TRUNCATABLE` capability.
+    pub fn truncator(&self) -> Option<fn(&mut F, u64) -> io::Result<()>> {
+        self.set_len.filter(|_| self.writable)
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::empty();
+        if self.writable {
+            capabilities |= Capabilities::WRITABLE;
+        }
+        if self.writable && self.set_len.is_some() {
+            capabilities |= Capabilities::TRUNCATABLE;
+        }
+        if self.read_at.is_some() {
+            capabilities |= Capabilities::POSITIONAL_READ;
+        }
+        if self.path.is_some() {
+            capabilities |= Capabilities::PERSISTENT_PATH;
+            if self.writable && self.from_file.is_some() {
+                capabilities |= Capabilities::ATOMIC_SAVE;
+            }
+        }
+        capabilities
+    }
+
+    /// Returns an `Unsupported` error for the given operation unless the
+    /// file has all of the given capabilities.
+    pub fn require(
+        &self,
+        operation: &'static str,
+        required: Capabilities,
+    ) -> io::Result<()> {
+        let missing = self.capabilities().missing(required);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Unsupported::new(operation, missing).into())
+        }
+    }
+}
+
+impl Backing<fs::File> {
+    /// Returns a description of a file opened at the given path.
+    pub fn file(path: PathBuf, writable: bool) -> Backing<fs::File> {
+        Backing {
+            writable,
+            read_only: !writable,
+            path: Some(path),
+            set_len: Some(|file, len| file.set_len(len)),
+            read_at: file_read_at(),
+            from_file: Some(|file| file),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_read_at() -> Option<ReadAt<fs::File>> {
+    use std::os::unix::fs::FileExt;
+    Some(|file, buf, offset| file.read_at(buf, offset))
+}
+
+#[cfg(windows)]
+fn file_read_at() -> Option<ReadAt<fs::File>> {
+    use std::os::windows::fs::FileExt;
+    // Unlike pread, this moves the file cursor, but every other read or
+    // write of the file seeks first anyway.
+    Some(|file, buf, offset| file.seek_read(buf, offset))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn file_read_at() -> Option<ReadAt<fs::File>> {
+    None
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{Capabilities, Unsupported};
+    use std::io;
+
+    #[test]
+    fn combine_capabilities() {
+        let caps = Capabilities::WRITABLE | Capabilities::PERSISTENT_PATH;
+        assert!(caps.contains(Capabilities::WRITABLE));
+        assert!(!caps.contains(Capabilities::ALL));
+        assert_eq!(
+            caps.missing(Capabilities::WRITABLE | Capabilities::ATOMIC_SAVE),
+            Capabilities::ATOMIC_SAVE
+        );
+        assert!(caps.missing(Capabilities::WRITABLE).is_empty());
+        assert_eq!(format!("{:?}", caps), "WRITABLE | PERSISTENT_PATH");
+        assert_eq!(format!("{:?}", Capabilities::empty()), "(empty)");
+    }
+
+    #[test]
+    fn unsupported_round_trips_through_io_error() {
+        let error = io::Error::from(Unsupported::new(
+            "save_atomic",
+            Capabilities::ATOMIC_SAVE,
+        ));
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(error.to_string().contains("ATOMIC_SAVE"));
+        let unsupported = Unsupported::from_io_error(&error).unwrap();
+        assert_eq!(unsupported.operation(), "save_atomic");
+        assert_eq!(unsupported.missing(), Capabilities::ATOMIC_SAVE);
+    }
+}
+
+//===========================================================================//
@@ -1,6 +1,83 @@
 use crate::internal::{consts, Allocator, Sector, SectorInit};
 use std::cmp;
+use std::fmt;
 use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+//===========================================================================//
+
+/// Identifies a chain of sectors or mini sectors in error messages.
+#[derive(Clone, Copy, Debug)]
+pub enum ChainName<'a> {
+    /// The chain holding the data of the stream at the given path.
+    Stream(&'a Path),
+    /// The chain of sectors holding the mini stream.
+    MiniStream,
+    /// The chain of directory sectors.
+    Directory,
+    /// The chain of MiniFAT sectors.
+    MiniFat,
+    /// The chain of DIFAT sectors.
+    Difat,
+    /// A chain of sectors known only by its starting sector.
+    StartingAt(u32),
+    /// A chain of mini sectors known only by its starting mini sector.
+    MiniStartingAt(u32),
+}
+
+impl fmt::Display for ChainName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ChainName::Stream(path) => write!(f, "stream {:?}", path),
+            ChainName::MiniStream => f.write_str("mini stream"),
+            ChainName::Directory => f.write_str("directory chain"),
+            ChainName::MiniFat => f.write_str("MiniFAT chain"),
+            ChainName::Difat => f.write_str("DIFAT chain"),
+            ChainName::StartingAt(sector_id) => {
+                write!(f, "chain starting at sector {}", sector_id)
+            }
+            ChainName::MiniStartingAt(sector_id) => {
+                write!(f, "chain starting at mini sector {}", sector_id)
+            }
+        }
+    }
+}
+
+/// Interprets `next_id`, the link to the sector at the given (zero-based)
+/// position within a chain, where the chain can only use sectors numbered
+/// below `num_sectors`.  This is the one place that decides what each
+/// special value means within a chain, so that the FAT, MiniFAT, directory,
+/// and DIFAT chains all treat them alike: `END_OF_CHAIN` is returned as-is
+/// to end the chain, while `FREE_SECTOR`, `FAT_SECTOR`, `DIFAT_SECTOR`, any
+/// other value above `MAX_REGULAR_SECTOR`, and sectors past the end are all
+/// errors naming the chain and position.
+pub fn next_in_chain(
+    chain: ChainName<'_>,
+    position: usize,
+    next_id: u32,
+    num_sectors: usize,
+) -> io::Result<u32> {
+    let found = match next_id {
+        consts::END_OF_CHAIN => return Ok(next_id),
+        consts::FREE_SECTOR => "FREESECT".to_string(),
+        consts::FAT_SECTOR => "FATSECT".to_string(),
+        consts::DIFAT_SECTOR => "DIFSECT".to_string(),
+        _ if next_id > consts::MAX_REGULAR_SECTOR => {
+            format!("invalid sector ID 0x{:08X}", next_id)
+        }
+        _ if next_id as usize >= num_sectors => format!(
+            "sector {}, but there are only {} sectors",
+            next_id, num_sectors
+        ),
+        _ => return Ok(next_id),
+    };
+    invalid_data!(
+        "The {} is broken at position {}: found {}",
+        chain,
+        position,
+        found
+    )
+}
 
 //===========================================================================//
 
@@ -17,22 +94,23 @@ impl<'a, F> Chain<'a, F> {
         start_sector_id: u32,
         init: SectorInit,
     ) -> io::Result<Chain<'a, F>> {
-        let mut sector_ids = Vec::<u32>::new();
-        let mut current_sector_id = start_sector_id;
-        let first_sector_id = start_sector_id;
-        while current_sector_id != consts::END_OF_CHAIN {
-            sector_ids.push(current_sector_id);
-            current_sector_id = allocator.next(current_sector_id)?;
-            if current_sector_id == first_sector_id {
-                invalid_data!(
-                    "Chain contained duplicate sector id {}",
-                    current_sector_id
-                );
-            }
-        }
+        let sector_ids = match allocator.take_cached_chain(start_sector_id) {
+            Some(sector_ids) => sector_ids,
+            None => allocator.chain_sector_ids(
+                start_sector_id,
+                ChainName::StartingAt(start_sector_id),
+            )?,
+        };
         Ok(Chain { allocator, init, sector_ids, offset_from_start: 0 })
     }
 
+    /// Closes the chain, letting the allocator remember its sectors so that
+    /// reopening it (as happens with each buffered write to a stream)
+    /// doesn't have to follow the whole chain through the FAT again.
+    pub fn release(self) {
+        self.allocator.cache_chain(self.sector_ids);
+    }
+
     pub fn start_sector_id(&self) -> u32 {
         self.sector_ids.first().copied().unwrap_or(consts::END_OF_CHAIN)
     }
@@ -67,6 +145,7 @@ impl<'a, F: Seek> Chain<'a, F> {
             .ok_or_else(|| {
                 io::Error::new(io::ErrorKind::InvalidData, "invalid sector id")
             })?;
+        self.allocator.cache_chain(self.sector_ids);
         self.allocator.seek_within_subsector(
             sector_id,
             subsector_index_within_sector,
@@ -87,23 +166,21 @@ impl<'a, F: Write + Seek> Chain<'a, F> {
             if let Some(&start_sector) = self.sector_ids.first() {
                 self.allocator.free_chain(start_sector)?;
             }
+            self.sector_ids.clear();
         } else if new_num_sectors <= self.sector_ids.len() {
             if new_num_sectors < self.sector_ids.len() {
                 self.allocator
                     .free_chain_after(self.sector_ids[new_num_sectors - 1])?;
+                self.sector_ids.truncate(new_num_sectors);
             }
             // TODO: init remainder of final sector
         } else {
-            for _ in self.sector_ids.len()..new_num_sectors {
-                let new_sector_id = if let Some(&last_sector_id) =
-                    self.sector_ids.last()
-                {
-                    self.allocator.extend_chain(last_sector_id, self.init)?
-                } else {
-                    self.allocator.begin_chain(self.init)?
-                };
-                self.sector_ids.push(new_sector_id);
-            }
+            let count = new_num_sectors - self.sector_ids.len();
+            self.allocator.extend_chain_by(
+                &mut self.sector_ids,
+                count,
+                self.init,
+            )?;
         }
         Ok(())
     }
@@ -148,10 +225,30 @@ impl<'a, F: Read + Seek> Read for Chain<'a, F> {
         debug_assert!(current_sector_index < self.sector_ids.len());
         let current_sector_id = self.sector_ids[current_sector_index];
         let offset_within_sector = self.offset_from_start % sector_len;
-        let mut sector = self
-            .allocator
-            .seek_within_sector(current_sector_id, offset_within_sector)?;
-        let bytes_read = sector.read(&mut buf[0..max_len])?;
+        // As with writes, read across a run of consecutive sectors at once.
+        let mut num_sectors = 1;
+        while current_sector_index + num_sectors < self.sector_ids.len()
+            && self.sector_ids[current_sector_index + num_sectors]
+                == current_sector_id + num_sectors as u32
+            && (num_sectors as u64) * sector_len - offset_within_sector
+                < max_len as u64
+        {
+            num_sectors += 1;
+        }
+        let bytes_read = if num_sectors > 1 {
+            let span_len = ((num_sectors as u64) * sector_len
+                - offset_within_sector)
+                .min(max_len as u64) as usize;
+            let offset = (current_sector_id as u64 + 1) * sector_len
+                + offset_within_sector;
+            self.allocator.read_span(offset, &mut buf[..span_len])?;
+            span_len
+        } else {
+            let mut sector = self
+                .allocator
+                .seek_within_sector(current_sector_id, offset_within_sector)?;
+            sector.read(&mut buf[0..max_len])?
+        };
         self.offset_from_start += bytes_read as u64;
         debug_assert!(self.offset_from_start <= total_len);
         Ok(bytes_read)
@@ -163,31 +260,46 @@ impl<'a, F: Write + Seek> Write for Chain<'a, F> {
         if buf.is_empty() {
             return Ok(0);
         }
-        let mut total_len = self.len();
+        // Allocate all the sectors this write needs up front, so that they
+        // can be zeroed and linked into the FAT in bulk, and so that the
+        // data can be written across runs of consecutive sectors at once.
         let sector_len = self.allocator.sector_len() as u64;
-        if self.offset_from_start == total_len {
-            let new_sector_id =
-                if let Some(&last_sector_id) = self.sector_ids.last() {
-                    self.allocator.extend_chain(last_sector_id, self.init)?
-                } else {
-                    self.allocator.begin_chain(self.init)?
-                };
-            self.sector_ids.push(new_sector_id);
-            total_len += sector_len;
-            debug_assert_eq!(total_len, self.len());
+        let end = self.offset_from_start + buf.len() as u64;
+        if end > self.len() {
+            let count = (end.div_ceil(sector_len) as usize)
+                .saturating_sub(self.sector_ids.len());
+            self.allocator.extend_chain_by(
+                &mut self.sector_ids,
+                count,
+                self.init,
+            )?;
         }
+        let total_len = self.len();
         let current_sector_index =
             (self.offset_from_start / sector_len) as usize;
         debug_assert!(current_sector_index < self.sector_ids.len());
         let current_sector_id = self.sector_ids[current_sector_index];
         let offset_within_sector = self.offset_from_start % sector_len;
-        let mut sector = self
-            .allocator
-            .seek_within_sector(current_sector_id, offset_within_sector)?;
-        let bytes_written = sector.write(buf)?;
-        self.offset_from_start += bytes_written as u64;
+        let mut num_sectors = 1;
+        while current_sector_index + num_sectors < self.sector_ids.len()
+            && self.sector_ids[current_sector_index + num_sectors]
+                == current_sector_id + num_sectors as u32
+            && (num_sectors as u64) * sector_len - offset_within_sector
+                < buf.len() as u64
+        {
+            num_sectors += 1;
+        }
+        let max_len = ((num_sectors as u64) * sector_len
+            - offset_within_sector)
+            .min(buf.len() as u64) as usize;
+        self.allocator.write_span(
+            current_sector_id,
+            offset_within_sector,
+            &buf[..max_len],
+        )?;
+        self.offset_from_start += max_len as u64;
         debug_assert!(self.offset_from_start <= total_len);
-        Ok(bytes_written)
+        Ok(max_len)
     }
 
     fn flush(&mut self) -> io::Result<()> {
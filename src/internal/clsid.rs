@@ -0,0 +1,82 @@
+use std::fmt;
+use std::path::Path;
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// A function that picks the CLSID for a new storage, given its path.
+type ClsidFn = dyn FnMut(&Path) -> Uuid + Send + Sync;
+
+/// Determines the CLSID given to each storage object as it is created (see
+/// [`CompoundFile::set_clsid_policy`](../struct.CompoundFile.html#method.set_clsid_policy)).
+#[derive(Default)]
+pub enum ClsidPolicy {
+    /// Leave the CLSID as all zeros.  This is the default.
+    #[default]
+    Null,
+    /// Give each storage a new random (version 4) UUID.
+    Random,
+    /// Derive each storage's CLSID from its full path within the compound
+    /// file (for example, `/Foo/Bar`), as a name-based (version 5) UUID in
+    /// the given namespace.  The same path always gets the same CLSID, so
+    /// building the same file twice gives identical output.
+    DeriveV5 {
+        /// The namespace in which to hash storage paths.
+        namespace: Uuid,
+    },
+    /// Call the given function with each storage's full path within the
+    /// compound file, and use the UUID it returns.
+    Custom(Box<ClsidFn>),
+}
+
+impl ClsidPolicy {
+    /// Returns the CLSID to give a new storage at the given path.
+    pub(crate) fn clsid_for(&mut self, path: &Path) -> Uuid {
+        match self {
+            ClsidPolicy::Null => Uuid::nil(),
+            ClsidPolicy::Random => Uuid::new_v4(),
+            ClsidPolicy::DeriveV5 { namespace } => {
+                Uuid::new_v5(namespace, path.to_string_lossy().as_bytes())
+            }
+            ClsidPolicy::Custom(function) => function(path),
+        }
+    }
+}
+
+impl fmt::Debug for ClsidPolicy {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClsidPolicy::Null => formatter.write_str("Null"),
+            ClsidPolicy::Random => formatter.write_str("Random"),
+            ClsidPolicy::DeriveV5 { namespace } => formatter
+                .debug_struct("DeriveV5")
+                .field("namespace", namespace)
+                .finish(),
+            ClsidPolicy::Custom(_) => formatter.write_str("Custom(..)"),
+        }
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::ClsidPolicy;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    #[test]
+    fn derive_v5_depends_only_on_path() {
+        let mut policy =
+            ClsidPolicy::DeriveV5 { namespace: Uuid::NAMESPACE_URL };
+        let foo = policy.clsid_for(Path::new("/foo"));
+        assert_eq!(foo.get_version_num(), 5);
+        assert_eq!(policy.clsid_for(Path::new("/foo")), foo);
+        assert_ne!(policy.clsid_for(Path::new("/bar/foo")), foo);
+        let mut other =
+            ClsidPolicy::DeriveV5 { namespace: Uuid::NAMESPACE_OID };
+        assert_ne!(other.clsid_for(Path::new("/foo")), foo);
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,380 @@
+use crate::internal::{
+    consts, DirEntry, FreeEntryPolicy, Header, MiniAllocator, ObjType,
+    SectorInit, Version,
+};
+use crate::WriteLeNumber;
+use fnv::FnvHashMap;
+use std::io::{self, Read, Seek, Write};
+
+//===========================================================================//
+
+/// How many bytes of a compacted copy to build in memory before moving it to
+/// a temporary file.
+pub const SPOOL_THRESHOLD: u64 = 16 << 20;
+
+//===========================================================================//
+
+/// A stream data chain to be copied into a compacted file.
+struct CompactChain {
+    is_mini: bool,
+    old_start: u32,
+    /// The number of bytes of the chain to copy.
+    len: u64,
+    /// The number of (mini) sectors the copy takes up.
+    num_sectors: u32,
+    /// The copy's starting (mini) sector.
+    new_start: u32,
+}
+
+/// Where everything goes in a compacted copy of a compound file: the FAT,
+/// DIFAT, directory, MiniFAT, and mini stream are laid out contiguously, in
+/// that order, right after the header, and each stream's data follows in
+/// stream ID order, with no free sectors anywhere.  Directory entries keep
+/// their stream IDs, and streams that share a chain still share it.
+pub struct CompactLayout {
+    version: Version,
+    num_fat_sectors: u32,
+    num_difat_sectors: u32,
+    num_dir_sectors: u32,
+    num_minifat_sectors: u32,
+    num_mini_stream_sectors: u32,
+    num_mini_sectors: u32,
+    num_sectors: u32,
+    chains: Vec<CompactChain>,
+    /// The index into `chains` of each chain, by its key (see
+    /// `MiniAllocator::chain_key`).
+    chain_indices: FnvHashMap<(bool, u32), usize>,
+}
+
+impl CompactLayout {
+    pub fn new<F>(minialloc: &MiniAllocator<F>) -> CompactLayout {
+        let version = minialloc.version();
+        let sector_len = version.sector_len() as u64;
+        let fat_entries_per_sector = version.fat_entries_per_sector() as u64;
+        let difat_entries_per_sector =
+            version.difat_entries_per_sector() as u64;
+
+        // Find each chain that holds stream data.  A chain shared by several
+        // streams is copied once, with enough data for the longest of them.
+        let mut chains = Vec::<CompactChain>::new();
+        let mut chain_indices = FnvHashMap::default();
+        for stream_id in 0..minialloc.num_dir_entries() {
+            let dir_entry = minialloc.dir_entry(stream_id);
+            let Some(key) = MiniAllocator::<F>::chain_key(dir_entry) else {
+                continue;
+            };
+            let len = minialloc.readable_len(stream_id);
+            let index = *chain_indices.entry(key).or_insert_with(|| {
+                chains.push(CompactChain {
+                    is_mini: key.0,
+                    old_start: key.1,
+                    len: 0,
+                    num_sectors: 0,
+                    new_start: consts::END_OF_CHAIN,
+                });
+                chains.len() - 1
+            });
+            chains[index].len = chains[index].len.max(len);
+        }
+
+        let mut num_mini_sectors = 0;
+        let mut num_data_sectors = 0;
+        for chain in chains.iter_mut() {
+            if chain.is_mini {
+                chain.num_sectors =
+                    chain.len.div_ceil(consts::MINI_SECTOR_LEN as u64) as u32;
+                if chain.num_sectors > 0 {
+                    chain.new_start = num_mini_sectors;
+                }
+                num_mini_sectors += chain.num_sectors;
+            } else {
+                chain.num_sectors = chain.len.div_ceil(sector_len) as u32;
+                num_data_sectors += chain.num_sectors;
+            }
+        }
+        let num_dir_sectors = (minialloc.num_dir_entries() as u64)
+            .div_ceil(version.dir_entries_per_sector() as u64)
+            .max(1);
+        let num_minifat_sectors =
+            (num_mini_sectors as u64).div_ceil(fat_entries_per_sector);
+        let num_mini_stream_sectors = (num_mini_sectors as u64
+            * consts::MINI_SECTOR_LEN as u64)
+            .div_ceil(sector_len);
+        let num_other_sectors = num_dir_sectors
+            + num_minifat_sectors
+            + num_mini_stream_sectors
+            + num_data_sectors as u64;
+        let mut num_fat_sectors = 1;
+        let mut num_difat_sectors = 0;
+        loop {
+            let total =
+                num_fat_sectors + num_difat_sectors + num_other_sectors;
+            let needed_fat = total.div_ceil(fat_entries_per_sector);
+            let needed_difat = needed_fat
+                .saturating_sub(consts::NUM_DIFAT_ENTRIES_IN_HEADER as u64)
+                .div_ceil(difat_entries_per_sector);
+            if needed_fat <= num_fat_sectors
+                && needed_difat <= num_difat_sectors
+            {
+                break;
+            }
+            num_fat_sectors = num_fat_sectors.max(needed_fat);
+            num_difat_sectors = num_difat_sectors.max(needed_difat);
+        }
+
+        let mut layout = CompactLayout {
+            version,
+            num_fat_sectors: num_fat_sectors as u32,
+            num_difat_sectors: num_difat_sectors as u32,
+            num_dir_sectors: num_dir_sectors as u32,
+            num_minifat_sectors: num_minifat_sectors as u32,
+            num_mini_stream_sectors: num_mini_stream_sectors as u32,
+            num_mini_sectors,
+            num_sectors: 0,
+            chains,
+            chain_indices,
+        };
+        let mut next_sector = layout.first_data_sector();
+        for chain in layout.chains.iter_mut() {
+            if !chain.is_mini && chain.num_sectors > 0 {
+                chain.new_start = next_sector;
+                next_sector += chain.num_sectors;
+            }
+        }
+        layout.num_sectors = next_sector;
+        layout
+    }
+
+    fn first_dir_sector(&self) -> u32 {
+        self.num_fat_sectors + self.num_difat_sectors
+    }
+
+    fn first_minifat_sector(&self) -> u32 {
+        self.first_dir_sector() + self.num_dir_sectors
+    }
+
+    fn first_mini_stream_sector(&self) -> u32 {
+        self.first_minifat_sector() + self.num_minifat_sectors
+    }
+
+    fn first_data_sector(&self) -> u32 {
+        self.first_mini_stream_sector() + self.num_mini_stream_sectors
+    }
+
+    /// Returns the length of the compacted file, in bytes.
+    pub fn file_len(&self) -> u64 {
+        (self.num_sectors as u64 + 1) * self.version.sector_len() as u64
+    }
+
+    /// Writes the compacted copy of the file to `writer`.  The file should
+    /// have been flushed first, and must not have changed since this layout
+    /// was computed.
+    pub fn write_to<F: Read + Seek, W: Write>(
+        &self,
+        minialloc: &mut MiniAllocator<F>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let version = self.version;
+        let sector_len = version.sector_len();
+        let fat_entries_per_sector = version.fat_entries_per_sector();
+        let first_dir_sector = self.first_dir_sector();
+        let first_minifat_sector = self.first_minifat_sector();
+        let first_mini_stream_sector = self.first_mini_stream_sector();
+        let first_data_sector = self.first_data_sector();
+        let chain_start = |start: u32, end: u32| {
+            if start < end {
+                start
+            } else {
+                consts::END_OF_CHAIN
+            }
+        };
+
+        let mut fat = Vec::<u32>::with_capacity(self.num_sectors as usize);
+        fat.resize(self.num_fat_sectors as usize, consts::FAT_SECTOR);
+        fat.resize(first_dir_sector as usize, consts::DIFAT_SECTOR);
+        let mut chains = vec![
+            (first_dir_sector, first_minifat_sector),
+            (first_minifat_sector, first_mini_stream_sector),
+            (first_mini_stream_sector, first_data_sector),
+        ];
+        for chain in self.chains.iter().filter(|chain| !chain.is_mini) {
+            chains
+                .push((chain.new_start, chain.new_start + chain.num_sectors));
+        }
+        for (start, end) in chains {
+            if start < end {
+                fat.extend(start + 1..end);
+                fat.push(consts::END_OF_CHAIN);
+            }
+        }
+        debug_assert_eq!(fat.len(), self.num_sectors as usize);
+        let difat: Vec<u32> = (0..self.num_fat_sectors).collect();
+
+        // Write the header, padded with zeroes to the length of a sector.
+        let mut header = Header {
+            version,
+            mini_sector_shift: consts::MINI_SECTOR_SHIFT,
+            // 2.2 requires this to be zero in V3
+            num_dir_sectors: if version.requires_dir_sector_count() {
+                self.num_dir_sectors
+            } else {
+                0
+            },
+            num_fat_sectors: self.num_fat_sectors,
+            first_dir_sector,
+            first_minifat_sector: chain_start(
+                first_minifat_sector,
+                first_mini_stream_sector,
+            ),
+            num_minifat_sectors: self.num_minifat_sectors,
+            first_difat_sector: chain_start(
+                self.num_fat_sectors,
+                first_dir_sector,
+            ),
+            num_difat_sectors: self.num_difat_sectors,
+            initial_difat_entries: [consts::FREE_SECTOR;
+                consts::NUM_DIFAT_ENTRIES_IN_HEADER],
+        };
+        for (entry, &sector_id) in
+            header.initial_difat_entries.iter_mut().zip(difat.iter())
+        {
+            *entry = sector_id;
+        }
+        header.write_to(writer)?;
+        writer.write_all(&vec![0; sector_len - consts::HEADER_LEN])?;
+
+        // Write FAT sectors:
+        for &entry in fat.iter() {
+            writer.write_le_u32(entry)?;
+        }
+        let num_fat_entries =
+            self.num_fat_sectors as usize * fat_entries_per_sector;
+        for _ in fat.len()..num_fat_entries {
+            writer.write_le_u32(consts::FREE_SECTOR)?;
+        }
+
+        // Write DIFAT sectors:
+        let mut remaining_difat =
+            difat.iter().skip(version.difat_header_entries());
+        for difat_sector_id in self.num_fat_sectors..first_dir_sector {
+            for _ in 0..version.difat_entries_per_sector() {
+                let entry = remaining_difat
+                    .next()
+                    .copied()
+                    .unwrap_or(consts::FREE_SECTOR);
+                writer.write_le_u32(entry)?;
+            }
+            if difat_sector_id + 1 < first_dir_sector {
+                writer.write_le_u32(difat_sector_id + 1)?;
+            } else {
+                writer.write_le_u32(consts::END_OF_CHAIN)?;
+            }
+        }
+
+        // Write directory sectors.  Unallocated entries are written the way
+        // `FreeEntryPolicy::Preserve` leaves them, so that whatever
+        // `deleted_entries` could find in them is kept, but their stale
+        // chains (which are not copied) are forgotten.
+        for stream_id in 0..minialloc.num_dir_entries() {
+            let mut dir_entry = minialloc.dir_entry(stream_id).clone();
+            match dir_entry.obj_type {
+                ObjType::Unallocated => {
+                    dir_entry = FreeEntryPolicy::Preserve.free(&dir_entry);
+                }
+                ObjType::Root => {
+                    dir_entry.start_sector = chain_start(
+                        first_mini_stream_sector,
+                        first_data_sector,
+                    );
+                    dir_entry.stream_len = self.num_mini_sectors as u64
+                        * consts::MINI_SECTOR_LEN as u64;
+                }
+                ObjType::Stream => {
+                    dir_entry.start_sector = MiniAllocator::<F>::chain_key(
+                        &dir_entry,
+                    )
+                    .map_or(consts::END_OF_CHAIN, |key| {
+                        self.chains[self.chain_indices[&key]].new_start
+                    });
+                }
+                ObjType::Storage => {}
+            }
+            dir_entry.write_to(writer)?;
+        }
+        let num_dir_entries =
+            self.num_dir_sectors as usize * version.dir_entries_per_sector();
+        let unallocated = DirEntry::unallocated();
+        for _ in minialloc.num_dir_entries() as usize..num_dir_entries {
+            unallocated.write_to(writer)?;
+        }
+
+        // Write MiniFAT sectors:
+        let mini_chains = || {
+            self.chains
+                .iter()
+                .filter(|chain| chain.is_mini && chain.num_sectors > 0)
+        };
+        for chain in mini_chains() {
+            for mini_sector in
+                chain.new_start + 1..chain.new_start + chain.num_sectors
+            {
+                writer.write_le_u32(mini_sector)?;
+            }
+            writer.write_le_u32(consts::END_OF_CHAIN)?;
+        }
+        let num_minifat_entries =
+            self.num_minifat_sectors as usize * fat_entries_per_sector;
+        for _ in self.num_mini_sectors as usize..num_minifat_entries {
+            writer.write_le_u32(consts::FREE_SECTOR)?;
+        }
+
+        // Write the mini stream, then the data of the other streams:
+        for chain in mini_chains() {
+            let mut source = minialloc.open_mini_chain(chain.old_start)?;
+            let padded_len =
+                chain.num_sectors as u64 * consts::MINI_SECTOR_LEN as u64;
+            copy_padded(&mut source, chain.len, padded_len, writer)?;
+        }
+        let mini_stream_len =
+            self.num_mini_sectors as u64 * consts::MINI_SECTOR_LEN as u64;
+        let padded_len =
+            self.num_mini_stream_sectors as u64 * sector_len as u64;
+        write_zeroes(writer, padded_len - mini_stream_len)?;
+        for chain in self.chains.iter() {
+            if chain.is_mini || chain.num_sectors == 0 {
+                continue;
+            }
+            let mut source =
+                minialloc.open_chain(chain.old_start, SectorInit::Fat)?;
+            let padded_len = chain.num_sectors as u64 * sector_len as u64;
+            copy_padded(&mut source, chain.len, padded_len, writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the first `len` bytes of `source` to `writer`, followed by enough
+/// zeroes to make `padded_len` bytes in all.
+fn copy_padded<R: Read, W: Write>(
+    source: &mut R,
+    len: u64,
+    padded_len: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    let copied = io::copy(&mut source.take(len), writer)?;
+    if copied < len {
+        invalid_data!(
+            "Chain ended after {} bytes, but {} were expected",
+            copied,
+            len
+        );
+    }
+    write_zeroes(writer, padded_len - len)
+}
+
+fn write_zeroes<W: Write>(writer: &mut W, len: u64) -> io::Result<()> {
+    io::copy(&mut io::repeat(0).take(len), writer)?;
+    Ok(())
+}
+
+//===========================================================================//
@@ -1,36 +1,70 @@
 // ========================================================================= //
 
-pub const HEADER_LEN: usize = 512; // length of CFB file header, in bytes
-pub const DIR_ENTRY_LEN: usize = 128; // length of directory entry, in bytes
+//! Numeric values defined by the compound file format, for interpreting the
+//! raw on-disk data exposed by the lower-level APIs (such as
+//! [`CompoundFile::raw_dir_entries`](../struct.CompoundFile.html#method.raw_dir_entries)).
+//! The names in the spec are given in parentheses where they differ.  The
+//! special sector and stream ID values are also available as associated
+//! constants of [`SectorId`](../struct.SectorId.html) and
+//! [`StreamId`](../struct.StreamId.html).
+
+/// The length of the file header, in bytes.
+pub const HEADER_LEN: usize = 512;
+/// The length of a directory entry, in bytes.
+pub const DIR_ENTRY_LEN: usize = 128;
+/// The number of DIFAT entries stored in the header itself.
 pub const NUM_DIFAT_ENTRIES_IN_HEADER: usize = 109;
 
 // Constants for CFB file header values:
+/// The signature at the start of every compound file.
 pub const MAGIC_NUMBER: [u8; 8] =
     [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+/// The minor version number written in the header.
 pub const MINOR_VERSION: u16 = 0x3e;
+/// The byte order mark in the header, which is always little-endian.
 pub const BYTE_ORDER_MARK: u16 = 0xfffe;
+/// The base-2 logarithm of the mini sector length.
 pub const MINI_SECTOR_SHIFT: u16 = 6; // 64-byte mini sectors
+/// The length of a mini sector, in bytes.
 pub const MINI_SECTOR_LEN: usize = 1 << (MINI_SECTOR_SHIFT as usize);
+pub(crate) const MIN_MINI_SECTOR_SHIFT: u16 = 4; // smallest we'll read (16 bytes)
+/// Streams shorter than this many bytes are stored in the mini stream.
 pub const MINI_STREAM_CUTOFF: u32 = 4096;
 
 // Constants for FAT entries:
+/// The largest index of a regular sector (`MAXREGSECT`).
 pub const MAX_REGULAR_SECTOR: u32 = 0xfffffffa;
+/// A reserved sector value that must not be used.
 pub const INVALID_SECTOR: u32 = 0xfffffffb;
+/// Marks a DIFAT sector in the FAT (`DIFSECT`).
 pub const DIFAT_SECTOR: u32 = 0xfffffffc;
+/// Marks a FAT sector in the FAT (`FATSECT`).
 pub const FAT_SECTOR: u32 = 0xfffffffd;
+/// Marks the end of a sector chain (`ENDOFCHAIN`).
 pub const END_OF_CHAIN: u32 = 0xfffffffe;
+/// Marks an unallocated sector in the FAT (`FREESECT`).
 pub const FREE_SECTOR: u32 = 0xffffffff;
 
 // Constants for directory entries:
+/// The name of the root directory entry.
 pub const ROOT_DIR_NAME: &str = "Root Entry";
+/// The object type of an unused directory entry (`STGTY_INVALID`).
 pub const OBJ_TYPE_UNALLOCATED: u8 = 0;
+/// The object type of a storage (`STGTY_STORAGE`).
 pub const OBJ_TYPE_STORAGE: u8 = 1;
+/// The object type of a stream (`STGTY_STREAM`).
 pub const OBJ_TYPE_STREAM: u8 = 2;
+/// The object type of the root storage (`STGTY_ROOT`).
 pub const OBJ_TYPE_ROOT: u8 = 5;
+/// The color of a red node in the directory's red-black trees.
 pub const COLOR_RED: u8 = 0;
+/// The color of a black node in the directory's red-black trees.
 pub const COLOR_BLACK: u8 = 1;
+/// The stream ID of the root directory entry.
 pub const ROOT_STREAM_ID: u32 = 0;
+/// The largest regular stream ID (`MAXREGSID`).
 pub const MAX_REGULAR_STREAM_ID: u32 = 0xfffffffa;
+/// Marks a missing sibling or child in a directory entry (`NOSTREAM`).
 pub const NO_STREAM: u32 = 0xffffffff;
 
 pub(crate) fn prettify(sectors: &[u32]) -> Vec<Sector> {
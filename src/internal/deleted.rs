@@ -0,0 +1,92 @@
+use crate::internal::{DirEntry, ObjType, StreamId, Timestamp};
+use std::time::SystemTime;
+
+//===========================================================================//
+
+/// Determines what happens to the directory entry of an object removed from
+/// a compound file (see
+/// [`CompoundFile::set_free_entry_policy`](../struct.CompoundFile.html#method.set_free_entry_policy)).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FreeEntryPolicy {
+    /// Mark the entry unallocated, but keep the object's name and
+    /// timestamps in it, so that they can be found afterwards with
+    /// [`CompoundFile::deleted_entries`](../struct.CompoundFile.html#method.deleted_entries).
+    /// The entry's tree links and stream location are still cleared.
+    Preserve,
+    /// Reset the entry to all zeros, as MS-CFB section 2.6.3 requires for
+    /// unallocated entries.  This is the default.
+    #[default]
+    Scrub,
+}
+
+impl FreeEntryPolicy {
+    /// Returns the directory entry to leave in place of the given one once
+    /// its object has been removed.
+    pub(crate) fn free(self, dir_entry: &DirEntry) -> DirEntry {
+        let mut freed = DirEntry::unallocated();
+        if self == FreeEntryPolicy::Preserve {
+            freed.name = dir_entry.name.clone();
+            freed.creation_time = dir_entry.creation_time;
+            freed.modified_time = dir_entry.modified_time;
+        }
+        freed
+    }
+}
+
+//===========================================================================//
+
+/// What remains of a removed object in an unallocated directory entry, as
+/// returned by
+/// [`CompoundFile::deleted_entries`](../struct.CompoundFile.html#method.deleted_entries).
+#[derive(Clone, Debug)]
+pub struct DeletedEntry {
+    stream_id: u32,
+    name: String,
+    creation_time: Timestamp,
+    modified_time: Timestamp,
+}
+
+impl DeletedEntry {
+    /// Returns a `DeletedEntry` for the given directory entry, or `None` if
+    /// the entry is in use or has nothing left in it.
+    pub(crate) fn new(
+        stream_id: u32,
+        dir_entry: &DirEntry,
+    ) -> Option<DeletedEntry> {
+        if dir_entry.obj_type != ObjType::Unallocated
+            || (dir_entry.name.is_empty()
+                && dir_entry.creation_time == Timestamp::zero()
+                && dir_entry.modified_time == Timestamp::zero())
+        {
+            return None;
+        }
+        Some(DeletedEntry {
+            stream_id,
+            name: dir_entry.name.clone(),
+            creation_time: dir_entry.creation_time,
+            modified_time: dir_entry.modified_time,
+        })
+    }
+
+    /// Returns the index of the directory entry.
+    pub fn stream_id(&self) -> StreamId {
+        StreamId::new(self.stream_id)
+    }
+
+    /// Returns the name that the removed object had.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the creation time that the removed object had.
+    pub fn created(&self) -> SystemTime {
+        self.creation_time.to_system_time()
+    }
+
+    /// Returns the modification time that the removed object had.
+    pub fn modified(&self) -> SystemTime {
+        self.modified_time.to_system_time()
+    }
+}
+
+//===========================================================================//
@@ -1,11 +1,16 @@
 use crate::internal::{
-    self, consts, Allocator, Chain, Color, DirEntry, ObjType, Sector,
-    SectorInit, Timestamp, Validation, Version,
+    self, consts, Allocator, Chain, ChainName, Color, DeletedEntry, DirEntry,
+    FreeEntryPolicy, Metrics, ObjType, ReadOnly, Sector, SectorAllocator,
+    SectorInit, Timestamp, Validation, ValidationIssue, ValidationIssueKind,
+    Version,
 };
 use crate::WriteLeNumber;
 use fnv::FnvHashSet;
 use std::cmp::Ordering;
-use std::io::{self, Seek, SeekFrom, Write};
+use std::collections::BTreeSet;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 
 //===========================================================================//
 
@@ -18,12 +23,59 @@ macro_rules! malformed {
 
 //===========================================================================//
 
+/// The source of `Directory::tree_stamp` values, shared by every directory
+/// so that no two directories (or states of one directory) get the same
+/// stamp.
+static NEXT_TREE_STAMP: AtomicU64 = AtomicU64::new(1);
+
+fn fresh_tree_stamp() -> u64 {
+    NEXT_TREE_STAMP.fetch_add(1, AtomicOrdering::Relaxed)
+}
+
+/// Returns true if the two entries differ in any way that matters for
+/// finding objects by path.
+fn shape_differs(old: &DirEntry, new: &DirEntry) -> bool {
+    old.obj_type != new.obj_type
+        || old.left_sibling != new.left_sibling
+        || old.right_sibling != new.right_sibling
+        || old.child != new.child
+        || old.name != new.name
+}
+
+//===========================================================================//
+
 /// A wrapper around the sector allocator that additionally provides management
 /// of the CFB directory chain.
 pub struct Directory<F> {
     allocator: Allocator<F>,
     dir_entries: Vec<DirEntry>,
     dir_start_sector: u32,
+    free_dir_entries: BTreeSet<u32>,
+    /// For each directory entry, the stream ID of the storage containing it
+    /// (or `NO_STREAM` for the root, and for entries that aren't in the
+    /// tree), since the entries themselves only point downwards.
+    parents: Vec<u32>,
+    /// For each directory entry, a number that changes whenever the entry is
+    /// allocated or freed, so that stream handles can tell whether their
+    /// entry still holds the object they were opened for.
+    generations: Vec<u64>,
+    /// The next number to hand out in `generations`; these are never reused,
+    /// even after the directory shrinks.
+    next_generation: u64,
+    free_entry_policy: FreeEntryPolicy,
+    /// The directory entries changed since the last call to
+    /// `take_touched_entries`, if changes are being tracked.
+    touched_entries: Option<FnvHashSet<u32>>,
+    /// A number that changes whenever any entry's name, type, or links
+    /// change (or entries are added or dropped), and that no other
+    /// directory ever has, so that a `ResolvedPath` with the same stamp is
+    /// known to still be accurate.
+    tree_stamp: u64,
+    /// The sectors of the directory chain, and the allocator's link
+    /// generation when they were looked up (see `dir_sector_ids`), so that
+    /// finding an entry's sector doesn't mean following the chain from the
+    /// start every time.
+    dir_sector_cache: Option<(u64, Vec<u32>)>,
 }
 
 impl<F> Directory<F> {
@@ -32,9 +84,30 @@ impl<F> Directory<F> {
         dir_entries: Vec<DirEntry>,
         dir_start_sector: u32,
         validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
     ) -> io::Result<Directory<F>> {
-        let directory = Directory { allocator, dir_entries, dir_start_sector };
-        directory.validate(validation)?;
+        let free_dir_entries = dir_entries
+            .iter()
+            .enumerate()
+            .filter(|&(_, entry)| entry.obj_type == ObjType::Unallocated)
+            .map(|(stream_id, _)| stream_id as u32)
+            .collect();
+        let generations = vec![0; dir_entries.len()];
+        let mut directory = Directory {
+            allocator,
+            dir_entries,
+            dir_start_sector,
+            free_dir_entries,
+            parents: Vec::new(),
+            generations,
+            next_generation: 1,
+            free_entry_policy: FreeEntryPolicy::default(),
+            touched_entries: None,
+            tree_stamp: fresh_tree_stamp(),
+            dir_sector_cache: None,
+        };
+        directory.validate(validation, issues)?;
+        directory.parents = directory.compute_parents();
         Ok(directory)
     }
 
@@ -46,6 +119,14 @@ impl<F> Directory<F> {
         self.allocator.inner()
     }
 
+    pub fn metrics(&self) -> &Metrics {
+        self.allocator.metrics()
+    }
+
+    pub fn metrics_mut(&mut self) -> &mut Metrics {
+        self.allocator.metrics_mut()
+    }
+
     pub fn sector_len(&self) -> usize {
         self.allocator.sector_len()
     }
@@ -54,13 +135,245 @@ impl<F> Directory<F> {
         self.allocator.into_inner()
     }
 
+    pub fn set_read_only(&mut self, reason: ReadOnly) {
+        self.allocator.set_read_only(reason);
+    }
+
+    pub fn allocator(&self) -> &Allocator<F> {
+        &self.allocator
+    }
+
+    pub fn dir_start_sector(&self) -> u32 {
+        self.dir_start_sector
+    }
+
+    pub fn set_allocator_policy(&mut self, policy: Box<dyn SectorAllocator>) {
+        self.allocator.set_policy(policy);
+    }
+
+    pub fn set_stream_path(
+        &mut self,
+        path: Option<PathBuf>,
+    ) -> Option<PathBuf> {
+        self.allocator.set_stream_path(path)
+    }
+
+    /// Tells the allocator whose data is about to be written, so that an
+    /// installed allocation policy can be given the stream's path.  Finding
+    /// the path means searching the directory tree, so this is skipped
+    /// unless a policy has been installed.
+    pub fn set_allocating_stream(&mut self, stream_id: u32) {
+        if self.allocator.has_policy() {
+            let path = self.path_for_stream_id(stream_id);
+            self.allocator.set_stream_path(path);
+        }
+    }
+
+    /// Returns the path of the given object, found by searching the
+    /// directory tree from the root, or `None` if it isn't in the tree.
+    pub fn path_for_stream_id(&self, stream_id: u32) -> Option<PathBuf> {
+        if stream_id == consts::ROOT_STREAM_ID {
+            return Some(PathBuf::from("/"));
+        }
+        let mut stack =
+            vec![(self.root_dir_entry().child, PathBuf::from("/"))];
+        while let Some((id, parent_path)) = stack.pop() {
+            if id == consts::NO_STREAM {
+                continue;
+            }
+            let dir_entry = self.dir_entry(id);
+            let path = parent_path.join(&dir_entry.name);
+            if id == stream_id {
+                return Some(path);
+            }
+            stack.push((dir_entry.left_sibling, parent_path.clone()));
+            stack.push((dir_entry.right_sibling, parent_path));
+            stack.push((dir_entry.child, path));
+        }
+        None
+    }
+
+    /// Returns the path of every object in the tree, indexed by stream ID,
+    /// with `None` for unallocated entries and entries that aren't in the
+    /// tree.  Each path is built from its parent's, so this takes a single
+    /// pass over the directory rather than a search of the tree for each
+    /// entry.  Temporary objects, and anything within them, are left out
+    /// unless `include_temporaries` is set.
+    pub fn paths_by_stream_id(
+        &self,
+        include_temporaries: bool,
+    ) -> Vec<Option<PathBuf>> {
+        let num_entries = self.dir_entries.len();
+        let mut paths: Vec<Option<PathBuf>> = vec![None; num_entries];
+        let mut done = vec![false; num_entries];
+        paths[consts::ROOT_STREAM_ID as usize] = Some(PathBuf::from("/"));
+        done[consts::ROOT_STREAM_ID as usize] = true;
+        let mut pending = Vec::new();
+        for stream_id in 0..num_entries as u32 {
+            // Find the nearest ancestor whose path is already known, then
+            // fill in the paths back down to this entry.
+            let mut current = stream_id;
+            while !done[current as usize] {
+                pending.push(current);
+                match self.parent_id(current) {
+                    Some(parent_id) => current = parent_id,
+                    None => break,
+                }
+            }
+            while let Some(id) = pending.pop() {
+                let name = &self.dir_entry(id).name;
+                let parent_path = self
+                    .parent_id(id)
+                    .and_then(|parent_id| paths[parent_id as usize].as_ref());
+                paths[id as usize] = match parent_path {
+                    Some(_)
+                        if !include_temporaries
+                            && internal::path::is_temporary_name(name) =>
+                    {
+                        None
+                    }
+                    Some(parent_path) => Some(parent_path.join(name)),
+                    None => None,
+                };
+                done[id as usize] = true;
+            }
+        }
+        paths
+    }
+
+    /// Returns the stream ID of the storage containing the given object, or
+    /// `None` for the root (or an entry that isn't in the tree).
+    pub fn parent_id(&self, stream_id: u32) -> Option<u32> {
+        match self.parents.get(stream_id as usize) {
+            Some(&parent_id) if parent_id != consts::NO_STREAM => {
+                Some(parent_id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the current generation of the given directory entry (see
+    /// `generations`), or `None` if the directory no longer has that entry.
+    pub fn generation(&self, stream_id: u32) -> Option<u64> {
+        self.generations.get(stream_id as usize).copied()
+    }
+
+    /// Gives the given directory entry a new generation number, invalidating
+    /// any stream handles for it.
+    fn bump_generation(&mut self, stream_id: u32) {
+        self.generations[stream_id as usize] = self.next_generation;
+        self.next_generation += 1;
+    }
+
+    /// Returns the directory's current tree stamp (see `tree_stamp`).
+    pub fn tree_stamp(&self) -> u64 {
+        self.tree_stamp
+    }
+
+    fn bump_tree_stamp(&mut self) {
+        self.tree_stamp = fresh_tree_stamp();
+    }
+
+    /// Works out the parent of every entry by walking the (already
+    /// validated) tree from the root.
+    fn compute_parents(&self) -> Vec<u32> {
+        let mut parents = vec![consts::NO_STREAM; self.dir_entries.len()];
+        let mut stack = vec![consts::ROOT_STREAM_ID];
+        while let Some(parent_id) = stack.pop() {
+            for stream_id in self.children_of(parent_id) {
+                parents[stream_id as usize] = parent_id;
+                stack.push(stream_id);
+            }
+        }
+        parents
+    }
+
+    /// Returns the stream IDs of the entries directly within the given
+    /// storage, in no particular order.
+    fn children_of(&self, parent_id: u32) -> Vec<u32> {
+        let mut children = Vec::new();
+        let mut stack = vec![self.dir_entry(parent_id).child];
+        while let Some(stream_id) = stack.pop() {
+            if stream_id == consts::NO_STREAM {
+                continue;
+            }
+            let dir_entry = self.dir_entry(stream_id);
+            stack.push(dir_entry.left_sibling);
+            stack.push(dir_entry.right_sibling);
+            children.push(stream_id);
+        }
+        children
+    }
+
+    /// Returns the stream IDs of the given entry and of everything within
+    /// it, in no particular order.
+    pub fn subtree_ids(&self, stream_id: u32) -> Vec<u32> {
+        let mut subtree = Vec::new();
+        let mut stack = vec![stream_id];
+        while let Some(stream_id) = stack.pop() {
+            subtree.push(stream_id);
+            if self.dir_entry(stream_id).obj_type != ObjType::Stream {
+                stack.extend(self.children_of(stream_id));
+            }
+        }
+        subtree
+    }
+
+    /// Returns the number of unallocated directory entries, according to the
+    /// free-entry index.
+    pub fn num_free_dir_entries(&self) -> u32 {
+        self.free_dir_entries.len() as u32
+    }
+
+    /// Returns the stream ID of the object at the given name chain, or `None`
+    /// if there is no such object.  Streams are never descended into, so a
+    /// name chain that continues past a stream never resolves (see
+    /// `stream_prefix_len`).
     pub fn stream_id_for_name_chain(&self, names: &[&str]) -> Option<u32> {
+        self.walk_name_chain(names).ok()
+    }
+
+    /// If some name other than the last one in the given name chain refers to
+    /// a stream, returns the length of the prefix of the chain that ends at
+    /// that stream.
+    pub fn stream_prefix_len(&self, names: &[&str]) -> Option<usize> {
+        match self.walk_name_chain(names) {
+            Ok(_) => None,
+            Err((prefix_len, stream_id)) => {
+                if self.dir_entry(stream_id).obj_type == ObjType::Stream {
+                    Some(prefix_len)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Returns the length of the longest prefix of the given name chain that
+    /// names an existing object, along with that object's stream ID.
+    pub fn longest_existing_prefix(&self, names: &[&str]) -> (usize, u32) {
+        match self.walk_name_chain(names) {
+            Ok(stream_id) => (names.len(), stream_id),
+            Err(prefix) => prefix,
+        }
+    }
+
+    /// Walks down the given name chain, returning the stream ID of the object
+    /// at the end of it.  On failure, returns the length of the longest
+    /// prefix that was found, and the stream ID of the object at the end of
+    /// that prefix (which is a stream if that's why the walk failed).
+    fn walk_name_chain(&self, names: &[&str]) -> Result<u32, (usize, u32)> {
         let mut stream_id = consts::ROOT_STREAM_ID;
-        for name in names.iter() {
-            stream_id = self.dir_entry(stream_id).child;
+        for (index, name) in names.iter().enumerate() {
+            let parent_id = stream_id;
+            let parent = self.dir_entry(parent_id);
+            if parent.obj_type == ObjType::Stream {
+                return Err((index, parent_id));
+            }
+            stream_id = parent.child;
             loop {
                 if stream_id == consts::NO_STREAM {
-                    return None;
+                    return Err((index, parent_id));
                 }
                 let dir_entry = self.dir_entry(stream_id);
                 match internal::path::compare_names(name, &dir_entry.name) {
@@ -70,7 +383,7 @@ impl<F> Directory<F> {
                 }
             }
         }
-        Some(stream_id)
+        Ok(stream_id)
     }
 
     pub fn open_chain(
@@ -85,27 +398,274 @@ impl<F> Directory<F> {
         self.dir_entry(consts::ROOT_STREAM_ID)
     }
 
+    pub fn dir_entries(&self) -> &[DirEntry] {
+        &self.dir_entries
+    }
+
+    pub fn free_entry_policy(&self) -> FreeEntryPolicy {
+        self.free_entry_policy
+    }
+
+    pub fn set_free_entry_policy(&mut self, policy: FreeEntryPolicy) {
+        self.free_entry_policy = policy;
+    }
+
+    pub fn zero_freed_sectors(&self) -> bool {
+        self.allocator.zero_freed_sectors()
+    }
+
+    pub fn set_zero_freed_sectors(&mut self, zero: bool) {
+        self.allocator.set_zero_freed_sectors(zero);
+    }
+
+    /// Returns what remains of removed objects in unallocated directory
+    /// entries, in stream ID order.
+    pub fn deleted_entries(&self) -> Vec<DeletedEntry> {
+        self.dir_entries
+            .iter()
+            .enumerate()
+            .filter_map(|(stream_id, dir_entry)| {
+                DeletedEntry::new(stream_id as u32, dir_entry)
+            })
+            .collect()
+    }
+
     pub fn dir_entry(&self, stream_id: u32) -> &DirEntry {
         &self.dir_entries[stream_id as usize]
     }
 
+    /// Returns the given entry for modification, assuming that its shape
+    /// (see `shape_differs`) may change.
     fn dir_entry_mut(&mut self, stream_id: u32) -> &mut DirEntry {
+        self.bump_tree_stamp();
+        self.touch_dir_entry(stream_id)
+    }
+
+    /// Returns the given entry for modification, leaving it to the caller
+    /// to bump the tree stamp if its shape changes.
+    fn touch_dir_entry(&mut self, stream_id: u32) -> &mut DirEntry {
+        if let Some(touched) = self.touched_entries.as_mut() {
+            touched.insert(stream_id);
+        }
         &mut self.dir_entries[stream_id as usize]
     }
 
-    fn validate(&self, validation: Validation) -> io::Result<()> {
+    /// Sets whether to keep track of which directory entries (and FAT
+    /// entries) are changed, for `take_touched_entries`.
+    pub fn set_track_changes(&mut self, track: bool) {
+        self.touched_entries =
+            if track { Some(FnvHashSet::default()) } else { None };
+        self.allocator.set_track_changes(track);
+    }
+
+    /// Returns the directory entries changed since the last call (if changes
+    /// are being tracked), in order.
+    pub fn take_touched_entries(&mut self) -> Vec<u32> {
+        let mut stream_ids: Vec<u32> = match self.touched_entries.as_mut() {
+            Some(touched) => touched.drain().collect(),
+            None => Vec::new(),
+        };
+        stream_ids.sort_unstable();
+        stream_ids
+    }
+
+    pub fn take_touched_sectors(&mut self) -> Vec<u32> {
+        self.allocator.take_touched_sectors()
+    }
+
+    /// Returns a description of each way in which the given directory
+    /// entries are inconsistent with their immediate neighbors in the tree,
+    /// or with the free-entry and parent indices.  Entries past the end of
+    /// the directory are ignored.
+    pub fn entry_problems(&self, stream_ids: &[u32]) -> Vec<String> {
+        let num_entries = self.dir_entries.len();
+        let mut problems = Vec::new();
+        for &stream_id in stream_ids {
+            let Some(dir_entry) = self.dir_entries.get(stream_id as usize)
+            else {
+                continue;
+            };
+            let parent_id = self.parents[stream_id as usize];
+            let is_free = dir_entry.obj_type == ObjType::Unallocated;
+            if is_free != self.free_dir_entries.contains(&stream_id) {
+                problems.push(format!(
+                    "entry {} has object type {:?}, but is {}listed as free",
+                    stream_id,
+                    dir_entry.obj_type,
+                    if is_free { "not " } else { "" }
+                ));
+            }
+            let links = [
+                ("left sibling", dir_entry.left_sibling),
+                ("right sibling", dir_entry.right_sibling),
+                ("child", dir_entry.child),
+            ];
+            if is_free {
+                if links.iter().any(|&(_, id)| id != consts::NO_STREAM) {
+                    problems.push(format!(
+                        "unallocated entry {} still has tree links",
+                        stream_id
+                    ));
+                }
+                if parent_id != consts::NO_STREAM {
+                    problems.push(format!(
+                        "unallocated entry {} still has parent {}",
+                        stream_id, parent_id
+                    ));
+                }
+                continue;
+            }
+            for (link, id) in links {
+                if id == consts::NO_STREAM {
+                    continue;
+                }
+                let Some(linked) = self.dir_entries.get(id as usize) else {
+                    problems.push(format!(
+                        "entry {} has {} {}, but directory entry count is {}",
+                        stream_id, link, id, num_entries
+                    ));
+                    continue;
+                };
+                if linked.obj_type == ObjType::Unallocated {
+                    problems.push(format!(
+                        "entry {} has {} {}, which is unallocated",
+                        stream_id, link, id
+                    ));
+                    continue;
+                }
+                let ordering = match link {
+                    "left sibling" => internal::path::compare_names(
+                        &linked.name,
+                        &dir_entry.name,
+                    ),
+                    "right sibling" => internal::path::compare_names(
+                        &dir_entry.name,
+                        &linked.name,
+                    ),
+                    _ => Ordering::Less,
+                };
+                if ordering != Ordering::Less {
+                    problems.push(format!(
+                        "name ordering, {:?} vs its {} {:?}",
+                        dir_entry.name, link, linked.name
+                    ));
+                }
+                let expected_parent =
+                    if link == "child" { stream_id } else { parent_id };
+                if self.parents[id as usize] != expected_parent {
+                    problems.push(format!(
+                        "entry {} is the {} of entry {}, but its parent is \
+                         recorded as {}",
+                        id, link, stream_id, self.parents[id as usize]
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Returns a description of each way in which the directory tree as a
+    /// whole is malformed: loops, entries reachable more than once or not at
+    /// all, siblings out of order, or a parent index that doesn't match the
+    /// tree.
+    pub fn tree_problems(&self) -> Vec<String> {
+        let num_entries = self.dir_entries.len();
+        let mut problems = Vec::new();
+        let mut visited = vec![false; num_entries];
+        visited[consts::ROOT_STREAM_ID as usize] = true;
+        // Each item is a stream ID, the storage containing it, and the names
+        // that its subtree must sort strictly between.
+        let mut stack: Vec<(u32, u32, Option<&str>, Option<&str>)> = vec![(
+            self.root_dir_entry().child,
+            consts::ROOT_STREAM_ID,
+            None,
+            None,
+        )];
+        while let Some((stream_id, parent_id, lower, upper)) = stack.pop() {
+            if stream_id == consts::NO_STREAM
+                || stream_id as usize >= num_entries
+            {
+                continue;
+            }
+            if visited[stream_id as usize] {
+                problems.push(format!(
+                    "entry {} is reachable more than once",
+                    stream_id
+                ));
+                continue;
+            }
+            visited[stream_id as usize] = true;
+            let dir_entry = self.dir_entry(stream_id);
+            let name = dir_entry.name.as_str();
+            let too_low = lower.is_some_and(|lower| {
+                internal::path::compare_names(lower, name) != Ordering::Less
+            });
+            let too_high = upper.is_some_and(|upper| {
+                internal::path::compare_names(name, upper) != Ordering::Less
+            });
+            if too_low || too_high {
+                problems.push(format!(
+                    "entry {} ({:?}) is out of order among its siblings",
+                    stream_id, name
+                ));
+            }
+            if self.parents[stream_id as usize] != parent_id {
+                problems.push(format!(
+                    "entry {} is within entry {}, but its parent is \
+                     recorded as {}",
+                    stream_id, parent_id, self.parents[stream_id as usize]
+                ));
+            }
+            stack.push((dir_entry.left_sibling, parent_id, lower, Some(name)));
+            stack.push((
+                dir_entry.right_sibling,
+                parent_id,
+                Some(name),
+                upper,
+            ));
+            stack.push((dir_entry.child, stream_id, None, None));
+        }
+        for (stream_id, dir_entry) in self.dir_entries.iter().enumerate() {
+            let is_free = dir_entry.obj_type == ObjType::Unallocated;
+            if !is_free && !visited[stream_id] {
+                problems.push(format!(
+                    "entry {} ({:?}) is allocated but not in the tree",
+                    stream_id, dir_entry.name
+                ));
+            }
+        }
+        if let Some(&stream_id) =
+            self.free_dir_entries.range(num_entries as u32..).next()
+        {
+            problems.push(format!(
+                "entry {} is listed as free, but directory entry count is {}",
+                stream_id, num_entries
+            ));
+        }
+        problems.extend(
+            self.entry_problems(&(0..num_entries as u32).collect::<Vec<_>>()),
+        );
+        problems
+    }
+
+    pub fn fat_problems(&self, sector_ids: Option<&[u32]>) -> Vec<String> {
+        self.allocator.fat_problems(sector_ids)
+    }
+
+    fn validate(
+        &mut self,
+        validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
+    ) -> io::Result<()> {
         if self.dir_entries.is_empty() {
             malformed!("root entry is missing");
         }
-        let root_entry = self.root_dir_entry();
-        if root_entry.stream_len % consts::MINI_SECTOR_LEN as u64 != 0 {
-            malformed!(
-                "root stream len is {}, but should be multiple of {}",
-                root_entry.stream_len,
-                consts::MINI_SECTOR_LEN
-            );
-        }
         let mut visited = FnvHashSet::default();
+        // Links that lead nowhere (child IDs that are out of range or refer
+        // to an unallocated entry, and sibling IDs that are out of range):
+        // the entry containing the link, which link it is, the ID it refers
+        // to, and a description of the problem.
+        let mut dangling = Vec::<(u32, Link, u32, String)>::new();
         let mut stack = vec![(consts::ROOT_STREAM_ID, false)];
         while let Some((stream_id, parent_is_red)) = stack.pop() {
             if visited.contains(&stream_id) {
@@ -135,68 +695,203 @@ impl<F> Directory<F> {
             // (see https://github.com/mdsteele/rust-cfb/issues/10).  We still
             // want to be able to read these files, so we only consider this an
             // error under Strict validation.
-            if parent_is_red && node_is_red && validation.is_strict() {
-                malformed!("RB tree has adjacent red nodes");
-            }
-            let left_sibling = dir_entry.left_sibling;
-            if left_sibling != consts::NO_STREAM {
-                if left_sibling as usize >= self.dir_entries.len() {
-                    malformed!(
-                        "left sibling index is {}, but directory entry count \
-                         is {}",
-                        left_sibling,
-                        self.dir_entries.len()
-                    );
-                }
-                let entry = &self.dir_entry(left_sibling);
-                if internal::path::compare_names(&entry.name, &dir_entry.name)
-                    != Ordering::Less
-                {
-                    malformed!(
-                        "name ordering, {:?} vs {:?}",
-                        dir_entry.name,
-                        entry.name
-                    );
+            if parent_is_red && node_is_red {
+                if validation.is_strict() {
+                    malformed!("RB tree has adjacent red nodes");
                 }
-                stack.push((left_sibling, node_is_red));
+                issues.push(
+                    ValidationIssue::new(
+                        ValidationIssueKind::AdjacentRedNodes,
+                        format!(
+                            "Entry {:?} is red and has a red parent",
+                            dir_entry.name
+                        ),
+                    )
+                    .with_stream_id(stream_id),
+                );
             }
-            let right_sibling = dir_entry.right_sibling;
-            if right_sibling != consts::NO_STREAM {
-                if right_sibling as usize >= self.dir_entries.len() {
-                    malformed!(
-                        "right sibling index is {}, but directory entry count \
-                         is {}",
-                        right_sibling, self.dir_entries.len());
-                }
-                let entry = &self.dir_entry(right_sibling);
-                if internal::path::compare_names(&dir_entry.name, &entry.name)
-                    != Ordering::Less
-                {
-                    malformed!(
-                        "name ordering, {:?} vs {:?}",
-                        dir_entry.name,
-                        entry.name
-                    );
+            for link in [Link::Left, Link::Right, Link::Child] {
+                let target = link.get(dir_entry);
+                if target == consts::NO_STREAM {
+                    continue;
                 }
-                stack.push((right_sibling, node_is_red));
-            }
-            let child = dir_entry.child;
-            if child != consts::NO_STREAM {
-                if child as usize >= self.dir_entries.len() {
-                    malformed!(
-                        "child index is {}, but directory entry count is {}",
-                        child,
+                let problem = if target as usize >= self.dir_entries.len() {
+                    format!(
+                        "{} index is {}, but directory entry count is {}",
+                        link.name(),
+                        target,
                         self.dir_entries.len()
-                    );
+                    )
+                } else if matches!(link, Link::Child)
+                    && self.dir_entry(target).obj_type == ObjType::Unallocated
+                {
+                    format!(
+                        "{} {} is an unallocated entry",
+                        link.name(),
+                        target
+                    )
+                } else {
+                    let entry = &self.dir_entry(target);
+                    let out_of_order = match link {
+                        Link::Left => {
+                            internal::path::compare_names(
+                                &entry.name,
+                                &dir_entry.name,
+                            ) != Ordering::Less
+                        }
+                        Link::Right => {
+                            internal::path::compare_names(
+                                &dir_entry.name,
+                                &entry.name,
+                            ) != Ordering::Less
+                        }
+                        Link::Child => false,
+                    };
+                    if out_of_order {
+                        malformed!(
+                            "name ordering, {:?} vs {:?}",
+                            dir_entry.name,
+                            entry.name
+                        );
+                    }
+                    match link {
+                        Link::Child => stack.push((target, false)),
+                        _ => stack.push((target, node_is_red)),
+                    }
+                    continue;
+                };
+                if validation.is_strict() {
+                    malformed!(problem);
                 }
-                stack.push((child, false));
+                dangling.push((stream_id, link, target, problem));
             }
         }
+        // Treat each dangling link as an empty subtree, so that the rest of
+        // the tree can still be read.  This is done only once the whole tree
+        // has been checked, so that the entries' paths can be worked out.
+        for &(stream_id, link, _, _) in dangling.iter() {
+            link.set(self.dir_entry_mut(stream_id), consts::NO_STREAM);
+        }
+        for (stream_id, link, target, problem) in dangling {
+            let path = self
+                .path_for_stream_id(stream_id)
+                .unwrap_or_else(|| PathBuf::from("?"));
+            let issue = match link {
+                Link::Child => ValidationIssue::new(
+                    ValidationIssueKind::DanglingChild,
+                    format!(
+                        "Storage {:?} has a dangling child ({}), which was \
+                         treated as empty",
+                        path, problem
+                    ),
+                ),
+                Link::Left | Link::Right => ValidationIssue::new(
+                    ValidationIssueKind::DanglingSibling,
+                    format!(
+                        "Entry {:?} has a dangling {} ({}), whose branch \
+                         was skipped",
+                        path,
+                        link.name(),
+                        problem
+                    ),
+                ),
+            };
+            issues
+                .push(issue.with_stream_id(stream_id).with_target_id(target));
+        }
         Ok(())
     }
 }
 
+/// One of the three links from a directory entry to others in the tree.
+#[derive(Clone, Copy)]
+enum Link {
+    Left,
+    Right,
+    Child,
+}
+
+impl Link {
+    fn name(self) -> &'static str {
+        match self {
+            Link::Left => "left sibling",
+            Link::Right => "right sibling",
+            Link::Child => "child",
+        }
+    }
+
+    fn get(self, dir_entry: &DirEntry) -> u32 {
+        match self {
+            Link::Left => dir_entry.left_sibling,
+            Link::Right => dir_entry.right_sibling,
+            Link::Child => dir_entry.child,
+        }
+    }
+
+    fn set(self, dir_entry: &mut DirEntry, stream_id: u32) {
+        match self {
+            Link::Left => dir_entry.left_sibling = stream_id,
+            Link::Right => dir_entry.right_sibling = stream_id,
+            Link::Child => dir_entry.child = stream_id,
+        }
+    }
+}
+
+impl<F> Directory<F> {
+    /// Returns the sectors of the directory chain, in order.  These are
+    /// remembered between calls, and only looked up again from the start of
+    /// the chain if an existing link in the FAT has changed since; if the
+    /// chain has merely been extended (as when the directory grows), only
+    /// the new sectors are looked up.
+    fn dir_sector_ids(&mut self) -> io::Result<&[u32]> {
+        let generation = self.allocator.link_generation();
+        let sector_ids = match self.dir_sector_cache.take() {
+            Some((cached_generation, mut sector_ids))
+                if cached_generation == generation
+                    && sector_ids.first() == Some(&self.dir_start_sector) =>
+            {
+                loop {
+                    let position = sector_ids.len() - 1;
+                    let next = self.allocator.next(
+                        sector_ids[position],
+                        ChainName::Directory,
+                        position,
+                    )?;
+                    if next == consts::END_OF_CHAIN {
+                        break;
+                    }
+                    if sector_ids.len() > self.allocator.fat().len() {
+                        invalid_data!(
+                            "The {} contains a loop",
+                            ChainName::Directory
+                        );
+                    }
+                    sector_ids.push(next);
+                }
+                sector_ids
+            }
+            _ => self.allocator.chain_sector_ids(
+                self.dir_start_sector,
+                ChainName::Directory,
+            )?,
+        };
+        Ok(&self.dir_sector_cache.insert((generation, sector_ids)).1)
+    }
+}
+
 impl<F: Seek> Directory<F> {
+    pub fn check_backing_len(&mut self) -> io::Result<()> {
+        self.allocator.check_backing_len()
+    }
+
+    pub fn backing_len(&mut self) -> io::Result<u64> {
+        self.allocator.backing_len()
+    }
+
+    pub fn diagnose_read_error(&mut self, error: io::Error) -> io::Error {
+        self.allocator.diagnose_read_error(error)
+    }
+
     pub fn seek_within_header(
         &mut self,
         offset_within_header: u64,
@@ -219,11 +914,14 @@ impl<F: Seek> Directory<F> {
         let dir_entries_per_sector =
             self.version().dir_entries_per_sector() as u32;
         let index_within_sector = stream_id % dir_entries_per_sector;
-        let mut directory_sector = self.dir_start_sector;
-        for _ in 0..(stream_id / dir_entries_per_sector) {
-            debug_assert_ne!(directory_sector, consts::END_OF_CHAIN);
-            directory_sector = self.allocator.next(directory_sector)?;
-        }
+        let position = (stream_id / dir_entries_per_sector) as usize;
+        let Some(&directory_sector) = self.dir_sector_ids()?.get(position)
+        else {
+            invalid_data!(
+                "Directory entry {} is past the end of the directory chain",
+                stream_id
+            );
+        };
         self.allocator.seek_within_subsector(
             directory_sector,
             index_within_sector,
@@ -233,7 +931,109 @@ impl<F: Seek> Directory<F> {
     }
 }
 
+impl<F: Read + Seek> Directory<F> {
+    pub fn read_span(
+        &mut self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        self.allocator.read_span(offset, buf)
+    }
+
+    /// Reads the on-disk bytes of the given directory entry, without parsing
+    /// them.
+    pub fn read_raw_dir_entry(
+        &mut self,
+        stream_id: u32,
+    ) -> io::Result<[u8; consts::DIR_ENTRY_LEN]> {
+        let mut raw = [0u8; consts::DIR_ENTRY_LEN];
+        self.seek_to_dir_entry(stream_id)?.read_exact(&mut raw)?;
+        Ok(raw)
+    }
+}
+
 impl<F: Write + Seek> Directory<F> {
+    pub fn repair_backing_len(&mut self) -> io::Result<u64> {
+        self.allocator.repair_backing_len()
+    }
+
+    pub fn truncate_backing(
+        &mut self,
+        set_len: fn(&mut F, u64) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        self.allocator.truncate_backing(set_len)
+    }
+
+    /// Writes `buf` to the file starting at the given offset within the
+    /// given sector, continuing on into the sectors that physically follow
+    /// it.  All of the sectors must already exist.
+    pub fn write_span(
+        &mut self,
+        sector_id: u32,
+        offset_within_sector: u64,
+        buf: &[u8],
+    ) -> io::Result<()> {
+        self.allocator.write_span(sector_id, offset_within_sector, buf)
+    }
+
+    /// Replaces the underlying file's contents with those of `other`, a
+    /// compacted copy of this file (see `CompactLayout`), using `install`
+    /// (see `Sectors::adopt_compacted`), and takes on its directory.  Since the copy keeps every entry's stream ID, entries keep
+    /// their generations; any entries the copy adds to pad out its last
+    /// directory sector start out fresh.
+    pub fn adopt_compacted<G, I>(
+        &mut self,
+        other: Directory<G>,
+        install: I,
+    ) -> io::Result<()>
+    where
+        I: FnOnce(&mut F, G) -> io::Result<()>,
+    {
+        debug_assert!(other.dir_entries.len() >= self.dir_entries.len());
+        self.allocator.adopt_compacted(other.allocator, install)?;
+        self.dir_entries = other.dir_entries;
+        self.bump_tree_stamp();
+        self.dir_start_sector = other.dir_start_sector;
+        self.free_dir_entries = other.free_dir_entries;
+        self.parents = other.parents;
+        self.generations.resize(self.dir_entries.len(), 0);
+        if let Some(touched) = self.touched_entries.as_mut() {
+            touched.clear();
+        }
+        Ok(())
+    }
+
+    pub fn wipe_free_sectors(&mut self) -> io::Result<u64> {
+        self.allocator.wipe_free_sectors()
+    }
+
+    pub fn wipe_chain_slack(
+        &mut self,
+        start_sector_id: u32,
+        len: u64,
+        chain: ChainName<'_>,
+    ) -> io::Result<u64> {
+        self.allocator.wipe_chain_slack(start_sector_id, len, chain)
+    }
+
+    /// Rewrites every directory entry from its in-memory copy, resetting
+    /// unallocated entries to all zeros and clearing any stale bytes after
+    /// entry names.
+    pub fn rewrite_dir_entries(&mut self) -> io::Result<()> {
+        for dir_entry in self.dir_entries.iter_mut() {
+            if dir_entry.obj_type == ObjType::Unallocated {
+                *dir_entry = DirEntry::unallocated();
+            }
+        }
+        let mut chain = self
+            .allocator
+            .open_chain(self.dir_start_sector, SectorInit::Dir)?;
+        for dir_entry in self.dir_entries.iter() {
+            dir_entry.write_to(&mut chain)?;
+        }
+        Ok(())
+    }
+
     /// Allocates a new chain with one sector, and returns the starting sector
     /// number.
     pub fn begin_chain(&mut self, init: SectorInit) -> io::Result<u32> {
@@ -256,6 +1056,19 @@ impl<F: Write + Seek> Directory<F> {
         self.allocator.free_chain(start_sector_id)
     }
 
+    /// Releases FAT sectors that only describe sectors beyond the end of the
+    /// file.
+    pub fn release_unused_fat_sectors(&mut self) -> io::Result<()> {
+        self.allocator.release_unused_fat_sectors()
+    }
+
+    /// Drops the free sectors at the end of the file, along with any FAT and
+    /// DIFAT sectors that only describe them, and returns the new number of
+    /// sectors.
+    pub fn release_free_tail(&mut self) -> io::Result<u32> {
+        self.allocator.release_free_tail()
+    }
+
     /// Inserts a new directory entry into the tree under the specified parent
     /// entry, then returns the new stream ID.
     pub fn insert_dir_entry(
@@ -263,10 +1076,28 @@ impl<F: Write + Seek> Directory<F> {
         parent_id: u32,
         name: &str,
         obj_type: ObjType,
+    ) -> io::Result<u32> {
+        self.insert_dir_entry_with(parent_id, name, obj_type, |_| {})
+    }
+
+    /// Like `insert_dir_entry`, but first calls the given function to fill
+    /// in the new entry's metadata, so that the entry is only ever written
+    /// with that metadata.
+    pub fn insert_dir_entry_with<G: FnOnce(&mut DirEntry)>(
+        &mut self,
+        parent_id: u32,
+        name: &str,
+        obj_type: ObjType,
+        init: G,
     ) -> io::Result<u32> {
         debug_assert!(
             obj_type == ObjType::Storage || obj_type == ObjType::Stream
         );
+        internal::path::validate_name(name)?;
+        // Find where in the tree the new entry belongs.  Callers check that
+        // the name isn't already taken (by walking the tree the same way).
+        let (path, ordering) = self.find_insert_position(parent_id, name)?;
+
         // Create a new directory entry.
         let stream_id = self.allocate_dir_entry()?;
         // 2.6.1 streams must have creation and modified time of 0
@@ -274,55 +1105,271 @@ impl<F: Write + Seek> Directory<F> {
         if obj_type == ObjType::Storage {
             ts = Timestamp::now();
         }
-        *self.dir_entry_mut(stream_id) = DirEntry::new(name, obj_type, ts);
+        let mut dir_entry = DirEntry::new(name, obj_type, ts);
+        init(&mut dir_entry);
+        // New entries start out red, as in any red-black tree insertion.
+        dir_entry.color = Color::Red;
+        *self.dir_entry_mut(stream_id) = dir_entry;
+        self.parents[stream_id as usize] = parent_id;
+        self.bump_generation(stream_id);
+        // Write the new entry to the underlying file before linking it into
+        // the tree, so that the tree never refers to an unwritten entry.
+        self.write_dir_entry(stream_id)?;
+        self.link_dir_entry(parent_id, path, ordering, stream_id)?;
+        Ok(stream_id)
+    }
 
-        // Insert the new entry into the tree.
+    /// Removes a directory entry from the tree and deallocates it.
+    pub fn remove_dir_entry(
+        &mut self,
+        parent_id: u32,
+        name: &str,
+    ) -> io::Result<()> {
+        let stream_id = self.unlink_dir_entry(parent_id, name)?;
+        debug_assert_eq!(self.dir_entry(stream_id).child, consts::NO_STREAM);
+        self.free_dir_entry(stream_id)?;
+        Ok(())
+    }
+
+    /// Moves a directory entry from one storage to another (which may be the
+    /// same one) and gives it a new name, relinking it into the new parent's
+    /// tree.  The entry keeps its stream ID, generation, and children, so
+    /// open stream handles for it (or for anything below it) stay valid.
+    /// Callers check that the new name isn't already taken by any other
+    /// entry, and that the new parent isn't the entry itself or below it.
+    pub fn rename_dir_entry(
+        &mut self,
+        parent_id: u32,
+        name: &str,
+        new_parent_id: u32,
+        new_name: &str,
+    ) -> io::Result<u32> {
+        internal::path::validate_name(new_name)?;
+        let stream_id = self.unlink_dir_entry(parent_id, name)?;
+        {
+            let dir_entry = self.dir_entry_mut(stream_id);
+            dir_entry.name = new_name.to_string();
+            dir_entry.left_sibling = consts::NO_STREAM;
+            dir_entry.right_sibling = consts::NO_STREAM;
+            dir_entry.color = Color::Red;
+        }
+        self.parents[stream_id as usize] = new_parent_id;
+        self.write_dir_entry(stream_id)?;
+        let (path, ordering) =
+            self.find_insert_position(new_parent_id, new_name)?;
+        self.link_dir_entry(new_parent_id, path, ordering, stream_id)?;
+        Ok(stream_id)
+    }
+
+    /// Makes the entry `to_stream_id` take over the object held by entry
+    /// `from_stream_id` (its type-specific contents, metadata, and
+    /// children), under the given name, which must compare equal to its
+    /// current one.  The new contents are written with a single directory
+    /// entry write, so that if this is interrupted, the entry holds either
+    /// its old object or the new one.  Afterwards, `from_stream_id` is a
+    /// childless copy of the object, still in the tree, which the caller
+    /// must remove (without freeing its data, if it's a stream); open
+    /// stream handles for the old object at `to_stream_id` are invalidated.
+    /// Returns the replaced directory entry, whose data the caller must
+    /// free.
+    pub fn replace_dir_entry(
+        &mut self,
+        from_stream_id: u32,
+        to_stream_id: u32,
+        name: &str,
+    ) -> io::Result<DirEntry> {
+        let from_entry = self.dir_entry(from_stream_id).clone();
+        let old_entry = self.dir_entry(to_stream_id).clone();
+        debug_assert_eq!(from_entry.obj_type, old_entry.obj_type);
+        debug_assert_eq!(old_entry.child, consts::NO_STREAM);
+        debug_assert_eq!(
+            internal::path::compare_names(name, &old_entry.name),
+            Ordering::Equal
+        );
+        self.with_dir_entry_mut(to_stream_id, |dir_entry| {
+            dir_entry.name = name.to_string();
+            dir_entry.child = from_entry.child;
+            dir_entry.clsid = from_entry.clsid;
+            dir_entry.state_bits = from_entry.state_bits;
+            dir_entry.creation_time = from_entry.creation_time;
+            dir_entry.modified_time = from_entry.modified_time;
+            dir_entry.start_sector = from_entry.start_sector;
+            dir_entry.stream_len = from_entry.stream_len;
+        })?;
+        self.bump_generation(to_stream_id);
+        if from_entry.child != consts::NO_STREAM {
+            self.set_child(from_stream_id, consts::NO_STREAM)?;
+            for stream_id in self.children_of(to_stream_id) {
+                self.parents[stream_id as usize] = to_stream_id;
+            }
+        }
+        Ok(old_entry)
+    }
+
+    /// Finds where an entry with the given name belongs in the given
+    /// storage's tree, returning the path from the root of the tree to the
+    /// entry to link it below (which is empty if the tree is empty) and which
+    /// side to link it on (or `Equal`, if the tree is empty).  Fails if the
+    /// name is already taken.
+    fn find_insert_position(
+        &self,
+        parent_id: u32,
+        name: &str,
+    ) -> io::Result<(Vec<u32>, Ordering)> {
         let mut sibling_id = self.dir_entry(parent_id).child;
-        let mut prev_sibling_id = parent_id;
+        let mut path = Vec::new();
         let mut ordering = Ordering::Equal;
         while sibling_id != consts::NO_STREAM {
             let sibling = self.dir_entry(sibling_id);
-            prev_sibling_id = sibling_id;
+            path.push(sibling_id);
             ordering = internal::path::compare_names(name, &sibling.name);
+            debug_assert_ne!(ordering, Ordering::Equal, "insert duplicate");
             sibling_id = match ordering {
                 Ordering::Less => sibling.left_sibling,
                 Ordering::Greater => sibling.right_sibling,
-                Ordering::Equal => panic!("internal error: insert duplicate"),
+                Ordering::Equal => already_exists!(
+                    "Storage already has an entry named {:?}",
+                    sibling.name
+                ),
             };
         }
-        match ordering {
-            Ordering::Less => {
-                self.dir_entry_mut(prev_sibling_id).left_sibling = stream_id;
-                let mut sector =
-                    self.seek_within_dir_entry(prev_sibling_id, 68)?;
-                sector.write_le_u32(stream_id)?;
+        Ok((path, ordering))
+    }
+
+    /// Links an (already written, red) entry into its parent's tree, at the
+    /// position returned by `find_insert_position`, then restores the
+    /// red-black properties along the given path, so that lookups in the
+    /// tree stay logarithmic.
+    fn link_dir_entry(
+        &mut self,
+        parent_id: u32,
+        path: Vec<u32>,
+        ordering: Ordering,
+        stream_id: u32,
+    ) -> io::Result<()> {
+        debug_assert_eq!(self.dir_entry(stream_id).color, Color::Red);
+        match (path.last(), ordering) {
+            (Some(&sibling_id), Ordering::Less) => {
+                self.set_left_sibling(sibling_id, stream_id)?;
             }
-            Ordering::Greater => {
-                self.dir_entry_mut(prev_sibling_id).right_sibling = stream_id;
-                let mut sector =
-                    self.seek_within_dir_entry(prev_sibling_id, 72)?;
-                sector.write_le_u32(stream_id)?;
+            (Some(&sibling_id), Ordering::Greater) => {
+                self.set_right_sibling(sibling_id, stream_id)?;
             }
-            Ordering::Equal => {
-                debug_assert_eq!(prev_sibling_id, parent_id);
-                self.dir_entry_mut(parent_id).child = stream_id;
-                let mut sector = self.seek_within_dir_entry(parent_id, 76)?;
-                sector.write_le_u32(stream_id)?;
+            _ => {
+                debug_assert!(path.is_empty());
+                self.set_child(parent_id, stream_id)?;
             }
         }
-        // TODO: rebalance tree
+        self.rebalance_after_insert(parent_id, path, stream_id)
+    }
 
-        // Write new entry to underyling file.
-        self.write_dir_entry(stream_id)?;
-        Ok(stream_id)
+    /// Restores the red-black properties of the given storage's tree after
+    /// linking the red entry `stream_id` into it, below the given path from
+    /// the root of the tree.  This is the usual insertion fix-up: it
+    /// recolors entries up the path while the new entry's uncle is red,
+    /// then does at most two rotations, so it changes only a handful of
+    /// entries (amortized).  If the tree didn't have adjacent red entries
+    /// before, it doesn't afterwards either.
+    fn rebalance_after_insert(
+        &mut self,
+        parent_id: u32,
+        mut path: Vec<u32>,
+        mut stream_id: u32,
+    ) -> io::Result<()> {
+        loop {
+            let Some(&node_parent) = path.last() else {
+                // The entry is the root of the tree, which is always black.
+                return self.set_color(stream_id, Color::Black);
+            };
+            if self.dir_entry(node_parent).color == Color::Black {
+                return Ok(());
+            }
+            if path.len() < 2 {
+                // The entry's parent is a red root; just make it black.
+                return self.set_color(node_parent, Color::Black);
+            }
+            let grandparent = path[path.len() - 2];
+            let parent_is_left =
+                self.dir_entry(grandparent).left_sibling == node_parent;
+            let uncle = if parent_is_left {
+                self.dir_entry(grandparent).right_sibling
+            } else {
+                self.dir_entry(grandparent).left_sibling
+            };
+            if uncle != consts::NO_STREAM
+                && self.dir_entry(uncle).color == Color::Red
+            {
+                self.set_color(node_parent, Color::Black)?;
+                self.set_color(uncle, Color::Black)?;
+                self.set_color(grandparent, Color::Red)?;
+                path.truncate(path.len() - 2);
+                stream_id = grandparent;
+                continue;
+            }
+            let node_is_left =
+                self.dir_entry(node_parent).left_sibling == stream_id;
+            let mut top = node_parent;
+            if node_is_left != parent_is_left {
+                // The entry is an inner grandchild; rotate it up over its
+                // parent first, so that it becomes an outer one.
+                self.rotate_up(
+                    parent_id,
+                    Some(grandparent),
+                    node_parent,
+                    stream_id,
+                )?;
+                top = stream_id;
+            }
+            let great_grandparent = path.len().checked_sub(3).map(|i| path[i]);
+            self.rotate_up(parent_id, great_grandparent, grandparent, top)?;
+            self.set_color(top, Color::Black)?;
+            self.set_color(grandparent, Color::Red)?;
+            return Ok(());
+        }
     }
 
-    /// Removes a directory entry from the tree and deallocates it.
-    pub fn remove_dir_entry(
+    /// Rotates the given entry up over `node_parent`, the entry above it in
+    /// the given storage's tree, where `grandparent` is the entry above that
+    /// (or `None` if `node_parent` is the root of the tree).
+    fn rotate_up(
         &mut self,
         parent_id: u32,
-        name: &str,
+        grandparent: Option<u32>,
+        node_parent: u32,
+        stream_id: u32,
     ) -> io::Result<()> {
+        if self.dir_entry(node_parent).left_sibling == stream_id {
+            let inner = self.dir_entry(stream_id).right_sibling;
+            self.set_left_sibling(node_parent, inner)?;
+            self.set_right_sibling(stream_id, node_parent)?;
+        } else {
+            debug_assert_eq!(
+                self.dir_entry(node_parent).right_sibling,
+                stream_id
+            );
+            let inner = self.dir_entry(stream_id).left_sibling;
+            self.set_right_sibling(node_parent, inner)?;
+            self.set_left_sibling(stream_id, node_parent)?;
+        }
+        match grandparent {
+            Some(grandparent) => {
+                if self.dir_entry(grandparent).left_sibling == node_parent {
+                    self.set_left_sibling(grandparent, stream_id)
+                } else {
+                    self.set_right_sibling(grandparent, stream_id)
+                }
+            }
+            None => self.set_child(parent_id, stream_id),
+        }
+    }
+
+    /// Unlinks the entry with the given name from its parent's tree, without
+    /// deallocating it, and returns its stream ID.
+    fn unlink_dir_entry(
+        &mut self,
+        parent_id: u32,
+        name: &str,
+    ) -> io::Result<u32> {
         // Find the directory entry with the given name below the parent.
         let mut stream_ids = Vec::new();
         let mut stream_id = self.dir_entry(parent_id).child;
@@ -337,124 +1384,185 @@ impl<F: Write + Seek> Directory<F> {
                 Ordering::Greater => stream_id = dir_entry.right_sibling,
             }
         }
-        debug_assert_eq!(self.dir_entry(stream_id).child, consts::NO_STREAM);
 
-        // Restructure the tree.
-        let mut replacement_id = consts::NO_STREAM;
-        loop {
-            let left_sibling = self.dir_entry(stream_id).left_sibling;
-            let right_sibling = self.dir_entry(stream_id).right_sibling;
-            if left_sibling == consts::NO_STREAM
-                && right_sibling == consts::NO_STREAM
-            {
-                break;
-            } else if left_sibling == consts::NO_STREAM {
-                replacement_id = right_sibling;
-                break;
-            } else if right_sibling == consts::NO_STREAM {
-                replacement_id = left_sibling;
-                break;
-            }
+        // Restructure the tree.  If the entry has two children, its in-order
+        // predecessor takes its place.  This relinks entries rather than
+        // moving them between slots, so that every other object keeps its
+        // stream ID (which open stream handles refer to).
+        let left_sibling = self.dir_entry(stream_id).left_sibling;
+        let right_sibling = self.dir_entry(stream_id).right_sibling;
+        let replacement_id = if left_sibling == consts::NO_STREAM {
+            right_sibling
+        } else if right_sibling == consts::NO_STREAM {
+            left_sibling
+        } else {
+            let mut pred_parent_id = stream_id;
             let mut predecessor_id = left_sibling;
             loop {
-                stream_ids.push(predecessor_id);
                 let next_id = self.dir_entry(predecessor_id).right_sibling;
                 if next_id == consts::NO_STREAM {
                     break;
                 }
+                pred_parent_id = predecessor_id;
                 predecessor_id = next_id;
             }
-            let mut pred_entry = self.dir_entry(predecessor_id).clone();
-            debug_assert_eq!(pred_entry.right_sibling, consts::NO_STREAM);
-            pred_entry.left_sibling = left_sibling;
-            pred_entry.right_sibling = right_sibling;
-            pred_entry.write_to(&mut self.seek_to_dir_entry(stream_id)?)?;
-            *self.dir_entry_mut(stream_id) = pred_entry;
-            stream_id = predecessor_id;
+            // The predecessor's left child either moves up to take its
+            // place, or stays below it as it changes color, so either way
+            // it's made black (see below).
+            let pred_left = self.dir_entry(predecessor_id).left_sibling;
+            if pred_left != consts::NO_STREAM {
+                self.set_color(pred_left, Color::Black)?;
+            }
+            if pred_parent_id != stream_id {
+                self.set_right_sibling(pred_parent_id, pred_left)?;
+                self.set_left_sibling(predecessor_id, left_sibling)?;
+            }
+            self.set_right_sibling(predecessor_id, right_sibling)?;
+            let color = self.dir_entry(stream_id).color;
+            self.set_color(predecessor_id, color)?;
+            predecessor_id
+        };
+        // An entry that moves up to take the place of its parent is made
+        // black, so that it can't end up below another red entry.  (This
+        // keeps the tree free of adjacent red entries, but can leave it less
+        // balanced than a full red-black deletion would.)
+        if replacement_id != consts::NO_STREAM
+            && (left_sibling == consts::NO_STREAM
+                || right_sibling == consts::NO_STREAM)
+        {
+            self.set_color(replacement_id, Color::Black)?;
         }
-        // TODO: recolor nodes
 
         // Remove the entry.
         debug_assert_eq!(stream_ids.last(), Some(&stream_id));
         stream_ids.pop();
         if let Some(&sibling_id) = stream_ids.last() {
             if self.dir_entry(sibling_id).left_sibling == stream_id {
-                self.dir_entry_mut(sibling_id).left_sibling = replacement_id;
-                let mut sector = self.seek_within_dir_entry(sibling_id, 68)?;
-                sector.write_le_u32(replacement_id)?;
+                self.set_left_sibling(sibling_id, replacement_id)?;
             } else {
                 debug_assert_eq!(
                     self.dir_entry(sibling_id).right_sibling,
                     stream_id
                 );
-                self.dir_entry_mut(sibling_id).right_sibling = replacement_id;
-                let mut sector = self.seek_within_dir_entry(sibling_id, 72)?;
-                sector.write_le_u32(replacement_id)?;
+                self.set_right_sibling(sibling_id, replacement_id)?;
             }
         } else {
-            self.dir_entry_mut(parent_id).child = replacement_id;
-            let mut sector = self.seek_within_dir_entry(parent_id, 76)?;
-            sector.write_le_u32(replacement_id)?;
+            self.set_child(parent_id, replacement_id)?;
         }
-        self.free_dir_entry(stream_id)?;
-        Ok(())
+        Ok(stream_id)
     }
 
     /// Adds a new (uninitialized) entry to the directory and returns the new
     /// stream ID.
     fn allocate_dir_entry(&mut self) -> io::Result<u32> {
-        // If there's an existing unalloated directory entry, use that.
-        for (stream_id, entry) in self.dir_entries.iter().enumerate() {
-            if entry.obj_type == ObjType::Unallocated {
-                return Ok(stream_id as u32);
-            }
+        // If there's an existing unallocated directory entry, use that.
+        if let Some(stream_id) = self.free_dir_entries.pop_first() {
+            debug_assert_eq!(
+                self.dir_entry(stream_id).obj_type,
+                ObjType::Unallocated
+            );
+            return Ok(stream_id);
         }
         // Otherwise, we need a new entry; if there's not room in the directory
         // chain to add it, then first we need to add a new directory sector.
         let dir_entries_per_sector = self.version().dir_entries_per_sector();
         let unallocated_dir_entry = DirEntry::unallocated();
         if self.dir_entries.len() % dir_entries_per_sector == 0 {
-            let start_sector = self.dir_start_sector;
-            self.allocator.extend_chain(start_sector, SectorInit::Dir)?;
+            // Extend the chain from its last sector, rather than following
+            // it from the start.
+            let last_sector =
+                self.dir_sector_ids()?.last().copied().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Malformed directory (directory chain is empty)",
+                    )
+                })?;
+            self.allocator.extend_chain(last_sector, SectorInit::Dir)?;
             self.update_num_dir_sectors()?;
         }
         // Add a new entry to the end of the directory and return it.
         let stream_id = self.dir_entries.len() as u32;
         self.dir_entries.push(unallocated_dir_entry);
+        self.parents.push(consts::NO_STREAM);
+        self.generations.push(0);
+        if let Some(touched) = self.touched_entries.as_mut() {
+            touched.insert(stream_id);
+        }
         Ok(stream_id)
     }
 
-    /// Increase header num_dir_sectors if version V4
+    /// Increase header num_dir_sectors if the version uses it
     /// note: not updating this value breaks ole32 compatibility
     fn update_num_dir_sectors(&mut self) -> io::Result<()> {
-        let start_sector = self.dir_start_sector;
-        if self.version() == Version::V4 {
-            let num_dir_sectors =
-                self.count_directory_sectors(start_sector)?;
+        if self.version().requires_dir_sector_count() {
+            let num_dir_sectors = self.dir_sector_ids()?.len() as u32;
             self.seek_within_header(40)?.write_le_u32(num_dir_sectors)?;
         }
         Ok(())
     }
 
-    fn count_directory_sectors(
-        &mut self,
-        start_sector: u32,
-    ) -> io::Result<u32> {
-        let mut num_dir_sectors = 1;
-        let mut next_sector = self.allocator.next(start_sector)?;
-        while next_sector != consts::END_OF_CHAIN {
-            num_dir_sectors += 1;
-            next_sector = self.allocator.next(next_sector)?;
+    /// Rewrites the header's version, sector shift, and number of directory
+    /// sectors fields to agree with this file's version (for a file whose
+    /// header gave a version that didn't match its sector shift).
+    pub fn rewrite_header_version(&mut self) -> io::Result<()> {
+        let version = self.version();
+        self.seek_within_header(26)?.write_le_u16(version.number())?;
+        self.seek_within_header(30)?.write_le_u16(version.sector_shift())?;
+        if version.requires_dir_sector_count() {
+            self.update_num_dir_sectors()
+        } else {
+            self.seek_within_header(40)?.write_le_u32(0)
         }
-        Ok(num_dir_sectors)
+    }
+
+    /// Frees any directory sectors at the end of the directory chain that
+    /// contain only unallocated entries (always keeping at least one sector),
+    /// and returns the number of sectors freed.
+    pub fn release_unused_dir_sectors(&mut self) -> io::Result<u32> {
+        let dir_entries_per_sector = self.version().dir_entries_per_sector();
+        let num_used_entries = self
+            .dir_entries
+            .iter()
+            .rposition(|entry| entry.obj_type != ObjType::Unallocated)
+            .map_or(1, |index| index + 1);
+        let num_sectors =
+            num_used_entries.div_ceil(dir_entries_per_sector).max(1);
+        let num_entries = num_sectors * dir_entries_per_sector;
+        if self.dir_entries.len() <= num_entries {
+            return Ok(0);
+        }
+        let num_released = (self.dir_entries.len() - num_entries)
+            .div_ceil(dir_entries_per_sector)
+            as u32;
+        let start_sector = self.dir_start_sector;
+        let sector_len = self.sector_len() as u64;
+        self.allocator
+            .open_chain(start_sector, SectorInit::Dir)?
+            .set_len(num_sectors as u64 * sector_len)?;
+        self.dir_entries.truncate(num_entries);
+        self.bump_tree_stamp();
+        self.parents.truncate(num_entries);
+        self.generations.truncate(num_entries);
+        self.free_dir_entries.split_off(&(num_entries as u32));
+        self.update_num_dir_sectors()?;
+        Ok(num_released)
     }
 
     /// Deallocates the specified directory entry.
     fn free_dir_entry(&mut self, stream_id: u32) -> io::Result<()> {
         debug_assert_ne!(stream_id, consts::ROOT_STREAM_ID);
-        let dir_entry = DirEntry::unallocated();
+        // Zeroing freed space takes precedence over preserving the entry.
+        let policy = if self.zero_freed_sectors() {
+            FreeEntryPolicy::Scrub
+        } else {
+            self.free_entry_policy
+        };
+        let dir_entry = policy.free(self.dir_entry(stream_id));
         dir_entry.write_to(&mut self.seek_to_dir_entry(stream_id)?)?;
         *self.dir_entry_mut(stream_id) = dir_entry;
+        self.parents[stream_id as usize] = consts::NO_STREAM;
+        self.bump_generation(stream_id);
+        self.free_dir_entries.insert(stream_id);
         // TODO: Truncate directory chain if last directory sector is now all
         //       unallocated.
         //       In that case, also call update_num_dir_sectors()
@@ -463,17 +1571,71 @@ impl<F: Write + Seek> Directory<F> {
 
     /// Calls the given function with a mutable reference to the specified
     /// directory entry, then writes the updated directory entry to the
-    /// underlying file once the function returns.
-    pub fn with_dir_entry_mut<W>(
+    /// underlying file once the function returns, and returns what the
+    /// function returned.
+    pub fn with_dir_entry_mut<W, T>(
         &mut self,
         stream_id: u32,
         func: W,
+    ) -> io::Result<T>
+    where
+        W: FnOnce(&mut DirEntry) -> T,
+    {
+        let old_entry = self.dir_entry(stream_id).clone();
+        let result = func(self.touch_dir_entry(stream_id));
+        if shape_differs(&old_entry, self.dir_entry(stream_id)) {
+            self.bump_tree_stamp();
+        }
+        self.write_dir_entry(stream_id)?;
+        Ok(result)
+    }
+
+    /// Calls the given function with a mutable reference to each of the
+    /// specified directory entries, then writes the updated entries to the
+    /// underlying file.  Entries are written in order, with a single write
+    /// for each run of adjacent entries, so that updating every entry in a
+    /// directory sector writes that sector once.
+    pub fn with_dir_entries_mut<W>(
+        &mut self,
+        stream_ids: &[u32],
+        mut func: W,
     ) -> io::Result<()>
     where
-        W: FnOnce(&mut DirEntry),
+        W: FnMut(&mut DirEntry),
     {
-        func(&mut self.dir_entries[stream_id as usize]);
-        self.write_dir_entry(stream_id)
+        let mut stream_ids = stream_ids.to_vec();
+        stream_ids.sort_unstable();
+        stream_ids.dedup();
+        for &stream_id in stream_ids.iter() {
+            let old_entry = self.dir_entry(stream_id).clone();
+            func(self.touch_dir_entry(stream_id));
+            if shape_differs(&old_entry, self.dir_entry(stream_id)) {
+                self.bump_tree_stamp();
+            }
+        }
+        let mut chain = self
+            .allocator
+            .open_chain(self.dir_start_sector, SectorInit::Dir)?;
+        let mut buffer = Vec::new();
+        let mut start = 0;
+        while start < stream_ids.len() {
+            let mut end = start + 1;
+            while end < stream_ids.len()
+                && stream_ids[end] == stream_ids[end - 1] + 1
+            {
+                end += 1;
+            }
+            buffer.clear();
+            for &stream_id in &stream_ids[start..end] {
+                self.dir_entries[stream_id as usize].write_to(&mut buffer)?;
+            }
+            let offset =
+                (consts::DIR_ENTRY_LEN as u64) * (stream_ids[start] as u64);
+            chain.seek(SeekFrom::Start(offset))?;
+            chain.write_all(&buffer)?;
+            start = end;
+        }
+        Ok(())
     }
 
     /// Calls the given function with a mutable reference to the root directory
@@ -486,13 +1648,49 @@ impl<F: Write + Seek> Directory<F> {
         self.with_dir_entry_mut(consts::ROOT_STREAM_ID, func)
     }
 
+    /// Sets the left sibling of the given directory entry, both in memory
+    /// and in the underlying file.
+    fn set_left_sibling(
+        &mut self,
+        stream_id: u32,
+        left: u32,
+    ) -> io::Result<()> {
+        self.dir_entry_mut(stream_id).left_sibling = left;
+        self.seek_within_dir_entry(stream_id, 68)?.write_le_u32(left)
+    }
+
+    /// Sets the right sibling of the given directory entry, both in memory
+    /// and in the underlying file.
+    fn set_right_sibling(
+        &mut self,
+        stream_id: u32,
+        right: u32,
+    ) -> io::Result<()> {
+        self.dir_entry_mut(stream_id).right_sibling = right;
+        self.seek_within_dir_entry(stream_id, 72)?.write_le_u32(right)
+    }
+
+    /// Sets the color of the given directory entry, both in memory and in the
+    /// underlying file, unless it already has that color.
+    fn set_color(&mut self, stream_id: u32, color: Color) -> io::Result<()> {
+        if self.dir_entry(stream_id).color == color {
+            return Ok(());
+        }
+        self.dir_entry_mut(stream_id).color = color;
+        self.seek_within_dir_entry(stream_id, 67)?
+            .write_all(&[color.as_byte()])
+    }
+
+    /// Sets the child of the given storage entry, both in memory and in the
+    /// underlying file.
+    fn set_child(&mut self, stream_id: u32, child: u32) -> io::Result<()> {
+        self.dir_entry_mut(stream_id).child = child;
+        self.seek_within_dir_entry(stream_id, 76)?.write_le_u32(child)
+    }
+
     fn write_dir_entry(&mut self, stream_id: u32) -> io::Result<()> {
-        let mut chain = self
-            .allocator
-            .open_chain(self.dir_start_sector, SectorInit::Dir)?;
-        let offset = (consts::DIR_ENTRY_LEN as u64) * (stream_id as u64);
-        chain.seek(SeekFrom::Start(offset))?;
-        self.dir_entries[stream_id as usize].write_to(&mut chain)
+        let dir_entry = self.dir_entries[stream_id as usize].clone();
+        dir_entry.write_to(&mut self.seek_to_dir_entry(stream_id)?)
     }
 
     /// Flushes all changes to the underlying file.
@@ -501,6 +1699,12 @@ impl<F: Write + Seek> Directory<F> {
     }
 }
 
+impl<F: Read + Write + Seek> Directory<F> {
+    pub fn reset_unused_header_fields(&mut self) -> io::Result<bool> {
+        self.allocator.reset_unused_header_fields()
+    }
+}
+
 //===========================================================================//
 
 #[cfg(test)]
@@ -523,9 +1727,17 @@ mod tests {
         let sectors = Sectors::new(version, data_len as u64, cursor);
         let mut fat = vec![consts::END_OF_CHAIN; num_sectors];
         fat[0] = consts::FAT_SECTOR;
-        let allocator =
-            Allocator::new(sectors, vec![], vec![0], fat, validation).unwrap();
-        Directory::new(allocator, entries, 1, validation).unwrap()
+        let allocator = Allocator::new(
+            sectors,
+            vec![],
+            vec![0],
+            fat,
+            validation,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        Directory::new(allocator, entries, 1, validation, &mut Vec::new())
+            .unwrap()
     }
 
     #[test]
@@ -534,18 +1746,6 @@ mod tests {
         make_directory(vec![], Validation::Permissive);
     }
 
-    #[test]
-    #[should_panic(
-        expected = "Malformed directory (root stream len is 147, but should \
-                    be multiple of 64)"
-    )]
-    fn invalid_mini_stream_len() {
-        let mut root_entry = DirEntry::empty_root_entry();
-        root_entry.start_sector = 2;
-        root_entry.stream_len = 147;
-        make_directory(vec![root_entry], Validation::Permissive);
-    }
-
     #[test]
     #[should_panic(expected = "Malformed directory (loop in tree)")]
     fn storage_is_child_of_itself() {
@@ -621,6 +1821,71 @@ mod tests {
             Validation::Permissive,
         );
     }
+
+    /// Returns the height of the tree below the given entry, checking along
+    /// the way that it has no adjacent red entries, and appending the names
+    /// in it to `names` in order.
+    fn check_tree<F>(
+        directory: &Directory<F>,
+        stream_id: u32,
+        parent_is_red: bool,
+        names: &mut Vec<String>,
+    ) -> usize {
+        if stream_id == consts::NO_STREAM {
+            return 0;
+        }
+        let dir_entry = directory.dir_entry(stream_id);
+        let is_red = dir_entry.color == Color::Red;
+        assert!(
+            !(parent_is_red && is_red),
+            "{:?} has a red parent",
+            dir_entry.name
+        );
+        let left =
+            check_tree(directory, dir_entry.left_sibling, is_red, names);
+        names.push(dir_entry.name.clone());
+        let right =
+            check_tree(directory, dir_entry.right_sibling, is_red, names);
+        1 + left.max(right)
+    }
+
+    #[test]
+    fn inserts_keep_tree_balanced() {
+        let mut directory = make_directory(
+            vec![DirEntry::empty_root_entry()],
+            Validation::Strict,
+        );
+        let root_id = consts::ROOT_STREAM_ID;
+        // Inserting in sorted order would make an unbalanced tree into a
+        // list.
+        let mut expected: Vec<String> =
+            (0..1000).map(|index| format!("s{:04}", index)).collect();
+        for name in expected.iter() {
+            directory
+                .insert_dir_entry(root_id, name, ObjType::Stream)
+                .unwrap();
+        }
+        // Remove some entries, and insert others in reverse order.
+        for index in (0..1000).step_by(3) {
+            let name = format!("s{:04}", index);
+            directory.remove_dir_entry(root_id, &name).unwrap();
+        }
+        expected.retain(|name| name[1..].parse::<usize>().unwrap() % 3 != 0);
+        for index in (0..300).rev() {
+            let name = format!("t{:04}", index);
+            directory
+                .insert_dir_entry(root_id, &name, ObjType::Stream)
+                .unwrap();
+            expected.push(name);
+        }
+        let mut names = Vec::new();
+        let child = directory.root_dir_entry().child;
+        let height = check_tree(&directory, child, false, &mut names);
+        expected.sort();
+        assert_eq!(names, expected);
+        // A red-black tree of n entries is at most 2 * log2(n + 1) high.
+        assert!(height <= 21, "height {}", height);
+    }
 }
 
 //===========================================================================//
@@ -1,5 +1,8 @@
 use crate::internal::consts::{self, MAX_REGULAR_STREAM_ID, NO_STREAM};
-use crate::internal::{self, Color, ObjType, Timestamp, Validation, Version};
+use crate::internal::{
+    self, Color, ObjType, Timestamp, Validation, ValidationIssue,
+    ValidationIssueKind, Version,
+};
 use crate::{ReadLeNumber, WriteLeNumber};
 use std::io::{self, Read, Write};
 use uuid::Uuid;
@@ -107,6 +110,7 @@ impl DirEntry {
         reader: &mut R,
         version: Version,
         validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
     ) -> io::Result<DirEntry> {
         let mut name: String = {
             let mut name_chars: Vec<u16> = Vec::with_capacity(32);
@@ -132,8 +136,14 @@ impl DirEntry {
             // Look, CFB is a weird format.)  Anyway, some CFB files in the
             // wild don't do this, so under Permissive validation we don't
             // enforce it.
-            if validation.is_strict() && name_chars[name_len_chars] != 0 {
-                malformed!("name not null-terminated");
+            if name_chars[name_len_chars] != 0 {
+                if validation.is_strict() {
+                    malformed!("name not null-terminated");
+                }
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::NameNotTerminated,
+                    "Directory entry name not null-terminated".to_string(),
+                ));
             }
             match String::from_utf16(&name_chars[0..name_len_chars]) {
                 Ok(name) => name,
@@ -166,6 +176,14 @@ impl DirEntry {
                         consts::ROOT_DIR_NAME
                     );
                 }
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::RootEntryName,
+                    format!(
+                        "Root entry name is {:?}, but should be {:?}",
+                        name,
+                        consts::ROOT_DIR_NAME
+                    ),
+                ));
                 name = consts::ROOT_DIR_NAME.to_string();
             }
         } else {
@@ -200,16 +218,17 @@ impl DirEntry {
         }
 
         // Section 2.6.1 of the MS-CFB spec states that "In a stream object,
-        // this [CLSID] field MUST be set to all zeroes."  However, some CFB
-        // files in the wild violate this, so under Permissive validation we
-        // don't enforce it; instead, for non-storage objects we just ignore
-        // the CLSID data entirely and treat it as though it were nil.
-        let mut clsid = DirEntry::read_clsid(reader)?;
+        // this [CLSID] field MUST be set to all zeroes."  However, files
+        // written by some old exporters have garbage there, and are otherwise
+        // perfectly usable, so even under Strict validation we only report
+        // it.  The bytes are kept as they are, so that they survive being
+        // written back, but `Entry::clsid` reports nil for every stream.
+        let clsid = DirEntry::read_clsid(reader)?;
         if obj_type == ObjType::Stream && !clsid.is_nil() {
-            if validation.is_strict() {
-                malformed!("non-null stream CLSID: {:?}", clsid);
-            }
-            clsid = Uuid::nil();
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::StreamClsid,
+                format!("Stream {:?} has non-null CLSID {}", name, clsid),
+            ));
         }
 
         let state_bits = reader.read_le_u32()?;
@@ -226,8 +245,34 @@ impl DirEntry {
                     creation_time.value()
                 );
             }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::StreamTimestamp,
+                format!(
+                    "Stream {:?} has non-zero creation time {}",
+                    name,
+                    creation_time.value()
+                ),
+            ));
             creation_time = Timestamp::zero();
         }
+        // The same section also says that "for a root storage object, [the
+        // creation time] MUST be all zeroes."  Under Permissive validation,
+        // we don't enforce this, and just report whatever time is there.
+        if obj_type == ObjType::Root && creation_time != Timestamp::zero() {
+            if validation.is_strict() {
+                malformed!(
+                    "non-zero root creation time: {}",
+                    creation_time.value()
+                );
+            }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::RootCreationTime,
+                format!(
+                    "Root entry has non-zero creation time {}",
+                    creation_time.value()
+                ),
+            ));
+        }
         let mut modified_time = Timestamp::read_from(reader)?;
         if obj_type == ObjType::Stream && modified_time != Timestamp::zero() {
             if validation.is_strict() {
@@ -236,6 +281,14 @@ impl DirEntry {
                     modified_time.value()
                 );
             }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::StreamTimestamp,
+                format!(
+                    "Stream {:?} has non-zero modified time {}",
+                    name,
+                    modified_time.value()
+                ),
+            ));
             modified_time = Timestamp::zero();
         }
 
@@ -252,10 +305,20 @@ impl DirEntry {
             if validation.is_strict() && start_sector != 0 {
                 malformed!("non-zero storage start sector: {}", start_sector);
             }
-            start_sector = 0;
             if validation.is_strict() && stream_len != 0 {
                 malformed!("non-zero storage stream length: {}", stream_len);
             }
+            if start_sector != 0 || stream_len != 0 {
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::StorageStreamFields,
+                    format!(
+                        "Storage {:?} has start sector {} and stream length \
+                         {}, but both should be zero",
+                        name, start_sector, stream_len
+                    ),
+                ));
+            }
+            start_sector = 0;
             stream_len = 0;
         }
 
@@ -276,6 +339,16 @@ impl DirEntry {
     }
 
     pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // Assemble the whole entry first and write it all at once, so that a
+        // failed write never leaves an entry half-updated (e.g. with a new
+        // start sector but the old stream length).
+        let mut buffer = Vec::with_capacity(consts::DIR_ENTRY_LEN);
+        self.write_fields_to(&mut buffer)?;
+        debug_assert_eq!(buffer.len(), consts::DIR_ENTRY_LEN);
+        writer.write_all(&buffer)
+    }
+
+    fn write_fields_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         debug_assert!(internal::path::validate_name(&self.name).is_ok());
         let name_utf16: Vec<u16> = self.name.encode_utf16().collect();
         debug_assert!(name_utf16.len() < 32);
@@ -285,7 +358,15 @@ impl DirEntry {
         for _ in name_utf16.len()..32 {
             writer.write_le_u16(0)?;
         }
-        writer.write_le_u16((name_utf16.len() as u16 + 1) * 2)?;
+        // Unallocated entries must be all zeros (MS-CFB 2.6.3), so their
+        // (empty) name has no terminator to count.  Only an entry freed under
+        // `FreeEntryPolicy::Preserve` keeps its name.
+        let name_len_bytes = if self.name.is_empty() {
+            0
+        } else {
+            (name_utf16.len() as u16 + 1) * 2
+        };
+        writer.write_le_u16(name_len_bytes)?;
         writer.write_all(&[self.obj_type.as_byte()])?;
         writer.write_all(&[self.color.as_byte()])?;
         writer.write_le_u32(self.left_sibling)?;
@@ -307,7 +388,8 @@ impl DirEntry {
 mod tests {
     use super::DirEntry;
     use crate::internal::{
-        consts, Color, ObjType, Timestamp, Validation, Version,
+        consts, Color, ObjType, Timestamp, Validation, ValidationIssueKind,
+        Version,
     };
     use std::time::UNIX_EPOCH;
     use uuid::Uuid;
@@ -337,6 +419,7 @@ mod tests {
             &mut (&input as &[u8]),
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(&dir_entry.name, "Foobar");
@@ -381,6 +464,7 @@ mod tests {
             &mut (&input as &[u8]),
             Version::V4,
             Validation::Strict,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(&dir_entry.name, "Foobar");
@@ -429,6 +513,7 @@ mod tests {
             &mut (&input as &[u8]),
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
     }
@@ -458,6 +543,7 @@ mod tests {
             &mut (&input as &[u8]),
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
     }
@@ -488,8 +574,13 @@ mod tests {
     )]
     fn non_zero_creation_time_on_stream_strict() {
         let mut input: &[u8] = &NON_ZERO_CREATION_TIME_ON_STREAM;
-        DirEntry::read_from(&mut input, Version::V4, Validation::Strict)
-            .unwrap();
+        DirEntry::read_from(
+            &mut input,
+            Version::V4,
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -499,6 +590,7 @@ mod tests {
             &mut input,
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(dir_entry.obj_type, ObjType::Stream);
@@ -531,8 +623,13 @@ mod tests {
     )]
     fn non_zero_modified_time_on_stream_strict() {
         let mut input: &[u8] = &NON_ZERO_MODIFIED_TIME_ON_STREAM;
-        DirEntry::read_from(&mut input, Version::V4, Validation::Strict)
-            .unwrap();
+        DirEntry::read_from(
+            &mut input,
+            Version::V4,
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -542,6 +639,7 @@ mod tests {
             &mut input,
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(dir_entry.obj_type, ObjType::Stream);
@@ -568,14 +666,19 @@ mod tests {
     ];
 
     #[test]
-    #[should_panic(
-        expected = "Malformed directory entry (non-null stream CLSID: \
-                    04030201-0605-0807-0908-070605040302)"
-    )]
     fn non_null_clsid_on_stream_strict() {
         let mut input: &[u8] = &NON_NULL_CLSID_ON_STREAM;
-        DirEntry::read_from(&mut input, Version::V4, Validation::Strict)
-            .unwrap();
+        let mut issues = Vec::new();
+        let dir_entry = DirEntry::read_from(
+            &mut input,
+            Version::V4,
+            Validation::Strict,
+            &mut issues,
+        )
+        .unwrap();
+        assert_eq!(dir_entry.obj_type, ObjType::Stream);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind(), ValidationIssueKind::StreamClsid);
     }
 
     // Regression test for https://github.com/mdsteele/rust-cfb/issues/26
@@ -585,16 +688,23 @@ mod tests {
         // Section 2.6.1 of the MS-CFB spec states that "In a stream object,
         // this [CLSID] field MUST be set to all zeroes."  However, some CFB
         // files in the wild violate this.  So we allow parsing a stream dir
-        // entry with a non-nil CLSID under Permissive validation, but we
-        // ignore that CLSID and just set it to all zeroes.
+        // entry with a non-nil CLSID, and keep the CLSID exactly as it was,
+        // so that writing the entry back doesn't change it.
         let dir_entry = DirEntry::read_from(
             &mut input,
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(dir_entry.obj_type, ObjType::Stream);
-        assert!(dir_entry.clsid.is_nil());
+        assert_eq!(
+            dir_entry.clsid,
+            Uuid::parse_str("04030201-0605-0807-0908-070605040302").unwrap()
+        );
+        let mut output = Vec::new();
+        dir_entry.write_to(&mut output).unwrap();
+        assert_eq!(output.as_slice(), &NON_NULL_CLSID_ON_STREAM[..]);
     }
 
     const NON_NULL_TERMINATED_NAME: [u8; consts::DIR_ENTRY_LEN] = [
@@ -622,8 +732,13 @@ mod tests {
     )]
     fn non_null_terminated_name_strict() {
         let mut input: &[u8] = &NON_NULL_TERMINATED_NAME;
-        DirEntry::read_from(&mut input, Version::V4, Validation::Strict)
-            .unwrap();
+        DirEntry::read_from(
+            &mut input,
+            Version::V4,
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     // Regression test for https://github.com/mdsteele/rust-cfb/issues/26
@@ -638,6 +753,7 @@ mod tests {
             &mut input,
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(dir_entry.name, "Foobar");
@@ -654,6 +770,7 @@ mod tests {
             &mut input.as_slice(),
             Version::V4,
             Validation::Strict,
+            &mut Vec::new(),
         );
         assert_eq!(
             result.err().unwrap().to_string(),
@@ -672,6 +789,7 @@ mod tests {
             &mut input.as_slice(),
             Version::V4,
             Validation::Strict,
+            &mut Vec::new(),
         );
         assert_eq!(
             result.err().unwrap().to_string(),
@@ -697,6 +815,7 @@ mod tests {
             &mut (&input as &[u8]),
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(dir_entry.obj_type, ObjType::Storage);
@@ -730,8 +849,13 @@ mod tests {
     )]
     fn root_entry_with_incorrect_name_strict() {
         let mut input: &[u8] = &ROOT_ENTRY_WITH_INCORRECT_NAME;
-        DirEntry::read_from(&mut input, Version::V4, Validation::Strict)
-            .unwrap();
+        DirEntry::read_from(
+            &mut input,
+            Version::V4,
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     // Regression test for https://github.com/mdsteele/rust-cfb/issues/29
@@ -747,6 +871,7 @@ mod tests {
             &mut input,
             Version::V4,
             Validation::Permissive,
+            &mut Vec::new(),
         )
         .unwrap();
         assert_eq!(dir_entry.obj_type, ObjType::Root);
@@ -1,5 +1,11 @@
-use crate::internal::{consts, DirEntry, MiniAllocator, ObjType, Timestamp};
+use crate::internal::path::is_temporary_name;
+use crate::internal::{
+    consts, DirEntry, MetadataFields, MiniAllocator, ObjType, StreamId,
+    Timestamp,
+};
 use std::fmt;
+use std::io;
+use std::iter::FusedIterator;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
@@ -10,6 +16,7 @@ use uuid::Uuid;
 /// Metadata about a single object (storage or stream) in a compound file.
 #[derive(Clone)]
 pub struct Entry {
+    stream_id: u32,
     name: String,
     path: PathBuf,
     obj_type: ObjType,
@@ -18,19 +25,43 @@ pub struct Entry {
     creation_time: Timestamp,
     modified_time: Timestamp,
     stream_len: u64,
+    readable_len: u64,
+    chain_len: u64,
 }
 
 impl Entry {
-    pub(crate) fn new(dir_entry: &DirEntry, path: PathBuf) -> Entry {
+    pub(crate) fn new<F>(
+        minialloc: &MiniAllocator<F>,
+        stream_id: u32,
+        path: PathBuf,
+    ) -> Entry {
+        let dir_entry = minialloc.dir_entry(stream_id);
+        let is_stream = dir_entry.obj_type == ObjType::Stream;
+        let readable_len =
+            if is_stream { minialloc.readable_len(stream_id) } else { 0 };
         Entry {
+            stream_id,
             name: dir_entry.name.clone(),
             path,
             obj_type: dir_entry.obj_type,
-            clsid: dir_entry.clsid,
+            // Streams have no CLSID, even if their directory entry has some
+            // bytes there (see `ValidationIssueKind::StreamClsid`).
+            clsid: if is_stream { Uuid::nil() } else { dir_entry.clsid },
             state_bits: dir_entry.state_bits,
             creation_time: dir_entry.creation_time,
             modified_time: dir_entry.modified_time,
-            stream_len: dir_entry.stream_len,
+            stream_len: if is_stream {
+                minialloc.reported_len(stream_id)
+            } else {
+                0
+            },
+            readable_len,
+            // A chain that can't be followed (one with a loop, say) holds
+            // nothing past what can be read.
+            chain_len: minialloc
+                .chain_len(stream_id)
+                .unwrap_or(readable_len)
+                .max(readable_len),
         }
     }
 
@@ -44,6 +75,14 @@ impl Entry {
         &self.path
     }
 
+    /// Returns the ID of this object's directory entry, which is what
+    /// lower-level APIs such as
+    /// [`CompoundFile::raw_dir_entry`](crate::CompoundFile::raw_dir_entry)
+    /// take.  Removing the object frees the ID for reuse by a later object.
+    pub fn stream_id(&self) -> StreamId {
+        StreamId::new(self.stream_id)
+    }
+
     /// Returns whether this entry is for a stream object (i.e. a "file" within
     /// the compound file).
     pub fn is_stream(&self) -> bool {
@@ -63,17 +102,52 @@ impl Entry {
     }
 
     /// Returns the size, in bytes, of the stream that this metadata is for.
+    /// This is always zero for storages, including the root storage; in
+    /// particular, the root's length is *not* the size of the mini stream
+    /// (use
+    /// [`CompoundFile::mini_stream_len`](crate::CompoundFile::mini_stream_len)
+    /// for that), so summing `len()` over a walk gives the total size of the
+    /// streams in the file.
     pub fn len(&self) -> u64 {
         self.stream_len
     }
 
-    /// Returns true if the stream is empty.
+    /// Returns true if this entry is for an empty stream.  This is always
+    /// true for storages (see [`Entry::len`]).
     pub fn is_empty(&self) -> bool {
         self.stream_len == 0
     }
 
+    /// Returns how many bytes of this stream can actually be read.  This is
+    /// the same as [`Entry::len`], unless the stream's declared length is
+    /// more than its sector chain holds (see
+    /// [`ValidationIssueKind::StreamLongerThanChain`](crate::ValidationIssueKind::StreamLongerThanChain)),
+    /// in which case reads stop at the end of the chain.  Callers that
+    /// preallocate a buffer for a stream's contents should size it by this,
+    /// rather than by the declared length, which a malformed file can set to
+    /// anything.
+    pub fn readable_len(&self) -> u64 {
+        self.readable_len
+    }
+
+    /// Returns how many bytes this stream's sector chain holds, regardless
+    /// of its declared length.  This is normally [`Entry::len`] rounded up
+    /// to a whole number of (mini) sectors; anything in the chain past the
+    /// declared length is slack, which
+    /// [`CompoundFile::open_stream_full_chain`](crate::CompoundFile::open_stream_full_chain)
+    /// can read.  A stream whose declared length understates its data (by
+    /// accident or by design) has a larger gap here than rounding explains.
+    /// This is always zero for storages.
+    pub fn chain_len(&self) -> u64 {
+        self.chain_len
+    }
+
     /// Returns the CLSID (that is, the object class GUID) for this object.
-    /// This will always be all zeros for stream objects.
+    /// This will always be all zeros for stream objects, even if the
+    /// stream's directory entry has other bytes in its CLSID field; those
+    /// are reported by
+    /// [`CompoundFile::validate`](crate::CompoundFile::validate), and kept
+    /// unchanged in the file.
     pub fn clsid(&self) -> &Uuid {
         &self.clsid
     }
@@ -94,6 +168,57 @@ impl Entry {
     pub fn modified(&self) -> SystemTime {
         self.modified_time.to_system_time()
     }
+
+    /// Returns what kind of object this entry represents.
+    pub fn file_type(&self) -> EntryKind {
+        match self.obj_type {
+            ObjType::Root => EntryKind::Root,
+            ObjType::Storage => EntryKind::Storage,
+            _ => EntryKind::Stream,
+        }
+    }
+
+    /// Returns this entry's metadata in the shape of
+    /// [`std::fs::Metadata`](https://doc.rust-lang.org/std/fs/struct.Metadata.html),
+    /// for use by code that is generic over filesystem-like entries.
+    pub fn metadata(&self) -> EntryMetadata {
+        EntryMetadata::from(self)
+    }
+
+    /// Copies the selected metadata fields from this entry to the given
+    /// directory entry, following the same rules as the individual setters:
+    /// streams never get times, and the root never gets a creation time.
+    /// The caller must check that the CLSID is only copied between storages.
+    pub(crate) fn copy_metadata_into(
+        &self,
+        dir_entry: &mut DirEntry,
+        fields: MetadataFields,
+    ) {
+        if fields.contains(MetadataFields::CLSID) {
+            debug_assert!(self.is_storage());
+            debug_assert_ne!(dir_entry.obj_type, ObjType::Stream);
+            dir_entry.clsid = self.clsid;
+        }
+        if fields.contains(MetadataFields::STATE_BITS) {
+            dir_entry.state_bits = self.state_bits;
+        }
+        if fields.contains(MetadataFields::CREATED)
+            && dir_entry.obj_type == ObjType::Storage
+        {
+            dir_entry.creation_time = self.creation_time;
+        }
+        if fields.contains(MetadataFields::MODIFIED)
+            && dir_entry.obj_type != ObjType::Stream
+        {
+            dir_entry.modified_time = self.modified_time;
+        }
+    }
+}
+
+impl AsRef<Path> for Entry {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
 }
 
 impl fmt::Debug for Entry {
@@ -109,6 +234,122 @@ impl fmt::Debug for Entry {
 
 //===========================================================================//
 
+/// The kind of object that an [`Entry`] represents, analogous to
+/// [`std::fs::FileType`](https://doc.rust-lang.org/std/fs/struct.FileType.html).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EntryKind {
+    /// A stream object (a "file").
+    Stream,
+    /// A storage object other than the root (a "directory").
+    Storage,
+    /// The root storage object.
+    Root,
+}
+
+impl EntryKind {
+    /// Returns true for storages, including the root storage.
+    pub fn is_dir(self) -> bool {
+        self != EntryKind::Stream
+    }
+
+    /// Returns true for streams.
+    pub fn is_file(self) -> bool {
+        self == EntryKind::Stream
+    }
+
+    /// Always returns false, since compound files have no symbolic links.
+    pub fn is_symlink(self) -> bool {
+        false
+    }
+}
+
+//===========================================================================//
+
+/// The subset of
+/// [`std::fs::Metadata`](https://doc.rust-lang.org/std/fs/struct.Metadata.html)
+/// that makes sense for an object in a compound file, with the same method
+/// names and signatures, as returned by [`Entry::metadata`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EntryMetadata {
+    kind: EntryKind,
+    len: u64,
+    created: Timestamp,
+    modified: Timestamp,
+}
+
+impl EntryMetadata {
+    /// Returns what kind of object this is.
+    pub fn file_type(&self) -> EntryKind {
+        self.kind
+    }
+
+    /// Returns true for storages, including the root storage.
+    pub fn is_dir(&self) -> bool {
+        self.kind.is_dir()
+    }
+
+    /// Returns true for streams.
+    pub fn is_file(&self) -> bool {
+        self.kind.is_file()
+    }
+
+    /// Always returns false, since compound files have no symbolic links.
+    pub fn is_symlink(&self) -> bool {
+        false
+    }
+
+    /// Returns the length of the stream, in bytes, or zero for storages (see
+    /// [`Entry::len`]).
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Always returns false; whether an object can be modified depends on
+    /// how the compound file was opened, not on the object itself.
+    pub fn readonly(&self) -> bool {
+        false
+    }
+
+    /// Returns the object's last modification time.  Fails with
+    /// `ErrorKind::Unsupported` if the time isn't recorded, which is always
+    /// the case for streams.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        system_time(self.modified)
+    }
+
+    /// Returns the object's creation time.  Fails with
+    /// `ErrorKind::Unsupported` if the time isn't recorded, which is always
+    /// the case for streams.
+    pub fn created(&self) -> io::Result<SystemTime> {
+        system_time(self.created)
+    }
+}
+
+impl From<&Entry> for EntryMetadata {
+    fn from(entry: &Entry) -> EntryMetadata {
+        EntryMetadata {
+            kind: entry.file_type(),
+            len: entry.len(),
+            created: entry.creation_time,
+            modified: entry.modified_time,
+        }
+    }
+}
+
+fn system_time(timestamp: Timestamp) -> io::Result<SystemTime> {
+    if timestamp == Timestamp::zero() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Timestamp is not recorded for this object",
+        ));
+    }
+    Ok(timestamp.to_system_time())
+}
+
+//===========================================================================//
+
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum EntriesOrder {
     Nonrecursive,
@@ -117,7 +358,9 @@ pub enum EntriesOrder {
 
 //===========================================================================//
 
-/// An iterator over the entries in a storage object.
+/// An iterator over the entries in a storage object.  Everything it needs
+/// is already in memory, so once created it can't fail; once it returns
+/// `None`, it always will.
 pub struct Entries<'a, F: 'a> {
     order: EntriesOrder,
     // TODO: Consider storing a Weak<RefCell<MiniAllocator<F>>> here instead of
@@ -126,6 +369,7 @@ pub struct Entries<'a, F: 'a> {
     // if the CFB tree structure is modified during iteration.
     minialloc: &'a Arc<RwLock<MiniAllocator<F>>>,
     stack: Vec<(PathBuf, u32, bool)>,
+    include_temporaries: bool,
 }
 
 impl<'a, F> Entries<'a, F> {
@@ -135,7 +379,12 @@ impl<'a, F> Entries<'a, F> {
         parent_path: PathBuf,
         start: u32,
     ) -> Entries<'a, F> {
-        let mut entries = Entries { order, minialloc, stack: Vec::new() };
+        let mut entries = Entries {
+            order,
+            minialloc,
+            stack: Vec::new(),
+            include_temporaries: false,
+        };
         match order {
             EntriesOrder::Nonrecursive => {
                 entries.stack_left_spine(&parent_path, start);
@@ -147,6 +396,13 @@ impl<'a, F> Entries<'a, F> {
         entries
     }
 
+    /// Makes this iterator include temporary objects even if the
+    /// `CompoundFile` is set to hide them.
+    pub(crate) fn include_temporaries(mut self) -> Entries<'a, F> {
+        self.include_temporaries = true;
+        self
+    }
+
     fn stack_left_spine(&mut self, parent_path: &Path, mut current_id: u32) {
         let minialloc = self.minialloc.read().unwrap();
         while current_id != consts::NO_STREAM {
@@ -160,26 +416,58 @@ impl<'a, F> Iterator for Entries<'a, F> {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Entry> {
-        if let Some((parent, stream_id, visit_siblings)) = self.stack.pop() {
+        while let Some((parent, stream_id, visit_siblings)) = self.stack.pop()
+        {
             let minialloc = self.minialloc.read().unwrap();
             let dir_entry = minialloc.dir_entry(stream_id);
             let path = join_path(&parent, dir_entry);
             if visit_siblings {
                 self.stack_left_spine(&parent, dir_entry.right_sibling);
             }
+            // Temporary objects (and anything within them) are hidden, except
+            // at the very top of the iteration, which the caller asked for by
+            // path.
+            if visit_siblings
+                && !self.include_temporaries
+                && !minialloc.show_temporaries()
+                && is_temporary_name(&dir_entry.name)
+            {
+                continue;
+            }
             if self.order == EntriesOrder::Preorder
                 && dir_entry.obj_type != ObjType::Stream
                 && dir_entry.child != consts::NO_STREAM
             {
                 self.stack_left_spine(&path, dir_entry.child);
             }
-            Some(Entry::new(dir_entry, path))
-        } else {
-            None
+            return Some(Entry::new(&minialloc, stream_id, path));
         }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each stacked entry yields exactly one item unless it's a hidden
+        // temporary, and no more entries can be yielded than the directory
+        // holds.  Counting exactly would mean walking the rest of the tree.
+        let minialloc = self.minialloc.read().unwrap();
+        let lower = if self.include_temporaries || minialloc.show_temporaries()
+        {
+            self.stack.len()
+        } else {
+            0
+        };
+        let upper = if self.stack.is_empty() {
+            0
+        } else {
+            minialloc.num_dir_entries() as usize
+        };
+        (lower, Some(upper))
     }
 }
 
+// Once the stack is empty, nothing ever pushes onto it again.
+impl<'a, F> FusedIterator for Entries<'a, F> {}
+
 //===========================================================================//
 
 fn join_path(parent_path: &Path, dir_entry: &DirEntry) -> PathBuf {
@@ -247,16 +535,24 @@ mod tests {
             vec![0],
             vec![consts::FAT_SECTOR, consts::END_OF_CHAIN],
             Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        let directory = Directory::new(
+            allocator,
+            dir_entries,
+            1,
+            Validation::Strict,
+            &mut Vec::new(),
         )
         .unwrap();
-        let directory =
-            Directory::new(allocator, dir_entries, 1, Validation::Strict)
-                .unwrap();
         let minialloc = MiniAllocator::new(
             directory,
             vec![],
             consts::END_OF_CHAIN,
+            consts::MINI_SECTOR_SHIFT,
             Validation::Strict,
+            &mut Vec::new(),
         )
         .unwrap();
         Arc::new(RwLock::new(minialloc))
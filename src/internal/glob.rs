@@ -0,0 +1,59 @@
+/// Returns true if `path` matches the glob `pattern`.  Within a pattern, `?`
+/// matches any one character other than `/`, `*` matches any run of
+/// characters other than `/`, and `**` matches any run of characters at all.
+///
+/// ```
+/// use cfb::glob_match;
+///
+/// assert!(glob_match("/dir/*", "/dir/big"));
+/// assert!(!glob_match("/*", "/dir/big"));
+/// assert!(glob_match("**/b?g", "/dir/big"));
+/// ```
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    glob_match_chars(&pattern, &path)
+}
+
+fn glob_match_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => (0..=path.len())
+            .any(|index| glob_match_chars(&pattern[2..], &path[index..])),
+        Some('*') => {
+            let limit =
+                path.iter().position(|&chr| chr == '/').unwrap_or(path.len());
+            (0..=limit)
+                .any(|index| glob_match_chars(&pattern[1..], &path[index..]))
+        }
+        Some('?') => {
+            matches!(path.first(), Some(&chr) if chr != '/')
+                && glob_match_chars(&pattern[1..], &path[1..])
+        }
+        Some(&chr) => {
+            path.first() == Some(&chr)
+                && glob_match_chars(&pattern[1..], &path[1..])
+        }
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("/*", "/top"));
+        assert!(!glob_match("/*", "/a/one"));
+        assert!(glob_match("/a/**", "/a/b/two"));
+        assert!(glob_match("**", "/a/b/two"));
+        assert!(glob_match("/a/b/t??", "/a/b/two"));
+        assert!(!glob_match("/a/b/t??", "/a/b/three"));
+        assert!(!glob_match("/a?one", "/a/one"));
+        assert!(glob_match("/*/one", "/a/one"));
+    }
+}
+
+//===========================================================================//
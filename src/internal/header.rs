@@ -1,7 +1,9 @@
 use std::fmt;
 use std::io::{self, Read, Write};
 
-use crate::internal::{consts, Validation, Version};
+use crate::internal::{
+    consts, Validation, ValidationIssue, ValidationIssueKind, Version,
+};
 use crate::{ReadLeNumber, WriteLeNumber};
 
 //===========================================================================//
@@ -9,6 +11,7 @@ use crate::{ReadLeNumber, WriteLeNumber};
 #[derive(Clone, PartialEq, Eq)]
 pub struct Header {
     pub version: Version,
+    pub mini_sector_shift: u16,
     pub num_dir_sectors: u32,
     pub num_fat_sectors: u32,
     pub first_dir_sector: u32,
@@ -30,6 +33,7 @@ impl fmt::Debug for Header {
         }
         f.debug_struct("Header")
             .field("version", &self.version)
+            .field("mini_sector_shift", &self.mini_sector_shift)
             .field("num_dir_sectors", &self.num_dir_sectors)
             .field("num_fat_sectors", &self.num_fat_sectors)
             .field("first_dir_sector", &Sector::new(self.first_dir_sector))
@@ -52,6 +56,7 @@ impl Header {
     pub fn read_from<R: Read>(
         reader: &mut R,
         validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
     ) -> io::Result<Header> {
         let mut magic = [0u8; 8];
         reader.read_exact(&mut magic)?;
@@ -61,7 +66,20 @@ impl Header {
                 magic
             );
         }
-        reader.read_exact(&mut [0u8; 16])?; // reserved field
+
+        // According to section 2.2 of the MS-CFB spec, the header CLSID is
+        // "Reserved and unused class ID that MUST be set to all zeroes."
+        let mut clsid = [0u8; 16];
+        reader.read_exact(&mut clsid)?;
+        if clsid != [0u8; 16] {
+            if validation.is_strict() {
+                invalid_data!("Nonzero header CLSID: {:02x?}", clsid);
+            }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::NonzeroReservedField,
+                format!("Nonzero header CLSID: {:02x?}", clsid),
+            ));
+        }
 
         // Read the version number, but don't try to interpret it until after
         // we've checked the byte order mark.
@@ -78,7 +96,7 @@ impl Header {
             );
         }
 
-        let version = match Version::from_number(version_number) {
+        let mut version = match Version::from_number(version_number) {
             Some(version) => version,
             None => {
                 invalid_data!(
@@ -88,35 +106,99 @@ impl Header {
             }
         };
 
+        // According to section 2.2 of the MS-CFB spec, version 3 files MUST
+        // have a sector shift of 9, and version 4 files a sector shift of 12.
+        // Some writers have got the version wrong for the sector size they
+        // used, so under Permissive validation, we trust the sector shift
+        // (which is what determines the file's layout) instead.
         let sector_shift = reader.read_le_u16()?;
         if sector_shift != version.sector_shift() {
-            invalid_data!(
-                "Incorrect sector shift for CFB version {} (expected {}, \
-                 found {})",
-                version.number(),
-                version.sector_shift(),
-                sector_shift
-            );
+            let shift_version = [Version::V3, Version::V4]
+                .iter()
+                .copied()
+                .find(|other| other.sector_shift() == sector_shift);
+            match shift_version {
+                Some(shift_version) if !validation.is_strict() => {
+                    issues.push(ValidationIssue::new(
+                        ValidationIssueKind::VersionSectorShiftMismatch,
+                        format!(
+                            "Header gives CFB version {} but a sector shift \
+                             of {} (for version {}); read as version {}",
+                            version.number(),
+                            sector_shift,
+                            shift_version.number(),
+                            shift_version.number()
+                        ),
+                    ));
+                    version = shift_version;
+                }
+                Some(shift_version) => invalid_data!(
+                    "Incorrect sector shift for CFB version {} (expected {}, \
+                     found {}, which is the sector shift for version {})",
+                    version.number(),
+                    version.sector_shift(),
+                    sector_shift,
+                    shift_version.number()
+                ),
+                None => invalid_data!(
+                    "Incorrect sector shift for CFB version {} (expected {}, \
+                     found {})",
+                    version.number(),
+                    version.sector_shift(),
+                    sector_shift
+                ),
+            }
         }
 
+        // According to section 2.2 of the MS-CFB spec, the mini sector shift
+        // "MUST be set to 0x0006", but a few old writers use other values,
+        // and Windows honors the field.  So we accept anything that gives
+        // mini sectors no smaller than 16 bytes and no larger than a regular
+        // sector, and just warn about it, even under Strict validation.
         let mini_sector_shift = reader.read_le_u16()?;
-        if mini_sector_shift != consts::MINI_SECTOR_SHIFT {
+        if mini_sector_shift < consts::MIN_MINI_SECTOR_SHIFT
+            || mini_sector_shift > version.sector_shift()
+        {
             invalid_data!(
-                "Incorrect mini sector shift (expected {}, found {})",
+                "Unsupported mini sector shift for CFB version {} (expected \
+                 {}, found {})",
+                version.number(),
                 consts::MINI_SECTOR_SHIFT,
                 mini_sector_shift
             );
         }
+        if mini_sector_shift != consts::MINI_SECTOR_SHIFT {
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::NonstandardMiniSectorShift,
+                format!(
+                    "Nonstandard mini sector shift (expected {}, found {})",
+                    consts::MINI_SECTOR_SHIFT,
+                    mini_sector_shift
+                ),
+            ));
+        }
 
-        // TODO: require reserved field to be all zeros under strict validation
-        reader.read_exact(&mut [0u8; 6])?; // reserved field
+        // According to section 2.2 of the MS-CFB spec, the reserved field
+        // "MUST be set to all zeroes."  Under Permissive validation, we don't
+        // enforce this, since the field is otherwise unused.
+        let mut reserved = [0u8; 6];
+        reader.read_exact(&mut reserved)?;
+        if reserved != [0u8; 6] {
+            if validation.is_strict() {
+                invalid_data!("Nonzero reserved header field: {:?}", reserved);
+            }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::NonzeroReservedField,
+                format!("Nonzero reserved header field: {:?}", reserved),
+            ));
+        }
 
         // According to section 2.2 of the MS-CFB spec, "If Major Version is 3,
         // the Number of Directory Sectors MUST be zero."  However, under
         // Permissive validation, we don't enforce this, but instead just treat
         // the field as though it were zero for V3 files.
         let mut num_dir_sectors = reader.read_le_u32()?;
-        if version == Version::V3 && num_dir_sectors != 0 {
+        if !version.requires_dir_sector_count() && num_dir_sectors != 0 {
             if validation.is_strict() {
                 invalid_data!(
                     "Invalid number of directory sectors field (must be zero \
@@ -124,6 +206,14 @@ impl Header {
                     num_dir_sectors
                 );
             }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::DirSectorCountNotZero,
+                format!(
+                    "Number of directory sectors field is {}, but must be \
+                     zero for CFB version 3",
+                    num_dir_sectors
+                ),
+            ));
             num_dir_sectors = 0;
         }
 
@@ -150,11 +240,26 @@ impl Header {
             first_difat_sector = consts::END_OF_CHAIN;
         }
 
+        // According to section 2.5 of the MS-CFB spec, unused DIFAT entries
+        // MUST be set to FREE_SECTOR, so under Strict validation, once we see
+        // one, all of the remaining entries must be FREE_SECTOR too.
         let mut initial_difat_entries =
             [consts::FREE_SECTOR; consts::NUM_DIFAT_ENTRIES_IN_HEADER];
-        for entry in initial_difat_entries.iter_mut() {
+        for (index, entry) in initial_difat_entries.iter_mut().enumerate() {
             let next = reader.read_le_u32()?;
             if next == consts::FREE_SECTOR {
+                if validation.is_strict() {
+                    for _ in (index + 1)..consts::NUM_DIFAT_ENTRIES_IN_HEADER {
+                        let next = reader.read_le_u32()?;
+                        if next != consts::FREE_SECTOR {
+                            invalid_data!(
+                                "Initial DIFAT array has entry 0x{:08X} \
+                                 after an unused entry",
+                                next
+                            );
+                        }
+                    }
+                }
                 break;
             } else if next > consts::MAX_REGULAR_SECTOR {
                 invalid_data!(
@@ -166,8 +271,22 @@ impl Header {
             *entry = next;
         }
 
+        // According to section 2.2 of the MS-CFB spec, for version 4 files
+        // "the remaining part of the header (3,584 bytes) MUST be filled with
+        // all zeroes."  Since that space is otherwise unused, we only check
+        // it under Strict validation.
+        if validation.is_strict() && version.is_version_4() {
+            let mut padding =
+                vec![0u8; version.sector_len() - consts::HEADER_LEN];
+            reader.read_exact(&mut padding)?;
+            if padding.iter().any(|&byte| byte != 0) {
+                invalid_data!("Nonzero padding after version 4 header");
+            }
+        }
+
         Ok(Header {
             version,
+            mini_sector_shift,
             num_dir_sectors,
             num_fat_sectors,
             first_dir_sector,
@@ -186,6 +305,8 @@ impl Header {
         writer.write_le_u16(self.version.number())?;
         writer.write_le_u16(consts::BYTE_ORDER_MARK)?;
         writer.write_le_u16(self.version.sector_shift())?;
+        // New and rewritten headers always use standard 64-byte mini sectors,
+        // whatever shift an existing file was read with.
         writer.write_le_u16(consts::MINI_SECTOR_SHIFT)?;
         writer.write_all(&[0; 6])?; // reserved field
         writer.write_le_u32(self.num_dir_sectors)?;
@@ -208,13 +329,14 @@ impl Header {
 
 #[cfg(test)]
 mod tests {
-    use crate::internal::{consts, Validation, Version};
+    use crate::internal::{consts, Validation, ValidationIssueKind, Version};
 
     use super::Header;
 
     fn make_valid_header() -> Header {
         let mut header = Header {
             version: Version::V3,
+            mini_sector_shift: consts::MINI_SECTOR_SHIFT,
             num_dir_sectors: 0,
             num_fat_sectors: 1,
             first_dir_sector: 1,
@@ -241,9 +363,12 @@ mod tests {
         let header1 = make_valid_header();
         let mut data = Vec::<u8>::new();
         header1.write_to(&mut data).unwrap();
-        let header2 =
-            Header::read_from(&mut data.as_slice(), Validation::Strict)
-                .unwrap();
+        let header2 = Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
         assert_eq!(header1.version, header2.version);
         assert_eq!(header1.num_dir_sectors, header2.num_dir_sectors);
         assert_eq!(header1.num_fat_sectors, header2.num_fat_sectors);
@@ -265,7 +390,12 @@ mod tests {
     fn invalid_magic_number() {
         let mut data = make_valid_header_data();
         data[2] = 255;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -273,7 +403,12 @@ mod tests {
     fn invalid_version() {
         let mut data = make_valid_header_data();
         data[26] = 42;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -284,28 +419,98 @@ mod tests {
     fn invalid_byte_order_mark() {
         let mut data = make_valid_header_data();
         data[29] = 7;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
     #[should_panic(
         expected = "Incorrect sector shift for CFB version 3 (expected 9, \
-                    found 12)"
+                    found 12, which is the sector shift for version 4)"
     )]
     fn invalid_sector_shift() {
         let mut data = make_valid_header_data();
         data[30] = 12;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn mismatched_sector_shift_sets_version_when_permissive() {
+        let mut data = make_valid_header_data();
+        data[30] = 12;
+        let mut issues = Vec::new();
+        let header = Header::read_from(
+            &mut data.as_slice(),
+            Validation::Permissive,
+            &mut issues,
+        )
+        .unwrap();
+        assert_eq!(header.version, Version::V4);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].kind(),
+            ValidationIssueKind::VersionSectorShiftMismatch
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Incorrect sector shift for CFB version 3 (expected 9, \
+                    found 10)"
+    )]
+    fn unsupported_sector_shift_when_permissive() {
+        let mut data = make_valid_header_data();
+        data[30] = 10;
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Permissive,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
     #[should_panic(
-        expected = "Incorrect mini sector shift (expected 6, found 7)"
+        expected = "Unsupported mini sector shift for CFB version 3 \
+                    (expected 6, found 10)"
     )]
     fn invalid_mini_sector_shift() {
+        let mut data = make_valid_header_data();
+        data[32] = 10;
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn nonstandard_mini_sector_shift() {
         let mut data = make_valid_header_data();
         data[32] = 7;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        let mut issues = Vec::new();
+        let header = Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut issues,
+        )
+        .unwrap();
+        assert_eq!(header.mini_sector_shift, 7);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].kind(),
+            ValidationIssueKind::NonstandardMiniSectorShift
+        );
     }
 
     #[test]
@@ -316,18 +521,26 @@ mod tests {
     fn v3_non_zero_dir_sectors_strict() {
         let mut data = make_valid_header_data();
         data[40] = 37;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
     fn v3_non_zero_dir_sectors_permissive() {
         let mut data = make_valid_header_data();
         data[40] = 37;
-        let header =
-            Header::read_from(&mut data.as_slice(), Validation::Permissive)
-                .unwrap();
+        let header = Header::read_from(
+            &mut data.as_slice(),
+            Validation::Permissive,
+            &mut Vec::new(),
+        )
+        .unwrap();
         assert_eq!(header.num_dir_sectors, 0);
-        assert_eq!(format!("{header:?}"), "Header { version: V3, num_dir_sectors: 0, num_fat_sectors: 1, first_dir_sector: 1, first_minifat_sector: 2, num_minifat_sectors: 3, first_difat_sector: EOC, num_difat_sectors: 0, initial_difat_entries: [0] }");
+        assert_eq!(format!("{header:?}"), "Header { version: V3, mini_sector_shift: 6, num_dir_sectors: 0, num_fat_sectors: 1, first_dir_sector: 1, first_minifat_sector: 2, num_minifat_sectors: 3, first_difat_sector: EOC, num_difat_sectors: 0, initial_difat_entries: [0] }");
     }
 
     #[test]
@@ -337,7 +550,12 @@ mod tests {
     fn invalid_mini_stream_cutoff() {
         let mut data = make_valid_header_data();
         data[57] = 8;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 
     #[test]
@@ -348,7 +566,12 @@ mod tests {
     fn invalid_difat_array() {
         let mut data = make_valid_header_data();
         data[80] = 0xFB;
-        Header::read_from(&mut data.as_slice(), Validation::Strict).unwrap();
+        Header::read_from(
+            &mut data.as_slice(),
+            Validation::Strict,
+            &mut Vec::new(),
+        )
+        .unwrap();
     }
 }
 
@@ -0,0 +1,186 @@
+use crate::internal::consts;
+use std::fmt;
+
+//===========================================================================//
+
+/// The index of a regular sector within a compound file (the header is not
+/// counted, so sector 0 starts right after it), or one of the special values
+/// that the FAT and the header use in place of a sector index.
+///
+/// Both `Debug` and `Display` render the special values by name (e.g.
+/// `END_OF_CHAIN`) and regular sectors as their index, so that a dump of the
+/// FAT (see
+/// [`CompoundFile::raw_fat`](../struct.CompoundFile.html#method.raw_fat))
+/// reads like `[FAT, END_OF_CHAIN, 3, END_OF_CHAIN, FREE]`.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SectorId(u32);
+
+impl SectorId {
+    /// The largest index that a regular sector may have (`MAXREGSECT` in
+    /// the spec).
+    pub const MAX_REGULAR: SectorId = SectorId(consts::MAX_REGULAR_SECTOR);
+    /// A reserved value that must not be used (`0xfffffffb` in the spec).
+    pub const INVALID: SectorId = SectorId(consts::INVALID_SECTOR);
+    /// Marks a DIFAT sector in the FAT (`DIFSECT` in the spec).
+    pub const DIFAT: SectorId = SectorId(consts::DIFAT_SECTOR);
+    /// Marks a FAT sector in the FAT (`FATSECT` in the spec).
+    pub const FAT: SectorId = SectorId(consts::FAT_SECTOR);
+    /// Marks the last sector of a chain in the FAT, or an empty chain
+    /// wherever a starting sector is expected (`ENDOFCHAIN` in the spec).
+    pub const END_OF_CHAIN: SectorId = SectorId(consts::END_OF_CHAIN);
+    /// Marks an unallocated sector in the FAT (`FREESECT` in the spec).
+    pub const FREE: SectorId = SectorId(consts::FREE_SECTOR);
+
+    /// Wraps the given raw value.
+    pub const fn new(value: u32) -> SectorId {
+        SectorId(value)
+    }
+
+    /// Returns the raw value.
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if this is the index of a regular sector, rather than one
+    /// of the special values.
+    pub const fn is_regular(self) -> bool {
+        self.0 <= consts::MAX_REGULAR_SECTOR
+    }
+
+    /// Returns the name of the special value, or `None` for a regular
+    /// sector.
+    fn special_name(self) -> Option<&'static str> {
+        match self.0 {
+            consts::INVALID_SECTOR => Some("INVALID"),
+            consts::DIFAT_SECTOR => Some("DIFAT"),
+            consts::FAT_SECTOR => Some("FAT"),
+            consts::END_OF_CHAIN => Some("END_OF_CHAIN"),
+            consts::FREE_SECTOR => Some("FREE"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for SectorId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for SectorId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.special_name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl From<u32> for SectorId {
+    fn from(value: u32) -> SectorId {
+        SectorId(value)
+    }
+}
+
+impl From<SectorId> for u32 {
+    fn from(sector_id: SectorId) -> u32 {
+        sector_id.0
+    }
+}
+
+//===========================================================================//
+
+/// The index of a directory entry within a compound file (its position in
+/// the directory chain), or the special value that directory entries use for
+/// "no entry".
+///
+/// Both `Debug` and `Display` render [`StreamId::NONE`] by name and other
+/// values as their index.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StreamId(u32);
+
+impl StreamId {
+    /// The ID of the root storage entry, which is always the first entry in
+    /// the directory.
+    pub const ROOT: StreamId = StreamId(consts::ROOT_STREAM_ID);
+    /// The largest ID that a directory entry may have (`MAXREGSID` in the
+    /// spec).
+    pub const MAX_REGULAR: StreamId = StreamId(consts::MAX_REGULAR_STREAM_ID);
+    /// Marks a missing sibling or child in a directory entry (`NOSTREAM` in
+    /// the spec).
+    pub const NONE: StreamId = StreamId(consts::NO_STREAM);
+
+    /// Wraps the given raw value.
+    pub const fn new(value: u32) -> StreamId {
+        StreamId(value)
+    }
+
+    /// Returns the raw value.
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Debug for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == consts::NO_STREAM {
+            f.write_str("NONE")
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+impl From<u32> for StreamId {
+    fn from(value: u32) -> StreamId {
+        StreamId(value)
+    }
+}
+
+impl From<StreamId> for u32 {
+    fn from(stream_id: StreamId) -> u32 {
+        stream_id.0
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{SectorId, StreamId};
+
+    #[test]
+    fn special_sector_ids_render_by_name() {
+        let fat = [
+            SectorId::FAT,
+            SectorId::DIFAT,
+            SectorId::new(3),
+            SectorId::END_OF_CHAIN,
+            SectorId::FREE,
+            SectorId::INVALID,
+            SectorId::MAX_REGULAR,
+        ];
+        assert_eq!(
+            format!("{:?}", fat),
+            "[FAT, DIFAT, 3, END_OF_CHAIN, FREE, INVALID, 4294967290]"
+        );
+        assert_eq!(SectorId::END_OF_CHAIN.to_string(), "END_OF_CHAIN");
+        assert!(SectorId::MAX_REGULAR.is_regular());
+        assert!(!SectorId::INVALID.is_regular());
+    }
+
+    #[test]
+    fn special_stream_ids_render_by_name() {
+        assert_eq!(format!("{:?}", StreamId::NONE), "NONE");
+        assert_eq!(StreamId::ROOT.to_string(), "0");
+        assert_eq!(u32::from(StreamId::new(17)), 17);
+    }
+}
+
+//===========================================================================//
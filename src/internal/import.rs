@@ -0,0 +1,171 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//===========================================================================//
+
+/// Maps a local file name, and whether it names a directory, to the name of
+/// the object to create.
+type MapNames = dyn FnMut(&str, bool) -> String;
+
+/// Options for
+/// [`CompoundFile::create_storage_from_dir`](../struct.CompoundFile.html#method.create_storage_from_dir)
+/// and [`cfb::create_from_dir`](../fn.create_from_dir.html), which copy a
+/// directory tree on disk into a compound file.
+///
+/// ```
+/// use cfb::ImportOptions;
+///
+/// let options = ImportOptions::new()
+///     .preserve_times(true)
+///     .map_names(|name, is_storage| {
+///         match name.strip_suffix(".dump") {
+///             Some(stem) if !is_storage => stem.to_string(),
+///             _ => name.to_string(),
+///         }
+///     });
+/// ```
+#[derive(Default)]
+pub struct ImportOptions {
+    pub(crate) follow_symlinks: bool,
+    pub(crate) preserve_times: bool,
+    pub(crate) map_names: Option<Box<MapNames>>,
+}
+
+impl ImportOptions {
+    /// Returns the default options: symbolic links are skipped, timestamps
+    /// are left as they are when objects are created, and objects are named
+    /// exactly as their local files.
+    pub fn new() -> ImportOptions {
+        ImportOptions::default()
+    }
+
+    /// If true, symbolic links are followed, and imported as whatever they
+    /// point to; a link to a directory that contains it is reported as a
+    /// failure rather than followed forever.  If false, links are skipped
+    /// (and listed in [`ImportReport::skipped`]).  Defaults to false.
+    pub fn follow_symlinks(mut self, follow: bool) -> ImportOptions {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// If true, each storage is given the modification time of its
+    /// directory.  (Streams are left alone, since the CFB spec requires
+    /// their timestamps to be zero.)  Defaults to false.
+    pub fn preserve_times(mut self, preserve: bool) -> ImportOptions {
+        self.preserve_times = preserve;
+        self
+    }
+
+    /// Sets a function that is given the name of each local file or
+    /// directory, along with whether it will become a storage, and returns
+    /// the name to give the object (for example, to re-encode names for an
+    /// MSI package with `msi::encode_name`, from the `msi` feature).  The
+    /// returned name is checked like any other.
+    pub fn map_names<M>(mut self, map: M) -> ImportOptions
+    where
+        M: FnMut(&str, bool) -> String + 'static,
+    {
+        self.map_names = Some(Box::new(map));
+        self
+    }
+
+    /// Returns the name to give the object for the given local name.
+    pub(crate) fn object_name(
+        &mut self,
+        name: &str,
+        is_storage: bool,
+    ) -> String {
+        match self.map_names.as_mut() {
+            Some(map) => map(name, is_storage),
+            None => name.to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for ImportOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImportOptions")
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("preserve_times", &self.preserve_times)
+            .field("map_names", &self.map_names.is_some())
+            .finish()
+    }
+}
+
+//===========================================================================//
+
+/// A local file or directory that couldn't be imported, as part of an
+/// [`ImportReport`].
+#[derive(Debug)]
+pub struct ImportFailure {
+    pub(crate) local_path: PathBuf,
+    pub(crate) error: io::Error,
+}
+
+impl ImportFailure {
+    /// Returns the path of the local file or directory.
+    pub fn local_path(&self) -> &Path {
+        &self.local_path
+    }
+
+    /// Returns what went wrong.  For example, a name that is too long, or
+    /// that contains a character that object names can't (such as `/` or
+    /// `:`), gives an `InvalidInput` error; a name already taken by another
+    /// object gives an `AlreadyExists` error; and a local file that can't be
+    /// read gives whatever error reading it did.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for ImportFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.local_path.display(), self.error)
+    }
+}
+
+/// A report of what was done by
+/// [`CompoundFile::create_storage_from_dir`](../struct.CompoundFile.html#method.create_storage_from_dir).
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub(crate) created: Vec<PathBuf>,
+    pub(crate) skipped: Vec<PathBuf>,
+    pub(crate) failures: Vec<ImportFailure>,
+    pub(crate) num_bytes: u64,
+}
+
+impl ImportReport {
+    /// Returns the paths within the compound file of the streams and
+    /// storages that were created, in the order in which they were created.
+    pub fn created(&self) -> &[PathBuf] {
+        &self.created
+    }
+
+    /// Returns the local paths of the symbolic links (when they aren't
+    /// followed) and other special files, such as sockets, that were
+    /// skipped.
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    /// Returns the local files and directories that couldn't be imported.
+    /// Nothing within a directory that couldn't be imported is imported
+    /// either.
+    pub fn failures(&self) -> &[ImportFailure] {
+        &self.failures
+    }
+
+    /// Returns the total number of bytes copied into streams.
+    pub fn num_bytes(&self) -> u64 {
+        self.num_bytes
+    }
+
+    /// Returns true if everything (other than what was skipped) was
+    /// imported.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+//===========================================================================//
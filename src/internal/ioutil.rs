@@ -0,0 +1,260 @@
+//! Helpers for reading from and writing to the underlying file.
+//!
+//! A `Read` or `Write` implementation may return fewer bytes than asked for,
+//! or fail with `ErrorKind::Interrupted`, at any time, without anything
+//! being wrong with the file.  Every access to the underlying file goes
+//! through these helpers (or through `Sector`, which uses them), so that
+//! such readers behave exactly like well-behaved ones, and so that running
+//! out of data is reported in a way that says whether the file really ends
+//! there.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+//===========================================================================//
+
+/// Calls `reader.read` until it returns something other than an
+/// `Interrupted` error.
+pub fn read_retrying<R: Read + ?Sized>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+/// Calls `writer.write` until it returns something other than an
+/// `Interrupted` error.
+pub fn write_retrying<W: Write + ?Sized>(
+    writer: &mut W,
+    buf: &[u8],
+) -> io::Result<usize> {
+    loop {
+        match writer.write(buf) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+/// Calls `writer.flush` until it returns something other than an
+/// `Interrupted` error.
+pub fn flush_retrying<W: Write + ?Sized>(writer: &mut W) -> io::Result<()> {
+    loop {
+        match writer.flush() {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+/// Reads until the buffer is full or the reader is exhausted, retrying
+/// interrupted reads, and returns the number of bytes read.
+pub fn read_up_to<R: Read + ?Sized>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match read_retrying(reader, &mut buf[len..])? {
+            0 => break,
+            num_bytes => len += num_bytes,
+        }
+    }
+    Ok(len)
+}
+
+/// Fills `buf` from the reader's current position, retrying interrupted and
+/// short reads.  If the reader runs out of data first, the error says
+/// whether the file really ends there (see [`eof_error`]).
+pub fn read_exact<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> io::Result<()> {
+    let len = read_up_to(reader, buf)?;
+    if len < buf.len() {
+        return Err(eof_error(reader, buf.len() - len));
+    }
+    Ok(())
+}
+
+/// Writes all of `buf`, retrying interrupted and short writes.
+pub fn write_all<W: Write + ?Sized>(
+    writer: &mut W,
+    buf: &[u8],
+) -> io::Result<()> {
+    let mut len = 0;
+    while len < buf.len() {
+        match write_retrying(writer, &buf[len..])? {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!(
+                        "Underlying file accepted only {} of {} bytes",
+                        len,
+                        buf.len()
+                    ),
+                ));
+            }
+            num_bytes => len += num_bytes,
+        }
+    }
+    Ok(())
+}
+
+/// Returns the error for a reader that has reported end-of-file with
+/// `missing` bytes still to be read.  The file's length is checked (leaving
+/// the stream's position unchanged), so that a file that genuinely ends
+/// before the data can be told apart from a reader that stopped early.
+pub fn eof_error<S: Seek + ?Sized>(
+    stream: &mut S,
+    missing: usize,
+) -> io::Error {
+    let lengths = stream.stream_position().and_then(|position| {
+        let file_len = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(position))?;
+        Ok((position, file_len))
+    });
+    let message = match lengths {
+        Ok((position, file_len)) if file_len <= position => format!(
+            "Unexpected end of file: needed {} more bytes at offset {}, but \
+             the file is only {} bytes long",
+            missing, position, file_len
+        ),
+        Ok((position, file_len)) => format!(
+            "Underlying reader returned end-of-file at offset {} with {} \
+             bytes still to read, although the file is {} bytes long",
+            position, missing, file_len
+        ),
+        Err(_) => format!(
+            "Unexpected end of file with {} bytes still to read",
+            missing
+        ),
+    };
+    io::Error::new(io::ErrorKind::UnexpectedEof, message)
+}
+
+//===========================================================================//
+
+/// Wraps a reader so that its reads are retried when interrupted, and so
+/// that `read_exact` (and so anything built on it, such as
+/// `ReadLeNumber`) behaves like [`read_exact`] above.
+pub struct Retrying<'a, R: ?Sized>(pub &'a mut R);
+
+impl<'a, R: Read + Seek + ?Sized> Read for Retrying<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_retrying(self.0, buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        read_exact(self.0, buf)
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{eof_error, read_exact, read_up_to, write_all};
+    use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+    /// Returns at most one byte per call, after first failing with
+    /// `Interrupted`, and claims end-of-file after `stop_at` bytes.
+    struct Stingy {
+        inner: Cursor<Vec<u8>>,
+        interrupt: bool,
+        stop_at: u64,
+    }
+
+    impl Stingy {
+        fn new(data: Vec<u8>, stop_at: u64) -> Stingy {
+            Stingy { inner: Cursor::new(data), interrupt: false, stop_at }
+        }
+    }
+
+    impl Read for Stingy {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            if buf.is_empty() || self.inner.position() >= self.stop_at {
+                return Ok(0);
+            }
+            self.inner.read(&mut buf[..1])
+        }
+    }
+
+    impl Write for Stingy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.inner.write(&buf[..buf.len().min(1)])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Stingy {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn retries_interrupted_and_short_reads() {
+        let mut reader = Stingy::new(vec![1, 2, 3, 4, 5], 5);
+        let mut buf = [0u8; 3];
+        read_exact(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+        let mut buf = [0u8; 3];
+        assert_eq!(read_up_to(&mut reader, &mut buf).unwrap(), 2);
+        assert_eq!(buf, [4, 5, 0]);
+    }
+
+    #[test]
+    fn retries_interrupted_and_short_writes() {
+        let mut writer = Stingy::new(Vec::new(), 0);
+        write_all(&mut writer, &[1, 2, 3]).unwrap();
+        write_all(&mut writer, &[]).unwrap();
+        assert_eq!(writer.inner.into_inner(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn genuine_end_of_file() {
+        let mut reader = Stingy::new(vec![0; 10], 10);
+        reader.seek(SeekFrom::Start(8)).unwrap();
+        let error = read_exact(&mut reader, &mut [0u8; 4]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            error.to_string(),
+            "Unexpected end of file: needed 2 more bytes at offset 10, but \
+             the file is only 10 bytes long"
+        );
+        assert_eq!(reader.stream_position().unwrap(), 10);
+    }
+
+    #[test]
+    fn reader_stopped_early() {
+        let mut reader = Stingy::new(vec![0; 10], 6);
+        let error = read_exact(&mut reader, &mut [0u8; 8]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            error.to_string(),
+            "Underlying reader returned end-of-file at offset 6 with 2 bytes \
+             still to read, although the file is 10 bytes long"
+        );
+        assert_eq!(reader.stream_position().unwrap(), 6);
+        let error = eof_error(&mut reader, 1);
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
+
+//===========================================================================//
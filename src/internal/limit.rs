@@ -0,0 +1,238 @@
+use crate::internal::Version;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//===========================================================================//
+
+/// The error payload reported when an operation would grow a compound file
+/// past the largest size its version allows (see
+/// [`Version::max_file_len`](enum.Version.html#method.max_file_len)).  In
+/// practice, this means a version 3 file reaching 2 GiB.
+///
+/// This is returned wrapped in an `io::Error` (of kind `Other`); use
+/// [`from_io_error`](#method.from_io_error) to recognize it.  The check is
+/// made before anything is written for the sector that would cross the
+/// limit, so the file is left intact (although a stream write that fails
+/// this way may have written some of its data already).  To store more
+/// data, create a version 4 file instead.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FileTooLarge {
+    version: Version,
+    required: u64,
+}
+
+impl FileTooLarge {
+    pub(crate) fn new(version: Version, required: u64) -> FileTooLarge {
+        debug_assert!(required > version.max_file_len());
+        FileTooLarge { version, required }
+    }
+
+    /// Returns the version of the compound file.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns the largest length, in bytes, that the compound file may
+    /// have.
+    pub fn max_len(&self) -> u64 {
+        self.version.max_file_len()
+    }
+
+    /// Returns the length, in bytes, that the compound file would have
+    /// needed to grow to.
+    pub fn required(&self) -> u64 {
+        self.required
+    }
+
+    /// Returns the `FileTooLarge` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&FileTooLarge> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for FileTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Compound file would grow to {} bytes, but version {} files are \
+             limited to {} bytes",
+            self.required,
+            self.version.number(),
+            self.max_len()
+        )?;
+        if !self.version.is_version_4() {
+            write!(f, " (use Version::V4 for larger files)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for FileTooLarge {}
+
+impl From<FileTooLarge> for io::Error {
+    fn from(too_large: FileTooLarge) -> io::Error {
+        io::Error::other(too_large)
+    }
+}
+
+//===========================================================================//
+
+/// The error payload reported when an operation needs a capability that only
+/// version 4 compound files have, such as a stream longer than 2 GiB (see
+/// [`Version::supports_large_streams`](enum.Version.html#method.supports_large_streams)),
+/// but the compound file is version 3.
+///
+/// This is returned wrapped in an `io::Error` (of kind `InvalidInput`); use
+/// [`from_io_error`](#method.from_io_error) to recognize it.  Nothing is
+/// changed when an operation fails this way.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RequiresVersion4 {
+    capability: &'static str,
+}
+
+impl RequiresVersion4 {
+    pub(crate) fn new(capability: &'static str) -> RequiresVersion4 {
+        RequiresVersion4 { capability }
+    }
+
+    /// Returns a short description of the capability that was requested,
+    /// such as `"streams longer than 2 GiB"`.
+    pub fn capability(&self) -> &'static str {
+        self.capability
+    }
+
+    /// Returns the `RequiresVersion4` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&RequiresVersion4> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for RequiresVersion4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Version 3 compound files don't support {} (use Version::V4)",
+            self.capability
+        )
+    }
+}
+
+impl Error for RequiresVersion4 {}
+
+impl From<RequiresVersion4> for io::Error {
+    fn from(requires: RequiresVersion4) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, requires)
+    }
+}
+
+//===========================================================================//
+
+/// The default limit on how much of a stream the convenience methods that
+/// read whole streams into memory will buffer (see
+/// [`CompoundFile::set_max_buffer_len`](../struct.CompoundFile.html#method.set_max_buffer_len)).
+pub const DEFAULT_MAX_BUFFER_LEN: usize = 1 << 30;
+
+/// The error payload reported when a method that reads a whole stream into
+/// memory (such as
+/// [`CompoundFile::read_stream_to_vec`](../struct.CompoundFile.html#method.read_stream_to_vec))
+/// is asked to buffer more than the limit set with
+/// [`CompoundFile::set_max_buffer_len`](../struct.CompoundFile.html#method.set_max_buffer_len).
+///
+/// This is returned wrapped in an `io::Error` (of kind `OutOfMemory`); use
+/// [`from_io_error`](#method.from_io_error) to recognize it.  The check is
+/// made before anything is allocated or read.  To process such a stream,
+/// read it incrementally through
+/// [`CompoundFile::open_stream`](../struct.CompoundFile.html#method.open_stream)
+/// instead, or raise the limit.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TooLargeToBuffer {
+    path: PathBuf,
+    len: u64,
+    cap: usize,
+}
+
+impl TooLargeToBuffer {
+    pub(crate) fn new(path: &Path, len: u64, cap: usize) -> TooLargeToBuffer {
+        debug_assert!(len > cap as u64);
+        TooLargeToBuffer { path: path.to_path_buf(), len, cap }
+    }
+
+    /// Returns the path of the stream that was to be buffered.  (This is
+    /// empty for data that doesn't belong to any stream, such as a stale
+    /// chain.)
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of bytes that would have been buffered.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the limit that was exceeded, in bytes.
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// Returns the `TooLargeToBuffer` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&TooLargeToBuffer> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for TooLargeToBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Reading {:?} would buffer {} bytes, but at most {} may be \
+             buffered (use open_stream to read it incrementally)",
+            self.path, self.len, self.cap
+        )
+    }
+}
+
+impl Error for TooLargeToBuffer {}
+
+impl From<TooLargeToBuffer> for io::Error {
+    fn from(too_large: TooLargeToBuffer) -> io::Error {
+        io::Error::new(io::ErrorKind::OutOfMemory, too_large)
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{FileTooLarge, RequiresVersion4};
+    use crate::internal::Version;
+    use std::io;
+
+    #[test]
+    fn round_trip_through_io_error() {
+        let error = io::Error::from(FileTooLarge::new(Version::V3, 1 << 32));
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+        let too_large = FileTooLarge::from_io_error(&error).unwrap();
+        assert_eq!(too_large.version(), Version::V3);
+        assert_eq!(too_large.max_len(), 1 << 31);
+        assert_eq!(too_large.required(), 1 << 32);
+        assert!(error.to_string().contains("Version::V4"));
+
+        let other = io::Error::other("too large");
+        assert!(FileTooLarge::from_io_error(&other).is_none());
+    }
+
+    #[test]
+    fn requires_version_4_round_trip() {
+        let error = io::Error::from(RequiresVersion4::new("large streams"));
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let requires = RequiresVersion4::from_io_error(&error).unwrap();
+        assert_eq!(requires.capability(), "large streams");
+        assert!(error.to_string().contains("Version::V4"));
+        assert!(FileTooLarge::from_io_error(&error).is_none());
+    }
+}
+
+//===========================================================================//
@@ -33,6 +33,17 @@ macro_rules! invalid_input {
     };
 }
 
+macro_rules! out_of_memory {
+    ($e:expr) => {
+        return Err(::std::io::Error::new(::std::io::ErrorKind::OutOfMemory,
+                                         $e))
+    };
+    ($fmt:expr, $($arg:tt)+) => {
+        return Err(::std::io::Error::new(::std::io::ErrorKind::OutOfMemory,
+                                         format!($fmt, $($arg)+)))
+    };
+}
+
 macro_rules! not_found {
     ($e:expr) => {
         return Err(::std::io::Error::new(::std::io::ErrorKind::NotFound, $e))
@@ -0,0 +1,71 @@
+use std::io;
+use std::mem::size_of;
+
+//===========================================================================//
+
+/// Reserves room for `additional` more elements in `vec`, returning an
+/// `OutOfMemory` error (rather than aborting) if the memory can't be
+/// allocated.  This should be used for allocations whose size comes from a
+/// file's metadata, since a malformed file can claim any size it likes.
+/// `what` describes the data, for the error message.
+pub fn try_reserve<T>(
+    vec: &mut Vec<T>,
+    additional: usize,
+    what: &str,
+) -> io::Result<()> {
+    if vec.try_reserve(additional).is_err() {
+        out_of_memory!(
+            "Cannot allocate {} bytes for {}",
+            additional.saturating_mul(size_of::<T>()),
+            what
+        );
+    }
+    Ok(())
+}
+
+/// Returns an empty vector with room for `capacity` elements, or an
+/// `OutOfMemory` error if the memory can't be allocated (see `try_reserve`).
+pub fn try_vec_with_capacity<T>(
+    capacity: usize,
+    what: &str,
+) -> io::Result<Vec<T>> {
+    let mut vec = Vec::new();
+    try_reserve(&mut vec, capacity, what)?;
+    Ok(vec)
+}
+
+/// Returns a vector of `len` zero bytes, or an `OutOfMemory` error if the
+/// memory can't be allocated (see `try_reserve`).
+pub fn try_zeroed_vec(len: usize, what: &str) -> io::Result<Vec<u8>> {
+    let mut vec = try_vec_with_capacity(len, what)?;
+    vec.resize(len, 0);
+    Ok(vec)
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{try_reserve, try_vec_with_capacity, try_zeroed_vec};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn small_allocations_succeed() {
+        let vec = try_vec_with_capacity::<u32>(100, "test").unwrap();
+        assert!(vec.capacity() >= 100);
+        assert_eq!(try_zeroed_vec(10, "test").unwrap(), vec![0; 10]);
+    }
+
+    #[test]
+    fn impossible_allocations_fail_cleanly() {
+        let mut vec = vec![0u64];
+        let error =
+            try_reserve(&mut vec, usize::MAX / 4, "the FAT").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+        assert!(error.to_string().contains("the FAT"), "{}", error);
+        let error = try_zeroed_vec(usize::MAX, "stream data").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    }
+}
+
+//===========================================================================//
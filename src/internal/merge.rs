@@ -0,0 +1,271 @@
+use crate::internal::{self, MetadataFields};
+use crate::CompoundFile;
+use std::io::{self, Read, Seek, Write};
+use std::path::PathBuf;
+
+//===========================================================================//
+
+/// Options for [`merge`](fn.merge.html).
+///
+/// ```
+/// use cfb::MergeOptions;
+///
+/// let options = MergeOptions::new().dedup(true).root_metadata(false);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    pub(crate) dedup: bool,
+    pub(crate) root_metadata: bool,
+}
+
+impl MergeOptions {
+    /// Returns the default options: identical streams are stored once, and
+    /// each source's root metadata is given to its storage.
+    pub fn new() -> MergeOptions {
+        MergeOptions::default()
+    }
+
+    /// If true, a stream whose contents are identical to those of a stream
+    /// already merged (from any source, or written earlier with
+    /// [`CompoundFile::create_stream_dedup`](struct.CompoundFile.html#method.create_stream_dedup))
+    /// shares that stream's sector chain instead of storing another copy.
+    /// If false, every stream gets its own copy.  Defaults to true.
+    pub fn dedup(mut self, dedup: bool) -> MergeOptions {
+        self.dedup = dedup;
+        self
+    }
+
+    /// If true, each source's root CLSID, state bits, and modification time
+    /// are given to the storage it is merged into.  If false, the storages
+    /// are left with the metadata they are created with.  Defaults to true.
+    pub fn root_metadata(mut self, copy: bool) -> MergeOptions {
+        self.root_metadata = copy;
+        self
+    }
+}
+
+impl Default for MergeOptions {
+    fn default() -> MergeOptions {
+        MergeOptions { dedup: true, root_metadata: true }
+    }
+}
+
+//===========================================================================//
+
+/// What [`merge`](fn.merge.html) did with one source.
+#[derive(Clone, Debug, Default)]
+pub struct MergeSourceReport {
+    pub(crate) key: String,
+    pub(crate) num_storages: u64,
+    pub(crate) num_streams: u64,
+    pub(crate) num_shared_streams: u64,
+    pub(crate) num_bytes: u64,
+    pub(crate) num_bytes_saved: u64,
+}
+
+impl MergeSourceReport {
+    /// Returns the key that the source was given, which names the storage
+    /// it was merged into.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the path of the storage that the source was merged into.
+    pub fn path(&self) -> PathBuf {
+        internal::path::path_from_name_chain(&[self.key.as_str()])
+    }
+
+    /// Returns the number of storages copied from the source, not counting
+    /// its root.
+    pub fn num_storages(&self) -> u64 {
+        self.num_storages
+    }
+
+    /// Returns the number of streams copied from the source.
+    pub fn num_streams(&self) -> u64 {
+        self.num_streams
+    }
+
+    /// Returns the number of the source's streams that share a chain with
+    /// an identical stream merged before them, rather than storing their
+    /// own copy.
+    pub fn num_shared_streams(&self) -> u64 {
+        self.num_shared_streams
+    }
+
+    /// Returns the total length of the source's streams, in bytes.
+    pub fn num_bytes(&self) -> u64 {
+        self.num_bytes
+    }
+
+    /// Returns the total length of the source's streams that share a chain
+    /// with an identical stream, and so weren't stored again.
+    pub fn num_bytes_saved(&self) -> u64 {
+        self.num_bytes_saved
+    }
+}
+
+/// The result of [`merge`](fn.merge.html).
+#[derive(Clone, Debug, Default)]
+pub struct MergeReport {
+    pub(crate) sources: Vec<MergeSourceReport>,
+}
+
+impl MergeReport {
+    /// Returns a report for each source, in the order they were merged.
+    pub fn sources(&self) -> &[MergeSourceReport] {
+        &self.sources
+    }
+
+    /// Returns the total number of streams copied, across all sources.
+    pub fn num_streams(&self) -> u64 {
+        self.sources.iter().map(MergeSourceReport::num_streams).sum()
+    }
+
+    /// Returns the total length of the streams copied, in bytes.
+    pub fn num_bytes(&self) -> u64 {
+        self.sources.iter().map(MergeSourceReport::num_bytes).sum()
+    }
+
+    /// Returns the total length of the streams that weren't stored again,
+    /// because they share a chain with an identical stream.
+    pub fn num_bytes_saved(&self) -> u64 {
+        self.sources.iter().map(MergeSourceReport::num_bytes_saved).sum()
+    }
+}
+
+//===========================================================================//
+
+/// Combines many compound files into one container, with each source's
+/// contents copied into a storage (directly within `dest`'s root) named by
+/// the key it is paired with, as when archiving a large number of similar
+/// documents together.
+///
+/// With [`MergeOptions::dedup`] (the default), streams whose contents are
+/// identical, whether within one source or across several, are stored only
+/// once: each later copy is an ordinary directory entry that shares the
+/// first copy's sector chain, as with
+/// [`CompoundFile::create_stream_dedup`](struct.CompoundFile.html#method.create_stream_dedup).
+/// This keeps every source's tree exactly as it was, so any reader sees
+/// ordinary streams, and nothing else in the file needs to know about the
+/// deduplication.  The one caveat is for other libraries that modify the
+/// container in place: one that doesn't expect shared chains may free or
+/// overwrite a chain that another stream still uses.  (This crate reports
+/// shared chains as
+/// [`ValidationIssueKind::SharedChain`](enum.ValidationIssueKind.html#variant.SharedChain),
+/// and gives a stream its own copy of the data before changing it.)
+///
+/// Each stream is read into memory whole to be compared, so streams longer
+/// than the source's
+/// [`max_buffer_len`](struct.CompoundFile.html#method.max_buffer_len) fail
+/// the merge.  Temporary objects are not copied.  A key that isn't a valid
+/// object name, or that names an object already in `dest` (such as the key
+/// of an earlier source), fails with an error before anything from that
+/// source is copied; sources merged before it are kept.
+///
+/// ```
+/// use cfb::{CompoundFile, Version};
+/// use std::io::{Cursor, Write};
+///
+/// let mut sources = Vec::new();
+/// for index in 0..3 {
+///     let cursor = Cursor::new(Vec::new());
+///     let mut comp = CompoundFile::create_with_version(Version::V3, cursor)?;
+///     comp.create_stream("/Boilerplate")?.write_all(&[7; 10000])?;
+///     comp.create_stream("/Body")?.write_all(format!("#{}", index).as_bytes())?;
+///     sources.push((format!("report{}", index), comp));
+/// }
+/// let cursor = Cursor::new(Vec::new());
+/// let mut dest = CompoundFile::create_with_version(Version::V3, cursor)?;
+/// let report = cfb::merge(sources, &mut dest, cfb::MergeOptions::new())?;
+/// assert_eq!(report.num_bytes_saved(), 20000);
+/// assert!(dest.is_stream("/report2/Boilerplate"));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn merge<I, R, W>(
+    sources: I,
+    dest: &mut CompoundFile<W>,
+    options: MergeOptions,
+) -> io::Result<MergeReport>
+where
+    I: IntoIterator<Item = (String, CompoundFile<R>)>,
+    R: Read + Seek,
+    W: Read + Write + Seek,
+{
+    let mut report = MergeReport::default();
+    for (key, mut source) in sources {
+        let result = merge_source(&key, &mut source, dest, &options);
+        dest.self_check("merge");
+        report.sources.push(result?);
+    }
+    Ok(report)
+}
+
+fn merge_source<R, W>(
+    key: &str,
+    source: &mut CompoundFile<R>,
+    dest: &mut CompoundFile<W>,
+    options: &MergeOptions,
+) -> io::Result<MergeSourceReport>
+where
+    R: Read + Seek,
+    W: Read + Write + Seek,
+{
+    internal::path::validate_name(key)?;
+    let base = internal::path::path_from_name_chain(&[key]);
+    if dest.exists(&base) {
+        already_exists!(
+            "Merge key {:?} is already used in the container",
+            key
+        );
+    }
+    let mut report =
+        MergeSourceReport { key: key.to_string(), ..Default::default() };
+    let entries: Vec<_> = source.walk_relative("/")?.collect();
+    for (relative, entry) in entries {
+        let path = base.join(&relative);
+        if entry.is_root() {
+            dest.create_storage_with_path(&path)?;
+            if options.root_metadata {
+                let fields = MetadataFields::CLSID
+                    | MetadataFields::STATE_BITS
+                    | MetadataFields::MODIFIED;
+                dest.copy_metadata_from_entry_with_path(
+                    &entry, &path, fields,
+                )?;
+            }
+        } else if entry.is_storage() {
+            dest.create_storage_with_path(&path)?;
+            dest.copy_metadata_from_entry_with_path(
+                &entry,
+                &path,
+                MetadataFields::ALL,
+            )?;
+            report.num_storages += 1;
+        } else {
+            let data = source.read_stream_to_vec(entry.path())?;
+            let shared = if options.dedup && !data.is_empty() {
+                dest.create_stream_dedup_with_path(&path, &data)?
+            } else {
+                let mut stream = dest.create_stream_with_path(&path, false)?;
+                stream.write_all(&data)?;
+                stream.flush()?;
+                false
+            };
+            dest.copy_metadata_from_entry_with_path(
+                &entry,
+                &path,
+                MetadataFields::STATE_BITS,
+            )?;
+            report.num_streams += 1;
+            report.num_bytes += data.len() as u64;
+            if shared {
+                report.num_shared_streams += 1;
+                report.num_bytes_saved += data.len() as u64;
+            }
+        }
+    }
+    Ok(report)
+}
+
+//===========================================================================//
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+//===========================================================================//
+
+/// A set of metadata fields of a directory entry, for selecting which ones
+/// [`CompoundFile::copy_metadata`](../struct.CompoundFile.html#method.copy_metadata)
+/// copies.  Sets can be combined with `|`.
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+pub struct MetadataFields(u8);
+
+impl MetadataFields {
+    /// The CLSID.  Only storages (including the root) have one.
+    pub const CLSID: MetadataFields = MetadataFields(1 << 0);
+    /// The user-defined state bits.
+    pub const STATE_BITS: MetadataFields = MetadataFields(1 << 1);
+    /// The creation time.
+    pub const CREATED: MetadataFields = MetadataFields(1 << 2);
+    /// The modification time.
+    pub const MODIFIED: MetadataFields = MetadataFields(1 << 3);
+    /// Both the creation and the modification time.
+    pub const TIMES: MetadataFields =
+        MetadataFields(MetadataFields::CREATED.0 | MetadataFields::MODIFIED.0);
+    /// Every field.
+    pub const ALL: MetadataFields = MetadataFields(0b1111);
+
+    const NAMES: [(MetadataFields, &'static str); 4] = [
+        (MetadataFields::CLSID, "CLSID"),
+        (MetadataFields::STATE_BITS, "STATE_BITS"),
+        (MetadataFields::CREATED, "CREATED"),
+        (MetadataFields::MODIFIED, "MODIFIED"),
+    ];
+
+    /// Returns the empty set.
+    pub const fn empty() -> MetadataFields {
+        MetadataFields(0)
+    }
+
+    /// Returns true if no fields are selected.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if every field in `other` is also in `self`.
+    pub const fn contains(self, other: MetadataFields) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for MetadataFields {
+    type Output = MetadataFields;
+
+    fn bitor(self, other: MetadataFields) -> MetadataFields {
+        MetadataFields(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for MetadataFields {
+    fn bitor_assign(&mut self, other: MetadataFields) {
+        self.0 |= other.0;
+    }
+}
+
+impl fmt::Debug for MetadataFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = MetadataFields::NAMES
+            .iter()
+            .filter(|&&(field, _)| self.contains(field))
+            .map(|&(_, name)| name)
+            .collect();
+        if names.is_empty() {
+            f.write_str("(empty)")
+        } else {
+            f.write_str(&names.join(" | "))
+        }
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataFields;
+
+    #[test]
+    fn combine_fields() {
+        let mut fields = MetadataFields::CLSID | MetadataFields::CREATED;
+        assert!(fields.contains(MetadataFields::CLSID));
+        assert!(!fields.contains(MetadataFields::TIMES));
+        fields |= MetadataFields::MODIFIED;
+        assert!(fields.contains(MetadataFields::TIMES));
+        assert_eq!(fields | MetadataFields::STATE_BITS, MetadataFields::ALL);
+        assert!(MetadataFields::empty().is_empty());
+        assert!(MetadataFields::ALL.contains(MetadataFields::empty()));
+    }
+
+    #[test]
+    fn debug_lists_field_names() {
+        let fields = MetadataFields::CLSID | MetadataFields::MODIFIED;
+        assert_eq!(format!("{:?}", fields), "CLSID | MODIFIED");
+        assert_eq!(format!("{:?}", MetadataFields::empty()), "(empty)");
+    }
+}
+
+//===========================================================================//
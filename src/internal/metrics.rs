@@ -0,0 +1,307 @@
+#[cfg(feature = "metrics")]
+use std::convert::TryFrom;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+#[cfg(feature = "metrics")]
+use std::time::{Duration, Instant};
+
+//===========================================================================//
+
+/// An operation whose cost is reported to a [`MetricsSink`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Op {
+    /// Opening an existing compound file, including validating it.  The byte
+    /// count is the length of the file.
+    Open,
+    /// Opening a stream within a compound file.  The byte count is the
+    /// length of the stream.
+    OpenStream,
+    /// A single read from the sectors of the underlying file.  The byte
+    /// count is the number of bytes read.
+    ReadSectors,
+    /// A single write to the sectors of the underlying file.  The byte count
+    /// is the number of bytes written.
+    WriteSectors,
+    /// Flushing a compound file.  The byte count is always zero; the writes
+    /// made while flushing are reported as `WriteSectors`.
+    Flush,
+    /// Writing a stream handle's buffered data to the underlying file early,
+    /// to keep the total buffered data within the budget set with
+    /// [`CompoundFile::set_dirty_budget`](../struct.CompoundFile.html#method.set_dirty_budget).
+    /// The byte count is the number of buffered bytes written.
+    EarlyFlush,
+    /// Checking the structure of a compound file while opening it (this time
+    /// is also included in `Open`).  The byte count is always zero.
+    Validate,
+}
+
+impl Op {
+    /// All operations, in declaration order.
+    #[cfg(feature = "metrics")]
+    pub const ALL: [Op; 7] = [
+        Op::Open,
+        Op::OpenStream,
+        Op::ReadSectors,
+        Op::WriteSectors,
+        Op::Flush,
+        Op::EarlyFlush,
+        Op::Validate,
+    ];
+
+    #[cfg(feature = "metrics")]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+//===========================================================================//
+
+/// Receives a report of each operation performed on a compound file, as
+/// installed with
+/// [`CompoundFile::set_metrics_sink`](../struct.CompoundFile.html#method.set_metrics_sink).
+///
+/// The callback is made synchronously on the thread performing the
+/// operation, so it should be cheap; [`AtomicMetrics`] just adds to a few
+/// counters.
+#[cfg(feature = "metrics")]
+pub trait MetricsSink: Send + Sync {
+    /// Called when an operation completes successfully, with how long it
+    /// took and how many bytes it handled (see [`Op`] for what the byte
+    /// count means for each operation).
+    fn operation_completed(&self, op: Op, duration: Duration, bytes: u64);
+}
+
+#[cfg(feature = "metrics")]
+impl<T: MetricsSink + ?Sized> MetricsSink for Arc<T> {
+    fn operation_completed(&self, op: Op, duration: Duration, bytes: u64) {
+        (**self).operation_completed(op, duration, bytes);
+    }
+}
+
+//===========================================================================//
+
+/// The totals recorded by an [`AtomicMetrics`] sink for one kind of
+/// operation.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OpTotals {
+    /// The number of operations completed.
+    pub count: u64,
+    /// The total time taken by those operations.
+    pub duration: Duration,
+    /// The total number of bytes handled by those operations.
+    pub bytes: u64,
+}
+
+/// A [`MetricsSink`] that adds up the count, duration, and bytes of each
+/// kind of operation using atomic counters, so that it can be shared (e.g.
+/// in an `Arc`) between the compound files being measured and whatever
+/// scrapes the totals.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub struct AtomicMetrics {
+    counters: [OpCounters; Op::ALL.len()],
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+struct OpCounters {
+    count: AtomicU64,
+    nanos: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[cfg(feature = "metrics")]
+impl AtomicMetrics {
+    /// Creates a sink with all totals at zero.
+    pub fn new() -> AtomicMetrics {
+        AtomicMetrics::default()
+    }
+
+    /// Returns the totals recorded so far for the given operation.
+    pub fn totals(&self, op: Op) -> OpTotals {
+        let counters = &self.counters[op.index()];
+        OpTotals {
+            count: counters.count.load(Ordering::Relaxed),
+            duration: Duration::from_nanos(
+                counters.nanos.load(Ordering::Relaxed),
+            ),
+            bytes: counters.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Resets all totals to zero.
+    pub fn reset(&self) {
+        for counters in self.counters.iter() {
+            counters.count.store(0, Ordering::Relaxed);
+            counters.nanos.store(0, Ordering::Relaxed);
+            counters.bytes.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsSink for AtomicMetrics {
+    fn operation_completed(&self, op: Op, duration: Duration, bytes: u64) {
+        let counters = &self.counters[op.index()];
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        counters.count.fetch_add(1, Ordering::Relaxed);
+        counters.nanos.fetch_add(nanos, Ordering::Relaxed);
+        counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+//===========================================================================//
+
+/// When an operation being measured started (or nothing, if no one is
+/// listening or the `metrics` feature is disabled).
+#[derive(Clone, Copy)]
+pub struct Timer {
+    #[cfg(feature = "metrics")]
+    start: Option<Instant>,
+}
+
+impl Timer {
+    /// Starts timing an operation whose cost will be reported later with
+    /// `Metrics::defer`, before there is anywhere to report it.
+    #[inline]
+    pub fn now() -> Timer {
+        Timer {
+            #[cfg(feature = "metrics")]
+            start: Some(Instant::now()),
+        }
+    }
+
+    /// Stops timing, returning how long the operation took.
+    #[inline]
+    pub fn stop(self) -> Span {
+        Span {
+            #[cfg(feature = "metrics")]
+            duration: self.start.map(|start| start.elapsed()),
+        }
+    }
+}
+
+/// How long an operation took (or nothing, if it wasn't timed).
+#[derive(Clone, Copy)]
+pub struct Span {
+    #[cfg(feature = "metrics")]
+    duration: Option<Duration>,
+}
+
+impl std::ops::AddAssign for Span {
+    #[inline]
+    fn add_assign(&mut self, other: Span) {
+        #[cfg(feature = "metrics")]
+        {
+            self.duration = match (self.duration, other.duration) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            };
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = other;
+    }
+}
+
+/// Where a compound file reports its operations.  Without the `metrics`
+/// feature this is empty and all of its methods do nothing.
+#[derive(Default)]
+pub struct Metrics {
+    #[cfg(feature = "metrics")]
+    sink: Option<Arc<dyn MetricsSink>>,
+    /// Operations completed before a sink was installed (only those made
+    /// while opening the file), to be reported once one is.
+    #[cfg(feature = "metrics")]
+    pending: Vec<(Op, Duration, u64)>,
+}
+
+impl Metrics {
+    /// Starts timing an operation, if a sink is installed.
+    #[inline]
+    pub fn start(&self) -> Timer {
+        Timer {
+            #[cfg(feature = "metrics")]
+            start: self.sink.as_ref().map(|_| Instant::now()),
+        }
+    }
+
+    /// Reports an operation to the sink, if one is installed.
+    #[inline]
+    pub fn record(&self, op: Op, timer: Timer, bytes: u64) {
+        #[cfg(feature = "metrics")]
+        if let (Some(sink), Some(duration)) =
+            (&self.sink, timer.stop().duration)
+        {
+            sink.operation_completed(op, duration, bytes);
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = (op, timer, bytes);
+    }
+
+    /// Holds on to an operation until a sink is installed.
+    #[inline]
+    pub fn defer(&mut self, op: Op, span: Span, bytes: u64) {
+        #[cfg(feature = "metrics")]
+        if let Some(duration) = span.duration {
+            self.pending.push((op, duration, bytes));
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = (op, span, bytes);
+    }
+
+    /// Installs a sink, first reporting to it any deferred operations.
+    #[cfg(feature = "metrics")]
+    pub fn set_sink(&mut self, sink: Arc<dyn MetricsSink>) {
+        for (op, duration, bytes) in self.pending.drain(..) {
+            sink.operation_completed(op, duration, bytes);
+        }
+        self.sink = Some(sink);
+    }
+}
+
+//===========================================================================//
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::{AtomicMetrics, Metrics, MetricsSink, Op, Timer};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn atomic_metrics_adds_up_each_op() {
+        let metrics = AtomicMetrics::new();
+        metrics.operation_completed(Op::Flush, Duration::from_micros(3), 0);
+        metrics.operation_completed(Op::Flush, Duration::from_micros(4), 0);
+        metrics.operation_completed(Op::ReadSectors, Duration::ZERO, 512);
+        let flush = metrics.totals(Op::Flush);
+        assert_eq!(flush.count, 2);
+        assert_eq!(flush.duration, Duration::from_micros(7));
+        assert_eq!(metrics.totals(Op::ReadSectors).bytes, 512);
+        assert_eq!(metrics.totals(Op::Open).count, 0);
+        metrics.reset();
+        assert_eq!(metrics.totals(Op::Flush).count, 0);
+    }
+
+    #[test]
+    fn deferred_ops_are_replayed_when_sink_is_installed() {
+        let mut metrics = Metrics::default();
+        metrics.record(Op::Flush, metrics.start(), 0);
+        let mut span = Timer::now().stop();
+        span += Timer::now().stop();
+        metrics.defer(Op::Validate, span, 0);
+        metrics.defer(Op::Open, Timer::now().stop(), 100);
+        let sink = Arc::new(AtomicMetrics::new());
+        metrics.set_sink(sink.clone());
+        assert_eq!(sink.totals(Op::Validate).count, 1);
+        assert_eq!(sink.totals(Op::Open).bytes, 100);
+        assert_eq!(sink.totals(Op::Flush).count, 0);
+        metrics.record(Op::Flush, metrics.start(), 0);
+        assert_eq!(sink.totals(Op::Flush).count, 1);
+    }
+}
+
+//===========================================================================//
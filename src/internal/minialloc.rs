@@ -1,11 +1,17 @@
-use std::io::{self, Seek, SeekFrom, Write};
+use std::collections::BTreeSet;
+use std::hash::Hasher;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::mem::size_of;
+use std::path::{Path, PathBuf};
 
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 
 use crate::internal::{
-    consts, Chain, DirEntry, Directory, MiniChain, ObjType, Sector,
-    SectorInit, Validation, Version,
+    alloc, consts, next_in_chain, try_zeroed_vec, AuditLog, AuditOp, Chain,
+    ChainName, DeletedEntry, DirEntry, Directory, FreeEntryPolicy, Metrics,
+    MiniChain, ObjType, Reachability, ReadAt, ReadOnly, RecoveryWarning,
+    RecoveryWarningKind, Sector, SectorAllocator, SectorInit, Stats,
+    Validation, ValidationIssue, ValidationIssueKind, Version,
 };
 use crate::WriteLeNumber;
 
@@ -18,6 +24,26 @@ macro_rules! malformed {
     };
 }
 
+/// The maximum number of bytes that `read_streams` will read at once.
+const MAX_READ_SPAN_LEN: u64 = 1 << 20;
+
+/// How many incremental self-checks to run between checks of the whole file.
+const SWEEP_INTERVAL: u32 = 100;
+
+//===========================================================================//
+
+/// A stream whose length, as found when the file was opened, is more than
+/// its chain can hold.  This only applies for as long as the stream's
+/// directory entry still has the same generation, starting sector, and
+/// length; once the stream is removed or rewritten, its chain is whatever
+/// the writes made it.
+struct ShortStream {
+    generation: u64,
+    start_sector: u32,
+    stream_len: u64,
+    readable_len: u64,
+}
+
 //===========================================================================//
 
 /// A wrapper around the directory manager that additionally provides
@@ -26,6 +52,27 @@ pub struct MiniAllocator<F> {
     directory: Directory<F>,
     minifat: Vec<u32>,
     minifat_start_sector: u32,
+    mini_sector_len: usize,
+    free_mini_sectors: BTreeSet<u32>,
+    has_reservations: bool,
+    show_temporaries: bool,
+    dirty_bytes: u64,
+    dirty_budget: u64,
+    /// The number of bytes of buffered writes held by open stream handles,
+    /// for each stream (by stream ID and directory entry generation) that
+    /// has any.
+    dirty_streams: FnvHashMap<(u32, u64), usize>,
+    shared_chains: FnvHashMap<(bool, u32), u32>,
+    content_index: FnvHashMap<u64, Vec<u32>>,
+    short_streams: FnvHashMap<u32, ShortStream>,
+    /// Set for files opened with `CompoundFile::open_recover`, whose streams
+    /// report their readable lengths as their lengths.
+    recovered: bool,
+    audit: Option<AuditLog>,
+    /// The MiniFAT entries changed since the last self-check, if self-checks
+    /// are enabled.
+    touched_mini_sectors: Option<FnvHashSet<u32>>,
+    checks_since_sweep: u32,
 }
 
 impl<F> MiniAllocator<F> {
@@ -33,23 +80,437 @@ impl<F> MiniAllocator<F> {
         directory: Directory<F>,
         minifat: Vec<u32>,
         minifat_start_sector: u32,
+        mini_sector_shift: u16,
         validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
     ) -> io::Result<MiniAllocator<F>> {
-        let mut minialloc =
-            MiniAllocator { directory, minifat, minifat_start_sector };
-        minialloc.validate(validation)?;
+        let mut minialloc = MiniAllocator {
+            directory,
+            minifat,
+            minifat_start_sector,
+            mini_sector_len: 1 << mini_sector_shift,
+            free_mini_sectors: BTreeSet::new(),
+            has_reservations: false,
+            show_temporaries: false,
+            dirty_bytes: 0,
+            dirty_budget: u64::MAX,
+            dirty_streams: FnvHashMap::default(),
+            shared_chains: FnvHashMap::default(),
+            content_index: FnvHashMap::default(),
+            short_streams: FnvHashMap::default(),
+            recovered: false,
+            audit: None,
+            touched_mini_sectors: None,
+            checks_since_sweep: 0,
+        };
+        minialloc.validate(validation, issues)?;
+        minialloc.free_mini_sectors = alloc::free_indices(&minialloc.minifat);
+        minialloc.shared_chains = minialloc.count_shared_chains();
+        minialloc.short_streams = minialloc.find_short_streams(issues);
+        minialloc.report_hidden_chains(issues);
         Ok(minialloc)
     }
 
+    /// Returns the key identifying the chain (mini or regular) that holds
+    /// the given stream's data, or `None` if the stream has no chain.
+    pub fn chain_key(dir_entry: &DirEntry) -> Option<(bool, u32)> {
+        if dir_entry.obj_type != ObjType::Stream
+            || dir_entry.start_sector == consts::END_OF_CHAIN
+            || dir_entry.stream_len == 0
+        {
+            return None;
+        }
+        let is_mini = dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64;
+        Some((is_mini, dir_entry.start_sector))
+    }
+
+    /// Counts, for each chain referenced by more than one stream entry, how
+    /// many stream entries reference it.
+    fn count_shared_chains(&self) -> FnvHashMap<(bool, u32), u32> {
+        let mut counts = FnvHashMap::<(bool, u32), u32>::default();
+        for dir_entry in self.directory.dir_entries() {
+            if let Some(key) = MiniAllocator::<F>::chain_key(dir_entry) {
+                *counts.entry(key).or_insert(0) += 1;
+            }
+        }
+        counts.retain(|_, &mut count| count > 1);
+        counts
+    }
+
+    /// Finds the streams whose lengths are more than their chains can hold,
+    /// adding a validation issue for each.  Streams whose chains are broken
+    /// are skipped, since reading them fails anyway.
+    fn find_short_streams(
+        &self,
+        issues: &mut Vec<ValidationIssue>,
+    ) -> FnvHashMap<u32, ShortStream> {
+        let allocator = self.directory.allocator();
+        let mut short_streams = FnvHashMap::default();
+        for (stream_id, dir_entry) in
+            self.directory.dir_entries().iter().enumerate()
+        {
+            if dir_entry.obj_type != ObjType::Stream
+                || dir_entry.stream_len == 0
+            {
+                continue;
+            }
+            let stream_id = stream_id as u32;
+            let is_mini =
+                dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64;
+            let capacity = match MiniAllocator::<F>::chain_key(dir_entry) {
+                // A nonempty stream with no chain at all.
+                None => 0,
+                Some((true, start_sector)) => {
+                    let chain = ChainName::MiniStartingAt(start_sector);
+                    match self.mini_chain_sector_ids(start_sector, chain) {
+                        Ok(ids) => {
+                            ids.len() as u64 * self.mini_sector_len as u64
+                        }
+                        Err(_) => continue,
+                    }
+                }
+                Some((false, start_sector)) => {
+                    let chain = ChainName::StartingAt(start_sector);
+                    match allocator.chain_sector_ids(start_sector, chain) {
+                        Ok(ids) => {
+                            ids.len() as u64 * allocator.sector_len() as u64
+                        }
+                        Err(_) => continue,
+                    }
+                }
+            };
+            if capacity >= dir_entry.stream_len {
+                continue;
+            }
+            let Some(generation) = self.directory.generation(stream_id) else {
+                continue;
+            };
+            let name = match self.directory.path_for_stream_id(stream_id) {
+                Some(path) => format!("Stream {:?}", path),
+                None => format!("Unreachable stream entry {}", stream_id),
+            };
+            issues.push(
+                ValidationIssue::new(
+                    ValidationIssueKind::StreamLongerThanChain,
+                    format!(
+                        "{} has length {}, but its {} chain holds only {} \
+                         bytes",
+                        name,
+                        dir_entry.stream_len,
+                        if is_mini { "mini sector" } else { "sector" },
+                        capacity
+                    ),
+                )
+                .with_stream_id(stream_id),
+            );
+            short_streams.insert(
+                stream_id,
+                ShortStream {
+                    generation,
+                    start_sector: dir_entry.start_sector,
+                    stream_len: dir_entry.stream_len,
+                    readable_len: capacity,
+                },
+            );
+        }
+        short_streams
+    }
+
+    /// Returns how many bytes of the given stream can actually be read: its
+    /// length, unless its chain holds less than that (see
+    /// `find_short_streams`).
+    pub fn readable_len(&self, stream_id: u32) -> u64 {
+        let dir_entry = self.dir_entry(stream_id);
+        match self.short_streams.get(&stream_id) {
+            Some(short)
+                if Some(short.generation)
+                    == self.directory.generation(stream_id)
+                    && short.start_sector == dir_entry.start_sector
+                    && short.stream_len == dir_entry.stream_len =>
+            {
+                short.readable_len
+            }
+            _ => dir_entry.stream_len,
+        }
+    }
+
+    /// Returns how many bytes the sector chain of the given stream holds,
+    /// whatever its declared length: the number of sectors in the chain
+    /// times the sector size, or mini sectors times the mini sector size for
+    /// a stream in the mini stream.  (Which of the two it is still depends
+    /// on the declared length.)  This is zero for a stream with no chain.
+    pub fn chain_len(&self, stream_id: u32) -> io::Result<u64> {
+        let dir_entry = self.dir_entry(stream_id);
+        let start_sector = dir_entry.start_sector;
+        if dir_entry.obj_type != ObjType::Stream
+            || start_sector > consts::MAX_REGULAR_SECTOR
+        {
+            return Ok(0);
+        }
+        if dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64 {
+            let chain = ChainName::MiniStartingAt(start_sector);
+            let sector_ids =
+                self.mini_chain_sector_ids(start_sector, chain)?;
+            Ok(sector_ids.len() as u64 * self.mini_sector_len as u64)
+        } else {
+            let allocator = self.directory.allocator();
+            let chain = ChainName::StartingAt(start_sector);
+            let sector_ids =
+                allocator.chain_sector_ids(start_sector, chain)?;
+            Ok(sector_ids.len() as u64 * allocator.sector_len() as u64)
+        }
+    }
+
+    /// Returns the length that the given stream reports: its readable length
+    /// for a recovered file, and its declared length otherwise.
+    pub fn reported_len(&self, stream_id: u32) -> u64 {
+        if self.recovered {
+            self.readable_len(stream_id)
+        } else {
+            self.dir_entry(stream_id).stream_len
+        }
+    }
+
+    /// Marks this as a file opened with `CompoundFile::open_recover`: its
+    /// streams report their readable lengths, and it can't be modified.  Adds
+    /// a warning for each stream in the tree that is shorter than it claims
+    /// to be, and fills in the path of each warning about an object that is
+    /// still in the tree.
+    pub fn finish_recovery(&mut self, warnings: &mut Vec<RecoveryWarning>) {
+        for stream_id in 0..self.num_dir_entries() {
+            let dir_entry = self.dir_entry(stream_id);
+            if dir_entry.obj_type != ObjType::Stream
+                || self.parent_id(stream_id).is_none()
+            {
+                continue;
+            }
+            let readable_len = self.readable_len(stream_id);
+            if readable_len < dir_entry.stream_len {
+                warnings.push(
+                    RecoveryWarning::new(
+                        RecoveryWarningKind::StreamTruncated,
+                        format!(
+                            "Stream {:?} has length {}, but only {} bytes of \
+                             it could be salvaged",
+                            dir_entry.name, dir_entry.stream_len, readable_len
+                        ),
+                    )
+                    .with_stream_id(stream_id),
+                );
+            }
+        }
+        for warning in warnings.iter_mut() {
+            if warning.path().is_some() {
+                continue;
+            }
+            let Some(stream_id) = warning.stream_id() else {
+                continue;
+            };
+            let stream_id = stream_id.value();
+            if stream_id == consts::ROOT_STREAM_ID
+                || self.parent_id(stream_id).is_some()
+            {
+                if let Some(path) =
+                    self.directory.path_for_stream_id(stream_id)
+                {
+                    warning.set_path(path);
+                }
+            }
+        }
+        self.recovered = true;
+        self.directory.set_read_only(ReadOnly::Recovered);
+    }
+
+    /// Returns true if the given stream's chain is also referenced by at
+    /// least one other stream entry.
+    pub fn is_shared(&self, stream_id: u32) -> bool {
+        MiniAllocator::<F>::chain_key(self.dir_entry(stream_id))
+            .is_some_and(|key| self.shared_chains.contains_key(&key))
+    }
+
+    /// Returns the IDs of streams previously registered (in this session)
+    /// with the given content hash.  Some of these may since have been
+    /// modified or removed, so callers must check the contents.
+    pub fn dedup_candidates(&self, content_hash: u64) -> &[u32] {
+        self.content_index.get(&content_hash).map_or(&[], Vec::as_slice)
+    }
+
+    /// Records that the given stream has contents with the given hash, so
+    /// that later deduplicated streams can share its chain.
+    pub fn register_content(&mut self, content_hash: u64, stream_id: u32) {
+        let stream_ids = self.content_index.entry(content_hash).or_default();
+        if !stream_ids.contains(&stream_id) {
+            stream_ids.push(stream_id);
+        }
+    }
+
     pub fn version(&self) -> Version {
         self.directory.version()
     }
 
+    /// Returns the length of this file's mini sectors, as given by its
+    /// header.
+    pub fn mini_sector_len(&self) -> usize {
+        self.mini_sector_len
+    }
+
+    /// Starts (or redirects) recording modifications for the audit trail
+    /// stream at the given path.
+    pub fn enable_audit(&mut self, path: PathBuf) {
+        match self.audit.as_mut() {
+            Some(audit) => audit.set_path(path),
+            None => self.audit = Some(AuditLog::new(path)),
+        }
+    }
+
+    /// Records a modification for the audit trail, if it is enabled.
+    pub fn audit(
+        &mut self,
+        op: AuditOp,
+        path: &Path,
+        old_len: u64,
+        new_len: u64,
+    ) {
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record(op, path, old_len, new_len);
+        }
+    }
+
+    /// Records a metadata change to each of the given objects in the audit
+    /// trail, if it is enabled.
+    pub fn audit_metadata(&mut self, stream_ids: &[u32]) {
+        if self.audit.is_none() {
+            return;
+        }
+        for &stream_id in stream_ids {
+            if let Some(path) = self.directory.path_for_stream_id(stream_id) {
+                let len = self.dir_entry(stream_id).stream_len;
+                self.audit(AuditOp::SetMetadata, &path, len, len);
+            }
+        }
+    }
+
+    /// Records a rename in the audit trail, if it is enabled.  Streams that
+    /// have been modified since the last flush get their `ModifyStream`
+    /// records closed first, so that later changes are recorded under
+    /// their new paths.
+    pub fn audit_rename(
+        &mut self,
+        old_path: &Path,
+        new_path: &Path,
+        len: u64,
+    ) {
+        let stream_ids = match self.audit.as_ref() {
+            Some(audit) => audit.modified_streams(),
+            None => return,
+        };
+        for stream_id in stream_ids {
+            self.audit_stream_done(stream_id, false);
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            audit.record_rename(old_path, new_path, len);
+        }
+    }
+
+    /// Notes for the audit trail, if it is enabled, that the given stream is
+    /// about to be written to or resized.
+    pub fn audit_stream_modified(&mut self, stream_id: u32) {
+        match self.audit.as_ref() {
+            Some(audit) if !audit.is_modifying(stream_id) => {}
+            _ => return,
+        }
+        // This is only done once per stream between flushes, so the cost of
+        // finding its path is acceptable.
+        let path = match self.directory.path_for_stream_id(stream_id) {
+            Some(path) => path,
+            None => return,
+        };
+        let old_len = self.dir_entry(stream_id).stream_len;
+        if let Some(audit) = self.audit.as_mut() {
+            audit.begin_modify(stream_id, &path, old_len);
+        }
+    }
+
+    /// Finishes the audit trail's record of changes to the given stream, if
+    /// any, as of its current length.  If `discard`, the record is dropped
+    /// instead, because another record covers the changes.
+    pub fn audit_stream_done(&mut self, stream_id: u32, discard: bool) {
+        let stream_len = self.dir_entry(stream_id).stream_len;
+        if let Some(audit) = self.audit.as_mut() {
+            if discard {
+                audit.discard_modify(stream_id);
+            } else {
+                audit.end_modify(stream_id, stream_len);
+            }
+        }
+    }
+
+    /// Returns the path of the audit trail stream, and the encoded records
+    /// made since it was last written, if there are any.  Once they have
+    /// been appended to the stream, `clear_audit_records` must be called.
+    pub fn pending_audit_records(
+        &mut self,
+    ) -> io::Result<Option<(PathBuf, Vec<u8>, usize)>> {
+        let stream_ids = match self.audit.as_ref() {
+            Some(audit) => audit.modified_streams(),
+            None => return Ok(None),
+        };
+        for stream_id in stream_ids {
+            self.audit_stream_done(stream_id, false);
+        }
+        let Some(audit) = self.audit.as_ref() else {
+            return Ok(None);
+        };
+        let (data, count) = audit.encode()?;
+        if count == 0 {
+            return Ok(None);
+        }
+        Ok(Some((audit.path().to_path_buf(), data, count)))
+    }
+
+    /// Forgets the first `count` audit records, which have been written out.
+    pub fn clear_audit_records(&mut self, count: usize) {
+        if let Some(audit) = self.audit.as_mut() {
+            audit.clear(count);
+        }
+    }
+
+    pub fn set_allocator_policy(&mut self, policy: Box<dyn SectorAllocator>) {
+        self.directory.set_allocator_policy(policy);
+    }
+
+    /// Tells the allocator whose data is about to be written, for the
+    /// benefit of any installed allocation policy.
+    pub fn set_allocating_stream(&mut self, stream_id: u32) {
+        self.directory.set_allocating_stream(stream_id);
+    }
+
     pub fn inner(&self) -> &F {
         self.directory.inner()
     }
 
-    pub fn next_mini_sector(&self, sector_id: u32) -> io::Result<u32> {
+    /// Refuses all further writes to the underlying file.
+    pub fn set_read_only(&mut self, reason: ReadOnly) {
+        self.directory.set_read_only(reason);
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        self.directory.metrics()
+    }
+
+    pub fn metrics_mut(&mut self) -> &mut Metrics {
+        self.directory.metrics_mut()
+    }
+
+    /// Returns the mini sector following the given one, which is at the
+    /// given position within the named mini chain (or `END_OF_CHAIN` if the
+    /// chain ends there).
+    pub fn next_mini_sector(
+        &self,
+        sector_id: u32,
+        chain: ChainName<'_>,
+        position: usize,
+    ) -> io::Result<u32> {
         let index = sector_id as usize;
         if index >= self.minifat.len() {
             invalid_data!(
@@ -59,14 +520,33 @@ impl<F> MiniAllocator<F> {
                 self.minifat.len()
             );
         }
-        let next_id = self.minifat[index];
-        if next_id != consts::END_OF_CHAIN
-            && (next_id > consts::MAX_REGULAR_SECTOR
-                || next_id as usize >= self.minifat.len())
-        {
-            invalid_data!("next_id ({}) is invalid", next_id);
+        next_in_chain(
+            chain,
+            position + 1,
+            self.minifat[index],
+            self.minifat.len(),
+        )
+    }
+
+    /// Returns the IDs of the mini sectors in the mini chain starting at the
+    /// given mini sector, in order.
+    pub fn mini_chain_sector_ids(
+        &self,
+        start_sector_id: u32,
+        chain: ChainName<'_>,
+    ) -> io::Result<Vec<u32>> {
+        let mut sector_ids = Vec::new();
+        let mut current_sector_id = start_sector_id;
+        while current_sector_id != consts::END_OF_CHAIN {
+            if sector_ids.len() > self.minifat.len() {
+                invalid_data!("The {} contains a loop", chain);
+            }
+            let position = sector_ids.len();
+            sector_ids.push(current_sector_id);
+            current_sector_id =
+                self.next_mini_sector(current_sector_id, chain, position)?;
         }
-        Ok(next_id)
+        Ok(sector_ids)
     }
 
     pub fn into_inner(self) -> F {
@@ -77,6 +557,14 @@ impl<F> MiniAllocator<F> {
         self.directory.stream_id_for_name_chain(names)
     }
 
+    pub fn stream_prefix_len(&self, names: &[&str]) -> Option<usize> {
+        self.directory.stream_prefix_len(names)
+    }
+
+    pub fn longest_existing_prefix(&self, names: &[&str]) -> (usize, u32) {
+        self.directory.longest_existing_prefix(names)
+    }
+
     pub fn open_chain(
         &mut self,
         start_sector_id: u32,
@@ -100,10 +588,196 @@ impl<F> MiniAllocator<F> {
         self.directory.dir_entry(stream_id)
     }
 
-    fn validate(&mut self, validation: Validation) -> io::Result<()> {
+    /// Returns the current generation of the given directory entry, which
+    /// changes whenever the entry is allocated or freed, or `None` if the
+    /// directory no longer has that entry.
+    pub fn dir_entry_generation(&self, stream_id: u32) -> Option<u64> {
+        self.directory.generation(stream_id)
+    }
+
+    pub fn parent_id(&self, stream_id: u32) -> Option<u32> {
+        self.directory.parent_id(stream_id)
+    }
+
+    /// Returns a number that changes whenever the shape of the directory
+    /// tree changes, and that no other compound file shares.
+    pub fn tree_stamp(&self) -> u64 {
+        self.directory.tree_stamp()
+    }
+
+    pub fn subtree_ids(&self, stream_id: u32) -> Vec<u32> {
+        self.directory.subtree_ids(stream_id)
+    }
+    pub fn num_dir_entries(&self) -> u32 {
+        self.directory.dir_entries().len() as u32
+    }
+
+    pub fn fat(&self) -> &[u32] {
+        self.directory.allocator().fat()
+    }
+
+    /// Returns a hash of this file's version, FAT, MiniFAT, and directory
+    /// entries (as they would be written to disk).  Any change that adds,
+    /// removes, resizes, or moves a stream changes this, but overwriting a
+    /// stream's data in place does not.
+    pub fn structure_fingerprint(&self) -> u64 {
+        let mut hasher = fnv::FnvHasher::default();
+        hasher.write_u16(self.version().number());
+        for &next in self.fat() {
+            hasher.write_u32(next);
+        }
+        hasher.write_u32(consts::NO_STREAM);
+        for &next in self.minifat.iter() {
+            hasher.write_u32(next);
+        }
+        let mut buffer = Vec::with_capacity(consts::DIR_ENTRY_LEN);
+        for dir_entry in self.directory.dir_entries() {
+            buffer.clear();
+            // Writing into a Vec can't fail.
+            dir_entry.write_to(&mut buffer).unwrap();
+            hasher.write(&buffer);
+        }
+        hasher.finish()
+    }
+
+    /// Marks this file as having sectors reserved at creation time, which
+    /// will be released by the next call to `release_reservations()`.
+    pub fn set_has_reservations(&mut self, has_reservations: bool) {
+        self.has_reservations = has_reservations;
+    }
+
+    /// Returns true if iterating over entries should include temporary
+    /// objects.
+    pub fn show_temporaries(&self) -> bool {
+        self.show_temporaries
+    }
+
+    /// Returns the path of every object in the tree, indexed by stream ID,
+    /// hiding temporary objects unless they are being shown.
+    pub fn paths_by_stream_id(&self) -> Vec<Option<PathBuf>> {
+        self.directory.paths_by_stream_id(self.show_temporaries)
+    }
+
+    pub fn set_show_temporaries(&mut self, show: bool) {
+        self.show_temporaries = show;
+    }
+
+    pub fn free_entry_policy(&self) -> FreeEntryPolicy {
+        self.directory.free_entry_policy()
+    }
+
+    pub fn set_free_entry_policy(&mut self, policy: FreeEntryPolicy) {
+        self.directory.set_free_entry_policy(policy);
+    }
+
+    pub fn zero_freed_sectors(&self) -> bool {
+        self.directory.zero_freed_sectors()
+    }
+
+    pub fn set_zero_freed_sectors(&mut self, zero: bool) {
+        self.directory.set_zero_freed_sectors(zero);
+    }
+
+    pub fn deleted_entries(&self) -> Vec<DeletedEntry> {
+        self.directory.deleted_entries()
+    }
+
+    /// Returns the total number of bytes buffered, but not yet written, by
+    /// all open stream handles.
+    pub fn dirty_bytes(&self) -> u64 {
+        self.dirty_bytes
+    }
+
+    pub fn set_dirty_budget(&mut self, budget: u64) {
+        self.dirty_budget = budget;
+    }
+
+    /// Records that a handle to the given stream (whose directory entry had
+    /// the given generation when the handle was opened) now has `new_len`
+    /// bytes of buffered writes instead of `old_len`, and returns true if
+    /// the total is now over the dirty budget.
+    pub fn update_dirty_bytes(
+        &mut self,
+        stream_id: u32,
+        generation: u64,
+        old_len: usize,
+        new_len: usize,
+    ) -> bool {
+        self.dirty_bytes = self
+            .dirty_bytes
+            .saturating_sub(old_len as u64)
+            .saturating_add(new_len as u64);
+        let key = (stream_id, generation);
+        let len = self.dirty_streams.entry(key).or_default();
+        *len = len.saturating_sub(old_len).saturating_add(new_len);
+        if *len == 0 {
+            self.dirty_streams.remove(&key);
+        }
+        self.dirty_bytes > self.dirty_budget
+    }
+
+    /// Returns the paths, in stream ID order, of the streams that open
+    /// handles hold buffered writes for.  Handles to streams that have
+    /// since been removed don't count, since their writes will be discarded.
+    pub fn unflushed_streams(&self) -> Vec<PathBuf> {
+        let mut stream_ids: Vec<u32> = self
+            .dirty_streams
+            .keys()
+            .filter(|&&(stream_id, generation)| {
+                self.directory.generation(stream_id) == Some(generation)
+            })
+            .map(|&(stream_id, _)| stream_id)
+            .collect();
+        stream_ids.sort_unstable();
+        stream_ids.dedup();
+        stream_ids
+            .into_iter()
+            .filter_map(|stream_id| {
+                self.directory.path_for_stream_id(stream_id)
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> io::Result<Stats> {
+        let allocator = self.directory.allocator();
+        let dir_sectors = allocator.chain_sector_ids(
+            self.directory.dir_start_sector(),
+            ChainName::Directory,
+        )?;
+        let minifat_sectors = allocator
+            .chain_sector_ids(self.minifat_start_sector, ChainName::MiniFat)?;
+        let mini_stream_sectors = allocator.chain_sector_ids(
+            self.directory.root_dir_entry().start_sector,
+            ChainName::MiniStream,
+        )?;
+        let mut stats = Stats::new(
+            allocator.fat(),
+            &dir_sectors,
+            &minifat_sectors,
+            &mini_stream_sectors,
+        );
+        stats.num_free_mini_sectors = self.free_mini_sectors.len() as u32;
+        stats.num_free_dir_entries = self.directory.num_free_dir_entries();
+        stats.num_shared_streams = self.shared_chains.values().sum();
+        Ok(stats)
+    }
+
+    fn validate(
+        &mut self,
+        validation: Validation,
+        issues: &mut Vec<ValidationIssue>,
+    ) -> io::Result<()> {
         let root_entry = self.directory.root_dir_entry();
-        let root_stream_mini_sectors =
-            root_entry.stream_len / (consts::MINI_SECTOR_LEN as u64);
+        let mini_sector_len = self.mini_sector_len as u64;
+        if root_entry.stream_len % mini_sector_len != 0 {
+            invalid_data!(
+                "Malformed directory (root stream len is {}, but should be \
+                 multiple of {})",
+                root_entry.stream_len,
+                mini_sector_len
+            );
+        }
+        let root_stream_mini_sectors = root_entry.stream_len / mini_sector_len;
         if root_stream_mini_sectors < (self.minifat.len() as u64) {
             if validation.is_strict() {
                 malformed!(
@@ -112,46 +786,808 @@ impl<F> MiniAllocator<F> {
                 self.minifat.len(),
                 root_stream_mini_sectors
             );
+            }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::MiniFatTruncated,
+                format!(
+                    "MiniFAT has {} entries, but root stream has only {} \
+                     mini sectors",
+                    self.minifat.len(),
+                    root_stream_mini_sectors
+                ),
+            ));
+            self.minifat.truncate(root_stream_mini_sectors as usize);
+        }
+        let mut pointees = FnvHashSet::default();
+        for (from_mini_sector, &to_mini_sector) in
+            self.minifat.iter().enumerate()
+        {
+            if to_mini_sector <= consts::MAX_REGULAR_SECTOR {
+                if to_mini_sector as usize >= self.minifat.len() {
+                    malformed!(
+                        "MiniFAT has {} entries, but mini sector {} points to \
+                         {}",
+                        self.minifat.len(),
+                        from_mini_sector,
+                        to_mini_sector
+                    );
+                }
+                if pointees.contains(&to_mini_sector) {
+                    malformed!(
+                        "mini sector {} pointed to twice",
+                        to_mini_sector
+                    );
+                }
+                pointees.insert(to_mini_sector);
+            }
+        }
+        if let Err(error) = self.check_chains() {
+            if validation.is_strict() {
+                return Err(error);
+            }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::BrokenChain,
+                error.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Walks the chain of the mini stream and of every stream, checking that
+    /// each one runs through valid sectors to `END_OF_CHAIN`.
+    fn check_chains(&self) -> io::Result<()> {
+        let allocator = self.directory.allocator();
+        let root_entry = self.directory.root_dir_entry();
+        if root_entry.stream_len > 0 {
+            allocator.chain_sector_ids(
+                root_entry.start_sector,
+                ChainName::MiniStream,
+            )?;
+        }
+        for (stream_id, dir_entry) in
+            self.directory.dir_entries().iter().enumerate()
+        {
+            let Some((is_mini, start_sector)) =
+                MiniAllocator::<F>::chain_key(dir_entry)
+            else {
+                continue;
+            };
+            let walk = |chain| {
+                if is_mini {
+                    self.mini_chain_sector_ids(start_sector, chain)
+                } else {
+                    allocator.chain_sector_ids(start_sector, chain)
+                }
+            };
+            if walk(ChainName::StartingAt(start_sector)).is_ok() {
+                continue;
+            }
+            // Only now look up the stream's path (which means searching the
+            // whole tree) to name it in the error.  Streams that can't be
+            // reached from the root can never be opened, so their chains
+            // don't matter.
+            if let Some(path) =
+                self.directory.path_for_stream_id(stream_id as u32)
+            {
+                walk(ChainName::Stream(&path))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the IDs of the streams whose chains hold at least one more
+    /// (mini) sector than their lengths need.  Streams whose chains are
+    /// broken are skipped.
+    pub fn oversized_chains(&self) -> Vec<u32> {
+        let allocator = self.directory.allocator();
+        let mut stream_ids = Vec::new();
+        for (stream_id, dir_entry) in
+            self.directory.dir_entries().iter().enumerate()
+        {
+            let Some((is_mini, start_sector)) =
+                MiniAllocator::<F>::chain_key(dir_entry)
+            else {
+                continue;
+            };
+            let (sector_ids, sector_len) = if is_mini {
+                let chain = ChainName::MiniStartingAt(start_sector);
+                let sector_ids =
+                    self.mini_chain_sector_ids(start_sector, chain);
+                (sector_ids, self.mini_sector_len as u64)
+            } else {
+                let chain = ChainName::StartingAt(start_sector);
+                let sector_ids =
+                    allocator.chain_sector_ids(start_sector, chain);
+                (sector_ids, allocator.sector_len() as u64)
+            };
+            let Ok(sector_ids) = sector_ids else {
+                continue;
+            };
+            let num_needed = dir_entry.stream_len.div_ceil(sector_len);
+            if sector_ids.len() as u64 > num_needed {
+                stream_ids.push(stream_id as u32);
+            }
+        }
+        stream_ids
+    }
+
+    /// Returns the IDs of the unallocated directory entries whose (stale)
+    /// starting sector fields point at the head of an allocated chain that
+    /// nothing live uses, along with whether that chain is a mini chain (as
+    /// implied by the entry's stale stream length).
+    pub fn stale_chains(&self) -> Vec<(u32, bool)> {
+        let allocator = self.directory.allocator();
+        let fat = allocator.fat();
+        let mut live_sectors = FnvHashSet::default();
+        let mut live_mini_sectors = FnvHashSet::default();
+        let internal_chains = [
+            (self.directory.dir_start_sector(), ChainName::Directory),
+            (self.minifat_start_sector, ChainName::MiniFat),
+            (
+                self.directory.root_dir_entry().start_sector,
+                ChainName::MiniStream,
+            ),
+        ];
+        for (start_sector, chain) in internal_chains {
+            if let Ok(sector_ids) =
+                allocator.chain_sector_ids(start_sector, chain)
+            {
+                live_sectors.extend(sector_ids);
+            }
+        }
+        for dir_entry in self.directory.dir_entries() {
+            let Some((is_mini, start_sector)) =
+                MiniAllocator::<F>::chain_key(dir_entry)
+            else {
+                continue;
+            };
+            if is_mini {
+                let chain = ChainName::MiniStartingAt(start_sector);
+                if let Ok(ids) =
+                    self.mini_chain_sector_ids(start_sector, chain)
+                {
+                    live_mini_sectors.extend(ids);
+                }
+            } else {
+                let chain = ChainName::StartingAt(start_sector);
+                if let Ok(ids) =
+                    allocator.chain_sector_ids(start_sector, chain)
+                {
+                    live_sectors.extend(ids);
+                }
+            }
+        }
+        let mut stale = Vec::new();
+        for (stream_id, dir_entry) in
+            self.directory.dir_entries().iter().enumerate()
+        {
+            // An entry with no stale length (such as one zeroed when its
+            // object was removed) says nothing about which kind of chain its
+            // starting sector would refer to.
+            if dir_entry.obj_type != ObjType::Unallocated
+                || dir_entry.stream_len == 0
+            {
+                continue;
+            }
+            let start_sector = dir_entry.start_sector;
+            let is_mini =
+                dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64;
+            let (table, live, sector_ids) = if is_mini {
+                let chain = ChainName::MiniStartingAt(start_sector);
+                let sector_ids =
+                    self.mini_chain_sector_ids(start_sector, chain);
+                (&self.minifat[..], &live_mini_sectors, sector_ids)
+            } else {
+                let chain = ChainName::StartingAt(start_sector);
+                let sector_ids =
+                    allocator.chain_sector_ids(start_sector, chain);
+                (fat, &live_sectors, sector_ids)
+            };
+            let allocated =
+                table.get(start_sector as usize).is_some_and(|&next| {
+                    next == consts::END_OF_CHAIN
+                        || next <= consts::MAX_REGULAR_SECTOR
+                });
+            if !allocated {
+                continue;
+            }
+            let Ok(sector_ids) = sector_ids else {
+                continue;
+            };
+            if sector_ids.iter().all(|sector_id| !live.contains(sector_id)) {
+                stale.push((stream_id as u32, is_mini));
+            }
+        }
+        stale
+    }
+
+    /// Cross-checks the FAT, MiniFAT, and directory against each other, and
+    /// returns a description of each problem found: chains that are broken
+    /// or that share sectors (other than chains shared between streams on
+    /// purpose), allocated (mini) sectors that no chain uses, allocated
+    /// directory entries that aren't in the tree, and chains longer than
+    /// their streams need.
+    pub fn structure_problems(&self) -> Vec<String> {
+        let mut problems = self.chain_problems();
+        for stream_id in self.oversized_chains() {
+            let dir_entry = self.directory.dir_entry(stream_id);
+            problems.push(format!(
+                "The chain of directory entry {} ({:?}) is longer than its \
+                 length of {} needs",
+                stream_id, dir_entry.name, dir_entry.stream_len
+            ));
+        }
+        problems
+    }
+
+    /// Like `structure_problems`, but without reporting oversized chains,
+    /// which waste space but leave the file perfectly readable.
+    fn chain_problems(&self) -> Vec<String> {
+        self.chain_issues()
+            .into_iter()
+            .map(|issue| issue.message().to_string())
+            .collect()
+    }
+
+    /// Does the work of `chain_problems`, describing each problem as a
+    /// validation issue.
+    pub fn chain_issues(&self) -> Vec<ValidationIssue> {
+        let allocator = self.directory.allocator();
+        let mut problems = Vec::new();
+        let mut chains: Vec<(ChainName<'_>, u32)> = vec![
+            (ChainName::Directory, self.directory.dir_start_sector()),
+            (ChainName::MiniFat, self.minifat_start_sector),
+        ];
+        // The mini stream's chain may extend past the end of the mini stream
+        // (even when it's empty), so that freed mini sectors can be reused
+        // without reallocating it, so its chain is followed whenever there is
+        // one.
+        let root_entry = self.directory.root_dir_entry();
+        let has_mini_stream_chain = root_entry.stream_len > 0
+            || allocator
+                .fat()
+                .get(root_entry.start_sector as usize)
+                .is_some_and(|&next| {
+                    next == consts::END_OF_CHAIN
+                        || next <= consts::MAX_REGULAR_SECTOR
+                });
+        if has_mini_stream_chain {
+            chains.push((ChainName::MiniStream, root_entry.start_sector));
+        }
+        let mut mini_chains = Vec::new();
+        let stream_chains: BTreeSet<(bool, u32)> = self
+            .directory
+            .dir_entries()
+            .iter()
+            .filter_map(MiniAllocator::<F>::chain_key)
+            .collect();
+        for (is_mini, start_sector) in stream_chains {
+            if is_mini {
+                mini_chains.push(start_sector);
+            } else {
+                chains
+                    .push((ChainName::StartingAt(start_sector), start_sector));
+            }
+        }
+
+        let mut owners = FnvHashMap::<u32, usize>::default();
+        for (index, &(chain, start_sector)) in chains.iter().enumerate() {
+            let sector_ids =
+                match allocator.chain_sector_ids(start_sector, chain) {
+                    Ok(sector_ids) => sector_ids,
+                    Err(error) => {
+                        problems.push(
+                            ValidationIssue::new(
+                                ValidationIssueKind::BrokenChain,
+                                error.to_string(),
+                            )
+                            .with_sector_id(start_sector),
+                        );
+                        continue;
+                    }
+                };
+            for sector_id in sector_ids {
+                if let Some(&other) = owners.get(&sector_id) {
+                    problems.push(
+                        ValidationIssue::new(
+                            ValidationIssueKind::CrossLinkedSector,
+                            format!(
+                                "Sector {} is in both the {} and the {}",
+                                sector_id, chains[other].0, chain
+                            ),
+                        )
+                        .with_sector_id(sector_id),
+                    );
+                } else {
+                    owners.insert(sector_id, index);
+                }
+            }
+        }
+        let leaked: Vec<u32> = (0..allocator.fat().len() as u32)
+            .filter(|&sector_id| {
+                let next = allocator.fat()[sector_id as usize];
+                (next == consts::END_OF_CHAIN
+                    || next <= consts::MAX_REGULAR_SECTOR)
+                    && !owners.contains_key(&sector_id)
+            })
+            .collect();
+        if let Some(&first) = leaked.first() {
+            problems.push(
+                ValidationIssue::new(
+                    ValidationIssueKind::OrphanedSectors,
+                    format!(
+                        "{} sectors are allocated but not in any chain",
+                        leaked.len()
+                    ),
+                )
+                .with_sector_id(first),
+            );
+        }
+
+        let mut mini_owners = FnvHashMap::<u32, u32>::default();
+        for &start_sector in mini_chains.iter() {
+            let chain = ChainName::MiniStartingAt(start_sector);
+            let sector_ids =
+                match self.mini_chain_sector_ids(start_sector, chain) {
+                    Ok(sector_ids) => sector_ids,
+                    Err(error) => {
+                        problems.push(
+                            ValidationIssue::new(
+                                ValidationIssueKind::BrokenChain,
+                                error.to_string(),
+                            )
+                            .with_sector_id(start_sector),
+                        );
+                        continue;
+                    }
+                };
+            for sector_id in sector_ids {
+                if let Some(&other) = mini_owners.get(&sector_id) {
+                    problems.push(
+                        ValidationIssue::new(
+                            ValidationIssueKind::CrossLinkedSector,
+                            format!(
+                                "Mini sector {} is in both the {} and the {}",
+                                sector_id,
+                                ChainName::MiniStartingAt(other),
+                                chain
+                            ),
+                        )
+                        .with_sector_id(sector_id),
+                    );
+                } else {
+                    mini_owners.insert(sector_id, start_sector);
+                }
+            }
+        }
+        let leaked: Vec<u32> = (0..self.minifat.len() as u32)
+            .filter(|&sector_id| {
+                let next = self.minifat[sector_id as usize];
+                (next == consts::END_OF_CHAIN
+                    || next <= consts::MAX_REGULAR_SECTOR)
+                    && !mini_owners.contains_key(&sector_id)
+            })
+            .collect();
+        if let Some(&first) = leaked.first() {
+            problems.push(
+                ValidationIssue::new(
+                    ValidationIssueKind::OrphanedSectors,
+                    format!(
+                        "{} mini sectors are allocated but not in any mini \
+                         chain",
+                        leaked.len()
+                    ),
+                )
+                .with_sector_id(first),
+            );
+        }
+
+        for (stream_id, dir_entry) in
+            self.directory.dir_entries().iter().enumerate()
+        {
+            let stream_id = stream_id as u32;
+            if stream_id != consts::ROOT_STREAM_ID
+                && dir_entry.obj_type != ObjType::Unallocated
+                && self.directory.parent_id(stream_id).is_none()
+            {
+                problems.push(
+                    ValidationIssue::new(
+                        ValidationIssueKind::UnreachableEntry,
+                        format!(
+                            "Directory entry {} ({:?}) is allocated but not \
+                             in the tree",
+                            stream_id, dir_entry.name
+                        ),
+                    )
+                    .with_stream_id(stream_id),
+                );
+            }
+        }
+        problems
+    }
+
+    /// Enables or disables checking the file's consistency after each
+    /// mutation (see `self_check`).  Enabling it checks the whole file
+    /// straight away, so that any later failure is known to have been caused
+    /// by a mutation rather than having been present all along.
+    pub fn set_self_check(&mut self, enabled: bool) {
+        self.directory.set_track_changes(enabled);
+        self.touched_mini_sectors =
+            if enabled { Some(FnvHashSet::default()) } else { None };
+        self.checks_since_sweep = 0;
+        if enabled {
+            self.fail_on_problems(
+                "enabling self-checks",
+                self.sweep_problems(),
+            );
+        }
+    }
+
+    /// If self-checks are enabled, checks the parts of the file that have
+    /// changed since the last check for consistency, and every so often
+    /// checks the whole file.  Panics, naming the given operation, if any
+    /// problem is found.  Does nothing in release builds, or while already
+    /// panicking (e.g. when a `Stream` is dropped during unwinding), since
+    /// panicking again would abort.
+    pub fn self_check(&mut self, operation: &str) {
+        if !cfg!(debug_assertions) || std::thread::panicking() {
+            return;
+        }
+        let Some(touched) = self.touched_mini_sectors.as_mut() else {
+            return;
+        };
+        let mut mini_sector_ids: Vec<u32> = touched.drain().collect();
+        mini_sector_ids.sort_unstable();
+        let sector_ids = self.directory.take_touched_sectors();
+        let stream_ids = self.directory.take_touched_entries();
+        let mut problems = self.directory.fat_problems(Some(&sector_ids));
+        problems.extend(alloc::table_problems(
+            "MiniFAT",
+            &self.minifat,
+            &self.free_mini_sectors,
+            Some(&mini_sector_ids),
+        ));
+        problems.extend(self.directory.entry_problems(&stream_ids));
+        for &stream_id in stream_ids.iter() {
+            problems.extend(self.stream_len_problem(stream_id));
+        }
+        self.checks_since_sweep += 1;
+        if problems.is_empty() && self.checks_since_sweep >= SWEEP_INTERVAL {
+            self.checks_since_sweep = 0;
+            problems = self.sweep_problems();
+        }
+        self.fail_on_problems(operation, problems);
+    }
+
+    /// Returns a description of each problem found by checking the whole
+    /// file.
+    fn sweep_problems(&self) -> Vec<String> {
+        let mut problems = self.directory.fat_problems(None);
+        problems.extend(alloc::table_problems(
+            "MiniFAT",
+            &self.minifat,
+            &self.free_mini_sectors,
+            None,
+        ));
+        problems.extend(self.directory.tree_problems());
+        if problems.is_empty() {
+            // Following chains is only safe once the tables are known to be
+            // sound.
+            problems.extend(self.chain_problems());
+        }
+        problems
+    }
+
+    /// Returns a description of the problem if the given entry is a stream
+    /// whose length is more than its chain can hold.
+    fn stream_len_problem(&self, stream_id: u32) -> Option<String> {
+        let dir_entry =
+            self.directory.dir_entries().get(stream_id as usize)?;
+        let (is_mini, start_sector) =
+            MiniAllocator::<F>::chain_key(dir_entry)?;
+        let (sector_ids, sector_len) = if is_mini {
+            let chain = ChainName::MiniStartingAt(start_sector);
+            let sector_ids = self.mini_chain_sector_ids(start_sector, chain);
+            (sector_ids, self.mini_sector_len as u64)
+        } else {
+            let allocator = self.directory.allocator();
+            let chain = ChainName::StartingAt(start_sector);
+            let sector_ids = allocator.chain_sector_ids(start_sector, chain);
+            (sector_ids, allocator.sector_len() as u64)
+        };
+        match sector_ids {
+            Err(error) => Some(error.to_string()),
+            Ok(sector_ids)
+                if (sector_ids.len() as u64) * sector_len
+                    < dir_entry.stream_len =>
+            {
+                Some(format!(
+                    "entry {} ({:?}) has length {}, but its chain holds only \
+                     {} sectors of {} bytes",
+                    stream_id,
+                    dir_entry.name,
+                    dir_entry.stream_len,
+                    sector_ids.len(),
+                    sector_len
+                ))
+            }
+            Ok(_) => None,
+        }
+    }
+
+    fn fail_on_problems(&self, operation: &str, problems: Vec<String>) {
+        if !problems.is_empty() {
+            panic!(
+                "Self-check failed after {}: {}",
+                operation,
+                problems.join("; ")
+            );
+        }
+    }
+
+    /// Adds a validation issue for each chain found by `oversized_chains` or
+    /// `stale_chains`.  These are reported regardless of the validation
+    /// mode, since they don't stop the file from being read.
+    fn report_hidden_chains(&self, issues: &mut Vec<ValidationIssue>) {
+        for stream_id in self.oversized_chains() {
+            let dir_entry = self.directory.dir_entry(stream_id);
+            let name = match self.directory.path_for_stream_id(stream_id) {
+                Some(path) => format!("Stream {:?}", path),
+                None => format!("Unreachable stream entry {}", stream_id),
+            };
+            let is_mini =
+                dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64;
+            issues.push(
+                ValidationIssue::new(
+                    ValidationIssueKind::ChainSlack,
+                    format!(
+                        "{} has length {}, but its {} chain is at least one \
+                         sector longer than that needs",
+                        name,
+                        dir_entry.stream_len,
+                        if is_mini { "mini sector" } else { "sector" },
+                    ),
+                )
+                .with_stream_id(stream_id),
+            );
+        }
+        for (stream_id, is_mini) in self.stale_chains() {
+            let start_sector =
+                self.directory.dir_entry(stream_id).start_sector;
+            issues.push(
+                ValidationIssue::new(
+                    ValidationIssueKind::StaleChain,
+                    format!(
+                        "Unallocated directory entry {} points at allocated \
+                         {} chain starting at {}, which nothing else uses",
+                        stream_id,
+                        if is_mini { "mini sector" } else { "sector" },
+                        start_sector
+                    ),
+                )
+                .with_stream_id(stream_id)
+                .with_sector_id(start_sector),
+            );
+        }
+    }
+
+    /// Checks the file as it is now: its chains (see `chain_issues`), its
+    /// streams' lengths, its hidden chains, and its shared chains.  Each
+    /// issue about a reachable object is given that object's path.
+    pub fn validation_issues(&self) -> Vec<ValidationIssue> {
+        let mut issues = self.chain_issues();
+        self.find_short_streams(&mut issues);
+        self.report_hidden_chains(&mut issues);
+        for (stream_id, dir_entry) in
+            self.directory.dir_entries().iter().enumerate()
+        {
+            let stream_id = stream_id as u32;
+            if !self.is_shared(stream_id) {
+                continue;
+            }
+            issues.push(
+                ValidationIssue::new(
+                    ValidationIssueKind::SharedChain,
+                    format!(
+                        "Stream entry {} ({:?}) shares its chain, starting at \
+                         {}, with another stream",
+                        stream_id, dir_entry.name, dir_entry.start_sector
+                    ),
+                )
+                .with_stream_id(stream_id)
+                .with_sector_id(dir_entry.start_sector),
+            );
+        }
+        self.fill_issue_paths(&mut issues);
+        issues
+    }
+
+    /// Gives each issue about a directory entry that is reachable in the
+    /// tree (and that doesn't have a path yet) that entry's path.
+    pub fn fill_issue_paths(&self, issues: &mut [ValidationIssue]) {
+        if issues.iter().all(|issue| issue.path().is_some()) {
+            return;
+        }
+        let paths = self.directory.paths_by_stream_id(true);
+        for issue in issues.iter_mut() {
+            if issue.path().is_some() {
+                continue;
+            }
+            let Some(stream_id) = issue.stream_id() else {
+                continue;
+            };
+            let path =
+                paths.get(stream_id.value() as usize).cloned().flatten();
+            if let Some(path) = path {
+                issue.set_path(path);
+            }
+        }
+    }
+}
+
+impl<F: Seek> MiniAllocator<F> {
+    pub fn check_backing_len(&mut self) -> io::Result<()> {
+        self.directory.check_backing_len()
+    }
+
+    /// Classifies every byte that the FAT describes (see `Reachability`) in
+    /// a single pass over the directory followed by a single pass over the
+    /// FAT.  Chains that can't be followed are skipped, leaving their
+    /// sectors to be counted as orphaned.
+    pub fn reachability(&mut self) -> io::Result<Reachability> {
+        let file_len = self.directory.backing_len()?;
+        let allocator = self.directory.allocator();
+        let fat = allocator.fat();
+        let sector_len = allocator.sector_len() as u64;
+        let mini_sector_len = self.mini_sector_len as u64;
+        let mut reach = Reachability {
+            total_bytes: (fat.len() as u64 + 1) * sector_len,
+            file_len,
+            // The header always takes up a whole sector.
+            metadata_bytes: sector_len,
+            ..Reachability::default()
+        };
+        // Which sectors have already been counted, so that a sector that
+        // (wrongly) appears in more than one chain, or a chain shared by
+        // several streams, is only counted once.
+        let mut counted = vec![false; fat.len()];
+        let mut claim = |sector_ids: Vec<u32>| -> Vec<Option<u32>> {
+            sector_ids
+                .into_iter()
+                .map(|sector_id| {
+                    let flag = counted.get_mut(sector_id as usize)?;
+                    if *flag {
+                        return None;
+                    }
+                    *flag = true;
+                    Some(sector_id)
+                })
+                .collect()
+        };
+        let internal_chains = [
+            (self.directory.dir_start_sector(), ChainName::Directory),
+            (self.minifat_start_sector, ChainName::MiniFat),
+        ];
+        for (start_sector, chain) in internal_chains {
+            if let Ok(sector_ids) =
+                allocator.chain_sector_ids(start_sector, chain)
+            {
+                let claimed = claim(sector_ids).into_iter().flatten().count();
+                reach.metadata_bytes += claimed as u64 * sector_len;
+            }
+        }
+
+        // The mini stream is counted mini sector by mini sector, skipping any
+        // of its sectors that were already counted as something else.
+        let root_entry = self.directory.root_dir_entry();
+        let mini_stream_sectors = allocator
+            .chain_sector_ids(root_entry.start_sector, ChainName::MiniStream)
+            .map(&mut claim)
+            .unwrap_or_default();
+        let mini_per_sector = sector_len / mini_sector_len;
+        let mini_stream_len = root_entry
+            .stream_len
+            .min(mini_stream_sectors.len() as u64 * sector_len);
+        let num_mini_sectors = (mini_stream_len / mini_sector_len) as usize;
+        // For each mini sector, whether it has been counted (or lies in a
+        // sector that isn't part of the mini stream after all).
+        let mut mini_counted = vec![false; num_mini_sectors];
+        for (position, sector_id) in mini_stream_sectors.iter().enumerate() {
+            let start = position as u64 * sector_len;
+            if sector_id.is_none() {
+                let first = (start / mini_sector_len) as usize;
+                let last =
+                    (first + mini_per_sector as usize).min(num_mini_sectors);
+                for flag in mini_counted.iter_mut().take(last).skip(first) {
+                    *flag = true;
+                }
+            } else if start + sector_len > mini_stream_len {
+                reach.slack_bytes +=
+                    start + sector_len - mini_stream_len.max(start);
+            }
+        }
+
+        for (stream_id, dir_entry) in
+            self.directory.dir_entries().iter().enumerate()
+        {
+            if self.directory.parent_id(stream_id as u32).is_none() {
+                continue;
+            }
+            let Some((is_mini, start_sector)) =
+                MiniAllocator::<F>::chain_key(dir_entry)
+            else {
+                continue;
+            };
+            let mut remaining = dir_entry.stream_len;
+            if is_mini {
+                let chain = ChainName::MiniStartingAt(start_sector);
+                let Ok(sector_ids) =
+                    self.mini_chain_sector_ids(start_sector, chain)
+                else {
+                    continue;
+                };
+                for sector_id in sector_ids {
+                    let used = remaining.min(mini_sector_len);
+                    remaining -= used;
+                    match mini_counted.get_mut(sector_id as usize) {
+                        Some(flag) if !*flag => *flag = true,
+                        _ => continue,
+                    }
+                    reach.reachable_bytes += used;
+                    reach.slack_bytes += mini_sector_len - used;
+                }
             } else {
-                self.minifat.truncate(root_stream_mini_sectors as usize);
+                let chain = ChainName::StartingAt(start_sector);
+                let Ok(sector_ids) =
+                    allocator.chain_sector_ids(start_sector, chain)
+                else {
+                    continue;
+                };
+                for sector_id in claim(sector_ids) {
+                    let used = remaining.min(sector_len);
+                    remaining -= used;
+                    if sector_id.is_some() {
+                        reach.reachable_bytes += used;
+                        reach.slack_bytes += sector_len - used;
+                    }
+                }
             }
         }
-        let mut pointees = FnvHashSet::default();
-        for (from_mini_sector, &to_mini_sector) in
-            self.minifat.iter().enumerate()
+
+        for (mini_sector, _) in
+            mini_counted.iter().enumerate().filter(|&(_, &flag)| !flag)
         {
-            if to_mini_sector <= consts::MAX_REGULAR_SECTOR {
-                if to_mini_sector as usize >= self.minifat.len() {
-                    malformed!(
-                        "MiniFAT has {} entries, but mini sector {} points to \
-                         {}",
-                        self.minifat.len(),
-                        from_mini_sector,
-                        to_mini_sector
-                    );
+            match self.minifat.get(mini_sector) {
+                Some(&next) if next != consts::FREE_SECTOR => {
+                    reach.orphaned_bytes += mini_sector_len;
                 }
-                if pointees.contains(&to_mini_sector) {
-                    malformed!(
-                        "mini sector {} pointed to twice",
-                        to_mini_sector
-                    );
+                _ => reach.free_bytes += mini_sector_len,
+            }
+        }
+        for (&next, _) in
+            fat.iter().zip(counted.iter()).filter(|&(_, &flag)| !flag)
+        {
+            match next {
+                consts::FREE_SECTOR => reach.free_bytes += sector_len,
+                consts::FAT_SECTOR | consts::DIFAT_SECTOR => {
+                    reach.metadata_bytes += sector_len;
                 }
-                pointees.insert(to_mini_sector);
+                _ => reach.orphaned_bytes += sector_len,
             }
         }
-        Ok(())
+        Ok(reach)
+    }
+
+    pub fn diagnose_read_error(&mut self, error: io::Error) -> io::Error {
+        self.directory.diagnose_read_error(error)
     }
-}
 
-impl<F: Seek> MiniAllocator<F> {
     pub fn seek_within_mini_sector(
         &mut self,
         mini_sector: u32,
         offset_within_mini_sector: u64,
     ) -> io::Result<Sector<'_, F>> {
-        debug_assert!(
-            offset_within_mini_sector < consts::MINI_SECTOR_LEN as u64
-        );
+        debug_assert!(offset_within_mini_sector < self.mini_sector_len as u64);
         let mini_stream_start_sector =
             self.directory.root_dir_entry().start_sector;
         let chain = self
@@ -159,13 +1595,367 @@ impl<F: Seek> MiniAllocator<F> {
             .open_chain(mini_stream_start_sector, SectorInit::Fat)?;
         chain.into_subsector(
             mini_sector,
-            consts::MINI_SECTOR_LEN,
+            self.mini_sector_len,
             offset_within_mini_sector,
         )
     }
 }
 
+impl<F: Read + Seek> MiniAllocator<F> {
+    pub fn read_raw_dir_entry(
+        &mut self,
+        stream_id: u32,
+    ) -> io::Result<[u8; consts::DIR_ENTRY_LEN]> {
+        self.directory.read_raw_dir_entry(stream_id)
+    }
+
+    /// Reads everything in the (mini) chain starting at the given (mini)
+    /// sector past its first `offset` bytes.  If that is more than
+    /// `max_len` bytes, nothing is read, and the error made by `too_large`
+    /// from the length is returned instead.
+    pub fn read_chain_from<E: Into<io::Error>>(
+        &mut self,
+        is_mini: bool,
+        start_sector: u32,
+        offset: u64,
+        max_len: usize,
+        too_large: impl FnOnce(u64) -> E,
+    ) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        if is_mini {
+            let mut chain = self.open_mini_chain(start_sector)?;
+            let offset = offset.min(chain.len());
+            if chain.len() - offset > max_len as u64 {
+                return Err(too_large(chain.len() - offset).into());
+            }
+            chain.seek(SeekFrom::Start(offset))?;
+            chain.read_to_end(&mut data)?;
+        } else {
+            let mut chain = self.open_chain(start_sector, SectorInit::Fat)?;
+            let offset = offset.min(chain.len());
+            if chain.len() - offset > max_len as u64 {
+                return Err(too_large(chain.len() - offset).into());
+            }
+            chain.seek(SeekFrom::Start(offset))?;
+            chain.read_to_end(&mut data)?;
+        }
+        Ok(data)
+    }
+
+    /// Returns where in the file each piece of the given stream lives, in
+    /// order, as (file offset, length) pairs: one per sector, or per mini
+    /// sector for a stream in the mini stream.  The sectors of the mini
+    /// stream are looked up the first time they are needed, and kept in
+    /// `mini_stream_sectors` for next time.
+    pub fn stream_pieces(
+        &self,
+        stream_id: u32,
+        mini_stream_sectors: &mut Option<Vec<u32>>,
+    ) -> io::Result<Vec<(u64, usize)>> {
+        let sector_len = self.directory.sector_len();
+        let mini_sector_len = self.mini_sector_len;
+        // Size the contents by what the chain can deliver, rather than
+        // trusting the declared length of a stream whose chain is short.
+        let stream_len = self.readable_len(stream_id) as usize;
+        let dir_entry = self.directory.dir_entry(stream_id);
+        let path = self.directory.path_for_stream_id(stream_id);
+        let chain = path.as_deref().map_or(
+            ChainName::StartingAt(dir_entry.start_sector),
+            ChainName::Stream,
+        );
+        let mut pieces = Vec::new();
+        let mut offset = 0;
+        if dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64 {
+            let mini_stream_sectors = match mini_stream_sectors {
+                Some(ref sector_ids) => sector_ids,
+                None => mini_stream_sectors.insert(
+                    self.directory.allocator().chain_sector_ids(
+                        self.directory.root_dir_entry().start_sector,
+                        ChainName::MiniStream,
+                    )?,
+                ),
+            };
+            let mut mini_sector = dir_entry.start_sector;
+            while offset < stream_len {
+                if mini_sector == consts::END_OF_CHAIN {
+                    invalid_data!(
+                        "Mini chain for stream {} ends after {} bytes, but \
+                         stream length is {}",
+                        stream_id,
+                        offset,
+                        stream_len
+                    );
+                }
+                let mini_offset = mini_sector as usize * mini_sector_len;
+                let Some(&sector_id) =
+                    mini_stream_sectors.get(mini_offset / sector_len)
+                else {
+                    invalid_data!(
+                        "Mini sector {} is past the end of the mini stream",
+                        mini_sector
+                    );
+                };
+                let len = (stream_len - offset).min(mini_sector_len);
+                let file_offset = (sector_id as u64 + 1) * sector_len as u64
+                    + (mini_offset % sector_len) as u64;
+                pieces.push((file_offset, len));
+                let position = offset / mini_sector_len;
+                offset += len;
+                mini_sector =
+                    self.next_mini_sector(mini_sector, chain, position)?;
+            }
+        } else {
+            let sector_ids = self
+                .directory
+                .allocator()
+                .chain_sector_ids(dir_entry.start_sector, chain)?;
+            if sector_ids.len() < stream_len.div_ceil(sector_len) {
+                invalid_data!(
+                    "Chain for stream {} has only {} sectors, but stream \
+                     length is {}",
+                    stream_id,
+                    sector_ids.len(),
+                    stream_len
+                );
+            }
+            for sector_id in sector_ids {
+                if offset >= stream_len {
+                    break;
+                }
+                let len = (stream_len - offset).min(sector_len);
+                pieces.push(((sector_id as u64 + 1) * sector_len as u64, len));
+                offset += len;
+            }
+        }
+        Ok(pieces)
+    }
+
+    /// Reads from the given stream, starting at the given offset, into
+    /// `buf`, using `read_at` to read from the underlying file without
+    /// needing mutable access to it.  Returns the number of bytes read,
+    /// which is less than the length of `buf` only at the end of the
+    /// stream.
+    pub fn read_stream_at(
+        &self,
+        stream_id: u32,
+        offset: u64,
+        buf: &mut [u8],
+        read_at: ReadAt<F>,
+    ) -> io::Result<usize> {
+        let pieces = self.stream_pieces(stream_id, &mut None)?;
+        let mut piece_start = 0u64;
+        let mut num_read = 0;
+        for (file_offset, len) in pieces {
+            let piece_end = piece_start + len as u64;
+            let position = offset + num_read as u64;
+            if num_read == buf.len() {
+                break;
+            }
+            if position < piece_end {
+                let skip = position - piece_start;
+                let len =
+                    ((len as u64 - skip) as usize).min(buf.len() - num_read);
+                let mut target = &mut buf[num_read..num_read + len];
+                let mut at = file_offset + skip;
+                while !target.is_empty() {
+                    match read_at(self.inner(), target, at)? {
+                        0 => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "Underlying file ended within a stream's \
+                                 data",
+                            ))
+                        }
+                        count => {
+                            target = &mut target[count..];
+                            at += count as u64;
+                        }
+                    }
+                }
+                num_read += len;
+            }
+            piece_start = piece_end;
+        }
+        Ok(num_read)
+    }
+
+    /// Reads the entire contents of each of the given streams.  Rather than
+    /// reading the streams one at a time, this first works out where in the
+    /// file every piece of each stream lives, and then reads those pieces in
+    /// order of file offset, merging physically adjacent pieces into larger
+    /// reads, so that the underlying file is read in a single forward pass.
+    pub fn read_streams(
+        &mut self,
+        stream_ids: &[u32],
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let sector_len = self.directory.sector_len();
+        let mut mini_stream_sectors: Option<Vec<u32>> = None;
+        // Each piece is (file offset, length, stream index, stream offset).
+        let mut pieces = Vec::<(u64, usize, usize, usize)>::new();
+        let mut contents = Vec::with_capacity(stream_ids.len());
+        for (index, &stream_id) in stream_ids.iter().enumerate() {
+            let stream_len = self.readable_len(stream_id) as usize;
+            contents.push(try_zeroed_vec(stream_len, "stream data")?);
+            let mut offset = 0;
+            for (file_offset, len) in
+                self.stream_pieces(stream_id, &mut mini_stream_sectors)?
+            {
+                pieces.push((file_offset, len, index, offset));
+                offset += len;
+            }
+        }
+
+        // Read the pieces in order of file offset, merging runs of adjacent
+        // (or, for shared chains, overlapping) pieces into a single read.
+        // Pieces separated by less than a sector (such as the unused end of
+        // a stream's last sector) are merged too, since reading a few unused
+        // bytes is cheaper than seeking past them.
+        pieces.sort_unstable();
+        let mut buffer = Vec::new();
+        let mut start = 0;
+        while start < pieces.len() {
+            let span_offset = pieces[start].0;
+            let mut span_end = span_offset + pieces[start].1 as u64;
+            let mut end = start + 1;
+            while end < pieces.len()
+                && pieces[end].0 <= span_end + sector_len as u64
+                && span_end - span_offset < MAX_READ_SPAN_LEN
+            {
+                span_end = span_end.max(pieces[end].0 + pieces[end].1 as u64);
+                end += 1;
+            }
+            buffer.resize((span_end - span_offset) as usize, 0);
+            self.directory
+                .read_span(span_offset, &mut buffer)
+                .map_err(|error| self.directory.diagnose_read_error(error))?;
+            for &(file_offset, len, index, offset) in &pieces[start..end] {
+                let buffer_offset = (file_offset - span_offset) as usize;
+                contents[index][offset..(offset + len)].copy_from_slice(
+                    &buffer[buffer_offset..(buffer_offset + len)],
+                );
+            }
+            start = end;
+        }
+        Ok(contents)
+    }
+}
+
 impl<F: Write + Seek> MiniAllocator<F> {
+    pub fn repair_backing_len(&mut self) -> io::Result<u64> {
+        self.directory.repair_backing_len()
+    }
+
+    /// Sets the length of the underlying file to the length that its
+    /// sectors require, using `set_len`, and returns that length.
+    pub fn truncate_backing(
+        &mut self,
+        set_len: fn(&mut F, u64) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        self.directory.truncate_backing(set_len)
+    }
+
+    /// Replaces the underlying file's contents with those of `other`, a
+    /// compacted copy of this file (see `CompactLayout`), using `install`
+    /// (see `Sectors::adopt_compacted`), and takes on its layout, keeping
+    /// this file's settings.  If self-checks are enabled, the next check
+    /// covers the whole file.
+    pub fn adopt_compacted<G, I>(
+        &mut self,
+        other: MiniAllocator<G>,
+        install: I,
+    ) -> io::Result<()>
+    where
+        I: FnOnce(&mut F, G) -> io::Result<()>,
+    {
+        self.directory.adopt_compacted(other.directory, install)?;
+        self.minifat = other.minifat;
+        self.minifat_start_sector = other.minifat_start_sector;
+        self.mini_sector_len = other.mini_sector_len;
+        self.free_mini_sectors = other.free_mini_sectors;
+        self.has_reservations = false;
+        self.shared_chains = other.shared_chains;
+        // The copy's entries are all of generation zero, but the streams
+        // they describe are the same ones as before.
+        self.short_streams = other.short_streams;
+        for (&stream_id, short) in self.short_streams.iter_mut() {
+            short.generation =
+                self.directory.generation(stream_id).unwrap_or(0);
+        }
+        if let Some(touched) = self.touched_mini_sectors.as_mut() {
+            touched.clear();
+            self.checks_since_sweep = SWEEP_INTERVAL;
+        }
+        Ok(())
+    }
+
+    /// Overwrites with zeros all space that holds no live data: free
+    /// sectors, free mini sectors, the unused ends of the (mini) sectors
+    /// holding each stream and the mini stream, and unallocated directory
+    /// entries.  Returns the number of bytes of sector data overwritten (not
+    /// counting directory entries).
+    pub fn wipe_unused_space(&mut self) -> io::Result<u64> {
+        let mut num_bytes = self.directory.wipe_free_sectors()?;
+        let root_entry = self.directory.root_dir_entry();
+        let mini_stream_start = root_entry.start_sector;
+        let mini_stream_len = root_entry.stream_len;
+        num_bytes += self.directory.wipe_chain_slack(
+            mini_stream_start,
+            mini_stream_len,
+            ChainName::MiniStream,
+        )?;
+        // Streams sharing a chain have the same length, so each chain only
+        // needs to be wiped once.
+        let chains: BTreeSet<((bool, u32), u64)> = self
+            .directory
+            .dir_entries()
+            .iter()
+            .filter_map(|dir_entry| {
+                MiniAllocator::<F>::chain_key(dir_entry)
+                    .map(|key| (key, dir_entry.stream_len))
+            })
+            .collect();
+        let mini_sector_len = self.mini_sector_len as u64;
+        let zeros = vec![0u8; self.mini_sector_len];
+        for ((is_mini, start_sector), len) in chains {
+            if !is_mini {
+                num_bytes += self.directory.wipe_chain_slack(
+                    start_sector,
+                    len,
+                    ChainName::StartingAt(start_sector),
+                )?;
+                continue;
+            }
+            let sector_ids = self.mini_chain_sector_ids(
+                start_sector,
+                ChainName::MiniStartingAt(start_sector),
+            )?;
+            for (index, &sector_id) in sector_ids.iter().enumerate() {
+                let start = index as u64 * mini_sector_len;
+                let used = len.saturating_sub(start).min(mini_sector_len);
+                if used < mini_sector_len {
+                    let mut sector =
+                        self.seek_within_mini_sector(sector_id, used)?;
+                    sector.write_all(&zeros[used as usize..])?;
+                    num_bytes += mini_sector_len - used;
+                }
+            }
+        }
+        // Free mini sectors past the end of the mini stream were wiped along
+        // with its slack above.
+        let free_mini_sectors: Vec<u32> = self
+            .free_mini_sectors
+            .iter()
+            .copied()
+            .filter(|&id| (id as u64 + 1) * mini_sector_len <= mini_stream_len)
+            .collect();
+        for &sector_id in free_mini_sectors.iter() {
+            self.seek_within_mini_sector(sector_id, 0)?.write_all(&zeros)?;
+            num_bytes += mini_sector_len;
+        }
+        self.directory.rewrite_dir_entries()?;
+        Ok(num_bytes)
+    }
+
     /// Given the start sector of a chain, deallocates the entire chain.
     pub fn free_chain(&mut self, start_sector_id: u32) -> io::Result<()> {
         self.directory.free_chain(start_sector_id)
@@ -182,6 +1972,18 @@ impl<F: Write + Seek> MiniAllocator<F> {
         self.directory.insert_dir_entry(parent_id, name, obj_type)
     }
 
+    /// Like `insert_dir_entry`, but fills in the new entry's metadata first
+    /// (see `Directory::insert_dir_entry_with`).
+    pub fn insert_dir_entry_with<G: FnOnce(&mut DirEntry)>(
+        &mut self,
+        parent_id: u32,
+        name: &str,
+        obj_type: ObjType,
+        init: G,
+    ) -> io::Result<u32> {
+        self.directory.insert_dir_entry_with(parent_id, name, obj_type, init)
+    }
+
     /// Removes a directory entry from the tree and deallocates it.
     pub fn remove_dir_entry(
         &mut self,
@@ -191,20 +1993,179 @@ impl<F: Write + Seek> MiniAllocator<F> {
         self.directory.remove_dir_entry(parent_id, name)
     }
 
+    /// Moves a directory entry to a new parent storage and gives it a new
+    /// name, keeping its stream ID (see `Directory::rename_dir_entry`).
+    pub fn rename_dir_entry(
+        &mut self,
+        parent_id: u32,
+        name: &str,
+        new_parent_id: u32,
+        new_name: &str,
+    ) -> io::Result<u32> {
+        self.directory.rename_dir_entry(
+            parent_id,
+            name,
+            new_parent_id,
+            new_name,
+        )
+    }
+
     /// Calls the given function with a mutable reference to the specified
     /// directory entry, then writes the updated directory entry to the
-    /// underlying file once the function returns.
-    pub fn with_dir_entry_mut<W>(
+    /// underlying file once the function returns, and returns what the
+    /// function returned.
+    pub fn with_dir_entry_mut<W, T>(
         &mut self,
         stream_id: u32,
         func: W,
-    ) -> io::Result<()>
+    ) -> io::Result<T>
     where
-        W: FnOnce(&mut DirEntry),
+        W: FnOnce(&mut DirEntry) -> T,
     {
         self.directory.with_dir_entry_mut(stream_id, func)
     }
 
+    /// Calls the given function with a mutable reference to each of the
+    /// specified directory entries, then writes the updated entries to the
+    /// underlying file, batching writes of adjacent entries.
+    pub fn with_dir_entries_mut<W>(
+        &mut self,
+        stream_ids: &[u32],
+        func: W,
+    ) -> io::Result<()>
+    where
+        W: FnMut(&mut DirEntry),
+    {
+        self.directory.with_dir_entries_mut(stream_ids, func)
+    }
+
+    /// Points the (empty) stream `to_stream_id` at the chain holding the data
+    /// of stream `from_stream_id`, so that the two streams share a chain.
+    pub fn share_chain(
+        &mut self,
+        from_stream_id: u32,
+        to_stream_id: u32,
+    ) -> io::Result<()> {
+        debug_assert_eq!(
+            self.dir_entry(to_stream_id).start_sector,
+            consts::END_OF_CHAIN
+        );
+        let from_entry = self.dir_entry(from_stream_id);
+        let key = match MiniAllocator::<F>::chain_key(from_entry) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let (start_sector, stream_len) =
+            (from_entry.start_sector, from_entry.stream_len);
+        self.directory.with_dir_entry_mut(to_stream_id, |dir_entry| {
+            dir_entry.start_sector = start_sector;
+            dir_entry.stream_len = stream_len;
+        })?;
+        *self.shared_chains.entry(key).or_insert(1) += 1;
+        Ok(())
+    }
+
+    /// Points stream `to_stream_id` at the chain holding the data of stream
+    /// `from_stream_id` (so that the two share it, until the caller removes
+    /// the latter), and then releases the chain that `to_stream_id` used to
+    /// have.  The directory entry is updated before anything is freed, so
+    /// that if this is interrupted, `to_stream_id` is left holding either its
+    /// old data or the new data, never freed sectors.
+    pub fn adopt_chain(
+        &mut self,
+        from_stream_id: u32,
+        to_stream_id: u32,
+    ) -> io::Result<()> {
+        let old_entry = self.dir_entry(to_stream_id);
+        let old_key = MiniAllocator::<F>::chain_key(old_entry);
+        let old_start_sector = old_entry.start_sector;
+        let from_entry = self.dir_entry(from_stream_id);
+        let new_key = MiniAllocator::<F>::chain_key(from_entry);
+        let (start_sector, stream_len) =
+            (from_entry.start_sector, from_entry.stream_len);
+        self.directory.with_dir_entry_mut(to_stream_id, |dir_entry| {
+            dir_entry.start_sector = start_sector;
+            dir_entry.stream_len = stream_len;
+        })?;
+        if let Some(key) = new_key {
+            *self.shared_chains.entry(key).or_insert(1) += 1;
+        }
+        self.release_replaced_chain(old_key, old_start_sector)
+    }
+
+    /// Makes directory entry `to_stream_id` take over the object held by
+    /// `from_stream_id` under the given name (see
+    /// `Directory::replace_dir_entry`), and then releases the chain that
+    /// `to_stream_id` used to have.  If the object is a stream, its chain is
+    /// shared by both entries until the caller removes `from_stream_id`.
+    pub fn replace_dir_entry(
+        &mut self,
+        from_stream_id: u32,
+        to_stream_id: u32,
+        name: &str,
+    ) -> io::Result<()> {
+        let new_key =
+            MiniAllocator::<F>::chain_key(self.dir_entry(from_stream_id));
+        let old_entry = self.directory.replace_dir_entry(
+            from_stream_id,
+            to_stream_id,
+            name,
+        )?;
+        if let Some(key) = new_key {
+            *self.shared_chains.entry(key).or_insert(1) += 1;
+        }
+        let old_key = MiniAllocator::<F>::chain_key(&old_entry);
+        self.release_replaced_chain(old_key, old_entry.start_sector)
+    }
+
+    /// Releases a chain that a stream no longer uses, unless other streams
+    /// still share it.
+    fn release_replaced_chain(
+        &mut self,
+        old_key: Option<(bool, u32)>,
+        old_start_sector: u32,
+    ) -> io::Result<()> {
+        let Some(old_key) = old_key else {
+            return Ok(());
+        };
+        if let Some(count) = self.shared_chains.get_mut(&old_key) {
+            // Other streams still share the old chain, so leave it allocated.
+            *count -= 1;
+            if *count <= 1 {
+                self.shared_chains.remove(&old_key);
+            }
+        } else if old_key.0 {
+            self.free_mini_chain(old_start_sector)?;
+        } else {
+            self.free_chain(old_start_sector)?;
+        }
+        Ok(())
+    }
+
+    /// If the given stream's chain is shared with other streams, detaches
+    /// the stream from it (leaving the stream empty, and the chain allocated
+    /// for the other streams) and returns true.  Otherwise, does nothing and
+    /// returns false, in which case the caller owns the chain.
+    pub fn detach_chain(&mut self, stream_id: u32) -> io::Result<bool> {
+        let Some(key) =
+            MiniAllocator::<F>::chain_key(self.dir_entry(stream_id))
+        else {
+            return Ok(false);
+        };
+        let Some(count) = self.shared_chains.get_mut(&key) else {
+            return Ok(false);
+        };
+        *count -= 1;
+        if *count <= 1 {
+            self.shared_chains.remove(&key);
+        }
+        self.directory.with_dir_entry_mut(stream_id, |dir_entry| {
+            dir_entry.start_sector = consts::END_OF_CHAIN;
+            dir_entry.stream_len = 0;
+        })?;
+        Ok(true)
+    }
+
     /// Allocates a new mini chain with one sector, and returns the starting
     /// sector number.
     pub fn begin_mini_chain(&mut self) -> io::Result<u32> {
@@ -237,16 +2198,15 @@ impl<F: Write + Seek> MiniAllocator<F> {
     /// returns the new mini sector number.
     fn allocate_mini_sector(&mut self, value: u32) -> io::Result<u32> {
         // If there's an existing free mini sector, use that.
-        for mini_sector in 0..self.minifat.len() {
-            if self.minifat[mini_sector] == consts::FREE_SECTOR {
-                let mini_sector = mini_sector as u32;
-                self.set_minifat(mini_sector, value)?;
-                return Ok(mini_sector);
-            }
+        if let Some(&mini_sector) = self.free_mini_sectors.first() {
+            self.set_minifat(mini_sector, value)?;
+            return Ok(mini_sector);
         }
         // Otherwise, we need a new mini sector; if there's not room in the
         // MiniFAT to add it, then first we need to allocate a new MiniFAT
         // sector.
+        // (The MiniFAT chain may already have spare capacity, e.g. if it was
+        // reserved when the file was created.)
         let minifat_entries_per_sector = self.directory.sector_len() / 4;
         if self.minifat_start_sector == consts::END_OF_CHAIN {
             debug_assert!(self.minifat.is_empty());
@@ -257,13 +2217,17 @@ impl<F: Write + Seek> MiniAllocator<F> {
             header.write_le_u32(1)?;
         } else if self.minifat.len() % minifat_entries_per_sector == 0 {
             let start = self.minifat_start_sector;
-            self.directory.extend_chain(start, SectorInit::Fat)?;
             let num_minifat_sectors = self
                 .directory
                 .open_chain(start, SectorInit::Fat)?
-                .num_sectors() as u32;
-            let mut header = self.directory.seek_within_header(64)?;
-            header.write_le_u32(num_minifat_sectors)?;
+                .num_sectors();
+            if self.minifat.len()
+                >= num_minifat_sectors * minifat_entries_per_sector
+            {
+                self.directory.extend_chain(start, SectorInit::Fat)?;
+                let mut header = self.directory.seek_within_header(64)?;
+                header.write_le_u32(num_minifat_sectors as u32 + 1)?;
+            }
         }
         // Add a new mini sector to the end of the mini stream and return it.
         let new_mini_sector = self.minifat.len() as u32;
@@ -274,43 +2238,65 @@ impl<F: Write + Seek> MiniAllocator<F> {
 
     /// Adds a new mini sector to the end of the mini stream.
     fn append_mini_sector(&mut self) -> io::Result<()> {
+        // Any sector added here is for the mini stream itself, rather than
+        // for the stream whose data is being written into it.
+        let stream_path = self.directory.set_stream_path(None);
+        let result = self.extend_mini_stream_chain();
+        self.directory.set_stream_path(stream_path);
+        let new_start_sector = result?;
+
+        // Update length of mini stream in root directory entry.
+        let mini_sector_len = self.mini_sector_len as u64;
+        self.directory.with_root_dir_entry_mut(|dir_entry| {
+            dir_entry.start_sector = new_start_sector;
+            dir_entry.stream_len += mini_sector_len;
+        })
+    }
+
+    /// Makes sure that the mini stream's chain has room for one more mini
+    /// sector, and returns the chain's (possibly new) start sector.
+    fn extend_mini_stream_chain(&mut self) -> io::Result<u32> {
         let mini_stream_start_sector =
             self.directory.root_dir_entry().start_sector;
         let mini_stream_len = self.directory.root_dir_entry().stream_len;
-        debug_assert_eq!(mini_stream_len % consts::MINI_SECTOR_LEN as u64, 0);
+        debug_assert_eq!(mini_stream_len % self.mini_sector_len as u64, 0);
         let sector_len = self.directory.sector_len();
 
         // If the mini stream doesn't have room for new mini sector, add
-        // another regular sector to its chain.
-        let new_start_sector =
-            if mini_stream_start_sector == consts::END_OF_CHAIN {
-                debug_assert_eq!(mini_stream_len, 0);
-                self.directory.begin_chain(SectorInit::Zero)?
-            } else {
-                if mini_stream_len % sector_len as u64 == 0 {
-                    self.directory.extend_chain(
-                        mini_stream_start_sector,
-                        SectorInit::Zero,
-                    )?;
-                }
-                mini_stream_start_sector
-            };
-
-        // Update length of mini stream in root directory entry.
-        self.directory.with_root_dir_entry_mut(|dir_entry| {
-            dir_entry.start_sector = new_start_sector;
-            dir_entry.stream_len += consts::MINI_SECTOR_LEN as u64;
-        })
+        // another regular sector to its chain.  (The chain may already extend
+        // past the end of the mini stream, e.g. if mini sectors were freed or
+        // if the mini stream was reserved when the file was created.)
+        if mini_stream_start_sector == consts::END_OF_CHAIN {
+            debug_assert_eq!(mini_stream_len, 0);
+            return self.directory.begin_chain(SectorInit::Zero);
+        }
+        if mini_stream_len % sector_len as u64 == 0
+            && self
+                .directory
+                .open_chain(mini_stream_start_sector, SectorInit::Zero)?
+                .len()
+                <= mini_stream_len
+        {
+            self.directory
+                .extend_chain(mini_stream_start_sector, SectorInit::Zero)?;
+        }
+        Ok(mini_stream_start_sector)
     }
 
-    /// Deallocates the specified mini sector.
+    /// Deallocates the specified mini sector, first overwriting it with
+    /// zeros if `zero_freed_sectors` is set.
     fn free_mini_sector(&mut self, mini_sector: u32) -> io::Result<()> {
+        if self.zero_freed_sectors() {
+            let zeros = vec![0u8; self.mini_sector_len];
+            self.seek_within_mini_sector(mini_sector, 0)?.write_all(&zeros)?;
+        }
         self.set_minifat(mini_sector, consts::FREE_SECTOR)?;
         let mut mini_stream_len = self.directory.root_dir_entry().stream_len;
-        debug_assert_eq!(mini_stream_len % consts::MINI_SECTOR_LEN as u64, 0);
+        debug_assert_eq!(mini_stream_len % self.mini_sector_len as u64, 0);
         while self.minifat.last() == Some(&consts::FREE_SECTOR) {
-            mini_stream_len -= consts::MINI_SECTOR_LEN as u64;
+            mini_stream_len -= self.mini_sector_len as u64;
             self.minifat.pop();
+            self.free_mini_sectors.remove(&(self.minifat.len() as u32));
             // TODO: Truncate MiniFAT if last MiniFAT sector is now all free.
         }
 
@@ -348,6 +2334,63 @@ impl<F: Write + Seek> MiniAllocator<F> {
         Ok(())
     }
 
+    /// Releases any sectors that were reserved when the file was created (see
+    /// `CreateOptions`) but that are still unused.  Does nothing if there are
+    /// no reservations.
+    pub fn release_reservations(&mut self) -> io::Result<()> {
+        if !self.has_reservations {
+            return Ok(());
+        }
+        self.has_reservations = false;
+        let sector_len = self.directory.sector_len() as u64;
+
+        // Trim the mini stream chain down to the length of the mini stream.
+        let root_entry = self.directory.root_dir_entry();
+        let mini_stream_start_sector = root_entry.start_sector;
+        let mini_stream_len = root_entry.stream_len;
+        if mini_stream_start_sector != consts::END_OF_CHAIN {
+            self.directory
+                .open_chain(mini_stream_start_sector, SectorInit::Zero)?
+                .set_len(mini_stream_len)?;
+            if mini_stream_len == 0 {
+                self.directory.with_root_dir_entry_mut(|dir_entry| {
+                    dir_entry.start_sector = consts::END_OF_CHAIN;
+                })?;
+            }
+        }
+
+        // Trim the MiniFAT chain down to the length of the MiniFAT.
+        if self.minifat_start_sector != consts::END_OF_CHAIN {
+            let minifat_len = (self.minifat.len() * size_of::<u32>()) as u64;
+            let num_minifat_sectors = minifat_len.div_ceil(sector_len);
+            self.directory
+                .open_chain(self.minifat_start_sector, SectorInit::Fat)?
+                .set_len(minifat_len)?;
+            if num_minifat_sectors == 0 {
+                self.minifat_start_sector = consts::END_OF_CHAIN;
+            }
+            let mut header = self.directory.seek_within_header(60)?;
+            header.write_le_u32(self.minifat_start_sector)?;
+            header.write_le_u32(num_minifat_sectors as u32)?;
+        }
+
+        self.directory.release_unused_dir_sectors()?;
+        self.directory.release_unused_fat_sectors()
+    }
+
+    pub fn release_unused_dir_sectors(&mut self) -> io::Result<u32> {
+        self.directory.release_unused_dir_sectors()
+    }
+
+    /// Drops the free sectors at the end of the file, along with any FAT and
+    /// DIFAT sectors that only describe them, and returns the new length of
+    /// the file in bytes.
+    pub fn release_free_tail(&mut self) -> io::Result<u64> {
+        let num_sectors = self.directory.release_free_tail()?;
+        let sector_len = self.directory.sector_len() as u64;
+        Ok((num_sectors as u64 + 1) * sector_len)
+    }
+
     /// Sets `self.minifat[index] = value`, and also writes that change to the
     /// underlying file.  The `index` must be <= `self.minifat.len()`.
     fn set_minifat(&mut self, index: u32, value: u32) -> io::Result<()> {
@@ -364,6 +2407,14 @@ impl<F: Write + Seek> MiniAllocator<F> {
         } else {
             self.minifat[index as usize] = value;
         }
+        if value == consts::FREE_SECTOR {
+            self.free_mini_sectors.insert(index);
+        } else {
+            self.free_mini_sectors.remove(&index);
+        }
+        if let Some(touched) = self.touched_mini_sectors.as_mut() {
+            touched.insert(index);
+        }
         Ok(())
     }
 
@@ -371,6 +2422,67 @@ impl<F: Write + Seek> MiniAllocator<F> {
     pub fn flush(&mut self) -> io::Result<()> {
         self.directory.flush()
     }
+
+    pub fn rewrite_header_version(&mut self) -> io::Result<()> {
+        self.directory.rewrite_header_version()
+    }
+}
+
+impl<F: Read + Write + Seek> MiniAllocator<F> {
+    pub fn reset_unused_header_fields(&mut self) -> io::Result<bool> {
+        self.directory.reset_unused_header_fields()
+    }
+
+    /// Overwrites the bytes of the given stream starting at `offset` with
+    /// `bytes`, in place: only the bytes being replaced are written, into
+    /// the (mini) sectors already holding them, and the stream's chain and
+    /// directory entry are left as they are.  The caller must have checked
+    /// that the bytes lie within the stream's length, and that its chain
+    /// isn't shared.
+    pub fn patch_stream(
+        &mut self,
+        stream_id: u32,
+        offset: u64,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        debug_assert!(!self.is_shared(stream_id));
+        let end = offset + bytes.len() as u64;
+        let readable_len = self.readable_len(stream_id);
+        if end > readable_len {
+            invalid_data!(
+                "Chain for stream {} holds only {} bytes, too few to patch \
+                 bytes {}..{}",
+                stream_id,
+                readable_len,
+                offset,
+                end
+            );
+        }
+        let sector_len = self.directory.sector_len() as u64;
+        let pieces = self.stream_pieces(stream_id, &mut None)?;
+        self.audit_stream_modified(stream_id);
+        let mut piece_start = 0u64;
+        for (file_offset, len) in pieces {
+            let piece_end = piece_start + len as u64;
+            if piece_start >= end {
+                break;
+            }
+            if piece_end > offset {
+                let from = offset.max(piece_start);
+                let to = end.min(piece_end);
+                let at = file_offset + (from - piece_start);
+                let sector_id = (at / sector_len - 1) as u32;
+                self.directory.write_span(
+                    sector_id,
+                    at % sector_len,
+                    &bytes[(from - offset) as usize..(to - offset) as usize],
+                )?;
+            }
+            piece_start = piece_end;
+        }
+        self.audit_stream_done(stream_id, false);
+        Ok(())
+    }
 }
 
 //===========================================================================//
@@ -403,8 +2515,15 @@ mod tests {
         let sectors = Sectors::new(version, data_len as u64, cursor);
         let mut fat = vec![consts::END_OF_CHAIN; num_sectors];
         fat[0] = consts::FAT_SECTOR;
-        let allocator =
-            Allocator::new(sectors, vec![], vec![0], fat, validation).unwrap();
+        let allocator = Allocator::new(
+            sectors,
+            vec![],
+            vec![0],
+            fat,
+            validation,
+            &mut Vec::new(),
+        )
+        .unwrap();
         let mut root_entry = DirEntry::empty_root_entry();
         root_entry.child = 1;
         root_entry.start_sector = 3;
@@ -415,8 +2534,27 @@ mod tests {
         stream_entry.stream_len = root_entry.stream_len;
         let entries = vec![root_entry, stream_entry];
         let directory =
-            Directory::new(allocator, entries, 1, validation).unwrap();
-        MiniAllocator::new(directory, minifat, 2, validation).unwrap()
+            Directory::new(allocator, entries, 1, validation, &mut Vec::new())
+                .unwrap();
+        MiniAllocator::new(
+            directory,
+            minifat,
+            2,
+            consts::MINI_SECTOR_SHIFT,
+            validation,
+            &mut Vec::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Malformed directory (root stream len is 147, but should \
+                    be multiple of 64)"
+    )]
+    fn invalid_mini_stream_len() {
+        let minifat = vec![1, 2, consts::END_OF_CHAIN];
+        make_minialloc_with_root_stream_len(minifat, 147);
     }
 
     #[test]
@@ -448,6 +2586,88 @@ mod tests {
         let minifat = vec![1, 2, 1];
         make_minialloc(minifat);
     }
+
+    #[test]
+    fn self_check_consistent() {
+        let mut minialloc = make_minialloc(vec![1, 2, consts::END_OF_CHAIN]);
+        minialloc.set_self_check(true);
+        minialloc.set_minifat(2, 3).unwrap();
+        minialloc.set_minifat(3, consts::END_OF_CHAIN).unwrap();
+        minialloc.self_check("extending chain");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(
+        expected = "Self-check failed after bad write: MiniFAT entry 1 points \
+                    to 5, past the end of the table"
+    )]
+    fn self_check_bad_minifat_entry() {
+        let mut minialloc = make_minialloc(vec![1, 2, consts::END_OF_CHAIN]);
+        minialloc.set_self_check(true);
+        minialloc.set_minifat(1, 5).unwrap();
+        minialloc.self_check("bad write");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(
+        expected = "Self-check failed after bad link: entry 1 has left \
+                    sibling 7, but directory entry count is 2"
+    )]
+    fn self_check_bad_dir_entry() {
+        let mut minialloc = make_minialloc(vec![1, 2, consts::END_OF_CHAIN]);
+        minialloc.set_self_check(true);
+        minialloc
+            .with_dir_entry_mut(1, |dir_entry| dir_entry.left_sibling = 7)
+            .unwrap();
+        minialloc.self_check("bad link");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(
+        expected = "Self-check failed after bad length: entry 1 (\"foo\") \
+                    has length 1000, but its chain holds only 3 sectors of \
+                    64 bytes"
+    )]
+    fn self_check_stream_len_past_chain() {
+        let mut minialloc = make_minialloc(vec![1, 2, consts::END_OF_CHAIN]);
+        minialloc.set_self_check(true);
+        minialloc
+            .with_dir_entry_mut(1, |dir_entry| dir_entry.stream_len = 1000)
+            .unwrap();
+        minialloc.self_check("bad length");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(
+        expected = "Self-check failed after operation 99: MiniFAT entry 1 \
+                    points to free entry 2"
+    )]
+    fn self_check_sweep_finds_untracked_change() {
+        let mut minialloc = make_minialloc(vec![1, 2, consts::END_OF_CHAIN]);
+        minialloc.set_self_check(true);
+        // Changes that bypass `set_minifat` are only caught by a full sweep.
+        minialloc.minifat[2] = consts::FREE_SECTOR;
+        minialloc.free_mini_sectors.insert(2);
+        for index in 0..super::SWEEP_INTERVAL {
+            minialloc.self_check(&format!("operation {}", index));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(
+        expected = "Self-check failed after enabling self-checks: MiniFAT \
+                    entry 0 is 0x00000001, but is listed as free"
+    )]
+    fn self_check_baseline() {
+        let mut minialloc = make_minialloc(vec![1, 2, consts::END_OF_CHAIN]);
+        minialloc.free_mini_sectors.insert(0);
+        minialloc.set_self_check(true);
+    }
 }
 
 //===========================================================================//
@@ -1,4 +1,4 @@
-use crate::internal::{consts, MiniAllocator};
+use crate::internal::{consts, ChainName, MiniAllocator};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 
 //===========================================================================//
@@ -14,20 +14,10 @@ impl<'a, F> MiniChain<'a, F> {
         minialloc: &'a mut MiniAllocator<F>,
         start_sector_id: u32,
     ) -> io::Result<MiniChain<'a, F>> {
-        let mut sector_ids = Vec::<u32>::new();
-        let mut current_sector_id = start_sector_id;
-        let first_sector_id = start_sector_id;
-        while current_sector_id != consts::END_OF_CHAIN {
-            sector_ids.push(current_sector_id);
-            current_sector_id =
-                minialloc.next_mini_sector(current_sector_id)?;
-            if current_sector_id == first_sector_id {
-                invalid_data!(
-                    "Minichain contained duplicate sector id {}",
-                    current_sector_id
-                );
-            }
-        }
+        let sector_ids = minialloc.mini_chain_sector_ids(
+            start_sector_id,
+            ChainName::MiniStartingAt(start_sector_id),
+        )?;
         Ok(MiniChain { minialloc, sector_ids, offset_from_start: 0 })
     }
 
@@ -36,7 +26,8 @@ impl<'a, F> MiniChain<'a, F> {
     }
 
     pub fn len(&self) -> u64 {
-        (consts::MINI_SECTOR_LEN as u64) * (self.sector_ids.len() as u64)
+        (self.minialloc.mini_sector_len() as u64)
+            * (self.sector_ids.len() as u64)
     }
 }
 
@@ -45,7 +36,7 @@ impl<'a, F: Read + Write + Seek> MiniChain<'a, F> {
     /// `new_len` bytes, allocating or freeing sectors as needed.
     pub fn set_len(&mut self, new_len: u64) -> io::Result<()> {
         debug_assert!(new_len < consts::MINI_STREAM_CUTOFF as u64);
-        let sector_len = consts::MINI_SECTOR_LEN as u64;
+        let sector_len = self.minialloc.mini_sector_len() as u64;
         let new_num_sectors =
             ((sector_len + new_len - 1) / sector_len) as usize;
         if new_num_sectors == 0 {
@@ -72,10 +63,6 @@ impl<'a, F: Read + Write + Seek> MiniChain<'a, F> {
         }
         Ok(())
     }
-
-    pub fn free(self) -> io::Result<()> {
-        self.minialloc.free_mini_chain(self.start_sector_id())
-    }
 }
 
 impl<'a, F> Seek for MiniChain<'a, F> {
@@ -107,7 +94,7 @@ impl<'a, F: Read + Seek> Read for MiniChain<'a, F> {
         if max_len == 0 {
             return Ok(0);
         }
-        let sector_len = consts::MINI_SECTOR_LEN as u64;
+        let sector_len = self.minialloc.mini_sector_len() as u64;
         let current_sector_index =
             (self.offset_from_start / sector_len) as usize;
         debug_assert!(current_sector_index < self.sector_ids.len());
@@ -130,7 +117,7 @@ impl<'a, F: Read + Write + Seek> Write for MiniChain<'a, F> {
             return Ok(0);
         }
         let mut total_len = self.len();
-        let sector_len = consts::MINI_SECTOR_LEN as u64;
+        let sector_len = self.minialloc.mini_sector_len() as u64;
         if self.offset_from_start == total_len {
             let new_sector_id =
                 if let Some(&last_sector_id) = self.sector_ids.last() {
@@ -1,36 +1,132 @@
 #[macro_use]
 mod macros;
 
+mod aligned;
 mod alloc;
+mod audit;
+mod backing;
+mod capabilities;
 mod chain;
+mod clsid;
 mod color;
+mod compact;
 pub mod consts;
+mod deleted;
 mod directory;
 mod direntry;
 mod entry;
+mod glob;
 mod header;
+mod ids;
+mod import;
+pub mod ioutil;
+mod limit;
+mod memory;
+mod merge;
+mod metadata;
+mod metrics;
 mod minialloc;
 mod minichain;
+#[cfg(any(feature = "cli", feature = "msi"))]
+mod msi_name;
 mod objtype;
+mod options;
 pub mod path;
+mod policy;
+mod recover;
+mod resolved;
+mod sanitize;
+mod scan;
 mod sector;
+mod signature;
+mod snapshot;
+mod split;
+mod spool;
+mod stats;
 mod stream;
+mod sync;
 mod timestamp;
+mod touch;
 mod validate;
+mod verify;
 mod version;
 
+pub use self::aligned::{is_aligned, AlignedBytes, AlignedSlice};
 pub use self::alloc::Allocator;
-pub use self::chain::Chain;
+pub use self::audit::{read_audit_records, AuditLog, AuditOp, AuditRecord};
+pub use self::backing::BackingFileShrunk;
+pub use self::capabilities::{Backing, Capabilities, ReadAt, Unsupported};
+pub use self::chain::{next_in_chain, Chain, ChainName};
+pub use self::clsid::ClsidPolicy;
 pub use self::color::Color;
+pub use self::compact::{CompactLayout, SPOOL_THRESHOLD};
+pub use self::deleted::{DeletedEntry, FreeEntryPolicy};
 pub use self::directory::Directory;
 pub use self::direntry::DirEntry;
-pub use self::entry::{Entries, EntriesOrder, Entry};
+pub use self::entry::{
+    Entries, EntriesOrder, Entry, EntryKind, EntryMetadata,
+};
+pub use self::glob::glob_match;
 pub use self::header::Header;
+pub use self::ids::{SectorId, StreamId};
+pub use self::import::{ImportFailure, ImportOptions, ImportReport};
+pub use self::limit::{
+    FileTooLarge, RequiresVersion4, TooLargeToBuffer, DEFAULT_MAX_BUFFER_LEN,
+};
+pub use self::memory::{try_reserve, try_vec_with_capacity, try_zeroed_vec};
+pub use self::merge::{merge, MergeOptions, MergeReport, MergeSourceReport};
+pub use self::metadata::MetadataFields;
+#[cfg(feature = "metrics")]
+pub use self::metrics::{AtomicMetrics, MetricsSink, OpTotals};
+pub use self::metrics::{Metrics, Op, Timer};
 pub use self::minialloc::MiniAllocator;
 pub use self::minichain::MiniChain;
+#[cfg(feature = "msi")]
+pub use self::msi_name::to_b64;
+#[cfg(any(feature = "cli", feature = "msi"))]
+pub use self::msi_name::{
+    decode_msi_chars, decode_msi_name, decode_msi_name_into, encode_msi_name,
+    encode_msi_name_into, MsiNameChars,
+};
 pub use self::objtype::ObjType;
-pub use self::sector::{Sector, SectorInit, Sectors};
-pub use self::stream::Stream;
+pub use self::options::{CreateOptions, NewEntryOptions};
+pub use self::path::{ObjectNotFound, PathThroughStream};
+pub use self::policy::{
+    AllocContext, ClusterMetadataFirst, FirstFree, SectorAllocator,
+    SectorPurpose,
+};
+pub use self::recover::{
+    recover_chains, recover_links, recover_tree, RecoveryWarning,
+    RecoveryWarningKind,
+};
+pub use self::resolved::ResolvedPath;
+pub use self::sanitize::{
+    is_property_set_stream, scrub_property_set, SanitizeOptions,
+    SanitizeReport,
+};
+pub use self::scan::{
+    scan_dir, ScanDir, ScanEntry, ScanOptions, ScanOutcome, ScanResult,
+};
+pub use self::sector::{ReadOnly, Sector, SectorInit, Sectors};
+pub use self::signature::{
+    compare_names_for_signature, is_signature_stream_name, SignatureContent,
+    DIGITAL_SIGNATURE_STREAM_NAME, MSI_DIGITAL_SIGNATURE_EX_STREAM_NAME,
+};
+pub use self::snapshot::{
+    ExtraEntries, MetadataSnapshot, MissingEntries, RestorePolicy,
+    RestoreReport, SnapshotEntry,
+};
+pub use self::split::{split, SplitOptions, SplitReport};
+pub use self::spool::{Spool, SpoolPolicy};
+pub use self::stats::{Reachability, Stats};
+pub use self::stream::{Stream, StreamReader, StreamsStillOpen};
+pub use self::sync::{SyncOptions, SyncReport};
 pub use self::timestamp::Timestamp;
-pub use self::validate::Validation;
+pub use self::touch::TouchOptions;
+pub use self::validate::{
+    Severity, Validation, ValidationIssue, ValidationIssueKind,
+};
+pub use self::verify::{
+    ResumeToken, StreamVerification, VerifyOptions, VerifyReport,
+};
 pub use self::version::Version;
@@ -0,0 +1,197 @@
+use std::fmt;
+
+//===========================================================================//
+
+// The stream name encoding used by Windows Installer (MSI) packages, shared
+// by the `tool` and `msi` modules.
+
+const MSI_TABLE_PREFIX: char = '\u{4840}';
+
+fn from_b64(value: u32) -> char {
+    debug_assert!(value < 64);
+    if value < 10 {
+        char::from_u32(value + '0' as u32).unwrap()
+    } else if value < 36 {
+        char::from_u32(value - 10 + 'A' as u32).unwrap()
+    } else if value < 62 {
+        char::from_u32(value - 36 + 'a' as u32).unwrap()
+    } else if value == 62 {
+        '.'
+    } else {
+        '_'
+    }
+}
+
+pub fn to_b64(chr: char) -> Option<u32> {
+    match chr {
+        '0'..='9' => Some(chr as u32 - '0' as u32),
+        'A'..='Z' => Some(chr as u32 - 'A' as u32 + 10),
+        'a'..='z' => Some(chr as u32 - 'a' as u32 + 36),
+        '.' => Some(62),
+        '_' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decodes a stream name that was encoded in the way that Windows Installer
+/// (MSI) packages encode their stream names, and returns the decoded name and
+/// whether the stream was a table.  Names that are not encoded are returned
+/// unchanged.
+pub fn decode_msi_name(name: &str) -> (String, bool) {
+    let chars = decode_msi_chars(name);
+    let is_table = chars.is_table();
+    (chars.collect(), is_table)
+}
+
+/// Like [`decode_msi_name`](fn.decode_msi_name.html), but writes the decoded
+/// name to `out` instead of allocating a new string, and returns whether the
+/// stream was a table.
+pub fn decode_msi_name_into<W: fmt::Write + ?Sized>(
+    name: &str,
+    out: &mut W,
+) -> Result<bool, fmt::Error> {
+    let chars = decode_msi_chars(name);
+    let is_table = chars.is_table();
+    for chr in chars {
+        out.write_char(chr)?;
+    }
+    Ok(is_table)
+}
+
+/// Returns an iterator over the characters of a decoded MSI stream name,
+/// without allocating.  Whether the stream was a table is available from
+/// [`MsiNameChars::is_table`](struct.MsiNameChars.html#method.is_table).
+pub fn decode_msi_chars(name: &str) -> MsiNameChars<'_> {
+    let mut chars = name.chars();
+    let is_table = name.starts_with(MSI_TABLE_PREFIX);
+    if is_table {
+        chars.next();
+    }
+    MsiNameChars { chars, pending: None, is_table }
+}
+
+/// An iterator over the characters of a decoded MSI stream name.
+///
+/// This struct is created by
+/// [`decode_msi_chars`](fn.decode_msi_chars.html).
+#[derive(Clone, Debug)]
+pub struct MsiNameChars<'a> {
+    chars: std::str::Chars<'a>,
+    pending: Option<char>,
+    is_table: bool,
+}
+
+impl MsiNameChars<'_> {
+    /// Returns true if the encoded name was marked as a table.
+    pub fn is_table(&self) -> bool {
+        self.is_table
+    }
+}
+
+impl Iterator for MsiNameChars<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if let Some(chr) = self.pending.take() {
+            return Some(chr);
+        }
+        let chr = self.chars.next()?;
+        let value = chr as u32;
+        if (0x3800..0x4800).contains(&value) {
+            let value = value - 0x3800;
+            self.pending = Some(from_b64(value >> 6));
+            Some(from_b64(value & 0x3f))
+        } else if (0x4800..0x4840).contains(&value) {
+            Some(from_b64(value - 0x4800))
+        } else {
+            Some(chr)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Each remaining input character decodes to one or two characters.
+        let pending = self.pending.is_some() as usize;
+        let (min, max) = self.chars.size_hint();
+        let max = max.and_then(|max| max.checked_mul(2));
+        (min + pending, max.and_then(|max| max.checked_add(pending)))
+    }
+}
+
+impl std::iter::FusedIterator for MsiNameChars<'_> {}
+
+/// Encodes a stream name in the way that Windows Installer (MSI) packages
+/// encode their stream names.  This is the inverse of
+/// [`decode_msi_name`](fn.decode_msi_name.html).
+pub fn encode_msi_name(name: &str, is_table: bool) -> String {
+    let mut output = String::with_capacity(name.len());
+    encode_msi_name_into(name, is_table, &mut output)
+        .expect("writing to a String cannot fail");
+    output
+}
+
+/// Like [`encode_msi_name`](fn.encode_msi_name.html), but writes the encoded
+/// name to `out` instead of allocating a new string.
+pub fn encode_msi_name_into<W: fmt::Write + ?Sized>(
+    name: &str,
+    is_table: bool,
+    out: &mut W,
+) -> fmt::Result {
+    if is_table {
+        out.write_char(MSI_TABLE_PREFIX)?;
+    }
+    let mut chars = name.chars().peekable();
+    while let Some(chr) = chars.next() {
+        match to_b64(chr) {
+            Some(value1) => {
+                let value = match chars.peek().copied().and_then(to_b64) {
+                    Some(value2) => {
+                        chars.next();
+                        0x3800 + value1 + (value2 << 6)
+                    }
+                    None => 0x4800 + value1,
+                };
+                out.write_char(char::from_u32(value).unwrap())?;
+            }
+            None => out.write_char(chr)?,
+        }
+    }
+    Ok(())
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_msi_name, decode_msi_name_into, encode_msi_name};
+
+    #[test]
+    fn msi_name_round_trip() {
+        for &(name, is_table) in &[
+            ("Property", true),
+            ("_Validation", true),
+            ("Binary.icon", false),
+            ("odd length", false),
+            ("", true),
+            ("\u{5}SummaryInformation", false),
+        ] {
+            let encoded = encode_msi_name(name, is_table);
+            assert_eq!(
+                decode_msi_name(&encoded),
+                (name.to_string(), is_table)
+            );
+        }
+        let encoded = encode_msi_name("ab", false);
+        assert_eq!(encoded.chars().count(), 1);
+        assert_eq!(decode_msi_name("plain"), ("plain".to_string(), false));
+    }
+
+    #[test]
+    fn msi_name_decode_into() {
+        let mut name = String::new();
+        let encoded = encode_msi_name("Property", true);
+        assert_eq!(decode_msi_name_into(&encoded, &mut name), Ok(true));
+        assert_eq!(name, "Property");
+    }
+}
+
+//===========================================================================//
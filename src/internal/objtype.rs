@@ -2,16 +2,22 @@ use crate::internal::consts;
 
 //===========================================================================//
 
-/// The type of a directory entry.
+/// The type of a directory entry, as stored in its object type byte (see
+/// [`raw_dir_entries`](struct.CompoundFile.html#method.raw_dir_entries)).
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum ObjType {
+    /// An unused directory entry.
     Unallocated,
+    /// A storage other than the root.
     Storage,
+    /// A stream.
     Stream,
+    /// The root storage.
     Root,
 }
 
 impl ObjType {
+    /// Returns the byte that represents this type on disk.
     pub fn as_byte(&self) -> u8 {
         match self {
             ObjType::Unallocated => consts::OBJ_TYPE_UNALLOCATED,
@@ -21,6 +27,8 @@ impl ObjType {
         }
     }
 
+    /// Returns the type represented by the given byte, or `None` if the byte
+    /// isn't a valid object type.
     pub fn from_byte(byte: u8) -> Option<ObjType> {
         if byte == consts::OBJ_TYPE_UNALLOCATED {
             Some(ObjType::Unallocated)
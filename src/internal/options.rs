@@ -0,0 +1,364 @@
+use crate::internal::{consts, Timestamp, Version};
+use std::time::SystemTime;
+
+//===========================================================================//
+
+/// Options for creating a new compound file, as used by
+/// [`CompoundFile::create_with_options`](../struct.CompoundFile.html#method.create_with_options).
+///
+/// By default, a new compound file starts out with a single FAT sector and a
+/// single directory sector, and everything else is allocated as the file
+/// grows.  If the caller knows up front roughly how much will be written, the
+/// capacity hints below can be used to pre-allocate the FAT, DIFAT, directory,
+/// MiniFAT, and mini stream as one contiguous region at the front of the file,
+/// so that stream data subsequently lands in one contiguous region after the
+/// metadata.
+///
+/// Any reserved sectors that are still unused are released the next time
+/// [`CompoundFile::flush`](../struct.CompoundFile.html#method.flush) is
+/// called, unless `keep_unused_reservations(true)` was set.
+///
+/// ```
+/// use cfb::{CompoundFile, CreateOptions, Version};
+/// use std::io::Cursor;
+///
+/// let options = CreateOptions::new()
+///     .version(Version::V3)
+///     .expected_streams(100)
+///     .expected_total_bytes(1 << 20);
+/// let cursor = Cursor::new(Vec::new());
+/// let comp = CompoundFile::create_with_options(options, cursor).unwrap();
+/// assert_eq!(comp.version(), Version::V3);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreateOptions {
+    pub(crate) version: Version,
+    pub(crate) expected_streams: u64,
+    pub(crate) expected_total_bytes: u64,
+    pub(crate) expected_small_stream_bytes: u64,
+    pub(crate) keep_unused_reservations: bool,
+    pub(crate) stamp_root_modified_time: bool,
+}
+
+impl CreateOptions {
+    /// Returns the default options: a version 4 file with no capacity hints.
+    pub fn new() -> CreateOptions {
+        CreateOptions {
+            version: Version::V4,
+            expected_streams: 0,
+            expected_total_bytes: 0,
+            expected_small_stream_bytes: 0,
+            keep_unused_reservations: false,
+            stamp_root_modified_time: false,
+        }
+    }
+
+    /// Sets the CFB format version to use.
+    pub fn version(mut self, version: Version) -> CreateOptions {
+        self.version = version;
+        self
+    }
+
+    /// Hints the number of streams (and storages) that will be created, so
+    /// that enough directory sectors can be reserved up front.
+    pub fn expected_streams(mut self, num_streams: u64) -> CreateOptions {
+        self.expected_streams = num_streams;
+        self
+    }
+
+    /// Hints the total number of bytes of stream data that will be written,
+    /// so that enough FAT (and, if necessary, DIFAT) sectors can be reserved
+    /// up front.
+    pub fn expected_total_bytes(mut self, num_bytes: u64) -> CreateOptions {
+        self.expected_total_bytes = num_bytes;
+        self
+    }
+
+    /// Hints the total number of bytes that will be written to streams small
+    /// enough to be stored in the mini stream, so that the mini stream and
+    /// MiniFAT can be reserved up front.
+    pub fn expected_small_stream_bytes(
+        mut self,
+        num_bytes: u64,
+    ) -> CreateOptions {
+        self.expected_small_stream_bytes = num_bytes;
+        self
+    }
+
+    /// If true, reserved sectors that are still unused when the compound file
+    /// is flushed are kept in the file rather than being released.  This is
+    /// useful if more data will be appended in a later session.  Defaults to
+    /// false.
+    pub fn keep_unused_reservations(mut self, keep: bool) -> CreateOptions {
+        self.keep_unused_reservations = keep;
+        self
+    }
+
+    /// If true, the root entry's modified time is set to the time the file
+    /// is created, as some other implementations do.  If false, it is left
+    /// zero, which section 2.6.2 of the MS-CFB spec allows and which most
+    /// files have.  Defaults to false.
+    ///
+    /// Either way, the root's creation time is zero, since the spec requires
+    /// it to be, and nothing afterwards changes the root's modified time
+    /// except
+    /// [`set_modified_time`](../struct.CompoundFile.html#method.set_modified_time)
+    /// and the like.  Files that are opened rather than created keep
+    /// whatever root times they already have.
+    pub fn stamp_root_modified_time(mut self, stamp: bool) -> CreateOptions {
+        self.stamp_root_modified_time = stamp;
+        self
+    }
+
+    pub(crate) fn has_reservations(&self) -> bool {
+        self.layout() != InitialLayout::minimal()
+    }
+
+    /// Returns how many sectors the file is expected to end up with, once all
+    /// the hinted data has been written.
+    pub(crate) fn expected_num_sectors(&self) -> u64 {
+        self.layout().num_sectors() + self.num_data_sectors()
+    }
+
+    /// Returns how many regular sectors the hinted stream data is expected to
+    /// need, outside of the mini stream.
+    fn num_data_sectors(&self) -> u64 {
+        let sector_len = self.version.sector_len() as u64;
+        let small_bytes =
+            self.expected_small_stream_bytes.min(self.expected_total_bytes);
+        // Each regular stream wastes, on average, half of its last sector;
+        // err on the side of reserving too much.
+        (self.expected_total_bytes - small_bytes).div_ceil(sector_len)
+            + self.expected_streams
+    }
+
+    /// Computes how many sectors of each kind to allocate when creating the
+    /// file.
+    pub(crate) fn layout(&self) -> InitialLayout {
+        let sector_len = self.version.sector_len() as u64;
+        let fat_entries_per_sector =
+            self.version.fat_entries_per_sector() as u64;
+        let difat_entries_per_sector =
+            self.version.difat_entries_per_sector() as u64;
+        let dir_entries_per_sector =
+            self.version.dir_entries_per_sector() as u64;
+        // One directory entry for each stream, plus the root entry.
+        let num_dir_sectors = (self.expected_streams + 1)
+            .div_ceil(dir_entries_per_sector)
+            .max(1);
+        let num_mini_stream_sectors =
+            self.expected_small_stream_bytes.div_ceil(sector_len);
+        let num_mini_sectors = num_mini_stream_sectors * sector_len
+            / consts::MINI_SECTOR_LEN as u64;
+        let num_minifat_sectors =
+            num_mini_sectors.div_ceil(fat_entries_per_sector);
+        let num_data_sectors = self.num_data_sectors();
+        let num_other_sectors = num_dir_sectors
+            + num_minifat_sectors
+            + num_mini_stream_sectors
+            + num_data_sectors;
+        let mut num_fat_sectors = 1;
+        let mut num_difat_sectors = 0;
+        loop {
+            let total =
+                num_fat_sectors + num_difat_sectors + num_other_sectors;
+            let needed_fat = total.div_ceil(fat_entries_per_sector);
+            let needed_difat = needed_fat
+                .saturating_sub(consts::NUM_DIFAT_ENTRIES_IN_HEADER as u64)
+                .div_ceil(difat_entries_per_sector);
+            if needed_fat <= num_fat_sectors
+                && needed_difat <= num_difat_sectors
+            {
+                break;
+            }
+            num_fat_sectors = num_fat_sectors.max(needed_fat);
+            num_difat_sectors = num_difat_sectors.max(needed_difat);
+        }
+        InitialLayout {
+            num_fat_sectors,
+            num_difat_sectors,
+            num_dir_sectors,
+            num_minifat_sectors,
+            num_mini_stream_sectors,
+        }
+    }
+}
+
+impl Default for CreateOptions {
+    fn default() -> CreateOptions {
+        CreateOptions::new()
+    }
+}
+
+//===========================================================================//
+
+/// The metadata that a single new storage or stream starts out with, as used
+/// by
+/// [`CompoundFile::create_storage_with_options`](../struct.CompoundFile.html#method.create_storage_with_options)
+/// and
+/// [`CompoundFile::create_stream_with_options`](../struct.CompoundFile.html#method.create_stream_with_options).
+///
+/// The new directory entry is written once, already holding this metadata,
+/// so there is never a moment when it holds the current time instead; and
+/// creating the object changes no other entry's metadata.  With fixed times
+/// (or none), creating the same objects always writes the same bytes.
+///
+/// ```
+/// use cfb::{CompoundFile, NewEntryOptions};
+/// use std::io::Cursor;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+/// let options = NewEntryOptions::new().times(time).state_bits(3);
+/// let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+/// comp.create_storage_with_options("/foo", &options).unwrap();
+/// assert_eq!(comp.entry("/foo").unwrap().created(), time);
+/// assert_eq!(comp.entry("/foo").unwrap().state_bits(), 3);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NewEntryOptions {
+    pub(crate) created: Option<SystemTime>,
+    pub(crate) modified: Option<SystemTime>,
+    pub(crate) state_bits: Option<u32>,
+}
+
+impl NewEntryOptions {
+    /// Returns the default options, which give a new storage the current
+    /// time as its creation and modified times, and leave its state bits
+    /// zero, just as
+    /// [`create_storage`](../struct.CompoundFile.html#method.create_storage)
+    /// does.
+    pub fn new() -> NewEntryOptions {
+        NewEntryOptions::default()
+    }
+
+    /// Sets the creation time of a new storage, instead of the current time.
+    /// The time must be in the range accepted by
+    /// [`set_created_time`](../struct.CompoundFile.html#method.set_created_time),
+    /// or creating the object fails.  Streams ignore this, since the CFB
+    /// spec requires their times to be zero.
+    pub fn created(mut self, time: SystemTime) -> NewEntryOptions {
+        self.created = Some(time);
+        self
+    }
+
+    /// Sets the modified time of a new storage, instead of the current time,
+    /// with the same rules as [`created`](#method.created).
+    pub fn modified(mut self, time: SystemTime) -> NewEntryOptions {
+        self.modified = Some(time);
+        self
+    }
+
+    /// Sets both the creation and modified times of a new storage.
+    pub fn times(self, time: SystemTime) -> NewEntryOptions {
+        self.created(time).modified(time)
+    }
+
+    /// Leaves both times of a new storage zero (which reads back as January
+    /// 1, 1601 UTC), as they are for streams, rather than setting them to
+    /// the current time.
+    pub fn zero_times(self) -> NewEntryOptions {
+        self.times(Timestamp::zero().to_system_time())
+    }
+
+    /// Sets the new object's user-defined state bits.  When
+    /// [`create_stream_with_options`](../struct.CompoundFile.html#method.create_stream_with_options)
+    /// replaces an existing stream, that stream keeps its state bits unless
+    /// they are set here.
+    pub fn state_bits(mut self, bits: u32) -> NewEntryOptions {
+        self.state_bits = Some(bits);
+        self
+    }
+}
+
+//===========================================================================//
+
+/// The number of sectors of each kind to lay out, in this order, immediately
+/// after the header of a newly-created file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct InitialLayout {
+    pub num_fat_sectors: u64,
+    pub num_difat_sectors: u64,
+    pub num_dir_sectors: u64,
+    pub num_minifat_sectors: u64,
+    pub num_mini_stream_sectors: u64,
+}
+
+impl InitialLayout {
+    fn minimal() -> InitialLayout {
+        InitialLayout {
+            num_fat_sectors: 1,
+            num_difat_sectors: 0,
+            num_dir_sectors: 1,
+            num_minifat_sectors: 0,
+            num_mini_stream_sectors: 0,
+        }
+    }
+
+    pub fn num_sectors(&self) -> u64 {
+        self.num_fat_sectors
+            + self.num_difat_sectors
+            + self.num_dir_sectors
+            + self.num_minifat_sectors
+            + self.num_mini_stream_sectors
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{CreateOptions, InitialLayout};
+    use crate::internal::Version;
+
+    #[test]
+    fn default_layout_is_minimal() {
+        for &version in &[Version::V3, Version::V4] {
+            let options = CreateOptions::new().version(version);
+            assert_eq!(options.layout(), InitialLayout::minimal());
+            assert!(!options.has_reservations());
+        }
+    }
+
+    #[test]
+    fn hinted_layout() {
+        let options = CreateOptions::new()
+            .version(Version::V3)
+            .expected_streams(100)
+            .expected_total_bytes(1 << 20)
+            .expected_small_stream_bytes(1 << 16);
+        let layout = options.layout();
+        assert_eq!(layout.num_dir_sectors, 26);
+        assert_eq!(layout.num_mini_stream_sectors, 128);
+        assert_eq!(layout.num_minifat_sectors, 8);
+        assert_eq!(layout.num_difat_sectors, 0);
+        assert!(layout.num_fat_sectors * 128 >= 2048 + layout.num_sectors());
+    }
+
+    #[test]
+    fn huge_layout_needs_difat() {
+        let options = CreateOptions::new()
+            .version(Version::V3)
+            .expected_total_bytes(300 << 20);
+        let layout = options.layout();
+        assert!(layout.num_fat_sectors > 109);
+        assert!(layout.num_difat_sectors > 0);
+        let difat_capacity = 109 + 127 * layout.num_difat_sectors;
+        assert!(layout.num_fat_sectors <= difat_capacity);
+    }
+
+    #[test]
+    fn expected_num_sectors_counts_data() {
+        let options = CreateOptions::new()
+            .version(Version::V3)
+            .expected_streams(2)
+            .expected_total_bytes(1 << 20);
+        let layout = options.layout();
+        assert_eq!(
+            options.expected_num_sectors(),
+            layout.num_sectors() + 2050
+        );
+    }
+}
+
+//===========================================================================//
@@ -1,9 +1,13 @@
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::io;
 use std::path::{Component, Path, PathBuf};
 use std::sync::OnceLock;
 
+use crate::internal::EntryKind;
+
 // ========================================================================= //
 
 pub struct CaseMapper(HashMap<char, char>);
@@ -37,12 +41,22 @@ fn cfb_uppercase_char(c: char) -> char {
     case_mapper.simple_uppercase(c)
 }
 
+#[cfg(test)]
+thread_local! {
+    /// How many times `compare_names` has been called on this thread, so
+    /// that tests can check how much work path resolution does.
+    pub(crate) static NAME_COMPARISONS: std::cell::Cell<usize> =
+        const { std::cell::Cell::new(0) };
+}
+
 /// Compares two directory entry names according to CFB ordering, which is
 /// case-insensitive, and which always puts shorter names before longer names,
 /// as encoded in UTF-16 (i.e. [shortlex
 /// order](https://en.wikipedia.org/wiki/Shortlex_order), rather than
 /// dictionary order).
 pub fn compare_names(name1: &str, name2: &str) -> Ordering {
+    #[cfg(test)]
+    NAME_COMPARISONS.with(|count| count.set(count.get() + 1));
     match name1.encode_utf16().count().cmp(&name2.encode_utf16().count()) {
         // This is actually not 100% correct -- the MS-CFB spec specifies a
         // particular way of doing the uppercasing on individual UTF-16 code
@@ -112,13 +126,183 @@ pub fn path_from_name_chain(names: &[&str]) -> PathBuf {
     path
 }
 
+/// The prefix reserved for the names of temporary objects that this crate
+/// creates while carrying out multi-step operations.
+pub const TEMPORARY_NAME_PREFIX: &str = "\u{1}cfb.tmp.";
+
+/// Returns true if the given object name is reserved for temporary objects.
+pub fn is_temporary_name(name: &str) -> bool {
+    name.starts_with(TEMPORARY_NAME_PREFIX)
+}
+
+/// Returns the name of the temporary object with the given index.
+pub fn temporary_name(index: u32) -> String {
+    format!("{}{:08x}", TEMPORARY_NAME_PREFIX, index)
+}
+
+// ========================================================================= //
+
+/// The error payload reported when a path continues past a stream, as in
+/// `"/Stream/Child"`, since streams can't contain other objects.
+///
+/// This is returned wrapped in an `io::Error` (of kind `NotFound`, since no
+/// object can exist at such a path); use
+/// [`from_io_error`](#method.from_io_error) to recognize it.  Every method
+/// that resolves a path reports it for the first stream along the path, so
+/// the result doesn't depend on what (if anything) lies after that stream.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct PathThroughStream {
+    path: PathBuf,
+    stream_path: PathBuf,
+}
+
+impl PathThroughStream {
+    pub(crate) fn new(names: &[&str], prefix_len: usize) -> PathThroughStream {
+        debug_assert!(prefix_len > 0 && prefix_len < names.len());
+        PathThroughStream {
+            path: path_from_name_chain(names),
+            stream_path: path_from_name_chain(&names[..prefix_len]),
+        }
+    }
+
+    /// Returns the (normalized) path that was requested.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of the stream that the requested path continues
+    /// past.
+    pub fn stream_path(&self) -> &Path {
+        &self.stream_path
+    }
+
+    /// Returns the `PathThroughStream` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&PathThroughStream> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for PathThroughStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No such object: {:?} (because {:?} is a stream, not a storage)",
+            self.path, self.stream_path
+        )
+    }
+}
+
+impl Error for PathThroughStream {}
+
+impl From<PathThroughStream> for io::Error {
+    fn from(error: PathThroughStream) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, error)
+    }
+}
+
+// ========================================================================= //
+
+/// The error payload reported when no object exists at a path, recording how
+/// far along the path the existing objects go.  This lets a caller tell a
+/// missing object in an existing storage (which it could create) apart from
+/// a path whose storages are missing too.
+///
+/// This is returned wrapped in an `io::Error` of kind `NotFound`; use
+/// [`from_io_error`](#method.from_io_error) to recognize it.  Every method
+/// that resolves a path reports it the same way.  (If the path continues past
+/// a stream, a [`PathThroughStream`] is reported instead.)
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub struct ObjectNotFound {
+    what: &'static str,
+    missing: PathBuf,
+    ancestor: PathBuf,
+    ancestor_kind: EntryKind,
+}
+
+impl ObjectNotFound {
+    pub(crate) fn new(
+        what: &'static str,
+        names: &[&str],
+        prefix_len: usize,
+        ancestor_kind: EntryKind,
+    ) -> ObjectNotFound {
+        debug_assert!(prefix_len < names.len());
+        debug_assert!(ancestor_kind.is_dir());
+        ObjectNotFound {
+            what,
+            missing: path_from_name_chain(names),
+            ancestor: path_from_name_chain(&names[..prefix_len]),
+            ancestor_kind,
+        }
+    }
+
+    /// Returns the (normalized) path that was requested.
+    pub fn missing(&self) -> &Path {
+        &self.missing
+    }
+
+    /// Returns the path of the deepest object along the requested path that
+    /// does exist.  This is `"/"` if even the first storage is missing, and
+    /// is the parent of the requested path if only the object itself is.
+    pub fn nearest_existing_ancestor(&self) -> &Path {
+        &self.ancestor
+    }
+
+    /// Returns the kind of the
+    /// [`nearest_existing_ancestor`](#method.nearest_existing_ancestor),
+    /// which is always a storage or the root.
+    pub fn ancestor_kind(&self) -> EntryKind {
+        self.ancestor_kind
+    }
+
+    /// Returns true if the requested object's parent storage exists.
+    pub fn parent_exists(&self) -> bool {
+        self.missing.parent() == Some(self.ancestor.as_path())
+    }
+
+    /// Returns the `ObjectNotFound` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&ObjectNotFound> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for ObjectNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No such {}: {:?}", self.what, self.missing)?;
+        if !self.parent_exists() {
+            write!(f, " (only {:?} exists)", self.ancestor)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ObjectNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Include the message, so that unwrapping the error shows it.
+        f.debug_struct("ObjectNotFound")
+            .field("message", &self.to_string())
+            .field("nearest_existing_ancestor", &self.ancestor)
+            .field("ancestor_kind", &self.ancestor_kind)
+            .finish()
+    }
+}
+
+impl Error for ObjectNotFound {}
+
+impl From<ObjectNotFound> for io::Error {
+    fn from(error: ObjectNotFound) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, error)
+    }
+}
+
 // ========================================================================= //
 
 #[cfg(test)]
 mod tests {
     use super::{
-        cfb_uppercase_char, compare_names, name_chain_from_path,
-        path_from_name_chain, validate_name,
+        cfb_uppercase_char, compare_names, is_temporary_name,
+        name_chain_from_path, path_from_name_chain, temporary_name,
+        validate_name,
     };
     use std::cmp::Ordering;
     use std::path::{Path, PathBuf};
@@ -260,6 +444,16 @@ mod tests {
         std::fs::write("src/internal/uppercase.txt", array.to_string())
             .unwrap();
     }
+
+    #[test]
+    fn temporary_names() {
+        let name = temporary_name(0xabc);
+        assert_eq!(name, "\u{1}cfb.tmp.00000abc");
+        assert!(is_temporary_name(&name));
+        assert!(validate_name(&temporary_name(u32::MAX)).is_ok());
+        assert!(!is_temporary_name("\u{1}CompObj"));
+        assert!(!is_temporary_name("cfb.tmp.00000000"));
+    }
 }
 
 // ========================================================================= //
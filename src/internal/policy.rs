@@ -0,0 +1,232 @@
+use crate::internal::{consts, SectorId};
+use std::collections::BTreeSet;
+use std::iter::FusedIterator;
+use std::path::Path;
+
+//===========================================================================//
+
+/// The most sectors by which a single allocation may grow a file past its
+/// current end (see [`AllocContext::max_sector`]).
+const MAX_ALLOC_GROWTH: u32 = 4096;
+
+//===========================================================================//
+
+/// What a sector being allocated will be used for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum SectorPurpose<'a> {
+    /// A sector of the FAT.  These can only be placed in a free sector or at
+    /// the end of the file, never further out.
+    Fat,
+    /// A sector of the DIFAT.  Like FAT sectors, these can only be placed in
+    /// a free sector or at the end of the file.
+    Difat,
+    /// A sector of the directory chain.
+    Directory,
+    /// A sector of the MiniFAT chain.
+    MiniFat,
+    /// A sector of the mini stream, which holds the data of all streams
+    /// shorter than the mini stream cutoff.
+    MiniStream,
+    /// A sector holding data for the stream at the given path.
+    Stream(&'a Path),
+}
+
+impl SectorPurpose<'_> {
+    /// Returns true if the sector will hold the structure of the file (the
+    /// FAT, DIFAT, directory, or MiniFAT), rather than stream data.
+    pub fn is_metadata(&self) -> bool {
+        matches!(
+            self,
+            SectorPurpose::Fat
+                | SectorPurpose::Difat
+                | SectorPurpose::Directory
+                | SectorPurpose::MiniFat
+        )
+    }
+}
+
+//===========================================================================//
+
+/// The information available to a [`SectorAllocator`] when it is asked to
+/// choose a sector.
+#[derive(Clone, Copy, Debug)]
+pub struct AllocContext<'a> {
+    purpose: SectorPurpose<'a>,
+    free_sectors: &'a BTreeSet<u32>,
+    num_sectors: u32,
+    max_sector: SectorId,
+}
+
+impl<'a> AllocContext<'a> {
+    pub(crate) fn new(
+        purpose: SectorPurpose<'a>,
+        free_sectors: &'a BTreeSet<u32>,
+        num_sectors: u32,
+    ) -> AllocContext<'a> {
+        let max_sector =
+            if matches!(purpose, SectorPurpose::Fat | SectorPurpose::Difat) {
+                num_sectors
+            } else {
+                num_sectors.saturating_add(MAX_ALLOC_GROWTH)
+            }
+            .min(consts::MAX_REGULAR_SECTOR);
+        let max_sector = SectorId::new(max_sector);
+        AllocContext { purpose, free_sectors, num_sectors, max_sector }
+    }
+
+    /// Returns what the sector will be used for.
+    pub fn purpose(&self) -> SectorPurpose<'a> {
+        self.purpose
+    }
+
+    /// Returns the number of sectors currently in the file.  Choosing this
+    /// sector number (see [`end_of_file`](#method.end_of_file)) appends a new
+    /// sector to the end of the file.
+    pub fn num_sectors(&self) -> u32 {
+        self.num_sectors
+    }
+
+    /// Returns the sector just past the end of the file; choosing it appends
+    /// a new sector.
+    pub fn end_of_file(&self) -> SectorId {
+        SectorId::new(self.num_sectors)
+    }
+
+    /// Returns the largest sector number that may be chosen.  Choosing a
+    /// sector past the end of the file grows the file up to that sector, and
+    /// the sectors skipped over become free.
+    pub fn max_sector(&self) -> SectorId {
+        self.max_sector
+    }
+
+    /// Returns true if the given sector is within the file and unused.
+    pub fn is_free(&self, sector_id: SectorId) -> bool {
+        self.free_sectors.contains(&sector_id.value())
+    }
+
+    /// Returns the free sectors within the file, in increasing order.
+    pub fn free_sectors(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = SectorId>
+           + ExactSizeIterator
+           + FusedIterator
+           + 'a {
+        self.free_sectors.iter().copied().map(SectorId::new)
+    }
+}
+
+//===========================================================================//
+
+/// A policy deciding where in a compound file newly allocated sectors go.
+///
+/// A policy can be installed with
+/// [`CompoundFile::set_allocator`](../struct.CompoundFile.html#method.set_allocator).
+/// Whatever it returns from `alloc` is checked before use: it must be a free
+/// sector, or a sector between the end of the file and
+/// [`AllocContext::max_sector`]; any other answer makes the operation that
+/// needed the sector fail with an `InvalidInput` error, leaving the file
+/// intact.
+pub trait SectorAllocator: Send + Sync {
+    /// Chooses the sector to allocate.
+    fn alloc(&mut self, ctx: AllocContext<'_>) -> SectorId;
+
+    /// Called whenever a sector that was in use becomes free.  The default
+    /// implementation does nothing.
+    fn free(&mut self, sector_id: SectorId) {
+        let _ = sector_id;
+    }
+}
+
+/// The default policy: always allocates the lowest-numbered free sector,
+/// appending a new sector to the end of the file only if there is none.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FirstFree;
+
+impl SectorAllocator for FirstFree {
+    fn alloc(&mut self, ctx: AllocContext<'_>) -> SectorId {
+        ctx.free_sectors().next().unwrap_or(ctx.end_of_file())
+    }
+}
+
+/// A policy that keeps the structure of the file together at its start: the
+/// first `num_metadata_sectors` sectors are set aside for FAT, DIFAT,
+/// directory, and MiniFAT sectors, while stream data (including the mini
+/// stream) only goes after them.  Within each region, the lowest free sector
+/// is used first.  Once the metadata region is full, metadata sectors take
+/// whatever free sector is lowest, as with [`FirstFree`].
+#[derive(Clone, Copy, Debug)]
+pub struct ClusterMetadataFirst {
+    num_metadata_sectors: u32,
+}
+
+impl ClusterMetadataFirst {
+    /// Creates a policy that sets aside the first `num_metadata_sectors`
+    /// sectors of the file for metadata.
+    pub fn new(num_metadata_sectors: u32) -> ClusterMetadataFirst {
+        ClusterMetadataFirst { num_metadata_sectors }
+    }
+}
+
+impl SectorAllocator for ClusterMetadataFirst {
+    fn alloc(&mut self, ctx: AllocContext<'_>) -> SectorId {
+        if ctx.purpose().is_metadata() {
+            return ctx.free_sectors().next().unwrap_or(ctx.end_of_file());
+        }
+        let first_data_sector = SectorId::new(self.num_metadata_sectors);
+        ctx.free_sectors()
+            .find(|&sector_id| sector_id >= first_data_sector)
+            .unwrap_or_else(|| {
+                ctx.end_of_file().max(first_data_sector).min(ctx.max_sector())
+            })
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AllocContext, ClusterMetadataFirst, FirstFree, SectorAllocator,
+        SectorId, SectorPurpose,
+    };
+    use std::collections::BTreeSet;
+    use std::path::Path;
+
+    #[test]
+    fn first_free_prefers_lowest_free_sector() {
+        let free = BTreeSet::from([7, 3, 12]);
+        let ctx = AllocContext::new(SectorPurpose::Directory, &free, 20);
+        assert_eq!(FirstFree.alloc(ctx), SectorId::new(3));
+        let none = BTreeSet::new();
+        let ctx = AllocContext::new(SectorPurpose::Fat, &none, 20);
+        assert_eq!(FirstFree.alloc(ctx), SectorId::new(20));
+    }
+
+    #[test]
+    fn fat_sectors_cannot_grow_past_end() {
+        let free = BTreeSet::new();
+        let ctx = AllocContext::new(SectorPurpose::Difat, &free, 20);
+        assert_eq!(ctx.max_sector(), SectorId::new(20));
+        let path = Path::new("/foo");
+        let ctx = AllocContext::new(SectorPurpose::Stream(path), &free, 20);
+        assert!(ctx.max_sector() > SectorId::new(20));
+    }
+
+    #[test]
+    fn cluster_metadata_first_splits_regions() {
+        let mut policy = ClusterMetadataFirst::new(10);
+        let free = BTreeSet::from([2, 15]);
+        let ctx = AllocContext::new(SectorPurpose::MiniFat, &free, 20);
+        assert_eq!(policy.alloc(ctx), SectorId::new(2));
+        let ctx = AllocContext::new(SectorPurpose::MiniStream, &free, 20);
+        assert_eq!(policy.alloc(ctx), SectorId::new(15));
+        let free = BTreeSet::from([2]);
+        let ctx = AllocContext::new(SectorPurpose::MiniStream, &free, 4);
+        assert_eq!(policy.alloc(ctx), SectorId::new(10));
+        let ctx = AllocContext::new(SectorPurpose::MiniStream, &free, 20);
+        assert_eq!(policy.alloc(ctx), SectorId::new(20));
+    }
+}
+
+//===========================================================================//
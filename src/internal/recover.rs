@@ -0,0 +1,447 @@
+use crate::internal::{
+    self, consts, Allocator, ChainName, DirEntry, ObjType, SectorId, StreamId,
+};
+use fnv::FnvHashSet;
+use std::cmp::Ordering;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//===========================================================================//
+
+/// The kind of damage described by a
+/// [`RecoveryWarning`](struct.RecoveryWarning.html).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum RecoveryWarningKind {
+    /// A FAT sector listed in the DIFAT (or a DIFAT sector) was past the end
+    /// of the file, was an invalid sector ID, or couldn't be read.  It and
+    /// every FAT sector listed after it were ignored, so the sectors that
+    /// they describe were treated as unallocated.
+    FatSectorMissing,
+    /// A FAT or MiniFAT entry referred to a sector past the end of the table,
+    /// held an invalid value, or referred to a sector that another entry
+    /// already refers to.  The chain was ended at that entry's sector.
+    BrokenLink,
+    /// A chain reached a special value or an unallocated sector before
+    /// `END_OF_CHAIN`, and was ended at its last good sector.
+    ChainBroken,
+    /// A chain looped back on itself, and was ended at the last sector
+    /// before the loop.
+    ChainLoop,
+    /// A sector in use was cut short by the end of the file, and was treated
+    /// as unallocated; or a directory or MiniFAT sector couldn't be read, and
+    /// its contents were ignored.
+    UnreadableSector,
+    /// A directory entry couldn't be parsed, and was treated as unallocated.
+    UnreadableDirEntry,
+    /// A directory entry's sibling or child link was out of range, referred
+    /// to an unallocated entry or to one already in the tree, or broke the
+    /// ordering of names.  The link was ignored, so any entries reachable
+    /// only through it are missing from the recovered tree.
+    BrokenTreeLink,
+    /// The root entry's mini stream length was more than its chain holds,
+    /// or wasn't a multiple of the mini sector length, and was reduced.
+    MiniStreamTruncated,
+    /// A stream's length was more than its (possibly shortened) chain holds.
+    /// The stream reports the salvaged length instead.
+    StreamTruncated,
+}
+
+/// A piece of damage that was worked around while opening a compound file
+/// with [`CompoundFile::open_recover`](../struct.CompoundFile.html#method.open_recover).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecoveryWarning {
+    kind: RecoveryWarningKind,
+    path: Option<PathBuf>,
+    stream_id: Option<StreamId>,
+    sector_id: Option<SectorId>,
+    message: String,
+}
+
+impl RecoveryWarning {
+    pub(crate) fn new(
+        kind: RecoveryWarningKind,
+        message: String,
+    ) -> RecoveryWarning {
+        RecoveryWarning {
+            kind,
+            path: None,
+            stream_id: None,
+            sector_id: None,
+            message,
+        }
+    }
+
+    pub(crate) fn with_stream_id(mut self, stream_id: u32) -> RecoveryWarning {
+        self.stream_id = Some(StreamId::new(stream_id));
+        self
+    }
+
+    pub(crate) fn with_sector_id(mut self, sector_id: u32) -> RecoveryWarning {
+        self.sector_id = Some(SectorId::new(sector_id));
+        self
+    }
+
+    pub(crate) fn set_path(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    /// Returns what kind of damage this is.
+    pub fn kind(&self) -> RecoveryWarningKind {
+        self.kind
+    }
+
+    /// Returns the path of the object whose directory entry or chain was
+    /// damaged, if the damage concerns a particular object that is still
+    /// reachable in the recovered tree.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the ID of the directory entry that was damaged (or whose
+    /// chain was), if any.
+    pub fn stream_id(&self) -> Option<StreamId> {
+        self.stream_id
+    }
+
+    /// Returns the ID of the (mini) sector at which the damage was found:
+    /// for a chain that was cut short, the sector it now ends at (or the
+    /// invalid starting sector, if none of it could be salvaged).
+    pub fn sector_id(&self) -> Option<SectorId> {
+        self.sector_id
+    }
+
+    /// Returns a human-readable description of the damage.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for RecoveryWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+//===========================================================================//
+
+/// Returns true if a chain can continue into the given sector of `table`:
+/// the sector must be in range, and its own entry must continue or end the
+/// chain.
+fn is_usable(table: &[u32], sector_id: u32) -> bool {
+    match table.get(sector_id as usize) {
+        Some(&next) => {
+            next == consts::END_OF_CHAIN || next <= consts::MAX_REGULAR_SECTOR
+        }
+        None => false,
+    }
+}
+
+/// Follows the chain starting at `start` through `table` (the FAT or the
+/// MiniFAT), and returns the IDs of the sectors in it.  If the chain is
+/// broken or loops, it is ended at its last good sector by changing that
+/// sector's entry in `table` to `END_OF_CHAIN`, and a warning describing the
+/// damage is returned as well.  An invalid starting sector gives an empty
+/// chain; the caller must then stop referring to it.
+pub fn salvage_chain(
+    table: &mut [u32],
+    start: u32,
+    chain: ChainName<'_>,
+) -> (Vec<u32>, Option<RecoveryWarning>) {
+    let mut sector_ids = Vec::new();
+    if start == consts::END_OF_CHAIN {
+        return (sector_ids, None);
+    }
+    if !is_usable(table, start) {
+        let warning = RecoveryWarning::new(
+            RecoveryWarningKind::ChainBroken,
+            format!(
+                "The {} starts at unusable sector {}, and was treated as \
+                 empty",
+                chain,
+                SectorId::new(start)
+            ),
+        )
+        .with_sector_id(start);
+        return (sector_ids, Some(warning));
+    }
+    let mut seen = FnvHashSet::default();
+    let mut current = start;
+    loop {
+        seen.insert(current);
+        sector_ids.push(current);
+        let next = table[current as usize];
+        if next == consts::END_OF_CHAIN {
+            return (sector_ids, None);
+        }
+        let kind = if seen.contains(&next) {
+            RecoveryWarningKind::ChainLoop
+        } else if !is_usable(table, next) {
+            RecoveryWarningKind::ChainBroken
+        } else {
+            current = next;
+            continue;
+        };
+        table[current as usize] = consts::END_OF_CHAIN;
+        let problem = match kind {
+            RecoveryWarningKind::ChainLoop => "loops back to",
+            _ => "continues into unusable sector",
+        };
+        let warning = RecoveryWarning::new(
+            kind,
+            format!(
+                "The {} {} {} after {} sectors, and was ended at sector {}",
+                chain,
+                problem,
+                SectorId::new(next),
+                sector_ids.len(),
+                current
+            ),
+        )
+        .with_sector_id(current);
+        return (sector_ids, Some(warning));
+    }
+}
+
+/// Ends each chain in `table` (the FAT or the MiniFAT) at any entry that
+/// refers to a sector past the end of the table, holds the reserved
+/// `INVALID_SECTOR` value, or refers to a sector that an earlier entry
+/// already refers to, so that every remaining link is one that a chain can
+/// follow.  `name` names the table in warnings.
+pub fn recover_links(
+    table: &mut [u32],
+    name: &str,
+    warnings: &mut Vec<RecoveryWarning>,
+) {
+    let len = table.len();
+    let mut pointees = FnvHashSet::default();
+    for (from_sector, entry) in table.iter_mut().enumerate() {
+        let to_sector = *entry;
+        let problem = if to_sector == consts::INVALID_SECTOR {
+            format!("holds the invalid value 0x{:08X}", to_sector)
+        } else if to_sector > consts::MAX_REGULAR_SECTOR {
+            continue;
+        } else if to_sector as usize >= len {
+            format!(
+                "refers to sector {}, but there are only {}",
+                to_sector, len
+            )
+        } else if !pointees.insert(to_sector) {
+            format!("refers to sector {}, as another entry does", to_sector)
+        } else {
+            continue;
+        };
+        *entry = consts::END_OF_CHAIN;
+        warnings.push(
+            RecoveryWarning::new(
+                RecoveryWarningKind::BrokenLink,
+                format!(
+                    "{} entry {} {}, and was treated as END_OF_CHAIN",
+                    name, from_sector, problem
+                ),
+            )
+            .with_sector_id(from_sector as u32),
+        );
+    }
+}
+
+/// One of the links from a directory entry to another.
+#[derive(Clone, Copy)]
+enum TreeLink {
+    Left,
+    Right,
+    Child,
+}
+
+impl TreeLink {
+    fn name(self) -> &'static str {
+        match self {
+            TreeLink::Left => "left sibling",
+            TreeLink::Right => "right sibling",
+            TreeLink::Child => "child",
+        }
+    }
+
+    fn target_mut(self, dir_entry: &mut DirEntry) -> &mut u32 {
+        match self {
+            TreeLink::Left => &mut dir_entry.left_sibling,
+            TreeLink::Right => &mut dir_entry.right_sibling,
+            TreeLink::Child => &mut dir_entry.child,
+        }
+    }
+
+    /// Returns how the target's name must compare with the name of the
+    /// entry linking to it, or `None` if there is no constraint.
+    fn ordering(self) -> Option<Ordering> {
+        match self {
+            TreeLink::Left => Some(Ordering::Less),
+            TreeLink::Right => Some(Ordering::Greater),
+            TreeLink::Child => None,
+        }
+    }
+}
+
+/// Cuts every sibling and child link in the directory tree that can't be
+/// followed safely, so that the rest of the tree can still be read.  Returns
+/// an error only if the root entry itself is unusable.
+pub fn recover_tree(
+    dir_entries: &mut [DirEntry],
+    warnings: &mut Vec<RecoveryWarning>,
+) -> io::Result<()> {
+    if dir_entries.first().map(|entry| entry.obj_type) != Some(ObjType::Root) {
+        invalid_data!("Root directory entry is damaged beyond recovery");
+    }
+    let mut in_tree = vec![false; dir_entries.len()];
+    in_tree[consts::ROOT_STREAM_ID as usize] = true;
+    // Each entry to visit, with its own path and its parent's path.
+    let mut stack =
+        vec![(consts::ROOT_STREAM_ID, PathBuf::from("/"), PathBuf::from("/"))];
+    while let Some((stream_id, path, parent_path)) = stack.pop() {
+        for link in [TreeLink::Left, TreeLink::Right, TreeLink::Child] {
+            let dir_entry = &mut dir_entries[stream_id as usize];
+            let target = *link.target_mut(dir_entry);
+            if target == consts::NO_STREAM {
+                continue;
+            }
+            let dir_entry = &dir_entries[stream_id as usize];
+            let problem = match dir_entries.get(target as usize) {
+                None => Some(format!(
+                    "refers to entry {}, but there are only {}",
+                    target,
+                    dir_entries.len()
+                )),
+                Some(entry)
+                    if entry.obj_type != ObjType::Storage
+                        && entry.obj_type != ObjType::Stream =>
+                {
+                    Some(format!(
+                        "refers to entry {}, which is {:?}",
+                        target, entry.obj_type
+                    ))
+                }
+                Some(_) if in_tree[target as usize] => Some(format!(
+                    "refers to entry {}, which is already in the tree",
+                    target
+                )),
+                Some(entry) => link.ordering().and_then(|ordering| {
+                    let actual = internal::path::compare_names(
+                        &entry.name,
+                        &dir_entry.name,
+                    );
+                    (actual != ordering).then(|| {
+                        format!(
+                            "refers to entry {} ({:?}), which is out of \
+                             order",
+                            target, entry.name
+                        )
+                    })
+                }),
+            };
+            if let Some(problem) = problem {
+                let dir_entry = &mut dir_entries[stream_id as usize];
+                *link.target_mut(dir_entry) = consts::NO_STREAM;
+                let mut warning = RecoveryWarning::new(
+                    RecoveryWarningKind::BrokenTreeLink,
+                    format!(
+                        "The {} link of {:?} {}, and was ignored",
+                        link.name(),
+                        path,
+                        problem
+                    ),
+                )
+                .with_stream_id(stream_id);
+                warning.set_path(path.clone());
+                warnings.push(warning);
+                continue;
+            }
+            in_tree[target as usize] = true;
+            let name = &dir_entries[target as usize].name;
+            match link {
+                TreeLink::Child => {
+                    stack.push((target, path.join(name), path.clone()));
+                }
+                _ => stack.push((
+                    target,
+                    parent_path.join(name),
+                    parent_path.clone(),
+                )),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Salvages the mini stream's chain and the chain of every stream, cutting
+/// each one short at its last good (mini) sector, and clears the starting
+/// sector of any stream none of whose chain could be salvaged.  The root
+/// entry's mini stream length is reduced to what its chain holds, so that
+/// the MiniFAT is only consulted for mini sectors that actually exist.
+pub fn recover_chains<F>(
+    allocator: &mut Allocator<F>,
+    minifat: &mut [u32],
+    dir_entries: &mut [DirEntry],
+    mini_sector_len: u64,
+    warnings: &mut Vec<RecoveryWarning>,
+) {
+    let sector_len = allocator.sector_len() as u64;
+    let root_entry = &mut dir_entries[consts::ROOT_STREAM_ID as usize];
+    if root_entry.stream_len > 0 {
+        let (sector_ids, warning) = allocator
+            .salvage_chain(root_entry.start_sector, ChainName::MiniStream);
+        warnings
+            .extend(warning.map(|warning| {
+                warning.with_stream_id(consts::ROOT_STREAM_ID)
+            }));
+        if sector_ids.is_empty() {
+            root_entry.start_sector = consts::END_OF_CHAIN;
+        }
+        let capacity = sector_ids.len() as u64 * sector_len;
+        let salvaged = root_entry.stream_len.min(capacity) / mini_sector_len
+            * mini_sector_len;
+        if salvaged != root_entry.stream_len {
+            warnings.push(
+                RecoveryWarning::new(
+                    RecoveryWarningKind::MiniStreamTruncated,
+                    format!(
+                        "The mini stream has length {}, but only {} bytes \
+                         of it could be salvaged",
+                        root_entry.stream_len, salvaged
+                    ),
+                )
+                .with_stream_id(consts::ROOT_STREAM_ID),
+            );
+            root_entry.stream_len = salvaged;
+        }
+    }
+    let num_mini_sectors = root_entry.stream_len / mini_sector_len;
+    let minifat_len = minifat.len().min(num_mini_sectors as usize);
+    let minifat = &mut minifat[..minifat_len];
+    recover_links(minifat, "MiniFAT", warnings);
+
+    for (stream_id, dir_entry) in dir_entries.iter_mut().enumerate() {
+        if dir_entry.obj_type != ObjType::Stream
+            || dir_entry.stream_len == 0
+            || dir_entry.start_sector == consts::END_OF_CHAIN
+        {
+            continue;
+        }
+        let start_sector = dir_entry.start_sector;
+        let (sector_ids, warning) =
+            if dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64 {
+                let chain = ChainName::MiniStartingAt(start_sector);
+                salvage_chain(minifat, start_sector, chain)
+            } else {
+                let chain = ChainName::StartingAt(start_sector);
+                allocator.salvage_chain(start_sector, chain)
+            };
+        warnings.extend(
+            warning.map(|warning| warning.with_stream_id(stream_id as u32)),
+        );
+        if sector_ids.is_empty() {
+            dir_entry.start_sector = consts::END_OF_CHAIN;
+        }
+    }
+}
+
+//===========================================================================//
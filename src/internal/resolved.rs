@@ -0,0 +1,89 @@
+use crate::internal::{consts, MiniAllocator, ObjType};
+use std::path::{Path, PathBuf};
+
+//===========================================================================//
+
+/// A path that has already been looked up in a compound file, as returned by
+/// [`CompoundFile::resolve`](../struct.CompoundFile.html#method.resolve), so
+/// that it can be opened again without comparing names along the way.
+///
+/// A `ResolvedPath` remembers the stream ID of each object along the path.
+/// Using it in the file it came from, before any object there has been
+/// created, removed, or renamed, costs nothing beyond a counter check.
+/// Otherwise (including in a different file), each remembered stream ID is
+/// checked to still be in the same storage as before, with exactly the same
+/// name; if that fails, the path is looked up from scratch, as if it had
+/// never been resolved.  So a `ResolvedPath` is never wrong, only slower,
+/// and can be reused across many files that share the same structure.
+#[derive(Clone, Debug)]
+pub struct ResolvedPath {
+    path: PathBuf,
+    /// The name of each object along the path, as stored in the file.
+    names: Vec<String>,
+    /// The stream ID of each object along the path.
+    stream_ids: Vec<u32>,
+    /// The directory's tree stamp when the path was resolved.
+    tree_stamp: u64,
+}
+
+impl ResolvedPath {
+    pub(crate) fn new<F>(
+        minialloc: &MiniAllocator<F>,
+        path: PathBuf,
+        stream_ids: Vec<u32>,
+    ) -> ResolvedPath {
+        let names = stream_ids
+            .iter()
+            .map(|&stream_id| minialloc.dir_entry(stream_id).name.clone())
+            .collect();
+        ResolvedPath {
+            path,
+            names,
+            stream_ids,
+            tree_stamp: minialloc.tree_stamp(),
+        }
+    }
+
+    /// Returns the path that was resolved.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the names along the path, as stored in the file it was
+    /// resolved in.
+    pub(crate) fn names(&self) -> Vec<&str> {
+        self.names.iter().map(String::as_str).collect()
+    }
+
+    /// Returns the stream ID of the object at the end of the path, if the
+    /// remembered stream IDs are still accurate in the given file.
+    pub(crate) fn revalidate<F>(
+        &self,
+        minialloc: &MiniAllocator<F>,
+    ) -> Option<u32> {
+        let last =
+            self.stream_ids.last().copied().unwrap_or(consts::ROOT_STREAM_ID);
+        if self.tree_stamp == minialloc.tree_stamp() {
+            return Some(last);
+        }
+        let num_dir_entries = minialloc.num_dir_entries();
+        let mut parent_id = consts::ROOT_STREAM_ID;
+        for (&stream_id, name) in self.stream_ids.iter().zip(&self.names) {
+            if stream_id >= num_dir_entries
+                || minialloc.parent_id(stream_id) != Some(parent_id)
+            {
+                return None;
+            }
+            let dir_entry = minialloc.dir_entry(stream_id);
+            if dir_entry.obj_type == ObjType::Unallocated
+                || dir_entry.name != *name
+            {
+                return None;
+            }
+            parent_id = stream_id;
+        }
+        Some(last)
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,335 @@
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// The name of the stream holding the summary information property set.
+pub(crate) const SUMMARY_INFO_STREAM_NAME: &str = "\u{5}SummaryInformation";
+
+/// The name of the stream holding the document summary information property
+/// set.
+const DOC_SUMMARY_INFO_STREAM_NAME: &str = "\u{5}DocumentSummaryInformation";
+
+const SUMMARY_INFO_FMTID: Uuid =
+    Uuid::from_u128(0xf29f85e0_4ff9_1068_ab91_08002b27b3d9);
+const DOC_SUMMARY_INFO_FMTID: Uuid =
+    Uuid::from_u128(0xd5cdd502_2e9c_101b_9397_08002b2cf9ae);
+
+// Identifying properties within the summary information property set:
+const PID_AUTHOR: u32 = 4;
+const PID_LAST_AUTHOR: u32 = 8;
+const PID_LAST_PRINTED: u32 = 11;
+const PID_CREATE_TIME: u32 = 12;
+const PID_LAST_SAVE_TIME: u32 = 13;
+
+// Identifying properties within the document summary information property
+// set:
+const PID_MANAGER: u32 = 14;
+const PID_COMPANY: u32 = 15;
+
+// Property value types:
+const VT_LPSTR: u16 = 30;
+const VT_LPWSTR: u16 = 31;
+const VT_FILETIME: u16 = 64;
+
+//===========================================================================//
+
+/// Options for [`CompoundFile::sanitize`](../struct.CompoundFile.html#method.sanitize),
+/// which strips metadata that commonly leaks private information from a
+/// compound file before it is shared.
+///
+/// By default everything is stripped except storage CLSIDs, since those are
+/// often needed for the document to be opened by the right application.
+///
+/// ```
+/// use cfb::SanitizeOptions;
+///
+/// let options = SanitizeOptions::new().keep_times(true).clear_clsids(true);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SanitizeOptions {
+    pub(crate) keep_times: bool,
+    pub(crate) keep_properties: bool,
+    pub(crate) clear_clsids: bool,
+    pub(crate) keep_free_space: bool,
+}
+
+impl SanitizeOptions {
+    /// Returns the default options.
+    pub fn new() -> SanitizeOptions {
+        SanitizeOptions {
+            keep_times: false,
+            keep_properties: false,
+            clear_clsids: false,
+            keep_free_space: false,
+        }
+    }
+
+    /// If true, the creation and modification times of storages (and the
+    /// time properties in the summary information) are left alone.  Defaults
+    /// to false.
+    pub fn keep_times(mut self, keep: bool) -> SanitizeOptions {
+        self.keep_times = keep;
+        self
+    }
+
+    /// If true, the summary information and document summary information
+    /// property sets are left alone.  Otherwise, the author, last author,
+    /// manager, and company properties are blanked out in place (as are the
+    /// time properties, unless `keep_times(true)` is set), and property set
+    /// streams that can't be parsed are removed.  Defaults to false.
+    pub fn keep_properties(mut self, keep: bool) -> SanitizeOptions {
+        self.keep_properties = keep;
+        self
+    }
+
+    /// If true, the CLSIDs of all storages other than the root storage are
+    /// set to the null GUID.  Defaults to false.
+    pub fn clear_clsids(mut self, clear: bool) -> SanitizeOptions {
+        self.clear_clsids = clear;
+        self
+    }
+
+    /// If true, free sectors, the unused ends of stream sectors, and free
+    /// directory entries are left alone, rather than being overwritten with
+    /// zeros (they may hold data from removed or truncated streams).
+    /// Defaults to false.
+    pub fn keep_free_space(mut self, keep: bool) -> SanitizeOptions {
+        self.keep_free_space = keep;
+        self
+    }
+}
+
+impl Default for SanitizeOptions {
+    fn default() -> SanitizeOptions {
+        SanitizeOptions::new()
+    }
+}
+
+//===========================================================================//
+
+/// A report of what was changed by
+/// [`CompoundFile::sanitize`](../struct.CompoundFile.html#method.sanitize).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SanitizeReport {
+    pub(crate) num_times_cleared: u32,
+    pub(crate) num_properties_scrubbed: u32,
+    pub(crate) removed_property_sets: Vec<PathBuf>,
+    pub(crate) num_clsids_cleared: u32,
+    pub(crate) num_bytes_wiped: u64,
+    pub(crate) header_reset: bool,
+}
+
+impl SanitizeReport {
+    /// Returns the number of storages whose creation or modification time
+    /// was cleared.
+    pub fn num_times_cleared(&self) -> u32 {
+        self.num_times_cleared
+    }
+
+    /// Returns the number of identifying properties that were blanked out in
+    /// property set streams.
+    pub fn num_properties_scrubbed(&self) -> u32 {
+        self.num_properties_scrubbed
+    }
+
+    /// Returns the paths of property set streams that couldn't be parsed,
+    /// and so were removed rather than scrubbed.
+    pub fn removed_property_sets(&self) -> &[PathBuf] {
+        &self.removed_property_sets
+    }
+
+    /// Returns the number of storages whose CLSID was cleared.
+    pub fn num_clsids_cleared(&self) -> u32 {
+        self.num_clsids_cleared
+    }
+
+    /// Returns the number of bytes of free space and sector slack that were
+    /// overwritten with zeros.
+    pub fn num_bytes_wiped(&self) -> u64 {
+        self.num_bytes_wiped
+    }
+
+    /// Returns true if the header's transaction signature, CLSID, or
+    /// reserved fields were nonzero, and were reset.
+    pub fn header_reset(&self) -> bool {
+        self.header_reset
+    }
+}
+
+//===========================================================================//
+
+/// Returns true if the stream at the given path holds one of the property
+/// sets that `scrub_property_set` knows about.
+pub fn is_property_set_stream(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        name == SUMMARY_INFO_STREAM_NAME
+            || name == DOC_SUMMARY_INFO_STREAM_NAME
+    })
+}
+
+/// Blanks out the identifying properties (and, if `scrub_times`, the time
+/// properties) in the given property set stream data, in place, without
+/// changing its layout.  Returns the number of properties that were changed,
+/// or `None` if the data isn't a well-formed property set stream.
+pub fn scrub_property_set(data: &mut [u8], scrub_times: bool) -> Option<u32> {
+    if read_u16(data, 0)? != 0xfffe {
+        return None;
+    }
+    let num_sets = read_u32(data, 24)? as usize;
+    let mut num_scrubbed = 0;
+    for index in 0..num_sets {
+        let entry = 28 + 20 * index;
+        let fmtid =
+            Uuid::from_bytes_le(data.get(entry..entry + 16)?.try_into().ok()?);
+        let set_offset = read_u32(data, entry + 16)? as usize;
+        let (strings, times): (&[u32], &[u32]) = if fmtid == SUMMARY_INFO_FMTID
+        {
+            (
+                &[PID_AUTHOR, PID_LAST_AUTHOR],
+                &[PID_LAST_PRINTED, PID_CREATE_TIME, PID_LAST_SAVE_TIME],
+            )
+        } else if fmtid == DOC_SUMMARY_INFO_FMTID {
+            (&[PID_MANAGER, PID_COMPANY], &[])
+        } else {
+            continue;
+        };
+        let set_len = read_u32(data, set_offset)? as usize;
+        let set_end = set_offset.checked_add(set_len)?;
+        if set_end > data.len() {
+            return None;
+        }
+        let num_properties = read_u32(data, set_offset + 4)? as usize;
+        for property in 0..num_properties {
+            let table_entry = set_offset + 8 + 8 * property;
+            let pid = read_u32(data, table_entry)?;
+            let value_offset =
+                set_offset
+                    .checked_add(read_u32(data, table_entry + 4)? as usize)?;
+            let scrub_string = strings.contains(&pid);
+            let scrub_time = scrub_times && times.contains(&pid);
+            if !scrub_string && !scrub_time {
+                continue;
+            }
+            let value = match read_u16(data, value_offset)? {
+                VT_LPSTR if scrub_string => {
+                    let len = read_u32(data, value_offset + 4)? as usize;
+                    (value_offset + 8)..(value_offset + 8).checked_add(len)?
+                }
+                VT_LPWSTR if scrub_string => {
+                    let len = read_u32(data, value_offset + 4)? as usize;
+                    (value_offset + 8)
+                        ..(value_offset + 8)
+                            .checked_add(len.checked_mul(2)?)?
+                }
+                VT_FILETIME if scrub_time => {
+                    (value_offset + 4)..(value_offset + 12)
+                }
+                _ => continue,
+            };
+            if value.end > set_end {
+                return None;
+            }
+            let value = &mut data[value];
+            if value.iter().any(|&byte| byte != 0) {
+                value.fill(0);
+                num_scrubbed += 1;
+            }
+        }
+    }
+    Some(num_scrubbed)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{scrub_property_set, SUMMARY_INFO_FMTID};
+
+    /// Builds a summary information stream with an author (as an 8-bit
+    /// string), a title, and a creation time.
+    fn summary_info() -> Vec<u8> {
+        let mut values = Vec::new();
+        let mut table = Vec::new();
+        let properties: [(u32, u16, &[u8]); 3] = [
+            (2, 30, b"\x06\0\0\0Title\0"),
+            (4, 30, b"\x06\0\0\0Alice\0"),
+            (12, 64, b"\x01\x02\x03\x04\x05\x06\x07\x08"),
+        ];
+        let table_len = 8 + 8 * properties.len();
+        for &(pid, vt, value) in properties.iter() {
+            table.extend_from_slice(&pid.to_le_bytes());
+            table.extend_from_slice(
+                &((table_len + values.len()) as u32).to_le_bytes(),
+            );
+            values.extend_from_slice(&(vt as u32).to_le_bytes());
+            values.extend_from_slice(value);
+            while values.len() % 4 != 0 {
+                values.push(0);
+            }
+        }
+        let mut data = Vec::new();
+        data.extend_from_slice(&0xfffeu16.to_le_bytes());
+        data.extend_from_slice(&[0; 22]);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&SUMMARY_INFO_FMTID.to_bytes_le());
+        data.extend_from_slice(&48u32.to_le_bytes());
+        data.extend_from_slice(
+            &((table_len + values.len()) as u32).to_le_bytes(),
+        );
+        data.extend_from_slice(&(properties.len() as u32).to_le_bytes());
+        data.extend_from_slice(&table);
+        data.extend_from_slice(&values);
+        data
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn scrub_author_and_times() {
+        let mut data = summary_info();
+        let len = data.len();
+        assert_eq!(scrub_property_set(&mut data, true), Some(2));
+        assert_eq!(data.len(), len);
+        assert!(!contains(&data, b"Alice"));
+        assert!(!contains(&data, &[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert!(contains(&data, b"Title"));
+        // Scrubbing again changes nothing.
+        assert_eq!(scrub_property_set(&mut data, true), Some(0));
+    }
+
+    #[test]
+    fn scrub_author_but_keep_times() {
+        let mut data = summary_info();
+        assert_eq!(scrub_property_set(&mut data, false), Some(1));
+        assert!(!contains(&data, b"Alice"));
+        assert!(contains(&data, &[1, 2, 3, 4, 5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn malformed_property_sets() {
+        assert_eq!(scrub_property_set(&mut [], true), None);
+        assert_eq!(scrub_property_set(&mut [0xfe, 0xff, 0, 0], true), None);
+        let mut data = summary_info();
+        data.truncate(data.len() - 4);
+        assert_eq!(scrub_property_set(&mut data, true), None);
+        let mut data = summary_info();
+        data[0] = 0;
+        assert_eq!(scrub_property_set(&mut data, true), None);
+    }
+}
+
+//===========================================================================//
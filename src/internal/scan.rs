@@ -0,0 +1,472 @@
+use crate::internal::{consts, glob_match, ioutil};
+use crate::{CompoundFile, Entry, ValidationIssue};
+use std::collections::VecDeque;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, Seek, SeekFrom};
+use std::iter::FusedIterator;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//===========================================================================//
+
+/// When scanning on several threads, how many files to hand out per thread
+/// at a time.  Each batch is finished before any of its results are
+/// returned, so this trades how evenly the threads are kept busy against how
+/// far the scan runs ahead of the iterator.
+const FILES_PER_THREAD: usize = 4;
+
+/// Options for [`scan_dir`](fn.scan_dir.html).
+///
+/// ```
+/// use cfb::ScanOptions;
+/// use std::time::Duration;
+///
+/// let options = ScanOptions::new()
+///     .recursive(true)
+///     .file_name("*.msi")
+///     .file_name("*.doc")
+///     .hash_streams(true)
+///     .timeout(Duration::from_secs(10))
+///     .threads(4);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    pub(crate) recursive: bool,
+    pub(crate) file_names: Vec<String>,
+    pub(crate) hash_streams: bool,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) max_file_size: Option<u64>,
+    pub(crate) cancel: Option<Arc<AtomicBool>>,
+    pub(crate) threads: usize,
+}
+
+impl ScanOptions {
+    /// Returns the default options: scan only the given directory (not its
+    /// subdirectories), every file in it, without hashing streams and without
+    /// any time or size limits.
+    pub fn new() -> ScanOptions {
+        ScanOptions::default()
+    }
+
+    /// If true, subdirectories are scanned too.  Symbolic links to
+    /// directories are never followed.  Defaults to false.
+    pub fn recursive(mut self, recursive: bool) -> ScanOptions {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Adds a glob pattern (as for
+    /// [`glob_match`](fn.glob_match.html)) that file names are
+    /// matched against.  If any patterns are given, only files whose names
+    /// match at least one of them are scanned; the rest are skipped without
+    /// being opened or reported.
+    pub fn file_name(mut self, pattern: &str) -> ScanOptions {
+        self.file_names.push(pattern.to_string());
+        self
+    }
+
+    /// If true, the contents of every stream are read and hashed, and the
+    /// hash is recorded in the manifest.  Defaults to false.
+    pub fn hash_streams(mut self, hash: bool) -> ScanOptions {
+        self.hash_streams = hash;
+        self
+    }
+
+    /// Sets how long scanning any one file may take.  This is checked between
+    /// steps (including between chunks of stream data when hashing), so a
+    /// file may slightly overrun it.  Defaults to no limit.
+    pub fn timeout(mut self, timeout: Duration) -> ScanOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the largest file, in bytes, that will be opened; larger files are
+    /// reported as [`ScanOutcome::TooLarge`](enum.ScanOutcome.html).
+    /// Defaults to no limit.
+    pub fn max_file_size(mut self, max_len: u64) -> ScanOptions {
+        self.max_file_size = Some(max_len);
+        self
+    }
+
+    /// Sets a flag that can be set (from any thread) to stop the scan.  The
+    /// file being scanned when it is set is reported as
+    /// [`ScanOutcome::Cancelled`](enum.ScanOutcome.html), and no further
+    /// results are produced.  (Simply dropping the iterator returned by
+    /// `scan_dir` also stops the scan, between files or batches of files.)
+    pub fn cancel_flag(mut self, flag: Arc<AtomicBool>) -> ScanOptions {
+        self.cancel = Some(flag);
+        self
+    }
+
+    /// Sets how many files may be scanned at once, each on its own thread.
+    /// Results are still produced in the same order as when scanning one
+    /// file at a time.  Defaults to 1, which scans each file on the calling
+    /// thread as the iterator is advanced; 0 is treated as 1.
+    pub fn threads(mut self, threads: usize) -> ScanOptions {
+        self.threads = threads;
+        self
+    }
+}
+
+//===========================================================================//
+
+/// How scanning a single file turned out.
+#[derive(Debug)]
+pub enum ScanOutcome {
+    /// The file was opened as a compound file, and its manifest is complete.
+    Opened,
+    /// The file doesn't start with the compound file magic number, so it
+    /// wasn't opened.
+    NotCompoundFile,
+    /// The file is larger than the `max_file_size` limit (its length is
+    /// given), so it wasn't opened.
+    TooLarge(u64),
+    /// Scanning the file took longer than the `timeout` limit.  Whatever part
+    /// of the manifest was read before then is kept.
+    TimedOut,
+    /// The scan was cancelled while this file was being scanned.
+    Cancelled,
+    /// The file couldn't be read, or is a corrupt compound file.  This is
+    /// also used for a directory that couldn't be listed.
+    Failed(io::Error),
+}
+
+/// One object within a scanned compound file.
+#[derive(Clone, Debug)]
+pub struct ScanEntry {
+    entry: Entry,
+    hash: Option<u64>,
+}
+
+impl ScanEntry {
+    /// Returns the object's metadata.
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    /// Returns a 64-bit FNV-1a hash of the stream's contents, if this is a
+    /// stream and `hash_streams` was enabled.  This is meant for spotting
+    /// identical streams, not for security purposes.
+    pub fn hash(&self) -> Option<u64> {
+        self.hash
+    }
+}
+
+/// The result of scanning one file with [`scan_dir`](fn.scan_dir.html).
+#[derive(Debug)]
+pub struct ScanResult {
+    path: PathBuf,
+    outcome: ScanOutcome,
+    warnings: Vec<ValidationIssue>,
+    manifest: Vec<ScanEntry>,
+}
+
+impl ScanResult {
+    fn new(path: PathBuf, outcome: ScanOutcome) -> ScanResult {
+        ScanResult {
+            path,
+            outcome,
+            warnings: Vec::new(),
+            manifest: Vec::new(),
+        }
+    }
+
+    /// Returns the path of the file on disk.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how scanning the file turned out.
+    pub fn outcome(&self) -> &ScanOutcome {
+        &self.outcome
+    }
+
+    /// Returns true if the file was opened and fully scanned.
+    pub fn is_ok(&self) -> bool {
+        matches!(self.outcome, ScanOutcome::Opened)
+    }
+
+    /// Returns the spec violations that were tolerated while opening the
+    /// file (see
+    /// [`CompoundFile::open_warnings`](struct.CompoundFile.html#method.open_warnings)).
+    pub fn warnings(&self) -> &[ValidationIssue] {
+        &self.warnings
+    }
+
+    /// Returns every object in the file, in the order of
+    /// [`CompoundFile::walk`](struct.CompoundFile.html#method.walk).
+    pub fn manifest(&self) -> &[ScanEntry] {
+        &self.manifest
+    }
+}
+
+//===========================================================================//
+
+/// Scans the files in a local directory, opening each compound file
+/// permissively and recording its warnings and a manifest of its objects.
+///
+/// Files are scanned one at a time, in order of name (with each directory's
+/// files coming before its subdirectories), as the returned iterator is
+/// advanced; with [`ScanOptions::threads`](struct.ScanOptions.html#method.threads)
+/// set, a batch of files is scanned at once whenever the iterator runs out
+/// of results, but they are still returned in that order.  A problem with one file never stops the scan: a file that
+/// can't be read or is corrupt (even badly enough to make this crate panic)
+/// is reported as [`ScanOutcome::Failed`](enum.ScanOutcome.html), and the
+/// scan moves on to the next one.
+///
+/// ```no_run
+/// let options = cfb::ScanOptions::new().recursive(true).file_name("*.msi");
+/// for result in cfb::scan_dir("installers", options) {
+///     if !result.is_ok() {
+///         eprintln!("{}: {:?}", result.path().display(), result.outcome());
+///     }
+/// }
+/// ```
+pub fn scan_dir<P: AsRef<Path>>(path: P, options: ScanOptions) -> ScanDir {
+    ScanDir {
+        options,
+        dirs: vec![path.as_ref().to_path_buf()],
+        files: VecDeque::new(),
+        scanned: VecDeque::new(),
+        done: false,
+    }
+}
+
+/// The iterator returned by [`scan_dir`](fn.scan_dir.html).
+pub struct ScanDir {
+    options: ScanOptions,
+    /// Directories that have yet to be listed, with the next one last.
+    dirs: Vec<PathBuf>,
+    /// Files from the most recently listed directory that have yet to be
+    /// scanned.
+    files: VecDeque<PathBuf>,
+    /// Results of files scanned ahead (when scanning on several threads)
+    /// that have yet to be returned.
+    scanned: VecDeque<ScanResult>,
+    done: bool,
+}
+
+impl ScanDir {
+    fn is_cancelled(&self) -> bool {
+        is_cancelled(&self.options)
+    }
+
+    /// Lists the given directory, queueing up its (matching) files and, if
+    /// scanning recursively, its subdirectories.
+    fn list_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let mut files = Vec::new();
+        let mut subdirs = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if self.options.recursive {
+                    subdirs.push(entry.path());
+                }
+                continue;
+            }
+            // Follow symbolic links to files, but not to directories.
+            if file_type.is_symlink()
+                && !fs::metadata(entry.path()).is_ok_and(|meta| meta.is_file())
+            {
+                continue;
+            }
+            if self.matches(&entry.file_name().to_string_lossy()) {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        subdirs.sort();
+        self.files.extend(files);
+        self.dirs.extend(subdirs.into_iter().rev());
+        Ok(())
+    }
+
+    /// Scans the next batch of queued files across the configured number of
+    /// threads, queueing up their results in order.
+    fn scan_batch(&mut self) {
+        let threads = self.options.threads;
+        let batch_len = self.files.len().min(threads * FILES_PER_THREAD);
+        let paths: Vec<PathBuf> = self.files.drain(..batch_len).collect();
+        let slots: Vec<Mutex<Option<ScanResult>>> =
+            paths.iter().map(|_| Mutex::new(None)).collect();
+        let next_index = AtomicUsize::new(0);
+        let options = &self.options;
+        thread::scope(|scope| {
+            for _ in 0..threads.min(batch_len) {
+                scope.spawn(|| loop {
+                    let index = next_index.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(index) else {
+                        break;
+                    };
+                    let result = scan_file_isolated(path.clone(), options);
+                    *slots[index].lock().unwrap() = Some(result);
+                });
+            }
+        });
+        self.scanned.extend(
+            slots.into_iter().map(|slot| slot.into_inner().unwrap().unwrap()),
+        );
+    }
+
+    fn matches(&self, file_name: &str) -> bool {
+        self.options.file_names.is_empty()
+            || self
+                .options
+                .file_names
+                .iter()
+                .any(|pattern| glob_match(pattern, file_name))
+    }
+}
+
+impl Iterator for ScanDir {
+    type Item = ScanResult;
+
+    fn next(&mut self) -> Option<ScanResult> {
+        if self.done {
+            return None;
+        }
+        loop {
+            if self.is_cancelled() {
+                self.done = true;
+                return None;
+            }
+            if self.scanned.is_empty()
+                && self.options.threads > 1
+                && !self.files.is_empty()
+            {
+                self.scan_batch();
+            }
+            let next = match self.scanned.pop_front() {
+                Some(result) => Some(result),
+                None => self
+                    .files
+                    .pop_front()
+                    .map(|path| scan_file_isolated(path, &self.options)),
+            };
+            if let Some(result) = next {
+                if matches!(result.outcome, ScanOutcome::Cancelled) {
+                    self.done = true;
+                }
+                return Some(result);
+            }
+            let Some(dir) = self.dirs.pop() else {
+                self.done = true;
+                return None;
+            };
+            if let Err(error) = self.list_dir(&dir) {
+                return Some(ScanResult::new(dir, ScanOutcome::Failed(error)));
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Cancellation can end the scan at any point.  Until every directory
+        // has been listed, there's no telling how many files are left.
+        if self.done {
+            (0, Some(0))
+        } else if self.dirs.is_empty() {
+            (0, Some(self.files.len() + self.scanned.len()))
+        } else {
+            (0, None)
+        }
+    }
+}
+
+impl FusedIterator for ScanDir {}
+
+//===========================================================================//
+
+/// Scans a single file, turning a panic into a `Failed` outcome.
+fn scan_file_isolated(path: PathBuf, options: &ScanOptions) -> ScanResult {
+    let mut result = ScanResult::new(path, ScanOutcome::Opened);
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        scan_file(options, &mut result)
+    }));
+    result.outcome = match outcome {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(error)) => ScanOutcome::Failed(error),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            ScanOutcome::Failed(io::Error::other(format!(
+                "Panicked while scanning: {}",
+                message
+            )))
+        }
+    };
+    result
+}
+
+fn scan_file(
+    options: &ScanOptions,
+    result: &mut ScanResult,
+) -> io::Result<ScanOutcome> {
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut file = fs::File::open(&result.path)?;
+    let len = file.metadata()?.len();
+    if options.max_file_size.is_some_and(|max_len| len > max_len) {
+        return Ok(ScanOutcome::TooLarge(len));
+    }
+    let mut magic = [0u8; 8];
+    let num_read = ioutil::read_up_to(&mut file, &mut magic)?;
+    if num_read < magic.len() || magic != consts::MAGIC_NUMBER {
+        return Ok(ScanOutcome::NotCompoundFile);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    let mut comp = CompoundFile::open(file)?;
+    result.warnings = comp.open_warnings().to_vec();
+    let entries: Vec<Entry> = comp.walk().collect();
+    let mut buffer = vec![0u8; 0x10000];
+    for entry in entries {
+        if let Some(outcome) = check_limits(options, deadline) {
+            return Ok(outcome);
+        }
+        let hash = if options.hash_streams && entry.is_stream() {
+            let mut stream = comp.open_stream(entry.path())?;
+            let mut hasher = fnv::FnvHasher::default();
+            loop {
+                let count = ioutil::read_retrying(&mut stream, &mut buffer)?;
+                if count == 0 {
+                    break;
+                }
+                hasher.write(&buffer[..count]);
+                if let Some(outcome) = check_limits(options, deadline) {
+                    return Ok(outcome);
+                }
+            }
+            Some(hasher.finish())
+        } else {
+            None
+        };
+        result.manifest.push(ScanEntry { entry, hash });
+    }
+    Ok(ScanOutcome::Opened)
+}
+
+fn check_limits(
+    options: &ScanOptions,
+    deadline: Option<Instant>,
+) -> Option<ScanOutcome> {
+    if is_cancelled(options) {
+        Some(ScanOutcome::Cancelled)
+    } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        Some(ScanOutcome::TimedOut)
+    } else {
+        None
+    }
+}
+
+fn is_cancelled(options: &ScanOptions) -> bool {
+    options.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+//===========================================================================//
@@ -1,7 +1,17 @@
-use crate::internal::{consts, DirEntry, Version};
+use crate::internal::{
+    consts, ioutil, BackingFileShrunk, Capabilities, DirEntry, Metrics, Op,
+    Unsupported, Version,
+};
 use crate::WriteLeNumber;
 use std::cmp;
 use std::io::{self, Read, Seek, SeekFrom, Write};
+#[cfg(not(feature = "metrics"))]
+use std::marker::PhantomData;
+
+// ========================================================================= //
+
+/// The most zeros that `Sectors::zero_sectors` writes at a time.
+const ZERO_CHUNK_LEN: usize = 65536;
 
 // ========================================================================= //
 
@@ -11,6 +21,19 @@ pub struct Sectors<F> {
     inner: F,
     version: Version,
     num_sectors: u32,
+    /// The length that the underlying file must have for all of our sectors
+    /// to be readable.  This is the file's length when it was opened (whose
+    /// last sector may be partial), grown as sectors are appended and
+    /// reduced as they are forgotten.
+    expected_len: u64,
+    /// Set once the underlying file has been found to be shorter than
+    /// `expected_len`; flushing is refused until this is cleared.
+    shrunk: Option<BackingFileShrunk>,
+    /// Set for files opened with `CompoundFile::open_recover`, whose repairs
+    /// exist only in memory, or whose underlying file is known not to be
+    /// writable; all writes are then refused.
+    read_only: Option<ReadOnly>,
+    metrics: Metrics,
 }
 
 impl<F> Sectors<F> {
@@ -18,7 +41,15 @@ impl<F> Sectors<F> {
         let sector_len = version.sector_len() as u64;
         debug_assert!(inner_len >= sector_len);
         let num_sectors = inner_len.div_ceil(sector_len) as u32 - 1;
-        Sectors { inner, version, num_sectors }
+        Sectors {
+            inner,
+            version,
+            num_sectors,
+            expected_len: inner_len,
+            shrunk: None,
+            read_only: None,
+            metrics: Metrics::default(),
+        }
     }
 
     pub fn version(&self) -> Version {
@@ -40,9 +71,79 @@ impl<F> Sectors<F> {
     pub fn inner(&self) -> &F {
         &self.inner
     }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn metrics_mut(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+
+    /// Refuses all further writes to the underlying file.
+    pub fn set_read_only(&mut self, reason: ReadOnly) {
+        self.read_only = Some(reason);
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        match self.read_only {
+            Some(reason) => Err(reason.error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Forgets about all sectors from `num_sectors` onwards.  This doesn't
+    /// change the underlying file.
+    pub fn truncate(&mut self, num_sectors: u32) {
+        debug_assert!(num_sectors <= self.num_sectors);
+        self.num_sectors = num_sectors;
+        let sector_len = self.sector_len() as u64;
+        self.expected_len =
+            self.expected_len.min((num_sectors as u64 + 1) * sector_len);
+    }
 }
 
 impl<F: Seek> Sectors<F> {
+    /// Returns an error if the underlying file is (or has previously been
+    /// found to be) shorter than our sectors require.
+    pub fn check_backing_len(&mut self) -> io::Result<()> {
+        if let Some(shrunk) = self.shrunk {
+            return Err(shrunk.into());
+        }
+        let actual = self.inner.seek(SeekFrom::End(0))?;
+        if actual < self.expected_len {
+            let shrunk = BackingFileShrunk::new(self.expected_len, actual);
+            self.shrunk = Some(shrunk);
+            return Err(shrunk.into());
+        }
+        Ok(())
+    }
+
+    /// Returns the current length of the underlying file.
+    pub fn backing_len(&mut self) -> io::Result<u64> {
+        self.inner.seek(SeekFrom::End(0))
+    }
+
+    /// Given an error from reading sector data, returns a
+    /// `BackingFileShrunk` error instead if the read failed because the
+    /// underlying file is shorter than expected.  Any other error is
+    /// returned unchanged.
+    pub fn diagnose_read_error(&mut self, error: io::Error) -> io::Error {
+        if error.kind() != io::ErrorKind::UnexpectedEof
+            || BackingFileShrunk::from_io_error(&error).is_some()
+        {
+            return error;
+        }
+        match self.check_backing_len() {
+            Err(shrunk)
+                if BackingFileShrunk::from_io_error(&shrunk).is_some() =>
+            {
+                shrunk
+            }
+            _ => error,
+        }
+    }
+
     pub fn seek_within_header(
         &mut self,
         offset_within_header: u64,
@@ -53,6 +154,11 @@ impl<F: Seek> Sectors<F> {
             inner: &mut self.inner,
             sector_len: consts::HEADER_LEN,
             offset_within_sector: offset_within_header as usize,
+            read_only: self.read_only,
+            #[cfg(feature = "metrics")]
+            metrics: &self.metrics,
+            #[cfg(not(feature = "metrics"))]
+            metrics: PhantomData,
         })
     }
 
@@ -84,10 +190,43 @@ impl<F: Seek> Sectors<F> {
             inner: &mut self.inner,
             sector_len,
             offset_within_sector: offset_within_sector as usize,
+            read_only: self.read_only,
+            #[cfg(feature = "metrics")]
+            metrics: &self.metrics,
+            #[cfg(not(feature = "metrics"))]
+            metrics: PhantomData,
         })
     }
 }
 
+impl<F: Read + Seek> Sectors<F> {
+    /// Fills `buf` with the data starting at the given offset from the start
+    /// of the file, which may span any number of consecutive sectors.
+    pub fn read_span(
+        &mut self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        let sector_len = self.sector_len() as u64;
+        let end = offset + buf.len() as u64;
+        if offset < sector_len
+            || end > (self.num_sectors as u64 + 1) * sector_len
+        {
+            invalid_data!(
+                "Tried to read bytes {}..{}, but sector count is only {}",
+                offset,
+                end,
+                self.num_sectors
+            );
+        }
+        let timer = self.metrics.start();
+        self.inner.seek(SeekFrom::Start(offset))?;
+        ioutil::read_exact(&mut self.inner, buf)?;
+        self.metrics.record(Op::ReadSectors, timer, buf.len() as u64);
+        Ok(())
+    }
+}
+
 impl<F: Write + Seek> Sectors<F> {
     /// Creates or resets the specified sector using the given initializer.
     pub fn init_sector(
@@ -95,6 +234,7 @@ impl<F: Write + Seek> Sectors<F> {
         sector_id: u32,
         init: SectorInit,
     ) -> io::Result<()> {
+        self.check_writable()?;
         match sector_id.cmp(&self.num_sectors) {
             cmp::Ordering::Greater => invalid_data!(
                 "Tried to initialize sector {}, but sector count is only {}",
@@ -102,16 +242,153 @@ impl<F: Write + Seek> Sectors<F> {
                 self.num_sectors
             ),
             cmp::Ordering::Less => {}
-            cmp::Ordering::Equal => self.num_sectors += 1,
+            cmp::Ordering::Equal => {
+                self.num_sectors += 1;
+                let end =
+                    (self.num_sectors as u64 + 1) * self.sector_len() as u64;
+                self.expected_len = self.expected_len.max(end);
+            }
         }
         let mut sector = self.seek_to_sector(sector_id)?;
         init.initialize(&mut sector)?;
         Ok(())
     }
 
+    /// Zero-fills `count` consecutive sectors starting at the given sector,
+    /// with a single seek, creating any that are past the end of the file.
+    /// This is equivalent to (but much faster than) calling `init_sector`
+    /// with `SectorInit::Zero` on each of them in turn.
+    pub fn zero_sectors(
+        &mut self,
+        first_sector_id: u32,
+        count: u32,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        if first_sector_id > self.num_sectors {
+            invalid_data!(
+                "Tried to initialize sector {}, but sector count is only {}",
+                first_sector_id,
+                self.num_sectors
+            );
+        }
+        let sector_len = self.sector_len() as u64;
+        let end_sector_id = first_sector_id + count;
+        if end_sector_id > self.num_sectors {
+            self.num_sectors = end_sector_id;
+            let end = (self.num_sectors as u64 + 1) * sector_len;
+            self.expected_len = self.expected_len.max(end);
+        }
+        let timer = self.metrics.start();
+        let num_bytes = count as u64 * sector_len;
+        self.inner.seek(SeekFrom::Start(
+            (first_sector_id as u64 + 1) * sector_len,
+        ))?;
+        let zeros = [0u8; ZERO_CHUNK_LEN];
+        let mut remaining = num_bytes;
+        while remaining > 0 {
+            let chunk = remaining.min(ZERO_CHUNK_LEN as u64) as usize;
+            ioutil::write_all(&mut self.inner, &zeros[..chunk])?;
+            remaining -= chunk as u64;
+        }
+        self.metrics.record(Op::WriteSectors, timer, num_bytes);
+        Ok(())
+    }
+
+    /// Writes `buf` starting at the given offset from the start of the file,
+    /// which may span any number of consecutive existing sectors, with a
+    /// single seek and write.
+    pub fn write_span(&mut self, offset: u64, buf: &[u8]) -> io::Result<()> {
+        self.check_writable()?;
+        let sector_len = self.sector_len() as u64;
+        let end = offset + buf.len() as u64;
+        if offset < sector_len
+            || end > (self.num_sectors as u64 + 1) * sector_len
+        {
+            invalid_data!(
+                "Tried to write bytes {}..{}, but sector count is only {}",
+                offset,
+                end,
+                self.num_sectors
+            );
+        }
+        let timer = self.metrics.start();
+        self.inner.seek(SeekFrom::Start(offset))?;
+        ioutil::write_all(&mut self.inner, buf)?;
+        self.metrics.record(Op::WriteSectors, timer, buf.len() as u64);
+        Ok(())
+    }
+
+    /// Zero-fills the underlying file back up to the length that our sectors
+    /// require, if it has shrunk, and allows flushing again.  Returns the
+    /// number of bytes that were zero-filled.
+    pub fn repair_backing_len(&mut self) -> io::Result<u64> {
+        self.check_writable()?;
+        let actual = self.inner.seek(SeekFrom::End(0))?;
+        let missing = self.expected_len.saturating_sub(actual);
+        io::copy(&mut io::repeat(0).take(missing), &mut self.inner)?;
+        self.shrunk = None;
+        Ok(missing)
+    }
+
+    /// Takes on the sector count of `other`, a compacted copy of this file
+    /// (see `CompactLayout`), once `install` has put the copy in place of
+    /// the underlying file: either by overwriting the start of the
+    /// underlying file with the whole of the copy (leaving anything past its
+    /// end in place), or by replacing the underlying file outright.
+    pub fn adopt_compacted<G, I>(
+        &mut self,
+        other: Sectors<G>,
+        install: I,
+    ) -> io::Result<()>
+    where
+        I: FnOnce(&mut F, G) -> io::Result<()>,
+    {
+        self.check_writable()?;
+        install(&mut self.inner, other.inner)?;
+        self.num_sectors = other.num_sectors;
+        self.expected_len = other.expected_len;
+        Ok(())
+    }
+
+    /// Sets the length of the underlying file to the length that its
+    /// sectors require, using `set_len` (which must be able to shrink the
+    /// file), and returns that length.
+    pub fn truncate_backing(
+        &mut self,
+        set_len: fn(&mut F, u64) -> io::Result<()>,
+    ) -> io::Result<u64> {
+        self.check_writable()?;
+        set_len(&mut self.inner, self.expected_len)?;
+        Ok(self.expected_len)
+    }
+
     /// Flushes all changes to the underlying file.
     pub fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
+        ioutil::flush_retrying(&mut self.inner)
+    }
+}
+
+/// Why writes to the underlying file are refused.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReadOnly {
+    /// The file was opened with `CompoundFile::open_recover`.
+    Recovered,
+    /// The underlying file was opened without write access.
+    NotWritable,
+}
+
+impl ReadOnly {
+    fn error(self) -> io::Error {
+        match self {
+            ReadOnly::Recovered => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "The compound file was opened with open_recover, and can't \
+                 be modified",
+            ),
+            ReadOnly::NotWritable => {
+                Unsupported::new("write", Capabilities::WRITABLE).into()
+            }
+        }
     }
 }
 
@@ -123,6 +400,11 @@ pub struct Sector<'a, F: 'a> {
     inner: &'a mut F,
     sector_len: usize,
     offset_within_sector: usize,
+    read_only: Option<ReadOnly>,
+    #[cfg(feature = "metrics")]
+    metrics: &'a Metrics,
+    #[cfg(not(feature = "metrics"))]
+    metrics: PhantomData<&'a Metrics>,
 }
 
 impl<'a, F> Sector<'a, F> {
@@ -145,37 +427,87 @@ impl<'a, F> Sector<'a, F> {
             inner: self.inner,
             sector_len: len,
             offset_within_sector: self.offset_within_sector - start,
+            read_only: self.read_only,
+            metrics: self.metrics,
+        }
+    }
+
+    /// Reports a read or write of the underlying file to the metrics sink.
+    fn metered(
+        &mut self,
+        op: Op,
+        io: impl FnOnce(&mut F) -> io::Result<usize>,
+    ) -> io::Result<usize> {
+        #[cfg(feature = "metrics")]
+        {
+            let timer = self.metrics.start();
+            let num_bytes = io(self.inner)?;
+            self.metrics.record(op, timer, num_bytes as u64);
+            Ok(num_bytes)
+        }
+        #[cfg(not(feature = "metrics"))]
+        {
+            let _ = op;
+            io(self.inner)
         }
     }
 }
 
-impl<'a, F: Read> Read for Sector<'a, F> {
+impl<'a, F: Read + Seek> Read for Sector<'a, F> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let max_len = cmp::min(buf.len(), self.remaining());
         if max_len == 0 {
             return Ok(0);
         }
-        let bytes_read = self.inner.read(&mut buf[0..max_len])?;
+        let bytes_read = self.metered(Op::ReadSectors, |inner| {
+            ioutil::read_retrying(inner, &mut buf[0..max_len])
+        })?;
         self.offset_within_sector += bytes_read;
         debug_assert!(self.offset_within_sector <= self.len());
         Ok(bytes_read)
     }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if buf.len() > self.remaining() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Tried to read {} bytes, but only {} remain in the sector",
+                    buf.len(),
+                    self.remaining()
+                ),
+            ));
+        }
+        let bytes_read = self.metered(Op::ReadSectors, |inner| {
+            ioutil::read_up_to(inner, buf)
+        })?;
+        self.offset_within_sector += bytes_read;
+        if bytes_read < buf.len() {
+            return Err(ioutil::eof_error(self.inner, buf.len() - bytes_read));
+        }
+        Ok(())
+    }
 }
 
 impl<'a, F: Write> Write for Sector<'a, F> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(reason) = self.read_only {
+            return Err(reason.error());
+        }
         let max_len = cmp::min(buf.len(), self.remaining());
         if max_len == 0 {
             return Ok(0);
         }
-        let bytes_written = self.inner.write(&buf[0..max_len])?;
+        let bytes_written = self.metered(Op::WriteSectors, |inner| {
+            ioutil::write_retrying(inner, &buf[0..max_len])
+        })?;
         self.offset_within_sector += bytes_written;
         debug_assert!(self.offset_within_sector <= self.len());
         Ok(bytes_written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
+        ioutil::flush_retrying(self.inner)
     }
 }
 
@@ -189,8 +521,13 @@ impl<'a, F: Seek> Seek for Sector<'a, F> {
                 self.offset_within_sector as i64 + delta
             }
         };
-        if new_offset < 0 || new_offset > self.len() as i64 {
-            panic!("Internal error: cannot seek outside of sector");
+        let in_range = (0..=self.len() as i64).contains(&new_offset);
+        debug_assert!(
+            in_range,
+            "Internal error: cannot seek outside of sector"
+        );
+        if !in_range {
+            invalid_input!("Cannot seek outside of sector");
         }
         self.inner.seek(SeekFrom::Current(new_offset - old_offset))?;
         self.offset_within_sector = new_offset as usize;
@@ -214,36 +551,34 @@ impl SectorInit {
         sector: &mut Sector<'_, F>,
     ) -> io::Result<()> {
         debug_assert_eq!(sector.offset_within_sector, 0);
+        // Build the whole sector first, so that it is written to the
+        // underlying file all at once.
+        let mut data = Vec::with_capacity(sector.len());
         match self {
-            SectorInit::Zero => {
-                io::copy(
-                    &mut io::repeat(0).take(sector.len() as u64),
-                    sector,
-                )?;
-            }
+            SectorInit::Zero => data.resize(sector.len(), 0),
             SectorInit::Fat => {
                 debug_assert_eq!(sector.len() % 4, 0);
                 for _ in 0..(sector.len() / 4) {
-                    sector.write_le_u32(consts::FREE_SECTOR)?;
+                    data.write_le_u32(consts::FREE_SECTOR)?;
                 }
             }
             SectorInit::Difat => {
                 debug_assert_eq!(sector.len() % 4, 0);
                 debug_assert!(sector.len() >= 4);
                 for _ in 0..((sector.len() - 4) / 4) {
-                    sector.write_le_u32(consts::FREE_SECTOR)?;
+                    data.write_le_u32(consts::FREE_SECTOR)?;
                 }
-                sector.write_le_u32(consts::END_OF_CHAIN)?;
+                data.write_le_u32(consts::END_OF_CHAIN)?;
             }
             SectorInit::Dir => {
                 debug_assert_eq!(sector.len() % consts::DIR_ENTRY_LEN, 0);
                 let dir_entry = DirEntry::unallocated();
                 for _ in 0..(sector.len() / consts::DIR_ENTRY_LEN) {
-                    dir_entry.write_to(sector)?;
+                    dir_entry.write_to(&mut data)?;
                 }
             }
         }
-        Ok(())
+        sector.write_all(&data)
     }
 }
 
@@ -366,6 +701,7 @@ mod tests {
                     &mut sector,
                     Version::V3,
                     Validation::Strict,
+                    &mut Vec::new(),
                 )
                 .unwrap();
                 assert_eq!(dir_entry.obj_type, ObjType::Unallocated);
@@ -0,0 +1,93 @@
+use crate::internal::Stream;
+use std::cmp::Ordering;
+use std::io::{self, Cursor, Read, Seek};
+
+//===========================================================================//
+
+/// The name of the root-level stream holding an Authenticode signature.
+pub const DIGITAL_SIGNATURE_STREAM_NAME: &str = "\u{5}DigitalSignature";
+
+/// The name of the root-level stream holding the extended signature data
+/// (a hash of the file's metadata) that accompanies some Authenticode
+/// signatures.
+pub const MSI_DIGITAL_SIGNATURE_EX_STREAM_NAME: &str =
+    "\u{5}MsiDigitalSignatureEx";
+
+/// Returns true if the given root-level object name is one of the signature
+/// streams, which are excluded from the signed content.
+pub fn is_signature_stream_name(name: &str) -> bool {
+    name == DIGITAL_SIGNATURE_STREAM_NAME
+        || name == MSI_DIGITAL_SIGNATURE_EX_STREAM_NAME
+}
+
+/// Compares two sibling object names in the order that their contents are
+/// hashed for an Authenticode signature.
+///
+/// Note that this is *not* the order of `path::compare_names`: signing tools
+/// simply compare the raw UTF-16LE bytes of the names, so, for example,
+/// `"AA"` comes before `"b"`, and `'\u{4100}'` comes before `'\u{42}'`.
+pub fn compare_names_for_signature(name1: &str, name2: &str) -> Ordering {
+    let bytes1 = name1.encode_utf16().flat_map(u16::to_le_bytes);
+    let bytes2 = name2.encode_utf16().flat_map(u16::to_le_bytes);
+    bytes1.cmp(bytes2)
+}
+
+//===========================================================================//
+
+/// One piece of the content covered by an Authenticode signature, as
+/// returned by
+/// [`CompoundFile::signature_content_iter`](struct.CompoundFile.html#method.signature_content_iter).
+/// This is either the data of a stream or the 16-byte CLSID of a storage.
+pub struct SignatureContent<F> {
+    inner: SignatureContentInner<F>,
+}
+
+enum SignatureContentInner<F> {
+    Stream(Stream<F>),
+    Clsid(Cursor<[u8; 16]>),
+}
+
+impl<F> SignatureContent<F> {
+    pub(crate) fn stream(stream: Stream<F>) -> SignatureContent<F> {
+        SignatureContent { inner: SignatureContentInner::Stream(stream) }
+    }
+
+    pub(crate) fn clsid(clsid: [u8; 16]) -> SignatureContent<F> {
+        SignatureContent {
+            inner: SignatureContentInner::Clsid(Cursor::new(clsid)),
+        }
+    }
+
+    /// Returns true if this is the data of a stream, or false if it is the
+    /// CLSID of a storage (in its on-disk, little-endian byte order).
+    pub fn is_stream(&self) -> bool {
+        matches!(self.inner, SignatureContentInner::Stream(_))
+    }
+}
+
+impl<F: Read + Seek> Read for SignatureContent<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            SignatureContentInner::Stream(ref mut stream) => stream.read(buf),
+            SignatureContentInner::Clsid(ref mut cursor) => cursor.read(buf),
+        }
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::compare_names_for_signature;
+    use std::cmp::Ordering;
+
+    #[test]
+    fn signature_order() {
+        let mut names = vec!["b", "AA", "\u{4100}", "B", "A", "\u{42}x", "a"];
+        names.sort_by(|a, b| compare_names_for_signature(a, b));
+        assert_eq!(names, ["\u{4100}", "A", "AA", "B", "Bx", "a", "b"]);
+        assert_eq!(compare_names_for_signature("x", "x"), Ordering::Equal);
+    }
+}
+
+//===========================================================================//
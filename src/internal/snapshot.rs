@@ -0,0 +1,258 @@
+use crate::internal::{DirEntry, Entry, EntryKind, Timestamp};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// A copy of the metadata of every object in a compound file, made by
+/// [`CompoundFile::export_metadata`](../struct.CompoundFile.html#method.export_metadata):
+/// the tree of storages and streams, and each object's CLSID, state bits,
+/// and timestamps, but none of the streams' contents.  It can be put back
+/// with
+/// [`CompoundFile::restore_metadata`](../struct.CompoundFile.html#method.restore_metadata).
+///
+/// With the `serde` feature enabled, snapshots can be serialized, so that
+/// they can be kept outside of the process that made them.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetadataSnapshot {
+    pub(crate) entries: Vec<SnapshotEntry>,
+}
+
+impl MetadataSnapshot {
+    /// Returns the snapshot's objects, in the order that
+    /// [`CompoundFile::walk`](../struct.CompoundFile.html#method.walk)
+    /// visited them: each storage comes before everything within it.
+    pub fn entries(&self) -> &[SnapshotEntry] {
+        &self.entries
+    }
+
+    /// Returns the snapshot of the object at the given path, if there is
+    /// one.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&SnapshotEntry> {
+        self.entries.iter().find(|entry| entry.path == path.as_ref())
+    }
+}
+
+/// The metadata of one object in a [`MetadataSnapshot`].  Every field is
+/// kept exactly as it was in the directory entry, so restoring it writes
+/// back exactly the same bytes, even for fields (such as the timestamps of
+/// a stream) that the CFB spec says should be zero.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotEntry {
+    pub(crate) path: PathBuf,
+    pub(crate) kind: EntryKind,
+    pub(crate) clsid: Uuid,
+    pub(crate) state_bits: u32,
+    pub(crate) creation_time: u64,
+    pub(crate) modified_time: u64,
+    pub(crate) stream_len: u64,
+}
+
+impl SnapshotEntry {
+    pub(crate) fn new(entry: &Entry, dir_entry: &DirEntry) -> SnapshotEntry {
+        SnapshotEntry {
+            path: entry.path().to_path_buf(),
+            kind: entry.file_type(),
+            clsid: dir_entry.clsid,
+            state_bits: dir_entry.state_bits,
+            creation_time: dir_entry.creation_time.value(),
+            modified_time: dir_entry.modified_time.value(),
+            stream_len: entry.len(),
+        }
+    }
+
+    /// Returns true if the given directory entry already has this entry's
+    /// metadata.
+    pub(crate) fn matches(&self, dir_entry: &DirEntry) -> bool {
+        dir_entry.clsid == self.clsid
+            && dir_entry.state_bits == self.state_bits
+            && dir_entry.creation_time.value() == self.creation_time
+            && dir_entry.modified_time.value() == self.modified_time
+    }
+
+    /// Gives the given directory entry this entry's metadata.
+    pub(crate) fn copy_into(&self, dir_entry: &mut DirEntry) {
+        dir_entry.clsid = self.clsid;
+        dir_entry.state_bits = self.state_bits;
+        dir_entry.creation_time = Timestamp::from_value(self.creation_time);
+        dir_entry.modified_time = Timestamp::from_value(self.modified_time);
+    }
+
+    /// Returns the path of the object.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns what kind of object this is.
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    /// Returns the object's CLSID.
+    pub fn clsid(&self) -> &Uuid {
+        &self.clsid
+    }
+
+    /// Returns the object's user-defined state bits.
+    pub fn state_bits(&self) -> u32 {
+        self.state_bits
+    }
+
+    /// Returns the object's creation time.
+    pub fn created(&self) -> SystemTime {
+        Timestamp::from_value(self.creation_time).to_system_time()
+    }
+
+    /// Returns the object's modification time.
+    pub fn modified(&self) -> SystemTime {
+        Timestamp::from_value(self.modified_time).to_system_time()
+    }
+
+    /// Returns the length of the stream when the snapshot was made, or zero
+    /// for a storage.  This is for reference only: restoring a snapshot
+    /// never changes the length or contents of a stream.
+    pub fn len(&self) -> u64 {
+        self.stream_len
+    }
+
+    /// Returns true if this is an empty stream, or a storage.
+    pub fn is_empty(&self) -> bool {
+        self.stream_len == 0
+    }
+}
+
+//===========================================================================//
+
+/// What
+/// [`CompoundFile::restore_metadata`](../struct.CompoundFile.html#method.restore_metadata)
+/// does about an object that is in the snapshot, but no longer in the file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MissingEntries {
+    /// Fail with a `NotFound` error, before changing anything.
+    Error,
+    /// Leave the object out.
+    Skip,
+    /// Create the object again, as an empty stream or storage, with the
+    /// snapshot's metadata.
+    RecreateEmpty,
+}
+
+/// What
+/// [`CompoundFile::restore_metadata`](../struct.CompoundFile.html#method.restore_metadata)
+/// does about an object that is in the file, but wasn't when the snapshot
+/// was made.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ExtraEntries {
+    /// Fail with an `AlreadyExists` error, before changing anything.
+    Error,
+    /// Leave the object as it is.
+    Skip,
+    /// Remove the object, along with everything within it.
+    Remove,
+}
+
+/// Options for
+/// [`CompoundFile::restore_metadata`](../struct.CompoundFile.html#method.restore_metadata),
+/// saying what to do where the file's tree no longer matches the
+/// snapshot's.  An object that has been renamed since the snapshot was made
+/// counts as missing at its old path and extra at its new one; one that
+/// has been replaced by an object of the other kind (a stream by a
+/// storage, or vice versa) counts as both.
+///
+/// ```
+/// use cfb::{ExtraEntries, MissingEntries, RestorePolicy};
+///
+/// let policy = RestorePolicy::new()
+///     .missing(MissingEntries::RecreateEmpty)
+///     .extra(ExtraEntries::Remove);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RestorePolicy {
+    pub(crate) missing: MissingEntries,
+    pub(crate) extra: ExtraEntries,
+}
+
+impl RestorePolicy {
+    /// Returns the default policy, which fails (without changing anything)
+    /// unless the file has exactly the same objects as the snapshot.
+    pub fn new() -> RestorePolicy {
+        RestorePolicy::default()
+    }
+
+    /// Sets what to do about objects that are in the snapshot but not in
+    /// the file.  Defaults to [`MissingEntries::Error`].
+    pub fn missing(mut self, missing: MissingEntries) -> RestorePolicy {
+        self.missing = missing;
+        self
+    }
+
+    /// Sets what to do about objects that are in the file but not in the
+    /// snapshot.  Defaults to [`ExtraEntries::Error`].
+    pub fn extra(mut self, extra: ExtraEntries) -> RestorePolicy {
+        self.extra = extra;
+        self
+    }
+}
+
+impl Default for RestorePolicy {
+    fn default() -> RestorePolicy {
+        RestorePolicy {
+            missing: MissingEntries::Error,
+            extra: ExtraEntries::Error,
+        }
+    }
+}
+
+//===========================================================================//
+
+/// A report of what was changed by
+/// [`CompoundFile::restore_metadata`](../struct.CompoundFile.html#method.restore_metadata).
+/// All paths are paths within the compound file, in the order in which the
+/// changes were made.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RestoreReport {
+    pub(crate) updated: Vec<PathBuf>,
+    pub(crate) recreated: Vec<PathBuf>,
+    pub(crate) removed: Vec<PathBuf>,
+    pub(crate) skipped: Vec<PathBuf>,
+}
+
+impl RestoreReport {
+    /// Returns the paths of the existing objects whose metadata was changed
+    /// back to the snapshot's.
+    pub fn updated(&self) -> &[PathBuf] {
+        &self.updated
+    }
+
+    /// Returns the paths of the objects that were created again, empty,
+    /// with [`MissingEntries::RecreateEmpty`].
+    pub fn recreated(&self) -> &[PathBuf] {
+        &self.recreated
+    }
+
+    /// Returns the paths of the objects that were removed with
+    /// [`ExtraEntries::Remove`].  Objects within a removed storage aren't
+    /// listed separately.
+    pub fn removed(&self) -> &[PathBuf] {
+        &self.removed
+    }
+
+    /// Returns the paths of the objects, missing from the file or extra,
+    /// that were left alone with [`MissingEntries::Skip`] or
+    /// [`ExtraEntries::Skip`].
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    /// Returns true if nothing in the compound file was changed.
+    pub fn is_unchanged(&self) -> bool {
+        self.updated.is_empty()
+            && self.recreated.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+//===========================================================================//
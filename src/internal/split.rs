@@ -0,0 +1,160 @@
+use crate::internal::sanitize::SUMMARY_INFO_STREAM_NAME;
+use crate::{CompoundFile, Entry};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+//===========================================================================//
+
+/// Options for
+/// [`CompoundFile::split`](struct.CompoundFile.html#method.split).
+///
+/// ```
+/// use cfb::SplitOptions;
+///
+/// let options = SplitOptions::new()
+///     .summary_information(true)
+///     .top_level_streams(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SplitOptions {
+    pub(crate) summary_information: bool,
+    pub(crate) top_level_streams: bool,
+}
+
+impl SplitOptions {
+    /// Returns the default options: each output holds only its storage, and
+    /// streams outside of any storage are skipped.
+    pub fn new() -> SplitOptions {
+        SplitOptions::default()
+    }
+
+    /// If true, a copy of the source's `\u{5}SummaryInformation` stream (if
+    /// it has one) is added to the root of each storage's output, unless
+    /// the storage already has its own.  Defaults to false.
+    pub fn summary_information(mut self, include: bool) -> SplitOptions {
+        self.summary_information = include;
+        self
+    }
+
+    /// If true, the streams directly within the source's root storage are
+    /// written to one more output, obtained by calling the output function
+    /// with the source's root entry, after all of the storages.  If false,
+    /// they are skipped.  Either way, they are listed in the report.
+    /// Defaults to false.
+    pub fn top_level_streams(mut self, include: bool) -> SplitOptions {
+        self.top_level_streams = include;
+        self
+    }
+}
+
+//===========================================================================//
+
+/// The result of
+/// [`CompoundFile::split`](struct.CompoundFile.html#method.split).
+#[derive(Clone, Debug, Default)]
+pub struct SplitReport {
+    pub(crate) storages: Vec<PathBuf>,
+    pub(crate) top_level_streams: Vec<PathBuf>,
+    pub(crate) top_level_streams_written: bool,
+    pub(crate) num_streams: u64,
+    pub(crate) num_bytes: u64,
+}
+
+impl SplitReport {
+    /// Returns the path of each top-level storage that was written to its
+    /// own output, in the order the outputs were requested.
+    pub fn storages(&self) -> &[PathBuf] {
+        &self.storages
+    }
+
+    /// Returns the path of each stream directly within the source's root
+    /// storage.
+    pub fn top_level_streams(&self) -> &[PathBuf] {
+        &self.top_level_streams
+    }
+
+    /// Returns true if the top-level streams were written to an output of
+    /// their own, or false if they were skipped (or there weren't any).
+    pub fn top_level_streams_written(&self) -> bool {
+        self.top_level_streams_written
+    }
+
+    /// Returns the total number of streams written, across all outputs.
+    pub fn num_streams(&self) -> u64 {
+        self.num_streams
+    }
+
+    /// Returns the total length of the streams written, in bytes.
+    pub fn num_bytes(&self) -> u64 {
+        self.num_bytes
+    }
+}
+
+//===========================================================================//
+
+/// Implements [`CompoundFile::split`].
+pub fn split<F, W, O>(
+    comp: &mut CompoundFile<F>,
+    options: SplitOptions,
+    mut output: O,
+) -> io::Result<SplitReport>
+where
+    F: Read + Seek,
+    W: Read + Write + Seek,
+    O: FnMut(&Entry) -> io::Result<W>,
+{
+    let mut report = SplitReport::default();
+    let summary_path = Path::new("/").join(SUMMARY_INFO_STREAM_NAME);
+    let summary =
+        if options.summary_information && comp.is_stream(&summary_path) {
+            Some(comp.read_stream_to_vec(&summary_path)?)
+        } else {
+            None
+        };
+    let (storages, streams): (Vec<Entry>, Vec<Entry>) =
+        comp.read_root_storage().partition(Entry::is_storage);
+    for storage in storages {
+        let writer = output(&storage)?;
+        let mut split = comp.export_storage_as_cfb(storage.path(), writer)?;
+        if let Some(ref data) = summary {
+            if !split.exists(&summary_path) {
+                split.create_stream(&summary_path)?.write_all(data)?;
+                split.flush()?;
+            }
+        }
+        count_streams(&split, &mut report);
+        report.storages.push(storage.path().to_path_buf());
+    }
+    report.top_level_streams =
+        streams.iter().map(|entry| entry.path().to_path_buf()).collect();
+    if options.top_level_streams && !streams.is_empty() {
+        let root = comp.root_entry();
+        let writer = output(&root)?;
+        let mut split =
+            CompoundFile::create_with_version(comp.version(), writer)?;
+        split.set_storage_clsid("/", *root.clsid())?;
+        split.set_state_bits("/", root.state_bits())?;
+        split.set_modified_time("/", root.modified())?;
+        for entry in streams.iter() {
+            let mut source = comp.open_stream(entry.path())?;
+            let mut dest = split.create_stream(entry.path())?;
+            io::copy(&mut source, &mut dest)?;
+            dest.flush()?;
+            drop(dest);
+            split.set_state_bits(entry.path(), entry.state_bits())?;
+        }
+        split.flush()?;
+        count_streams(&split, &mut report);
+        report.top_level_streams_written = true;
+    }
+    Ok(report)
+}
+
+fn count_streams<W>(split: &CompoundFile<W>, report: &mut SplitReport) {
+    for entry in split.walk().filter(Entry::is_stream) {
+        report.num_streams += 1;
+        report.num_bytes += entry.len();
+    }
+}
+
+//===========================================================================//
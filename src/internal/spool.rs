@@ -0,0 +1,218 @@
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//===========================================================================//
+
+/// Determines where [`open_from_reader`](../fn.open_from_reader.html) buffers
+/// the data read from a non-seekable reader.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpoolPolicy {
+    /// Buffer all data in memory.
+    Memory,
+    /// Buffer data in memory until it exceeds `threshold` bytes, then move
+    /// it to a temporary file (which is deleted when the spool is dropped).
+    TempFile {
+        /// The maximum number of bytes to keep in memory.
+        threshold: u64,
+    },
+}
+
+//===========================================================================//
+
+/// A seekable, readable, and writable buffer holding data spooled from a
+/// reader, either in memory or in a temporary file.
+///
+/// A spool created with [`SpoolPolicy::TempFile`] starts out in memory and
+/// transparently moves its contents to a temporary file as soon as a write
+/// would grow it past the policy's threshold.
+pub struct Spool {
+    threshold: Option<u64>,
+    inner: SpoolInner,
+}
+
+enum SpoolInner {
+    Memory(Cursor<Vec<u8>>),
+    TempFile(TempFile),
+}
+
+impl Spool {
+    /// Creates a new, empty spool with the given policy.
+    pub fn new(policy: SpoolPolicy) -> Spool {
+        let threshold = match policy {
+            SpoolPolicy::Memory => None,
+            SpoolPolicy::TempFile { threshold } => Some(threshold),
+        };
+        Spool { threshold, inner: SpoolInner::Memory(Cursor::new(Vec::new())) }
+    }
+
+    /// Returns true if the spooled data has been moved to a temporary file,
+    /// or false if it is still held in memory.
+    pub fn is_temp_file(&self) -> bool {
+        matches!(self.inner, SpoolInner::TempFile(_))
+    }
+
+    fn migrate_if_needed(&mut self, num_bytes: usize) -> io::Result<()> {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return Ok(()),
+        };
+        let cursor = match self.inner {
+            SpoolInner::Memory(ref mut cursor) => cursor,
+            SpoolInner::TempFile(_) => return Ok(()),
+        };
+        let new_end = cursor.position().saturating_add(num_bytes as u64);
+        if new_end.max(cursor.get_ref().len() as u64) <= threshold {
+            return Ok(());
+        }
+        let mut temp = TempFile::create()?;
+        temp.file.write_all(cursor.get_ref())?;
+        temp.file.seek(SeekFrom::Start(cursor.position()))?;
+        self.inner = SpoolInner::TempFile(temp);
+        Ok(())
+    }
+}
+
+impl Read for Spool {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner {
+            SpoolInner::Memory(ref mut cursor) => cursor.read(buf),
+            SpoolInner::TempFile(ref mut temp) => temp.file.read(buf),
+        }
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.migrate_if_needed(buf.len())?;
+        match self.inner {
+            SpoolInner::Memory(ref mut cursor) => cursor.write(buf),
+            SpoolInner::TempFile(ref mut temp) => temp.file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            SpoolInner::Memory(ref mut cursor) => cursor.flush(),
+            SpoolInner::TempFile(ref mut temp) => temp.file.flush(),
+        }
+    }
+}
+
+impl Seek for Spool {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self.inner {
+            SpoolInner::Memory(ref mut cursor) => cursor.seek(pos),
+            SpoolInner::TempFile(ref mut temp) => temp.file.seek(pos),
+        }
+    }
+}
+
+//===========================================================================//
+
+/// A read-write file in the system's temporary directory that is deleted
+/// when dropped.
+struct TempFile {
+    file: fs::File,
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create() -> io::Result<TempFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or(0);
+        loop {
+            let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let name =
+                format!("cfb-spool-{}-{}-{}", process::id(), nanos, count);
+            let path = env::temp_dir().join(name);
+            match fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok(TempFile { file, path }),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{Spool, SpoolPolicy};
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+
+    fn spooled(policy: SpoolPolicy, data: &[u8]) -> Spool {
+        let mut spool = Spool::new(policy);
+        io::copy(&mut &data[..], &mut spool).unwrap();
+        spool.seek(SeekFrom::Start(0)).unwrap();
+        spool
+    }
+
+    fn contents(spool: &mut Spool) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        spool.seek(SeekFrom::Start(0)).unwrap();
+        spool.read_to_end(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn memory_spool_stays_in_memory() {
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        let mut spool = spooled(SpoolPolicy::Memory, &data);
+        assert!(!spool.is_temp_file());
+        assert_eq!(contents(&mut spool), data);
+    }
+
+    #[test]
+    fn temp_file_spool_below_threshold_stays_in_memory() {
+        let data = vec![7u8; 1000];
+        let policy = SpoolPolicy::TempFile { threshold: 1000 };
+        let mut spool = spooled(policy, &data);
+        assert!(!spool.is_temp_file());
+        assert_eq!(contents(&mut spool), data);
+    }
+
+    #[test]
+    fn temp_file_spool_migrates_mid_stream() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let policy = SpoolPolicy::TempFile { threshold: 4096 };
+        let mut spool = spooled(policy, &data);
+        assert!(spool.is_temp_file());
+        assert_eq!(contents(&mut spool), data);
+    }
+
+    #[test]
+    fn migration_preserves_position() {
+        let policy = SpoolPolicy::TempFile { threshold: 10 };
+        let mut spool = Spool::new(policy);
+        spool.write_all(b"0123456789").unwrap();
+        assert!(!spool.is_temp_file());
+        spool.seek(SeekFrom::Start(4)).unwrap();
+        spool.write_all(b"abcdefghij").unwrap();
+        assert!(spool.is_temp_file());
+        assert_eq!(spool.stream_position().unwrap(), 14);
+        assert_eq!(contents(&mut spool), b"0123abcdefghij");
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,299 @@
+use crate::internal::consts;
+
+//===========================================================================//
+
+/// Statistics about the physical layout of a compound file, as returned by
+/// [`CompoundFile::stats`](../struct.CompoundFile.html#method.stats).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Stats {
+    pub(crate) num_sectors: u32,
+    pub(crate) num_free_sectors: u32,
+    pub(crate) num_fat_sectors: u32,
+    pub(crate) num_difat_sectors: u32,
+    pub(crate) num_dir_sectors: u32,
+    pub(crate) num_minifat_sectors: u32,
+    pub(crate) num_mini_stream_sectors: u32,
+    pub(crate) num_free_mini_sectors: u32,
+    pub(crate) num_free_dir_entries: u32,
+    pub(crate) num_shared_streams: u32,
+    pub(crate) num_fragments: u32,
+    pub(crate) metadata_spread: u32,
+}
+
+impl Stats {
+    /// Computes layout statistics from the FAT and the sector IDs of the
+    /// directory chain, MiniFAT chain, and mini stream chain.
+    pub(crate) fn new(
+        fat: &[u32],
+        dir_sectors: &[u32],
+        minifat_sectors: &[u32],
+        mini_stream_sectors: &[u32],
+    ) -> Stats {
+        let mut num_free_sectors = 0;
+        let mut num_fat_sectors = 0;
+        let mut num_difat_sectors = 0;
+        let mut num_fragments = 0;
+        let mut metadata = vec![false; fat.len()];
+        for (sector_id, &next) in fat.iter().enumerate() {
+            match next {
+                consts::FREE_SECTOR => num_free_sectors += 1,
+                consts::FAT_SECTOR => {
+                    num_fat_sectors += 1;
+                    metadata[sector_id] = true;
+                }
+                consts::DIFAT_SECTOR => {
+                    num_difat_sectors += 1;
+                    metadata[sector_id] = true;
+                }
+                next if next <= consts::MAX_REGULAR_SECTOR
+                    && next as usize != sector_id + 1 =>
+                {
+                    num_fragments += 1;
+                }
+                _ => {}
+            }
+        }
+        for &sector_id in dir_sectors
+            .iter()
+            .chain(minifat_sectors)
+            .chain(mini_stream_sectors)
+        {
+            if let Some(flag) = metadata.get_mut(sector_id as usize) {
+                *flag = true;
+            }
+        }
+        let first = metadata.iter().position(|&flag| flag);
+        let last = metadata.iter().rposition(|&flag| flag);
+        let metadata_spread = match (first, last) {
+            (Some(first), Some(last)) => (first..=last)
+                .filter(|&index| {
+                    !metadata[index] && fat[index] != consts::FREE_SECTOR
+                })
+                .count() as u32,
+            _ => 0,
+        };
+        Stats {
+            num_sectors: fat.len() as u32,
+            num_free_sectors,
+            num_fat_sectors,
+            num_difat_sectors,
+            num_dir_sectors: dir_sectors.len() as u32,
+            num_minifat_sectors: minifat_sectors.len() as u32,
+            num_mini_stream_sectors: mini_stream_sectors.len() as u32,
+            num_free_mini_sectors: 0,
+            num_free_dir_entries: 0,
+            num_shared_streams: 0,
+            num_fragments,
+            metadata_spread,
+        }
+    }
+
+    /// Returns the total number of sectors in the file (not counting the
+    /// header).
+    pub fn num_sectors(&self) -> u32 {
+        self.num_sectors
+    }
+
+    /// Returns the number of sectors that are currently unallocated.
+    pub fn num_free_sectors(&self) -> u32 {
+        self.num_free_sectors
+    }
+
+    /// Returns the number of sectors used to store the FAT.
+    pub fn num_fat_sectors(&self) -> u32 {
+        self.num_fat_sectors
+    }
+
+    /// Returns the number of sectors used to store the DIFAT (not counting
+    /// the DIFAT entries stored in the header).
+    pub fn num_difat_sectors(&self) -> u32 {
+        self.num_difat_sectors
+    }
+
+    /// Returns the number of sectors in the directory chain.
+    pub fn num_dir_sectors(&self) -> u32 {
+        self.num_dir_sectors
+    }
+
+    /// Returns the number of sectors in the MiniFAT chain.
+    pub fn num_minifat_sectors(&self) -> u32 {
+        self.num_minifat_sectors
+    }
+
+    /// Returns the number of sectors in the chain backing the mini stream.
+    pub fn num_mini_stream_sectors(&self) -> u32 {
+        self.num_mini_stream_sectors
+    }
+
+    /// Returns the number of unallocated mini sectors within the mini stream,
+    /// all of which are available for reuse without growing the file.
+    pub fn num_free_mini_sectors(&self) -> u32 {
+        self.num_free_mini_sectors
+    }
+
+    /// Returns the number of unallocated directory entries within the
+    /// directory chain, all of which are available for reuse without growing
+    /// the file.
+    pub fn num_free_dir_entries(&self) -> u32 {
+        self.num_free_dir_entries
+    }
+
+    /// Returns the number of streams whose data chain is also used by at
+    /// least one other stream, as written by
+    /// [`CompoundFile::create_stream_dedup`](../struct.CompoundFile.html#method.create_stream_dedup).
+    /// Such aliasing is permitted by the format, and is not treated as
+    /// corruption when opening a file.
+    pub fn num_shared_streams(&self) -> u32 {
+        self.num_shared_streams
+    }
+
+    /// Returns the number of places where a sector chain (of any kind) jumps
+    /// to a sector other than the one physically following it.  A file in
+    /// which every chain is stored contiguously has zero fragments.
+    pub fn num_fragments(&self) -> u32 {
+        self.num_fragments
+    }
+
+    /// Returns the number of sectors holding stream data that lie between the
+    /// first and last metadata sectors (FAT, DIFAT, directory, MiniFAT, and
+    /// mini stream sectors).  Zero means that all metadata is clustered
+    /// together at one place in the file, with no stream data interleaved.
+    pub fn metadata_spread(&self) -> u32 {
+        self.metadata_spread
+    }
+}
+
+//===========================================================================//
+
+/// A breakdown of every byte that a compound file's FAT describes by whether
+/// anything can still reach it, as returned by
+/// [`CompoundFile::reachability`](../struct.CompoundFile.html#method.reachability).
+///
+/// The categories are disjoint, and always add up to
+/// [`total_bytes`](#method.total_bytes).  The mini stream is divided up mini
+/// sector by mini sector, rather than being counted as a whole, and a chain
+/// shared by several streams is only counted once.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Reachability {
+    pub(crate) reachable_bytes: u64,
+    pub(crate) slack_bytes: u64,
+    pub(crate) orphaned_bytes: u64,
+    pub(crate) free_bytes: u64,
+    pub(crate) metadata_bytes: u64,
+    pub(crate) total_bytes: u64,
+    pub(crate) file_len: u64,
+}
+
+impl Reachability {
+    /// Returns the number of bytes of stream data that can be read by
+    /// following the directory tree down from the root storage.
+    pub fn reachable_bytes(&self) -> u64 {
+        self.reachable_bytes
+    }
+
+    /// Returns the number of bytes in the chains of reachable streams (and of
+    /// the mini stream) past the end of their data: the unused end of each
+    /// chain's last (mini) sector, plus any whole (mini) sectors beyond that.
+    pub fn slack_bytes(&self) -> u64 {
+        self.slack_bytes
+    }
+
+    /// Returns the number of bytes in (mini) sectors that are allocated, but
+    /// that are not used by any reachable stream or by the file's own
+    /// structures, such as the chains of removed streams whose directory
+    /// entries were freed without freeing the chains (see
+    /// [`CompoundFile::stale_chains`](../struct.CompoundFile.html#method.stale_chains)),
+    /// or the chains of stream entries that aren't linked into the
+    /// directory tree.
+    pub fn orphaned_bytes(&self) -> u64 {
+        self.orphaned_bytes
+    }
+
+    /// Returns the number of bytes in unallocated sectors and unallocated
+    /// mini sectors.
+    pub fn free_bytes(&self) -> u64 {
+        self.free_bytes
+    }
+
+    /// Returns the number of bytes used by the file's own structures: the
+    /// header, and the FAT, DIFAT, directory, and MiniFAT sectors.
+    pub fn metadata_bytes(&self) -> u64 {
+        self.metadata_bytes
+    }
+
+    /// Returns the length that the FAT describes: the header plus one sector
+    /// for each FAT entry.  This is the sum of all the other categories.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// Returns the actual length of the underlying file, which may differ
+    /// from [`total_bytes`](#method.total_bytes) if the file is truncated or
+    /// has extra data past its last sector.
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    /// Returns how many of the bytes that the FAT describes are missing from
+    /// the end of the underlying file (including the missing part of a
+    /// partial last sector).  This is zero unless the file is truncated.
+    pub fn missing_bytes(&self) -> u64 {
+        self.total_bytes.saturating_sub(self.file_len)
+    }
+
+    /// Returns how many bytes the underlying file has past the end of the
+    /// length that the FAT describes, such as those left behind by
+    /// [`CompoundFile::compact`](../struct.CompoundFile.html#method.compact)
+    /// when the file can't be truncated.
+    pub fn excess_bytes(&self) -> u64 {
+        self.file_len.saturating_sub(self.total_bytes)
+    }
+
+    /// Returns the fraction of the bytes that the FAT describes that are
+    /// neither orphaned nor missing from the underlying file, from 0.0 to
+    /// 1.0.  Slack and free space are normal in any compound file, and don't
+    /// lower the score; a file that was written and closed cleanly scores
+    /// 1.0.
+    pub fn integrity_score(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 1.0;
+        }
+        let damaged = self.orphaned_bytes.saturating_add(self.missing_bytes());
+        let intact = self.total_bytes.saturating_sub(damaged);
+        intact as f64 / self.total_bytes as f64
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+    use crate::internal::consts::{END_OF_CHAIN, FAT_SECTOR, FREE_SECTOR};
+
+    #[test]
+    fn contiguous_layout() {
+        let fat = vec![FAT_SECTOR, END_OF_CHAIN, 3, END_OF_CHAIN];
+        let stats = Stats::new(&fat, &[1], &[], &[]);
+        assert_eq!(stats.num_sectors(), 4);
+        assert_eq!(stats.num_fat_sectors(), 1);
+        assert_eq!(stats.num_dir_sectors(), 1);
+        assert_eq!(stats.num_fragments(), 0);
+        assert_eq!(stats.metadata_spread(), 0);
+    }
+
+    #[test]
+    fn fragmented_layout() {
+        let fat =
+            vec![FAT_SECTOR, 4, 3, END_OF_CHAIN, END_OF_CHAIN, FREE_SECTOR];
+        let stats = Stats::new(&fat, &[1, 4], &[], &[]);
+        assert_eq!(stats.num_free_sectors(), 1);
+        assert_eq!(stats.num_fragments(), 1);
+        assert_eq!(stats.metadata_spread(), 2);
+        let fat = vec![FAT_SECTOR, FREE_SECTOR, END_OF_CHAIN, FREE_SECTOR];
+        let stats = Stats::new(&fat, &[2], &[], &[]);
+        assert_eq!(stats.metadata_spread(), 0);
+    }
+}
+
+//===========================================================================//
@@ -1,5 +1,15 @@
-use crate::internal::{consts, MiniAllocator, ObjType, SectorInit};
-use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use crate::internal::{
+    consts, try_zeroed_vec, MiniAllocator, ObjType, Op, RequiresVersion4,
+    SectorInit, Version,
+};
+use crate::CompoundFile;
+use std::error::Error;
+use std::fmt;
+use std::io::{
+    self, BufRead, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write,
+};
+use std::marker::PhantomData;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock, Weak};
 
 //===========================================================================//
@@ -9,15 +19,58 @@ const BUFFER_SIZE: usize = 8192;
 //===========================================================================//
 
 /// A stream entry in a compound file, much like a filesystem file.
+///
+/// The stream's directory entry (its length and starting sector) is owned by
+/// the `CompoundFile`; a `Stream` never keeps its own copy of it, other than
+/// the length implied by any writes that it has buffered but not yet flushed.
+/// As with file descriptors in a filesystem, the same stream may be opened
+/// more than once: each handle sees length changes made through other handles
+/// (or through the `CompoundFile`) as soon as it has no unflushed writes of
+/// its own, and if the stream was truncated to before this handle's position,
+/// the position is moved to the new end of the stream.  Buffered writes that
+/// land past the end of a stream that was truncated in the meantime are
+/// flushed after zero-padding the stream up to the write position.
+///
+/// If the stream is removed from the compound file while a handle to it is
+/// still open, the handle is cut off from it: all further reads, writes,
+/// and flushes through the handle fail with a `NotFound` error (and any
+/// writes it had buffered are discarded), even if a new stream is created
+/// at the same path.
+///
+/// If the stream's declared length is more than its sector chain holds (as
+/// can happen in a malformed file; see
+/// [`Entry::readable_len`](crate::Entry::readable_len)), reads stop at the
+/// end of the chain, so reading the stream to the end never buffers more
+/// than the file actually contains.
+///
+/// Where a stream's data lives is determined solely by its length, as the
+/// format requires: streams shorter than 4096 bytes are stored in the mini
+/// stream, and longer ones in regular sectors.  (Readers, including this
+/// crate, locate a stream's data from its length, so there is no way to keep
+/// a short stream in regular sectors.)  Writes are buffered, so a stream
+/// written sequentially is normally placed correctly from the start; if a
+/// short stream that has already been flushed grows past the cutoff, its
+/// existing data is copied into regular sectors exactly once.
 pub struct Stream<F> {
     minialloc: Weak<RwLock<MiniAllocator<F>>>,
     stream_id: u32,
+    /// The generation of the directory entry when this handle was opened;
+    /// if it has changed since, the stream has been removed.
+    generation: u64,
     total_len: u64,
+    version: Version,
     buffer: Box<[u8; BUFFER_SIZE]>,
     buf_pos: usize,
     buf_cap: usize,
     buf_offset_from_start: u64,
     flusher: Option<Box<dyn Flusher<F>>>,
+    /// How many bytes of buffered writes this handle has counted towards the
+    /// `CompoundFile`'s dirty budget.
+    dirty_len: usize,
+    /// True for a read-only handle opened with
+    /// `CompoundFile::open_stream_full_chain`, whose length is that of the
+    /// stream's chain, fixed when it was opened.
+    full_chain: bool,
 }
 
 impl<F> Stream<F> {
@@ -25,45 +78,155 @@ impl<F> Stream<F> {
         minialloc: &Arc<RwLock<MiniAllocator<F>>>,
         stream_id: u32,
     ) -> Stream<F> {
-        let total_len =
-            minialloc.read().unwrap().dir_entry(stream_id).stream_len;
+        let (total_len, version, generation) = {
+            let minialloc = minialloc.read().unwrap();
+            let stream_len = minialloc.reported_len(stream_id);
+            let generation = minialloc.dir_entry_generation(stream_id);
+            (stream_len, minialloc.version(), generation)
+        };
         Stream {
             minialloc: Arc::downgrade(minialloc),
             stream_id,
+            generation: generation.unwrap(),
             total_len,
+            version,
             buffer: Box::new([0; BUFFER_SIZE]),
             buf_pos: 0,
             buf_cap: 0,
             buf_offset_from_start: 0,
             flusher: None,
+            dirty_len: 0,
+            full_chain: false,
         }
     }
 
+    /// Returns a read-only handle to the stream whose length is everything
+    /// its sector chain holds, rather than its declared length.
+    pub(crate) fn new_full_chain(
+        minialloc: &Arc<RwLock<MiniAllocator<F>>>,
+        stream_id: u32,
+    ) -> io::Result<Stream<F>> {
+        let chain_len = minialloc.read().unwrap().chain_len(stream_id)?;
+        let mut stream = Stream::new(minialloc, stream_id);
+        stream.total_len = chain_len;
+        stream.full_chain = true;
+        Ok(stream)
+    }
+
+    pub(crate) fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
     fn minialloc(&self) -> io::Result<Arc<RwLock<MiniAllocator<F>>>> {
         self.minialloc
             .upgrade()
             .ok_or_else(|| io::Error::other("CompoundFile was dropped"))
     }
 
+    /// Returns an error if the stream has been removed since this handle was
+    /// opened.
+    fn check_not_removed(
+        &self,
+        minialloc: &MiniAllocator<F>,
+    ) -> io::Result<()> {
+        if minialloc.dir_entry_generation(self.stream_id)
+            != Some(self.generation)
+        {
+            not_found!("Stream was removed from the compound file");
+        }
+        Ok(())
+    }
+
     /// Returns the current length of the stream, in bytes.
     pub fn len(&self) -> u64 {
+        if self.flusher.is_none() && !self.full_chain {
+            if let Ok(minialloc) = self.minialloc() {
+                let minialloc = minialloc.read().unwrap();
+                if self.check_not_removed(&minialloc).is_ok() {
+                    return minialloc.reported_len(self.stream_id);
+                }
+            }
+        }
         self.total_len
     }
 
     /// Returns true if the stream is empty.
     pub fn is_empty(&self) -> bool {
-        self.total_len == 0
+        self.len() == 0
+    }
+
+    /// Returns how far into the stream reads may go: the end of the chain
+    /// for a full-chain handle, and otherwise the declared length, capped at
+    /// what the chain holds.
+    fn readable_len(&self, minialloc: &MiniAllocator<F>) -> u64 {
+        if self.full_chain {
+            self.total_len
+        } else {
+            minialloc.readable_len(self.stream_id)
+        }
+    }
+
+    /// Flushes any buffered writes and empties the buffer, leaving the
+    /// position where it was.
+    fn reset_buffer(&mut self) -> io::Result<()> {
+        self.flush_changes()?;
+        self.buf_offset_from_start += self.buf_pos as u64;
+        self.buf_pos = 0;
+        self.buf_cap = 0;
+        Ok(())
     }
 
     fn current_position(&self) -> u64 {
         self.buf_offset_from_start + (self.buf_pos as u64)
     }
 
+    /// Unless this handle has unflushed writes, reloads the stream length from
+    /// the directory entry, in case the stream was resized through another
+    /// handle since we last looked.
+    fn refresh_len(&mut self) {
+        if self.flusher.is_some() || self.full_chain {
+            return;
+        }
+        let stream_len = self.len();
+        if stream_len == self.total_len {
+            return;
+        }
+        let position = self.current_position().min(stream_len);
+        self.total_len = stream_len;
+        self.buf_offset_from_start = position;
+        self.buf_pos = 0;
+        self.buf_cap = 0;
+    }
+
     fn flush_changes(&mut self) -> io::Result<()> {
-        if let Some(flusher) = self.flusher.take() {
-            flusher.flush_changes(self)?;
+        let result = match self.flusher.take() {
+            Some(flusher) => flusher.flush_changes(self),
+            None => Ok(()),
+        };
+        // Either way, the buffered writes are gone now.
+        self.update_dirty_len();
+        result
+    }
+
+    /// Reports the number of bytes of buffered writes this handle holds to
+    /// the `CompoundFile`, and returns true if the total for all handles is
+    /// now over the dirty budget.
+    fn update_dirty_len(&mut self) -> bool {
+        let dirty_len = if self.flusher.is_some() { self.buf_cap } else { 0 };
+        if dirty_len == self.dirty_len {
+            return false;
         }
-        Ok(())
+        let Ok(minialloc) = self.minialloc() else {
+            return false;
+        };
+        let over_budget = minialloc.write().unwrap().update_dirty_bytes(
+            self.stream_id,
+            self.generation,
+            self.dirty_len,
+            dirty_len,
+        );
+        self.dirty_len = dirty_len;
+        over_budget
     }
 }
 
@@ -79,15 +242,18 @@ impl<F: Read + Write + Seek> Stream<F> {
     /// unless the stream is truncated to before the current position, in which
     /// case the position becomes the new end of the stream.
     pub fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.check_writable()?;
+        self.check_max_len(size)?;
+        self.refresh_len();
         if size != self.total_len {
             let new_position = self.current_position().min(size);
             self.flush_changes()?;
             let minialloc = self.minialloc()?;
-            resize_stream(
-                &mut minialloc.write().unwrap(),
-                self.stream_id,
-                size,
-            )?;
+            let mut minialloc = minialloc.write().unwrap();
+            self.check_not_removed(&minialloc)?;
+            let result = resize_stream(&mut minialloc, self.stream_id, size);
+            minialloc.self_check("Stream::set_len");
+            result?;
             self.total_len = size;
             self.buf_offset_from_start = new_position;
             self.buf_pos = 0;
@@ -96,6 +262,57 @@ impl<F: Read + Write + Seek> Stream<F> {
         Ok(())
     }
 
+    /// Returns an error if the stream can't grow to `new_len` bytes.  In a
+    /// version 3 file, that's a `RequiresVersion4` error.
+    fn check_max_len(&self, new_len: u64) -> io::Result<()> {
+        let max_len = self.version.max_stream_len();
+        if new_len > max_len {
+            if !self.version.supports_large_streams() {
+                return Err(RequiresVersion4::new(
+                    "streams longer than 2 GiB",
+                )
+                .into());
+            }
+            invalid_input!(
+                "Cannot grow stream to {} bytes, because the maximum stream \
+                 length is {} bytes",
+                new_len,
+                max_len
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns an error if this handle was opened read-only.
+    fn check_writable(&self) -> io::Result<()> {
+        if self.full_chain {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Stream was opened with open_stream_full_chain, and can't be \
+                 modified through this handle",
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_through(&mut self, buf: &[u8]) -> io::Result<()> {
+        debug_assert_eq!(self.buf_pos, 0);
+        let minialloc = self.minialloc()?;
+        let mut minialloc = minialloc.write().unwrap();
+        self.check_not_removed(&minialloc)?;
+        let result = write_at_offset(
+            &mut minialloc,
+            self.stream_id,
+            self.buf_offset_from_start,
+            buf,
+        );
+        minialloc.self_check("Stream::write");
+        result?;
+        self.total_len = minialloc.dir_entry(self.stream_id).stream_len;
+        self.buf_offset_from_start += buf.len() as u64;
+        Ok(())
+    }
+
     fn mark_modified(&mut self) {
         if self.flusher.is_none() {
             let flusher: Box<dyn Flusher<F>> = Box::new(FlushBuffer);
@@ -106,6 +323,7 @@ impl<F: Read + Write + Seek> Stream<F> {
 
 impl<F: Read + Seek> BufRead for Stream<F> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.refresh_len();
         if self.buf_pos >= self.buf_cap
             && self.current_position() < self.total_len
         {
@@ -113,10 +331,14 @@ impl<F: Read + Seek> BufRead for Stream<F> {
             self.buf_offset_from_start += self.buf_pos as u64;
             self.buf_pos = 0;
             let minialloc = self.minialloc()?;
+            let mut minialloc = minialloc.write().unwrap();
+            self.check_not_removed(&minialloc)?;
+            let readable_len = self.readable_len(&minialloc);
             self.buf_cap = read_data_from_stream(
-                &mut minialloc.write().unwrap(),
+                &mut minialloc,
                 self.stream_id,
                 self.buf_offset_from_start,
+                readable_len,
                 &mut self.buffer[..],
             )?;
         }
@@ -135,10 +357,51 @@ impl<F: Read + Seek> Read for Stream<F> {
         self.consume(num_bytes);
         Ok(num_bytes)
     }
+
+    /// Fills the slices in order.  If anything is buffered, only buffered
+    /// data is returned; otherwise, if the slices together hold at least a
+    /// buffer's worth, they are filled with a single read from the file,
+    /// bypassing the buffer.
+    fn read_vectored(
+        &mut self,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> io::Result<usize> {
+        let total = total_len(bufs.iter().map(|buf| buf.len()));
+        self.refresh_len();
+        if self.buf_pos < self.buf_cap
+            || total < BUFFER_SIZE
+            || self.current_position() >= self.total_len
+        {
+            let mut buffered_data = self.fill_buf()?;
+            let num_bytes = buffered_data.read_vectored(bufs)?;
+            self.consume(num_bytes);
+            return Ok(num_bytes);
+        }
+        self.reset_buffer()?;
+        let minialloc = self.minialloc()?;
+        let mut minialloc = minialloc.write().unwrap();
+        self.check_not_removed(&minialloc)?;
+        let readable_len = self.readable_len(&minialloc);
+        let remaining =
+            readable_len.saturating_sub(self.buf_offset_from_start);
+        let len = (total as u64).min(remaining) as usize;
+        let mut data = try_zeroed_vec(len, "stream data")?;
+        let num_bytes = read_data_from_stream(
+            &mut minialloc,
+            self.stream_id,
+            self.buf_offset_from_start,
+            readable_len,
+            &mut data,
+        )?;
+        let num_bytes = (&data[..num_bytes]).read_vectored(bufs)?;
+        self.buf_offset_from_start += num_bytes as u64;
+        Ok(num_bytes)
+    }
 }
 
 impl<F: Read + Seek> Seek for Stream<F> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.refresh_len();
         let new_pos: u64 =
             match pos {
                 SeekFrom::Start(delta) => {
@@ -214,15 +477,71 @@ impl<F: Read + Seek> Seek for Stream<F> {
 
 impl<F: Read + Write + Seek> Write for Stream<F> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        self.check_max_len(
+            self.current_position().saturating_add(buf.len() as u64),
+        )?;
+        self.refresh_len();
         debug_assert!(self.buf_pos <= self.buffer.len());
+        if self.flusher.is_none()
+            && self.buf_cap == 0
+            && buf.len() >= BUFFER_SIZE
+        {
+            // Nothing is buffered, and this write would fill the whole
+            // buffer anyway, so skip the copy and write straight through.
+            self.write_through(buf)?;
+            return Ok(buf.len());
+        }
         if self.buf_pos >= self.buffer.len() {
-            self.flush_changes()?;
-            self.buf_offset_from_start += self.buf_pos as u64;
-            self.buf_pos = 0;
-            self.buf_cap = 0;
+            self.reset_buffer()?;
+        }
+        self.write_buffered(&[IoSlice::new(buf)])
+    }
+
+    /// Writes all of the slices, in order, as a single write: into the
+    /// buffer if they fit, and otherwise straight through to the file in one
+    /// pass, so that the sectors they cover are allocated (and, if the
+    /// stream grows out of the mini stream, moved) just once.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.check_writable()?;
+        let total = total_len(bufs.iter().map(|buf| buf.len()));
+        self.check_max_len(
+            self.current_position().saturating_add(total as u64),
+        )?;
+        if total == 0 {
+            return Ok(0);
+        }
+        self.refresh_len();
+        if total > self.buffer.len() - self.buf_pos {
+            self.reset_buffer()?;
         }
+        if total >= BUFFER_SIZE {
+            let mut data = Vec::with_capacity(total);
+            for buf in bufs {
+                data.extend_from_slice(buf);
+            }
+            self.write_through(&data)?;
+            return Ok(total);
+        }
+        self.write_buffered(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.minialloc()?.write().unwrap().check_backing_len()?;
+        self.flush_changes()?;
+        let minialloc = self.minialloc()?;
+        minialloc.write().unwrap().flush()?;
+        Ok(())
+    }
+}
+
+impl<F: Read + Write + Seek> Stream<F> {
+    /// Copies as much of the slices as fits into the buffer, in order, and
+    /// returns how many bytes were copied.  If that puts the `CompoundFile`
+    /// over its dirty budget, the buffer is written through at once.
+    fn write_buffered(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
         let num_bytes_written =
-            (&mut self.buffer[self.buf_pos..]).write(buf)?;
+            (&mut self.buffer[self.buf_pos..]).write_vectored(bufs)?;
         self.mark_modified();
         self.buf_pos += num_bytes_written;
         debug_assert!(self.buf_pos <= self.buffer.len());
@@ -230,15 +549,24 @@ impl<F: Read + Write + Seek> Write for Stream<F> {
         self.total_len = self
             .total_len
             .max(self.buf_offset_from_start + self.buf_cap as u64);
+        if self.update_dirty_len() {
+            // Write this handle's buffer through now, rather than letting
+            // buffered data pile up past the budget.
+            let minialloc = self.minialloc()?;
+            let timer = minialloc.read().unwrap().metrics().start();
+            let num_bytes = self.buf_cap as u64;
+            self.flush_changes()?;
+            self.buf_offset_from_start += self.buf_pos as u64;
+            self.buf_pos = 0;
+            self.buf_cap = 0;
+            minialloc.read().unwrap().metrics().record(
+                Op::EarlyFlush,
+                timer,
+                num_bytes,
+            );
+        }
         Ok(num_bytes_written)
     }
-
-    fn flush(&mut self) -> io::Result<()> {
-        self.flush_changes()?;
-        let minialloc = self.minialloc()?;
-        minialloc.write().unwrap().flush()?;
-        Ok(())
-    }
 }
 
 impl<F> Drop for Stream<F> {
@@ -249,6 +577,118 @@ impl<F> Drop for Stream<F> {
 
 //===========================================================================//
 
+/// A read-only handle to a stream in a compound file, as returned by
+/// [`CompoundFile::open_stream_reader`](crate::CompoundFile::open_stream_reader).
+///
+/// Unlike a [`Stream`], a `StreamReader` borrows the `CompoundFile` it was
+/// opened from, so that any number of readers can be opened from a shared
+/// reference (for example, while iterating over the file's entries), each
+/// with its own position.  Since the file can't be modified while the
+/// borrow lasts, every reader sees the stream exactly as it was when opened.
+pub struct StreamReader<'a, F> {
+    stream: Stream<F>,
+    _comp: PhantomData<&'a CompoundFile<F>>,
+}
+
+impl<'a, F> StreamReader<'a, F> {
+    pub(crate) fn new(stream: Stream<F>) -> StreamReader<'a, F> {
+        StreamReader { stream, _comp: PhantomData }
+    }
+
+    /// Returns the length of the stream, in bytes.
+    pub fn len(&self) -> u64 {
+        self.stream.len()
+    }
+
+    /// Returns true if the stream is empty.
+    pub fn is_empty(&self) -> bool {
+        self.stream.is_empty()
+    }
+}
+
+impl<'a, F: Read + Seek> BufRead for StreamReader<'a, F> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.stream.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.stream.consume(amt)
+    }
+}
+
+impl<'a, F: Read + Seek> Read for StreamReader<'a, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+
+    fn read_vectored(
+        &mut self,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> io::Result<usize> {
+        self.stream.read_vectored(bufs)
+    }
+}
+
+impl<'a, F: Read + Seek> Seek for StreamReader<'a, F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.stream.seek(pos)
+    }
+}
+
+//===========================================================================//
+
+/// The error returned (wrapped in an `io::Error` of kind `InvalidInput`)
+/// when a compound file is flushed, or saved, while [`Stream`] handles still
+/// hold written data that they haven't passed on to it yet.  Since the
+/// buffers belong to the handles, the `CompoundFile` can't write them
+/// itself, and writing everything else without them would leave a file
+/// missing the streams' latest bytes; flush or drop the handles first.
+///
+/// Use [`from_io_error`](#method.from_io_error) to recognize it.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct StreamsStillOpen {
+    paths: Vec<PathBuf>,
+}
+
+impl StreamsStillOpen {
+    pub(crate) fn new(paths: Vec<PathBuf>) -> StreamsStillOpen {
+        debug_assert!(!paths.is_empty());
+        StreamsStillOpen { paths }
+    }
+
+    /// Returns the paths of the streams with unflushed writes, in stream ID
+    /// order.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Returns the `StreamsStillOpen` carried by the given error, if any.
+    pub fn from_io_error(error: &io::Error) -> Option<&StreamsStillOpen> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for StreamsStillOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Open stream handles have unflushed writes to {:?}; flush or \
+             drop them first",
+            self.paths
+        )
+    }
+}
+
+impl Error for StreamsStillOpen {}
+
+impl From<StreamsStillOpen> for io::Error {
+    fn from(error: StreamsStillOpen) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, error)
+    }
+}
+
+//===========================================================================//
+
 trait Flusher<F> {
     fn flush_changes(&self, stream: &mut Stream<F>) -> io::Result<()>;
 }
@@ -258,26 +698,59 @@ struct FlushBuffer;
 impl<F: Read + Write + Seek> Flusher<F> for FlushBuffer {
     fn flush_changes(&self, stream: &mut Stream<F>) -> io::Result<()> {
         let minialloc = stream.minialloc()?;
-        write_data_to_stream(
-            &mut minialloc.write().unwrap(),
+        let mut minialloc = minialloc.write().unwrap();
+        stream.check_not_removed(&minialloc)?;
+        let result = write_at_offset(
+            &mut minialloc,
             stream.stream_id,
             stream.buf_offset_from_start,
             &stream.buffer[..stream.buf_cap],
-        )?;
-        debug_assert_eq!(
-            minialloc.read().unwrap().dir_entry(stream.stream_id).stream_len,
-            stream.total_len
         );
+        minialloc.self_check("Stream::write");
+        result?;
+        stream.total_len = minialloc.dir_entry(stream.stream_id).stream_len;
         Ok(())
     }
 }
 
 //===========================================================================//
 
+/// Returns the total length of a set of slices.
+fn total_len<I: Iterator<Item = usize>>(lens: I) -> usize {
+    lens.fold(0, usize::saturating_add)
+}
+
+/// Writes `buf` to the given stream at the given offset.  If the stream was
+/// truncated through another handle since the offset was chosen, it is first
+/// zero-padded back out to the offset.
+fn write_at_offset<F: Read + Write + Seek>(
+    minialloc: &mut MiniAllocator<F>,
+    stream_id: u32,
+    offset: u64,
+    buf: &[u8],
+) -> io::Result<()> {
+    let mut stream_len = minialloc.dir_entry(stream_id).stream_len;
+    let zeros = [0u8; BUFFER_SIZE];
+    while stream_len < offset {
+        let num_bytes = (offset - stream_len).min(BUFFER_SIZE as u64) as usize;
+        write_data_to_stream(
+            minialloc,
+            stream_id,
+            stream_len,
+            &zeros[..num_bytes],
+        )?;
+        stream_len += num_bytes as u64;
+    }
+    write_data_to_stream(minialloc, stream_id, offset, buf)
+}
+
+/// Reads from the given offset of a stream, stopping at `readable_len`
+/// bytes from its start.
 fn read_data_from_stream<F: Read + Seek>(
     minialloc: &mut MiniAllocator<F>,
     stream_id: u32,
     buf_offset_from_start: u64,
+    readable_len: u64,
     buf: &mut [u8],
 ) -> io::Result<usize> {
     let (start_sector, stream_len) = {
@@ -285,10 +758,12 @@ fn read_data_from_stream<F: Read + Seek>(
         debug_assert_eq!(dir_entry.obj_type, ObjType::Stream);
         (dir_entry.start_sector, dir_entry.stream_len)
     };
-    let num_bytes = if buf_offset_from_start >= stream_len {
+    // Where the data lives depends on the declared length, even when
+    // reading up to some other length.
+    let num_bytes = if buf_offset_from_start >= readable_len {
         0
     } else {
-        let remaining = stream_len - buf_offset_from_start;
+        let remaining = readable_len - buf_offset_from_start;
         if remaining < buf.len() as u64 {
             remaining as usize
         } else {
@@ -296,26 +771,90 @@ fn read_data_from_stream<F: Read + Seek>(
         }
     };
     if num_bytes > 0 {
-        if stream_len < consts::MINI_STREAM_CUTOFF as u64 {
-            let mut chain = minialloc.open_mini_chain(start_sector)?;
-            chain.seek(SeekFrom::Start(buf_offset_from_start))?;
-            chain.read_exact(&mut buf[..num_bytes])?;
+        let result = if stream_len < consts::MINI_STREAM_CUTOFF as u64 {
+            minialloc.open_mini_chain(start_sector).and_then(|mut chain| {
+                chain.seek(SeekFrom::Start(buf_offset_from_start))?;
+                chain.read_exact(&mut buf[..num_bytes])
+            })
         } else {
-            let mut chain =
-                minialloc.open_chain(start_sector, SectorInit::Zero)?;
-            chain.seek(SeekFrom::Start(buf_offset_from_start))?;
-            chain.read_exact(&mut buf[..num_bytes])?;
-        }
+            minialloc.open_chain(start_sector, SectorInit::Zero).and_then(
+                |mut chain| {
+                    chain.seek(SeekFrom::Start(buf_offset_from_start))?;
+                    chain.read_exact(&mut buf[..num_bytes])?;
+                    chain.release();
+                    Ok(())
+                },
+            )
+        };
+        // If the underlying file was truncated behind our back, say so,
+        // rather than reporting a bare end-of-file error.
+        result.map_err(|error| minialloc.diagnose_read_error(error))?;
     }
     Ok(num_bytes)
 }
 
+/// Overwrites with zeros whatever is left of a stream's old contents in the
+/// final (mini) sector of its chain after truncating it from `old_len` bytes
+/// to `new_len` bytes.
+fn zero_truncated_tail<C: Write + Seek>(
+    chain: &mut C,
+    new_len: u64,
+    old_len: u64,
+) -> io::Result<()> {
+    let chain_len = chain.seek(SeekFrom::End(0))?;
+    let tail_end = old_len.min(chain_len);
+    if tail_end > new_len {
+        chain.seek(SeekFrom::Start(new_len))?;
+        chain.write_all(&vec![0u8; (tail_end - new_len) as usize])?;
+    }
+    Ok(())
+}
+
+/// Reads the first `len` bytes of a stream that lives in the mini stream, as
+/// when moving it out into regular sectors.  Rather than going one mini
+/// sector at a time, this reads the whole mini chain at once, merging
+/// physically adjacent mini sectors into larger reads.
+fn read_mini_stream<F: Read + Seek>(
+    minialloc: &mut MiniAllocator<F>,
+    stream_id: u32,
+    len: u64,
+) -> io::Result<Vec<u8>> {
+    debug_assert!(len < consts::MINI_STREAM_CUTOFF as u64);
+    let mut data = minialloc.read_streams(&[stream_id])?.remove(0);
+    if (data.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    data.truncate(len as usize);
+    Ok(data)
+}
+
+/// If the stream's chain is shared with other streams (see
+/// `CompoundFile::create_stream_dedup`), gives the stream its own copy of the
+/// chain, so that subsequent changes to it don't affect the other streams.
+fn unshare_stream<F: Read + Write + Seek>(
+    minialloc: &mut MiniAllocator<F>,
+    stream_id: u32,
+) -> io::Result<()> {
+    if !minialloc.is_shared(stream_id) {
+        return Ok(());
+    }
+    let stream_len = minialloc.dir_entry(stream_id).stream_len;
+    let mut data = try_zeroed_vec(stream_len as usize, "stream data")?;
+    let readable_len = minialloc.readable_len(stream_id);
+    read_data_from_stream(minialloc, stream_id, 0, readable_len, &mut data)?;
+    minialloc.detach_chain(stream_id)?;
+    write_data_to_stream(minialloc, stream_id, 0, &data)
+}
+
 fn write_data_to_stream<F: Read + Write + Seek>(
     minialloc: &mut MiniAllocator<F>,
     stream_id: u32,
     buf_offset_from_start: u64,
     buf: &[u8],
 ) -> io::Result<()> {
+    minialloc.audit_stream_modified(stream_id);
+    minialloc.set_allocating_stream(stream_id);
+    unshare_stream(minialloc, stream_id)?;
     let (old_start_sector, old_stream_len) = {
         let dir_entry = minialloc.dir_entry(stream_id);
         debug_assert_eq!(dir_entry.obj_type, ObjType::Stream);
@@ -341,7 +880,9 @@ fn write_data_to_stream<F: Read + Write + Seek>(
             let mut chain = minialloc
                 .open_chain(consts::END_OF_CHAIN, SectorInit::Zero)?;
             chain.write_all(buf)?;
-            chain.start_sector_id()
+            let start_sector_id = chain.start_sector_id();
+            chain.release();
+            start_sector_id
         }
     } else if old_stream_len < consts::MINI_STREAM_CUTOFF as u64 {
         // Case 2: The stream currently exists in a mini chain.
@@ -361,15 +902,16 @@ fn write_data_to_stream<F: Read + Write + Seek>(
             debug_assert!(
                 buf_offset_from_start < consts::MINI_STREAM_CUTOFF as u64
             );
-            let mut tmp = vec![0u8; buf_offset_from_start as usize];
-            let mut chain = minialloc.open_mini_chain(old_start_sector)?;
-            chain.read_exact(&mut tmp)?;
-            chain.free()?;
+            let tmp =
+                read_mini_stream(minialloc, stream_id, buf_offset_from_start)?;
+            minialloc.free_mini_chain(old_start_sector)?;
             let mut chain = minialloc
                 .open_chain(consts::END_OF_CHAIN, SectorInit::Zero)?;
             chain.write_all(&tmp)?;
             chain.write_all(buf)?;
-            chain.start_sector_id()
+            let start_sector_id = chain.start_sector_id();
+            chain.release();
+            start_sector_id
         }
     } else {
         // Case 3: The stream currently exists in a regular chain.  After the
@@ -381,6 +923,7 @@ fn write_data_to_stream<F: Read + Write + Seek>(
         chain.seek(SeekFrom::Start(buf_offset_from_start))?;
         chain.write_all(buf)?;
         debug_assert_eq!(chain.start_sector_id(), old_start_sector);
+        chain.release();
         old_start_sector
     };
     // Update the directory entry for this stream.
@@ -398,6 +941,12 @@ fn resize_stream<F: Read + Write + Seek>(
     stream_id: u32,
     new_stream_len: u64,
 ) -> io::Result<()> {
+    minialloc.audit_stream_modified(stream_id);
+    if new_stream_len == 0 && minialloc.detach_chain(stream_id)? {
+        return Ok(());
+    }
+    minialloc.set_allocating_stream(stream_id);
+    unshare_stream(minialloc, stream_id)?;
     let (old_start_sector, old_stream_len) = {
         let dir_entry = minialloc.dir_entry(stream_id);
         debug_assert_eq!(dir_entry.obj_type, ObjType::Stream);
@@ -409,9 +958,11 @@ fn resize_stream<F: Read + Write + Seek>(
         debug_assert_eq!(old_stream_len, 0);
         if new_stream_len < consts::MINI_STREAM_CUTOFF as u64 {
             // Case 1a: The new length is small enough that it should be placed
-            // into a new mini chain.
+            // into a new mini chain.  Freed mini sectors are reused without
+            // being cleared, so the new chain must be explicitly zeroed.
             let mut chain = minialloc.open_mini_chain(consts::END_OF_CHAIN)?;
             chain.set_len(new_stream_len)?;
+            chain.write_all(&vec![0u8; new_stream_len as usize])?;
             chain.start_sector_id()
         } else {
             // Case 1b: The new length is large enough that it should be placed
@@ -430,19 +981,33 @@ fn resize_stream<F: Read + Write + Seek>(
         } else if new_stream_len < consts::MINI_STREAM_CUTOFF as u64 {
             // Case 2b: The new length is still small enough to fit in a mini
             // chain.  Therefore, we just need to adjust the length of the
-            // existing chain.
+            // existing chain (zeroing any newly exposed bytes, which may
+            // hold stale data from earlier contents or freed mini sectors).
+            let zero_tail = minialloc.zero_freed_sectors();
             let mut chain = minialloc.open_mini_chain(old_start_sector)?;
             chain.set_len(new_stream_len)?;
+            if new_stream_len > old_stream_len {
+                chain.seek(SeekFrom::Start(old_stream_len))?;
+                chain.write_all(&vec![
+                    0u8;
+                    (new_stream_len - old_stream_len)
+                        as usize
+                ])?;
+            } else if zero_tail {
+                zero_truncated_tail(
+                    &mut chain,
+                    new_stream_len,
+                    old_stream_len,
+                )?;
+            }
             debug_assert_eq!(chain.start_sector_id(), old_start_sector);
             old_start_sector
         } else {
             // Case 2c: The new length is too large to fit in a mini chain.
             // Therefore, we should migrate the stream into a new regular
             // chain.
-            let mut tmp = vec![0u8; old_stream_len as usize];
-            let mut chain = minialloc.open_mini_chain(old_start_sector)?;
-            chain.read_exact(&mut tmp)?;
-            chain.free()?;
+            let tmp = read_mini_stream(minialloc, stream_id, old_stream_len)?;
+            minialloc.free_mini_chain(old_start_sector)?;
             let mut chain = minialloc
                 .open_chain(consts::END_OF_CHAIN, SectorInit::Zero)?;
             chain.write_all(&tmp)?;
@@ -470,10 +1035,29 @@ fn resize_stream<F: Read + Write + Seek>(
         } else {
             // Case 3c: The new length is still too large to fit in a mini
             // chain.  Therefore, we just need to adjust the length of the
-            // existing chain.
+            // existing chain.  Newly allocated sectors are zeroed, but the
+            // rest of the old final sector may hold stale data from before
+            // an earlier truncation, so zero that part explicitly.
+            let zero_tail = minialloc.zero_freed_sectors();
             let mut chain =
                 minialloc.open_chain(old_start_sector, SectorInit::Zero)?;
+            let old_chain_len = chain.len();
             chain.set_len(new_stream_len)?;
+            if zero_tail && new_stream_len < old_stream_len {
+                zero_truncated_tail(
+                    &mut chain,
+                    new_stream_len,
+                    old_stream_len,
+                )?;
+            }
+            let tail_end = new_stream_len.min(old_chain_len);
+            if tail_end > old_stream_len {
+                chain.seek(SeekFrom::Start(old_stream_len))?;
+                chain.write_all(&vec![
+                    0u8;
+                    (tail_end - old_stream_len) as usize
+                ])?;
+            }
             debug_assert_eq!(chain.start_sector_id(), old_start_sector);
             old_start_sector
         }
@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+//===========================================================================//
+
+/// Options for
+/// [`CompoundFile::sync_from_dir`](../struct.CompoundFile.html#method.sync_from_dir),
+/// which mirrors a directory on disk into a storage within a compound file.
+///
+/// ```
+/// use cfb::SyncOptions;
+///
+/// let options = SyncOptions::new().compare_contents(true).remove_missing(true);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SyncOptions {
+    pub(crate) compare_contents: bool,
+    pub(crate) remove_missing: bool,
+}
+
+impl SyncOptions {
+    /// Returns the default options: files are compared by size and
+    /// modification time, and objects whose source files have disappeared
+    /// are left alone.
+    pub fn new() -> SyncOptions {
+        SyncOptions::default()
+    }
+
+    /// If true, a file whose size matches its stream is compared against
+    /// the stream's contents, rather than by modification time.  This is
+    /// slower, since every such stream has to be read, but catches changes
+    /// that don't advance a file's modification time (such as restoring a
+    /// file from a backup).  Defaults to false.
+    pub fn compare_contents(mut self, compare: bool) -> SyncOptions {
+        self.compare_contents = compare;
+        self
+    }
+
+    /// If true, streams and storages within the synced storage that have no
+    /// corresponding file or directory on disk are removed.  Defaults to
+    /// false.
+    pub fn remove_missing(mut self, remove: bool) -> SyncOptions {
+        self.remove_missing = remove;
+        self
+    }
+}
+
+//===========================================================================//
+
+/// A report of what was changed by
+/// [`CompoundFile::sync_from_dir`](../struct.CompoundFile.html#method.sync_from_dir).
+/// All paths are paths within the compound file, in the order in which the
+/// changes were made.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SyncReport {
+    pub(crate) created: Vec<PathBuf>,
+    pub(crate) updated: Vec<PathBuf>,
+    pub(crate) removed: Vec<PathBuf>,
+    pub(crate) num_unchanged: u64,
+}
+
+impl SyncReport {
+    /// Returns the paths of the streams and storages that were created.
+    pub fn created(&self) -> &[PathBuf] {
+        &self.created
+    }
+
+    /// Returns the paths of the existing streams whose contents were
+    /// replaced.
+    pub fn updated(&self) -> &[PathBuf] {
+        &self.updated
+    }
+
+    /// Returns the paths of the streams and storages that were removed,
+    /// either because their source disappeared (with
+    /// [`SyncOptions::remove_missing`](struct.SyncOptions.html#method.remove_missing))
+    /// or because a file replaced a directory of the same name, or vice
+    /// versa.
+    pub fn removed(&self) -> &[PathBuf] {
+        &self.removed
+    }
+
+    /// Returns the number of streams that were found to be up to date, and
+    /// so were left alone.
+    pub fn num_unchanged(&self) -> u64 {
+        self.num_unchanged
+    }
+
+    /// Returns true if nothing in the compound file was changed.
+    pub fn is_unchanged(&self) -> bool {
+        self.created.is_empty()
+            && self.updated.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+//===========================================================================//
@@ -14,6 +14,10 @@ impl Timestamp {
         self.0
     }
 
+    pub(crate) fn from_value(value: u64) -> Timestamp {
+        Timestamp(value)
+    }
+
     /// Returns a timestamp representing the CFB file epoch of January 1, 1601
     /// UTC.  This is an appropriate value to use for an uninitialized
     /// timestamp.
@@ -31,6 +35,23 @@ impl Timestamp {
         Timestamp(timestamp_from_system_time(system_time))
     }
 
+    /// Returns a timestamp representing the given system time, or `None` if
+    /// the time is before the CFB file epoch or too far after it to fit in a
+    /// timestamp.  Precision finer than 100 nanoseconds is truncated.
+    pub fn checked_from_system_time(
+        system_time: SystemTime,
+    ) -> Option<Timestamp> {
+        let duration = system_time.duration_since(UNIX_EPOCH);
+        let timestamp = match duration {
+            Ok(duration) => UNIX_EPOCH_TIMESTAMP
+                .checked_add(checked_duration_to_timestamp_delta(duration)?)?,
+            Err(err) => UNIX_EPOCH_TIMESTAMP.checked_sub(
+                checked_duration_to_timestamp_delta(err.duration())?,
+            )?,
+        };
+        Some(Timestamp(timestamp))
+    }
+
     /// Returns the local system time that this timestamp represents.
     pub fn to_system_time(self) -> SystemTime {
         system_time_from_timestamp(self.0)
@@ -97,6 +118,13 @@ fn duration_to_timestamp_delta(duration: Duration) -> u64 {
         .saturating_add((duration.subsec_nanos() / 100) as u64)
 }
 
+fn checked_duration_to_timestamp_delta(duration: Duration) -> Option<u64> {
+    duration
+        .as_secs()
+        .checked_mul(10_000_000)?
+        .checked_add((duration.subsec_nanos() / 100) as u64)
+}
+
 fn timestamp_delta_to_duration(delta: u64) -> Duration {
     Duration::new(delta / 10_000_000, (delta % 10_000_000) as u32 * 100)
 }
@@ -107,7 +135,7 @@ fn timestamp_delta_to_duration(delta: u64) -> Duration {
 mod tests {
     use super::{
         duration_to_timestamp_delta, system_time_from_timestamp,
-        timestamp_delta_to_duration, timestamp_from_system_time,
+        timestamp_delta_to_duration, timestamp_from_system_time, Timestamp,
         UNIX_EPOCH_TIMESTAMP,
     };
     use std::time::{Duration, UNIX_EPOCH};
@@ -171,6 +199,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn checked_conversion_rejects_out_of_range_times() {
+        let checked = Timestamp::checked_from_system_time;
+        let epoch = UNIX_EPOCH - Duration::from_secs(11_644_473_600);
+        assert_eq!(checked(epoch), Some(Timestamp::zero()));
+        assert_eq!(checked(epoch - Duration::from_nanos(100)), None);
+        let time = UNIX_EPOCH + Duration::new(1489862796, 123_456_789);
+        assert_eq!(checked(time).unwrap().value(), 131343363961234567);
+        if let Some(max_time) = UNIX_EPOCH.checked_add(
+            timestamp_delta_to_duration(u64::MAX - UNIX_EPOCH_TIMESTAMP),
+        ) {
+            assert_eq!(checked(max_time).unwrap().value(), u64::MAX);
+            let past_max = max_time + Duration::from_nanos(100);
+            assert_eq!(checked(past_max), None);
+        }
+    }
+
     #[test]
     fn extreme_timestamps() {
         // If the system we're on can't represent these timestamps in a
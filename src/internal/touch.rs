@@ -0,0 +1,68 @@
+use std::time::SystemTime;
+
+//===========================================================================//
+
+/// Options for
+/// [`CompoundFile::touch_with`](../struct.CompoundFile.html#method.touch_with),
+/// which updates the timestamps of an object and, optionally, of the
+/// storages around it.
+///
+/// Only storages (and, for the modified time, the root storage) have
+/// timestamps: the CFB spec requires the times of streams, and the created
+/// time of the root, to be zero, so they are always left alone.
+///
+/// ```
+/// use cfb::TouchOptions;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+/// let options = TouchOptions::new().modified(time).ancestors(true);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TouchOptions {
+    pub(crate) modified: Option<SystemTime>,
+    pub(crate) created: Option<SystemTime>,
+    pub(crate) ancestors: bool,
+    pub(crate) recursive: bool,
+}
+
+impl TouchOptions {
+    /// Returns the default options, which are what
+    /// [`CompoundFile::touch`](../struct.CompoundFile.html#method.touch)
+    /// uses: only the given object is touched, its modified time is set to
+    /// the current time, and its created time is left alone.
+    pub fn new() -> TouchOptions {
+        TouchOptions::default()
+    }
+
+    /// Sets the modified time to give to touched objects.  Defaults to the
+    /// current time (as of the call to `touch_with`).
+    pub fn modified(mut self, time: SystemTime) -> TouchOptions {
+        self.modified = Some(time);
+        self
+    }
+
+    /// Also sets the created time of touched storages to the given time.
+    /// By default, created times are never changed, even if they are zero.
+    pub fn created(mut self, time: SystemTime) -> TouchOptions {
+        self.created = Some(time);
+        self
+    }
+
+    /// If true, every storage containing the object, up to and including
+    /// the root, is touched as well (as Windows does when something within
+    /// a folder changes).  Defaults to false.
+    pub fn ancestors(mut self, ancestors: bool) -> TouchOptions {
+        self.ancestors = ancestors;
+        self
+    }
+
+    /// If true and the object is a storage, everything within it is touched
+    /// as well.  Defaults to false.
+    pub fn recursive(mut self, recursive: bool) -> TouchOptions {
+        self.recursive = recursive;
+        self
+    }
+}
+
+//===========================================================================//
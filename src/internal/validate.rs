@@ -1,3 +1,7 @@
+use crate::internal::{SectorId, StreamId};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
 //===========================================================================//
 
 /// A parsing validation strategy.
@@ -20,3 +24,271 @@ impl Validation {
 }
 
 //===========================================================================//
+
+/// The kind of spec violation described by a
+/// [`ValidationIssue`](struct.ValidationIssue.html).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum ValidationIssueKind {
+    /// A reserved field in the header was not zero.
+    NonzeroReservedField,
+    /// A version 3 header had a nonzero directory sector count, which was
+    /// treated as zero.
+    DirSectorCountNotZero,
+    /// The DIFAT chain ended with `FREE_SECTOR` rather than `END_OF_CHAIN`.
+    DifatChainTerminator,
+    /// The DIFAT chain length didn't match the header.
+    DifatSectorCount,
+    /// The DIFAT was padded with zeros rather than `FREE_SECTOR`, and the
+    /// padding was ignored.
+    DifatZeroPadding,
+    /// The number of FAT sectors didn't match the header.
+    FatSectorCount,
+    /// The FAT had non-free entries past the end of the file, which were
+    /// ignored.
+    FatEntriesPastEof,
+    /// A FAT or DIFAT sector wasn't marked as such in the FAT, and was
+    /// treated as though it were.
+    SectorNotMarked,
+    /// The directory chain length didn't match the header.
+    DirSectorCount,
+    /// The MiniFAT chain length didn't match the header.
+    MiniFatSectorCount,
+    /// The MiniFAT had more entries than the mini stream has mini sectors,
+    /// and was truncated.
+    MiniFatTruncated,
+    /// A directory entry name wasn't null-terminated.
+    NameNotTerminated,
+    /// The root directory entry didn't have the name "Root Entry".
+    RootEntryName,
+    /// A stream had a non-nil CLSID, as files written by some old exporters
+    /// do.  The bytes are kept, and written back unchanged, but
+    /// [`Entry::clsid`](crate::Entry::clsid) reports nil for streams.  This
+    /// is reported even under strict validation.
+    StreamClsid,
+    /// A stream had nonzero timestamps, which were ignored.
+    StreamTimestamp,
+    /// The root entry had a nonzero creation time.
+    RootCreationTime,
+    /// A storage had a nonzero starting sector or stream length, which was
+    /// ignored.
+    StorageStreamFields,
+    /// The red-black tree of a storage's children had two adjacent red
+    /// nodes.
+    AdjacentRedNodes,
+    /// The sector chain of a stream (or of the mini stream) reached a special
+    /// value such as `FREE_SECTOR`, or a sector past the end of the file,
+    /// before `END_OF_CHAIN`.  Reading the affected streams will fail.
+    BrokenChain,
+    /// A storage's child ID was out of range or referred to an unallocated
+    /// directory entry, and the storage was treated as having no children.
+    DanglingChild,
+    /// An entry's left or right sibling ID was out of range (for example,
+    /// because the directory chain was cut short), and that branch of the
+    /// storage's tree was skipped.  The rest of the storage's children can
+    /// still be read.
+    DanglingSibling,
+    /// A directory entry couldn't be parsed at all (for example, because its
+    /// object type or name length was invalid), and was treated as
+    /// unallocated.  Its bytes can still be read with
+    /// [`CompoundFile::raw_dir_entry`](crate::CompoundFile::raw_dir_entry).
+    UnparseableDirEntry,
+    /// A temporary object left behind by an operation that was interrupted
+    /// (for example, by a crash) was found.  This isn't a spec violation;
+    /// the object is hidden, and is removed the next time the file is
+    /// flushed.
+    LeakedTemporary,
+    /// A stream's sector chain was at least one whole (mini) sector longer
+    /// than the stream's length needs, so the excess can hold data that no
+    /// reader will see.  This is reported even under strict validation, and
+    /// the excess can be read with
+    /// [`CompoundFile::read_stream_slack`](crate::CompoundFile::read_stream_slack).
+    ChainSlack,
+    /// An unallocated directory entry's starting sector field pointed at an
+    /// allocated chain that no live object uses, as happens when an entry is
+    /// deleted without freeing its chain.  This is reported even under strict
+    /// validation, and the chain can be read with
+    /// [`CompoundFile::read_stale_chain`](crate::CompoundFile::read_stale_chain).
+    StaleChain,
+    /// The header's mini sector shift wasn't the standard 6 (64-byte mini
+    /// sectors).  The file is read using the shift it declares, and this is
+    /// reported even under strict validation.
+    NonstandardMiniSectorShift,
+    /// A stream's length was more than its sector chain can hold (or it had
+    /// no chain at all).  Reads of the stream stop at the end of its chain,
+    /// as given by [`Entry::readable_len`](crate::Entry::readable_len), and
+    /// this is reported even under strict validation.
+    StreamLongerThanChain,
+    /// A sector is in more than one chain (other than a chain that several
+    /// streams share on purpose), so writing to one of the objects using it
+    /// would corrupt another.  Only reported by
+    /// [`CompoundFile::validate`](crate::CompoundFile::validate).
+    CrossLinkedSector,
+    /// Some (mini) sectors are allocated, but aren't in any chain, so they
+    /// waste space and may hold data that no reader will see.  This includes
+    /// the sectors of any stale chains.  Only reported by
+    /// [`CompoundFile::validate`](crate::CompoundFile::validate).
+    OrphanedSectors,
+    /// A directory entry is allocated, but isn't in the tree, so the object
+    /// it describes can't be reached.  Only reported by
+    /// [`CompoundFile::validate`](crate::CompoundFile::validate).
+    UnreachableEntry,
+    /// Several streams share a single chain, as
+    /// [`CompoundFile::create_stream_dedup`](crate::CompoundFile::create_stream_dedup)
+    /// makes them.  This isn't a problem for this crate, but other readers
+    /// may not expect it.  Only reported by
+    /// [`CompoundFile::validate`](crate::CompoundFile::validate).
+    SharedChain,
+    /// The header's major version didn't match its sector shift (version 3
+    /// files have 512-byte sectors, and version 4 files 4096-byte ones).  The
+    /// file is read as the version its sector shift belongs to, since that is
+    /// what determines its layout, and the next flush rewrites the header to
+    /// match.
+    VersionSectorShiftMismatch,
+    /// The file had more sectors than its FAT sectors can describe (as when
+    /// a writer didn't truncate the file after shrinking it).  The sectors
+    /// past the end of the FAT were ignored, and are overwritten as the file
+    /// grows.
+    SectorsPastFat,
+}
+
+impl ValidationIssueKind {
+    /// Returns how serious this kind of issue is.
+    pub fn severity(self) -> Severity {
+        match self {
+            ValidationIssueKind::LeakedTemporary
+            | ValidationIssueKind::ChainSlack
+            | ValidationIssueKind::StaleChain
+            | ValidationIssueKind::NonstandardMiniSectorShift
+            | ValidationIssueKind::StreamClsid
+            | ValidationIssueKind::SharedChain => Severity::Info,
+            ValidationIssueKind::BrokenChain
+            | ValidationIssueKind::DanglingChild
+            | ValidationIssueKind::DanglingSibling
+            | ValidationIssueKind::UnparseableDirEntry
+            | ValidationIssueKind::StreamLongerThanChain
+            | ValidationIssueKind::CrossLinkedSector => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+/// How serious a [`ValidationIssue`](struct.ValidationIssue.html) is.  The
+/// levels are ordered, so that (for example) a file can be classified by its
+/// most severe issue.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Not a violation of the CFB spec (or one that strict validation
+    /// tolerates), but something a tool may want to know about.
+    Info,
+    /// A violation of the CFB spec that makes
+    /// [`open_strict`](crate::CompoundFile::open_strict) fail, but that
+    /// doesn't stop any data from being read.
+    Warning,
+    /// Damage that stops some of the file's data from being read (or, for a
+    /// cross-linked sector, from being written safely).
+    Error,
+}
+
+/// A spec violation that was tolerated while opening a compound file with
+/// permissive validation, as returned by
+/// [`CompoundFile::open_warnings`](../struct.CompoundFile.html#method.open_warnings),
+/// or a problem found by
+/// [`CompoundFile::validate`](../struct.CompoundFile.html#method.validate).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationIssue {
+    kind: ValidationIssueKind,
+    path: Option<PathBuf>,
+    stream_id: Option<StreamId>,
+    target_id: Option<StreamId>,
+    sector_id: Option<SectorId>,
+    message: String,
+}
+
+impl ValidationIssue {
+    pub(crate) fn new(
+        kind: ValidationIssueKind,
+        message: String,
+    ) -> ValidationIssue {
+        ValidationIssue {
+            kind,
+            path: None,
+            stream_id: None,
+            target_id: None,
+            sector_id: None,
+            message,
+        }
+    }
+
+    pub(crate) fn with_stream_id(mut self, stream_id: u32) -> ValidationIssue {
+        self.stream_id = Some(StreamId::new(stream_id));
+        self
+    }
+
+    pub(crate) fn with_target_id(mut self, stream_id: u32) -> ValidationIssue {
+        self.target_id = Some(StreamId::new(stream_id));
+        self
+    }
+
+    pub(crate) fn with_sector_id(mut self, sector_id: u32) -> ValidationIssue {
+        self.sector_id = Some(SectorId::new(sector_id));
+        self
+    }
+
+    pub(crate) fn set_stream_id(&mut self, stream_id: u32) {
+        self.stream_id = Some(StreamId::new(stream_id));
+    }
+
+    pub(crate) fn set_path(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    /// Returns what kind of spec violation this is.
+    pub fn kind(&self) -> ValidationIssueKind {
+        self.kind
+    }
+
+    /// Returns how serious this issue is (the same as
+    /// `self.kind().severity()`).
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+
+    /// Returns the path of the object that the issue concerns, if it
+    /// concerns a particular object that is reachable in the tree.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the ID of the directory entry that the issue concerns, if
+    /// any.
+    pub fn stream_id(&self) -> Option<StreamId> {
+        self.stream_id
+    }
+
+    /// Returns the ID that the issue's directory entry refers to, if the
+    /// issue is a bad reference from one entry to another (such as a
+    /// dangling child or sibling).  The ID may be out of range.
+    pub fn target_id(&self) -> Option<StreamId> {
+        self.target_id
+    }
+
+    /// Returns the ID of the (mini) sector at which the issue was found, if
+    /// it concerns a particular sector.
+    pub fn sector_id(&self) -> Option<SectorId> {
+        self.sector_id
+    }
+
+    /// Returns a human-readable description of the problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+//===========================================================================//
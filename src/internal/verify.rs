@@ -0,0 +1,267 @@
+use std::fmt;
+use std::io;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+//===========================================================================//
+
+/// Options for
+/// [`CompoundFile::verify_deep`](../struct.CompoundFile.html#method.verify_deep).
+///
+/// ```
+/// use cfb::VerifyOptions;
+///
+/// let options = VerifyOptions::new()
+///     .hash_streams(true)
+///     .progress(|done, total| {
+///         eprintln!("{}/{} bytes", done, total);
+///         true
+///     });
+/// ```
+#[derive(Default)]
+pub struct VerifyOptions {
+    pub(crate) hash_streams: bool,
+    pub(crate) progress: Option<Box<dyn FnMut(u64, u64) -> bool>>,
+    pub(crate) checkpoint: Option<CheckpointFn>,
+}
+
+type CheckpointFn = Box<dyn FnMut(&ResumeToken) -> ControlFlow<()>>;
+
+impl VerifyOptions {
+    /// Returns the default options: streams are read but not hashed, and no
+    /// progress is reported.
+    pub fn new() -> VerifyOptions {
+        VerifyOptions::default()
+    }
+
+    /// If true, the contents of every stream are hashed as they are read,
+    /// and the hash is recorded in the report.  Defaults to false.
+    pub fn hash_streams(mut self, hash: bool) -> VerifyOptions {
+        self.hash_streams = hash;
+        self
+    }
+
+    /// Sets a function to call as data is read, with the number of bytes of
+    /// stream data read so far and the total declared length of all streams.
+    /// It's called after each chunk of data, so returning false from it
+    /// stops the verification promptly; the report then covers only the
+    /// streams finished before that, and
+    /// [`is_cancelled`](struct.VerifyReport.html#method.is_cancelled) is
+    /// true.
+    pub fn progress<P>(mut self, progress: P) -> VerifyOptions
+    where
+        P: FnMut(u64, u64) -> bool + 'static,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Sets a function to call with a [`ResumeToken`] saying how far the
+    /// verification has got: after each chunk of data (with the offset
+    /// reached within the stream being read), and after each stream is
+    /// finished (with an offset of zero).  A process that saves the latest
+    /// token can pass it to
+    /// [`CompoundFile::verify_deep_from`](../struct.CompoundFile.html#method.verify_deep_from)
+    /// after a restart.  Returning `ControlFlow::Break` stops the
+    /// verification, just as returning false from the
+    /// [`progress`](#method.progress) function does.
+    pub fn checkpoint<C>(mut self, checkpoint: C) -> VerifyOptions
+    where
+        C: FnMut(&ResumeToken) -> ControlFlow<()> + 'static,
+    {
+        self.checkpoint = Some(Box::new(checkpoint));
+        self
+    }
+}
+
+impl fmt::Debug for VerifyOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifyOptions")
+            .field("hash_streams", &self.hash_streams)
+            .field("progress", &self.progress.is_some())
+            .field("checkpoint", &self.checkpoint.is_some())
+            .finish()
+    }
+}
+
+//===========================================================================//
+
+/// How far an interrupted
+/// [`CompoundFile::verify_deep`](../struct.CompoundFile.html#method.verify_deep)
+/// got, so that it can be carried on with
+/// [`CompoundFile::verify_deep_from`](../struct.CompoundFile.html#method.verify_deep_from)
+/// instead of starting over: the last stream, in the order of
+/// [`CompoundFile::walk`](../struct.CompoundFile.html#method.walk), that was
+/// fully verified, and how far into the next one it got.  Resuming skips
+/// the finished streams and verifies the next one again from its start.
+///
+/// A token also records a fingerprint of the file's structure (its header,
+/// FAT, MiniFAT, and directory), and resuming fails if that has changed
+/// since the token was made.  This catches any change that adds, removes,
+/// renames, resizes, or moves a stream, but not one that overwrites a
+/// stream's data in place.
+///
+/// With the `serde` feature enabled, tokens can be serialized, so that they
+/// can be saved across restarts.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResumeToken {
+    pub(crate) fingerprint: u64,
+    pub(crate) last_done: Option<PathBuf>,
+    pub(crate) offset: u64,
+}
+
+impl ResumeToken {
+    /// Returns the path of the last stream that was fully verified, or
+    /// `None` if none were.
+    pub fn last_done(&self) -> Option<&Path> {
+        self.last_done.as_deref()
+    }
+
+    /// Returns how many bytes of the stream after
+    /// [`last_done`](#method.last_done) had been verified.  This is for
+    /// reporting only; resuming verifies that stream again from its start.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+//===========================================================================//
+
+/// How reading one stream turned out, as part of a [`VerifyReport`].
+#[derive(Debug)]
+pub struct StreamVerification {
+    pub(crate) path: PathBuf,
+    pub(crate) len: u64,
+    pub(crate) bytes_read: u64,
+    pub(crate) hash: Option<u64>,
+    pub(crate) error: Option<io::Error>,
+}
+
+impl StreamVerification {
+    /// Returns the path of the stream.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the stream's declared length, in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the stream's declared length is zero.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns how many bytes were read from the stream before it ended or
+    /// an error occurred.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns a 64-bit FNV-1a hash of the stream's contents, if
+    /// `hash_streams` was enabled and the whole stream was read.  This is
+    /// meant for spotting changed or identical streams, not for security
+    /// purposes.
+    pub fn hash(&self) -> Option<u64> {
+        self.hash
+    }
+
+    /// Returns the error that stopped the stream from being read to its
+    /// declared end, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Returns true if the whole stream was read without error.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+//===========================================================================//
+
+/// The result of
+/// [`CompoundFile::verify_deep`](../struct.CompoundFile.html#method.verify_deep).
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub(crate) streams: Vec<StreamVerification>,
+    pub(crate) structure_problems: Vec<String>,
+    pub(crate) bytes_read: u64,
+    pub(crate) elapsed: Duration,
+    pub(crate) cancelled: bool,
+    pub(crate) resume_token: Option<ResumeToken>,
+}
+
+impl VerifyReport {
+    /// Returns true if the verification ran to completion, every stream was
+    /// read to its end, and no structural problems were found.
+    pub fn is_ok(&self) -> bool {
+        !self.cancelled
+            && self.structure_problems.is_empty()
+            && self.streams.iter().all(StreamVerification::is_ok)
+    }
+
+    /// Returns the outcome for each stream, in the order of
+    /// [`CompoundFile::walk`](../struct.CompoundFile.html#method.walk).  For
+    /// a resumed verification, only the streams verified in this run are
+    /// included.
+    pub fn streams(&self) -> &[StreamVerification] {
+        &self.streams
+    }
+
+    /// Returns the outcomes for just the streams that couldn't be read to
+    /// their ends.
+    pub fn failed_streams(
+        &self,
+    ) -> impl Iterator<Item = &StreamVerification> + '_ {
+        self.streams.iter().filter(|stream| !stream.is_ok())
+    }
+
+    /// Returns a description of each inconsistency found between the FAT,
+    /// MiniFAT, and directory, such as sectors used by two chains, allocated
+    /// sectors used by none, or directory entries missing from the tree.
+    pub fn structure_problems(&self) -> &[String] {
+        &self.structure_problems
+    }
+
+    /// Returns the total number of bytes of stream data read.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns how long the verification took.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the average rate at which stream data was read, in bytes per
+    /// second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes_read as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns true if the progress function stopped the verification
+    /// early.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// If the verification was stopped early, returns the token to carry
+    /// it on from with
+    /// [`CompoundFile::verify_deep_from`](../struct.CompoundFile.html#method.verify_deep_from).
+    /// The streams in this report and in the resumed one's together are
+    /// then the same as an uninterrupted run's.
+    pub fn resume_token(&self) -> Option<&ResumeToken> {
+        self.resume_token.as_ref()
+    }
+}
+
+//===========================================================================//
@@ -13,7 +13,7 @@ pub enum Version {
 
 impl Version {
     /// Returns the version enum for the given version number, or `None`.
-    pub fn from_number(number: u16) -> Option<Version> {
+    pub const fn from_number(number: u16) -> Option<Version> {
         match number {
             3 => Some(Version::V3),
             4 => Some(Version::V4),
@@ -22,15 +22,57 @@ impl Version {
     }
 
     /// Returns the version number for this version.
-    pub fn number(self) -> u16 {
+    pub const fn number(self) -> u16 {
         match self {
             Version::V3 => 3,
             Version::V4 => 4,
         }
     }
 
+    /// Returns true if this is version 4.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert!(!Version::V3.is_version_4());
+    /// assert!(Version::V4.is_version_4());
+    /// ```
+    pub const fn is_version_4(self) -> bool {
+        matches!(self, Version::V4)
+    }
+
+    /// Returns true if streams in this version may be longer than 2 GiB
+    /// (that is, if the full 64 bits of each directory entry's Stream Size
+    /// field are used).  Only version 4 supports this.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert!(!Version::V3.supports_large_streams());
+    /// assert!(Version::V4.supports_large_streams());
+    /// ```
+    pub const fn supports_large_streams(self) -> bool {
+        self.is_version_4()
+    }
+
+    /// Returns true if the header's Number of Directory Sectors field is
+    /// used in this version.  In version 3 files, it must be zero.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert!(!Version::V3.requires_dir_sector_count());
+    /// assert!(Version::V4.requires_dir_sector_count());
+    /// ```
+    pub const fn requires_dir_sector_count(self) -> bool {
+        self.is_version_4()
+    }
+
     /// Returns the sector shift used in this version.
-    pub fn sector_shift(self) -> u16 {
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.sector_shift(), 9);
+    /// assert_eq!(Version::V4.sector_shift(), 12);
+    /// ```
+    pub const fn sector_shift(self) -> u16 {
         match self {
             Version::V3 => 9,  // 512-byte sectors
             Version::V4 => 12, // 4096-byte sectors
@@ -44,22 +86,134 @@ impl Version {
     /// assert_eq!(Version::V3.sector_len(), 512);
     /// assert_eq!(Version::V4.sector_len(), 4096);
     /// ```
-    pub fn sector_len(self) -> usize {
+    pub const fn sector_len(self) -> usize {
         1 << (self.sector_shift() as usize)
     }
 
     /// Returns the bitmask used for reading stream lengths in this version.
-    pub fn stream_len_mask(self) -> u64 {
+    pub const fn stream_len_mask(self) -> u64 {
+        if self.supports_large_streams() {
+            0xffffffffffffffff
+        } else {
+            0xffffffff
+        }
+    }
+
+    /// Returns the maximum length of a stream in this version, in bytes.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.max_stream_len(), 0x80000000);
+    /// assert_eq!(Version::V4.max_stream_len(), u64::MAX);
+    /// ```
+    pub const fn max_stream_len(self) -> u64 {
+        // See the Stream Size field in MS-CFB section 2.6.1.
+        if self.supports_large_streams() {
+            u64::MAX
+        } else {
+            0x80000000
+        }
+    }
+
+    /// Returns the largest total length, in bytes, to which this library
+    /// will grow a compound file of this version.  Version 3 files are
+    /// limited to 2 GiB, since many implementations compute sector offsets
+    /// with signed 32-bit arithmetic; version 4 files can use every sector
+    /// number the FAT can express.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.max_file_len(), 0x80000000);
+    /// assert_eq!(Version::V4.max_file_len(), 0xfffffffb000);
+    /// ```
+    pub const fn max_file_len(self) -> u64 {
         match self {
-            Version::V3 => 0xffffffff,
-            Version::V4 => 0xffffffffffffffff,
+            Version::V3 => 0x80000000,
+            Version::V4 => {
+                (consts::MAX_REGULAR_SECTOR as u64 + 1)
+                    * self.sector_len() as u64
+            }
         }
     }
 
+    /// Returns the largest number of sectors (not counting the header) that
+    /// a compound file of this version may have; see `max_file_len`.
+    pub(crate) const fn max_num_sectors(self) -> u64 {
+        self.max_file_len() / self.sector_len() as u64 - 1
+    }
+
+    /// Returns the length of mini sectors, which is the same in all
+    /// versions.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.mini_sector_len(), 64);
+    /// assert_eq!(Version::V4.mini_sector_len(), 64);
+    /// ```
+    pub const fn mini_sector_len(self) -> usize {
+        consts::MINI_SECTOR_LEN
+    }
+
+    /// Returns the mini stream cutoff, which is the same in all versions:
+    /// streams shorter than this many bytes are stored in mini sectors, and
+    /// longer ones in regular sectors.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.mini_stream_cutoff(), 4096);
+    /// assert_eq!(Version::V4.mini_stream_cutoff(), 4096);
+    /// ```
+    pub const fn mini_stream_cutoff(self) -> u64 {
+        consts::MINI_STREAM_CUTOFF as u64
+    }
+
     /// Returns the number of directory entries per sector in this version.
-    pub fn dir_entries_per_sector(self) -> usize {
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.dir_entries_per_sector(), 4);
+    /// assert_eq!(Version::V4.dir_entries_per_sector(), 32);
+    /// ```
+    pub const fn dir_entries_per_sector(self) -> usize {
         self.sector_len() / consts::DIR_ENTRY_LEN
     }
+
+    /// Returns the number of entries per FAT (or MiniFAT) sector in this
+    /// version.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.fat_entries_per_sector(), 128);
+    /// assert_eq!(Version::V4.fat_entries_per_sector(), 1024);
+    /// ```
+    pub const fn fat_entries_per_sector(self) -> usize {
+        self.sector_len() / 4
+    }
+
+    /// Returns the number of FAT sector locations per DIFAT sector in this
+    /// version (the last four bytes of each DIFAT sector point to the next
+    /// one).
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.difat_entries_per_sector(), 127);
+    /// assert_eq!(Version::V4.difat_entries_per_sector(), 1023);
+    /// ```
+    pub const fn difat_entries_per_sector(self) -> usize {
+        self.fat_entries_per_sector() - 1
+    }
+
+    /// Returns the number of FAT sector locations stored in the header
+    /// itself, which is the same in all versions.
+    ///
+    /// ```
+    /// use cfb::Version;
+    /// assert_eq!(Version::V3.difat_header_entries(), 109);
+    /// assert_eq!(Version::V4.difat_header_entries(), 109);
+    /// ```
+    pub const fn difat_header_entries(self) -> usize {
+        consts::NUM_DIFAT_ENTRIES_IN_HEADER
+    }
 }
 
 // ========================================================================= //
@@ -68,6 +222,23 @@ impl Version {
 mod tests {
     use super::Version;
 
+    const V4_SECTOR_LEN: usize = Version::V4.sector_len();
+
+    #[test]
+    fn sizes_are_const() {
+        assert_eq!(V4_SECTOR_LEN, 4096);
+    }
+
+    #[test]
+    fn max_num_sectors() {
+        assert_eq!(Version::V3.max_num_sectors(), 4194303);
+        assert_eq!(
+            (Version::V3.max_num_sectors() + 1) * 512,
+            Version::V3.max_file_len()
+        );
+        assert_eq!(Version::V4.max_num_sectors(), 0xfffffffa);
+    }
+
     #[test]
     fn number_round_trip() {
         for &version in &[Version::V3, Version::V4] {
@@ -42,34 +42,144 @@
 //! let mut stream = comp2.create_stream("/spam/eggs").unwrap();
 //! stream.write_all(&data).unwrap();
 //! ```
+//!
+//! # Crate layout
+//!
+//! Everything needed for typical reading and writing can be imported at once
+//! with `use cfb::prelude::*;` (see the [`prelude`] module).  Beyond that:
+//!
+//! * The crate root holds [`CompoundFile`], along with the option, report,
+//!   and error types that its methods take and return.
+//! * Operations on a single compound file are methods of [`CompoundFile`].
+//!   The free functions at the root are for opening or creating files by
+//!   path ([`open`], [`open_strict`], [`open_rw`], [`open_from_reader`],
+//!   [`create`], and [`create_from_dir`]), and for work that spans several
+//!   compound files ([`merge`] and [`scan_dir`], whose file name filters
+//!   use [`glob_match`]).
+//! * [`propset`] reads and writes OLE property set streams.
+//! * [`consts`] has the constants of the CFB format.
+//!
+//! Some functionality is behind Cargo features, each with a module of its
+//! own:
+//!
+//! | Feature   | Module         | Provides                                   |
+//! |-----------|----------------|--------------------------------------------|
+//! | `async`   | `async_file`   | Reading compound files with Tokio          |
+//! | `compat`  | `compat`       | Adapters for code written for other crates |
+//! | `metrics` | `metrics`      | Counting the I/O that operations do        |
+//! | `msi`     | `msi`          | Windows Installer databases                |
+//! | `serde`   | (none)         | Serializing snapshots and tokens           |
+//! | `cli`     | `tool`         | `cfbtool` and its building blocks          |
+//!
+//! The main types of the `async_file` and `metrics` modules are also
+//! re-exported at the root.
 
 #![warn(missing_docs)]
 
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs;
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::mem::size_of;
+use std::hash::Hasher;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::iter::FusedIterator;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
 
 use fnv::FnvHashSet;
 use uuid::Uuid;
 
-use crate::internal::consts;
+#[cfg(feature = "async")]
+#[doc(no_inline)]
+pub use crate::async_file::{AsyncCompoundFile, AsyncStream, MetadataImage};
+pub use crate::internal::consts;
+pub use crate::internal::path::TEMPORARY_NAME_PREFIX;
+#[cfg(not(feature = "metrics"))]
+use crate::internal::Op;
 use crate::internal::{
-    Allocator, DirEntry, Directory, EntriesOrder, Header, MiniAllocator,
-    ObjType, SectorInit, Sectors, Timestamp, Validation,
+    compare_names_for_signature, ioutil, is_property_set_stream,
+    next_in_chain, read_audit_records, scrub_property_set, try_reserve,
+    try_vec_with_capacity, Allocator, Backing, ChainName, CompactLayout,
+    DirEntry, Directory, EntriesOrder, Header, MiniAllocator, ReadOnly,
+    SectorInit, Sectors, Timer, Timestamp, Validation, DEFAULT_MAX_BUFFER_LEN,
+    DIGITAL_SIGNATURE_STREAM_NAME, MSI_DIGITAL_SIGNATURE_EX_STREAM_NAME,
 };
-pub use crate::internal::{Entries, Entry, Stream, Version};
+pub use crate::internal::{
+    glob_match, merge, scan_dir, AlignedBytes, AlignedSlice, AllocContext,
+    AuditOp, AuditRecord, BackingFileShrunk, Capabilities, ClsidPolicy,
+    ClusterMetadataFirst, CreateOptions, DeletedEntry, Entries, Entry,
+    EntryKind, EntryMetadata, ExtraEntries, FileTooLarge, FirstFree,
+    FreeEntryPolicy, ImportFailure, ImportOptions, ImportReport, MergeOptions,
+    MergeReport, MergeSourceReport, MetadataFields, MetadataSnapshot,
+    MissingEntries, NewEntryOptions, ObjType, ObjectNotFound,
+    PathThroughStream, Reachability, RecoveryWarning, RecoveryWarningKind,
+    RequiresVersion4, ResolvedPath, RestorePolicy, RestoreReport, ResumeToken,
+    SanitizeOptions, SanitizeReport, ScanDir, ScanEntry, ScanOptions,
+    ScanOutcome, ScanResult, SectorAllocator, SectorId, SectorPurpose,
+    Severity, SignatureContent, SnapshotEntry, SplitOptions, SplitReport,
+    Spool, SpoolPolicy, Stats, Stream, StreamId, StreamReader,
+    StreamVerification, StreamsStillOpen, SyncOptions, SyncReport,
+    TooLargeToBuffer, TouchOptions, Unsupported, ValidationIssue,
+    ValidationIssueKind, VerifyOptions, VerifyReport, Version,
+};
+#[cfg(feature = "metrics")]
+#[doc(no_inline)]
+pub use crate::metrics::{AtomicMetrics, MetricsSink, Op, OpTotals};
 
 #[macro_use]
 mod internal;
+#[cfg(feature = "async")]
+pub mod async_file;
+#[cfg(feature = "compat")]
+pub mod compat;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "msi")]
+pub mod msi;
+pub mod prelude;
+pub mod propset;
+#[cfg(feature = "cli")]
+pub mod tool;
 
 //===========================================================================//
 
 /// Opens an existing compound file at the given path in read-only mode.
+///
+/// Any attempt to modify the returned `CompoundFile` fails with an
+/// [`Unsupported`] error, since it lacks the
+/// [`WRITABLE`](struct.Capabilities.html#associatedconstant.WRITABLE)
+/// capability.
 pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CompoundFile<fs::File>> {
-    CompoundFile::open(fs::File::open(path)?)
+    let path = path.as_ref();
+    let comp = CompoundFile::open(fs::File::open(path)?)?;
+    Ok(comp.with_backing(Backing::file(path.to_path_buf(), false)))
+}
+
+/// Like [`open`], but is stricter when parsing, and returns an error if the
+/// file violates the CFB spec in any way (see
+/// [`CompoundFile::open_strict`]).
+pub fn open_strict<P: AsRef<Path>>(
+    path: P,
+) -> io::Result<CompoundFile<fs::File>> {
+    let path = path.as_ref();
+    let comp = CompoundFile::open_strict(fs::File::open(path)?)?;
+    Ok(comp.with_backing(Backing::file(path.to_path_buf(), false)))
+}
+
+/// Opens an existing compound file from a reader that need not be seekable
+/// (such as stdin or a network stream), by first spooling all of its data
+/// according to the given policy.
+///
+/// The returned `CompoundFile` is both readable and writable, but any changes
+/// made to it only affect the spooled copy of the data.
+pub fn open_from_reader<R: Read>(
+    mut reader: R,
+    spool: SpoolPolicy,
+) -> io::Result<CompoundFile<Spool>> {
+    let mut spool = Spool::new(spool);
+    io::copy(&mut reader, &mut spool)?;
+    spool.seek(SeekFrom::Start(0))?;
+    Ok(CompoundFile::open(spool)?.with_backing(Backing::writable()))
 }
 
 /// Opens an existing compound file at the given path in read-write mode.
@@ -79,7 +189,8 @@ pub fn open_rw<P: AsRef<Path>>(path: P) -> io::Result<CompoundFile<fs::File>> {
 
 fn open_rw_with_path(path: &Path) -> io::Result<CompoundFile<fs::File>> {
     let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
-    CompoundFile::open(file)
+    let comp = CompoundFile::open(file)?;
+    Ok(comp.with_backing(Backing::file(path.to_path_buf(), true)))
 }
 
 /// Creates a new compound file with no contents at the given path.
@@ -97,7 +208,115 @@ fn create_with_path(path: &Path) -> io::Result<CompoundFile<fs::File>> {
         .create(true)
         .truncate(true)
         .open(path)?;
-    CompoundFile::create(file)
+    let comp = CompoundFile::create(file)?;
+    Ok(comp.with_backing(Backing::file(path.to_path_buf(), true)))
+}
+
+/// Creates a new compound file at the given path (overwriting any existing
+/// file there), fills it with the contents of the directory `fs_dir` on
+/// disk, and flushes it.  This is shorthand for calling
+/// [`create_storage_from_dir`](struct.CompoundFile.html#method.create_storage_from_dir)
+/// on the root storage of a new file; see there for details.
+pub fn create_from_dir<D: AsRef<Path>, P: AsRef<Path>>(
+    fs_dir: D,
+    path: P,
+    options: ImportOptions,
+) -> io::Result<ImportReport> {
+    let fs_dir = fs_dir.as_ref();
+    if !fs::metadata(fs_dir)?.is_dir() {
+        invalid_input!("Not a directory: {:?}", fs_dir);
+    }
+    let mut comp = create_with_path(path.as_ref())?;
+    let report = comp.create_storage_from_dir(fs_dir, "/", options)?;
+    comp.flush()?;
+    Ok(report)
+}
+
+/// Splits a compound file into one standalone compound file per top-level
+/// storage.  This is the old name of [`CompoundFile::split`].
+#[deprecated(note = "use `CompoundFile::split` instead")]
+pub fn split<F, W, O>(
+    comp: &mut CompoundFile<F>,
+    options: SplitOptions,
+    output: O,
+) -> io::Result<SplitReport>
+where
+    F: Read + Seek,
+    W: Read + Write + Seek,
+    O: FnMut(&Entry) -> io::Result<W>,
+{
+    comp.split(options, output)
+}
+
+/// Converts a time passed to one of the timestamp setters, failing if it
+/// can't be represented as a CFB timestamp.
+fn checked_timestamp(time: std::time::SystemTime) -> io::Result<Timestamp> {
+    match Timestamp::checked_from_system_time(time) {
+        Some(timestamp) => Ok(timestamp),
+        None => invalid_input!(
+            "{:?} is out of range for a CFB timestamp (which must be no \
+             earlier than 1601-01-01 UTC)",
+            time
+        ),
+    }
+}
+
+/// Checks the times in the given options, returning them as timestamps.
+fn checked_entry_times(
+    options: &NewEntryOptions,
+) -> io::Result<(Option<Timestamp>, Option<Timestamp>)> {
+    let created = options.created.map(checked_timestamp).transpose()?;
+    let modified = options.modified.map(checked_timestamp).transpose()?;
+    Ok((created, modified))
+}
+
+/// Returns the paths of the entries of a local directory, sorted by name,
+/// along with their metadata (without following symbolic links).
+fn read_local_dir(dir: &Path) -> io::Result<Vec<(PathBuf, fs::Metadata)>> {
+    let mut children = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let metadata = fs::symlink_metadata(dir_entry.path())?;
+        children.push((dir_entry.path(), metadata));
+    }
+    children.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(children)
+}
+
+/// Returns true if the two readers yield exactly the same bytes.
+fn same_contents<R: Read, S: Read>(mut a: R, mut b: S) -> io::Result<bool> {
+    let mut buf_a = vec![0u8; 8192];
+    let mut buf_b = vec![0u8; 8192];
+    loop {
+        let len = ioutil::read_up_to(&mut a, &mut buf_a)?;
+        if ioutil::read_up_to(&mut b, &mut buf_b[..len.max(1)])? != len {
+            return Ok(false);
+        }
+        if len == 0 {
+            return Ok(true);
+        }
+        if buf_a[..len] != buf_b[..len] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Reads `count` little-endian `u32` values from the start of the given
+/// sector, as a unit, so that a sector that can't be read contributes
+/// nothing.
+fn read_sector_u32s<F: Read + Seek>(
+    sectors: &mut Sectors<F>,
+    sector_id: u32,
+    count: usize,
+) -> io::Result<Vec<u32>> {
+    let mut buffer = vec![0u8; count * 4];
+    sectors.seek_to_sector(sector_id)?.read_exact(&mut buffer)?;
+    Ok(buffer
+        .chunks_exact(4)
+        .map(|chunk| {
+            u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])
+        })
+        .collect())
 }
 
 //===========================================================================//
@@ -107,6 +326,12 @@ fn create_with_path(path: &Path) -> io::Result<CompoundFile<fs::File>> {
 /// [`Cursor`](https://doc.rust-lang.org/std/io/struct.Cursor.html)).
 pub struct CompoundFile<F> {
     minialloc: Arc<RwLock<MiniAllocator<F>>>,
+    open_warnings: Vec<ValidationIssue>,
+    leaked_temporaries: Vec<PathBuf>,
+    header_version_mismatch: bool,
+    backing: Backing<F>,
+    clsid_policy: ClsidPolicy,
+    max_buffer_len: usize,
 }
 
 impl<F> CompoundFile<F> {
@@ -123,18 +348,410 @@ impl<F> CompoundFile<F> {
         self.minialloc().version()
     }
 
-    fn stream_id_for_name_chain(&self, names: &[&str]) -> Option<u32> {
-        self.minialloc().stream_id_for_name_chain(names)
+    /// Returns true if this is a version 4 compound file.
+    pub fn is_version_4(&self) -> bool {
+        self.version().is_version_4()
+    }
+
+    /// Returns the sector shift of this compound file (see
+    /// [`Version::sector_shift`]).
+    pub fn sector_shift(&self) -> u16 {
+        self.version().sector_shift()
+    }
+
+    /// Returns the length of this compound file's sectors, in bytes (see
+    /// [`Version::sector_len`]).
+    pub fn sector_len(&self) -> usize {
+        self.version().sector_len()
+    }
+
+    /// Returns true if streams in this compound file may be longer than 2
+    /// GiB (see [`Version::supports_large_streams`]).  Trying to make a
+    /// stream that long in a file that doesn't support it fails with a
+    /// [`RequiresVersion4`] error.
+    pub fn supports_large_streams(&self) -> bool {
+        self.version().supports_large_streams()
+    }
+
+    /// Returns true if this compound file's header records the number of
+    /// directory sectors (see [`Version::requires_dir_sector_count`]).
+    pub fn requires_dir_sector_count(&self) -> bool {
+        self.version().requires_dir_sector_count()
+    }
+
+    /// Returns the length of this compound file's mini sectors, in bytes.
+    /// This is normally [`Version::mini_sector_len`], but a file opened with
+    /// a nonstandard mini sector shift in its header (see
+    /// [`ValidationIssueKind::NonstandardMiniSectorShift`]) uses the length
+    /// that its header gives.
+    pub fn mini_sector_len(&self) -> usize {
+        self.minialloc().mini_sector_len()
+    }
+
+    /// Returns the length, in bytes, below which streams are stored in mini
+    /// sectors (see [`Version::mini_stream_cutoff`]).
+    pub fn mini_stream_cutoff(&self) -> u64 {
+        self.version().mini_stream_cutoff()
+    }
+
+    /// Returns the number of directory entries per sector (see
+    /// [`Version::dir_entries_per_sector`]).
+    pub fn dir_entries_per_sector(&self) -> usize {
+        self.version().dir_entries_per_sector()
+    }
+
+    /// Returns the number of entries per FAT sector (see
+    /// [`Version::fat_entries_per_sector`]).
+    pub fn fat_entries_per_sector(&self) -> usize {
+        self.version().fat_entries_per_sector()
+    }
+
+    /// Returns the number of FAT sector locations stored in the header (see
+    /// [`Version::difat_header_entries`]).
+    pub fn difat_header_entries(&self) -> usize {
+        self.version().difat_header_entries()
+    }
+
+    /// Returns statistics about the physical layout of the compound file,
+    /// such as how many sectors are in use for each purpose and how
+    /// fragmented the file's sector chains are.
+    pub fn stats(&self) -> io::Result<Stats> {
+        self.minialloc().stats()
+    }
+
+    /// Returns the current length, in bytes, of the mini stream, the
+    /// internal stream (owned by the root storage) that holds the contents
+    /// of all streams smaller than the mini stream cutoff.  This is not
+    /// reported by `self.root_entry().len()`, which is always zero.
+    pub fn mini_stream_len(&self) -> u64 {
+        self.minialloc().root_dir_entry().stream_len
+    }
+
+    /// Returns the spec violations that were tolerated when this compound
+    /// file was opened, in the order they were encountered.  These are
+    /// collected during parsing, so this requires no extra I/O.  The slice
+    /// is always empty for newly created files, and for files opened with
+    /// `open_strict()` (which fails on any such violation instead) it only
+    /// ever reports leaked temporary objects (see
+    /// [`set_show_temporaries`](#method.set_show_temporaries)), which aren't
+    /// spec violations.
+    pub fn open_warnings(&self) -> &[ValidationIssue] {
+        &self.open_warnings
+    }
+
+    /// Checks the file for spec violations and damage, and returns every
+    /// issue found, so that a file can be classified by the
+    /// [`severity`](struct.ValidationIssue.html#method.severity) of its
+    /// worst issue: a file with no issues above `Severity::Info` is clean,
+    /// and one with an issue of `Severity::Error` has data that can't be
+    /// read.  (A file that can't be opened at all is, of course,
+    /// unreadable.)
+    ///
+    /// This reports the issues from
+    /// [`open_warnings`](#method.open_warnings) that can only be found while
+    /// parsing the file (such as bad header fields or directory entries), as
+    /// they were when the file was opened.  It then checks the file as it is
+    /// now: every chain is followed to check for breaks, sectors in more
+    /// than one chain, and sectors in no chain at all; every allocated
+    /// directory entry is checked to be in the tree; and each stream is
+    /// checked against its chain.  Each issue about an object in the tree
+    /// carries that object's path.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues: Vec<ValidationIssue> = self
+            .open_warnings
+            .iter()
+            .filter(|issue| {
+                !matches!(
+                    issue.kind(),
+                    ValidationIssueKind::BrokenChain
+                        | ValidationIssueKind::StreamLongerThanChain
+                        | ValidationIssueKind::ChainSlack
+                        | ValidationIssueKind::StaleChain
+                )
+            })
+            .cloned()
+            .collect();
+        issues.extend(self.minialloc().validation_issues());
+        issues
+    }
+
+    /// Sets whether iterating over entries (with `walk`, `read_storage`, and
+    /// so on) includes temporary objects.  Defaults to false.
+    ///
+    /// Some multi-step operations (such as
+    /// [`replace_stream`](#method.replace_stream)) write to a temporary
+    /// object first, and then swap it into place, so that they never leave
+    /// an object half-written.  Temporary objects have names beginning with
+    /// [`TEMPORARY_NAME_PREFIX`](constant.TEMPORARY_NAME_PREFIX.html), which
+    /// can't be used for other objects, so they never collide with them.  If
+    /// such an operation is interrupted (for example, by a crash), its
+    /// temporary object may be left behind; opening the file again reports
+    /// any such objects in [`open_warnings`](#method.open_warnings), and the
+    /// next [`flush`](#method.flush) removes them.  They are hidden from
+    /// iteration in the meantime, unless this is set to true (which can be
+    /// useful for debugging).
+    pub fn set_show_temporaries(&mut self, show: bool) {
+        self.minialloc_mut().set_show_temporaries(show);
+    }
+
+    /// Returns what removing an object does with its directory entry (see
+    /// [`set_free_entry_policy`](#method.set_free_entry_policy)).
+    pub fn free_entry_policy(&self) -> FreeEntryPolicy {
+        self.minialloc().free_entry_policy()
+    }
+
+    /// Sets what removing an object (with `remove_stream`, `remove_storage`,
+    /// `remove_storage_all`, or any other operation that removes objects)
+    /// does with its directory entry.  Defaults to
+    /// [`FreeEntryPolicy::Scrub`], which resets the entry to all zeros, as
+    /// the spec requires.  With [`FreeEntryPolicy::Preserve`], the entry is
+    /// still marked unallocated, but keeps the object's name and timestamps
+    /// for [`deleted_entries`](#method.deleted_entries) to find, until the
+    /// entry is reused for a new object.
+    ///
+    /// This setting isn't stored in the file, and applies only to objects
+    /// removed after it is set.
+    pub fn set_free_entry_policy(&mut self, policy: FreeEntryPolicy) {
+        self.minialloc_mut().set_free_entry_policy(policy);
+    }
+
+    /// Returns whether space is overwritten with zeros as it is freed (see
+    /// [`set_zero_freed_sectors`](#method.set_zero_freed_sectors)).
+    pub fn zero_freed_sectors(&self) -> bool {
+        self.minialloc().zero_freed_sectors()
+    }
+
+    /// Sets whether space is overwritten with zeros at the moment it is
+    /// freed, so that removed or truncated data can't be recovered from the
+    /// file afterwards.  Defaults to false.  When this is set:
+    ///
+    /// * Sectors and mini sectors are zeroed as they are released to the
+    ///   free list, whether by `remove_stream`, `remove_storage_all`,
+    ///   [`Stream::set_len`], or any other operation that frees them.
+    /// * Truncating a stream also zeroes the rest of its final (mini)
+    ///   sector, past the new end of the stream.
+    /// * Removed objects' directory entries are reset to all zeros, even if
+    ///   the [free entry policy](#method.set_free_entry_policy) is
+    ///   [`FreeEntryPolicy::Preserve`].
+    ///
+    /// This setting isn't stored in the file, and applies only to space
+    /// freed after it is set; use [`sanitize`](#method.sanitize) to wipe
+    /// space that was already free.
+    pub fn set_zero_freed_sectors(&mut self, zero: bool) {
+        self.minialloc_mut().set_zero_freed_sectors(zero);
+    }
+
+    /// Returns the policy for choosing the CLSIDs of new storages (see
+    /// [`set_clsid_policy`](#method.set_clsid_policy)).
+    pub fn clsid_policy(&self) -> &ClsidPolicy {
+        &self.clsid_policy
+    }
+
+    /// Sets how storages created from now on get their CLSIDs, whether by
+    /// [`create_storage`](#method.create_storage),
+    /// [`create_storage_all`](#method.create_storage_all) (for each storage
+    /// it creates), or any other operation that creates storages, such as
+    /// [`create_storage_from_dir`](#method.create_storage_from_dir).
+    /// Defaults to [`ClsidPolicy::Null`], which leaves them all zeros.
+    /// Operations that copy storages from elsewhere, such as
+    /// [`copy_storage_from`](#method.copy_storage_from), still give the
+    /// copies their originals' CLSIDs.
+    ///
+    /// This setting isn't stored in the file.
+    pub fn set_clsid_policy(&mut self, policy: ClsidPolicy) {
+        self.clsid_policy = policy;
+    }
+
+    /// Returns the most bytes that methods which read a whole stream into
+    /// memory will buffer at once (see
+    /// [`set_max_buffer_len`](#method.set_max_buffer_len)).
+    pub fn max_buffer_len(&self) -> usize {
+        self.max_buffer_len
+    }
+
+    /// Sets the most bytes that methods which read a whole stream (or
+    /// chain) into memory will buffer at once, such as
+    /// [`read_stream_to_vec`](#method.read_stream_to_vec),
+    /// [`read_many`](#method.read_many) (for each stream),
+    /// [`read_stream_aligned`](#method.read_stream_aligned),
+    /// [`read_stream_slack`](#method.read_stream_slack), and
+    /// [`read_property_set`](#method.read_property_set).  Asking them to
+    /// buffer more fails with a [`TooLargeToBuffer`] error before anything
+    /// is allocated, rather than risking running out of memory on a huge
+    /// (or maliciously crafted) stream.  Methods that process streams
+    /// incrementally, such as [`open_stream`](#method.open_stream) and
+    /// [`verify_deep`](#method.verify_deep) (even when it hashes streams),
+    /// aren't limited.  Defaults to 1
+    /// GiB; use `usize::MAX` to remove the limit.
+    ///
+    /// This setting isn't stored in the file.
+    pub fn set_max_buffer_len(&mut self, max_len: usize) {
+        self.max_buffer_len = max_len;
+    }
+
+    /// Returns a `TooLargeToBuffer` error if buffering `len` bytes of the
+    /// given stream would exceed the limit set with `set_max_buffer_len`.
+    /// Every method that reads a whole stream into memory checks this first.
+    fn check_buffer_len(&self, path: &Path, len: u64) -> io::Result<()> {
+        if len > self.max_buffer_len as u64 {
+            return Err(
+                TooLargeToBuffer::new(path, len, self.max_buffer_len).into()
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the name and timestamps left in each unallocated directory
+    /// entry that still has any, in stream ID order.  These are left behind
+    /// by objects removed under [`FreeEntryPolicy::Preserve`] (whether by
+    /// this library or by another writer that doesn't scrub freed entries);
+    /// under the default [`FreeEntryPolicy::Scrub`], removed objects leave
+    /// nothing to find.
+    pub fn deleted_entries(&self) -> Vec<DeletedEntry> {
+        self.minialloc().deleted_entries()
+    }
+
+    /// Enables or disables self-checking, for debugging this library (or
+    /// code that uses it).  While enabled, every method that modifies the
+    /// compound file (including writing to or resizing a [`Stream`]) checks
+    /// afterwards that the parts of the FAT, MiniFAT, and directory it
+    /// changed are still consistent with each other, and every so often
+    /// checks the whole file, panicking at the first problem found with a
+    /// message naming the operation that caused it.  Enabling it checks the
+    /// whole file straight away, so it panics then if the file was already
+    /// inconsistent when opened.
+    ///
+    /// Self-checking only happens in builds with debug assertions enabled;
+    /// in release builds, this does nothing.
+    pub fn set_self_check(&mut self, enabled: bool) {
+        if cfg!(debug_assertions) {
+            self.minialloc_mut().set_self_check(enabled);
+        }
+    }
+
+    /// Runs a self-check (see `set_self_check`), if enabled, naming the given
+    /// operation in case it fails.
+    fn self_check(&mut self, operation: &str) {
+        self.minialloc_mut().self_check(operation);
+    }
+
+    /// Limits how many bytes of written data the open [`Stream`] handles of
+    /// this compound file may hold in their buffers, in total, before
+    /// writing it to the underlying file.  Defaults to no limit (each handle
+    /// buffers up to 8 KiB).
+    ///
+    /// Whenever a write would push the total over the budget, the handle
+    /// being written to writes its buffered data through to the stream's
+    /// sectors at once (reported to the metrics sink, if any, as
+    /// `Op::EarlyFlush`).  This only affects when stream data reaches the
+    /// underlying file; changes to the FAT, directory, and header are
+    /// written as they happen either way, in the same order as without a
+    /// budget.
+    pub fn set_dirty_budget(&mut self, bytes: u64) {
+        self.minialloc_mut().set_dirty_budget(bytes);
+    }
+
+    /// Returns the number of bytes of written data currently held in the
+    /// buffers of this compound file's open [`Stream`] handles (see
+    /// [`set_dirty_budget`](#method.set_dirty_budget)).
+    pub fn dirty_bytes(&self) -> u64 {
+        self.minialloc().dirty_bytes()
+    }
+
+    /// Returns the paths of the streams that open [`Stream`] handles hold
+    /// written data for, which they haven't yet passed on to this compound
+    /// file.  While this is non-empty, [`flush`](#method.flush) fails with
+    /// [`StreamsStillOpen`], and [`into_inner`](#method.into_inner) would
+    /// return a file missing that data.  Flushing or dropping the handles
+    /// passes the data on.
+    pub fn unflushed_streams(&self) -> Vec<PathBuf> {
+        self.minialloc().unflushed_streams()
+    }
+
+    /// Fails with [`StreamsStillOpen`] if any open [`Stream`] handles hold
+    /// unflushed writes.
+    fn check_no_unflushed_streams(&self) -> io::Result<()> {
+        let paths = self.unflushed_streams();
+        if paths.is_empty() {
+            return Ok(());
+        }
+        Err(StreamsStillOpen::new(paths).into())
+    }
+
+    /// Installs a sink that is told how long each operation on this compound
+    /// file takes, and how many bytes it handles (see [`Op`]), replacing any
+    /// sink installed before.  Opening the file is measured even though no
+    /// sink can be installed until afterwards: the `Open` and `Validate`
+    /// operations are reported to the first sink as soon as it is installed.
+    ///
+    /// To read the numbers back, install an [`AtomicMetrics`] in an `Arc`
+    /// and keep a clone of the `Arc`.  This method only exists with the
+    /// `metrics` feature; without it, no timing is done at all.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.minialloc_mut().metrics_mut().set_sink(Arc::new(sink));
+    }
+
+    /// Returns the stream ID of the object at the given name chain, or `None`
+    /// if there is no such object.  Returns a `PathThroughStream` error if
+    /// the name chain continues past a stream.
+    fn stream_id_for_name_chain(
+        &self,
+        names: &[&str],
+    ) -> io::Result<Option<u32>> {
+        let minialloc = self.minialloc();
+        if let Some(stream_id) = minialloc.stream_id_for_name_chain(names) {
+            return Ok(Some(stream_id));
+        }
+        match minialloc.stream_prefix_len(names) {
+            Some(prefix_len) => {
+                Err(PathThroughStream::new(names, prefix_len).into())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the stream ID of the object at the given name chain.  If there
+    /// is no such object, returns an `ObjectNotFound` error that describes
+    /// the missing object as `what` (e.g. `"stream"`), or a
+    /// `PathThroughStream` error if the name chain continues past a stream.
+    fn resolve_name_chain(
+        &self,
+        names: &[&str],
+        what: &'static str,
+    ) -> io::Result<u32> {
+        let minialloc = self.minialloc();
+        let (prefix_len, stream_id) = minialloc.longest_existing_prefix(names);
+        if prefix_len == names.len() {
+            return Ok(stream_id);
+        }
+        let kind = match minialloc.dir_entry(stream_id).obj_type {
+            ObjType::Root => EntryKind::Root,
+            ObjType::Storage => EntryKind::Storage,
+            _ => return Err(PathThroughStream::new(names, prefix_len).into()),
+        };
+        Err(ObjectNotFound::new(what, names, prefix_len, kind).into())
     }
 
     /// Returns information about the root storage object.  This is equivalent
     /// to `self.entry("/").unwrap()` (but always succeeds).
     pub fn root_entry(&self) -> Entry {
-        Entry::new(self.minialloc().root_dir_entry(), PathBuf::from("/"))
+        Entry::new(
+            &self.minialloc(),
+            consts::ROOT_STREAM_ID,
+            PathBuf::from("/"),
+        )
     }
 
     /// Given a path within the compound file, get information about that
     /// stream or storage object.
+    ///
+    /// Like every method that takes a path, this ignores trailing separators
+    /// (so `"Storage/"` names the same object as `"Storage"`), and if the
+    /// path continues past a stream (as in `"Stream/Child"`), fails with a
+    /// `NotFound` error carrying a [`PathThroughStream`].  If an object along
+    /// the path is missing, the `NotFound` error instead carries an
+    /// [`ObjectNotFound`] recording the deepest storage that does exist.
     pub fn entry<P: AsRef<Path>>(&self, path: P) -> io::Result<Entry> {
         self.entry_with_path(path.as_ref())
     }
@@ -142,11 +759,103 @@ impl<F> CompoundFile<F> {
     fn entry_with_path(&self, path: &Path) -> io::Result<Entry> {
         let names = internal::path::name_chain_from_path(path)?;
         let path = internal::path::path_from_name_chain(&names);
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!("No such object: {:?}", path),
-        };
-        Ok(Entry::new(self.minialloc().dir_entry(stream_id), path))
+        let stream_id = self.resolve_name_chain(&names, "object")?;
+        Ok(Entry::new(&self.minialloc(), stream_id, path))
+    }
+
+    /// Looks up the given path, returning a token that can be used to find
+    /// the same object again (in this compound file, or in another with the
+    /// same structure) without comparing names along the way; see
+    /// [`ResolvedPath`].  Fails in the same ways as [`entry`](#method.entry).
+    pub fn resolve<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<ResolvedPath> {
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "object")?;
+        let minialloc = self.minialloc();
+        let mut stream_ids = vec![stream_id; names.len()];
+        for index in (1..names.len()).rev() {
+            stream_ids[index - 1] =
+                minialloc.parent_id(stream_ids[index]).unwrap_or_default();
+        }
+        Ok(ResolvedPath::new(&minialloc, path, stream_ids))
+    }
+
+    /// Returns the stream ID of the object at a resolved path, falling back
+    /// to looking the path up from scratch if the resolution is out of date.
+    fn resolved_stream_id(
+        &self,
+        resolved: &ResolvedPath,
+        what: &'static str,
+    ) -> io::Result<u32> {
+        if let Some(stream_id) = resolved.revalidate(&self.minialloc()) {
+            return Ok(stream_id);
+        }
+        self.resolve_name_chain(&resolved.names(), what)
+    }
+
+    /// Like [`entry`](#method.entry), but for a path that has already been
+    /// [resolved](#method.resolve).
+    pub fn entry_resolved(
+        &self,
+        resolved: &ResolvedPath,
+    ) -> io::Result<Entry> {
+        let stream_id = self.resolved_stream_id(resolved, "object")?;
+        Ok(Entry::new(
+            &self.minialloc(),
+            stream_id,
+            resolved.path().to_path_buf(),
+        ))
+    }
+
+    /// Returns information about the storage object containing the stream or
+    /// storage object at the provided path, or `None` if the path refers to
+    /// the root storage.  This is the first item of
+    /// [`ancestors`](#method.ancestors).
+    pub fn parent_entry<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<Option<Entry>> {
+        Ok(self.ancestors(path)?.next())
+    }
+
+    /// Returns an iterator over the storage objects containing the stream or
+    /// storage object at the provided path, starting with its immediate
+    /// parent and ending with the root storage (so the iterator is empty for
+    /// the root storage itself).  This is useful for finding out, say, the
+    /// CLSID of the embedded object that a stream belongs to.
+    pub fn ancestors<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<
+        impl DoubleEndedIterator<Item = Entry> + ExactSizeIterator + FusedIterator,
+    > {
+        self.ancestors_with_path(path.as_ref())
+    }
+
+    fn ancestors_with_path(
+        &self,
+        path: &Path,
+    ) -> io::Result<std::vec::IntoIter<Entry>> {
+        let names = internal::path::name_chain_from_path(path)?;
+        let mut stream_id = self.resolve_name_chain(&names, "object")?;
+        let minialloc = self.minialloc();
+        let mut ancestors = Vec::with_capacity(names.len());
+        for depth in (0..names.len()).rev() {
+            stream_id = match minialloc.parent_id(stream_id) {
+                Some(parent_id) => parent_id,
+                None => invalid_data!(
+                    "Object {:?} is not in the directory tree",
+                    internal::path::path_from_name_chain(&names)
+                ),
+            };
+            let path = internal::path::path_from_name_chain(&names[..depth]);
+            ancestors.push(Entry::new(&minialloc, stream_id, path));
+        }
+        debug_assert_eq!(stream_id, consts::ROOT_STREAM_ID);
+        Ok(ancestors.into_iter())
     }
 
     /// Returns an iterator over the entries within the root storage object.
@@ -176,10 +885,7 @@ impl<F> CompoundFile<F> {
     ) -> io::Result<Entries<'_, F>> {
         let names = internal::path::name_chain_from_path(path)?;
         let path = internal::path::path_from_name_chain(&names);
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!("No such storage: {:?}", path),
-        };
+        let stream_id = self.resolve_name_chain(&names, "storage")?;
         let start = {
             let minialloc = self.minialloc();
             let dir_entry = minialloc.dir_entry(stream_id);
@@ -228,13 +934,7 @@ impl<F> CompoundFile<F> {
         path: &Path,
     ) -> io::Result<Entries<'_, F>> {
         let mut names = internal::path::name_chain_from_path(path)?;
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!(
-                "No such object: {:?}",
-                internal::path::path_from_name_chain(&names)
-            ),
-        };
+        let stream_id = self.resolve_name_chain(&names, "object")?;
         names.pop();
         let parent_path = internal::path::path_from_name_chain(&names);
         Ok(Entries::new(
@@ -245,50 +945,118 @@ impl<F> CompoundFile<F> {
         ))
     }
 
+    /// Returns a copy of the metadata of every object in the compound file
+    /// (its tree of storages and streams, and each object's CLSID, state
+    /// bits, and timestamps), but not the contents of any stream, so that
+    /// it can later be put back with
+    /// [`restore_metadata`](#method.restore_metadata).  Objects are listed
+    /// in the order that [`walk`](#method.walk) visits them; temporary
+    /// objects are left out unless they are shown (see
+    /// [`set_show_temporaries`](#method.set_show_temporaries)).
+    pub fn export_metadata(&self) -> MetadataSnapshot {
+        let entries: Vec<Entry> = self.walk().collect();
+        let minialloc = self.minialloc();
+        let entries = entries
+            .iter()
+            .map(|entry| {
+                let dir_entry = minialloc.dir_entry(entry.stream_id().value());
+                SnapshotEntry::new(entry, dir_entry)
+            })
+            .collect();
+        MetadataSnapshot { entries }
+    }
+
+    /// Returns an iterator over all entries under a storage subtree,
+    /// including the storage itself, in the same order as `walk_storage`.
+    /// Each entry is paired with its path relative to the given storage
+    /// (which is empty for the storage itself), so that the same subtree
+    /// yields the same paths no matter where it is located within the file.
+    /// Returns an error if the path refers to a stream rather than a storage.
+    pub fn walk_relative<P: AsRef<Path>>(
+        &self,
+        base: P,
+    ) -> io::Result<impl FusedIterator<Item = (PathBuf, Entry)> + '_> {
+        self.walk_relative_with_path(base.as_ref())
+    }
+
+    fn walk_relative_with_path(
+        &self,
+        base: &Path,
+    ) -> io::Result<impl FusedIterator<Item = (PathBuf, Entry)> + '_> {
+        let names = internal::path::name_chain_from_path(base)?;
+        let base = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "storage")?;
+        if self.minialloc().dir_entry(stream_id).obj_type == ObjType::Stream {
+            invalid_input!("Not a storage: {:?}", base);
+        }
+        let entries = self.walk_storage_with_path(&base)?;
+        Ok(entries.map(move |entry| {
+            let relative = entry.path().strip_prefix(&base).unwrap();
+            (relative.to_path_buf(), entry)
+        }))
+    }
+
+    /// Returns an iterator over every object in the compound file, starting
+    /// with the root entry, in the order of their directory entries (that
+    /// is, by stream ID) rather than in any order of the storage tree.  Each
+    /// object is yielded exactly once, with its full path, and temporary
+    /// objects are hidden just as with [`walk`](#method.walk), so this
+    /// yields the same entries as `walk()`, in a different order.
+    ///
+    /// Since it never chases the tree's sibling and child links, this is the
+    /// better choice for whole-file metadata scans where the order doesn't
+    /// matter: the directory is visited front to back, and each path is
+    /// built from its parent's in the same pass.
+    pub fn iter_entries_physical(
+        &self,
+    ) -> impl FusedIterator<Item = Entry> + '_ {
+        let paths = self.minialloc().paths_by_stream_id();
+        paths.into_iter().enumerate().filter_map(move |(stream_id, path)| {
+            Some(Entry::new(&self.minialloc(), stream_id as u32, path?))
+        })
+    }
+
     /// Returns true if there is an existing stream or storage at the given
     /// path, or false if there is nothing at that path.
     pub fn exists<P: AsRef<Path>>(&self, path: P) -> bool {
-        match internal::path::name_chain_from_path(path.as_ref()) {
-            Ok(names) => self.stream_id_for_name_chain(&names).is_some(),
-            Err(_) => false,
-        }
+        self.obj_type_with_path(path.as_ref()).is_some()
     }
 
     /// Returns true if there is an existing stream at the given path, or false
     /// if there is a storage or nothing at that path.
     pub fn is_stream<P: AsRef<Path>>(&self, path: P) -> bool {
-        match internal::path::name_chain_from_path(path.as_ref()) {
-            Ok(names) => match self.stream_id_for_name_chain(&names) {
-                Some(stream_id) => {
-                    self.minialloc().dir_entry(stream_id).obj_type
-                        == ObjType::Stream
-                }
-                None => false,
-            },
-            Err(_) => false,
-        }
+        self.obj_type_with_path(path.as_ref()) == Some(ObjType::Stream)
     }
 
     /// Returns true if there is an existing storage at the given path, or
     /// false if there is a stream or nothing at that path.
     pub fn is_storage<P: AsRef<Path>>(&self, path: P) -> bool {
-        match internal::path::name_chain_from_path(path.as_ref()) {
-            Ok(names) => match self.stream_id_for_name_chain(&names) {
-                Some(stream_id) => {
-                    self.minialloc().dir_entry(stream_id).obj_type
-                        != ObjType::Stream
-                }
-                None => false,
-            },
-            Err(_) => false,
-        }
+        matches!(
+            self.obj_type_with_path(path.as_ref()),
+            Some(ObjType::Storage | ObjType::Root)
+        )
     }
 
-    // TODO: pub fn copy_stream
+    /// Returns the type of the object at the given path, or `None` if the
+    /// path is invalid or there is nothing there (including if the path
+    /// continues past a stream).
+    fn obj_type_with_path(&self, path: &Path) -> Option<ObjType> {
+        let names = internal::path::name_chain_from_path(path).ok()?;
+        let minialloc = self.minialloc();
+        let stream_id = minialloc.stream_id_for_name_chain(&names)?;
+        Some(minialloc.dir_entry(stream_id).obj_type)
+    }
 
-    // TODO: pub fn rename
+    // TODO: pub fn copy_stream
 
     /// Consumes the `CompoundFile`, returning the underlying reader/writer.
+    ///
+    /// This doesn't flush anything, and can't fail, so data still buffered
+    /// in open [`Stream`] handles (see
+    /// [`unflushed_streams`](#method.unflushed_streams)) is not in the
+    /// returned file, and can no longer reach it.  Call
+    /// [`flush`](#method.flush) first, which fails with [`StreamsStillOpen`]
+    /// if any handles still hold such data.
     pub fn into_inner(self) -> F {
         // We only ever retain Weak copies of the CompoundFile's minialloc Rc
         // (e.g. in Stream structs), so the Rc::try_unwrap() should always
@@ -298,6 +1066,42 @@ impl<F> CompoundFile<F> {
             Err(_) => unreachable!(),
         }
     }
+
+    /// Returns the optional capabilities of the underlying reader/writer,
+    /// as far as the constructor that opened or created this file could
+    /// tell.  Methods that need a capability the file lacks fail with an
+    /// [`Unsupported`] error naming it, without changing anything.
+    ///
+    /// The path-based constructors ([`open`](fn.open.html),
+    /// [`open_rw`](fn.open_rw.html), and [`create`](fn.create.html)) know
+    /// the most about their files.  Files created with
+    /// [`CompoundFile::create`](#method.create) (or opened with
+    /// [`open_from_reader`](fn.open_from_reader.html)) are known to be
+    /// writable; files opened with [`CompoundFile::open`](#method.open)
+    /// from an arbitrary reader report no capabilities, even if the reader
+    /// is in fact writable.
+    pub fn capabilities(&self) -> Capabilities {
+        self.backing.capabilities()
+    }
+
+    /// Returns the path this file was opened or created at, if it has one
+    /// (that is, if it has the
+    /// [`PERSISTENT_PATH`](struct.Capabilities.html#associatedconstant.PERSISTENT_PATH)
+    /// capability).  The path is as it was given to the constructor.
+    pub fn path(&self) -> Option<&Path> {
+        self.backing.path.as_deref()
+    }
+
+    /// Records what the constructor knows about the underlying file.
+    fn with_backing(self, backing: Backing<F>) -> CompoundFile<F> {
+        if backing.read_only {
+            self.minialloc
+                .write()
+                .unwrap()
+                .set_read_only(ReadOnly::NotWritable);
+        }
+        CompoundFile { backing, ..self }
+    }
 }
 
 impl<F: Seek> CompoundFile<F> {
@@ -310,18 +1114,101 @@ impl<F: Seek> CompoundFile<F> {
         self.open_stream_with_path(path.as_ref())
     }
 
-    fn open_stream_with_path(&mut self, path: &Path) -> io::Result<Stream<F>> {
+    /// Opens an existing stream in the compound file for reading everything
+    /// in its sector chain, ignoring its declared length.  This is a
+    /// diagnostic API, for recovery and forensics: the returned stream's
+    /// length is the chain's capacity (see
+    /// [`Entry::chain_len`](crate::Entry::chain_len)), and everything past
+    /// the declared length ([`Entry::len`](crate::Entry::len)) is slack,
+    /// which may hold leftovers of earlier contents, garbage, or data hidden
+    /// there on purpose.  For a stream in the mini stream, this is the chain
+    /// of mini sectors.  The length is fixed when the stream is opened, and
+    /// the stream is read-only: writing to it or resizing it fails with a
+    /// `PermissionDenied` error.
+    ///
+    /// Returns an error if the stream's chain is malformed (for example, if
+    /// it contains a loop).
+    pub fn open_stream_full_chain<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<Stream<F>> {
+        let path = path.as_ref();
         let names = internal::path::name_chain_from_path(path)?;
         let path = internal::path::path_from_name_chain(&names);
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!("No such stream: {:?}", path),
-        };
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
         if self.minialloc().dir_entry(stream_id).obj_type != ObjType::Stream {
             invalid_input!("Not a stream: {:?}", path);
         }
+        Stream::new_full_chain(&self.minialloc, stream_id)
+    }
+
+    /// Opens an existing stream in the compound file for reading only.
+    /// Since this only needs a shared reference to the compound file, any
+    /// number of readers (for the same stream or for different ones) can be
+    /// open at once, each with its own position, and reads through them can
+    /// be freely interleaved.  The compound file can't be modified until
+    /// they have all been dropped.
+    pub fn open_stream_reader<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> io::Result<StreamReader<'_, F>> {
+        let stream = self.open_stream_with_path(path.as_ref())?;
+        Ok(StreamReader::new(stream))
+    }
+
+    /// Like [`open_stream`](#method.open_stream), but for a path that has
+    /// already been [resolved](#method.resolve).
+    pub fn open_stream_resolved(
+        &mut self,
+        resolved: &ResolvedPath,
+    ) -> io::Result<Stream<F>> {
+        let timer = self.minialloc().metrics().start();
+        let stream_id = self.resolved_stream_id(resolved, "stream")?;
+        self.open_stream_with_id(stream_id, resolved.path(), timer)
+    }
+
+    fn open_stream_with_path(&self, path: &Path) -> io::Result<Stream<F>> {
+        let timer = self.minialloc().metrics().start();
+        let names = internal::path::name_chain_from_path(path)?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        self.open_stream_with_id(stream_id, &path, timer)
+    }
+
+    fn open_stream_with_id(
+        &self,
+        stream_id: u32,
+        path: &Path,
+        timer: Timer,
+    ) -> io::Result<Stream<F>> {
+        let minialloc = self.minialloc();
+        let dir_entry = minialloc.dir_entry(stream_id);
+        if dir_entry.obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path);
+        }
+        minialloc.metrics().record(
+            Op::OpenStream,
+            timer,
+            dir_entry.stream_len,
+        );
+        drop(minialloc);
         Ok(Stream::new(&self.minialloc, stream_id))
     }
+
+    /// Returns a breakdown of every byte that the FAT describes by whether
+    /// anything can still reach it: stream data reachable from the root
+    /// storage, slack past the end of that data, orphaned (allocated but
+    /// unused) sectors, free space, and the file's own structures.  The
+    /// categories add up to the length that the FAT describes, which is
+    /// compared separately with the actual length of the underlying file, so
+    /// that truncated files and files with extra data at the end can be told
+    /// apart.
+    ///
+    /// Data that is still buffered in open streams isn't accounted for until
+    /// those streams are flushed.
+    pub fn reachability(&mut self) -> io::Result<Reachability> {
+        self.minialloc_mut().reachability()
+    }
 }
 
 impl<F: Read + Seek> CompoundFile<F> {
@@ -329,7 +1216,7 @@ impl<F: Read + Seek> CompoundFile<F> {
     /// underlying reader also supports the `Write` trait, then the
     /// `CompoundFile` object will be writable as well.
     pub fn open(inner: F) -> io::Result<CompoundFile<F>> {
-        CompoundFile::open_internal(inner, Validation::Permissive)
+        CompoundFile::open_internal(inner, Validation::Permissive, None)
     }
 
     /// Like `open()`, but is stricter when parsing and will return an error if
@@ -338,13 +1225,54 @@ impl<F: Read + Seek> CompoundFile<F> {
     /// implemention (such as this crate itself) to help ensure compatibility
     /// with other readers.
     pub fn open_strict(inner: F) -> io::Result<CompoundFile<F>> {
-        CompoundFile::open_internal(inner, Validation::Strict)
+        CompoundFile::open_internal(inner, Validation::Strict, None)
+    }
+
+    /// Like `open()`, but salvages as much as it can from a damaged file
+    /// (for example, one that was truncated, or whose FAT or directory is
+    /// partly overwritten) instead of refusing it.  Along with the file,
+    /// returns a description of each piece of damage that was worked around.
+    ///
+    /// A chain that is broken or that loops is ended at its last good
+    /// sector; a FAT sector that can't be read is treated as describing only
+    /// unallocated sectors; a directory entry that can't be parsed is
+    /// treated as unallocated; and a sibling or child link that can't be
+    /// followed is ignored, so that whatever else is reachable from the root
+    /// can still be read.  A stream whose chain holds less than its declared
+    /// length reports the salvaged length instead (as its
+    /// [`Entry::len`](struct.Entry.html#method.len) and
+    /// [`Stream::len`](struct.Stream.html#method.len)), and reads that many
+    /// bytes.
+    ///
+    /// The repairs are made only in memory, so the returned file can't be
+    /// modified: any attempt to write to it fails with
+    /// `ErrorKind::PermissionDenied`.  To keep what was salvaged, copy it
+    /// into a new file (for example, with
+    /// [`copy_storage_from`](#method.copy_storage_from)).  Damage to the
+    /// header, or to the root directory entry, still can't be recovered from.
+    pub fn open_recover(
+        inner: F,
+    ) -> io::Result<(CompoundFile<F>, Vec<RecoveryWarning>)> {
+        let mut warnings = Vec::new();
+        let comp = CompoundFile::open_internal(
+            inner,
+            Validation::Permissive,
+            Some(&mut warnings),
+        )?;
+        Ok((comp, warnings))
     }
 
+    /// Opens the file with the given validation.  If `recovery` is given,
+    /// damage that would otherwise make opening fail is worked around where
+    /// possible, and described there instead (see `open_recover`).
     fn open_internal(
         mut inner: F,
         validation: Validation,
+        recovery: Option<&mut Vec<RecoveryWarning>>,
     ) -> io::Result<CompoundFile<F>> {
+        let open_timer = Timer::now();
+        let recovering = recovery.is_some();
+        let mut warnings = Vec::<RecoveryWarning>::new();
         let inner_len = inner.seek(SeekFrom::End(0))?;
         if inner_len < consts::HEADER_LEN as u64 {
             invalid_data!(
@@ -353,9 +1281,14 @@ impl<F: Read + Seek> CompoundFile<F> {
             );
         }
         inner.seek(SeekFrom::Start(0))?;
+        let mut issues = Vec::new();
 
         // 2.2 Compound File Header
-        let header = Header::read_from(&mut inner, validation)?;
+        let mut header = Header::read_from(
+            &mut ioutil::Retrying(&mut inner),
+            validation,
+            &mut issues,
+        )?;
         // Major Version
         let sector_len = header.version.sector_len();
         if inner_len
@@ -382,24 +1315,49 @@ impl<F: Read + Seek> CompoundFile<F> {
         difat.extend_from_slice(&header.initial_difat_entries);
         let mut seen_sector_ids = FnvHashSet::default();
         let mut difat_sector_ids = Vec::new();
-        let mut current_difat_sector = header.first_difat_sector;
-        while current_difat_sector != consts::END_OF_CHAIN
-            && current_difat_sector != consts::FREE_SECTOR
+        // A file with no DIFAT sectors may give FREE_SECTOR rather than
+        // END_OF_CHAIN as the start of the (empty) DIFAT chain.
+        let mut current_difat_sector = if header.first_difat_sector
+            == consts::FREE_SECTOR
         {
-            if current_difat_sector > consts::MAX_REGULAR_SECTOR {
-                invalid_data!(
-                    "DIFAT chain includes invalid sector index {}",
-                    current_difat_sector
-                );
-            } else if current_difat_sector >= num_sectors {
-                invalid_data!(
-                    "DIFAT chain includes sector index {}, but sector count \
-                     is only {}",
-                    current_difat_sector,
-                    num_sectors
-                );
+            consts::END_OF_CHAIN
+        } else {
+            match next_in_chain(
+                ChainName::Difat,
+                0,
+                header.first_difat_sector,
+                num_sectors as usize,
+            ) {
+                Ok(sector_id) => sector_id,
+                Err(error) if recovering => {
+                    warnings.push(
+                        RecoveryWarning::new(
+                            RecoveryWarningKind::FatSectorMissing,
+                            format!("{}; the DIFAT chain was ignored", error),
+                        )
+                        .with_sector_id(header.first_difat_sector),
+                    );
+                    consts::END_OF_CHAIN
+                }
+                Err(error) => return Err(error),
             }
+        };
+        'difat: while current_difat_sector != consts::END_OF_CHAIN {
             if seen_sector_ids.contains(&current_difat_sector) {
+                if recovering {
+                    warnings.push(
+                        RecoveryWarning::new(
+                            RecoveryWarningKind::ChainLoop,
+                            format!(
+                                "The DIFAT chain loops back to sector {}, \
+                                 and was ended there",
+                                current_difat_sector
+                            ),
+                        )
+                        .with_sector_id(current_difat_sector),
+                    );
+                    break;
+                }
                 invalid_data!(
                     "DIFAT chain includes duplicate sector index {}",
                     current_difat_sector,
@@ -407,12 +1365,51 @@ impl<F: Read + Seek> CompoundFile<F> {
             }
             seen_sector_ids.insert(current_difat_sector);
             difat_sector_ids.push(current_difat_sector);
-            let mut sector = sectors.seek_to_sector(current_difat_sector)?;
-            for _ in 0..(sector_len / size_of::<u32>() - 1) {
-                let next = sector.read_le_u32()?;
+            let num_entries = header.version.difat_entries_per_sector();
+            try_reserve(&mut difat, num_entries, "the DIFAT")?;
+            let mut entries = match read_sector_u32s(
+                &mut sectors,
+                current_difat_sector,
+                num_entries + 1,
+            ) {
+                Ok(entries) => entries,
+                Err(error) if recovering => {
+                    warnings.push(
+                        RecoveryWarning::new(
+                            RecoveryWarningKind::FatSectorMissing,
+                            format!(
+                                "DIFAT sector {} couldn't be read ({}); the \
+                                 FAT sectors it lists were ignored",
+                                current_difat_sector, error
+                            ),
+                        )
+                        .with_sector_id(current_difat_sector),
+                    );
+                    difat_sector_ids.pop();
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
+            let next_difat_sector = entries.pop().unwrap();
+            for next in entries {
                 if next != consts::FREE_SECTOR
                     && next > consts::MAX_REGULAR_SECTOR
                 {
+                    if recovering {
+                        warnings.push(
+                            RecoveryWarning::new(
+                                RecoveryWarningKind::FatSectorMissing,
+                                format!(
+                                    "DIFAT refers to invalid sector index \
+                                     {}; it and the FAT sectors after it \
+                                     were ignored",
+                                    next
+                                ),
+                            )
+                            .with_sector_id(next),
+                        );
+                        break 'difat;
+                    }
                     invalid_data!(
                         "DIFAT refers to invalid sector index {}",
                         next
@@ -420,25 +1417,65 @@ impl<F: Read + Seek> CompoundFile<F> {
                 }
                 difat.push(next);
             }
-            current_difat_sector = sector.read_le_u32()?;
-            if validation.is_strict()
-                && current_difat_sector == consts::FREE_SECTOR
-            {
+            if next_difat_sector == consts::FREE_SECTOR {
+                if validation.is_strict() {
+                    invalid_data!(
+                        "DIFAT chain must terminate with {}, not {}",
+                        consts::END_OF_CHAIN,
+                        consts::FREE_SECTOR
+                    );
+                }
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::DifatChainTerminator,
+                    format!(
+                        "DIFAT chain terminates with {}, not {}",
+                        consts::FREE_SECTOR,
+                        consts::END_OF_CHAIN
+                    ),
+                ));
+                break;
+            }
+            current_difat_sector = match next_in_chain(
+                ChainName::Difat,
+                difat_sector_ids.len(),
+                next_difat_sector,
+                num_sectors as usize,
+            ) {
+                Ok(sector_id) => sector_id,
+                Err(error) if recovering => {
+                    warnings.push(
+                        RecoveryWarning::new(
+                            RecoveryWarningKind::ChainBroken,
+                            format!(
+                                "{}; the DIFAT chain was ended at sector {}",
+                                error, current_difat_sector
+                            ),
+                        )
+                        .with_sector_id(current_difat_sector),
+                    );
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
+        }
+        if header.num_difat_sectors as usize != difat_sector_ids.len() {
+            if validation.is_strict() {
                 invalid_data!(
-                    "DIFAT chain must terminate with {}, not {}",
-                    consts::END_OF_CHAIN,
-                    consts::FREE_SECTOR
+                    "Incorrect DIFAT chain length (header says {}, actual is \
+                     {})",
+                    header.num_difat_sectors,
+                    difat_sector_ids.len()
                 );
             }
-        }
-        if validation.is_strict()
-            && header.num_difat_sectors as usize != difat_sector_ids.len()
-        {
-            invalid_data!(
-                "Incorrect DIFAT chain length (header says {}, actual is {})",
-                header.num_difat_sectors,
-                difat_sector_ids.len()
-            );
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::DifatSectorCount,
+                format!(
+                    "Header says there are {} DIFAT sectors, but the chain has \
+                     {}",
+                    header.num_difat_sectors,
+                    difat_sector_ids.len()
+                ),
+            ));
         }
         // The DIFAT should be padded with FREE_SECTOR, but DIFAT sectors
         // may instead instead be incorrectly zero padded (see
@@ -446,45 +1483,95 @@ impl<F: Read + Seek> CompoundFile<F> {
         // In case num_fat_sectors is not reliable, only remove zeroes,
         // and don't remove sectors from the header DIFAT.
         if !validation.is_strict() {
+            let difat_len = difat.len();
             while difat.len() > consts::NUM_DIFAT_ENTRIES_IN_HEADER
                 && difat.len() > header.num_fat_sectors as usize
                 && difat.last() == Some(&0)
             {
                 difat.pop();
             }
+            if difat.len() < difat_len {
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::DifatZeroPadding,
+                    format!(
+                        "DIFAT is padded with {} zero entries",
+                        difat_len - difat.len()
+                    ),
+                ));
+            }
         }
         while difat.last() == Some(&consts::FREE_SECTOR) {
             difat.pop();
         }
-        if validation.is_strict()
-            && header.num_fat_sectors as usize != difat.len()
-        {
-            invalid_data!(
-                "Incorrect number of FAT sectors (header says {}, DIFAT says \
-                 {})",
-                header.num_fat_sectors,
-                difat.len()
-            );
-        }
-
-        // Read in FAT.
-        let mut fat = Vec::<u32>::new();
-        for &sector_index in difat.iter() {
-            if sector_index >= num_sectors {
+        if header.num_fat_sectors as usize != difat.len() {
+            if validation.is_strict() {
                 invalid_data!(
-                    "DIFAT refers to sector {}, but sector count is only {}",
-                    sector_index,
-                    num_sectors
+                    "Incorrect number of FAT sectors (header says {}, DIFAT \
+                     says {})",
+                    header.num_fat_sectors,
+                    difat.len()
                 );
             }
-            let mut sector = sectors.seek_to_sector(sector_index)?;
-            for _ in 0..(sector_len / size_of::<u32>()) {
-                fat.push(sector.read_le_u32()?);
-            }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::FatSectorCount,
+                format!(
+                    "Header says there are {} FAT sectors, but the DIFAT lists \
+                     {}",
+                    header.num_fat_sectors,
+                    difat.len()
+                ),
+            ));
         }
-        // If the number of sectors in the file is not a multiple of the number
-        // of FAT entries per sector, then the last FAT sector must be padded
-        // with FREE_SECTOR entries (see MS-CFB section 2.3).  However, some
+
+        // Read in FAT.  Since the DIFAT may list the same sector many times,
+        // this can be much larger than the file.
+        let fat_len = difat
+            .len()
+            .saturating_mul(header.version.fat_entries_per_sector())
+            .max(num_sectors as usize);
+        let mut fat = try_vec_with_capacity::<u32>(fat_len, "the FAT")?;
+        for (index, &sector_index) in difat.iter().enumerate() {
+            let result = if sector_index >= num_sectors {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "DIFAT refers to sector {}, but sector count is only \
+                         {}",
+                        sector_index, num_sectors
+                    ),
+                ))
+            } else {
+                read_sector_u32s(
+                    &mut sectors,
+                    sector_index,
+                    header.version.fat_entries_per_sector(),
+                )
+            };
+            match result {
+                Ok(entries) => fat.extend(entries),
+                Err(error) if recovering => {
+                    warnings.push(
+                        RecoveryWarning::new(
+                            RecoveryWarningKind::FatSectorMissing,
+                            format!(
+                                "FAT sector {} couldn't be read ({}); it and \
+                                 the {} FAT sectors after it were ignored",
+                                sector_index,
+                                error,
+                                difat.len() - index - 1
+                            ),
+                        )
+                        .with_sector_id(sector_index),
+                    );
+                    difat.truncate(index);
+                    break;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        // If the number of sectors in the file is not a multiple of the number
+        // of FAT entries per sector, then the last FAT sector must be padded
+        // with FREE_SECTOR entries (see MS-CFB section 2.3).  However, some
         // CFB implementations incorrectly pad the last FAT sector with zeros
         // (see https://github.com/mdsteele/rust-cfb/issues/8), so we allow
         // this under Permissive validation.  Since zero is normally a
@@ -494,17 +1581,29 @@ impl<F: Read + Seek> CompoundFile<F> {
         // Files have been seen with erroneous other types of sectors beyond
         // EOF, so strip those as well.
         if !validation.is_strict() {
+            let mut num_stripped = 0;
             while fat.len() > num_sectors as usize {
                 if fat.last() == Some(&0)
                     || fat.last() == Some(&consts::DIFAT_SECTOR)
                     || fat.last() == Some(&consts::FAT_SECTOR)
-                    || fat.last() == Some(&consts::FREE_SECTOR)
                 {
+                    num_stripped += 1;
+                    fat.pop();
+                } else if fat.last() == Some(&consts::FREE_SECTOR) {
                     fat.pop();
                 } else {
                     break;
                 }
             }
+            if num_stripped > 0 {
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::FatEntriesPastEof,
+                    format!(
+                        "FAT has {} non-free entries past the end of the file",
+                        num_stripped
+                    ),
+                ));
+            }
         }
         // Strip FREE_SECTOR entries from the end of the FAT.
         while fat.len() > num_sectors as usize
@@ -512,41 +1611,122 @@ impl<F: Read + Seek> CompoundFile<F> {
         {
             fat.pop();
         }
-        while fat.len() < num_sectors as usize {
+        // The FAT can describe no more sectors than fit in the FAT sectors
+        // the DIFAT lists; any sectors in the file beyond that are a stale
+        // tail that no FAT entry covers.
+        let fat_capacity = difat
+            .len()
+            .saturating_mul(header.version.fat_entries_per_sector());
+        let num_described = (num_sectors as usize).min(fat_capacity);
+        if num_described < num_sectors as usize {
+            if validation.is_strict() {
+                invalid_data!(
+                    "File has {} sectors, but its {} FAT sectors can only \
+                     describe {}",
+                    num_sectors,
+                    difat.len(),
+                    fat_capacity
+                );
+            }
+            issues.push(ValidationIssue::new(
+                ValidationIssueKind::SectorsPastFat,
+                format!(
+                    "File has {} sectors, but its {} FAT sectors can only \
+                     describe {}; the rest were ignored",
+                    num_sectors,
+                    difat.len(),
+                    fat_capacity
+                ),
+            ));
+        }
+        while fat.len() < num_described {
             fat.push(consts::FREE_SECTOR);
         }
+        if recovering {
+            // A sector cut short by the end of the file can't be read in
+            // full, so treat it (and anything past it) as unallocated.
+            let num_whole_sectors = (inner_len / sector_len as u64 - 1) as u32;
+            let partial = fat.get(num_whole_sectors as usize);
+            if partial.is_some_and(|&next| next != consts::FREE_SECTOR) {
+                warnings.push(
+                    RecoveryWarning::new(
+                        RecoveryWarningKind::UnreadableSector,
+                        format!(
+                            "Sector {} is cut short by the end of the file, \
+                             and was treated as unallocated",
+                            num_whole_sectors
+                        ),
+                    )
+                    .with_sector_id(num_whole_sectors),
+                );
+            }
+            fat.truncate(num_whole_sectors as usize);
+            internal::recover_links(&mut fat, "FAT", &mut warnings);
+        }
+
+        let validate_timer = Timer::now();
+        let mut allocator = Allocator::new(
+            sectors,
+            difat_sector_ids,
+            difat,
+            fat,
+            validation,
+            &mut issues,
+        )?;
+        let mut validate_span = validate_timer.stop();
 
-        let mut allocator =
-            Allocator::new(sectors, difat_sector_ids, difat, fat, validation)?;
+        // When recovering, cut the directory and MiniFAT chains short where
+        // they're damaged, so that they can be followed below.
+        if recovering {
+            let (sector_ids, warning) = allocator
+                .salvage_chain(header.first_dir_sector, ChainName::Directory);
+            warnings.extend(warning);
+            if sector_ids.is_empty() {
+                invalid_data!(
+                    "Directory chain is damaged beyond recovery (it starts \
+                     at sector {})",
+                    header.first_dir_sector
+                );
+            }
+            let (sector_ids, warning) = allocator.salvage_chain(
+                header.first_minifat_sector,
+                ChainName::MiniFat,
+            );
+            warnings.extend(warning);
+            if sector_ids.is_empty() {
+                header.first_minifat_sector = consts::END_OF_CHAIN;
+            }
+        }
 
         // Read in directory.
         let mut dir_entries = Vec::<DirEntry>::new();
         let mut seen_dir_sectors = FnvHashSet::default();
-        let mut current_dir_sector = header.first_dir_sector;
+        let mut current_dir_sector = next_in_chain(
+            ChainName::Directory,
+            0,
+            header.first_dir_sector,
+            num_sectors as usize,
+        )?;
         let mut dir_sector_count = 1;
         while current_dir_sector != consts::END_OF_CHAIN {
-            if validation.is_strict()
-                && header.version == Version::V4
-                && dir_sector_count > header.num_dir_sectors
+            if header.version.requires_dir_sector_count()
+                && dir_sector_count as u64 == header.num_dir_sectors as u64 + 1
             {
-                invalid_data!(
-                    "Directory chain includes at least {} sectors which is greater than header num_dir_sectors {}",
-                    dir_sector_count,
-                    header.num_dir_sectors
-                );
-            }
-            if current_dir_sector > consts::MAX_REGULAR_SECTOR {
-                invalid_data!(
-                    "Directory chain includes invalid sector index {}",
-                    current_dir_sector
-                );
-            } else if current_dir_sector >= num_sectors {
-                invalid_data!(
-                    "Directory chain includes sector index {}, but sector \
-                     count is only {}",
-                    current_dir_sector,
-                    num_sectors
-                );
+                if validation.is_strict() {
+                    invalid_data!(
+                        "Directory chain includes at least {} sectors which is greater than header num_dir_sectors {}",
+                        dir_sector_count,
+                        header.num_dir_sectors
+                    );
+                }
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::DirSectorCount,
+                    format!(
+                        "Directory chain has more than the {} sectors the \
+                         header says it has",
+                        header.num_dir_sectors
+                    ),
+                ));
             }
             if seen_dir_sectors.contains(&current_dir_sector) {
                 invalid_data!(
@@ -555,46 +1735,153 @@ impl<F: Read + Seek> CompoundFile<F> {
                 );
             }
             seen_dir_sectors.insert(current_dir_sector);
-            {
-                let mut sector =
-                    allocator.seek_to_sector(current_dir_sector)?;
-                for _ in 0..header.version.dir_entries_per_sector() {
-                    dir_entries.push(DirEntry::read_from(
-                        &mut sector,
+            let num_entries = header.version.dir_entries_per_sector();
+            try_reserve(&mut dir_entries, num_entries, "the directory")?;
+            let mut buffer = vec![0u8; num_entries * consts::DIR_ENTRY_LEN];
+            let result = allocator
+                .seek_to_sector(current_dir_sector)
+                .and_then(|mut sector| sector.read_exact(&mut buffer));
+            let readable = match result {
+                Ok(()) => true,
+                Err(error) if recovering => {
+                    // Keep the sector in the chain, so that the directory
+                    // still lines up with it, but treat it as empty.
+                    warnings.push(
+                        RecoveryWarning::new(
+                            RecoveryWarningKind::UnreadableSector,
+                            format!(
+                                "Directory sector {} couldn't be read ({}); \
+                                 its entries were treated as unallocated",
+                                current_dir_sector, error
+                            ),
+                        )
+                        .with_sector_id(current_dir_sector),
+                    );
+                    for _ in 0..num_entries {
+                        dir_entries.push(DirEntry::unallocated());
+                    }
+                    false
+                }
+                Err(error) => return Err(error),
+            };
+            if readable {
+                for raw in buffer.chunks_exact(consts::DIR_ENTRY_LEN) {
+                    let stream_id = dir_entries.len() as u32;
+                    let num_issues = issues.len();
+                    let result = DirEntry::read_from(
+                        &mut &raw[..],
                         header.version,
                         validation,
-                    )?);
+                        &mut issues,
+                    );
+                    for issue in issues[num_issues..].iter_mut() {
+                        issue.set_stream_id(stream_id);
+                    }
+                    let dir_entry = match result {
+                        Ok(dir_entry) => dir_entry,
+                        Err(error)
+                            if !validation.is_strict()
+                                && error.kind()
+                                    == io::ErrorKind::InvalidData =>
+                        {
+                            // If nothing in the tree refers to this entry,
+                            // it doesn't matter what's in it; if something
+                            // does, validating the tree will fail.
+                            issues.push(
+                                ValidationIssue::new(
+                                    ValidationIssueKind::UnparseableDirEntry,
+                                    format!(
+                                        "Directory entry {} couldn't be \
+                                         parsed ({}), and was treated as \
+                                         unallocated",
+                                        stream_id, error
+                                    ),
+                                )
+                                .with_stream_id(stream_id),
+                            );
+                            if recovering {
+                                warnings.push(
+                                    RecoveryWarning::new(
+                                        RecoveryWarningKind::UnreadableDirEntry,
+                                        format!(
+                                            "Directory entry {} couldn't be \
+                                             parsed ({}), and was treated as \
+                                             unallocated",
+                                            stream_id, error
+                                        ),
+                                    )
+                                    .with_stream_id(stream_id),
+                                );
+                            }
+                            DirEntry::unallocated()
+                        }
+                        Err(error) => return Err(error),
+                    };
+                    dir_entries.push(dir_entry);
                 }
             }
-            current_dir_sector = allocator.next(current_dir_sector)?;
+            current_dir_sector = allocator.next(
+                current_dir_sector,
+                ChainName::Directory,
+                dir_sector_count - 1,
+            )?;
             dir_sector_count += 1;
         }
 
-        let mut directory = Directory::new(
-            allocator,
-            dir_entries,
-            header.first_dir_sector,
-            validation,
-        )?;
-
         // Read in MiniFAT.
-        let minifat = {
-            let mut chain = directory
+        let mut minifat = {
+            allocator.chain_sector_ids(
+                header.first_minifat_sector,
+                ChainName::MiniFat,
+            )?;
+            let mut chain = allocator
                 .open_chain(header.first_minifat_sector, SectorInit::Fat)?;
-            if validation.is_strict()
-                && header.num_minifat_sectors as usize != chain.num_sectors()
-            {
-                invalid_data!(
-                    "Incorrect MiniFAT chain length (header says {}, actual \
-                     is {})",
-                    header.num_minifat_sectors,
-                    chain.num_sectors()
-                );
+            if header.num_minifat_sectors as usize != chain.num_sectors() {
+                if validation.is_strict() {
+                    invalid_data!(
+                        "Incorrect MiniFAT chain length (header says {}, \
+                         actual is {})",
+                        header.num_minifat_sectors,
+                        chain.num_sectors()
+                    );
+                }
+                issues.push(ValidationIssue::new(
+                    ValidationIssueKind::MiniFatSectorCount,
+                    format!(
+                        "Header says there are {} MiniFAT sectors, but the \
+                         chain has {}",
+                        header.num_minifat_sectors,
+                        chain.num_sectors()
+                    ),
+                ));
             }
             let num_minifat_entries = (chain.len() / 4) as usize;
-            let mut minifat = Vec::<u32>::with_capacity(num_minifat_entries);
+            let mut minifat = try_vec_with_capacity::<u32>(
+                num_minifat_entries,
+                "the MiniFAT",
+            )?;
             for _ in 0..num_minifat_entries {
-                minifat.push(chain.read_le_u32()?);
+                match chain.read_le_u32() {
+                    Ok(entry) => minifat.push(entry),
+                    Err(error) if recovering => {
+                        warnings.push(RecoveryWarning::new(
+                            RecoveryWarningKind::UnreadableSector,
+                            format!(
+                                "The MiniFAT couldn't be read past entry {} \
+                                 ({}); the rest of it was ignored",
+                                minifat.len(),
+                                error
+                            ),
+                        ));
+                        // Drop any entries read from the unreadable sector.
+                        let entries_per_sector =
+                            header.version.fat_entries_per_sector();
+                        let len = minifat.len();
+                        minifat.truncate(len - len % entries_per_sector);
+                        break;
+                    }
+                    Err(error) => return Err(error),
+                }
             }
             while minifat.last() == Some(&consts::FREE_SECTOR) {
                 minifat.pop();
@@ -602,421 +1889,3441 @@ impl<F: Read + Seek> CompoundFile<F> {
             minifat
         };
 
-        let minialloc = MiniAllocator::new(
+        if recovering {
+            internal::recover_tree(&mut dir_entries, &mut warnings)?;
+            internal::recover_chains(
+                &mut allocator,
+                &mut minifat,
+                &mut dir_entries,
+                1 << header.mini_sector_shift,
+                &mut warnings,
+            );
+        }
+
+        let validate_timer = Timer::now();
+        let directory = Directory::new(
+            allocator,
+            dir_entries,
+            header.first_dir_sector,
+            validation,
+            &mut issues,
+        )?;
+        validate_span += validate_timer.stop();
+
+        let validate_timer = Timer::now();
+        let mut minialloc = MiniAllocator::new(
             directory,
             minifat,
             header.first_minifat_sector,
+            header.mini_sector_shift,
             validation,
+            &mut issues,
         )?;
+        validate_span += validate_timer.stop();
+        minialloc.fill_issue_paths(&mut issues);
+        if let Some(recovery) = recovery {
+            minialloc.finish_recovery(&mut warnings);
+            recovery.extend(warnings);
+        }
+        let metrics = minialloc.metrics_mut();
+        metrics.defer(Op::Validate, validate_span, 0);
+        metrics.defer(Op::Open, open_timer.stop(), inner_len);
 
-        Ok(CompoundFile { minialloc: Arc::new(RwLock::new(minialloc)) })
+        let header_version_mismatch = issues.iter().any(|issue| {
+            issue.kind() == ValidationIssueKind::VersionSectorShiftMismatch
+        });
+        let mut comp = CompoundFile {
+            minialloc: Arc::new(RwLock::new(minialloc)),
+            open_warnings: issues,
+            leaked_temporaries: Vec::new(),
+            header_version_mismatch,
+            backing: Backing::unknown(),
+            clsid_policy: ClsidPolicy::default(),
+            max_buffer_len: DEFAULT_MAX_BUFFER_LEN,
+        };
+        comp.leaked_temporaries = comp
+            .walk()
+            .include_temporaries()
+            .filter(|entry| internal::path::is_temporary_name(entry.name()))
+            .map(|entry| entry.path().to_path_buf())
+            .collect();
+        for path in comp.leaked_temporaries.iter() {
+            let mut issue = ValidationIssue::new(
+                ValidationIssueKind::LeakedTemporary,
+                format!(
+                    "Found temporary object {:?} left behind by an \
+                     interrupted operation",
+                    path
+                ),
+            );
+            issue.set_path(path.clone());
+            comp.open_warnings.push(issue);
+        }
+        Ok(comp)
     }
-}
 
-impl<F: Read + Write + Seek> CompoundFile<F> {
-    /// Creates a new compound file with no contents, using the underlying
-    /// reader/writer.  The reader/writer should be initially empty.
-    pub fn create(inner: F) -> io::Result<CompoundFile<F>> {
-        CompoundFile::create_with_version(Version::V4, inner)
+    /// Checks that the whole file can be read, end to end: every stream is
+    /// read to its declared length (optionally hashing its contents), and the
+    /// FAT, MiniFAT, and directory are cross-checked against each other.  A
+    /// stream that can't be read is recorded in the report, and doesn't stop
+    /// the rest from being checked.
+    ///
+    /// Unlike opening a file (which checks its structures but doesn't read
+    /// any stream data), this touches every sector that holds stream data, so
+    /// it also catches I/O errors and truncated files.
+    ///
+    /// If the verification is stopped early (by the progress or checkpoint
+    /// function), the report has a [`ResumeToken`] that can be passed to
+    /// [`verify_deep_from`](#method.verify_deep_from) to carry on from where
+    /// it stopped.
+    pub fn verify_deep(&mut self, options: VerifyOptions) -> VerifyReport {
+        let streams: Vec<Entry> =
+            self.walk().filter(|entry| entry.is_stream()).collect();
+        self.verify_streams(streams, 0, None, options)
     }
 
-    /// Creates a new compound file of the given version with no contents,
-    /// using the underlying writer.  The writer should be initially empty.
-    pub fn create_with_version(
-        version: Version,
-        mut inner: F,
-    ) -> io::Result<CompoundFile<F>> {
-        let mut header = Header {
-            version,
-            // 2.2 requires this to be zero in V3
-            num_dir_sectors: if version == Version::V3 { 0 } else { 1 },
-            num_fat_sectors: 1,
-            first_dir_sector: 1,
-            first_minifat_sector: consts::END_OF_CHAIN,
-            num_minifat_sectors: 0,
-            first_difat_sector: consts::END_OF_CHAIN,
-            num_difat_sectors: 0,
-            initial_difat_entries: [consts::FREE_SECTOR;
-                consts::NUM_DIFAT_ENTRIES_IN_HEADER],
+    /// Carries on a verification that was stopped early, from the point
+    /// recorded in the given token: the streams up to and including the
+    /// token's [`last_done`](struct.ResumeToken.html#method.last_done) are
+    /// skipped, and the rest are verified as
+    /// [`verify_deep`](#method.verify_deep) would.  The report only includes
+    /// the streams verified in this run, but its progress counts include the
+    /// skipped streams' lengths.
+    ///
+    /// Fails with an error of kind `InvalidInput`, without reading anything,
+    /// if the file's structure has changed since the token was made, or if
+    /// the token's last stream no longer exists.
+    pub fn verify_deep_from(
+        &mut self,
+        token: &ResumeToken,
+        options: VerifyOptions,
+    ) -> io::Result<VerifyReport> {
+        let fingerprint = self.minialloc().structure_fingerprint();
+        if token.fingerprint != fingerprint {
+            invalid_input!(
+                "Resume token is for a different version of this file"
+            );
+        }
+        let streams: Vec<Entry> =
+            self.walk().filter(|entry| entry.is_stream()).collect();
+        let num_skipped = match token.last_done {
+            None => 0,
+            Some(ref last_done) => {
+                match streams
+                    .iter()
+                    .position(|entry| entry.path() == last_done)
+                {
+                    Some(index) => index + 1,
+                    None => invalid_input!(
+                        "Resume token's last stream {:?} doesn't exist",
+                        last_done
+                    ),
+                }
+            }
         };
-        header.initial_difat_entries[0] = 0;
-        header.write_to(&mut inner)?;
+        let skipped_len: u64 =
+            streams[..num_skipped].iter().map(Entry::len).sum();
+        let last_done = token.last_done.clone();
+        let streams = streams.into_iter().skip(num_skipped).collect();
+        Ok(self.verify_streams(streams, skipped_len, last_done, options))
+    }
 
-        // Pad the header with zeroes so it's the length of a sector.
-        let sector_len = version.sector_len();
-        debug_assert!(sector_len >= consts::HEADER_LEN);
-        if sector_len > consts::HEADER_LEN {
-            inner.write_all(&vec![0; sector_len - consts::HEADER_LEN])?;
+    /// Verifies the given streams, in order, for `verify_deep` and
+    /// `verify_deep_from`.  `skipped_len` is the total length of the streams
+    /// already verified by an earlier run, the last of which was
+    /// `last_done`.
+    fn verify_streams(
+        &mut self,
+        streams: Vec<Entry>,
+        skipped_len: u64,
+        mut last_done: Option<PathBuf>,
+        mut options: VerifyOptions,
+    ) -> VerifyReport {
+        let start = Instant::now();
+        let fingerprint = self.minialloc().structure_fingerprint();
+        let mut report = VerifyReport {
+            structure_problems: self.minialloc().structure_problems(),
+            ..VerifyReport::default()
+        };
+        let total_len =
+            skipped_len + streams.iter().map(Entry::len).sum::<u64>();
+        let mut buffer = vec![0u8; 0x10000];
+        'streams: for entry in streams {
+            let mut result = StreamVerification {
+                path: entry.path().to_path_buf(),
+                len: entry.len(),
+                bytes_read: 0,
+                hash: None,
+                error: None,
+            };
+            let mut hasher = fnv::FnvHasher::default();
+            match self.open_stream_with_path(entry.path()) {
+                Ok(mut stream) => {
+                    while result.bytes_read < result.len {
+                        let remaining = result.len - result.bytes_read;
+                        let chunk_len = buffer.len().min(remaining as usize);
+                        let count = match ioutil::read_retrying(
+                            &mut stream,
+                            &mut buffer[..chunk_len],
+                        ) {
+                            Ok(0) => {
+                                result.error = Some(io::Error::new(
+                                    io::ErrorKind::UnexpectedEof,
+                                    format!(
+                                        "Stream ended after {} of its {} \
+                                         bytes",
+                                        result.bytes_read, result.len
+                                    ),
+                                ));
+                                break;
+                            }
+                            Ok(count) => count,
+                            Err(error) => {
+                                result.error = Some(error);
+                                break;
+                            }
+                        };
+                        hasher.write(&buffer[..count]);
+                        result.bytes_read += count as u64;
+                        report.bytes_read += count as u64;
+                        let mut keep_going = true;
+                        if let Some(progress) = options.progress.as_mut() {
+                            keep_going = progress(
+                                skipped_len + report.bytes_read,
+                                total_len,
+                            );
+                        }
+                        let token = ResumeToken {
+                            fingerprint,
+                            last_done: last_done.clone(),
+                            offset: result.bytes_read,
+                        };
+                        if let Some(checkpoint) = options.checkpoint.as_mut() {
+                            if keep_going {
+                                keep_going = checkpoint(&token).is_continue();
+                            }
+                        }
+                        if !keep_going {
+                            report.cancelled = true;
+                            report.resume_token = Some(token);
+                            break 'streams;
+                        }
+                    }
+                }
+                Err(error) => result.error = Some(error),
+            }
+            if options.hash_streams && result.error.is_none() {
+                result.hash = Some(hasher.finish());
+            }
+            last_done = Some(result.path.clone());
+            report.streams.push(result);
+            if let Some(checkpoint) = options.checkpoint.as_mut() {
+                let token = ResumeToken {
+                    fingerprint,
+                    last_done: last_done.clone(),
+                    offset: 0,
+                };
+                if checkpoint(&token).is_break() {
+                    report.cancelled = true;
+                    report.resume_token = Some(token);
+                    break;
+                }
+            }
         }
+        report.elapsed = start.elapsed();
+        report
+    }
 
-        // Write FAT sector:
-        let fat: Vec<u32> = vec![consts::FAT_SECTOR, consts::END_OF_CHAIN];
-        for &entry in fat.iter() {
-            inner.write_le_u32(entry)?;
-        }
-        for _ in fat.len()..(sector_len / size_of::<u32>()) {
-            inner.write_le_u32(consts::FREE_SECTOR)?;
+    /// Reads the entire contents of each of the streams at the given paths,
+    /// returning them in the same order as the paths were given.
+    ///
+    /// This gives the same results as opening and reading each stream in
+    /// turn, but plans the reads first: it works out where every piece of
+    /// every stream lives, then reads them in order of file offset, merging
+    /// adjacent sectors into larger reads.  When extracting many streams from
+    /// slow or high-latency storage, this avoids seeking back and forth
+    /// across the file.
+    pub fn read_many<P: AsRef<Path>>(
+        &mut self,
+        paths: &[P],
+    ) -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+        let mut resolved_paths = Vec::with_capacity(paths.len());
+        let mut stream_ids = Vec::with_capacity(paths.len());
+        for path in paths {
+            let names = internal::path::name_chain_from_path(path.as_ref())?;
+            let path = internal::path::path_from_name_chain(&names);
+            let stream_id = self.resolve_name_chain(&names, "stream")?;
+            if self.minialloc().dir_entry(stream_id).obj_type
+                != ObjType::Stream
+            {
+                invalid_input!("Not a stream: {:?}", path);
+            }
+            let len = self.minialloc().readable_len(stream_id);
+            self.check_buffer_len(&path, len)?;
+            resolved_paths.push(path);
+            stream_ids.push(stream_id);
         }
-        let difat: Vec<u32> = vec![0];
-        let difat_sector_ids: Vec<u32> = vec![];
+        let contents = self.minialloc_mut().read_streams(&stream_ids)?;
+        Ok(resolved_paths.into_iter().zip(contents).collect())
+    }
 
-        // Write directory sector:
-        let root_dir_entry = DirEntry::empty_root_entry();
-        root_dir_entry.write_to(&mut inner)?;
-        for _ in 1..version.dir_entries_per_sector() {
-            DirEntry::unallocated().write_to(&mut inner)?;
+    /// Reads the entire contents of the stream at the given path into a new
+    /// buffer.  Fails with a [`TooLargeToBuffer`] error, without reading
+    /// anything, if the stream is longer than the limit set with
+    /// [`set_max_buffer_len`](#method.set_max_buffer_len); use
+    /// [`open_stream`](#method.open_stream) to process such streams
+    /// incrementally.
+    pub fn read_stream_to_vec<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<Vec<u8>> {
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        let minialloc = self.minialloc();
+        if minialloc.dir_entry(stream_id).obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path);
         }
+        let len = minialloc.readable_len(stream_id);
+        drop(minialloc);
+        self.check_buffer_len(&path, len)?;
+        let mut data = internal::try_zeroed_vec(len as usize, "stream data")?;
+        Stream::new(&self.minialloc, stream_id).read_exact(&mut data)?;
+        Ok(data)
+    }
 
-        let sectors = Sectors::new(version, 3 * sector_len as u64, inner);
-        let allocator = Allocator::new(
-            sectors,
-            difat_sector_ids,
-            difat,
-            fat,
-            Validation::Strict,
-        )?;
-        let directory = Directory::new(
-            allocator,
-            vec![root_dir_entry],
-            1,
-            Validation::Strict,
-        )?;
-        let minialloc = MiniAllocator::new(
-            directory,
-            vec![],
-            consts::END_OF_CHAIN,
-            Validation::Strict,
-        )?;
-        Ok(CompoundFile { minialloc: Arc::new(RwLock::new(minialloc)) })
+    /// Reads from the stream at the given path, starting at the given
+    /// offset, into `buf`, and returns the number of bytes read, which is
+    /// less than the length of `buf` only at the end of the stream.
+    ///
+    /// Unlike reading through a [`Stream`], this only needs a shared
+    /// reference, so several threads can read from one `CompoundFile` at
+    /// once.  It reads what has been written to the underlying file, so
+    /// data still buffered in open [`Stream`] handles isn't seen.  Requires
+    /// the
+    /// [`POSITIONAL_READ`](struct.Capabilities.html#associatedconstant.POSITIONAL_READ)
+    /// capability.
+    pub fn read_stream_at<P: AsRef<Path>>(
+        &self,
+        path: P,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        self.backing
+            .require("read_stream_at", Capabilities::POSITIONAL_READ)?;
+        let read_at = self.backing.read_at.unwrap();
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        let minialloc = self.minialloc();
+        if minialloc.dir_entry(stream_id).obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path.as_ref());
+        }
+        minialloc.read_stream_at(stream_id, offset, buf, read_at)
     }
 
-    /// Creates a new, empty storage object (i.e. "directory") at the provided
-    /// path.  The parent storage object must already exist.
-    pub fn create_storage<P: AsRef<Path>>(
-        &mut self,
+    /// Copies the storage at the given path, and everything within it, into
+    /// a new compound file (of the same version) created with the given
+    /// reader/writer, and returns that file.  The storage becomes the new
+    /// file's root: its CLSID, state bits, and modification time are given
+    /// to the new root entry, and everything within it keeps its path
+    /// relative to the storage, along with its own metadata.  (The new root
+    /// has no creation time, since the spec requires it to be zero.)
+    ///
+    /// Temporary objects (see
+    /// [`set_show_temporaries`](#method.set_show_temporaries)) are not
+    /// copied.
+    ///
+    /// This only needs a shared reference, so with the compound file behind
+    /// an `RwLock`, a consistent copy (say, for a backup) can be taken under
+    /// a read lock, alongside other readers, while writers wait.
+    pub fn export_storage_as_cfb<P: AsRef<Path>, W: Read + Write + Seek>(
+        &self,
         path: P,
-    ) -> io::Result<()> {
-        self.create_storage_with_path(path.as_ref())
+        writer: W,
+    ) -> io::Result<CompoundFile<W>> {
+        self.export_storage_as_cfb_with_path(path.as_ref(), writer)
     }
 
-    fn create_storage_with_path(&mut self, path: &Path) -> io::Result<()> {
-        let mut names = internal::path::name_chain_from_path(path)?;
-        if let Some(stream_id) = self.stream_id_for_name_chain(&names) {
-            let path = internal::path::path_from_name_chain(&names);
-            if self.minialloc().dir_entry(stream_id).obj_type
-                != ObjType::Stream
-            {
-                already_exists!(
-                    "Cannot create storage at {:?} because a \
-                                 storage already exists there",
-                    path
-                );
+    fn export_storage_as_cfb_with_path<W: Read + Write + Seek>(
+        &self,
+        path: &Path,
+        writer: W,
+    ) -> io::Result<CompoundFile<W>> {
+        let storage = self.entry(path)?;
+        if !storage.is_storage() {
+            invalid_input!("Not a storage: {:?}", path);
+        }
+        let mut output =
+            CompoundFile::create_with_version(self.version(), writer)?;
+        output.set_storage_clsid("/", *storage.clsid())?;
+        output.set_state_bits("/", storage.state_bits())?;
+        output.set_modified_time("/", storage.modified())?;
+        let entries: Vec<Entry> =
+            self.walk_storage_with_path(storage.path())?.skip(1).collect();
+        for entry in entries {
+            let relative = match entry.path().strip_prefix(storage.path()) {
+                Ok(relative) => Path::new("/").join(relative),
+                Err(_) => continue,
+            };
+            if entry.is_stream() {
+                let mut source = self.open_stream_with_path(entry.path())?;
+                let mut dest = output.create_stream(&relative)?;
+                io::copy(&mut source, &mut dest)?;
+                dest.flush()?;
             } else {
-                already_exists!(
-                    "Cannot create storage at {:?} because a \
-                                 stream already exists there",
-                    path
-                );
+                output.create_storage(&relative)?;
+                output.set_storage_clsid(&relative, *entry.clsid())?;
+                output.set_created_time(&relative, entry.created())?;
+                output.set_modified_time(&relative, entry.modified())?;
             }
+            output.set_state_bits(&relative, entry.state_bits())?;
         }
-        // If names is empty, that means we're trying to create the root.  But
-        // the root always already exists and will have been rejected above.
-        debug_assert!(!names.is_empty());
-        let name = names.pop().unwrap();
-        let parent_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!("Parent storage doesn't exist"),
-        };
-        self.minialloc_mut().insert_dir_entry(
-            parent_id,
-            name,
-            ObjType::Storage,
-        )?;
-        Ok(())
+        output.flush()?;
+        Ok(output)
     }
 
-    /// Recursively creates a storage and all of its parent storages if they
-    /// are missing.
-    pub fn create_storage_all<P: AsRef<Path>>(
+    /// Splits this compound file into one standalone compound file per
+    /// top-level storage, as when pulling the embedded objects out of a
+    /// container.
+    ///
+    /// For each storage directly within the root, in order, `output` is
+    /// called with the storage's entry, and the storage is written to the
+    /// returned reader/writer with
+    /// [`export_storage_as_cfb`](#method.export_storage_as_cfb), so it
+    /// becomes the root of its new file.  This leaves the naming and
+    /// placement of the outputs up to the caller.  See [`SplitOptions`] for
+    /// what happens to the streams directly within the root.
+    ///
+    /// ```no_run
+    /// use std::fs;
+    ///
+    /// let mut comp = cfb::open("container.doc")?;
+    /// let options = cfb::SplitOptions::new().summary_information(true);
+    /// let report = comp.split(options, |entry| {
+    ///     fs::File::options()
+    ///         .read(true)
+    ///         .write(true)
+    ///         .create_new(true)
+    ///         .open(format!("{}.cfb", entry.name()))
+    /// })?;
+    /// println!("wrote {} files", report.storages().len());
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn split<W, O>(
         &mut self,
-        path: P,
-    ) -> io::Result<()> {
-        self.create_storage_all_with_path(path.as_ref())
+        options: SplitOptions,
+        output: O,
+    ) -> io::Result<SplitReport>
+    where
+        W: Read + Write + Seek,
+        O: FnMut(&Entry) -> io::Result<W>,
+    {
+        internal::split(self, options, output)
     }
 
-    fn create_storage_all_with_path(&mut self, path: &Path) -> io::Result<()> {
-        let names = internal::path::name_chain_from_path(path)?;
-        for length in 1..(names.len() + 1) {
-            let prefix_path =
-                internal::path::path_from_name_chain(&names[..length]);
-            if self.is_storage(&prefix_path) {
-                continue;
+    /// Writes a normalized copy of this compound file (of the same version)
+    /// to a new compound file created with the given reader/writer, and
+    /// returns that file.  The copy depends only on the file's logical
+    /// contents (its tree of objects, their metadata, and the streams' data),
+    /// and not on how the file was physically laid out, so two files with
+    /// equal contents always produce byte-identical copies.  This makes the
+    /// copy suitable for storing in version control, where layout noise
+    /// would otherwise make binary diffs needlessly large.
+    ///
+    /// In the copy, directory entries are numbered in traversal order (each
+    /// storage's children in the spec's name order), the FAT, DIFAT,
+    /// directory, MiniFAT, and mini stream are laid out at the front of the
+    /// file at their minimal sizes, and each stream's data follows
+    /// contiguously in the same order.  There are no free sectors, and no
+    /// free directory entries other than those padding out the last
+    /// directory sector.  Reserved fields are zero, and so are the
+    /// transaction signature and the root's creation time.
+    ///
+    /// Temporary objects (see
+    /// [`TEMPORARY_NAME_PREFIX`](constant.TEMPORARY_NAME_PREFIX.html)) are
+    /// left out, and streams whose chains are shorter than their declared
+    /// lengths are copied up to [`Entry::readable_len`].  The copy always
+    /// uses standard 64-byte mini sectors.
+    pub fn rewrite_canonical<W: Read + Write + Seek>(
+        &mut self,
+        writer: W,
+    ) -> io::Result<CompoundFile<W>> {
+        let entries: Vec<Entry> = self
+            .walk()
+            .include_temporaries()
+            .filter(|entry| {
+                !entry.path().iter().any(|name| {
+                    internal::path::is_temporary_name(&name.to_string_lossy())
+                })
+            })
+            .collect();
+        let mut num_objects = 0;
+        let mut total_bytes = 0;
+        let mut small_stream_bytes = 0;
+        for entry in entries.iter().skip(1) {
+            num_objects += 1;
+            let len = entry.readable_len();
+            total_bytes += len;
+            if len < consts::MINI_STREAM_CUTOFF as u64 {
+                small_stream_bytes +=
+                    len.next_multiple_of(consts::MINI_SECTOR_LEN as u64);
             }
-            self.create_storage_with_path(&prefix_path)?;
         }
-        Ok(())
+        let options = CreateOptions::new()
+            .version(self.version())
+            .expected_streams(num_objects)
+            .expected_total_bytes(total_bytes)
+            .expected_small_stream_bytes(small_stream_bytes);
+        let mut output = CompoundFile::create_with_options(options, writer)?;
+        output.copy_metadata_from_entry(
+            &entries[0],
+            "/",
+            MetadataFields::ALL,
+        )?;
+        for entry in entries.iter().skip(1) {
+            if entry.is_stream() {
+                let mut source = self.open_stream(entry.path())?;
+                let mut dest = output.create_stream(entry.path())?;
+                io::copy(&mut source, &mut dest)?;
+                dest.flush()?;
+                output.copy_metadata_from_entry(
+                    entry,
+                    entry.path(),
+                    MetadataFields::STATE_BITS,
+                )?;
+            } else {
+                output.create_storage(entry.path())?;
+                output.copy_metadata_from_entry(
+                    entry,
+                    entry.path(),
+                    MetadataFields::ALL,
+                )?;
+            }
+        }
+        output.flush()?;
+        Ok(output)
     }
 
-    /// Removes the storage object at the provided path.  The storage object
-    /// must exist and have no children.
-    pub fn remove_storage<P: AsRef<Path>>(
+    /// Returns an iterator over the on-disk bytes of every directory entry,
+    /// along with its stream ID, in stream ID order.  This includes
+    /// unallocated entries, and entries that couldn't be parsed when the
+    /// file was opened (see
+    /// [`ValidationIssueKind::UnparseableDirEntry`](enum.ValidationIssueKind.html#variant.UnparseableDirEntry)),
+    /// exactly as they appear in the directory chain, reserved fields and
+    /// all.  Entries modified since the file was opened are read as they
+    /// are now.  If reading an entry fails, the iterator yields that error
+    /// and then ends.
+    pub fn raw_dir_entries(
+        &mut self,
+    ) -> impl FusedIterator<
+        Item = io::Result<(StreamId, [u8; consts::DIR_ENTRY_LEN])>,
+    > + '_ {
+        let num_entries = self.minialloc().num_dir_entries();
+        let mut failed = false;
+        (0..num_entries)
+            .map_while(move |stream_id| {
+                if failed {
+                    return None;
+                }
+                let result =
+                    self.minialloc_mut().read_raw_dir_entry(stream_id);
+                failed = result.is_err();
+                Some(result.map(|raw| (StreamId::new(stream_id), raw)))
+            })
+            .fuse()
+    }
+
+    /// Returns the on-disk bytes of the directory entry with the given
+    /// stream ID (see [`raw_dir_entries`](#method.raw_dir_entries)).
+    pub fn raw_dir_entry(
+        &mut self,
+        stream_id: StreamId,
+    ) -> io::Result<[u8; consts::DIR_ENTRY_LEN]> {
+        let stream_id = stream_id.value();
+        let num_entries = self.minialloc().num_dir_entries();
+        if stream_id >= num_entries {
+            not_found!(
+                "No directory entry {} (the directory has {} entries)",
+                stream_id,
+                num_entries
+            );
+        }
+        self.minialloc_mut().read_raw_dir_entry(stream_id)
+    }
+
+    /// Returns every entry of the FAT, in sector order: for each sector of
+    /// the file, the next sector in its chain, or one of the special values
+    /// (see [`SectorId`]).  Entries past the end of the file, which are
+    /// stored in the last FAT sector but don't describe any sector, are not
+    /// included.
+    pub fn raw_fat(&self) -> Vec<SectorId> {
+        self.minialloc().fat().iter().copied().map(SectorId::new).collect()
+    }
+
+    /// Reads the entire contents of the stream at the given path into a new
+    /// buffer whose start is aligned to `align` bytes, which must be a power
+    /// of two.  This is for zero-copy formats (such as FlatBuffers or rkyv
+    /// archives) that need their data aligned in memory, which a `Vec<u8>`
+    /// doesn't guarantee.  The buffer holds exactly the stream's data.  (For
+    /// a file held in memory, see also
+    /// [`stream_slice_aligned`](#method.stream_slice_aligned), which can
+    /// avoid the copy.)
+    pub fn read_stream_aligned<P: AsRef<Path>>(
         &mut self,
         path: P,
-    ) -> io::Result<()> {
-        self.remove_storage_with_path(path.as_ref())
+        align: usize,
+    ) -> io::Result<AlignedBytes> {
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        let minialloc = self.minialloc();
+        if minialloc.dir_entry(stream_id).obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path);
+        }
+        let len = minialloc.readable_len(stream_id);
+        drop(minialloc);
+        self.check_buffer_len(&path, len)?;
+        let mut bytes = AlignedBytes::zeroed(len as usize, align)?;
+        Stream::new(&self.minialloc, stream_id).read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads everything in the sector chain of the stream at the given path
+    /// past the end of the stream's data: the unused end of its last (mini)
+    /// sector, plus any whole sectors beyond that (see
+    /// [`ValidationIssueKind::ChainSlack`](enum.ValidationIssueKind.html#variant.ChainSlack)).
+    /// Fails with a [`TooLargeToBuffer`] error if there is more than the
+    /// limit set with [`set_max_buffer_len`](#method.set_max_buffer_len).
+    pub fn read_stream_slack<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<Vec<u8>> {
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        let dir_entry = self.minialloc().dir_entry(stream_id).clone();
+        if dir_entry.obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path);
+        }
+        if dir_entry.stream_len == 0 {
+            return Ok(Vec::new());
+        }
+        let is_mini = dir_entry.stream_len < self.mini_stream_cutoff();
+        let max_len = self.max_buffer_len;
+        self.minialloc_mut().read_chain_from(
+            is_mini,
+            dir_entry.start_sector,
+            dir_entry.stream_len,
+            max_len,
+            |len| TooLargeToBuffer::new(&path, len, max_len),
+        )
+    }
+
+    /// Returns the IDs of the unallocated directory entries whose starting
+    /// sector fields still point at allocated chains that nothing else uses
+    /// (see
+    /// [`ValidationIssueKind::StaleChain`](enum.ValidationIssueKind.html#variant.StaleChain)).
+    pub fn stale_chains(&self) -> Vec<StreamId> {
+        let stale = self.minialloc().stale_chains();
+        stale
+            .into_iter()
+            .map(|(stream_id, _)| StreamId::new(stream_id))
+            .collect()
+    }
+
+    /// Reads the entire stale chain (see
+    /// [`stale_chains`](#method.stale_chains)) that the given unallocated
+    /// directory entry points at, including any bytes past the entry's stale
+    /// stream length.  Fails with a [`TooLargeToBuffer`] error (with an
+    /// empty path) if the chain is longer than the limit set with
+    /// [`set_max_buffer_len`](#method.set_max_buffer_len).
+    pub fn read_stale_chain(
+        &mut self,
+        stream_id: StreamId,
+    ) -> io::Result<Vec<u8>> {
+        let stream_id = stream_id.value();
+        let stale = self.minialloc().stale_chains();
+        let Some(&(_, is_mini)) =
+            stale.iter().find(|&&(id, _)| id == stream_id)
+        else {
+            not_found!("Directory entry {} has no stale chain", stream_id);
+        };
+        let start_sector = self.minialloc().dir_entry(stream_id).start_sector;
+        let max_len = self.max_buffer_len;
+        self.minialloc_mut().read_chain_from(
+            is_mini,
+            start_sector,
+            0,
+            max_len,
+            |len| TooLargeToBuffer::new(Path::new(""), len, max_len),
+        )
+    }
+
+    /// Parses the audit trail stream at the given path (as written after
+    /// calling [`enable_audit_trail`](#method.enable_audit_trail)) into its
+    /// records, oldest first.  Modifications that haven't been flushed yet
+    /// are not included.  Returns an error if the stream contains a record
+    /// in a format version that this version of the library doesn't know.
+    pub fn read_audit_trail<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<Vec<AuditRecord>> {
+        read_audit_records(self.open_stream(path)?)
+    }
+
+    /// Returns the content covered by an Authenticode signature over this
+    /// file (as used to sign MSI packages), in the order in which signing
+    /// tools hash it.  Hashing everything that the iterator yields, in order,
+    /// gives the digest that the signature in the
+    /// [`digital_signature`](#method.digital_signature) stream signs.
+    ///
+    /// Starting from the root, each storage's children are visited in order
+    /// of the raw UTF-16LE bytes of their names (which is *not* the order
+    /// that compound files sort names in); each stream yields its data, and
+    /// each storage yields its contents and then its own CLSID.  The
+    /// signature streams in the root storage are skipped.  (If the file also
+    /// has an [`msi_digital_signature_ex`](#method.msi_digital_signature_ex)
+    /// stream, the signature additionally covers a hash of the file's
+    /// metadata, which this doesn't produce.)
+    ///
+    /// If opening a stream fails, the iterator yields that error and then
+    /// ends, since the content after it could no longer be hashed correctly.
+    pub fn signature_content_iter(
+        &mut self,
+    ) -> impl FusedIterator<Item = io::Result<(PathBuf, SignatureContent<F>)>> + '_
+    {
+        let plan = self.signature_plan();
+        let mut failed = false;
+        plan.into_iter()
+            .map_while(move |(path, clsid)| {
+                if failed {
+                    return None;
+                }
+                let content = match clsid {
+                    Some(clsid) => Ok(SignatureContent::clsid(clsid)),
+                    None => {
+                        self.open_stream(&path).map(SignatureContent::stream)
+                    }
+                };
+                failed = content.is_err();
+                Some(content.map(|content| (path, content)))
+            })
+            .fuse()
+    }
+
+    /// Lists the objects whose content is covered by an Authenticode
+    /// signature, in hashing order, along with the CLSIDs of the storages.
+    fn signature_plan(&self) -> Vec<(PathBuf, Option<[u8; 16]>)> {
+        let mut children = HashMap::<PathBuf, Vec<Entry>>::new();
+        for entry in self.iter_entries_physical().skip(1) {
+            let parent = entry.path().parent().unwrap().to_path_buf();
+            children.entry(parent).or_default().push(entry);
+        }
+        let mut plan = Vec::new();
+        // Storages are pushed once to be expanded, then again (after their
+        // children) to have their CLSID hashed.
+        let mut stack = vec![(self.root_entry(), false)];
+        while let Some((entry, expanded)) = stack.pop() {
+            let path = entry.path().to_path_buf();
+            if entry.is_stream() {
+                plan.push((path, None));
+            } else if expanded {
+                plan.push((path, Some(entry.clsid().to_bytes_le())));
+            } else {
+                let mut entries = children.remove(&path).unwrap_or_default();
+                if entry.is_root() {
+                    entries.retain(|child| {
+                        !internal::is_signature_stream_name(child.name())
+                    });
+                }
+                entries.sort_by(|child1, child2| {
+                    compare_names_for_signature(child2.name(), child1.name())
+                });
+                stack.push((entry, true));
+                stack.extend(entries.into_iter().map(|child| (child, false)));
+            }
+        }
+        plan
+    }
+
+    /// Returns the contents of the Authenticode signature stream
+    /// (`\u{5}DigitalSignature`) in the root storage, or `None` if the file
+    /// isn't signed.
+    pub fn digital_signature(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.read_root_stream(DIGITAL_SIGNATURE_STREAM_NAME)
+    }
+
+    /// Returns the contents of the extended signature stream
+    /// (`\u{5}MsiDigitalSignatureEx`) in the root storage, or `None` if
+    /// there isn't one.
+    pub fn msi_digital_signature_ex(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.read_root_stream(MSI_DIGITAL_SIGNATURE_EX_STREAM_NAME)
+    }
+
+    fn read_root_stream(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let path = internal::path::path_from_name_chain(&[name]);
+        if !self.is_stream(&path) {
+            return Ok(None);
+        }
+        self.read_stream_to_vec(&path).map(Some)
+    }
+
+    /// Discards all in-memory state and opens the underlying file again as it
+    /// is now (as with `open()`).  This is one way to recover after an operation fails with
+    /// [`BackingFileShrunk`](struct.BackingFileShrunk.html) because the file
+    /// was truncated by someone else; the other is
+    /// [`repair`](#method.repair).  Any `Stream` handles from before the
+    /// reload must not be used afterwards.
+    pub fn reload(mut self) -> io::Result<CompoundFile<F>> {
+        let backing = std::mem::replace(&mut self.backing, Backing::unknown());
+        Ok(CompoundFile::open(self.into_inner())?.with_backing(backing))
+    }
+}
+
+impl<'a> CompoundFile<Cursor<&'a [u8]>> {
+    /// Returns the contents of the stream at the given path as a slice
+    /// aligned to `align` bytes, which must be a power of two, for zero-copy
+    /// formats that need their data aligned in memory.  If the stream's
+    /// data is contiguous in the file (as it is for a stream written in one
+    /// go), and happens to start at a suitably aligned address, the slice is
+    /// borrowed directly from the underlying buffer (or memory map), without
+    /// copying.  Otherwise, this falls back to copying the data into a new
+    /// aligned buffer, like
+    /// [`read_stream_aligned`](#method.read_stream_aligned).
+    ///
+    /// To make the fast path likely for large alignments, keep the whole
+    /// file in an aligned buffer (for example, with
+    /// [`AlignedBytes::copy_from_slice`]): each sector of the file then
+    /// starts at a multiple of the sector length (see
+    /// [`sector_len`](#method.sector_len)).  Streams shorter than the
+    /// [mini stream cutoff](#method.mini_stream_cutoff) live in the mini
+    /// stream, so they are only aligned to the mini sector length.
+    pub fn stream_slice_aligned<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        align: usize,
+    ) -> io::Result<AlignedSlice<'a>> {
+        if !align.is_power_of_two() {
+            invalid_input!("Alignment {} is not a power of two", align);
+        }
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        let minialloc = self.minialloc();
+        if minialloc.dir_entry(stream_id).obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path);
+        }
+        let data: &'a [u8] = minialloc.inner().get_ref();
+        let pieces = minialloc.stream_pieces(stream_id, &mut None)?;
+        drop(minialloc);
+        let start = pieces.first().map_or(0, |&(offset, _)| offset);
+        let mut end = start;
+        for &(offset, len) in pieces.iter() {
+            if offset != end {
+                return self
+                    .read_stream_aligned(&path, align)
+                    .map(AlignedSlice::Owned);
+            }
+            end += len as u64;
+        }
+        match data.get(start as usize..end as usize) {
+            Some(slice) if internal::is_aligned(slice, align) => {
+                Ok(AlignedSlice::Borrowed(slice))
+            }
+            _ => {
+                self.read_stream_aligned(&path, align).map(AlignedSlice::Owned)
+            }
+        }
+    }
+}
+
+impl<F: Read + Write + Seek> CompoundFile<F> {
+    /// Creates a new compound file with no contents, using the underlying
+    /// reader/writer.  The reader/writer should be initially empty.
+    pub fn create(inner: F) -> io::Result<CompoundFile<F>> {
+        CompoundFile::create_with_version(Version::V4, inner)
+    }
+
+    /// Creates a new compound file of the given version with no contents,
+    /// using the underlying writer.  The writer should be initially empty.
+    ///
+    /// Version 3 files can't grow past 2 GiB; any operation that would need
+    /// them to fails with a [`FileTooLarge`] error, without writing the
+    /// sector that would have crossed the limit.
+    pub fn create_with_version(
+        version: Version,
+        inner: F,
+    ) -> io::Result<CompoundFile<F>> {
+        CompoundFile::create_with_options(
+            CreateOptions::new().version(version),
+            inner,
+        )
+    }
+
+    /// Creates a new compound file with no contents, using the given options
+    /// and the underlying writer.  The writer should be initially empty.
+    /// Fails with a [`FileTooLarge`] error if the capacity hints would need a
+    /// larger file than the chosen version allows.
+    pub fn create_with_options(
+        options: CreateOptions,
+        mut inner: F,
+    ) -> io::Result<CompoundFile<F>> {
+        let version = options.version;
+        let layout = options.layout();
+        let expected_num_sectors = options.expected_num_sectors();
+        if expected_num_sectors > version.max_num_sectors() {
+            let sector_len = version.sector_len() as u64;
+            let required = (expected_num_sectors + 1) * sector_len;
+            return Err(FileTooLarge::new(version, required).into());
+        }
+        // Lay out the FAT, DIFAT, directory, MiniFAT, and mini stream sectors
+        // contiguously, in that order, right after the header.
+        let num_fat_sectors = layout.num_fat_sectors as u32;
+        let num_difat_sectors = layout.num_difat_sectors as u32;
+        let first_difat_sector = num_fat_sectors;
+        let first_dir_sector = first_difat_sector + num_difat_sectors;
+        let first_minifat_sector =
+            first_dir_sector + layout.num_dir_sectors as u32;
+        let first_mini_stream_sector =
+            first_minifat_sector + layout.num_minifat_sectors as u32;
+        let num_sectors = layout.num_sectors() as u32;
+        let mut fat = Vec::<u32>::with_capacity(num_sectors as usize);
+        fat.resize(num_fat_sectors as usize, consts::FAT_SECTOR);
+        fat.resize(first_dir_sector as usize, consts::DIFAT_SECTOR);
+        for &(start, end) in &[
+            (first_dir_sector, first_minifat_sector),
+            (first_minifat_sector, first_mini_stream_sector),
+            (first_mini_stream_sector, num_sectors),
+        ] {
+            if start < end {
+                fat.extend(start + 1..end);
+                fat.push(consts::END_OF_CHAIN);
+            }
+        }
+        debug_assert_eq!(fat.len(), num_sectors as usize);
+        let difat: Vec<u32> = (0..num_fat_sectors).collect();
+        let difat_sector_ids: Vec<u32> =
+            (first_difat_sector..first_dir_sector).collect();
+
+        let chain_start = |start: u32, end: u32| {
+            if start < end {
+                start
+            } else {
+                consts::END_OF_CHAIN
+            }
+        };
+        let mut header = Header {
+            version,
+            mini_sector_shift: consts::MINI_SECTOR_SHIFT,
+            // 2.2 requires this to be zero in V3
+            num_dir_sectors: if version.requires_dir_sector_count() {
+                layout.num_dir_sectors as u32
+            } else {
+                0
+            },
+            num_fat_sectors,
+            first_dir_sector,
+            first_minifat_sector: chain_start(
+                first_minifat_sector,
+                first_mini_stream_sector,
+            ),
+            num_minifat_sectors: layout.num_minifat_sectors as u32,
+            first_difat_sector: chain_start(
+                first_difat_sector,
+                first_dir_sector,
+            ),
+            num_difat_sectors,
+            initial_difat_entries: [consts::FREE_SECTOR;
+                consts::NUM_DIFAT_ENTRIES_IN_HEADER],
+        };
+        for (entry, &sector_id) in
+            header.initial_difat_entries.iter_mut().zip(difat.iter())
+        {
+            *entry = sector_id;
+        }
+        header.write_to(&mut inner)?;
+
+        // Pad the header with zeroes so it's the length of a sector (only
+        // version 4 sectors are longer than the header).
+        let sector_len = version.sector_len();
+        debug_assert!(sector_len >= consts::HEADER_LEN);
+        if version.is_version_4() {
+            inner.write_all(&vec![0; sector_len - consts::HEADER_LEN])?;
+        }
+
+        // Write FAT sectors:
+        let fat_entries_per_sector = version.fat_entries_per_sector();
+        for &entry in fat.iter() {
+            inner.write_le_u32(entry)?;
+        }
+        for _ in fat.len()..(num_fat_sectors as usize * fat_entries_per_sector)
+        {
+            inner.write_le_u32(consts::FREE_SECTOR)?;
+        }
+
+        // Write DIFAT sectors:
+        let difat_entries_per_sector = version.difat_entries_per_sector();
+        let mut remaining_difat =
+            difat.iter().skip(version.difat_header_entries());
+        for &difat_sector_id in difat_sector_ids.iter() {
+            for _ in 0..difat_entries_per_sector {
+                let entry = remaining_difat
+                    .next()
+                    .copied()
+                    .unwrap_or(consts::FREE_SECTOR);
+                inner.write_le_u32(entry)?;
+            }
+            if difat_sector_id + 1 < first_dir_sector {
+                inner.write_le_u32(difat_sector_id + 1)?;
+            } else {
+                inner.write_le_u32(consts::END_OF_CHAIN)?;
+            }
+        }
+
+        // Write directory sectors:
+        let mut root_dir_entry = DirEntry::empty_root_entry();
+        if options.stamp_root_modified_time {
+            root_dir_entry.modified_time = Timestamp::now();
+        }
+        root_dir_entry.start_sector =
+            chain_start(first_mini_stream_sector, num_sectors);
+        root_dir_entry.write_to(&mut inner)?;
+        let num_dir_entries =
+            layout.num_dir_sectors as usize * version.dir_entries_per_sector();
+        for _ in 1..num_dir_entries {
+            DirEntry::unallocated().write_to(&mut inner)?;
+        }
+        // Keep the directory's unallocated entries in memory too, so that the
+        // new file looks the same as it will once reopened.
+        let mut dir_entries = vec![root_dir_entry];
+        dir_entries.resize(num_dir_entries, DirEntry::unallocated());
+
+        // Write MiniFAT sectors and mini stream sectors:
+        for _ in
+            0..(layout.num_minifat_sectors as usize * fat_entries_per_sector)
+        {
+            inner.write_le_u32(consts::FREE_SECTOR)?;
+        }
+        let zeroes = vec![0u8; sector_len];
+        for _ in 0..layout.num_mini_stream_sectors {
+            inner.write_all(&zeroes)?;
+        }
+
+        let inner_len = (num_sectors as u64 + 1) * sector_len as u64;
+        let sectors = Sectors::new(version, inner_len, inner);
+        let allocator = Allocator::new(
+            sectors,
+            difat_sector_ids,
+            difat,
+            fat,
+            Validation::Strict,
+            &mut Vec::new(),
+        )?;
+        let directory = Directory::new(
+            allocator,
+            dir_entries,
+            first_dir_sector,
+            Validation::Strict,
+            &mut Vec::new(),
+        )?;
+        let mut minialloc = MiniAllocator::new(
+            directory,
+            vec![],
+            header.first_minifat_sector,
+            header.mini_sector_shift,
+            Validation::Strict,
+            &mut Vec::new(),
+        )?;
+        minialloc.set_has_reservations(
+            options.has_reservations() && !options.keep_unused_reservations,
+        );
+        Ok(CompoundFile {
+            minialloc: Arc::new(RwLock::new(minialloc)),
+            open_warnings: Vec::new(),
+            leaked_temporaries: Vec::new(),
+            header_version_mismatch: false,
+            backing: Backing::writable(),
+            clsid_policy: ClsidPolicy::default(),
+            max_buffer_len: DEFAULT_MAX_BUFFER_LEN,
+        })
+    }
+
+    /// Creates a new, empty storage object (i.e. "directory") at the provided
+    /// path.  The parent storage object must already exist.  The storage's
+    /// creation and modified times are set to the current time; to make the
+    /// output reproducible, use
+    /// [`create_storage_with_options`](#method.create_storage_with_options)
+    /// to give it other times from the start.
+    pub fn create_storage<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<()> {
+        let result = self.create_storage_with_path(path.as_ref());
+        self.self_check("create_storage");
+        result
+    }
+
+    /// Like [`create_storage`](#method.create_storage), but gives the new
+    /// storage the times and state bits in the given options, rather than
+    /// the current time.  The new storage's directory entry is written once,
+    /// with its final metadata (including any CLSID from the
+    /// [CLSID policy](#method.set_clsid_policy)), and no other object's
+    /// metadata changes: the parent storage's modified time, in particular,
+    /// is left alone.  (Other entries' tree links may change, as inserting
+    /// into the parent's tree requires.)  Fails with an `InvalidInput` error,
+    /// without creating anything, if a time is out of range.
+    pub fn create_storage_with_options<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: &NewEntryOptions,
+    ) -> io::Result<()> {
+        let result = self.create_storage_entry(path.as_ref(), options);
+        self.self_check("create_storage_with_options");
+        result
+    }
+
+    fn create_storage_with_path(&mut self, path: &Path) -> io::Result<()> {
+        self.create_storage_entry(path, &NewEntryOptions::default())
+    }
+
+    fn create_storage_entry(
+        &mut self,
+        path: &Path,
+        options: &NewEntryOptions,
+    ) -> io::Result<()> {
+        let (created, modified) = checked_entry_times(options)?;
+        let mut names = internal::path::name_chain_from_path(path)?;
+        if let Some(stream_id) = self.stream_id_for_name_chain(&names)? {
+            let path = internal::path::path_from_name_chain(&names);
+            if self.minialloc().dir_entry(stream_id).obj_type
+                != ObjType::Stream
+            {
+                already_exists!(
+                    "Cannot create storage at {:?} because a \
+                                 storage already exists there",
+                    path
+                );
+            } else {
+                already_exists!(
+                    "Cannot create storage at {:?} because a \
+                                 stream already exists there",
+                    path
+                );
+            }
+        }
+        // If names is empty, that means we're trying to create the root.  But
+        // the root always already exists and will have been rejected above.
+        debug_assert!(!names.is_empty());
+        let path = internal::path::path_from_name_chain(&names);
+        let Some(name) = names.pop() else {
+            already_exists!("The root storage always exists");
+        };
+        if internal::path::is_temporary_name(name) {
+            invalid_input!(
+                "Object name {:?} is reserved for temporary objects",
+                name
+            );
+        }
+        let parent_id = self.resolve_name_chain(&names, "parent storage")?;
+        let clsid = self.clsid_policy.clsid_for(&path);
+        let mut minialloc = self.minialloc_mut();
+        minialloc.insert_dir_entry_with(
+            parent_id,
+            name,
+            ObjType::Storage,
+            |dir_entry| {
+                dir_entry.clsid = clsid;
+                if let Some(created) = created {
+                    dir_entry.creation_time = created;
+                }
+                if let Some(modified) = modified {
+                    dir_entry.modified_time = modified;
+                }
+                if let Some(bits) = options.state_bits {
+                    dir_entry.state_bits = bits;
+                }
+            },
+        )?;
+        minialloc.audit(AuditOp::CreateStorage, &path, 0, 0);
+        Ok(())
+    }
+
+    /// Recursively creates a storage and all of its parent storages if they
+    /// are missing.
+    pub fn create_storage_all<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<()> {
+        let result = self.create_storage_all_with_path(path.as_ref());
+        self.self_check("create_storage_all");
+        result
+    }
+
+    fn create_storage_all_with_path(&mut self, path: &Path) -> io::Result<()> {
+        let names = internal::path::name_chain_from_path(path)?;
+        // Fail up front if the path continues past an existing stream.
+        self.stream_id_for_name_chain(&names)?;
+        for length in 1..(names.len() + 1) {
+            let prefix_path =
+                internal::path::path_from_name_chain(&names[..length]);
+            if self.is_storage(&prefix_path) {
+                continue;
+            }
+            self.create_storage_with_path(&prefix_path)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the storage object at the provided path.  The storage object
+    /// must exist and have no children.
+    pub fn remove_storage<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<()> {
+        let result = self.remove_storage_with_path(path.as_ref());
+        self.self_check("remove_storage");
+        result
+    }
+
+    fn remove_storage_with_path(&mut self, path: &Path) -> io::Result<()> {
+        // Leaked temporaries are invisible, so they mustn't keep a storage
+        // from counting as empty.
+        self.remove_leaked_temporaries()?;
+        let mut names = internal::path::name_chain_from_path(path)?;
+        let stream_id = self.resolve_name_chain(&names, "storage")?;
+        {
+            let minialloc = self.minialloc();
+            let dir_entry = minialloc.dir_entry(stream_id);
+            if dir_entry.obj_type == ObjType::Root {
+                invalid_input!("Cannot remove the root storage object");
+            }
+            if dir_entry.obj_type == ObjType::Stream {
+                invalid_input!("Not a storage: {:?}", path);
+            }
+            debug_assert_eq!(dir_entry.obj_type, ObjType::Storage);
+            if dir_entry.child != consts::NO_STREAM {
+                invalid_input!("Storage is not empty: {:?}", path);
+            }
+        }
+        debug_assert!(!names.is_empty());
+        let path = internal::path::path_from_name_chain(&names);
+        let Some(name) = names.pop() else {
+            invalid_input!("Cannot remove the root storage object");
+        };
+        let parent_id = self.resolve_name_chain(&names, "parent storage")?;
+        let mut minialloc = self.minialloc_mut();
+        minialloc.remove_dir_entry(parent_id, name)?;
+        minialloc.audit(AuditOp::RemoveStorage, &path, 0, 0);
+        Ok(())
+    }
+
+    /// Renames the stream or storage at `from` to `to`, which may also be in
+    /// a different storage (so this can move objects, along with everything
+    /// below them, anywhere within the compound file).  Only the directory
+    /// is changed, so this is fast no matter how much data is involved, and
+    /// the object keeps its state bits, CLSID, and timestamps.  `to` may
+    /// differ from `from` only in case.
+    ///
+    /// Fails if `from` is the root storage, if the parent storage of `to`
+    /// doesn't exist, if `to` is (or would be inside) `from` itself, if some
+    /// other object already exists at `to` (with an `AlreadyExists` error;
+    /// see [`rename_overwrite`](#method.rename_overwrite) to replace it
+    /// instead), or if the new name is invalid (more than 31 UTF-16 code
+    /// units long, or containing `/`, `\`, `:`, or `!`).
+    ///
+    /// Streams that are already open, whether for the renamed object or for
+    /// anything below it, keep working.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> io::Result<()> {
+        let result =
+            self.rename_with_paths(from.as_ref(), to.as_ref(), false, false);
+        self.self_check("rename");
+        result
+    }
+
+    /// Like [`rename`](#method.rename), but if another object already
+    /// exists at `to`, replaces it, freeing its data.  A stream can only
+    /// replace a stream, and a storage can only replace an empty storage
+    /// (see [`rename_overwrite_all`](#method.rename_overwrite_all));
+    /// anything else fails with an `InvalidInput` error, as does replacing
+    /// a storage that contains `from`.
+    ///
+    /// The replacement itself is a single directory entry write, made
+    /// before anything is freed, so if this is interrupted, `to` holds
+    /// either the old object or the new one (and `from` may still be there
+    /// too, sharing its data).  Streams that are open for `from` or for the
+    /// replaced object stop working, as if they had been removed; streams
+    /// open for anything below `from` keep working.
+    pub fn rename_overwrite<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> io::Result<()> {
+        let result =
+            self.rename_with_paths(from.as_ref(), to.as_ref(), true, false);
+        self.self_check("rename_overwrite");
+        result
+    }
+
+    /// Like [`rename_overwrite`](#method.rename_overwrite), but a storage
+    /// can also replace a storage that isn't empty, whose contents are
+    /// removed first (as with
+    /// [`remove_storage_all`](#method.remove_storage_all)).
+    pub fn rename_overwrite_all<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+    ) -> io::Result<()> {
+        let result =
+            self.rename_with_paths(from.as_ref(), to.as_ref(), true, true);
+        self.self_check("rename_overwrite_all");
+        result
+    }
+
+    fn rename_with_paths(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        overwrite: bool,
+        overwrite_all: bool,
+    ) -> io::Result<()> {
+        let names = internal::path::name_chain_from_path(from)?;
+        let stream_id = self.resolve_name_chain(&names, "object")?;
+        let Some(parent_id) = self.minialloc().parent_id(stream_id) else {
+            invalid_input!("Cannot rename the root storage object");
+        };
+        let mut new_names = internal::path::name_chain_from_path(to)?;
+        let new_path = internal::path::path_from_name_chain(&new_names);
+        let Some(new_name) = new_names.pop() else {
+            invalid_input!("Cannot rename an object to the root storage");
+        };
+        internal::path::validate_name(new_name)?;
+        if internal::path::is_temporary_name(new_name) {
+            invalid_input!(
+                "Object name {:?} is reserved for temporary objects",
+                new_name
+            );
+        }
+        let new_parent_id =
+            self.resolve_name_chain(&new_names, "parent storage")?;
+        let path = internal::path::path_from_name_chain(&names);
+        let replaced_id = {
+            let minialloc = self.minialloc();
+            if minialloc.dir_entry(new_parent_id).obj_type == ObjType::Stream {
+                invalid_input!(
+                    "Not a storage: {:?}",
+                    internal::path::path_from_name_chain(&new_names)
+                );
+            }
+            let mut ancestor_id = Some(new_parent_id);
+            while let Some(id) = ancestor_id {
+                if id == stream_id {
+                    invalid_input!("Cannot move {:?} inside itself", path);
+                }
+                ancestor_id = minialloc.parent_id(id);
+            }
+            new_names.push(new_name);
+            match minialloc.stream_id_for_name_chain(&new_names) {
+                Some(other_id) if other_id != stream_id => other_id,
+                _ => consts::NO_STREAM,
+            }
+        };
+        if replaced_id != consts::NO_STREAM {
+            if !overwrite {
+                already_exists!(
+                    "Cannot rename {:?} to {:?} because an object already \
+                     exists there",
+                    path,
+                    new_path
+                );
+            }
+            return self.rename_replacing(
+                stream_id,
+                &path,
+                replaced_id,
+                &new_path,
+                new_name,
+                overwrite_all,
+            );
+        }
+        let mut minialloc = self.minialloc_mut();
+        let dir_entry = minialloc.dir_entry(stream_id);
+        if new_parent_id == parent_id && dir_entry.name == new_name {
+            return Ok(());
+        }
+        let name = dir_entry.name.clone();
+        let len = dir_entry.stream_len;
+        minialloc.rename_dir_entry(
+            parent_id,
+            &name,
+            new_parent_id,
+            new_name,
+        )?;
+        minialloc.audit_rename(&path, &new_path, len);
+        Ok(())
+    }
+
+    /// Moves the object `stream_id` at `path` over the existing object
+    /// `replaced_id` at `new_path`, for `rename_overwrite`.
+    fn rename_replacing(
+        &mut self,
+        stream_id: u32,
+        path: &Path,
+        replaced_id: u32,
+        new_path: &Path,
+        new_name: &str,
+        overwrite_all: bool,
+    ) -> io::Result<()> {
+        // Leaked temporaries are invisible, so they mustn't keep a storage
+        // from counting as empty.
+        self.remove_leaked_temporaries()?;
+        let (obj_type, len, replaced_len) = {
+            let minialloc = self.minialloc();
+            let dir_entry = minialloc.dir_entry(stream_id);
+            let replaced = minialloc.dir_entry(replaced_id);
+            let is_storage = |obj_type| obj_type != ObjType::Stream;
+            if is_storage(dir_entry.obj_type) != is_storage(replaced.obj_type)
+            {
+                let (what, replaced_what) = if is_storage(dir_entry.obj_type) {
+                    ("storage", "stream")
+                } else {
+                    ("stream", "storage")
+                };
+                invalid_input!(
+                    "Cannot replace the {} at {:?} with the {} at {:?}",
+                    replaced_what,
+                    new_path,
+                    what,
+                    path
+                );
+            }
+            let mut ancestor_id = minialloc.parent_id(stream_id);
+            while let Some(id) = ancestor_id {
+                if id == replaced_id {
+                    invalid_input!(
+                        "Cannot replace {:?} with {:?}, which is inside it",
+                        new_path,
+                        path
+                    );
+                }
+                ancestor_id = minialloc.parent_id(id);
+            }
+            if replaced.child != consts::NO_STREAM && !overwrite_all {
+                invalid_input!("Storage is not empty: {:?}", new_path);
+            }
+            (dir_entry.obj_type, dir_entry.stream_len, replaced.stream_len)
+        };
+        if obj_type == ObjType::Storage {
+            let children: Vec<Entry> = self.read_storage(new_path)?.collect();
+            for child in children {
+                if child.is_stream() {
+                    self.remove_stream_with_path(child.path())?;
+                } else {
+                    self.remove_storage_all_with_path(child.path())?;
+                }
+            }
+        } else {
+            self.minialloc_mut().audit_stream_done(replaced_id, false);
+        }
+        {
+            let mut minialloc = self.minialloc_mut();
+            minialloc.replace_dir_entry(stream_id, replaced_id, new_name)?;
+            minialloc.audit_stream_done(stream_id, false);
+        }
+        self.remove_object(stream_id)?;
+        let op = if obj_type == ObjType::Storage {
+            AuditOp::RemoveStorage
+        } else {
+            AuditOp::RemoveStream
+        };
+        let mut minialloc = self.minialloc_mut();
+        minialloc.audit(op, new_path, replaced_len, 0);
+        minialloc.audit_rename(path, new_path, len);
+        Ok(())
+    }
+
+    /// Recursively removes a storage and all of its children.  If called on
+    /// the root storage, recursively removes all of its children but not the
+    /// root storage itself (which cannot be removed).
+    pub fn remove_storage_all<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<()> {
+        let result = self.remove_storage_all_with_path(path.as_ref());
+        self.self_check("remove_storage_all");
+        result
+    }
+
+    fn remove_storage_all_with_path(&mut self, path: &Path) -> io::Result<()> {
+        let mut stack = self.walk_storage(path)?.collect::<Vec<Entry>>();
+        while let Some(entry) = stack.pop() {
+            if entry.is_stream() {
+                self.remove_stream_with_path(entry.path())?;
+            } else if !entry.is_root() {
+                self.remove_storage_with_path(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Recursively copies the storage at `from` in `source`, along with all of
+    /// its children, to `to` in this compound file.  Each copied object keeps
+    /// its name, its state bits, and (for storages) its CLSID and timestamps,
+    /// following the same rules as
+    /// [`copy_metadata_from_entry`](#method.copy_metadata_from_entry).
+    /// Temporary objects (see
+    /// [`TEMPORARY_NAME_PREFIX`](constant.TEMPORARY_NAME_PREFIX.html)) are
+    /// not copied.
+    ///
+    /// If `to` is missing, it is created (so its parent storage must already
+    /// exist); if it is already a storage (such as the root), the copied
+    /// children are merged into it, and it keeps its own metadata.  `from`
+    /// may be the root storage.
+    ///
+    /// If an object already exists where one is to be copied, this fails
+    /// with an `AlreadyExists` error, unless `overwrite` is true, in which
+    /// case an existing stream (or a storage, where a stream is to be copied,
+    /// or vice versa) is replaced, and an existing storage is merged into
+    /// like `to` is.  Every such conflict, and every invalid path, is detected
+    /// before anything is changed, so failing for those reasons leaves this
+    /// file untouched.
+    pub fn copy_storage_from<G, P, Q>(
+        &mut self,
+        source: &mut CompoundFile<G>,
+        from: P,
+        to: Q,
+        overwrite: bool,
+    ) -> io::Result<()>
+    where
+        G: Read + Seek,
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let result = self.copy_storage_from_with_paths(
+            source,
+            from.as_ref(),
+            to.as_ref(),
+            overwrite,
+        );
+        self.self_check("copy_storage_from");
+        result
+    }
+
+    fn copy_storage_from_with_paths<G: Read + Seek>(
+        &mut self,
+        source: &mut CompoundFile<G>,
+        from: &Path,
+        to: &Path,
+        overwrite: bool,
+    ) -> io::Result<()> {
+        let from = source.entry(from)?;
+        if !from.is_storage() {
+            invalid_input!("Not a storage: {:?}", from.path());
+        }
+        let to_names = internal::path::name_chain_from_path(to)?;
+        let to = internal::path::path_from_name_chain(&to_names);
+        let copies: Vec<(PathBuf, Entry)> = source
+            .walk_relative(from.path())?
+            .map(|(relative, entry)| (to.join(relative), entry))
+            .collect();
+
+        // Check for conflicts before changing anything.  Objects whose
+        // parents are created (or replaced) by the copy can't conflict.
+        let mut created: HashSet<PathBuf> = HashSet::new();
+        for (dest, entry) in copies.iter() {
+            if let Some(parent) = dest.parent() {
+                if created.contains(parent) {
+                    created.insert(dest.clone());
+                    continue;
+                }
+            }
+            match self.entry(dest) {
+                Ok(existing) => {
+                    let merges = existing.is_storage() && entry.is_storage();
+                    if *dest != to || !merges {
+                        if !overwrite {
+                            already_exists!(
+                                "Object already exists: {:?}",
+                                dest
+                            );
+                        }
+                        if existing.is_root() {
+                            invalid_input!(
+                                "Cannot replace the root storage object"
+                            );
+                        }
+                    }
+                    if !merges {
+                        created.insert(dest.clone());
+                    }
+                }
+                Err(error) => match ObjectNotFound::from_io_error(&error) {
+                    Some(not_found) if not_found.parent_exists() => {
+                        created.insert(dest.clone());
+                    }
+                    _ => return Err(error),
+                },
+            }
+        }
+
+        for (dest, entry) in copies.iter() {
+            let existing = if created.contains(dest) {
+                self.entry(dest).ok()
+            } else {
+                // This is a storage that the copy merges into.
+                continue;
+            };
+            if entry.is_storage() {
+                if existing.is_some() {
+                    self.remove_stream_with_path(dest)?;
+                }
+                self.create_storage_with_path(dest)?;
+                self.copy_metadata_from_entry_with_path(
+                    entry,
+                    dest,
+                    MetadataFields::ALL,
+                )?;
+            } else {
+                if existing.is_some_and(|existing| existing.is_storage()) {
+                    self.remove_storage_all_with_path(dest)?;
+                }
+                let mut reader = source.open_stream(entry.path())?;
+                let mut writer = self.create_stream_with_path(dest, true)?;
+                io::copy(&mut reader, &mut writer)?;
+                writer.flush()?;
+                drop(writer);
+                self.copy_metadata_from_entry_with_path(
+                    entry,
+                    dest,
+                    MetadataFields::STATE_BITS,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the CLSID for the storage object at the provided path.  (To get
+    /// the current CLSID for a storage object, use
+    /// `self.entry(path)?.clsid()`.)
+    pub fn set_storage_clsid<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        clsid: Uuid,
+    ) -> io::Result<()> {
+        let result = self.set_storage_clsid_with_path(path.as_ref(), clsid);
+        self.self_check("set_storage_clsid");
+        result.map(|_| ())
+    }
+
+    /// Like [`set_storage_clsid`](#method.set_storage_clsid), but returns
+    /// the storage's previous CLSID.  On error, nothing is changed.
+    pub fn replace_storage_clsid<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        clsid: Uuid,
+    ) -> io::Result<Uuid> {
+        let result = self.set_storage_clsid_with_path(path.as_ref(), clsid);
+        self.self_check("replace_storage_clsid");
+        result
+    }
+
+    fn set_storage_clsid_with_path(
+        &mut self,
+        path: &Path,
+        clsid: Uuid,
+    ) -> io::Result<Uuid> {
+        let names = internal::path::name_chain_from_path(path)?;
+        let stream_id = self.resolve_name_chain(&names, "storage")?;
+        let mut minialloc = self.minialloc_mut();
+        if minialloc.dir_entry(stream_id).obj_type == ObjType::Stream {
+            invalid_input!(
+                "Not a storage: {:?}",
+                internal::path::path_from_name_chain(&names)
+            );
+        }
+        let old_clsid = minialloc
+            .with_dir_entry_mut(stream_id, |dir_entry| {
+                std::mem::replace(&mut dir_entry.clsid, clsid)
+            })?;
+        let path = internal::path::path_from_name_chain(&names);
+        minialloc.audit(AuditOp::SetMetadata, &path, 0, 0);
+        Ok(old_clsid)
+    }
+
+    /// Creates and returns a new, empty stream object at the provided path.
+    /// If a stream already exists at that path, it will be replaced by the new
+    /// stream.  The parent storage object must already exist.
+    pub fn create_stream<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<Stream<F>> {
+        let result = self.create_stream_with_path(path.as_ref(), true);
+        self.self_check("create_stream");
+        result
+    }
+
+    /// Creates and returns a new, empty stream object at the provided path.
+    /// Returns an error if a stream already exists at that path.  The parent
+    /// storage object must already exist.
+    pub fn create_new_stream<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<Stream<F>> {
+        let result = self.create_stream_with_path(path.as_ref(), false);
+        self.self_check("create_new_stream");
+        result
+    }
+
+    /// Like [`create_stream`](#method.create_stream), but gives the new
+    /// stream the state bits in the given options.  (The options' times must
+    /// still be in range, but are otherwise ignored, since the CFB spec
+    /// requires a stream's times to be zero.)
+    /// As with
+    /// [`create_storage_with_options`](#method.create_storage_with_options),
+    /// the new stream's directory entry is written with its final metadata,
+    /// and no other object's metadata changes.  If a stream already exists
+    /// at the path, it is replaced, and keeps its state bits unless the
+    /// options set them.
+    pub fn create_stream_with_options<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: &NewEntryOptions,
+    ) -> io::Result<Stream<F>> {
+        let result = self.create_stream_entry(path.as_ref(), true, options);
+        self.self_check("create_stream_with_options");
+        result
+    }
+
+    fn create_stream_with_path(
+        &mut self,
+        path: &Path,
+        overwrite: bool,
+    ) -> io::Result<Stream<F>> {
+        self.create_stream_entry(path, overwrite, &NewEntryOptions::default())
+    }
+
+    fn create_stream_entry(
+        &mut self,
+        path: &Path,
+        overwrite: bool,
+        options: &NewEntryOptions,
+    ) -> io::Result<Stream<F>> {
+        checked_entry_times(options)?;
+        let mut names = internal::path::name_chain_from_path(path)?;
+        if let Some(stream_id) = self.stream_id_for_name_chain(&names)? {
+            if self.minialloc().dir_entry(stream_id).obj_type
+                != ObjType::Stream
+            {
+                already_exists!(
+                    "Cannot create stream at {:?} because a \
+                                 storage already exists there",
+                    internal::path::path_from_name_chain(&names)
+                );
+            } else if !overwrite {
+                already_exists!(
+                    "Cannot create new stream at {:?} because a \
+                                 stream already exists there",
+                    internal::path::path_from_name_chain(&names)
+                );
+            } else {
+                // Replacing a stream is recorded in the audit trail as
+                // creating it, rather than as truncating it.
+                let path = internal::path::path_from_name_chain(&names);
+                let old_len = {
+                    let mut minialloc = self.minialloc_mut();
+                    minialloc.audit_stream_done(stream_id, false);
+                    minialloc.dir_entry(stream_id).stream_len
+                };
+                let mut stream = Stream::new(&self.minialloc, stream_id);
+                stream.set_len(0)?;
+                let mut minialloc = self.minialloc_mut();
+                if let Some(bits) = options.state_bits {
+                    minialloc.with_dir_entry_mut(stream_id, |dir_entry| {
+                        dir_entry.state_bits = bits;
+                    })?;
+                }
+                minialloc.audit_stream_done(stream_id, true);
+                minialloc.audit(AuditOp::CreateStream, &path, old_len, 0);
+                return Ok(stream);
+            }
+        }
+        // If names is empty, that means we're trying to create the root.  But
+        // the root always already exists and will have been rejected above.
+        debug_assert!(!names.is_empty());
+        let path = internal::path::path_from_name_chain(&names);
+        let Some(name) = names.pop() else {
+            already_exists!("The root storage always exists");
+        };
+        if internal::path::is_temporary_name(name) {
+            invalid_input!(
+                "Object name {:?} is reserved for temporary objects",
+                name
+            );
+        }
+        let parent_id = self.resolve_name_chain(&names, "parent storage")?;
+        let new_stream_id = {
+            let mut minialloc = self.minialloc_mut();
+            let stream_id = minialloc.insert_dir_entry_with(
+                parent_id,
+                name,
+                ObjType::Stream,
+                |dir_entry| {
+                    if let Some(bits) = options.state_bits {
+                        dir_entry.state_bits = bits;
+                    }
+                },
+            )?;
+            minialloc.audit(AuditOp::CreateStream, &path, 0, 0);
+            stream_id
+        };
+        Ok(Stream::new(&self.minialloc, new_stream_id))
+    }
+
+    /// Creates a stream at the provided path containing `data`, like
+    /// `create_stream` followed by `write_all`, except that if a stream with
+    /// identical contents was previously written with this method (during
+    /// the lifetime of this `CompoundFile`), the new stream will share that
+    /// stream's sector chain instead of storing a second copy of the data.
+    ///
+    /// Sharing is invisible to readers: writing to or resizing either stream
+    /// first gives it its own copy of the data, and removing a stream only
+    /// frees the shared chain once no other stream uses it.  The number of
+    /// streams sharing chains is reported by
+    /// [`Stats::num_shared_streams`](struct.Stats.html#method.num_shared_streams).
+    pub fn create_stream_dedup<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let result = self.create_stream_dedup_with_path(path.as_ref(), data);
+        self.self_check("create_stream_dedup");
+        result.map(|_| ())
+    }
+
+    /// Like `create_stream_dedup`, but returns true if the new stream shares
+    /// another stream's chain.
+    fn create_stream_dedup_with_path(
+        &mut self,
+        path: &Path,
+        data: &[u8],
+    ) -> io::Result<bool> {
+        let content_hash = {
+            let mut hasher = fnv::FnvHasher::default();
+            hasher.write_u64(data.len() as u64);
+            hasher.write(data);
+            hasher.finish()
+        };
+        let mut stream = self.create_stream_with_path(path, true)?;
+        let stream_id = stream.stream_id();
+        let candidates =
+            self.minialloc().dedup_candidates(content_hash).to_vec();
+        for candidate in candidates {
+            if candidate != stream_id
+                && self.stream_has_contents(candidate, data)?
+            {
+                drop(stream);
+                let mut minialloc = self.minialloc_mut();
+                minialloc.audit_stream_modified(stream_id);
+                minialloc.share_chain(candidate, stream_id)?;
+                return Ok(true);
+            }
+        }
+        stream.write_all(data)?;
+        stream.flush()?;
+        drop(stream);
+        self.minialloc_mut().register_content(content_hash, stream_id);
+        Ok(false)
+    }
+
+    /// Returns true if `stream_id` is (still) a stream containing exactly
+    /// `data`.
+    fn stream_has_contents(
+        &mut self,
+        stream_id: u32,
+        data: &[u8],
+    ) -> io::Result<bool> {
+        {
+            let minialloc = self.minialloc();
+            if stream_id >= minialloc.num_dir_entries() {
+                return Ok(false);
+            }
+            let dir_entry = minialloc.dir_entry(stream_id);
+            if dir_entry.obj_type != ObjType::Stream
+                || dir_entry.stream_len != data.len() as u64
+            {
+                return Ok(false);
+            }
+        }
+        let mut contents = Vec::with_capacity(data.len());
+        Stream::new(&self.minialloc, stream_id).read_to_end(&mut contents)?;
+        Ok(contents == data)
+    }
+
+    /// Removes the stream object at the provided path.
+    pub fn remove_stream<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<()> {
+        let result = self.remove_stream_with_path(path.as_ref());
+        self.self_check("remove_stream");
+        result
+    }
+
+    fn remove_stream_with_path(&mut self, path: &Path) -> io::Result<()> {
+        let names = internal::path::name_chain_from_path(path)?;
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        let stream_len = {
+            let minialloc = self.minialloc();
+            let dir_entry = minialloc.dir_entry(stream_id);
+            if dir_entry.obj_type != ObjType::Stream {
+                invalid_input!("Not a stream: {:?}", path);
+            }
+            dir_entry.stream_len
+        };
+        self.minialloc_mut().audit_stream_done(stream_id, false);
+        self.remove_object(stream_id)?;
+        let path = internal::path::path_from_name_chain(&names);
+        self.minialloc_mut().audit(
+            AuditOp::RemoveStream,
+            &path,
+            stream_len,
+            0,
+        );
+        Ok(())
+    }
+
+    /// Removes the given stream or (empty) storage, freeing a stream's data
+    /// unless its chain is shared with other streams.  Doesn't record
+    /// anything in the audit trail.
+    fn remove_object(&mut self, stream_id: u32) -> io::Result<()> {
+        let (name, obj_type, start_sector_id, stream_len) = {
+            let minialloc = self.minialloc();
+            let dir_entry = minialloc.dir_entry(stream_id);
+            debug_assert_eq!(dir_entry.child, consts::NO_STREAM);
+            (
+                dir_entry.name.clone(),
+                dir_entry.obj_type,
+                dir_entry.start_sector,
+                dir_entry.stream_len,
+            )
+        };
+        let Some(parent_id) = self.minialloc().parent_id(stream_id) else {
+            invalid_input!("Cannot remove the root storage object");
+        };
+        let mut minialloc = self.minialloc_mut();
+        if obj_type != ObjType::Stream || minialloc.detach_chain(stream_id)? {
+            // Storages have no data, and chains that other streams still
+            // share must stay allocated.
+        } else if stream_len < consts::MINI_STREAM_CUTOFF as u64 {
+            minialloc.free_mini_chain(start_sector_id)?;
+        } else {
+            minialloc.free_chain(start_sector_id)?;
+        }
+        minialloc.remove_dir_entry(parent_id, &name)
+    }
+
+    /// Creates a new, empty temporary stream within the given storage, and
+    /// returns its stream ID and path.
+    fn create_temporary_stream(
+        &mut self,
+        parent_names: &[&str],
+    ) -> io::Result<(u32, PathBuf)> {
+        let parent_id =
+            self.resolve_name_chain(parent_names, "parent storage")?;
+        let mut minialloc = self.minialloc_mut();
+        for index in 0.. {
+            let name = internal::path::temporary_name(index);
+            let mut names = parent_names.to_vec();
+            names.push(&name);
+            if minialloc.stream_id_for_name_chain(&names).is_some() {
+                continue;
+            }
+            let stream_id = minialloc.insert_dir_entry(
+                parent_id,
+                &name,
+                ObjType::Stream,
+            )?;
+            let path = internal::path::path_from_name_chain(&names);
+            return Ok((stream_id, path));
+        }
+        unreachable!()
+    }
+
+    /// Removes a temporary object (and, if it's a storage, everything within
+    /// it).  Doesn't record anything in the audit trail.
+    fn remove_temporary(&mut self, path: &Path) -> io::Result<()> {
+        let entries: Vec<Entry> =
+            self.walk_storage_with_path(path)?.include_temporaries().collect();
+        for entry in entries.iter().rev() {
+            let names = internal::path::name_chain_from_path(entry.path())?;
+            let Some(stream_id) = self.stream_id_for_name_chain(&names)?
+            else {
+                continue;
+            };
+            self.minialloc_mut().audit_stream_done(stream_id, true);
+            self.remove_object(stream_id)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the temporary objects that were found when the file was
+    /// opened (see [`set_show_temporaries`](#method.set_show_temporaries)).
+    fn remove_leaked_temporaries(&mut self) -> io::Result<()> {
+        while let Some(path) = self.leaked_temporaries.last().cloned() {
+            self.remove_temporary(&path)?;
+            self.leaked_temporaries.pop();
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of the existing stream at the given path with
+    /// the data read from `reader`, returning the number of bytes written.
+    ///
+    /// Unlike `create_stream` followed by writing, this never leaves the
+    /// stream half-written: the new data is first written to a temporary
+    /// stream in the same storage, and only once that has succeeded is the
+    /// stream switched over to it (with a single directory entry write).  If
+    /// this fails partway through, the stream keeps its old contents, and
+    /// the temporary stream is removed (or, if it can't be, for example
+    /// because the process crashed, it is cleaned up the next time the file
+    /// is opened and flushed; see
+    /// [`set_show_temporaries`](#method.set_show_temporaries)).
+    pub fn replace_stream<P: AsRef<Path>, R: Read>(
+        &mut self,
+        path: P,
+        reader: &mut R,
+    ) -> io::Result<u64> {
+        let result = self.replace_stream_with_path(path.as_ref(), reader);
+        self.self_check("replace_stream");
+        result
+    }
+
+    fn replace_stream_with_path<R: Read>(
+        &mut self,
+        path: &Path,
+        reader: &mut R,
+    ) -> io::Result<u64> {
+        let mut names = internal::path::name_chain_from_path(path)?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        if self.minialloc().dir_entry(stream_id).obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path);
+        }
+        names.pop();
+        let (temp_id, temp_path) = self.create_temporary_stream(&names)?;
+        let mut temp = Stream::new(&self.minialloc, temp_id);
+        let num_bytes =
+            match io::copy(reader, &mut temp).and_then(|num_bytes| {
+                temp.flush()?;
+                Ok(num_bytes)
+            }) {
+                Ok(num_bytes) => num_bytes,
+                Err(error) => {
+                    drop(temp);
+                    // If this fails too, the temporary stream is left for the
+                    // next session to clean up.
+                    let _ = self.remove_temporary(&temp_path);
+                    return Err(error);
+                }
+            };
+        drop(temp);
+        let mut minialloc = self.minialloc_mut();
+        minialloc.audit_stream_modified(stream_id);
+        minialloc.adopt_chain(temp_id, stream_id)?;
+        minialloc.audit_stream_done(stream_id, false);
+        drop(minialloc);
+        self.remove_temporary(&temp_path)?;
+        Ok(num_bytes)
+    }
+
+    /// Overwrites `bytes.len()` bytes of the existing stream at the given
+    /// path, starting at `offset`, in place, as when filling in a signature
+    /// or checksum field of a file that is otherwise complete.
+    ///
+    /// Unlike writing through a [`Stream`], this guarantees that nothing but
+    /// those bytes changes: only the parts of the (mini) sectors that hold
+    /// them are written, the stream's sector chain is never reallocated or
+    /// moved (even between the mini stream and regular sectors), and its
+    /// directory entry, including its length, is left alone.  (Writing to a
+    /// stream never changes any timestamps, since the CFB spec requires the
+    /// timestamps of streams to be zero.)  If `offset + bytes.len()` is past
+    /// the end of the stream, this fails without writing anything, as it
+    /// does for a stream whose chain it shares with another stream (see
+    /// [`create_stream_dedup`](#method.create_stream_dedup)), since
+    /// patching one would change the other.
+    ///
+    /// Like [`read_stream_at`](#method.read_stream_at), this works directly
+    /// on the underlying file, so data buffered in open [`Stream`] handles
+    /// for the same stream isn't seen, and may overwrite the patch when it
+    /// is flushed.
+    pub fn patch_stream<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        offset: u64,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        let result = self.patch_stream_with_path(path.as_ref(), offset, bytes);
+        self.self_check("patch_stream");
+        result
+    }
+
+    fn patch_stream_with_path(
+        &mut self,
+        path: &Path,
+        offset: u64,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        let names = internal::path::name_chain_from_path(path)?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "stream")?;
+        let mut minialloc = self.minialloc_mut();
+        let dir_entry = minialloc.dir_entry(stream_id);
+        if dir_entry.obj_type != ObjType::Stream {
+            invalid_input!("Not a stream: {:?}", path);
+        }
+        let stream_len = dir_entry.stream_len;
+        let end = offset.checked_add(bytes.len() as u64);
+        if end.map_or(true, |end| end > stream_len) {
+            invalid_input!(
+                "Cannot patch {} bytes at offset {} of {:?}, which is only \
+                 {} bytes long",
+                bytes.len(),
+                offset,
+                path,
+                stream_len
+            );
+        }
+        if minialloc.is_shared(stream_id) {
+            invalid_input!(
+                "Cannot patch {:?} in place, because its chain is shared \
+                 with another stream",
+                path
+            );
+        }
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        minialloc.patch_stream(stream_id, offset, bytes)
+    }
+
+    /// Replaces the Authenticode signature stream
+    /// (`\u{5}DigitalSignature`) in the root storage with the given data, or
+    /// removes it if `signature` is `None`.
+    pub fn set_digital_signature(
+        &mut self,
+        signature: Option<&[u8]>,
+    ) -> io::Result<()> {
+        self.set_root_stream(DIGITAL_SIGNATURE_STREAM_NAME, signature)
+    }
+
+    /// Replaces the extended signature stream
+    /// (`\u{5}MsiDigitalSignatureEx`) in the root storage with the given
+    /// data, or removes it if `data` is `None`.
+    pub fn set_msi_digital_signature_ex(
+        &mut self,
+        data: Option<&[u8]>,
+    ) -> io::Result<()> {
+        self.set_root_stream(MSI_DIGITAL_SIGNATURE_EX_STREAM_NAME, data)
+    }
+
+    fn set_root_stream(
+        &mut self,
+        name: &str,
+        data: Option<&[u8]>,
+    ) -> io::Result<()> {
+        let path = internal::path::path_from_name_chain(&[name]);
+        match data {
+            Some(data) => self.create_stream(&path)?.write_all(data),
+            None if self.is_stream(&path) => self.remove_stream(&path),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the user-defined bitflags for the object at the provided path.
+    /// (To get the current state bits for an object, use
+    /// `self.entry(path)?.state_bits()`.)
+    pub fn set_state_bits<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        bits: u32,
+    ) -> io::Result<()> {
+        let result = self.set_state_bits_with_path(path.as_ref(), bits);
+        self.self_check("set_state_bits");
+        result.map(|_| ())
+    }
+
+    /// Like [`set_state_bits`](#method.set_state_bits), but returns the
+    /// object's previous state bits.  On error, nothing is changed.
+    pub fn replace_state_bits<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        bits: u32,
+    ) -> io::Result<u32> {
+        let result = self.set_state_bits_with_path(path.as_ref(), bits);
+        self.self_check("replace_state_bits");
+        result
+    }
+
+    fn set_state_bits_with_path(
+        &mut self,
+        path: &Path,
+        bits: u32,
+    ) -> io::Result<u32> {
+        self.set_entry_with_path(path, |dir_entry| {
+            std::mem::replace(&mut dir_entry.state_bits, bits)
+        })
+    }
+
+    /// Sets the modified time for the object at the given path to now.  This
+    /// is shorthand for `touch_with(path, TouchOptions::new())`: the created
+    /// time is never changed (even if it is zero), nothing else is touched,
+    /// and calling it on a stream has no effect, since the CFB spec requires
+    /// the timestamps of streams to be zero.
+    pub fn touch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.touch_with(path, TouchOptions::new())
+    }
+
+    /// Updates the timestamps of the object at the given path and, depending
+    /// on the options, of the storages containing it or of everything
+    /// within it.  Each touched storage gets the modified time from the
+    /// options (or the current time), and also the created time if one is
+    /// given; the root storage gets only the modified time, and streams are
+    /// left alone, as with
+    /// [`set_modified_time`](#method.set_modified_time) and
+    /// [`set_created_time`](#method.set_created_time).
+    ///
+    /// The changed directory entries are written together, so touching a
+    /// large subtree writes each directory sector once.  Fails with an
+    /// `InvalidInput` error, without changing anything, if either time is
+    /// out of range for a CFB timestamp.
+    pub fn touch_with<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: TouchOptions,
+    ) -> io::Result<()> {
+        let result = self.touch_with_path(path.as_ref(), options);
+        self.self_check("touch_with");
+        result
+    }
+
+    fn touch_with_path(
+        &mut self,
+        path: &Path,
+        options: TouchOptions,
+    ) -> io::Result<()> {
+        let modified = checked_timestamp(
+            options.modified.unwrap_or_else(std::time::SystemTime::now),
+        )?;
+        let created = options.created.map(checked_timestamp).transpose()?;
+        let names = internal::path::name_chain_from_path(path)?;
+        let stream_id = self.resolve_name_chain(&names, "object")?;
+        let mut minialloc = self.minialloc_mut();
+        let mut stream_ids = if options.recursive {
+            minialloc.subtree_ids(stream_id)
+        } else {
+            vec![stream_id]
+        };
+        if options.ancestors {
+            let mut parent_id = minialloc.parent_id(stream_id);
+            while let Some(stream_id) = parent_id {
+                stream_ids.push(stream_id);
+                parent_id = minialloc.parent_id(stream_id);
+            }
+        }
+        stream_ids.retain(|&stream_id| {
+            minialloc.dir_entry(stream_id).obj_type != ObjType::Stream
+        });
+        minialloc.with_dir_entries_mut(&stream_ids, |dir_entry| {
+            dir_entry.modified_time = modified;
+            if let Some(created) = created {
+                if dir_entry.obj_type == ObjType::Storage {
+                    dir_entry.creation_time = created;
+                }
+            }
+        })?;
+        minialloc.audit_metadata(&stream_ids);
+        Ok(())
+    }
+
+    /// Sets the modified time for the object at the given path.  The time is
+    /// stored with 100-nanosecond precision (anything finer is truncated),
+    /// and must be no earlier than January 1, 1601 UTC (the CFB epoch, which
+    /// reads back as a zero timestamp) and within the range of a 64-bit CFB
+    /// timestamp; otherwise, this fails with an `InvalidInput` error.
+    ///
+    /// Has no effect on streams, since the CFB spec requires their
+    /// timestamps to be zero.  Nothing else changes an object's times after
+    /// it is created (writing to a stream doesn't touch its storage), so
+    /// times set with this method are kept until they are set again.
+    pub fn set_modified_time<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        ts: std::time::SystemTime,
+    ) -> io::Result<()> {
+        let result = self.set_modified_time_with_path(path.as_ref(), ts);
+        self.self_check("set_modified_time");
+        result.map(|_| ())
+    }
+
+    /// Like [`set_modified_time`](#method.set_modified_time), but returns
+    /// the object's previous modified time, as
+    /// [`Entry::modified`](struct.Entry.html#method.modified) would have.
+    /// On error, nothing is changed.
+    pub fn replace_modified_time<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        ts: std::time::SystemTime,
+    ) -> io::Result<std::time::SystemTime> {
+        let result = self.set_modified_time_with_path(path.as_ref(), ts);
+        self.self_check("replace_modified_time");
+        result
+    }
+
+    fn set_modified_time_with_path(
+        &mut self,
+        path: &Path,
+        ts: std::time::SystemTime,
+    ) -> io::Result<std::time::SystemTime> {
+        let ts = checked_timestamp(ts)?;
+        self.set_entry_with_path(path, |dir_entry| {
+            let old_ts = dir_entry.modified_time;
+            if dir_entry.obj_type != ObjType::Stream {
+                dir_entry.modified_time = ts;
+            }
+            old_ts.to_system_time()
+        })
+    }
+
+    /// Sets the created time for the object at the given path, which must be
+    /// in the same range as for
+    /// [`set_modified_time`](#method.set_modified_time).
+    /// Has no effect on streams or on the root storage due to requirements
+    /// imposed by CFB spec.
+    pub fn set_created_time<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        ts: std::time::SystemTime,
+    ) -> io::Result<()> {
+        let result = self.set_created_time_with_path(path.as_ref(), ts);
+        self.self_check("set_created_time");
+        result.map(|_| ())
+    }
+
+    /// Like [`set_created_time`](#method.set_created_time), but returns the
+    /// object's previous created time, as
+    /// [`Entry::created`](struct.Entry.html#method.created) would have.  On
+    /// error, nothing is changed.
+    pub fn replace_created_time<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        ts: std::time::SystemTime,
+    ) -> io::Result<std::time::SystemTime> {
+        let result = self.set_created_time_with_path(path.as_ref(), ts);
+        self.self_check("replace_created_time");
+        result
+    }
+
+    fn set_created_time_with_path(
+        &mut self,
+        path: &Path,
+        ts: std::time::SystemTime,
+    ) -> io::Result<std::time::SystemTime> {
+        let ts = checked_timestamp(ts)?;
+        self.set_entry_with_path(path, |dir_entry| {
+            let old_ts = dir_entry.creation_time;
+            if dir_entry.obj_type == ObjType::Storage {
+                dir_entry.creation_time = ts;
+            }
+            old_ts.to_system_time()
+        })
+    }
+
+    /// Copies the selected metadata fields of the object at `from` to the
+    /// object at `to`, with a single directory entry write.  Times are
+    /// copied following the same rules as
+    /// [`set_created_time`](#method.set_created_time) and
+    /// [`set_modified_time`](#method.set_modified_time), so they are left
+    /// alone on streams (and the creation time on the root).  Fails with an
+    /// `InvalidInput` error, without changing anything, if
+    /// [`MetadataFields::CLSID`] is selected and either object is a stream,
+    /// since streams don't have CLSIDs.
+    pub fn copy_metadata<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        from: P,
+        to: Q,
+        fields: MetadataFields,
+    ) -> io::Result<()> {
+        let result = self.entry(from).and_then(|source| {
+            self.copy_metadata_from_entry_with_path(
+                &source,
+                to.as_ref(),
+                fields,
+            )
+        });
+        self.self_check("copy_metadata");
+        result
+    }
+
+    /// Like [`copy_metadata`](#method.copy_metadata), but copies from an
+    /// [`Entry`] snapshot, which may have come from a different compound
+    /// file.
+    pub fn copy_metadata_from_entry<P: AsRef<Path>>(
+        &mut self,
+        source: &Entry,
+        to: P,
+        fields: MetadataFields,
+    ) -> io::Result<()> {
+        let result = self.copy_metadata_from_entry_with_path(
+            source,
+            to.as_ref(),
+            fields,
+        );
+        self.self_check("copy_metadata_from_entry");
+        result
+    }
+
+    fn copy_metadata_from_entry_with_path(
+        &mut self,
+        source: &Entry,
+        to: &Path,
+        fields: MetadataFields,
+    ) -> io::Result<()> {
+        let names = internal::path::name_chain_from_path(to)?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "object")?;
+        let mut minialloc = self.minialloc_mut();
+        let target_is_stream =
+            minialloc.dir_entry(stream_id).obj_type == ObjType::Stream;
+        if fields.contains(MetadataFields::CLSID)
+            && (source.is_stream() || target_is_stream)
+        {
+            invalid_input!(
+                "Cannot copy the CLSID of {:?} to {:?}, because only \
+                 storages have CLSIDs",
+                source.path(),
+                path
+            );
+        }
+        minialloc.with_dir_entry_mut(stream_id, |dir_entry| {
+            source.copy_metadata_into(dir_entry, fields)
+        })?;
+        let stream_len = minialloc.dir_entry(stream_id).stream_len;
+        minialloc.audit(AuditOp::SetMetadata, &path, stream_len, stream_len);
+        Ok(())
+    }
+
+    /// Puts back the metadata recorded in a snapshot made by
+    /// [`export_metadata`](#method.export_metadata): each object that is in
+    /// both the snapshot and the file (at the same path, and of the same
+    /// kind) gets back the CLSID, state bits, and timestamps it had, exactly
+    /// as they were, with a directory entry write only if they have
+    /// changed.  What happens to objects that are only in one or the other
+    /// is up to the `policy`; with the default policy, such objects make
+    /// this fail without changing anything.
+    ///
+    /// This never changes the contents or length of a stream that still
+    /// exists.  Objects recreated with
+    /// [`MissingEntries::RecreateEmpty`](enum.MissingEntries.html#variant.RecreateEmpty)
+    /// are empty, since the snapshot doesn't hold their contents.
+    pub fn restore_metadata(
+        &mut self,
+        snapshot: &MetadataSnapshot,
+        policy: RestorePolicy,
+    ) -> io::Result<RestoreReport> {
+        let result = self.restore_metadata_internal(snapshot, &policy);
+        self.self_check("restore_metadata");
+        result
+    }
+
+    fn restore_metadata_internal(
+        &mut self,
+        snapshot: &MetadataSnapshot,
+        policy: &RestorePolicy,
+    ) -> io::Result<RestoreReport> {
+        // Match up the snapshot's objects with the file's, by path, before
+        // changing anything, so that the policy's errors leave the file
+        // alone.
+        let mut matched = FnvHashSet::<u32>::default();
+        let mut missing = Vec::<&SnapshotEntry>::new();
+        let mut in_the_way = Vec::<&SnapshotEntry>::new();
+        for entry in snapshot.entries() {
+            match self.entry_with_path(entry.path()) {
+                Ok(current) if current.file_type() == entry.kind() => {
+                    matched.insert(current.stream_id().value());
+                }
+                Ok(_) => {
+                    missing.push(entry);
+                    in_the_way.push(entry);
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    missing.push(entry);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        let extra: Vec<Entry> = self
+            .walk()
+            .filter(|entry| {
+                !entry.is_root()
+                    && !matched.contains(&entry.stream_id().value())
+            })
+            .collect();
+        if let Some(entry) = missing.first() {
+            if policy.missing == MissingEntries::Error {
+                not_found!(
+                    "{:?} is in the metadata snapshot, but not in the file",
+                    entry.path()
+                );
+            }
+        }
+        if let Some(entry) = extra.first() {
+            if policy.extra == ExtraEntries::Error {
+                already_exists!(
+                    "{:?} is in the file, but not in the metadata snapshot",
+                    entry.path()
+                );
+            }
+        }
+        if let Some(entry) = in_the_way.first() {
+            if policy.missing == MissingEntries::RecreateEmpty
+                && policy.extra != ExtraEntries::Remove
+            {
+                already_exists!(
+                    "Cannot recreate {:?}, because an object of another \
+                     kind is in the way",
+                    entry.path()
+                );
+            }
+        }
+
+        let mut report = RestoreReport::default();
+        for entry in extra {
+            let path = entry.path();
+            if policy.extra == ExtraEntries::Skip {
+                report.skipped.push(path.to_path_buf());
+                continue;
+            }
+            if report.removed.iter().any(|removed| path.starts_with(removed)) {
+                continue;
+            }
+            if entry.is_stream() {
+                self.remove_stream_with_path(path)?;
+            } else {
+                self.remove_storage_all_with_path(path)?;
+            }
+            report.removed.push(path.to_path_buf());
+        }
+        let missing: FnvHashSet<&Path> =
+            missing.iter().map(|entry| entry.path()).collect();
+        for entry in snapshot.entries() {
+            let path = entry.path();
+            if missing.contains(path) {
+                if policy.missing == MissingEntries::Skip {
+                    report.skipped.push(path.to_path_buf());
+                    continue;
+                }
+                if entry.kind() == EntryKind::Stream {
+                    self.create_stream_with_path(path, false)?;
+                } else {
+                    self.create_storage_with_path(path)?;
+                }
+                report.recreated.push(path.to_path_buf());
+            }
+            let names = internal::path::name_chain_from_path(path)?;
+            let stream_id = self.resolve_name_chain(&names, "object")?;
+            let mut minialloc = self.minialloc_mut();
+            if entry.matches(minialloc.dir_entry(stream_id)) {
+                continue;
+            }
+            minialloc.with_dir_entry_mut(stream_id, |dir_entry| {
+                entry.copy_into(dir_entry)
+            })?;
+            let stream_len = minialloc.dir_entry(stream_id).stream_len;
+            minialloc.audit(
+                AuditOp::SetMetadata,
+                path,
+                stream_len,
+                stream_len,
+            );
+            if !missing.contains(path) {
+                report.updated.push(path.to_path_buf());
+            }
+        }
+        Ok(report)
+    }
+
+    fn set_entry_with_path<T, G: FnOnce(&mut DirEntry) -> T>(
+        &mut self,
+        path: &Path,
+        f: G,
+    ) -> io::Result<T> {
+        let names = internal::path::name_chain_from_path(path)?;
+        let path = internal::path::path_from_name_chain(&names);
+        let stream_id = self.resolve_name_chain(&names, "object")?;
+        let mut minialloc = self.minialloc_mut();
+        let result = minialloc.with_dir_entry_mut(stream_id, f)?;
+        let stream_len = minialloc.dir_entry(stream_id).stream_len;
+        minialloc.audit(AuditOp::SetMetadata, &path, stream_len, stream_len);
+        Ok(result)
+    }
+
+    /// Installs a policy deciding where newly allocated sectors are placed
+    /// within the file, replacing the default [`FirstFree`] policy.  Only
+    /// sectors allocated from now on are affected; nothing already in the
+    /// file is moved.  See [`SectorAllocator`] for how the policy's choices
+    /// are checked.
+    pub fn set_allocator(&mut self, allocator: Box<dyn SectorAllocator>) {
+        self.minialloc_mut().set_allocator_policy(allocator);
+    }
+
+    /// Starts keeping an audit trail of modifications to this compound file
+    /// in the stream at the given path, which is created (on the next
+    /// flush) if it doesn't exist yet.  Every storage or stream created,
+    /// removed, or renamed, stream written to or resized, and metadata
+    /// change from now on is recorded, along with when it was made and the
+    /// object's sizes before and after; streams written to several times
+    /// between flushes get a single record (unless something is renamed in
+    /// between).  Whenever the compound file is flushed, the
+    /// records made since the previous flush are appended to the stream.
+    /// Read them back with [`read_audit_trail`](#method.read_audit_trail).
+    ///
+    /// Changes to the audit trail stream itself are never recorded.  The
+    /// stream is an ordinary stream, so it is kept by anything that keeps
+    /// the file's streams (including
+    /// [`shrink_to_fit`](#method.shrink_to_fit) and copying with
+    /// `tool::extract_all` and `tool::insert_all`, from the `cli` feature),
+    /// and the records from each editing session are added to those from
+    /// earlier sessions.
+    /// Auditing is not remembered in the file, so it must be enabled again
+    /// each time the file is opened.  Calling this again with a different
+    /// path sends the records not yet written to the new stream instead.
+    pub fn enable_audit_trail<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<()> {
+        let names = internal::path::name_chain_from_path(path.as_ref())?;
+        if names.is_empty() {
+            invalid_input!("The audit trail cannot be the root storage");
+        }
+        for name in names.iter() {
+            internal::path::validate_name(name)?;
+        }
+        let path = internal::path::path_from_name_chain(&names);
+        if self.is_storage(&path) {
+            invalid_input!("Not a stream: {:?}", path);
+        }
+        self.minialloc_mut().enable_audit(path);
+        Ok(())
     }
 
-    fn remove_storage_with_path(&mut self, path: &Path) -> io::Result<()> {
-        let mut names = internal::path::name_chain_from_path(path)?;
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(parent_id) => parent_id,
-            None => not_found!("No such storage: {:?}", path),
+    /// Appends any audit records made since the last flush to the audit
+    /// trail stream (if auditing is enabled).
+    fn write_audit_trail(&mut self) -> io::Result<()> {
+        let (path, data, count) =
+            match self.minialloc_mut().pending_audit_records()? {
+                Some(pending) => pending,
+                None => return Ok(()),
+            };
+        let mut stream = if self.is_stream(&path) {
+            self.open_stream_with_path(&path)?
+        } else {
+            self.create_stream_with_path(&path, false)?
         };
-        {
-            let minialloc = self.minialloc();
-            let dir_entry = minialloc.dir_entry(stream_id);
-            if dir_entry.obj_type == ObjType::Root {
-                invalid_input!("Cannot remove the root storage object");
-            }
-            if dir_entry.obj_type == ObjType::Stream {
-                invalid_input!("Not a storage: {:?}", path);
-            }
-            debug_assert_eq!(dir_entry.obj_type, ObjType::Storage);
-            if dir_entry.child != consts::NO_STREAM {
-                invalid_input!("Storage is not empty: {:?}", path);
-            }
-        }
-        debug_assert!(!names.is_empty());
-        let name = names.pop().unwrap();
-        let parent_id = self.stream_id_for_name_chain(&names).unwrap();
-        self.minialloc_mut().remove_dir_entry(parent_id, name)?;
+        stream.seek(SeekFrom::End(0))?;
+        stream.write_all(&data)?;
+        stream.flush()?;
+        drop(stream);
+        self.minialloc_mut().clear_audit_records(count);
         Ok(())
     }
 
-    /// Recursively removes a storage and all of its children.  If called on
-    /// the root storage, recursively removes all of its children but not the
-    /// root storage itself (which cannot be removed).
-    pub fn remove_storage_all<P: AsRef<Path>>(
-        &mut self,
-        path: P,
-    ) -> io::Result<()> {
-        self.remove_storage_all_with_path(path.as_ref())
+    /// Flushes all changes to the underlying file.  If the file was created
+    /// with capacity hints (see `CreateOptions`), this also releases any
+    /// reserved sectors that are still unused, unless the options say to keep
+    /// them.
+    ///
+    /// Before writing anything, this checks that the underlying file is still
+    /// as long as its sectors require, and fails with
+    /// [`BackingFileShrunk`](struct.BackingFileShrunk.html) if it has been
+    /// truncated by someone else, since writing a FAT describing sectors past
+    /// the new end of the file would only make things worse.  Once that has
+    /// happened, flushing keeps failing until [`repair`](#method.repair) or
+    /// [`reload`](#method.reload) is called.
+    ///
+    /// Writes made through a [`Stream`] are buffered in that handle until it
+    /// is flushed or dropped, and this method can't reach them.  Rather than
+    /// quietly writing a file without them, it fails with
+    /// [`StreamsStillOpen`] (an `InvalidInput` error) if any open handles
+    /// still hold unflushed writes, without writing anything; flush or drop
+    /// those handles and try again.  Handles to streams that have since been
+    /// removed don't count.  Since a handle leaked with `mem::forget` never
+    /// flushes, the only way past one is to remove its stream.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let result = self.flush_internal();
+        self.self_check("flush");
+        result
     }
 
-    fn remove_storage_all_with_path(&mut self, path: &Path) -> io::Result<()> {
-        let mut stack = self.walk_storage(path)?.collect::<Vec<Entry>>();
-        while let Some(entry) = stack.pop() {
-            if entry.is_stream() {
-                self.remove_stream_with_path(entry.path())?;
-            } else if !entry.is_root() {
-                self.remove_storage_with_path(entry.path())?;
-            }
+    fn flush_internal(&mut self) -> io::Result<()> {
+        self.check_no_unflushed_streams()?;
+        self.minialloc_mut().check_backing_len()?;
+        self.remove_leaked_temporaries()?;
+        self.write_audit_trail()?;
+        if self.header_version_mismatch {
+            self.minialloc_mut().rewrite_header_version()?;
+            self.header_version_mismatch = false;
         }
+        let mut minialloc = self.minialloc_mut();
+        let timer = minialloc.metrics().start();
+        minialloc.release_reservations()?;
+        minialloc.flush()?;
+        minialloc.metrics().record(Op::Flush, timer, 0);
         Ok(())
     }
 
-    /// Sets the CLSID for the storage object at the provided path.  (To get
-    /// the current CLSID for a storage object, use
-    /// `self.entry(path)?.clsid()`.)
-    pub fn set_storage_clsid<P: AsRef<Path>>(
+    /// Recovers from the underlying file having been truncated by someone
+    /// else (see [`BackingFileShrunk`](struct.BackingFileShrunk.html)) by
+    /// zero-filling it back up to the length that the compound file
+    /// requires, and allows flushing again.  The contents of any sectors that
+    /// were cut off are lost, so streams that used them will read back zeros
+    /// there.  Returns the number of bytes that were zero-filled (which is
+    /// zero if the file hadn't shrunk).
+    pub fn repair(&mut self) -> io::Result<u64> {
+        let result = self.minialloc_mut().repair_backing_len();
+        self.self_check("repair");
+        result
+    }
+
+    /// Strips metadata that commonly leaks private information before the
+    /// compound file is shared, as selected by the given options (see
+    /// [`SanitizeOptions`](struct.SanitizeOptions.html) for the defaults),
+    /// then flushes the file and returns a report of what was changed:
+    ///
+    /// * Identifying properties (such as the author) in summary information
+    ///   property sets are blanked out in place.
+    /// * Storage creation and modification times are cleared.
+    /// * Storage CLSIDs are cleared (only if requested).
+    /// * Free sectors, the unused ends of stream sectors, and unallocated
+    ///   directory entries, which may hold data from removed or truncated
+    ///   streams, are overwritten with zeros.
+    /// * The header's transaction signature and reserved fields are reset.
+    ///
+    /// Other than the property set streams, no stream's contents are
+    /// changed.
+    pub fn sanitize(
         &mut self,
-        path: P,
-        clsid: Uuid,
-    ) -> io::Result<()> {
-        self.set_storage_clsid_with_path(path.as_ref(), clsid)
+        options: SanitizeOptions,
+    ) -> io::Result<SanitizeReport> {
+        let result = self.sanitize_internal(options);
+        self.self_check("sanitize");
+        result
     }
 
-    fn set_storage_clsid_with_path(
+    fn sanitize_internal(
         &mut self,
-        path: &Path,
-        clsid: Uuid,
-    ) -> io::Result<()> {
-        let names = internal::path::name_chain_from_path(path)?;
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!(
-                "No such storage: {:?}",
-                internal::path::path_from_name_chain(&names)
-            ),
-        };
-        let mut minialloc = self.minialloc_mut();
-        if minialloc.dir_entry(stream_id).obj_type == ObjType::Stream {
-            invalid_input!(
-                "Not a storage: {:?}",
-                internal::path::path_from_name_chain(&names)
-            );
+        options: SanitizeOptions,
+    ) -> io::Result<SanitizeReport> {
+        let mut report = SanitizeReport::default();
+        if !options.keep_properties {
+            let paths: Vec<PathBuf> = self
+                .walk()
+                .filter(|entry| {
+                    entry.is_stream() && is_property_set_stream(entry.path())
+                })
+                .map(|entry| entry.path().to_path_buf())
+                .collect();
+            for path in paths {
+                let mut data = self.read_stream_to_vec(&path)?;
+                match scrub_property_set(&mut data, !options.keep_times) {
+                    Some(0) => {}
+                    Some(num_scrubbed) => {
+                        report.num_properties_scrubbed += num_scrubbed;
+                        self.open_stream_with_path(&path)?.write_all(&data)?;
+                    }
+                    None => {
+                        self.remove_stream_with_path(&path)?;
+                        report.removed_property_sets.push(path);
+                    }
+                }
+            }
         }
-        minialloc.with_dir_entry_mut(stream_id, |dir_entry| {
-            dir_entry.clsid = clsid;
-        })
+        if options.clear_clsids {
+            let paths: Vec<PathBuf> = self
+                .walk()
+                .filter(|entry| {
+                    entry.is_storage()
+                        && !entry.is_root()
+                        && !entry.clsid().is_nil()
+                })
+                .map(|entry| entry.path().to_path_buf())
+                .collect();
+            for path in paths {
+                self.set_storage_clsid_with_path(&path, Uuid::nil())?;
+                report.num_clsids_cleared += 1;
+            }
+        }
+        if !options.keep_times {
+            let zero = Timestamp::zero().to_system_time();
+            let paths: Vec<PathBuf> = self
+                .walk()
+                .filter(|entry| {
+                    entry.created() != zero || entry.modified() != zero
+                })
+                .map(|entry| entry.path().to_path_buf())
+                .collect();
+            for path in paths {
+                self.set_entry_with_path(&path, |dir_entry| {
+                    dir_entry.creation_time = Timestamp::zero();
+                    dir_entry.modified_time = Timestamp::zero();
+                })?;
+                report.num_times_cleared += 1;
+            }
+        }
+        // Flush first, so that any sectors released by flushing are wiped
+        // too.
+        self.flush()?;
+        if !options.keep_free_space {
+            report.num_bytes_wiped =
+                self.minialloc_mut().wipe_unused_space()?;
+        }
+        report.header_reset =
+            self.minialloc_mut().reset_unused_header_fields()?;
+        self.flush()?;
+        Ok(report)
     }
 
-    /// Creates and returns a new, empty stream object at the provided path.
-    /// If a stream already exists at that path, it will be replaced by the new
-    /// stream.  The parent storage object must already exist.
-    pub fn create_stream<P: AsRef<Path>>(
+    /// Mirrors the directory `fs_dir` on disk into the storage at
+    /// `inner_path` (which is created, along with any missing parents, if
+    /// it doesn't exist yet), and returns a report of what was changed.
+    /// Directories become storages and files become streams, named after
+    /// the local names.  Only streams whose files have changed are
+    /// rewritten (each with
+    /// [`replace_stream`](#method.replace_stream), so a failed sync never
+    /// leaves a stream half-written), and objects whose source has
+    /// disappeared are removed only if the options say to (see
+    /// [`SyncOptions`](struct.SyncOptions.html) for the defaults).  Nothing
+    /// is flushed.
+    ///
+    /// Since the CFB spec requires stream timestamps to be zero, a file's
+    /// modification time is instead checked against the modified time of
+    /// its storage, which each sync that changes the storage sets to the
+    /// latest modification time of the files synced into it.  So by
+    /// default, a file is considered unchanged if its size matches its
+    /// stream and it hasn't been modified since then.  A directory that
+    /// hasn't changed since the last sync leaves the compound file entirely
+    /// untouched, so that the next flush doesn't write anything.
+    pub fn sync_from_dir<D: AsRef<Path>, P: AsRef<Path>>(
         &mut self,
-        path: P,
-    ) -> io::Result<Stream<F>> {
-        self.create_stream_with_path(path.as_ref(), true)
+        fs_dir: D,
+        inner_path: P,
+        options: SyncOptions,
+    ) -> io::Result<SyncReport> {
+        let result = self.sync_from_dir_internal(
+            fs_dir.as_ref(),
+            inner_path.as_ref(),
+            &options,
+        );
+        self.self_check("sync_from_dir");
+        result
     }
 
-    /// Creates and returns a new, empty stream object at the provided path.
-    /// Returns an error if a stream already exists at that path.  The parent
-    /// storage object must already exist.
-    pub fn create_new_stream<P: AsRef<Path>>(
+    fn sync_from_dir_internal(
         &mut self,
-        path: P,
-    ) -> io::Result<Stream<F>> {
-        self.create_stream_with_path(path.as_ref(), false)
+        fs_dir: &Path,
+        inner_path: &Path,
+        options: &SyncOptions,
+    ) -> io::Result<SyncReport> {
+        let names = internal::path::name_chain_from_path(inner_path)?;
+        let path = internal::path::path_from_name_chain(&names);
+        if !fs::metadata(fs_dir)?.is_dir() {
+            invalid_input!("Not a directory: {:?}", fs_dir);
+        }
+        let mut report = SyncReport::default();
+        let path = match self.entry_with_path(&path) {
+            Ok(entry) if entry.is_storage() => entry.path().to_path_buf(),
+            Ok(_) => invalid_input!("Not a storage: {:?}", path),
+            Err(_) => {
+                self.create_storage_all_with_path(&path)?;
+                report.created.push(path.clone());
+                path
+            }
+        };
+        self.sync_storage(fs_dir, &path, options, &mut report)?;
+        Ok(report)
     }
 
-    fn create_stream_with_path(
+    fn sync_storage(
         &mut self,
+        dir: &Path,
         path: &Path,
-        overwrite: bool,
-    ) -> io::Result<Stream<F>> {
-        let mut names = internal::path::name_chain_from_path(path)?;
-        if let Some(stream_id) = self.stream_id_for_name_chain(&names) {
-            if self.minialloc().dir_entry(stream_id).obj_type
-                != ObjType::Stream
-            {
-                already_exists!(
-                    "Cannot create stream at {:?} because a \
-                                 storage already exists there",
-                    internal::path::path_from_name_chain(&names)
-                );
-            } else if !overwrite {
-                already_exists!(
-                    "Cannot create new stream at {:?} because a \
-                                 stream already exists there",
-                    internal::path::path_from_name_chain(&names)
+        options: &SyncOptions,
+        report: &mut SyncReport,
+    ) -> io::Result<()> {
+        let watermark = Timestamp::from_system_time(
+            self.entry_with_path(path)?.modified(),
+        );
+        let mut local = Vec::new();
+        for dir_entry in fs::read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let Ok(name) = dir_entry.file_name().into_string() else {
+                invalid_input!(
+                    "Cannot sync {:?}, whose name isn't valid Unicode",
+                    dir_entry.path()
                 );
+            };
+            let metadata = fs::metadata(dir_entry.path())?;
+            if metadata.is_dir() || metadata.is_file() {
+                local.push((name, dir_entry.path(), metadata));
+            }
+        }
+        local.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut seen = HashSet::<String>::new();
+        let mut newest = watermark;
+        let mut changed = false;
+        for (name, local_path, metadata) in local {
+            let mut child_path = path.join(&name);
+            let mut existing_len = None;
+            if let Ok(entry) = self.entry_with_path(&child_path) {
+                seen.insert(entry.name().to_string());
+                child_path = path.join(entry.name());
+                if entry.is_storage() == metadata.is_dir() {
+                    existing_len = Some(entry.len());
+                } else {
+                    if entry.is_storage() {
+                        self.remove_storage_all_with_path(&child_path)?;
+                    } else {
+                        self.remove_stream_with_path(&child_path)?;
+                    }
+                    report.removed.push(child_path.clone());
+                    changed = true;
+                }
             } else {
-                let mut stream = Stream::new(&self.minialloc, stream_id);
-                stream.set_len(0)?;
-                return Ok(stream);
+                seen.insert(name);
+            }
+            if metadata.is_dir() {
+                if existing_len.is_none() {
+                    self.create_storage_with_path(&child_path)?;
+                    report.created.push(child_path.clone());
+                    changed = true;
+                }
+                self.sync_storage(&local_path, &child_path, options, report)?;
+                continue;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(Timestamp::checked_from_system_time);
+            if let Some(modified) = modified {
+                if modified.value() > newest.value() {
+                    newest = modified;
+                }
             }
+            if let Some(existing_len) = existing_len {
+                let unchanged = if metadata.len() != existing_len {
+                    false
+                } else if options.compare_contents {
+                    let mut file = fs::File::open(&local_path)?;
+                    let stream = self.open_stream_with_path(&child_path)?;
+                    same_contents(&mut file, stream)?
+                } else {
+                    modified.is_some_and(|modified| {
+                        modified.value() <= watermark.value()
+                    })
+                };
+                if unchanged {
+                    report.num_unchanged += 1;
+                    continue;
+                }
+                let mut file = fs::File::open(&local_path)?;
+                self.replace_stream_with_path(&child_path, &mut file)?;
+                report.updated.push(child_path);
+            } else {
+                let mut file = fs::File::open(&local_path)?;
+                let mut stream =
+                    self.create_stream_with_path(&child_path, false)?;
+                io::copy(&mut file, &mut stream)?;
+                stream.flush()?;
+                report.created.push(child_path);
+            }
+            changed = true;
         }
-        // If names is empty, that means we're trying to create the root.  But
-        // the root always already exists and will have been rejected above.
-        debug_assert!(!names.is_empty());
-        let name = names.pop().unwrap();
-        let parent_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!("Parent storage doesn't exist"),
-        };
-        let new_stream_id = self.minialloc_mut().insert_dir_entry(
-            parent_id,
-            name,
-            ObjType::Stream,
-        )?;
-        Ok(Stream::new(&self.minialloc, new_stream_id))
+        if options.remove_missing {
+            let missing: Vec<Entry> = self
+                .read_storage(path)?
+                .filter(|entry| !seen.contains(entry.name()))
+                .collect();
+            for entry in missing {
+                if entry.is_storage() {
+                    self.remove_storage_all_with_path(entry.path())?;
+                } else {
+                    self.remove_stream_with_path(entry.path())?;
+                }
+                report.removed.push(entry.path().to_path_buf());
+            }
+        }
+        if changed && newest != watermark {
+            self.set_modified_time_with_path(path, newest.to_system_time())?;
+        }
+        Ok(())
     }
 
-    /// Removes the stream object at the provided path.
-    pub fn remove_stream<P: AsRef<Path>>(
+    /// Copies the directory tree `fs_dir` on disk into the storage at
+    /// `dest_storage` (which is created, along with any missing parents, if
+    /// it doesn't exist yet), and returns a report of what was done.  Each
+    /// subdirectory becomes a storage and each file becomes a stream, named
+    /// after the local name (or whatever
+    /// [`ImportOptions::map_names`](struct.ImportOptions.html#method.map_names)
+    /// makes of it); files are copied a piece at a time, rather than being
+    /// read into memory whole.  Directories are merged into storages that
+    /// already exist, but nothing else is replaced.  Nothing is flushed.
+    ///
+    /// A local file or directory that can't be imported doesn't stop the
+    /// rest of the import: it is listed in
+    /// [`ImportReport::failures`](struct.ImportReport.html#method.failures)
+    /// instead, with the reason.  That covers names that aren't valid object
+    /// names (too long, or containing `/`, `\`, `:`, or `!`), names that are
+    /// already taken, and local files that can't be read (a stream whose
+    /// file fails part-way through is removed again).  Errors writing to the
+    /// compound file itself still end the import.
+    pub fn create_storage_from_dir<D: AsRef<Path>, P: AsRef<Path>>(
         &mut self,
-        path: P,
-    ) -> io::Result<()> {
-        self.remove_stream_with_path(path.as_ref())
+        fs_dir: D,
+        dest_storage: P,
+        mut options: ImportOptions,
+    ) -> io::Result<ImportReport> {
+        let result = self.create_storage_from_dir_internal(
+            fs_dir.as_ref(),
+            dest_storage.as_ref(),
+            &mut options,
+        );
+        self.self_check("create_storage_from_dir");
+        result
     }
 
-    fn remove_stream_with_path(&mut self, path: &Path) -> io::Result<()> {
-        let mut names = internal::path::name_chain_from_path(path)?;
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(parent_id) => parent_id,
-            None => not_found!("No such stream: {:?}", path),
-        };
-        let (start_sector_id, is_in_mini_stream) = {
-            let minialloc = self.minialloc();
-            let dir_entry = minialloc.dir_entry(stream_id);
-            if dir_entry.obj_type != ObjType::Stream {
-                invalid_input!("Not a stream: {:?}", path);
+    fn create_storage_from_dir_internal(
+        &mut self,
+        fs_dir: &Path,
+        dest_storage: &Path,
+        options: &mut ImportOptions,
+    ) -> io::Result<ImportReport> {
+        let names = internal::path::name_chain_from_path(dest_storage)?;
+        let path = internal::path::path_from_name_chain(&names);
+        let metadata = fs::metadata(fs_dir)?;
+        if !metadata.is_dir() {
+            invalid_input!("Not a directory: {:?}", fs_dir);
+        }
+        let mut report = ImportReport::default();
+        let path = match self.entry_with_path(&path) {
+            Ok(entry) if entry.is_storage() => entry.path().to_path_buf(),
+            Ok(_) => invalid_input!("Not a storage: {:?}", path),
+            Err(_) => {
+                self.create_storage_all_with_path(&path)?;
+                report.created.push(path.clone());
+                path
             }
-            debug_assert_eq!(dir_entry.child, consts::NO_STREAM);
-            (
-                dir_entry.start_sector,
-                dir_entry.stream_len < consts::MINI_STREAM_CUTOFF as u64,
-            )
         };
-        if is_in_mini_stream {
-            self.minialloc_mut().free_mini_chain(start_sector_id)?;
-        } else {
-            self.minialloc_mut().free_chain(start_sector_id)?;
+        let children = read_local_dir(fs_dir)?;
+        let mut ancestors = vec![fs::canonicalize(fs_dir)?];
+        let mut buffer = vec![0; 64 * 1024];
+        self.import_dir(
+            children,
+            &path,
+            options,
+            &mut ancestors,
+            &mut buffer,
+            &mut report,
+        )?;
+        if options.preserve_times {
+            self.preserve_modified_time(&path, &metadata)?;
+        }
+        Ok(report)
+    }
+
+    /// Imports the given children of a local directory into the storage at
+    /// `path`.  `ancestors` holds the canonical paths of the directories
+    /// being imported, so that symlink loops can be caught.
+    fn import_dir(
+        &mut self,
+        children: Vec<(PathBuf, fs::Metadata)>,
+        path: &Path,
+        options: &mut ImportOptions,
+        ancestors: &mut Vec<PathBuf>,
+        buffer: &mut [u8],
+        report: &mut ImportReport,
+    ) -> io::Result<()> {
+        for (local_path, mut metadata) in children {
+            let mut fail = |error: io::Error| {
+                report.failures.push(ImportFailure {
+                    local_path: local_path.clone(),
+                    error,
+                });
+            };
+            if metadata.file_type().is_symlink() {
+                if !options.follow_symlinks {
+                    report.skipped.push(local_path);
+                    continue;
+                }
+                metadata = match fs::metadata(&local_path) {
+                    Ok(metadata) => metadata,
+                    Err(error) => {
+                        fail(error);
+                        continue;
+                    }
+                };
+            }
+            if !metadata.is_dir() && !metadata.is_file() {
+                report.skipped.push(local_path);
+                continue;
+            }
+            let is_storage = metadata.is_dir();
+            let name = match local_path.file_name().unwrap().to_str() {
+                Some(name) => options.object_name(name, is_storage),
+                None => {
+                    fail(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "File name isn't valid Unicode",
+                    ));
+                    continue;
+                }
+            };
+            if let Err(error) = internal::path::validate_name(&name) {
+                fail(error);
+                continue;
+            }
+            let child_path = path.join(&name);
+            let existing = self.entry_with_path(&child_path).ok();
+            if let Some(ref entry) = existing {
+                if !(is_storage && entry.is_storage()) {
+                    fail(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!(
+                            "An object named {:?} already exists in {:?}",
+                            entry.name(),
+                            path
+                        ),
+                    ));
+                    continue;
+                }
+            }
+            if is_storage {
+                let canonical = match fs::canonicalize(&local_path) {
+                    Ok(canonical) => canonical,
+                    Err(error) => {
+                        fail(error);
+                        continue;
+                    }
+                };
+                if ancestors.contains(&canonical) {
+                    fail(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Symbolic link loop",
+                    ));
+                    continue;
+                }
+                let grandchildren = match read_local_dir(&local_path) {
+                    Ok(grandchildren) => grandchildren,
+                    Err(error) => {
+                        fail(error);
+                        continue;
+                    }
+                };
+                let child_path = match existing {
+                    Some(entry) => entry.path().to_path_buf(),
+                    None => {
+                        self.create_storage_with_path(&child_path)?;
+                        report.created.push(child_path.clone());
+                        child_path
+                    }
+                };
+                ancestors.push(canonical);
+                self.import_dir(
+                    grandchildren,
+                    &child_path,
+                    options,
+                    ancestors,
+                    buffer,
+                    report,
+                )?;
+                ancestors.pop();
+                if options.preserve_times {
+                    self.preserve_modified_time(&child_path, &metadata)?;
+                }
+                continue;
+            }
+            let mut file = match fs::File::open(&local_path) {
+                Ok(file) => file,
+                Err(error) => {
+                    fail(error);
+                    continue;
+                }
+            };
+            let mut stream =
+                self.create_stream_with_path(&child_path, false)?;
+            let mut num_bytes = 0;
+            let read_error = loop {
+                let num_read = match ioutil::read_retrying(&mut file, buffer) {
+                    Ok(0) => break None,
+                    Ok(num_read) => num_read,
+                    Err(error) => break Some(error),
+                };
+                stream.write_all(&buffer[..num_read])?;
+                num_bytes += num_read as u64;
+            };
+            drop(stream);
+            if let Some(error) = read_error {
+                self.remove_stream_with_path(&child_path)?;
+                fail(error);
+                continue;
+            }
+            report.num_bytes += num_bytes;
+            report.created.push(child_path);
         }
-        debug_assert!(!names.is_empty());
-        let name = names.pop().unwrap();
-        let parent_id = self.stream_id_for_name_chain(&names).unwrap();
-        self.minialloc_mut().remove_dir_entry(parent_id, name)?;
         Ok(())
     }
 
-    /// Sets the user-defined bitflags for the object at the provided path.
-    /// (To get the current state bits for an object, use
-    /// `self.entry(path)?.state_bits()`.)
-    pub fn set_state_bits<P: AsRef<Path>>(
+    /// Gives the storage at `path` the modification time from the given
+    /// local metadata, if it has one that a CFB timestamp can hold.
+    fn preserve_modified_time(
         &mut self,
-        path: P,
-        bits: u32,
+        path: &Path,
+        metadata: &fs::Metadata,
     ) -> io::Result<()> {
-        self.set_entry_with_path(path.as_ref(), |dir_entry| {
-            dir_entry.state_bits = bits
-        })
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(Timestamp::checked_from_system_time);
+        if let Some(modified) = modified {
+            self.set_modified_time_with_path(path, modified.to_system_time())?;
+        }
+        Ok(())
     }
 
-    /// Sets the modified time for the object at the given path to now.  Has no
-    /// effect when called on the root storage.
-    pub fn touch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        self.set_modified_time(path, std::time::SystemTime::now())
+    /// Frees the directory sectors at the end of the directory chain that
+    /// hold only unallocated entries (as left behind by removing many
+    /// objects), and returns how many sectors were freed.  Live entries are
+    /// never moved, so nothing changes if the last directory sector holds any
+    /// of them.  The freed sectors become available for reuse (and, if they
+    /// are at the end of the file, can then be dropped with
+    /// [`shrink_to_fit`](#method.shrink_to_fit)).
+    pub fn shrink_directory(&mut self) -> io::Result<u32> {
+        let result = self.minialloc_mut().release_unused_dir_sectors();
+        self.self_check("shrink_directory");
+        result
     }
 
-    /// Sets the modified time for the object at the given path.
-    /// Has no effect on streams due to requirements imposed by CFB spec.
-    pub fn set_modified_time<P: AsRef<Path>>(
-        &mut self,
-        path: P,
-        ts: std::time::SystemTime,
-    ) -> io::Result<()> {
-        self.set_entry_with_path(path.as_ref(), |dir_entry| {
-            if dir_entry.obj_type != ObjType::Stream {
-                dir_entry.modified_time = Timestamp::from_system_time(ts);
-            }
-        })
+    /// Flushes all changes to the underlying file (as with `flush()`), then
+    /// drops any free sectors at the end of the file, along with the FAT and
+    /// DIFAT sectors that only described them, and updates the header to
+    /// match.  This is useful after removing a lot of data, so that the
+    /// file's metadata is the same as if it had been created at the smaller
+    /// size.  Returns the new length of the file, in bytes.
+    ///
+    /// Note that only free space at the _end_ of the file can be released;
+    /// free sectors between sectors that are still in use are left alone.
+    ///
+    /// If the underlying file has the
+    /// [`TRUNCATABLE`](struct.Capabilities.html#associatedconstant.TRUNCATABLE)
+    /// capability, it is truncated to the returned length.  Otherwise, any
+    /// data past the returned length is left in place, and the caller must
+    /// truncate the file to that length (e.g. with
+    /// [`File::set_len`](https://doc.rust-lang.org/std/fs/struct.File.html#method.set_len))
+    /// before it is opened again, or the stale tail will be taken for part of
+    /// the file.
+    #[must_use = "unless the file is TRUNCATABLE, it must be truncated to \
+                  the returned length"]
+    pub fn shrink_to_fit(&mut self) -> io::Result<u64> {
+        let result = self.shrink_to_fit_internal();
+        self.self_check("shrink_to_fit");
+        result
     }
 
-    /// Sets the created time for the object at the given path.
-    /// Has no effect on streams due to requirements imposed by CFB spec.
-    pub fn set_created_time<P: AsRef<Path>>(
-        &mut self,
-        path: P,
-        ts: std::time::SystemTime,
-    ) -> io::Result<()> {
-        self.set_entry_with_path(path.as_ref(), |dir_entry| {
-            if dir_entry.obj_type != ObjType::Stream {
-                dir_entry.creation_time = Timestamp::from_system_time(ts);
+    fn shrink_to_fit_internal(&mut self) -> io::Result<u64> {
+        self.minialloc_mut().check_backing_len()?;
+        self.remove_leaked_temporaries()?;
+        self.write_audit_trail()?;
+        let truncator = self.backing.truncator();
+        let mut minialloc = self.minialloc_mut();
+        minialloc.release_reservations()?;
+        let mut len = minialloc.release_free_tail()?;
+        if let Some(set_len) = truncator {
+            len = minialloc.truncate_backing(set_len)?;
+        }
+        minialloc.flush()?;
+        Ok(len)
+    }
+
+    /// Like [`shrink_to_fit`](#method.shrink_to_fit), but requires the
+    /// [`TRUNCATABLE`](struct.Capabilities.html#associatedconstant.TRUNCATABLE)
+    /// capability, so that the space freed at the end of the file is sure to
+    /// be returned to the filesystem.  Returns an error, without changing
+    /// anything, if the underlying file lacks it.
+    pub fn flush_and_truncate(&mut self) -> io::Result<u64> {
+        let result = self.flush_and_truncate_internal();
+        self.self_check("flush_and_truncate");
+        result
+    }
+
+    fn flush_and_truncate_internal(&mut self) -> io::Result<u64> {
+        self.backing
+            .require("flush_and_truncate", Capabilities::TRUNCATABLE)?;
+        self.shrink_to_fit_internal()
+    }
+
+    /// Flushes all changes, then writes a compacted copy of the file (as
+    /// [`compact`](#method.compact) would make) to a new file beside it,
+    /// syncs that to disk, and renames it over the original.  Should this be
+    /// interrupted, the file at [`path`](#method.path) is left either as it
+    /// was or fully rewritten, never partly rewritten.  Afterwards, this
+    /// `CompoundFile` uses the new file.  Returns the new length of the
+    /// file, in bytes.
+    ///
+    /// Requires the
+    /// [`ATOMIC_SAVE`](struct.Capabilities.html#associatedconstant.ATOMIC_SAVE)
+    /// capability.  Returns an error, without changing anything, if any
+    /// [`Stream`] handles for this file are still open.
+    pub fn save_atomic(&mut self) -> io::Result<u64> {
+        let result = self.save_atomic_internal();
+        self.self_check("save_atomic");
+        result
+    }
+
+    fn save_atomic_internal(&mut self) -> io::Result<u64> {
+        self.backing.require("save_atomic", Capabilities::ATOMIC_SAVE)?;
+        self.check_no_unflushed_streams()?;
+        if Arc::weak_count(&self.minialloc) > 0 {
+            invalid_input!("Can't save a file while streams are open");
+        }
+        self.flush_internal()?;
+        let path = self.backing.path.clone().unwrap();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = path.with_file_name(format!(
+            ".{}.{}.save",
+            file_name,
+            std::process::id()
+        ));
+        let (copy, len) = match self.write_compacted_copy(&temp_path) {
+            Ok(result) => result,
+            Err(error) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(error);
             }
-        })
+        };
+        if let Err(error) = fs::rename(&temp_path, &path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error);
+        }
+        let copy = match Arc::try_unwrap(copy.minialloc) {
+            Ok(rwlock) => rwlock.into_inner().unwrap(),
+            Err(_) => unreachable!(),
+        };
+        self.minialloc_mut().adopt_compacted(copy, |inner, file| {
+            *inner = file;
+            Ok(())
+        })?;
+        Ok(len)
     }
 
-    fn set_entry_with_path<G: FnMut(&mut DirEntry)>(
+    /// Writes a compacted copy of this file to a new file at the given
+    /// path, and opens it.
+    fn write_compacted_copy(
         &mut self,
         path: &Path,
-        f: G,
-    ) -> io::Result<()> {
-        let names = internal::path::name_chain_from_path(path)?;
-        let path = internal::path::path_from_name_chain(&names);
-        let stream_id = match self.stream_id_for_name_chain(&names) {
-            Some(stream_id) => stream_id,
-            None => not_found!("No such object: {:?}", path),
+    ) -> io::Result<(CompoundFile<F>, u64)> {
+        let from_file = self.backing.from_file.unwrap();
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let mut minialloc = self.minialloc_mut();
+        let layout = CompactLayout::new(&minialloc);
+        layout.write_to(&mut minialloc, &mut file)?;
+        file.sync_all()?;
+        let copy = CompoundFile::open_internal(
+            from_file(file),
+            Validation::Permissive,
+            None,
+        )?;
+        Ok((copy, layout.file_len()))
+    }
+
+    /// Flushes all changes to the underlying file (as with `flush()`), then
+    /// rewrites the whole file so that nothing is wasted: every sector chain
+    /// is made contiguous, free sectors (and free mini sectors) are dropped,
+    /// and the FAT, DIFAT, and MiniFAT are rebuilt at their minimal sizes.
+    /// The FAT, DIFAT, directory, MiniFAT, and mini stream are laid out at
+    /// the front of the file, followed by each stream's data in stream ID
+    /// order.  Returns the new length of the file, in bytes.
+    ///
+    /// The tree of objects, their metadata, and the streams' data all read
+    /// back exactly as before, and every object keeps its stream ID.  Any
+    /// stale chains left behind by removed objects (see
+    /// [`stale_chains`](#method.stale_chains)) are dropped, as are the
+    /// unused sectors past the end of a stream's data.  The new file always
+    /// uses standard 64-byte mini sectors.
+    ///
+    /// The new file is built in a temporary buffer (in memory, or in a
+    /// temporary file if it's large) before being copied over the old one.
+    /// If the underlying file has the
+    /// [`TRUNCATABLE`](struct.Capabilities.html#associatedconstant.TRUNCATABLE)
+    /// capability, it is then truncated to the returned length.  Otherwise,
+    /// the old file's data past the returned length is left in place, and
+    /// the caller must truncate the file to that length before it is opened
+    /// again, or its stale tail will be taken for part of the file.  Returns
+    /// an error, without changing anything, if any [`Stream`] handles for
+    /// this file are still open.
+    #[must_use = "unless the file is TRUNCATABLE, it must be truncated to \
+                  the returned length"]
+    pub fn compact(&mut self) -> io::Result<u64> {
+        let result = self.compact_internal();
+        self.self_check("compact");
+        result
+    }
+
+    fn compact_internal(&mut self) -> io::Result<u64> {
+        self.check_no_unflushed_streams()?;
+        if Arc::weak_count(&self.minialloc) > 0 {
+            invalid_input!("Can't compact a file while streams are open");
+        }
+        self.flush_internal()?;
+        let truncator = self.backing.truncator();
+        let mut minialloc = self.minialloc_mut();
+        let layout = CompactLayout::new(&minialloc);
+        let mut image = Spool::new(SpoolPolicy::TempFile {
+            threshold: internal::SPOOL_THRESHOLD,
+        });
+        layout.write_to(&mut minialloc, &mut image)?;
+        let compacted =
+            CompoundFile::open_internal(image, Validation::Permissive, None)?;
+        let compacted = match Arc::try_unwrap(compacted.minialloc) {
+            Ok(rwlock) => rwlock.into_inner().unwrap(),
+            Err(_) => unreachable!(),
         };
-        self.minialloc_mut().with_dir_entry_mut(stream_id, f)?;
-        Ok(())
+        minialloc.adopt_compacted(compacted, |inner, mut image| {
+            image.seek(SeekFrom::Start(0))?;
+            inner.seek(SeekFrom::Start(0))?;
+            io::copy(&mut image, inner)?;
+            Ok(())
+        })?;
+        if let Some(set_len) = truncator {
+            minialloc.truncate_backing(set_len)?;
+        }
+        minialloc.flush()?;
+        Ok(layout.file_len())
     }
 
-    /// Flushes all changes to the underlying file.
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.minialloc_mut().flush()
+    /// Returns how many bytes [`compact`](#method.compact) would save, given
+    /// the file as it was last flushed, without changing anything.  Data
+    /// still buffered in open [`Stream`] handles isn't taken into account.
+    pub fn compact_estimate(&self) -> u64 {
+        let minialloc = self.minialloc();
+        let sector_len = minialloc.version().sector_len() as u64;
+        let current_len = (minialloc.fat().len() as u64 + 1) * sector_len;
+        let compacted_len = CompactLayout::new(&minialloc).file_len();
+        current_len.saturating_sub(compacted_len)
     }
 }
 
@@ -1062,7 +5369,6 @@ impl<T: Write> WriteLeNumber for T {}
 #[cfg(test)]
 mod tests {
     use std::io::{self, Cursor, Seek, SeekFrom};
-    use std::mem::size_of;
     use std::path::Path;
 
     use crate::internal::{
@@ -1077,6 +5383,7 @@ mod tests {
         let mut data = Vec::<u8>::new();
         let mut header = Header {
             version,
+            mini_sector_shift: consts::MINI_SECTOR_SHIFT,
             num_dir_sectors: 0,
             num_fat_sectors: 1,
             first_dir_sector: 1,
@@ -1097,7 +5404,7 @@ mod tests {
         // Pad the FAT sector with zeros instead of FREE_SECTOR.  Technically
         // this violates the MS-CFB spec (section 2.3), but apparently some CFB
         // implementations do this.
-        for _ in fat.len()..(version.sector_len() / size_of::<u32>()) {
+        for _ in fat.len()..version.fat_entries_per_sector() {
             data.write_le_u32(0)?;
         }
         // Write directory sector:
@@ -1164,6 +5471,7 @@ mod tests {
         // Construct header full of DIFAT entries
         let header = Header {
             version,
+            mini_sector_shift: consts::MINI_SECTOR_SHIFT,
             num_dir_sectors: 0,
             num_fat_sectors: num_fat_sectors as u32,
             first_dir_sector: dir_sector as u32,
@@ -1184,8 +5492,7 @@ mod tests {
         }
 
         // Write the DIFAT sector
-        let num_difat_entries_in_sector =
-            version.sector_len() / size_of::<u32>() - 1;
+        let num_difat_entries_in_sector = version.difat_entries_per_sector();
         for i in 0..num_difat_entries_in_sector {
             let difat_entry_i = i + consts::NUM_DIFAT_ENTRIES_IN_HEADER;
 
@@ -1202,8 +5509,7 @@ mod tests {
         data.write_le_u32(consts::END_OF_CHAIN)?;
 
         // Write the first two FAT sectors, referencing the header data
-        let num_fat_entries_in_sector =
-            version.sector_len() / size_of::<u32>();
+        let num_fat_entries_in_sector = version.fat_entries_per_sector();
         let mut fat = vec![consts::FREE_SECTOR; num_fat_entries_in_sector * 2];
         fat[difat_sector] = consts::DIFAT_SECTOR;
         fat[dir_sector] = consts::END_OF_CHAIN;
@@ -1290,6 +5596,7 @@ mod tests {
         // cfb has spare DIFAT_SECTOR entries in FAT not accounted for in header
         let mut hdr = Header {
             version: Version::V3,
+            mini_sector_shift: consts::MINI_SECTOR_SHIFT,
             num_dir_sectors: 0,
             num_fat_sectors: 1,
             first_dir_sector: 0,
@@ -1336,6 +5643,51 @@ mod tests {
         let mut f = cfb.create_stream("stream").unwrap();
         f.write_all(&vec![0; 1024 * 1024]).unwrap();
     }
+
+    #[test]
+    fn resolved_paths_skip_name_comparisons() {
+        use crate::internal::path::NAME_COMPARISONS;
+        use std::io::Write;
+
+        let mut template =
+            CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        template.create_storage_all("/Doc/Parts").unwrap();
+        let paths: Vec<String> =
+            (0..40).map(|index| format!("/Doc/Parts/Part{index}")).collect();
+        for path in paths.iter() {
+            template.create_stream(path).unwrap();
+        }
+        let data = template.into_inner().into_inner();
+        let mut comp = CompoundFile::open(Cursor::new(data.clone())).unwrap();
+        let resolved: Vec<_> =
+            paths.iter().map(|path| comp.resolve(path).unwrap()).collect();
+
+        // Opening a file checks the order of its entries, so do that first.
+        let mut others: Vec<_> = (0..5)
+            .map(|_| CompoundFile::open(Cursor::new(data.clone())).unwrap())
+            .collect();
+        NAME_COMPARISONS.with(|count| count.set(0));
+        for _ in 0..25 {
+            for resolved in resolved.iter() {
+                comp.open_stream_resolved(resolved)
+                    .unwrap()
+                    .write_all(b"stamp")
+                    .unwrap();
+            }
+        }
+        // Other files with the same structure revalidate without comparing
+        // names, too.
+        for other in others.iter_mut() {
+            for resolved in resolved.iter() {
+                assert_eq!(other.entry_resolved(resolved).unwrap().len(), 0);
+                other.open_stream_resolved(resolved).unwrap();
+            }
+        }
+        assert_eq!(NAME_COMPARISONS.with(|count| count.get()), 0);
+
+        comp.open_stream(&paths[0]).unwrap();
+        assert!(NAME_COMPARISONS.with(|count| count.get()) > 0);
+    }
 }
 
 //===========================================================================//
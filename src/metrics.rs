@@ -0,0 +1,24 @@
+//! Measuring how long operations on compound files take, and how many bytes
+//! they handle.
+//!
+//! A [`MetricsSink`] installed with
+//! [`CompoundFile::set_metrics_sink`](../struct.CompoundFile.html#method.set_metrics_sink)
+//! is told about each [`Op`] as it finishes; [`AtomicMetrics`] is a sink
+//! that just adds them up.
+//!
+//! This module is only available with the `metrics` feature.
+//!
+//! ```
+//! use cfb::metrics::{AtomicMetrics, Op};
+//! use std::io::{Cursor, Write};
+//! use std::sync::Arc;
+//!
+//! let metrics = Arc::new(AtomicMetrics::new());
+//! let mut comp = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+//! comp.set_metrics_sink(metrics.clone());
+//! comp.create_stream("/foo").unwrap().write_all(b"foo").unwrap();
+//! comp.flush().unwrap();
+//! assert_eq!(metrics.totals(Op::Flush).count, 1);
+//! ```
+
+pub use crate::internal::{AtomicMetrics, MetricsSink, Op, OpTotals};
@@ -0,0 +1,482 @@
+//! Creating the skeleton of a Windows Installer (MSI) database.
+//!
+//! An MSI database is a compound file with a particular root CLSID, a string
+//! pool and system tables stored in streams with specially encoded names, and
+//! a summary information property set describing the package.  Getting all of
+//! the constants and formats right by hand is fiddly, so
+//! [`MsiSkeleton::create`](struct.MsiSkeleton.html#method.create) writes an
+//! empty (but valid) database, which can then be filled in with tables.
+//!
+//! This module is only available with the `msi` feature.
+//!
+//! ```
+//! use cfb::msi::{MsiArch, MsiOptions, MsiSkeleton};
+//! use std::io::Cursor;
+//! use uuid::Uuid;
+//!
+//! let package_code = Uuid::from_u128(0x1234);
+//! let options = MsiOptions::new(MsiArch::X64, package_code)
+//!     .title("Installation Database");
+//! let comp = MsiSkeleton::create(Cursor::new(Vec::new()), options).unwrap();
+//! assert_eq!(*comp.root_entry().clsid(), cfb::msi::DATABASE_CLSID);
+//! ```
+//!
+//! The names of an MSI database's streams are encoded to pack them into the
+//! compound file's name length limit; [`encode_name`](fn.encode_name.html)
+//! and [`decode_name`](fn.decode_name.html) convert between the encoded and
+//! the readable names ([`encode_into`](fn.encode_into.html),
+//! [`decode_into`](fn.decode_into.html), and
+//! [`decode_chars`](fn.decode_chars.html) do the same without allocating),
+//! and
+//! [`CompoundFile::open_msi_stream`](../struct.CompoundFile.html#method.open_msi_stream)
+//! opens a stream by its readable name.
+
+use std::fmt;
+use std::io::{self, Read, Seek, Write};
+use std::time::SystemTime;
+
+use uuid::Uuid;
+
+pub use crate::internal::MsiNameChars;
+use crate::internal::{
+    decode_msi_chars, decode_msi_name, decode_msi_name_into, encode_msi_name,
+    encode_msi_name_into, to_b64, Timestamp,
+};
+use crate::{CompoundFile, Stream, Version};
+
+//===========================================================================//
+
+/// The root storage CLSID that marks a compound file as an MSI database.
+pub const DATABASE_CLSID: Uuid =
+    Uuid::from_u128(0x000c1084_0000_0000_c000_000000000046);
+
+/// The name of the stream holding the summary information property set.
+pub const SUMMARY_INFO_STREAM_NAME: &str = "\u{5}SummaryInformation";
+
+/// The format ID of the summary information property set.
+const SUMMARY_INFO_FMTID: Uuid =
+    Uuid::from_u128(0xf29f85e0_4ff9_1068_ab91_08002b27b3d9);
+
+/// The names of the streams (before encoding) that make up an empty
+/// database: the string pool, and the (empty) system tables.
+const SKELETON_STREAM_NAMES: &[&str] =
+    &["_StringPool", "_StringData", "_Tables", "_Columns"];
+
+const UTF8_CODEPAGE: u16 = 65001;
+
+// Property IDs within the summary information property set:
+const PID_CODEPAGE: u32 = 1;
+const PID_TITLE: u32 = 2;
+const PID_SUBJECT: u32 = 3;
+const PID_AUTHOR: u32 = 4;
+const PID_COMMENTS: u32 = 6;
+const PID_TEMPLATE: u32 = 7;
+const PID_REVISION: u32 = 9;
+const PID_CREATE_TIME: u32 = 12;
+const PID_LAST_SAVE_TIME: u32 = 13;
+const PID_PAGE_COUNT: u32 = 14;
+const PID_WORD_COUNT: u32 = 15;
+const PID_APP_NAME: u32 = 18;
+
+// Property value types:
+const VT_I2: u32 = 2;
+const VT_I4: u32 = 3;
+const VT_LPSTR: u32 = 30;
+const VT_FILETIME: u32 = 64;
+
+//===========================================================================//
+
+/// The processor architecture that an MSI package targets.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MsiArch {
+    /// 32-bit x86.
+    Intel,
+    /// 64-bit x86 (AMD64).
+    X64,
+    /// 64-bit Itanium.
+    Intel64,
+    /// 64-bit ARM.
+    Arm64,
+}
+
+impl MsiArch {
+    /// Returns the platform name used in the summary information's Template
+    /// property.
+    pub fn platform_name(self) -> &'static str {
+        match self {
+            MsiArch::Intel => "Intel",
+            MsiArch::X64 => "x64",
+            MsiArch::Intel64 => "Intel64",
+            MsiArch::Arm64 => "Arm64",
+        }
+    }
+
+    /// Returns the minimum Windows Installer version (times 100) that can
+    /// install a package for this architecture, which the summary
+    /// information records as its Page Count property.
+    pub fn min_installer_version(self) -> i32 {
+        match self {
+            MsiArch::Intel | MsiArch::X64 | MsiArch::Intel64 => 200,
+            MsiArch::Arm64 => 500,
+        }
+    }
+}
+
+//===========================================================================//
+
+/// Options for creating an MSI database skeleton with
+/// [`MsiSkeleton::create`](struct.MsiSkeleton.html#method.create).
+///
+/// The text properties are stored in the database's codepage, so unless the
+/// codepage is UTF-8 (65001), they may only contain ASCII characters.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MsiOptions {
+    arch: MsiArch,
+    package_code: Uuid,
+    codepage: u16,
+    languages: Vec<u16>,
+    title: Option<String>,
+    subject: Option<String>,
+    author: Option<String>,
+    comments: Option<String>,
+    creating_application: Option<String>,
+    created: Option<SystemTime>,
+}
+
+impl MsiOptions {
+    /// Returns options for a package targeting `arch`, identified by the
+    /// given package code (which must be unique to this package), using
+    /// codepage 1252 and language 1033 (US English).
+    pub fn new(arch: MsiArch, package_code: Uuid) -> MsiOptions {
+        MsiOptions {
+            arch,
+            package_code,
+            codepage: 1252,
+            languages: vec![1033],
+            title: None,
+            subject: None,
+            author: None,
+            comments: None,
+            creating_application: None,
+            created: None,
+        }
+    }
+
+    /// Sets the codepage of the database's strings and summary information
+    /// (e.g. 1252, or 0 for a codepage-neutral database).
+    pub fn codepage(mut self, codepage: u16) -> MsiOptions {
+        self.codepage = codepage;
+        self
+    }
+
+    /// Sets the language IDs that the package supports, as listed in the
+    /// summary information's Template property.
+    pub fn languages(mut self, languages: Vec<u16>) -> MsiOptions {
+        self.languages = languages;
+        self
+    }
+
+    /// Sets the summary information's Title property.
+    pub fn title<S: Into<String>>(mut self, title: S) -> MsiOptions {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Sets the summary information's Subject property (usually the product
+    /// name).
+    pub fn subject<S: Into<String>>(mut self, subject: S) -> MsiOptions {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    /// Sets the summary information's Author property (usually the
+    /// manufacturer).
+    pub fn author<S: Into<String>>(mut self, author: S) -> MsiOptions {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Sets the summary information's Comments property.
+    pub fn comments<S: Into<String>>(mut self, comments: S) -> MsiOptions {
+        self.comments = Some(comments.into());
+        self
+    }
+
+    /// Sets the summary information's Creating Application property.
+    pub fn creating_application<S: Into<String>>(
+        mut self,
+        name: S,
+    ) -> MsiOptions {
+        self.creating_application = Some(name.into());
+        self
+    }
+
+    /// Sets the creation (and last saved) time recorded in the summary
+    /// information.  Defaults to the current time.
+    pub fn created(mut self, time: SystemTime) -> MsiOptions {
+        self.created = Some(time);
+        self
+    }
+
+    /// Returns the summary information's Template property, e.g.
+    /// `"x64;1033"`.
+    fn template(&self) -> String {
+        let languages: Vec<String> =
+            self.languages.iter().map(|lang| lang.to_string()).collect();
+        format!("{};{}", self.arch.platform_name(), languages.join(","))
+    }
+}
+
+//===========================================================================//
+
+/// Encodes a readable MSI table or stream name into the name of the stream
+/// that holds it within the database, marking it as a table if `is_table` is
+/// true.  This is the inverse of [`decode_name`](fn.decode_name.html).
+///
+/// Returns an error if the name contains a character outside of the
+/// encodable alphabet (ASCII letters and digits, `.`, and `_`), since such a
+/// name could never be found by Windows Installer.
+///
+/// ```
+/// use cfb::msi::{decode_name, encode_name};
+///
+/// let encoded = encode_name("_StringPool", true).unwrap();
+/// assert_eq!(encoded.chars().count(), 7);
+/// assert_eq!(decode_name(&encoded), ("_StringPool".to_string(), true));
+/// assert!(encode_name("!_StringPool", true).is_err());
+/// ```
+pub fn encode_name(name: &str, is_table: bool) -> io::Result<String> {
+    let mut output = String::with_capacity(name.len());
+    encode_into(name, is_table, &mut output)?;
+    Ok(output)
+}
+
+/// Like [`encode_name`](fn.encode_name.html), but writes the encoded name to
+/// `out` instead of allocating a new string.  Nothing is written if the name
+/// cannot be encoded.
+pub fn encode_into<W: fmt::Write + ?Sized>(
+    name: &str,
+    is_table: bool,
+    out: &mut W,
+) -> io::Result<()> {
+    if let Some(chr) = name.chars().find(|&chr| to_b64(chr).is_none()) {
+        invalid_input!(
+            "MSI name {:?} contains {:?}, which cannot be encoded",
+            name,
+            chr
+        );
+    }
+    encode_msi_name_into(name, is_table, out)
+        .map_err(|_| io::Error::other("failed to write MSI name"))
+}
+
+/// Decodes the name of a stream within an MSI database, and returns the
+/// readable name and whether the stream holds a table.  Characters that
+/// aren't part of the encoding (such as in `"\u{5}SummaryInformation"`)
+/// are passed through unchanged.
+pub fn decode_name(name: &str) -> (String, bool) {
+    decode_msi_name(name)
+}
+
+/// Like [`decode_name`](fn.decode_name.html), but writes the readable name
+/// to `out` instead of allocating a new string, and returns whether the
+/// stream holds a table.
+///
+/// ```
+/// use cfb::msi::{decode_into, encode_name};
+///
+/// let mut buffer = String::with_capacity(64);
+/// for name in ["_StringPool", "_Tables", "_Columns"] {
+///     buffer.clear();
+///     let encoded = encode_name(name, true).unwrap();
+///     assert_eq!(decode_into(&encoded, &mut buffer), Ok(true));
+///     assert_eq!(buffer, name);
+/// }
+/// ```
+pub fn decode_into<W: fmt::Write + ?Sized>(
+    name: &str,
+    out: &mut W,
+) -> Result<bool, fmt::Error> {
+    decode_msi_name_into(name, out)
+}
+
+/// Returns an iterator over the characters of the readable name of a stream
+/// within an MSI database, without allocating.  The iterator's
+/// [`is_table`](struct.MsiNameChars.html#method.is_table) method
+/// tells whether the stream holds a table.
+pub fn decode_chars(name: &str) -> MsiNameChars<'_> {
+    decode_msi_chars(name)
+}
+
+impl<F: Seek> CompoundFile<F> {
+    /// Opens an existing stream in the root storage of an MSI database, given
+    /// its readable name, encoding the name as with
+    /// [`msi::encode_name`](msi/fn.encode_name.html).
+    ///
+    /// This method is only available with the `msi` feature.
+    pub fn open_msi_stream(
+        &mut self,
+        name: &str,
+        is_table: bool,
+    ) -> io::Result<Stream<F>> {
+        let path = format!("/{}", encode_name(name, is_table)?);
+        self.open_stream(path)
+    }
+}
+
+//===========================================================================//
+
+/// Creates empty MSI databases.
+pub struct MsiSkeleton {
+    _private: (),
+}
+
+impl MsiSkeleton {
+    /// Writes an empty MSI database to `inner`, and returns it as a compound
+    /// file so that tables can be added.  The database has the MSI root
+    /// CLSID, an empty string pool and system tables, and summary
+    /// information describing the package as given by `options`.
+    pub fn create<F: Read + Write + Seek>(
+        inner: F,
+        options: MsiOptions,
+    ) -> io::Result<CompoundFile<F>> {
+        let summary_info = summary_info(&options)?;
+        let mut comp = CompoundFile::create_with_version(Version::V3, inner)?;
+        comp.set_storage_clsid("/", DATABASE_CLSID)?;
+        for name in SKELETON_STREAM_NAMES {
+            let path = format!("/{}", encode_msi_name(name, true));
+            let mut stream = comp.create_stream(&path)?;
+            if *name == "_StringPool" {
+                // The string pool starts with the codepage (with the high bit
+                // clear, for two-byte string references), then lists no
+                // strings.
+                let header = u32::from(options.codepage);
+                stream.write_all(&header.to_le_bytes())?;
+            }
+        }
+        let path = format!("/{}", SUMMARY_INFO_STREAM_NAME);
+        comp.create_stream(&path)?.write_all(&summary_info)?;
+        comp.flush()?;
+        Ok(comp)
+    }
+}
+
+//===========================================================================//
+
+/// A property value in a property set.
+enum PropertyValue {
+    I2(i16),
+    I4(i32),
+    Str(Vec<u8>),
+    FileTime(u64),
+}
+
+impl PropertyValue {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        match *self {
+            PropertyValue::I2(value) => {
+                out.extend_from_slice(&VT_I2.to_le_bytes());
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::I4(value) => {
+                out.extend_from_slice(&VT_I4.to_le_bytes());
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::Str(ref bytes) => {
+                out.extend_from_slice(&VT_LPSTR.to_le_bytes());
+                let len = bytes.len() as u32 + 1;
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(bytes);
+                out.push(0);
+            }
+            PropertyValue::FileTime(value) => {
+                out.extend_from_slice(&VT_FILETIME.to_le_bytes());
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        while out.len() % 4 != 0 {
+            out.push(0);
+        }
+    }
+}
+
+/// Encodes a string property in the given codepage.
+fn encode_string(string: &str, codepage: u16) -> io::Result<PropertyValue> {
+    if codepage != UTF8_CODEPAGE && !string.is_ascii() {
+        invalid_input!(
+            "Summary information string {:?} is not ASCII, so can't be \
+             stored in codepage {}",
+            string,
+            codepage
+        );
+    }
+    Ok(PropertyValue::Str(string.as_bytes().to_vec()))
+}
+
+/// Serializes the summary information property set for the given options.
+fn summary_info(options: &MsiOptions) -> io::Result<Vec<u8>> {
+    let codepage = options.codepage;
+    let created = Timestamp::from_system_time(
+        options.created.unwrap_or_else(SystemTime::now),
+    )
+    .value();
+    let revision =
+        format!("{{{}}}", options.package_code.hyphenated()).to_uppercase();
+    let mut properties = vec![
+        (PID_CODEPAGE, PropertyValue::I2(codepage as i16)),
+        (PID_TEMPLATE, encode_string(&options.template(), codepage)?),
+        (PID_REVISION, encode_string(&revision, codepage)?),
+        (PID_CREATE_TIME, PropertyValue::FileTime(created)),
+        (PID_LAST_SAVE_TIME, PropertyValue::FileTime(created)),
+        (
+            PID_PAGE_COUNT,
+            PropertyValue::I4(options.arch.min_installer_version()),
+        ),
+        (PID_WORD_COUNT, PropertyValue::I4(0)),
+    ];
+    let strings = [
+        (PID_TITLE, &options.title),
+        (PID_SUBJECT, &options.subject),
+        (PID_AUTHOR, &options.author),
+        (PID_COMMENTS, &options.comments),
+        (PID_APP_NAME, &options.creating_application),
+    ];
+    for &(pid, value) in strings.iter() {
+        if let Some(value) = value {
+            properties.push((pid, encode_string(value, codepage)?));
+        }
+    }
+    properties.sort_by_key(|&(pid, _)| pid);
+
+    // The property set: its size and property count, a table of property
+    // IDs and offsets, then the property values themselves.
+    let table_len = 8 + 8 * properties.len();
+    let mut values = Vec::new();
+    let mut table = Vec::new();
+    for (pid, value) in properties.iter() {
+        table.extend_from_slice(&pid.to_le_bytes());
+        table.extend_from_slice(
+            &((table_len + values.len()) as u32).to_le_bytes(),
+        );
+        value.write_to(&mut values);
+    }
+    let set_len = (table_len + values.len()) as u32;
+
+    // The property set stream header, with a single property set.
+    let mut data = Vec::new();
+    data.extend_from_slice(&0xfffeu16.to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes());
+    // System identifier: Win32, version 6.0.
+    data.extend_from_slice(&0x0002_0006u32.to_le_bytes());
+    data.extend_from_slice(&[0; 16]);
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&SUMMARY_INFO_FMTID.to_bytes_le());
+    data.extend_from_slice(&48u32.to_le_bytes());
+    data.extend_from_slice(&set_len.to_le_bytes());
+    data.extend_from_slice(&(properties.len() as u32).to_le_bytes());
+    data.extend_from_slice(&table);
+    data.extend_from_slice(&values);
+    Ok(data)
+}
+
+//===========================================================================//
@@ -0,0 +1,28 @@
+//! The types and traits needed for typical reading and writing of compound
+//! files, for importing all at once.
+//!
+//! Along with this crate's own types, this re-exports the `std::io` traits
+//! that [`Stream`] implements, since reading or writing a stream needs them.
+//!
+//! ```
+//! use cfb::prelude::*;
+//! use std::io::Cursor;
+//!
+//! let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+//! comp.create_storage("/foo").unwrap();
+//! comp.create_stream("/foo/bar").unwrap().write_all(b"baz").unwrap();
+//!
+//! let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+//! let mut data = Vec::new();
+//! comp.open_stream("/foo/bar").unwrap().read_to_end(&mut data).unwrap();
+//! assert_eq!(data, b"baz");
+//! let entries: Vec<Entry> = comp.walk().collect();
+//! assert_eq!(entries.len(), 3);
+//! ```
+
+pub use crate::{
+    CompoundFile, CreateOptions, Entry, NewEntryOptions, ObjType, Severity,
+    Stream, StreamId, ValidationIssue, ValidationIssueKind, VerifyOptions,
+    VerifyReport, Version,
+};
+pub use std::io::{Read, Seek, SeekFrom, Write};
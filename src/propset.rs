@@ -0,0 +1,1299 @@
+//! Reading and writing OLE property set streams, such as the
+//! `"\u{5}SummaryInformation"` and `"\u{5}DocumentSummaryInformation"`
+//! streams found in Office documents and MSI packages.  See [MS-OLEPS](
+//! https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-oleps)
+//! for the format specification.
+//!
+//! A property set stream holds one or more sections (usually just one), each
+//! identified by a format ID and holding a list of typed values keyed by
+//! property ID.  Text properties of type `VT_LPSTR` are stored in the code
+//! page given by the section's [`PID_CODEPAGE`](constant.PID_CODEPAGE.html)
+//! property; [`PropertySection::string`](struct.PropertySection.html#method.string)
+//! and [`PropertySection::set_string`](struct.PropertySection.html#method.set_string)
+//! take care of converting to and from it.
+//!
+//! Sections may also have a dictionary, which gives some of their properties
+//! names.  The user-defined properties section of the document summary
+//! information stream (see
+//! [`FMTID_USER_DEFINED_PROPERTIES`](constant.FMTID_USER_DEFINED_PROPERTIES.html))
+//! uses it to hold a document's custom properties, which are best looked up
+//! by name with
+//! [`PropertySection::get_by_name`](struct.PropertySection.html#method.get_by_name).
+//!
+//! ```
+//! use cfb::propset::{self, PropertySet};
+//! use std::io::Cursor;
+//!
+//! let mut comp = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+//! let mut set = PropertySet::new(propset::FMTID_SUMMARY_INFO);
+//! set.section_mut().set_string(propset::PID_TITLE, "Quarterly report").unwrap();
+//! comp.write_property_set(propset::SUMMARY_INFO_PATH, &set).unwrap();
+//!
+//! let set = comp.read_property_set(propset::SUMMARY_INFO_PATH).unwrap();
+//! assert_eq!(
+//!     set.section().string(propset::PID_TITLE).as_deref(),
+//!     Some("Quarterly report")
+//! );
+//! ```
+
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use uuid::Uuid;
+
+use crate::internal::Timestamp;
+use crate::CompoundFile;
+
+//===========================================================================//
+
+/// The path of the summary information property set stream.
+pub const SUMMARY_INFO_PATH: &str = "/\u{5}SummaryInformation";
+
+/// The path of the document summary information property set stream.
+pub const DOC_SUMMARY_INFO_PATH: &str = "/\u{5}DocumentSummaryInformation";
+
+/// The format ID of the summary information property set.
+pub const FMTID_SUMMARY_INFO: Uuid =
+    Uuid::from_u128(0xf29f85e0_4ff9_1068_ab91_08002b27b3d9);
+
+/// The format ID of the document summary information property set.
+pub const FMTID_DOC_SUMMARY_INFO: Uuid =
+    Uuid::from_u128(0xd5cdd502_2e9c_101b_9397_08002b2cf9ae);
+
+/// The format ID of the user-defined properties section, which follows the
+/// document summary information section in the same stream.
+pub const FMTID_USER_DEFINED_PROPERTIES: Uuid =
+    Uuid::from_u128(0xd5cdd505_2e9c_101b_9397_08002b2cf9ae);
+
+/// The property ID of a section's dictionary of property names.
+pub const PID_DICTIONARY: u32 = 0;
+/// The property ID of a section's code page, a `VT_I2`.
+pub const PID_CODEPAGE: u32 = 1;
+
+// Summary information property IDs:
+/// The title of the document.
+pub const PID_TITLE: u32 = 2;
+/// The subject of the document.
+pub const PID_SUBJECT: u32 = 3;
+/// The author of the document.
+pub const PID_AUTHOR: u32 = 4;
+/// Keywords describing the document.
+pub const PID_KEYWORDS: u32 = 5;
+/// Comments about the document.
+pub const PID_COMMENTS: u32 = 6;
+/// The template the document was created from (for MSI packages, the
+/// platform and languages).
+pub const PID_TEMPLATE: u32 = 7;
+/// The last person to save the document.
+pub const PID_LAST_AUTHOR: u32 = 8;
+/// The revision number (for MSI packages, the package code).
+pub const PID_REVISION: u32 = 9;
+/// The total time spent editing the document, as a `VT_FILETIME` duration.
+pub const PID_EDIT_TIME: u32 = 10;
+/// When the document was last printed.
+pub const PID_LAST_PRINTED: u32 = 11;
+/// When the document was created.
+pub const PID_CREATE_TIME: u32 = 12;
+/// When the document was last saved.
+pub const PID_LAST_SAVE_TIME: u32 = 13;
+/// The number of pages (for MSI packages, the minimum installer version).
+pub const PID_PAGE_COUNT: u32 = 14;
+/// The number of words (for MSI packages, the source image flags).
+pub const PID_WORD_COUNT: u32 = 15;
+/// The number of characters.
+pub const PID_CHAR_COUNT: u32 = 16;
+/// A thumbnail of the document, as a `VT_CF`.
+pub const PID_THUMBNAIL: u32 = 17;
+/// The name of the application that created the document.
+pub const PID_APP_NAME: u32 = 18;
+/// The document's security flags.
+pub const PID_SECURITY: u32 = 19;
+
+/// The code page for UTF-16, in which `VT_LPSTR` values are stored as
+/// little-endian UTF-16.
+pub const CODEPAGE_UTF16: u16 = 1200;
+/// The code page for Windows Western European (a superset of Latin-1).
+pub const CODEPAGE_WINDOWS_1252: u16 = 1252;
+/// The code page for ISO 8859-1 (Latin-1).
+pub const CODEPAGE_LATIN1: u16 = 28591;
+/// The code page for US-ASCII.
+pub const CODEPAGE_ASCII: u16 = 20127;
+/// The code page for UTF-8.
+pub const CODEPAGE_UTF8: u16 = 65001;
+
+// Property value types:
+const VT_EMPTY: u16 = 0;
+const VT_NULL: u16 = 1;
+const VT_I2: u16 = 2;
+const VT_I4: u16 = 3;
+const VT_R4: u16 = 4;
+const VT_R8: u16 = 5;
+const VT_BOOL: u16 = 11;
+const VT_UI4: u16 = 19;
+const VT_I8: u16 = 20;
+const VT_UI8: u16 = 21;
+const VT_LPSTR: u16 = 30;
+const VT_LPWSTR: u16 = 31;
+const VT_FILETIME: u16 = 64;
+const VT_BLOB: u16 = 65;
+const VT_CLSID: u16 = 72;
+
+const BYTE_ORDER_MARK: u16 = 0xfffe;
+
+/// The system identifier written by `PropertySet::new`: Win32, version 6.0.
+const DEFAULT_SYSTEM_IDENTIFIER: u32 = 0x0002_0006;
+
+/// The most sections a property set stream may have.  Real streams have one
+/// or two; this just keeps a corrupt count from allocating without bound.
+const MAX_NUM_SECTIONS: u32 = 64;
+
+/// The characters that Windows-1252 maps bytes 0x80 to 0x9f to.  The five
+/// bytes that it leaves undefined map to the C1 control characters, as
+/// Windows itself does, so that every byte survives a round trip.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}',
+    '\u{2020}', '\u{2021}', '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}',
+    '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}', '\u{90}', '\u{2018}',
+    '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}',
+    '\u{17e}', '\u{178}',
+];
+
+//===========================================================================//
+
+/// A typed property value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    /// `VT_EMPTY`: no value.
+    Empty,
+    /// `VT_NULL`: a null value.
+    Null,
+    /// `VT_I2`: a 16-bit signed integer.
+    I2(i16),
+    /// `VT_I4`: a 32-bit signed integer.
+    I4(i32),
+    /// `VT_R4`: a 32-bit floating point number.
+    R4(f32),
+    /// `VT_R8`: a 64-bit floating point number.
+    R8(f64),
+    /// `VT_BOOL`: a boolean.
+    Bool(bool),
+    /// `VT_UI4`: a 32-bit unsigned integer.
+    UI4(u32),
+    /// `VT_I8`: a 64-bit signed integer.
+    I8(i64),
+    /// `VT_UI8`: a 64-bit unsigned integer.
+    UI8(u64),
+    /// `VT_LPSTR`: a string, as bytes in the section's code page (without
+    /// the null terminator, but with any padding after it).  Use
+    /// [`PropertySection::string`](struct.PropertySection.html#method.string)
+    /// to decode it.
+    LpStr(Vec<u8>),
+    /// `VT_LPWSTR`: a UTF-16 string.
+    LpWStr(String),
+    /// `VT_FILETIME`: a number of 100-nanosecond intervals, either since
+    /// January 1, 1601 UTC, or (for
+    /// [`PID_EDIT_TIME`](constant.PID_EDIT_TIME.html)) as a duration.
+    FileTime(u64),
+    /// `VT_BLOB`: an array of bytes.
+    Blob(Vec<u8>),
+    /// `VT_CLSID`: a GUID.
+    Clsid(Uuid),
+    /// The dictionary ([`PID_DICTIONARY`](constant.PID_DICTIONARY.html)),
+    /// which has no type: pairs of property ID and name, with each name as
+    /// bytes in the section's code page (without the null terminator, as
+    /// for `LpStr`).  Use
+    /// [`PropertySection::names`](struct.PropertySection.html#method.names)
+    /// to decode them.
+    Dictionary(Vec<(u32, Vec<u8>)>),
+    /// A value of any other type (such as a vector, or a `VT_CF` thumbnail),
+    /// kept as the bytes that were stored for it, starting with its type.
+    /// These bytes are written back unchanged.
+    Other(Vec<u8>),
+}
+
+impl PropertyValue {
+    /// Parses the value stored in `data`, which runs from the start of the
+    /// value to the end of its section, and may extend past the value
+    /// itself.  `extent` is how much of `data` comes before the next value.
+    fn parse(
+        pid: u32,
+        data: &[u8],
+        extent: usize,
+    ) -> io::Result<PropertyValue> {
+        if pid == PID_DICTIONARY {
+            return Ok(PropertyValue::Other(data[..extent].to_vec()));
+        }
+        let type_code = match read_u16(data, 0) {
+            Some(type_code) => type_code,
+            None => invalid_data!("Property {} has no type", pid),
+        };
+        let value = data.get(4..).unwrap_or(&[]);
+        let fixed = |len: usize| -> io::Result<&[u8]> {
+            match value.get(..len) {
+                Some(bytes) => Ok(bytes),
+                None => invalid_data!(
+                    "Property {} of type {} is truncated",
+                    pid,
+                    type_code
+                ),
+            }
+        };
+        let counted = |unit: usize| -> io::Result<&[u8]> {
+            let count = fixed(4)?;
+            let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+            let end =
+                count.checked_mul(unit).and_then(|len| len.checked_add(4));
+            match end.and_then(|end| value.get(4..end)) {
+                Some(bytes) => Ok(bytes),
+                None => invalid_data!(
+                    "Property {} of type {} has length {}, which runs past \
+                     the end of its section",
+                    pid,
+                    type_code,
+                    count
+                ),
+            }
+        };
+        Ok(match type_code {
+            VT_EMPTY => PropertyValue::Empty,
+            VT_NULL => PropertyValue::Null,
+            VT_I2 => PropertyValue::I2(i16::from_le_bytes(
+                fixed(2)?.try_into().unwrap(),
+            )),
+            VT_I4 => PropertyValue::I4(i32::from_le_bytes(
+                fixed(4)?.try_into().unwrap(),
+            )),
+            VT_R4 => PropertyValue::R4(f32::from_le_bytes(
+                fixed(4)?.try_into().unwrap(),
+            )),
+            VT_R8 => PropertyValue::R8(f64::from_le_bytes(
+                fixed(8)?.try_into().unwrap(),
+            )),
+            VT_BOOL => PropertyValue::Bool(fixed(2)? != [0, 0]),
+            VT_UI4 => PropertyValue::UI4(u32::from_le_bytes(
+                fixed(4)?.try_into().unwrap(),
+            )),
+            VT_I8 => PropertyValue::I8(i64::from_le_bytes(
+                fixed(8)?.try_into().unwrap(),
+            )),
+            VT_UI8 => PropertyValue::UI8(u64::from_le_bytes(
+                fixed(8)?.try_into().unwrap(),
+            )),
+            VT_LPSTR => PropertyValue::LpStr(counted(1)?.to_vec()),
+            VT_LPWSTR => {
+                let units: Vec<u16> = counted(2)?
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .take_while(|&unit| unit != 0)
+                    .collect();
+                PropertyValue::LpWStr(String::from_utf16_lossy(&units))
+            }
+            VT_FILETIME => PropertyValue::FileTime(u64::from_le_bytes(
+                fixed(8)?.try_into().unwrap(),
+            )),
+            VT_BLOB => PropertyValue::Blob(counted(1)?.to_vec()),
+            VT_CLSID => PropertyValue::Clsid(Uuid::from_bytes_le(
+                fixed(16)?.try_into().unwrap(),
+            )),
+            _ => PropertyValue::Other(data[..extent].to_vec()),
+        })
+    }
+
+    /// Appends this value, padded to a multiple of four bytes, to `out`.
+    /// `VT_LPSTR` values are null-terminated as the given code page
+    /// requires.
+    fn write_to(&self, codepage: Option<u16>, out: &mut Vec<u8>) {
+        let start = out.len();
+        let write_type = |type_code: u16, out: &mut Vec<u8>| {
+            out.extend_from_slice(&u32::from(type_code).to_le_bytes());
+        };
+        match *self {
+            PropertyValue::Empty => write_type(VT_EMPTY, out),
+            PropertyValue::Null => write_type(VT_NULL, out),
+            PropertyValue::I2(value) => {
+                write_type(VT_I2, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::I4(value) => {
+                write_type(VT_I4, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::R4(value) => {
+                write_type(VT_R4, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::R8(value) => {
+                write_type(VT_R8, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::Bool(value) => {
+                write_type(VT_BOOL, out);
+                let value: u16 = if value { 0xffff } else { 0 };
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::UI4(value) => {
+                write_type(VT_UI4, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::I8(value) => {
+                write_type(VT_I8, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::UI8(value) => {
+                write_type(VT_UI8, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::LpStr(ref bytes) => {
+                write_type(VT_LPSTR, out);
+                let terminator: &[u8] = if codepage == Some(CODEPAGE_UTF16) {
+                    &[0, 0]
+                } else {
+                    &[0]
+                };
+                let len = (bytes.len() + terminator.len()) as u32;
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(terminator);
+            }
+            PropertyValue::LpWStr(ref string) => {
+                write_type(VT_LPWSTR, out);
+                let units: Vec<u16> =
+                    string.encode_utf16().chain(Some(0)).collect();
+                out.extend_from_slice(&(units.len() as u32).to_le_bytes());
+                for unit in units {
+                    out.extend_from_slice(&unit.to_le_bytes());
+                }
+            }
+            PropertyValue::FileTime(value) => {
+                write_type(VT_FILETIME, out);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            PropertyValue::Blob(ref bytes) => {
+                write_type(VT_BLOB, out);
+                out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                out.extend_from_slice(bytes);
+            }
+            PropertyValue::Clsid(clsid) => {
+                write_type(VT_CLSID, out);
+                out.extend_from_slice(&clsid.to_bytes_le());
+            }
+            PropertyValue::Dictionary(ref entries) => {
+                let unit = dictionary_char_len(codepage);
+                out.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+                for (pid, name) in entries.iter() {
+                    let entry_start = out.len();
+                    out.extend_from_slice(&pid.to_le_bytes());
+                    // The length is in characters, counting the null
+                    // terminator.
+                    let len = name.len().div_ceil(unit) + 1;
+                    out.extend_from_slice(&(len as u32).to_le_bytes());
+                    out.extend_from_slice(name);
+                    out.resize(entry_start + 8 + len * unit, 0);
+                    // In UTF-16, each entry is padded separately.
+                    while unit == 2 && (out.len() - entry_start) % 4 != 0 {
+                        out.push(0);
+                    }
+                }
+            }
+            PropertyValue::Other(ref bytes) => out.extend_from_slice(bytes),
+        }
+        while (out.len() - start) % 4 != 0 {
+            out.push(0);
+        }
+    }
+}
+
+//===========================================================================//
+
+/// One section of a property set stream: a format ID, and a list of
+/// properties in the order they are stored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropertySection {
+    fmtid: Uuid,
+    properties: Vec<(u32, PropertyValue)>,
+}
+
+impl PropertySection {
+    /// Returns a new section with the given format ID, whose only property
+    /// is a code page of Windows-1252.
+    pub fn new(fmtid: Uuid) -> PropertySection {
+        let codepage = PropertyValue::I2(CODEPAGE_WINDOWS_1252 as i16);
+        PropertySection { fmtid, properties: vec![(PID_CODEPAGE, codepage)] }
+    }
+
+    /// Returns the format ID of this section.
+    pub fn fmtid(&self) -> Uuid {
+        self.fmtid
+    }
+
+    /// Returns the properties in this section, as pairs of property ID and
+    /// value, in the order they are stored.
+    pub fn properties(&self) -> &[(u32, PropertyValue)] {
+        &self.properties
+    }
+
+    /// Returns the value of the given property, if present.
+    pub fn get(&self, pid: u32) -> Option<&PropertyValue> {
+        self.properties
+            .iter()
+            .find(|&&(id, _)| id == pid)
+            .map(|(_, value)| value)
+    }
+
+    /// Sets the value of the given property, replacing any existing value
+    /// (in place) or else adding it to the end.  Returns the old value, if
+    /// any.
+    ///
+    /// Note that a `VT_LPSTR` value must already be in this section's code
+    /// page; use [`set_string`](#method.set_string) to convert it.
+    pub fn set(
+        &mut self,
+        pid: u32,
+        value: PropertyValue,
+    ) -> Option<PropertyValue> {
+        match self.properties.iter_mut().find(|(id, _)| *id == pid) {
+            Some((_, old)) => Some(std::mem::replace(old, value)),
+            None => {
+                self.properties.push((pid, value));
+                None
+            }
+        }
+    }
+
+    /// Removes the given property, returning its value, if present.
+    pub fn remove(&mut self, pid: u32) -> Option<PropertyValue> {
+        let index = self.properties.iter().position(|&(id, _)| id == pid)?;
+        Some(self.properties.remove(index).1)
+    }
+
+    /// Returns this section's code page, as given by its
+    /// [`PID_CODEPAGE`](constant.PID_CODEPAGE.html) property.
+    pub fn codepage(&self) -> Option<u16> {
+        match self.get(PID_CODEPAGE) {
+            // Code pages above 32767 (such as UTF-8) are stored as negative
+            // numbers.
+            Some(&PropertyValue::I2(codepage)) => Some(codepage as u16),
+            _ => None,
+        }
+    }
+
+    /// Changes this section's code page, converting every `VT_LPSTR`
+    /// property, and every name in the dictionary, to it.  Returns an error, changing nothing, if one of them
+    /// can't be represented in the new code page (or, unless it is ASCII,
+    /// if either code page is one that this module can't convert).
+    pub fn set_codepage(&mut self, codepage: u16) -> io::Result<()> {
+        let old_codepage = self.codepage();
+        let convert = |pid: u32, bytes: &[u8]| -> io::Result<Vec<u8>> {
+            match decode_string(bytes, old_codepage) {
+                Some(string) => encode_string(&string, Some(codepage)),
+                None => invalid_input!(
+                    "Property {} can't be converted from code page {:?}",
+                    pid,
+                    old_codepage
+                ),
+            }
+        };
+        let mut converted = Vec::new();
+        for (index, (pid, value)) in self.properties.iter().enumerate() {
+            match *value {
+                PropertyValue::LpStr(ref bytes) => converted.push((
+                    index,
+                    PropertyValue::LpStr(convert(*pid, bytes)?),
+                )),
+                PropertyValue::Dictionary(ref entries) => {
+                    let entries = entries
+                        .iter()
+                        .map(|(pid, name)| Ok((*pid, convert(*pid, name)?)))
+                        .collect::<io::Result<_>>()?;
+                    converted
+                        .push((index, PropertyValue::Dictionary(entries)));
+                }
+                _ => {}
+            }
+        }
+        for (index, value) in converted {
+            self.properties[index].1 = value;
+        }
+        self.set(PID_CODEPAGE, PropertyValue::I2(codepage as i16));
+        Ok(())
+    }
+
+    /// Returns the value of the given string property (`VT_LPSTR`, decoded
+    /// from this section's code page, or `VT_LPWSTR`).  Bytes that can't be
+    /// decoded are replaced with U+FFFD; use [`get`](#method.get) to see the
+    /// stored bytes.
+    pub fn string(&self, pid: u32) -> Option<String> {
+        match *self.get(pid)? {
+            PropertyValue::LpStr(ref bytes) => {
+                let codepage = self.codepage();
+                Some(
+                    decode_string(bytes, codepage)
+                        .unwrap_or_else(|| decode_lossy(bytes, codepage)),
+                )
+            }
+            PropertyValue::LpWStr(ref string) => Some(string.clone()),
+            _ => None,
+        }
+    }
+
+    /// Sets the given property to a `VT_LPSTR` string, encoded in this
+    /// section's code page.  Returns an error if the string can't be
+    /// represented in that code page.
+    pub fn set_string(&mut self, pid: u32, string: &str) -> io::Result<()> {
+        let bytes = encode_string(string, self.codepage())?;
+        self.set(pid, PropertyValue::LpStr(bytes));
+        Ok(())
+    }
+
+    /// Returns the value of the given integer property (`VT_I4`, or
+    /// `VT_I2`).
+    pub fn i32(&self, pid: u32) -> Option<i32> {
+        match *self.get(pid)? {
+            PropertyValue::I4(value) => Some(value),
+            PropertyValue::I2(value) => Some(i32::from(value)),
+            _ => None,
+        }
+    }
+
+    /// Sets the given property to a `VT_I4` integer.
+    pub fn set_i32(&mut self, pid: u32, value: i32) {
+        self.set(pid, PropertyValue::I4(value));
+    }
+
+    /// Returns the value of the given `VT_BOOL` property.
+    pub fn bool(&self, pid: u32) -> Option<bool> {
+        match *self.get(pid)? {
+            PropertyValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Sets the given property to a `VT_BOOL`.
+    pub fn set_bool(&mut self, pid: u32, value: bool) {
+        self.set(pid, PropertyValue::Bool(value));
+    }
+
+    /// Returns the time given by the given `VT_FILETIME` property.  Returns
+    /// `None` if the property is zero, which conventionally means that the
+    /// time is unknown (e.g. for a document that was never printed).
+    pub fn time(&self, pid: u32) -> Option<SystemTime> {
+        match *self.get(pid)? {
+            PropertyValue::FileTime(0) => None,
+            PropertyValue::FileTime(value) => {
+                Some(Timestamp::from_value(value).to_system_time())
+            }
+            _ => None,
+        }
+    }
+
+    /// Sets the given property to a `VT_FILETIME` for the given time.
+    /// Returns an error if the time is before 1601 or too far in the future
+    /// to be stored.
+    pub fn set_time(&mut self, pid: u32, time: SystemTime) -> io::Result<()> {
+        let timestamp = match Timestamp::checked_from_system_time(time) {
+            Some(timestamp) => timestamp,
+            None => invalid_input!(
+                "Time {:?} can't be stored in a property set",
+                time
+            ),
+        };
+        self.set(pid, PropertyValue::FileTime(timestamp.value()));
+        Ok(())
+    }
+
+    /// Returns the names in this section's dictionary (see
+    /// [`PID_DICTIONARY`](constant.PID_DICTIONARY.html)), as pairs of
+    /// property ID and name, in the order they are stored.  Names are
+    /// decoded from this section's code page, with bytes that can't be
+    /// decoded replaced by U+FFFD.  Returns an empty list if the section has
+    /// no dictionary, as is the case for most sections other than
+    /// [`FMTID_USER_DEFINED_PROPERTIES`](constant.FMTID_USER_DEFINED_PROPERTIES.html).
+    pub fn names(&self) -> Vec<(u32, String)> {
+        let codepage = self.codepage();
+        self.dictionary()
+            .iter()
+            .map(|(pid, name)| {
+                let name = decode_string(name, codepage)
+                    .unwrap_or_else(|| decode_lossy(name, codepage));
+                (*pid, name)
+            })
+            .collect()
+    }
+
+    /// Returns the name that the dictionary gives the given property, if
+    /// any.
+    pub fn name(&self, pid: u32) -> Option<String> {
+        self.names()
+            .into_iter()
+            .find(|&(id, _)| id == pid)
+            .map(|(_, name)| name)
+    }
+
+    /// Returns the ID of the property with the given name, if any.  As in
+    /// Windows, names are compared case-insensitively.
+    pub fn pid_by_name(&self, name: &str) -> Option<u32> {
+        let name = name.to_lowercase();
+        self.names()
+            .into_iter()
+            .find(|(_, other)| other.to_lowercase() == name)
+            .map(|(pid, _)| pid)
+    }
+
+    /// Returns the value of the property with the given name, if present.
+    pub fn get_by_name(&self, name: &str) -> Option<&PropertyValue> {
+        self.get(self.pid_by_name(name)?)
+    }
+
+    /// Gives the given property a name in the dictionary, replacing any
+    /// name it already has, and adding a dictionary to the start of the
+    /// section if there is none.  Returns an error if the name is empty,
+    /// is already used by another property, or can't be represented in
+    /// this section's code page.
+    pub fn set_name(&mut self, pid: u32, name: &str) -> io::Result<()> {
+        if pid == PID_DICTIONARY || pid == PID_CODEPAGE {
+            invalid_input!("Property {} can't be given a name", pid);
+        }
+        if name.is_empty() {
+            invalid_input!("Property names must not be empty");
+        }
+        match self.pid_by_name(name) {
+            Some(other) if other != pid => already_exists!(
+                "Property {} is already named {:?}",
+                other,
+                name
+            ),
+            _ => {}
+        }
+        let bytes = encode_string(name, self.codepage())?;
+        let entries = self.dictionary_mut();
+        match entries.iter_mut().find(|(id, _)| *id == pid) {
+            Some((_, old)) => *old = bytes,
+            None => entries.push((pid, bytes)),
+        }
+        Ok(())
+    }
+
+    /// Sets the value of the property with the given name, replacing any
+    /// existing value (in place).  If no property has that name yet, the
+    /// value is given the lowest property ID above every one already in
+    /// use, and the name is added to the dictionary (see
+    /// [`set_name`](#method.set_name)).  Returns the old value, if any.
+    pub fn set_by_name(
+        &mut self,
+        name: &str,
+        value: PropertyValue,
+    ) -> io::Result<Option<PropertyValue>> {
+        let pid = match self.pid_by_name(name) {
+            Some(pid) => pid,
+            None => {
+                // IDs from 0x80000000 up are reserved for special purposes.
+                let pid = self
+                    .properties
+                    .iter()
+                    .map(|&(pid, _)| pid)
+                    .chain(self.dictionary().iter().map(|&(pid, _)| pid))
+                    .filter(|&pid| pid < 0x8000_0000)
+                    .fold(PID_CODEPAGE, u32::max)
+                    + 1;
+                if pid >= 0x8000_0000 {
+                    invalid_input!("No property IDs are left for {:?}", name);
+                }
+                self.set_name(pid, name)?;
+                pid
+            }
+        };
+        Ok(self.set(pid, value))
+    }
+
+    /// Removes the property with the given name, and its name, returning
+    /// its value, if present.
+    pub fn remove_by_name(&mut self, name: &str) -> Option<PropertyValue> {
+        let pid = self.pid_by_name(name)?;
+        self.dictionary_mut().retain(|&(id, _)| id != pid);
+        self.remove(pid)
+    }
+
+    /// Returns the entries of this section's dictionary, if it has one.
+    fn dictionary(&self) -> &[(u32, Vec<u8>)] {
+        match self.get(PID_DICTIONARY) {
+            Some(PropertyValue::Dictionary(entries)) => entries,
+            _ => &[],
+        }
+    }
+
+    /// Returns the entries of this section's dictionary, first adding an
+    /// empty dictionary to the start of the section if there is none.
+    fn dictionary_mut(&mut self) -> &mut Vec<(u32, Vec<u8>)> {
+        let index = match self
+            .properties
+            .iter()
+            .position(|&(pid, _)| pid == PID_DICTIONARY)
+        {
+            Some(index) => index,
+            None => {
+                let dictionary = PropertyValue::Dictionary(Vec::new());
+                self.properties.insert(0, (PID_DICTIONARY, dictionary));
+                0
+            }
+        };
+        let value = &mut self.properties[index].1;
+        if !matches!(value, PropertyValue::Dictionary(_)) {
+            *value = PropertyValue::Dictionary(Vec::new());
+        }
+        match value {
+            PropertyValue::Dictionary(entries) => entries,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Parses the section starting at the start of `data`.
+    fn parse(fmtid: Uuid, data: &[u8]) -> io::Result<PropertySection> {
+        let (Some(len), Some(num_properties)) =
+            (read_u32(data, 0), read_u32(data, 4))
+        else {
+            invalid_data!("Property set section {} is truncated", fmtid);
+        };
+        let len = len as usize;
+        if len > data.len() {
+            invalid_data!(
+                "Property set section {} has length {}, but only {} bytes \
+                 remain in the stream",
+                fmtid,
+                len,
+                data.len()
+            );
+        }
+        let data = &data[..len];
+        let table_len = (num_properties as usize)
+            .checked_mul(8)
+            .and_then(|table_len| table_len.checked_add(8));
+        if table_len.map_or(true, |table_len| table_len > len) {
+            invalid_data!(
+                "Property set section {} claims {} properties, but is only \
+                 {} bytes long",
+                fmtid,
+                num_properties,
+                len
+            );
+        }
+        let mut table = Vec::with_capacity(num_properties as usize);
+        for index in 0..num_properties as usize {
+            let pid = read_u32(data, 8 + 8 * index).unwrap();
+            let offset = read_u32(data, 12 + 8 * index).unwrap() as usize;
+            if offset >= len {
+                invalid_data!(
+                    "Property {} in section {} is at offset {}, past the end \
+                     of the section",
+                    pid,
+                    fmtid,
+                    offset
+                );
+            }
+            table.push((pid, offset));
+        }
+        // Each value extends up to the next value (or the end of the
+        // section), which is as much as can be kept for types that aren't
+        // understood.
+        let mut offsets: Vec<usize> =
+            table.iter().map(|&(_, offset)| offset).collect();
+        offsets.push(len);
+        offsets.sort_unstable();
+        let mut properties = Vec::with_capacity(table.len());
+        for (pid, offset) in table {
+            let extent =
+                offsets[offsets.partition_point(|&other| other <= offset)];
+            let value =
+                PropertyValue::parse(pid, &data[offset..], extent - offset)?;
+            properties.push((pid, value));
+        }
+        let mut section = PropertySection { fmtid, properties };
+        // Strip the null terminator from each VT_LPSTR, now that the code
+        // page (which may be anywhere in the table) says how long it is.
+        // Any further nulls are padding that some writers count in the
+        // length, and are kept so that the value is written back unchanged.
+        let terminator_len =
+            if section.codepage() == Some(CODEPAGE_UTF16) { 2 } else { 1 };
+        // The dictionary's layout likewise depends on the code page.
+        let codepage = section.codepage();
+        for (pid, value) in section.properties.iter_mut() {
+            match *value {
+                PropertyValue::LpStr(ref mut bytes) => {
+                    let len = bytes.len().saturating_sub(terminator_len);
+                    if bytes[len..].iter().all(|&byte| byte == 0) {
+                        bytes.truncate(len);
+                    }
+                }
+                PropertyValue::Other(ref bytes) if *pid == PID_DICTIONARY => {
+                    *value = PropertyValue::Dictionary(parse_dictionary(
+                        bytes, codepage,
+                    )?);
+                }
+                _ => {}
+            }
+        }
+        Ok(section)
+    }
+
+    /// Appends this section to `out`.
+    fn write_to(&self, out: &mut Vec<u8>) {
+        let codepage = self.codepage();
+        let table_len = 8 + 8 * self.properties.len();
+        let mut table = Vec::with_capacity(table_len);
+        let mut values = Vec::new();
+        for (pid, value) in self.properties.iter() {
+            table.extend_from_slice(&pid.to_le_bytes());
+            let offset = (table_len + values.len()) as u32;
+            table.extend_from_slice(&offset.to_le_bytes());
+            value.write_to(codepage, &mut values);
+        }
+        let len = (table_len + values.len()) as u32;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(self.properties.len() as u32).to_le_bytes());
+        out.extend_from_slice(&table);
+        out.extend_from_slice(&values);
+    }
+}
+
+//===========================================================================//
+
+/// A property set stream: a header, followed by one or more sections of
+/// properties.
+///
+/// The stream is usually read with
+/// [`CompoundFile::read_property_set`](../struct.CompoundFile.html#method.read_property_set),
+/// but can be read from (and written to) anything with
+/// [`read_from`](#method.read_from) and [`write_to`](#method.write_to).
+/// Property order, and values of types that aren't understood, are
+/// preserved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropertySet {
+    format_version: u16,
+    system_identifier: u32,
+    clsid: Uuid,
+    sections: Vec<PropertySection>,
+}
+
+impl PropertySet {
+    /// Returns a new property set with a single section with the given
+    /// format ID (see [`PropertySection::new`](struct.PropertySection.html#method.new)).
+    pub fn new(fmtid: Uuid) -> PropertySet {
+        PropertySet {
+            format_version: 0,
+            system_identifier: DEFAULT_SYSTEM_IDENTIFIER,
+            clsid: Uuid::nil(),
+            sections: vec![PropertySection::new(fmtid)],
+        }
+    }
+
+    /// Returns the format version of the stream (0 or 1).
+    pub fn format_version(&self) -> u16 {
+        self.format_version
+    }
+
+    /// Returns the identifier of the system that wrote the stream (the
+    /// operating system kind and version).
+    pub fn system_identifier(&self) -> u32 {
+        self.system_identifier
+    }
+
+    /// Returns the CLSID recorded in the stream's header.
+    pub fn clsid(&self) -> &Uuid {
+        &self.clsid
+    }
+
+    /// Returns the format ID of the first section, which identifies the
+    /// property set.
+    pub fn fmtid(&self) -> Uuid {
+        self.section().fmtid()
+    }
+
+    /// Returns the first section, which holds the property set's
+    /// properties.
+    pub fn section(&self) -> &PropertySection {
+        &self.sections[0]
+    }
+
+    /// Returns the first section, for changing its properties.
+    pub fn section_mut(&mut self) -> &mut PropertySection {
+        &mut self.sections[0]
+    }
+
+    /// Returns all sections, in the order they are stored.
+    pub fn sections(&self) -> &[PropertySection] {
+        &self.sections
+    }
+
+    /// Returns the section with the given format ID, if any.
+    pub fn section_by_fmtid(&self, fmtid: Uuid) -> Option<&PropertySection> {
+        self.sections.iter().find(|section| section.fmtid == fmtid)
+    }
+
+    /// Returns the section with the given format ID, adding a new one (see
+    /// [`PropertySection::new`](struct.PropertySection.html#method.new)) to
+    /// the end if there is none.
+    pub fn section_by_fmtid_mut(
+        &mut self,
+        fmtid: Uuid,
+    ) -> &mut PropertySection {
+        match self.sections.iter().position(|section| section.fmtid == fmtid) {
+            Some(index) => &mut self.sections[index],
+            None => {
+                self.sections.push(PropertySection::new(fmtid));
+                self.sections.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Reads a property set stream from `reader`, to its end.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<PropertySet> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        PropertySet::parse(&data)
+    }
+
+    /// Writes this property set as a stream to `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    fn parse(data: &[u8]) -> io::Result<PropertySet> {
+        if data.len() < 28 {
+            invalid_data!(
+                "Property set stream is only {} bytes long",
+                data.len()
+            );
+        }
+        let byte_order = read_u16(data, 0).unwrap();
+        if byte_order != BYTE_ORDER_MARK {
+            invalid_data!(
+                "Invalid property set byte order mark (expected 0x{:04x}, \
+                 found 0x{:04x})",
+                BYTE_ORDER_MARK,
+                byte_order
+            );
+        }
+        let format_version = read_u16(data, 2).unwrap();
+        let system_identifier = read_u32(data, 4).unwrap();
+        let clsid = Uuid::from_bytes_le(data[8..24].try_into().unwrap());
+        let num_sections = read_u32(data, 24).unwrap();
+        if num_sections == 0 || num_sections > MAX_NUM_SECTIONS {
+            invalid_data!(
+                "Invalid property set section count ({})",
+                num_sections
+            );
+        }
+        let mut sections = Vec::with_capacity(num_sections as usize);
+        for index in 0..num_sections as usize {
+            let entry = 28 + 20 * index;
+            let (Some(fmtid), Some(offset)) =
+                (data.get(entry..entry + 16), read_u32(data, entry + 16))
+            else {
+                invalid_data!("Property set stream header is truncated");
+            };
+            let fmtid = Uuid::from_bytes_le(fmtid.try_into().unwrap());
+            let Some(section) = data.get(offset as usize..) else {
+                invalid_data!(
+                    "Property set section {} is at offset {}, past the end \
+                     of the stream",
+                    fmtid,
+                    offset
+                );
+            };
+            sections.push(PropertySection::parse(fmtid, section)?);
+        }
+        Ok(PropertySet { format_version, system_identifier, clsid, sections })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&BYTE_ORDER_MARK.to_le_bytes());
+        data.extend_from_slice(&self.format_version.to_le_bytes());
+        data.extend_from_slice(&self.system_identifier.to_le_bytes());
+        data.extend_from_slice(&self.clsid.to_bytes_le());
+        data.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+        let mut sections = Vec::new();
+        let header_len = 28 + 20 * self.sections.len();
+        for section in self.sections.iter() {
+            data.extend_from_slice(&section.fmtid.to_bytes_le());
+            let offset = (header_len + sections.len()) as u32;
+            data.extend_from_slice(&offset.to_le_bytes());
+            section.write_to(&mut sections);
+        }
+        data.extend_from_slice(&sections);
+        data
+    }
+}
+
+//===========================================================================//
+
+/// Decodes a `VT_LPSTR` value in the given code page, or returns `None` if
+/// it isn't valid in that code page, or the code page isn't one that this
+/// module can convert (in which case only ASCII can be decoded).  The value
+/// ends at its first null terminator.
+fn decode_string(bytes: &[u8], codepage: Option<u16>) -> Option<String> {
+    if codepage == Some(CODEPAGE_UTF16) {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        return String::from_utf16(&units).ok();
+    }
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    let bytes = &bytes[..end];
+    match codepage {
+        Some(CODEPAGE_UTF8) => String::from_utf8(bytes.to_vec()).ok(),
+        Some(CODEPAGE_WINDOWS_1252) => Some(
+            bytes
+                .iter()
+                .map(|&byte| match byte {
+                    0x80..=0x9f => WINDOWS_1252_HIGH[byte as usize - 0x80],
+                    _ => char::from(byte),
+                })
+                .collect(),
+        ),
+        Some(CODEPAGE_LATIN1) => {
+            Some(bytes.iter().map(|&byte| char::from(byte)).collect())
+        }
+        _ if bytes.is_ascii() => {
+            Some(bytes.iter().map(|&byte| char::from(byte)).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Decodes a `VT_LPSTR` value as best it can, replacing anything that can't
+/// be decoded with U+FFFD.
+fn decode_lossy(bytes: &[u8], codepage: Option<u16>) -> String {
+    if codepage == Some(CODEPAGE_UTF8) {
+        let end =
+            bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+        return String::from_utf8_lossy(&bytes[..end]).into_owned();
+    }
+    if codepage == Some(CODEPAGE_UTF16) {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| {
+            if byte.is_ascii() {
+                char::from(byte)
+            } else {
+                char::REPLACEMENT_CHARACTER
+            }
+        })
+        .collect()
+}
+
+/// Encodes a string as a `VT_LPSTR` value (without its null terminator) in
+/// the given code page.
+fn encode_string(string: &str, codepage: Option<u16>) -> io::Result<Vec<u8>> {
+    if string.contains('\0') {
+        invalid_input!("Property string {:?} contains a null", string);
+    }
+    let bytes = match codepage {
+        Some(CODEPAGE_UTF16) => Some(
+            string
+                .encode_utf16()
+                .flat_map(|unit| unit.to_le_bytes())
+                .collect(),
+        ),
+        Some(CODEPAGE_UTF8) => Some(string.as_bytes().to_vec()),
+        Some(CODEPAGE_WINDOWS_1252) => string
+            .chars()
+            .map(|chr| match chr as u32 {
+                0..=0x7f | 0xa0..=0xff => Some(chr as u8),
+                _ => WINDOWS_1252_HIGH
+                    .iter()
+                    .position(|&high| high == chr)
+                    .map(|index| 0x80 + index as u8),
+            })
+            .collect(),
+        Some(CODEPAGE_LATIN1) => {
+            string.chars().map(|chr| u8::try_from(chr as u32).ok()).collect()
+        }
+        _ if string.is_ascii() => Some(string.as_bytes().to_vec()),
+        _ => None,
+    };
+    match bytes {
+        Some(bytes) => Ok(bytes),
+        None => invalid_input!(
+            "Property string {:?} can't be stored in code page {:?}",
+            string,
+            codepage
+        ),
+    }
+}
+
+/// Returns the size in bytes of a character of a dictionary name in the
+/// given code page.
+fn dictionary_char_len(codepage: Option<u16>) -> usize {
+    if codepage == Some(CODEPAGE_UTF16) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Parses the dictionary stored in `data` (which may extend past its end),
+/// in the given code page.
+fn parse_dictionary(
+    data: &[u8],
+    codepage: Option<u16>,
+) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let Some(num_entries) = read_u32(data, 0) else {
+        invalid_data!("Property set dictionary is truncated");
+    };
+    // Each entry takes at least nine bytes, which bounds the allocation.
+    if num_entries as usize > data.len() / 9 {
+        invalid_data!(
+            "Property set dictionary claims {} entries, but is only {} \
+             bytes long",
+            num_entries,
+            data.len()
+        );
+    }
+    let unit = dictionary_char_len(codepage);
+    let mut entries = Vec::with_capacity(num_entries as usize);
+    let mut offset = 4;
+    for _ in 0..num_entries {
+        let (Some(pid), Some(len)) =
+            (read_u32(data, offset), read_u32(data, offset + 4))
+        else {
+            invalid_data!("Property set dictionary is truncated");
+        };
+        let start = offset + 8;
+        let end = (len as usize).checked_mul(unit).map(|len| start + len);
+        let Some(name) = end.and_then(|end| data.get(start..end)) else {
+            invalid_data!(
+                "Name of property {} has length {}, which runs past the end \
+                 of its section",
+                pid,
+                len
+            );
+        };
+        // As for VT_LPSTR values, only the null terminator is stripped, so
+        // that the name is written back unchanged.
+        let mut name = name.to_vec();
+        let stripped = name.len().saturating_sub(unit);
+        if name[stripped..].iter().all(|&byte| byte == 0) {
+            name.truncate(stripped);
+        }
+        entries.push((pid, name));
+        offset = start + len as usize * unit;
+        if unit == 2 {
+            offset = (offset + 3) & !3;
+        }
+    }
+    Ok(entries)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+//===========================================================================//
+
+impl<F: Read + Seek> CompoundFile<F> {
+    /// Reads the property set stream at the given path (such as
+    /// [`propset::SUMMARY_INFO_PATH`](propset/constant.SUMMARY_INFO_PATH.html)).
+    /// Like [`read_stream_to_vec`](struct.CompoundFile.html#method.read_stream_to_vec),
+    /// this fails if the stream is longer than the limit set with
+    /// [`set_max_buffer_len`](struct.CompoundFile.html#method.set_max_buffer_len).
+    pub fn read_property_set<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<PropertySet> {
+        PropertySet::parse(&self.read_stream_to_vec(path)?)
+    }
+}
+
+impl<F: Read + Write + Seek> CompoundFile<F> {
+    /// Writes a property set to the stream at the given path, replacing the
+    /// stream's contents if it already exists (without ever leaving it
+    /// half-written; see
+    /// [`replace_stream`](struct.CompoundFile.html#method.replace_stream)),
+    /// or else creating it.  The parent storage must already exist.
+    pub fn write_property_set<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        set: &PropertySet,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        let data = set.to_bytes();
+        if self.is_stream(path) {
+            self.replace_stream(path, &mut data.as_slice())?;
+        } else {
+            self.create_new_stream(path)?.write_all(&data)?;
+        }
+        Ok(())
+    }
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_string, encode_string, CODEPAGE_LATIN1, CODEPAGE_UTF16,
+        CODEPAGE_UTF8, CODEPAGE_WINDOWS_1252,
+    };
+
+    #[test]
+    fn windows_1252_round_trip() {
+        let bytes: Vec<u8> = (1..=255).collect();
+        let string =
+            decode_string(&bytes, Some(CODEPAGE_WINDOWS_1252)).unwrap();
+        assert_eq!(string.chars().count(), 255);
+        assert!(string.contains('\u{20ac}'));
+        assert_eq!(
+            encode_string(&string, Some(CODEPAGE_WINDOWS_1252)).unwrap(),
+            bytes
+        );
+        assert!(encode_string("\u{3b1}", Some(CODEPAGE_WINDOWS_1252)).is_err());
+    }
+
+    #[test]
+    fn codepage_conversions() {
+        let string = "caf\u{e9}";
+        assert_eq!(
+            encode_string(string, Some(CODEPAGE_LATIN1)).unwrap(),
+            b"caf\xe9"
+        );
+        assert_eq!(
+            encode_string(string, Some(CODEPAGE_UTF8)).unwrap(),
+            "caf\u{e9}".as_bytes()
+        );
+        assert_eq!(
+            encode_string(string, Some(CODEPAGE_UTF16)).unwrap(),
+            b"c\0a\0f\0\xe9\0"
+        );
+        assert_eq!(
+            decode_string(b"c\0a\0f\0\xe9\0\0\0", Some(CODEPAGE_UTF16))
+                .unwrap(),
+            string
+        );
+        // Unknown code pages can only hold ASCII.
+        assert_eq!(decode_string(b"abc\0\0", Some(932)).unwrap(), "abc");
+        assert_eq!(decode_string(b"\x82\xa0", Some(932)), None);
+        assert!(encode_string(string, Some(932)).is_err());
+        assert!(encode_string(string, None).is_err());
+    }
+}
+
+//===========================================================================//
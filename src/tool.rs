@@ -0,0 +1,1747 @@
+//! Building blocks for command-line tools that inspect and edit compound
+//! files.
+//!
+//! These are the pieces that the `cfbtool` binary is made of, exposed so
+//! that other tools can reuse them without shelling out.  This module is
+//! only built with the `cli` feature.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, IsTerminal, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub use crate::internal::glob_match;
+pub use crate::internal::{
+    decode_msi_chars, decode_msi_name, decode_msi_name_into, encode_msi_name,
+    encode_msi_name_into, MsiNameChars,
+};
+use crate::internal::{ioutil, Timestamp};
+use crate::{CompoundFile, Entry, EntryKind, SectorId, StreamId};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use serde_json::Value;
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// Splits a command-line argument of the form `FILE:PATH` into the path of
+/// the compound file on disk and the path of an object within it.  If there
+/// is no separator, the inner path is empty.
+///
+/// A literal colon within `FILE` can be written as `::`.  On Windows, a
+/// leading drive letter (as in `C:\x.msi:/Stream`) is not treated as a
+/// separator.
+///
+/// ```
+/// use cfb::tool::split_path;
+/// use std::path::PathBuf;
+///
+/// assert_eq!(
+///     split_path("dir/x.msi:/Stream"),
+///     (PathBuf::from("dir/x.msi"), PathBuf::from("/Stream"))
+/// );
+/// assert_eq!(
+///     split_path("odd::name.msi:/Stream"),
+///     (PathBuf::from("odd:name.msi"), PathBuf::from("/Stream"))
+/// );
+/// ```
+pub fn split_path(arg: &str) -> (PathBuf, PathBuf) {
+    split_path_with_drive_letters(arg, cfg!(windows))
+}
+
+fn split_path_with_drive_letters(
+    arg: &str,
+    drive_letters: bool,
+) -> (PathBuf, PathBuf) {
+    let mut file = String::new();
+    let mut rest = arg;
+    if drive_letters && has_drive_letter(arg) {
+        file.push_str(&arg[..2]);
+        rest = &arg[2..];
+    }
+    let mut chars = rest.char_indices().peekable();
+    while let Some((index, chr)) = chars.next() {
+        if chr != ':' {
+            file.push(chr);
+        } else if chars.peek().map(|&(_, next)| next) == Some(':') {
+            chars.next();
+            file.push(':');
+        } else {
+            let inner = &rest[index + 1..];
+            return (PathBuf::from(file), PathBuf::from(inner));
+        }
+    }
+    (PathBuf::from(file), PathBuf::new())
+}
+
+/// Returns true if the argument starts with a drive letter and colon that
+/// are followed by a path separator (as in `C:\` or `C:/`).
+fn has_drive_letter(arg: &str) -> bool {
+    let bytes = arg.as_bytes();
+    bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'\\' || bytes[2] == b'/')
+}
+
+//===========================================================================//
+
+/// Formats a length in bytes for display in a fixed-width column, switching
+/// to larger (binary) units as the number grows.
+pub fn format_size(len: u64) -> String {
+    if len >= 10_000_000_000 {
+        format!("{} GB", len / (1 << 30))
+    } else if len >= 100_000_000 {
+        format!("{} MB", len / (1 << 20))
+    } else if len >= 1_000_000 {
+        format!("{} kB", len / (1 << 10))
+    } else {
+        format!("{} B ", len)
+    }
+}
+
+/// Parses a size such as `4096`, `64K`, or `2M` (with binary units).
+pub fn parse_size(arg: &str) -> Result<u64, String> {
+    let arg = arg.trim();
+    let (digits, shift) = match arg.chars().last() {
+        Some('k' | 'K') => (&arg[..arg.len() - 1], 10),
+        Some('m' | 'M') => (&arg[..arg.len() - 1], 20),
+        Some('g' | 'G') => (&arg[..arg.len() - 1], 30),
+        Some('t' | 'T') => (&arg[..arg.len() - 1], 40),
+        _ => (arg, 0),
+    };
+    let value: u64 =
+        digits.parse().map_err(|_| format!("invalid size: {:?}", arg))?;
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size too large: {:?}", arg))
+}
+
+/// Formats the UTC calendar date of the given time as `YYYY-MM-DD`.
+pub fn format_date(time: SystemTime) -> String {
+    let (year, month, day, _, _) = civil_from_system_time(time);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats the given time in UTC as an RFC 3339 timestamp, such as
+/// `2001-09-09T01:46:40Z`.  Fractional seconds are included (to the 100ns
+/// resolution of a FILETIME) only if there are any.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (year, month, day, secs_of_day, nanos) = civil_from_system_time(time);
+    let mut output = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    );
+    if nanos >= 100 {
+        output.push_str(&format!(".{:07}", nanos / 100));
+    }
+    output.push('Z');
+    output
+}
+
+/// Splits the given time into its UTC year, month, day, seconds within the
+/// day, and nanoseconds within the second.
+fn civil_from_system_time(time: SystemTime) -> (i64, i64, i64, i64, u32) {
+    let (secs, nanos): (i64, u32) = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        Err(err) => {
+            let duration = err.duration();
+            match duration.subsec_nanos() {
+                0 => (-(duration.as_secs() as i64), 0),
+                nanos => {
+                    (-(duration.as_secs() as i64) - 1, 1_000_000_000 - nanos)
+                }
+            }
+        }
+    };
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = secs.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month =
+        if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day, secs.rem_euclid(86_400), nanos)
+}
+
+/// Formats an entry for a directory listing under the given display name.
+/// The short format is just the name; the long format also includes the
+/// object type, state bits, size (streams only), and date, plus a second line
+/// with the CLSID for storages.
+pub fn format_entry(name: &str, entry: &Entry, long: bool) -> String {
+    if !long {
+        return name.to_string();
+    }
+    let mut output = format!(
+        "{}{:08x}   {:>10}   {}   {}",
+        if entry.is_storage() { '+' } else { '-' },
+        entry.state_bits(),
+        if entry.is_stream() {
+            format_size(entry.len())
+        } else {
+            String::new()
+        },
+        format_date(entry.created().max(entry.modified())),
+        name
+    );
+    if entry.is_storage() {
+        output.push_str(&format!("\n {}", entry.clsid().hyphenated()));
+    }
+    output
+}
+
+//===========================================================================//
+
+/// How object names and paths are written to `cfbtool`'s output.
+///
+/// Names may contain control characters (such as the `\u{5}` that begins
+/// `\u{5}SummaryInformation`), which would otherwise be sent to the terminal
+/// as-is.  Whatever the style, output should be written through
+/// `std::io::stdout` as text, which on Windows consoles is converted to
+/// UTF-16 rather than passed through as bytes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NameStyle {
+    /// Names are written exactly as they are stored.
+    Raw,
+    /// Control characters, and characters that change the direction of the
+    /// surrounding text, are written as `\u{...}` escapes.  This is the
+    /// default when writing to a terminal.
+    Terminal,
+    /// Like `Terminal`, but backslashes are also escaped (as `\\`), so that
+    /// the original name can always be recovered with
+    /// [`unescape_name`](fn.unescape_name.html).  This is the default when
+    /// output is redirected to a file or pipe.
+    Escaped,
+}
+
+impl NameStyle {
+    /// Returns the style for names written to standard output: `Raw` if
+    /// `raw` is true, and otherwise `Terminal` or `Escaped`, depending on
+    /// whether standard output is a terminal.
+    pub fn for_stdout(raw: bool) -> NameStyle {
+        if raw {
+            NameStyle::Raw
+        } else if io::stdout().is_terminal() {
+            NameStyle::Terminal
+        } else {
+            NameStyle::Escaped
+        }
+    }
+}
+
+/// Formats an object name for output in the given style.
+///
+/// ```
+/// use cfb::tool::{escape_name, NameStyle};
+///
+/// let name = "\u{5}SummaryInformation";
+/// assert_eq!(
+///     escape_name(name, NameStyle::Escaped),
+///     "\\u{5}SummaryInformation"
+/// );
+/// assert_eq!(escape_name(name, NameStyle::Raw), name);
+/// ```
+pub fn escape_name(name: &str, style: NameStyle) -> String {
+    let mut output = String::with_capacity(name.len());
+    for chr in name.chars() {
+        match chr {
+            '\\' if style == NameStyle::Escaped => output.push_str("\\\\"),
+            _ if style != NameStyle::Raw && is_unprintable_char(chr) => {
+                output.extend(chr.escape_unicode());
+            }
+            _ => output.push(chr),
+        }
+    }
+    output
+}
+
+/// Formats a path within a compound file for output in the given style.
+pub fn escape_path(path: &Path, style: NameStyle) -> String {
+    escape_name(&path.to_string_lossy(), style)
+}
+
+/// Recovers a name from its [`NameStyle::Escaped`](enum.NameStyle.html)
+/// form, or returns `None` if it contains an invalid escape.
+///
+/// ```
+/// use cfb::tool::{escape_name, unescape_name, NameStyle};
+///
+/// let name = "tab\there\\";
+/// let escaped = escape_name(name, NameStyle::Escaped);
+/// assert_eq!(unescape_name(&escaped).as_deref(), Some(name));
+/// assert_eq!(unescape_name("\\x"), None);
+/// ```
+pub fn unescape_name(escaped: &str) -> Option<String> {
+    let mut output = String::with_capacity(escaped.len());
+    let mut rest = escaped;
+    while let Some(index) = rest.find('\\') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        if let Some(after) = rest.strip_prefix('\\') {
+            output.push('\\');
+            rest = after;
+        } else {
+            let after = rest.strip_prefix("u{")?;
+            let end = after.find('}')?;
+            let value = u32::from_str_radix(&after[..end], 16).ok()?;
+            output.push(char::from_u32(value)?);
+            rest = &after[end + 1..];
+        }
+    }
+    output.push_str(rest);
+    Some(output)
+}
+
+/// Returns true for characters that could disturb a terminal if written to
+/// it unescaped: control characters, and the characters that override the
+/// direction of the text around them.
+fn is_unprintable_char(chr: char) -> bool {
+    chr.is_control()
+        || matches!(
+            chr,
+            '\u{61c}'
+                | '\u{200e}'
+                | '\u{200f}'
+                | '\u{202a}'..='\u{202e}'
+                | '\u{2066}'..='\u{2069}'
+        )
+}
+
+//===========================================================================//
+
+/// Copies all data from `reader` into a stream at `path` within the compound
+/// file, creating the stream (or replacing an existing one).  The parent
+/// storage must already exist.  Returns the number of bytes copied.
+pub fn put_stream<F: Read + Write + Seek, R: Read>(
+    comp: &mut CompoundFile<F>,
+    path: &Path,
+    reader: &mut R,
+) -> io::Result<u64> {
+    let mut stream = comp.create_stream(path)?;
+    let num_bytes = io::copy(reader, &mut stream)?;
+    stream.flush()?;
+    Ok(num_bytes)
+}
+
+/// The name of the manifest that
+/// [`extract_all`](fn.extract_all.html) writes alongside the extracted
+/// files, recording the exact name and metadata of each object.
+pub const MANIFEST_FILE_NAME: &str = ".cfb-manifest.json";
+
+/// Recursively copies the object at `path` within the compound file into the
+/// local directory `output_dir`, which must already exist.  Storages become
+/// directories and streams become files with a `.dump` extension; both are
+/// named after the MSI-decoded name of the object.  Returns the paths of the
+/// files that were written.
+///
+/// Object names can't always be used as-is for local files: characters that
+/// some filesystems reject or rewrite (such as control characters, `:`, or
+/// Unicode combining marks) are replaced with `_`, and names that would
+/// collide on a case-insensitive filesystem get a `~N` suffix.  So that
+/// nothing is lost, a manifest named
+/// [`MANIFEST_FILE_NAME`](constant.MANIFEST_FILE_NAME.html) is also written
+/// to `output_dir`, mapping each local file back to the exact name of its
+/// object, along with the object's CLSID, state bits, and timestamps;
+/// [`insert_all`](fn.insert_all.html) uses it to reconstruct the original
+/// objects.
+pub fn extract_all<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &Path,
+    output_dir: &Path,
+) -> io::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    let entries: Vec<Entry> = comp.walk_storage(path)?.collect();
+    let base = match entries.first() {
+        Some(entry) if entry.is_storage() => entry.path().to_path_buf(),
+        Some(entry) => entry.path().parent().unwrap().to_path_buf(),
+        None => return Ok(written),
+    };
+    let mut manifest = Vec::new();
+    let mut locals = HashMap::<PathBuf, PathBuf>::new();
+    locals.insert(base.clone(), PathBuf::new());
+    let mut taken = HashMap::<PathBuf, HashSet<String>>::new();
+    taken
+        .entry(PathBuf::new())
+        .or_default()
+        .insert(MANIFEST_FILE_NAME.to_lowercase());
+    for entry in entries.iter() {
+        if entry.path() == base {
+            manifest.push(ManifestEntry::new(entry, &base, PathBuf::new()));
+            continue;
+        }
+        let parent = &locals[entry.path().parent().unwrap()];
+        let siblings = taken.entry(parent.clone()).or_default();
+        let name =
+            unique_local_name(entry.name(), entry.is_stream(), siblings);
+        let relative = parent.join(name);
+        manifest.push(ManifestEntry::new(entry, &base, relative.clone()));
+        let local = output_dir.join(&relative);
+        if entry.is_storage() {
+            fs::create_dir(&local)?;
+            locals.insert(entry.path().to_path_buf(), relative);
+            continue;
+        }
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&local)?;
+        io::copy(&mut comp.open_stream(entry.path())?, &mut file)?;
+        written.push(local);
+    }
+    let mut file = fs::File::create(output_dir.join(MANIFEST_FILE_NAME))?;
+    write_manifest(&mut file, &manifest)?;
+    Ok(written)
+}
+
+/// Recursively copies the contents of the local directory `input_dir` into
+/// the storage at `path` within the compound file, which must already exist.
+/// Existing streams are replaced.  Returns the paths of the streams that
+/// were written.
+///
+/// If `input_dir` contains a manifest written by
+/// [`extract_all`](fn.extract_all.html), exactly the objects it lists are
+/// inserted, with their original names and metadata, regardless of what the
+/// filesystem did to the local file names.  Otherwise, directories become
+/// storages and files become streams, named after the local names (minus any
+/// `.dump` extension).
+pub fn insert_all<F: Read + Write + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &Path,
+    input_dir: &Path,
+) -> io::Result<Vec<PathBuf>> {
+    if !comp.entry(path)?.is_storage() {
+        not_found!("No such storage: {:?}", path);
+    }
+    let manifest_path = input_dir.join(MANIFEST_FILE_NAME);
+    let manifest = if manifest_path.is_file() {
+        read_manifest(&fs::read_to_string(&manifest_path)?)?
+    } else {
+        manifest_from_dir(input_dir, Path::new(""), &mut Vec::new())?
+    };
+    let mut written = Vec::new();
+    for entry in manifest.iter() {
+        let mut target = path.to_path_buf();
+        target.extend(entry.names.iter());
+        if entry.is_stream {
+            let mut file = fs::File::open(input_dir.join(&entry.local))?;
+            put_stream(comp, &target, &mut file)?;
+            comp.set_state_bits(&target, entry.state_bits)?;
+            written.push(target);
+            continue;
+        }
+        if !comp.exists(&target) {
+            comp.create_storage(&target)?;
+        }
+        if let Some(ref metadata) = entry.storage {
+            comp.set_storage_clsid(&target, metadata.clsid)?;
+            comp.set_state_bits(&target, entry.state_bits)?;
+            let created = Timestamp::from_value(metadata.created);
+            comp.set_created_time(&target, created.to_system_time())?;
+            let modified = Timestamp::from_value(metadata.modified);
+            comp.set_modified_time(&target, modified.to_system_time())?;
+        }
+    }
+    Ok(written)
+}
+
+/// Builds a manifest (without metadata) for a directory that has none, with
+/// objects named after the local files.
+fn manifest_from_dir(
+    input_dir: &Path,
+    relative: &Path,
+    names: &mut Vec<String>,
+) -> io::Result<Vec<ManifestEntry>> {
+    let mut children = fs::read_dir(input_dir.join(relative))?
+        .map(|child| child.map(|child| child.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    children.sort();
+    let mut manifest = Vec::new();
+    for file_name in children {
+        let mut name = match file_name.to_str() {
+            Some(name) => name.to_string(),
+            None => invalid_data!("Non-UTF-8 file name: {:?}", file_name),
+        };
+        let local = relative.join(&file_name);
+        let is_stream = !input_dir.join(&local).is_dir();
+        if is_stream && name.ends_with(".dump") {
+            name.truncate(name.len() - ".dump".len());
+        }
+        names.push(name);
+        manifest.push(ManifestEntry {
+            local: local.clone(),
+            names: names.clone(),
+            is_stream,
+            state_bits: 0,
+            storage: None,
+        });
+        if !is_stream {
+            manifest.extend(manifest_from_dir(input_dir, &local, names)?);
+        }
+        names.pop();
+    }
+    Ok(manifest)
+}
+
+/// Returns the local path that the object at `path` (relative to the storage
+/// being extracted) is extracted to within `output_dir`, ignoring any name
+/// collisions.
+fn local_path(output_dir: &Path, relative: &Path) -> PathBuf {
+    let mut local = output_dir.to_path_buf();
+    for component in relative.iter() {
+        let name = decode_msi_name(&component.to_string_lossy()).0;
+        local.push(sanitize_name(&name));
+    }
+    local
+}
+
+/// Like `local_path`, but for a stream, which gets a `.dump` extension.
+fn local_dump_path(output_dir: &Path, relative: &Path) -> PathBuf {
+    let mut name = local_path(output_dir, relative).into_os_string();
+    name.push(".dump");
+    PathBuf::from(name)
+}
+
+/// Returns a local file name for an object, that differs (ignoring case)
+/// from all the names already `taken` by its siblings, and adds it to them.
+fn unique_local_name(
+    name: &str,
+    is_stream: bool,
+    taken: &mut HashSet<String>,
+) -> String {
+    let base = sanitize_name(&decode_msi_name(name).0);
+    let mut suffix = 1;
+    loop {
+        let mut candidate = if suffix == 1 {
+            base.clone()
+        } else {
+            format!("{}~{}", base, suffix)
+        };
+        if is_stream {
+            candidate.push_str(".dump");
+        }
+        if taken.insert(candidate.to_lowercase()) {
+            return candidate;
+        }
+        suffix += 1;
+    }
+}
+
+/// Returns a version of `name` that can be used as a file name on all common
+/// filesystems, and that no filesystem will normalize into a different name.
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|chr| if is_unportable_char(chr) { '_' } else { chr })
+        .collect();
+    if sanitized.is_empty() || sanitized.ends_with(['.', ' ']) {
+        sanitized.push('_');
+    }
+    let stem = sanitized.split('.').next().unwrap().to_ascii_uppercase();
+    let is_device = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit());
+    if is_device {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn is_unportable_char(chr: char) -> bool {
+    chr.is_control()
+        || "/\\:*?\"<>|".contains(chr)
+        // Combining marks, which macOS may compose or decompose:
+        || matches!(
+            chr as u32,
+            0x300..=0x36f
+                | 0x1ab0..=0x1aff
+                | 0x1dc0..=0x1dff
+                | 0x20d0..=0x20ff
+                | 0xfe20..=0xfe2f
+        )
+}
+
+//===========================================================================//
+
+/// One object recorded in an extraction manifest.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ManifestEntry {
+    /// Where the object was extracted to, relative to the output directory.
+    local: PathBuf,
+    /// The exact names of the object and its ancestors, relative to the
+    /// storage that was extracted.  Empty for that storage itself.
+    names: Vec<String>,
+    is_stream: bool,
+    state_bits: u32,
+    /// Metadata that is only recorded for storages.
+    storage: Option<StorageMetadata>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct StorageMetadata {
+    clsid: Uuid,
+    created: u64,
+    modified: u64,
+}
+
+impl ManifestEntry {
+    fn new(entry: &Entry, base: &Path, local: PathBuf) -> ManifestEntry {
+        let relative = entry.path().strip_prefix(base).unwrap();
+        let storage = if entry.is_storage() {
+            Some(StorageMetadata {
+                clsid: *entry.clsid(),
+                created: Timestamp::from_system_time(entry.created()).value(),
+                modified: Timestamp::from_system_time(entry.modified())
+                    .value(),
+            })
+        } else {
+            None
+        };
+        ManifestEntry {
+            local,
+            names: relative
+                .iter()
+                .map(|name| name.to_string_lossy().into_owned())
+                .collect(),
+            is_stream: entry.is_stream(),
+            state_bits: entry.state_bits(),
+            storage,
+        }
+    }
+}
+
+fn write_manifest<W: Write>(
+    out: &mut W,
+    manifest: &[ManifestEntry],
+) -> io::Result<()> {
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"version\": 1,")?;
+    writeln!(out, "  \"entries\": [")?;
+    for (index, entry) in manifest.iter().enumerate() {
+        let local = entry
+            .local
+            .iter()
+            .map(|name| name.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let names = entry
+            .names
+            .iter()
+            .map(|name| json_string(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let kind = if entry.is_stream { "stream" } else { "storage" };
+        write!(
+            out,
+            "    {{\"local\": {}, \"names\": [{}], \"type\": \"{}\", \
+             \"state_bits\": {}",
+            json_string(&local),
+            names,
+            kind,
+            entry.state_bits
+        )?;
+        if let Some(ref metadata) = entry.storage {
+            write!(
+                out,
+                ", \"clsid\": \"{}\", \"created\": {}, \"modified\": {}",
+                metadata.clsid, metadata.created, metadata.modified
+            )?;
+        }
+        let comma = if index + 1 < manifest.len() { "," } else { "" };
+        writeln!(out, "}}{}", comma)?;
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")
+}
+
+fn read_manifest(text: &str) -> io::Result<Vec<ManifestEntry>> {
+    let json: Value = serde_json::from_str(text)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    if json.get("version").and_then(Value::as_u64) != Some(1) {
+        invalid_data!("Unsupported manifest version");
+    }
+    let entries = match json.get("entries") {
+        Some(Value::Array(entries)) => entries,
+        _ => invalid_data!("Manifest has no entries"),
+    };
+    entries.iter().map(manifest_entry_from_json).collect()
+}
+
+fn manifest_entry_from_json(json: &Value) -> io::Result<ManifestEntry> {
+    let local = match json.get("local") {
+        Some(Value::String(local)) => local,
+        _ => invalid_data!("Manifest entry has no local path"),
+    };
+    // Don't let a manifest point at files outside the input directory.
+    if !local.is_empty()
+        && local.split('/').any(|name| {
+            name.is_empty()
+                || name == "."
+                || name == ".."
+                || name.contains('\\')
+        })
+    {
+        invalid_data!("Invalid local path in manifest: {:?}", local);
+    }
+    let names = match json.get("names") {
+        Some(Value::Array(names)) => names
+            .iter()
+            .map(|name| match name {
+                Value::String(name) => Ok(name.clone()),
+                _ => invalid_data!("Invalid name in manifest"),
+            })
+            .collect::<io::Result<Vec<String>>>()?,
+        _ => invalid_data!("Manifest entry has no names"),
+    };
+    let is_stream = match json.get("type") {
+        Some(Value::String(kind)) if kind == "stream" => true,
+        Some(Value::String(kind)) if kind == "storage" => false,
+        _ => invalid_data!("Invalid object type in manifest"),
+    };
+    let number = |key: &str| match json.get(key).and_then(Value::as_u64) {
+        Some(number) => Ok(number),
+        None => invalid_data!("Manifest entry has no {}", key),
+    };
+    let state_bits = match u32::try_from(number("state_bits")?) {
+        Ok(state_bits) => state_bits,
+        Err(_) => invalid_data!("Invalid state bits in manifest"),
+    };
+    let storage = if is_stream {
+        None
+    } else {
+        let clsid = match json.get("clsid") {
+            Some(Value::String(clsid)) => match Uuid::parse_str(clsid) {
+                Ok(clsid) => clsid,
+                Err(_) => invalid_data!("Invalid CLSID in manifest"),
+            },
+            _ => invalid_data!("Manifest entry has no clsid"),
+        };
+        Some(StorageMetadata {
+            clsid,
+            created: number("created")?,
+            modified: number("modified")?,
+        })
+    };
+    Ok(ManifestEntry {
+        local: local.split('/').filter(|name| !name.is_empty()).collect(),
+        names,
+        is_stream,
+        state_bits,
+        storage,
+    })
+}
+
+//===========================================================================//
+
+/// Cumulative usage of one storage and everything below it, as computed by
+/// [`disk_usage`](fn.disk_usage.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiskUsage {
+    /// The path of the storage.
+    pub path: PathBuf,
+    /// How many levels below the starting storage this storage is.
+    pub depth: usize,
+    /// The total length of all streams under this storage.
+    pub len: u64,
+    /// The total space allocated to all streams under this storage,
+    /// including the unused tail of each stream's last (mini) sector.
+    pub allocated: u64,
+    /// The number of streams under this storage.
+    pub num_streams: u64,
+}
+
+/// Walks the storage at `root` once, and returns one row per storage
+/// (including `root` itself), sorted by descending size.
+pub fn disk_usage<F>(
+    comp: &CompoundFile<F>,
+    root: &Path,
+) -> io::Result<Vec<DiskUsage>> {
+    let sector_len = comp.version().sector_len() as u64;
+    let mini_sector_len = comp.mini_sector_len() as u64;
+    let root = comp.entry(root)?.path().to_path_buf();
+    let mut rows = BTreeMap::<PathBuf, DiskUsage>::new();
+    for entry in comp.walk_storage(&root)? {
+        let path = entry.path();
+        if !entry.is_stream() {
+            let depth = path.strip_prefix(&root).unwrap().iter().count();
+            rows.insert(
+                path.to_path_buf(),
+                DiskUsage {
+                    path: path.to_path_buf(),
+                    depth,
+                    len: 0,
+                    allocated: 0,
+                    num_streams: 0,
+                },
+            );
+            continue;
+        }
+        let allocated = if entry.len() < comp.mini_stream_cutoff() {
+            entry.len().div_ceil(mini_sector_len) * mini_sector_len
+        } else {
+            entry.len().div_ceil(sector_len) * sector_len
+        };
+        for ancestor in path.ancestors().skip(1) {
+            if let Some(row) = rows.get_mut(ancestor) {
+                row.len += entry.len();
+                row.allocated += allocated;
+                row.num_streams += 1;
+            }
+            if ancestor == root {
+                break;
+            }
+        }
+    }
+    let mut rows: Vec<DiskUsage> = rows.into_values().collect();
+    rows.sort_by(|a, b| b.len.cmp(&a.len).then_with(|| a.path.cmp(&b.path)));
+    Ok(rows)
+}
+
+/// Writes disk usage rows as a table, with sizes either in bytes or in
+/// human-readable units, and paths in the given style.
+pub fn write_disk_usage<W: Write>(
+    out: &mut W,
+    rows: &[DiskUsage],
+    bytes: bool,
+    style: NameStyle,
+) -> io::Result<()> {
+    let size = |len: u64| {
+        if bytes {
+            len.to_string()
+        } else {
+            format_size(len)
+        }
+    };
+    for row in rows {
+        writeln!(
+            out,
+            "{:>12}  {:>12}  {:>7}  {}",
+            size(row.len),
+            size(row.allocated),
+            row.num_streams,
+            escape_path(&row.path, style)
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes disk usage rows as a JSON array.
+pub fn write_disk_usage_json<W: Write>(
+    out: &mut W,
+    rows: &[DiskUsage],
+) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (index, row) in rows.iter().enumerate() {
+        let comma = if index + 1 < rows.len() { "," } else { "" };
+        writeln!(
+            out,
+            "  {{\"path\": {}, \"size\": {}, \"allocated\": {}, \
+             \"streams\": {}}}{}",
+            json_string(&row.path.to_string_lossy()),
+            row.len,
+            row.allocated,
+            row.num_streams,
+            comma
+        )?;
+    }
+    writeln!(out, "]")
+}
+
+fn json_string(string: &str) -> String {
+    Value::from(string).to_string()
+}
+
+//===========================================================================//
+
+/// Everything known about a single object, as gathered by
+/// [`stat_entry`](fn.stat_entry.html).  Facts that couldn't be determined
+/// (or that don't apply to this kind of object) are `None`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EntryStat {
+    /// The full path of the object.
+    pub path: PathBuf,
+    /// The UTF-16 code units of the name, exactly as stored.
+    pub name_units: Option<Vec<u16>>,
+    /// What kind of object this is.
+    pub kind: EntryKind,
+    /// The ID of the object's directory entry.
+    pub stream_id: StreamId,
+    /// The length of the stream (always zero for storages).
+    pub len: u64,
+    /// How many bytes the stream's sector chain holds (always zero for
+    /// storages).
+    pub allocated: u64,
+    /// For a stream, whether its data is in the mini stream.
+    pub in_mini_stream: Option<bool>,
+    /// For a stream outside the mini stream, whether its sectors are
+    /// consecutive in the file.
+    pub contiguous: Option<bool>,
+    /// The first sector (or mini sector) of the stream's chain, as stored.
+    pub start_sector: Option<SectorId>,
+    /// The object's CLSID.
+    pub clsid: Uuid,
+    /// The object's user-defined state bits.
+    pub state_bits: u32,
+    /// The creation time, as a FILETIME (see [`EntryStat::created`]).
+    pub created_filetime: u64,
+    /// The modification time, as a FILETIME (see [`EntryStat::modified`]).
+    pub modified_filetime: u64,
+    /// The left sibling ID, as stored.
+    pub left_sibling: Option<StreamId>,
+    /// The right sibling ID, as stored.
+    pub right_sibling: Option<StreamId>,
+    /// The child ID, as stored.
+    pub child: Option<StreamId>,
+    /// True if the entry is red in its storage's red-black tree, false if it
+    /// is black.
+    pub red: Option<bool>,
+    /// The messages of the validation issues that concern this object,
+    /// either directly or through a link to it.
+    pub issues: Vec<String>,
+}
+
+impl EntryStat {
+    /// Returns the creation time.
+    pub fn created(&self) -> SystemTime {
+        Timestamp::from_value(self.created_filetime).to_system_time()
+    }
+
+    /// Returns the modification time.
+    pub fn modified(&self) -> SystemTime {
+        Timestamp::from_value(self.modified_filetime).to_system_time()
+    }
+}
+
+/// Gathers everything known about the object at `path`.  Facts that come
+/// from the object's raw directory entry or from the FAT are left out if
+/// they can't be read, rather than failing the whole call.
+pub fn stat_entry<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &Path,
+) -> io::Result<EntryStat> {
+    let entry = comp.entry(path)?;
+    let stream_id = entry.stream_id();
+    let raw = comp.raw_dir_entry(stream_id).ok();
+    let raw_u32 = |offset: usize| {
+        raw.map(|raw| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&raw[offset..offset + 4]);
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let name_units = raw.map(|raw| {
+        let name_len = u16::from_le_bytes([raw[64], raw[65]]) as usize;
+        let mut units: Vec<u16> = raw[..64]
+            .chunks_exact(2)
+            .take(name_len / 2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        if units.last() == Some(&0) {
+            units.pop();
+        }
+        units
+    });
+    let start_sector = raw_u32(116).map(SectorId::new);
+    let in_mini_stream = if entry.is_stream() {
+        Some(entry.len() < comp.mini_stream_cutoff())
+    } else {
+        None
+    };
+    let contiguous = match (in_mini_stream, start_sector) {
+        (Some(false), Some(start)) if start.is_regular() => {
+            let fat = comp.raw_fat();
+            let mut sector = start.value();
+            let mut contiguous = true;
+            // Stop after visiting as many sectors as there are, in case the
+            // chain loops.
+            for _ in 0..fat.len() {
+                match fat.get(sector as usize) {
+                    Some(&next) if next == SectorId::END_OF_CHAIN => break,
+                    Some(&next) if next.value() == sector + 1 => {
+                        sector += 1;
+                    }
+                    _ => {
+                        contiguous = false;
+                        break;
+                    }
+                }
+            }
+            Some(contiguous)
+        }
+        _ => None,
+    };
+    let issues = comp
+        .validate()
+        .into_iter()
+        .filter(|issue| {
+            issue.stream_id() == Some(stream_id)
+                || issue.target_id() == Some(stream_id)
+                || issue.path() == Some(entry.path())
+        })
+        .map(|issue| issue.message().to_string())
+        .collect();
+    Ok(EntryStat {
+        path: entry.path().to_path_buf(),
+        name_units,
+        kind: entry.file_type(),
+        stream_id,
+        len: entry.len(),
+        allocated: entry.chain_len(),
+        in_mini_stream,
+        contiguous,
+        start_sector,
+        clsid: *entry.clsid(),
+        state_bits: entry.state_bits(),
+        created_filetime: Timestamp::from_system_time(entry.created()).value(),
+        modified_filetime: Timestamp::from_system_time(entry.modified())
+            .value(),
+        left_sibling: raw_u32(68).map(StreamId::new),
+        right_sibling: raw_u32(72).map(StreamId::new),
+        child: raw_u32(76).map(StreamId::new),
+        red: raw.map(|raw| raw[67] == 0),
+        issues,
+    })
+}
+
+fn kind_name(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::Root => "root",
+        EntryKind::Storage => "storage",
+        EntryKind::Stream => "stream",
+    }
+}
+
+fn format_name_units(units: &[u16]) -> String {
+    let units: Vec<String> =
+        units.iter().map(|unit| format!("{:04x}", unit)).collect();
+    units.join(" ")
+}
+
+/// Writes an object's facts as one `key: value` line each, with the path
+/// in the given style.  Facts that are `None` are left out.
+pub fn write_stat<W: Write>(
+    out: &mut W,
+    stat: &EntryStat,
+    style: NameStyle,
+) -> io::Result<()> {
+    let mut line =
+        |key: &str, value: String| writeln!(out, "{:>13}: {}", key, value);
+    line("path", escape_path(&stat.path, style))?;
+    if let Some(ref units) = stat.name_units {
+        line("name units", format_name_units(units))?;
+    }
+    line("kind", kind_name(stat.kind).to_string())?;
+    line("stream id", stat.stream_id.to_string())?;
+    line("size", stat.len.to_string())?;
+    line("allocated", stat.allocated.to_string())?;
+    if let Some(in_mini_stream) = stat.in_mini_stream {
+        let mut storage =
+            if in_mini_stream { "mini" } else { "regular" }.to_string();
+        match stat.contiguous {
+            Some(true) => storage.push_str(", contiguous"),
+            Some(false) => storage.push_str(", fragmented"),
+            None => {}
+        }
+        line("storage", storage)?;
+    }
+    if let Some(start_sector) = stat.start_sector {
+        line("start sector", start_sector.to_string())?;
+    }
+    line("clsid", stat.clsid.hyphenated().to_string())?;
+    line("state bits", format!("{:08x}", stat.state_bits))?;
+    line(
+        "created",
+        format!(
+            "{} (FILETIME {})",
+            format_rfc3339(stat.created()),
+            stat.created_filetime
+        ),
+    )?;
+    line(
+        "modified",
+        format!(
+            "{} (FILETIME {})",
+            format_rfc3339(stat.modified()),
+            stat.modified_filetime
+        ),
+    )?;
+    for (key, link) in [
+        ("left sibling", stat.left_sibling),
+        ("right sibling", stat.right_sibling),
+        ("child", stat.child),
+    ] {
+        if let Some(link) = link {
+            line(key, link.to_string())?;
+        }
+    }
+    if let Some(red) = stat.red {
+        line("color", if red { "red" } else { "black" }.to_string())?;
+    }
+    for issue in stat.issues.iter() {
+        line("issue", issue.clone())?;
+    }
+    Ok(())
+}
+
+/// Writes an object's facts as a JSON object.  Facts that are `None` are
+/// left out; sibling and child IDs of `NONE` are written as `null`.
+pub fn write_stat_json<W: Write>(
+    out: &mut W,
+    stat: &EntryStat,
+) -> io::Result<()> {
+    let mut fields: Vec<(&str, String)> = Vec::new();
+    fields.push(("path", json_string(&stat.path.to_string_lossy())));
+    if let Some(ref units) = stat.name_units {
+        let units: Vec<String> =
+            units.iter().map(|unit| unit.to_string()).collect();
+        fields.push(("name_units", format!("[{}]", units.join(", "))));
+    }
+    fields.push(("kind", json_string(kind_name(stat.kind))));
+    fields.push(("stream_id", stat.stream_id.value().to_string()));
+    fields.push(("size", stat.len.to_string()));
+    fields.push(("allocated", stat.allocated.to_string()));
+    if let Some(in_mini_stream) = stat.in_mini_stream {
+        fields.push(("in_mini_stream", in_mini_stream.to_string()));
+    }
+    if let Some(contiguous) = stat.contiguous {
+        fields.push(("contiguous", contiguous.to_string()));
+    }
+    if let Some(start_sector) = stat.start_sector {
+        fields.push(("start_sector", start_sector.value().to_string()));
+    }
+    fields.push(("clsid", json_string(&stat.clsid.hyphenated().to_string())));
+    fields.push(("state_bits", stat.state_bits.to_string()));
+    fields.push(("created", json_string(&format_rfc3339(stat.created()))));
+    fields.push(("created_filetime", stat.created_filetime.to_string()));
+    fields.push(("modified", json_string(&format_rfc3339(stat.modified()))));
+    fields.push(("modified_filetime", stat.modified_filetime.to_string()));
+    for (key, link) in [
+        ("left_sibling", stat.left_sibling),
+        ("right_sibling", stat.right_sibling),
+        ("child", stat.child),
+    ] {
+        if let Some(link) = link {
+            let value = if link == StreamId::NONE {
+                "null".to_string()
+            } else {
+                link.value().to_string()
+            };
+            fields.push((key, value));
+        }
+    }
+    if let Some(red) = stat.red {
+        fields.push(("color", json_string(if red { "red" } else { "black" })));
+    }
+    let issues: Vec<String> =
+        stat.issues.iter().map(|issue| json_string(issue)).collect();
+    fields.push(("issues", format!("[{}]", issues.join(", "))));
+    writeln!(out, "{{")?;
+    for (index, (key, value)) in fields.iter().enumerate() {
+        let comma = if index + 1 < fields.len() { "," } else { "" };
+        writeln!(out, "  {}: {}{}", json_string(key), value, comma)?;
+    }
+    writeln!(out, "}}")
+}
+
+//===========================================================================//
+
+/// The streams that changed between two versions of a watched compound file,
+/// as reported by [`Watcher::poll`](struct.Watcher.html#method.poll).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WatchChanges {
+    /// Streams that did not exist (or did not match the filter) before.
+    pub added: Vec<PathBuf>,
+    /// Streams that no longer exist.
+    pub removed: Vec<PathBuf>,
+    /// Streams whose contents changed.
+    pub modified: Vec<PathBuf>,
+}
+
+impl WatchChanges {
+    /// Returns true if no streams were added, removed, or modified.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+    }
+}
+
+/// The outcome of one call to
+/// [`Watcher::poll`](struct.Watcher.html#method.poll).
+#[derive(Debug)]
+pub enum WatchStatus {
+    /// The file has not changed since it was last extracted.
+    Unchanged,
+    /// The file has changed, but has not yet been left alone for the
+    /// debounce interval, so may still be being written to.
+    Settling,
+    /// The file has changed, but could not be read as a compound file (for
+    /// example because it is locked, or was caught halfway through being
+    /// saved).  It will be tried again once the debounce interval has passed.
+    Unreadable(io::Error),
+    /// The file was re-read, and the streams that changed were extracted.
+    Extracted(WatchChanges),
+}
+
+/// What a file's metadata looked like at some point, used to notice when it
+/// has been written to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> io::Result<FileStamp> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileStamp {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// What is known about a stream as of when it was last extracted.
+#[derive(Clone, Debug)]
+struct WatchedStream {
+    len: u64,
+    modified: SystemTime,
+    hash: u64,
+    local: PathBuf,
+}
+
+/// Why re-extracting a watched file failed.
+enum WatchError {
+    /// The compound file could not be read; this is retried later.
+    Read(io::Error),
+    /// The extracted files could not be written.
+    Write(io::Error),
+}
+
+/// Keeps a local directory in sync with the streams of a compound file on
+/// disk, re-extracting only the streams that change whenever the file is
+/// modified.  Streams are named as with
+/// [`extract_all`](fn.extract_all.html), except that no manifest is written
+/// and names that only differ in case are not told apart.
+///
+/// The watcher does not block or spawn threads; call
+/// [`poll`](#method.poll) to check for changes, either whenever a
+/// [`FileEvents`](struct.FileEvents.html) for the file reports one, or
+/// periodically.
+pub struct Watcher {
+    path: PathBuf,
+    output_dir: PathBuf,
+    filter: Option<String>,
+    debounce: Duration,
+    streams: BTreeMap<PathBuf, WatchedStream>,
+    extracted: Option<FileStamp>,
+    pending: Option<(FileStamp, Instant)>,
+}
+
+impl Watcher {
+    /// Creates a watcher that extracts the streams of the compound file at
+    /// `path` into `output_dir`, which must already exist.  If `filter` is
+    /// given, only streams whose paths match it (see
+    /// [`glob_match`](fn.glob_match.html)) are extracted.  After the file is
+    /// modified, it is only re-read once it has gone unmodified for
+    /// `debounce`, so that a save in progress is not read halfway through.
+    ///
+    /// Nothing is extracted until the first call to `poll`.
+    pub fn new(
+        path: &Path,
+        output_dir: &Path,
+        filter: Option<String>,
+        debounce: Duration,
+    ) -> Watcher {
+        Watcher {
+            path: path.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            filter,
+            debounce,
+            streams: BTreeMap::new(),
+            extracted: None,
+            pending: None,
+        }
+    }
+
+    /// Checks whether the file has changed, and if it has (and has since
+    /// settled), re-extracts the streams that differ from last time.  Returns
+    /// an error only if the extracted files could not be written.
+    pub fn poll(&mut self) -> io::Result<WatchStatus> {
+        let stamp = match FileStamp::of(&self.path) {
+            Ok(stamp) => stamp,
+            Err(error) => return Ok(WatchStatus::Unreadable(error)),
+        };
+        if self.extracted == Some(stamp) {
+            self.pending = None;
+            return Ok(WatchStatus::Unchanged);
+        }
+        match self.pending {
+            Some((pending, since)) if pending == stamp => {
+                if since.elapsed() < self.debounce {
+                    return Ok(WatchStatus::Settling);
+                }
+            }
+            _ => {
+                self.pending = Some((stamp, Instant::now()));
+                return Ok(WatchStatus::Settling);
+            }
+        }
+        match self.sync() {
+            Ok(changes) => {
+                self.extracted = Some(stamp);
+                self.pending = None;
+                Ok(WatchStatus::Extracted(changes))
+            }
+            Err(WatchError::Read(error)) => {
+                self.pending = Some((stamp, Instant::now()));
+                Ok(WatchStatus::Unreadable(error))
+            }
+            Err(WatchError::Write(error)) => Err(error),
+        }
+    }
+
+    fn sync(&mut self) -> Result<WatchChanges, WatchError> {
+        let mut comp = crate::open(&self.path).map_err(WatchError::Read)?;
+        let entries: Vec<Entry> = comp
+            .walk()
+            .filter(|entry| entry.is_stream())
+            .filter(|entry| match self.filter {
+                Some(ref filter) => {
+                    glob_match(filter, &entry.path().to_string_lossy())
+                }
+                None => true,
+            })
+            .collect();
+        let mut changes = WatchChanges::default();
+        let mut streams = BTreeMap::new();
+        for entry in entries {
+            let path = entry.path();
+            let old = self.streams.remove(path);
+            if let Some(old) = old.as_ref() {
+                // Only bother hashing the contents if the cheaper checks
+                // can't tell that the stream changed.
+                if old.len == entry.len() && old.modified == entry.modified() {
+                    let mut stream =
+                        comp.open_stream(path).map_err(WatchError::Read)?;
+                    let hash = copy_hashed(&mut stream, &mut io::sink())?;
+                    if hash == old.hash {
+                        streams.insert(path.to_path_buf(), old.clone());
+                        continue;
+                    }
+                }
+            }
+            let relative = path.strip_prefix("/").unwrap();
+            let local = local_dump_path(&self.output_dir, relative);
+            if let Some(parent) = local.parent() {
+                fs::create_dir_all(parent).map_err(WatchError::Write)?;
+            }
+            let mut file =
+                fs::File::create(&local).map_err(WatchError::Write)?;
+            let mut stream =
+                comp.open_stream(path).map_err(WatchError::Read)?;
+            let hash = copy_hashed(&mut stream, &mut file)?;
+            if old.is_some() {
+                changes.modified.push(path.to_path_buf());
+            } else {
+                changes.added.push(path.to_path_buf());
+            }
+            let watched = WatchedStream {
+                len: entry.len(),
+                modified: entry.modified(),
+                hash,
+                local,
+            };
+            streams.insert(path.to_path_buf(), watched);
+        }
+        for (path, old) in std::mem::replace(&mut self.streams, streams) {
+            match fs::remove_file(&old.local) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(WatchError::Write(error)),
+            }
+            changes.removed.push(path);
+        }
+        Ok(changes)
+    }
+}
+
+/// Copies all data from `reader` to `writer`, returning a hash of it.
+fn copy_hashed<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, WatchError> {
+    let mut hasher = fnv::FnvHasher::default();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let num_bytes = match ioutil::read_retrying(reader, &mut buffer) {
+            Ok(0) => return Ok(hasher.finish()),
+            Ok(num_bytes) => num_bytes,
+            Err(error) => return Err(WatchError::Read(error)),
+        };
+        hasher.write(&buffer[..num_bytes]);
+        writer.write_all(&buffer[..num_bytes]).map_err(WatchError::Write)?;
+    }
+}
+
+/// Waits for a file on disk to change, using the operating system's file
+/// change notifications (through the `notify` crate), so that a
+/// [`Watcher`](struct.Watcher.html) need only be polled when there may be
+/// something to see.
+///
+/// The file's directory is watched rather than the file itself, so that
+/// notifications keep arriving after a program saves the file by replacing
+/// it.  Some file systems (such as many network shares) never deliver
+/// notifications; on those, poll the watcher periodically instead.
+pub struct FileEvents {
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<()>,
+}
+
+impl FileEvents {
+    /// Starts watching for changes to the file at `path`.  Returns an error
+    /// if the operating system's notifications can't be used (for example,
+    /// because too many files are already being watched).
+    pub fn new(path: &Path) -> io::Result<FileEvents> {
+        let file_name = path.file_name().map(|name| name.to_os_string());
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let (sender, events) = mpsc::channel();
+        let handler = move |result: notify::Result<notify::Event>| {
+            let relevant = match result {
+                Ok(event) => event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == file_name.as_deref()),
+                // An error may mean that notifications were lost, so assume
+                // that the file changed.
+                Err(_) => true,
+            };
+            if relevant {
+                let _ = sender.send(());
+            }
+        };
+        let mut watcher =
+            notify::recommended_watcher(handler).map_err(notify_error)?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(notify_error)?;
+        Ok(FileEvents { _watcher: watcher, events })
+    }
+
+    /// Blocks until the file may have changed, or until `timeout` (if
+    /// given) has passed, and returns true in the former case.  Any other
+    /// notifications that have already arrived are consumed along with the
+    /// first, since one call to
+    /// [`Watcher::poll`](struct.Watcher.html#method.poll) covers them all.
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let changed = match timeout {
+            Some(timeout) => self.events.recv_timeout(timeout).is_ok(),
+            None => self.events.recv().is_ok(),
+        };
+        while self.events.try_recv().is_ok() {}
+        changed
+    }
+}
+
+fn notify_error(error: notify::Error) -> io::Error {
+    match error.kind {
+        notify::ErrorKind::Io(error) => error,
+        _ => io::Error::other(error),
+    }
+}
+
+/// Writes one line per changed stream: `A` for added, `M` for modified, and
+/// `D` for removed, followed by the stream's path in the given style.
+pub fn write_watch_changes<W: Write>(
+    out: &mut W,
+    changes: &WatchChanges,
+    style: NameStyle,
+) -> io::Result<()> {
+    let groups = [
+        ('A', &changes.added),
+        ('M', &changes.modified),
+        ('D', &changes.removed),
+    ];
+    for (letter, paths) in groups.iter() {
+        for path in paths.iter() {
+            writeln!(out, "{}  {}", letter, escape_path(path, style))?;
+        }
+    }
+    Ok(())
+}
+
+//===========================================================================//
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        disk_usage, extract_all, format_date, parse_size, read_manifest,
+        sanitize_name, split_path_with_drive_letters, stat_entry,
+        write_disk_usage, write_disk_usage_json, write_stat, write_stat_json,
+        DiskUsage, NameStyle,
+    };
+    use crate::{CompoundFile, Version};
+    use std::io::{Cursor, Write};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn make_fixture() -> CompoundFile<Cursor<Vec<u8>>> {
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+        comp.create_storage_all("/a/b").unwrap();
+        comp.create_storage("/c").unwrap();
+        let streams: &[(&str, usize)] = &[
+            ("/top", 10),
+            ("/a/one", 5000),
+            ("/a/b/two", 100),
+            ("/a/b/three", 1),
+            ("/c/four", 700),
+        ];
+        for &(path, len) in streams {
+            let mut stream = comp.create_stream(path).unwrap();
+            stream.write_all(&vec![0; len]).unwrap();
+        }
+        comp
+    }
+
+    fn split(arg: &str, drive_letters: bool) -> (PathBuf, PathBuf) {
+        split_path_with_drive_letters(arg, drive_letters)
+    }
+
+    #[test]
+    fn split_paths() {
+        let pair = |file: &str, inner: &str| {
+            (PathBuf::from(file), PathBuf::from(inner))
+        };
+        assert_eq!(split("x.msi:/foo", false), pair("x.msi", "/foo"));
+        assert_eq!(split("x.msi", false), pair("x.msi", ""));
+        assert_eq!(split("x.msi:", false), pair("x.msi", ""));
+        assert_eq!(split("a::b.msi:/foo", false), pair("a:b.msi", "/foo"));
+        assert_eq!(split("a::b.msi", false), pair("a:b.msi", ""));
+        assert_eq!(
+            split(r"C:\x.msi:Stream", true),
+            pair(r"C:\x.msi", "Stream")
+        );
+        assert_eq!(split("C:/x.msi:/a/b", true), pair("C:/x.msi", "/a/b"));
+        assert_eq!(split("C:/x.msi:/a/b", false), pair("C", "/x.msi:/a/b"));
+        assert_eq!(split("x:/foo", true), pair("x:/foo", ""));
+    }
+
+    #[test]
+    fn format_dates() {
+        assert_eq!(format_date(UNIX_EPOCH), "1970-01-01");
+        let day = Duration::from_secs(86_400);
+        assert_eq!(format_date(UNIX_EPOCH + day * 11_016), "2000-02-29");
+        assert_eq!(
+            format_date(UNIX_EPOCH - Duration::from_secs(1)),
+            "1969-12-31"
+        );
+        let cfb_epoch = UNIX_EPOCH - Duration::from_secs(11_644_473_600);
+        assert_eq!(format_date(cfb_epoch), "1601-01-01");
+    }
+
+    #[test]
+    fn extract_subtree() {
+        let mut comp = make_fixture();
+        let dir = std::env::temp_dir()
+            .join(format!("cfb-tool-extract-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let mut written = extract_all(&mut comp, Path::new("/a"), &dir)
+            .unwrap()
+            .into_iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        written.sort();
+        assert_eq!(
+            written,
+            vec![
+                PathBuf::from("b/three.dump"),
+                PathBuf::from("b/two.dump"),
+                PathBuf::from("one.dump"),
+            ]
+        );
+        let data = std::fs::read(dir.join("one.dump")).unwrap();
+        assert_eq!(data.len(), 5000);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn du_output(rows: &[DiskUsage], bytes: bool) -> String {
+        let mut output = Vec::new();
+        write_disk_usage(&mut output, rows, bytes, NameStyle::Escaped)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn du_golden_output() {
+        let comp = make_fixture();
+        let rows = disk_usage(&comp, Path::new("/")).unwrap();
+        let expected = [
+            "        5811          6080        5  /",
+            "        5101          5312        3  /a",
+            "         700           704        1  /c",
+            "         101           192        2  /a/b",
+            "",
+        ];
+        assert_eq!(du_output(&rows, true), expected.join("\n"));
+        let expected = [
+            "     5811 B        6080 B         5  /",
+            "     5101 B        5312 B         3  /a",
+            "      700 B         704 B         1  /c",
+            "      101 B         192 B         2  /a/b",
+            "",
+        ];
+        assert_eq!(du_output(&rows, false), expected.join("\n"));
+    }
+
+    #[test]
+    fn du_of_substorage() {
+        let comp = make_fixture();
+        let rows = disk_usage(&comp, Path::new("/a")).unwrap();
+        let paths: Vec<_> = rows.iter().map(|row| row.path.clone()).collect();
+        assert_eq!(paths, vec![Path::new("/a"), Path::new("/a/b")]);
+        assert_eq!(rows[0].depth, 0);
+        assert_eq!(rows[1].depth, 1);
+    }
+
+    #[test]
+    fn du_json_output() {
+        let comp = make_fixture();
+        let mut rows = disk_usage(&comp, Path::new("/")).unwrap();
+        rows.retain(|row| row.depth <= 1 && row.len >= 1000);
+        let mut output = Vec::new();
+        write_disk_usage_json(&mut output, &rows).unwrap();
+        let expected = "\
+[
+  {\"path\": \"/\", \"size\": 5811, \"allocated\": 6080, \"streams\": 5},
+  {\"path\": \"/a\", \"size\": 5101, \"allocated\": 5312, \"streams\": 3}
+]
+";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn stat_golden_output() {
+        let mut comp = make_fixture();
+        let stat = stat_entry(&mut comp, Path::new("/a/b/two")).unwrap();
+        let mut output = Vec::new();
+        write_stat(&mut output, &stat, NameStyle::Escaped).unwrap();
+        let expected = [
+            "         path: /a/b/two",
+            "   name units: 0074 0077 006f",
+            "         kind: stream",
+            "    stream id: 6",
+            "         size: 100",
+            "    allocated: 128",
+            "      storage: mini",
+            " start sector: 1",
+            "        clsid: 00000000-0000-0000-0000-000000000000",
+            "   state bits: 00000000",
+            "      created: 1601-01-01T00:00:00Z (FILETIME 0)",
+            "     modified: 1601-01-01T00:00:00Z (FILETIME 0)",
+            " left sibling: NONE",
+            "right sibling: 7",
+            "        child: NONE",
+            "        color: black",
+            "",
+        ];
+        assert_eq!(String::from_utf8(output).unwrap(), expected.join("\n"));
+    }
+
+    #[test]
+    fn stat_json_output() {
+        let mut comp = make_fixture();
+        let stat = stat_entry(&mut comp, Path::new("/a/one")).unwrap();
+        let mut output = Vec::new();
+        write_stat_json(&mut output, &stat).unwrap();
+        let expected = "\
+{
+  \"path\": \"/a/one\",
+  \"name_units\": [111, 110, 101],
+  \"kind\": \"stream\",
+  \"stream_id\": 5,
+  \"size\": 5000,
+  \"allocated\": 5120,
+  \"in_mini_stream\": false,
+  \"contiguous\": true,
+  \"start_sector\": 5,
+  \"clsid\": \"00000000-0000-0000-0000-000000000000\",
+  \"state_bits\": 0,
+  \"created\": \"1601-01-01T00:00:00Z\",
+  \"created_filetime\": 0,
+  \"modified\": \"1601-01-01T00:00:00Z\",
+  \"modified_filetime\": 0,
+  \"left_sibling\": null,
+  \"right_sibling\": null,
+  \"child\": null,
+  \"color\": \"red\",
+  \"issues\": []
+}
+";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn sanitize_names() {
+        assert_eq!(sanitize_name("plain name.txt"), "plain name.txt");
+        assert_eq!(
+            sanitize_name("\u{5}SummaryInformation"),
+            "_SummaryInformation"
+        );
+        assert_eq!(sanitize_name("a*b?c"), "a_b_c");
+        assert_eq!(sanitize_name("cafe\u{301}"), "cafe_");
+        assert_eq!(sanitize_name("caf\u{e9}"), "caf\u{e9}");
+        assert_eq!(sanitize_name("dots.."), "dots.._");
+        assert_eq!(sanitize_name("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_name("COM1"), "_COM1");
+        assert_eq!(sanitize_name("COMMA"), "COMMA");
+    }
+
+    #[test]
+    fn manifest_json_escapes() {
+        let text = "{\"version\": 1, \"entries\": [\n\
+                    {\"local\": \"a/b.dump\", \
+                    \"names\": [\"\\u0001x\", \"\\ud83d\\ude00\\\"\"], \
+                    \"type\": \"stream\", \"state_bits\": 7}]}";
+        let manifest = read_manifest(text).unwrap();
+        assert_eq!(manifest.len(), 1);
+        assert_eq!(manifest[0].local, PathBuf::from("a/b.dump"));
+        assert_eq!(manifest[0].names, ["\u{1}x", "\u{1f600}\""]);
+        assert_eq!(manifest[0].state_bits, 7);
+        assert!(read_manifest("{\"version\": 2, \"entries\": []}").is_err());
+        assert!(read_manifest("{\"version\": 1, \"entries\": [").is_err());
+        let unpaired =
+            "{\"version\": 1, \"entries\": [{\"local\": \"\\ud800\"}]}";
+        assert!(read_manifest(unpaired).is_err());
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("2m"), Ok(2 << 20));
+        assert!(parse_size("lots").is_err());
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,133 @@
+use cfb::{AlignedBytes, AlignedSlice, CompoundFile, Version};
+use std::io::{Cursor, ErrorKind, Write};
+
+//===========================================================================//
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+fn is_aligned(slice: &[u8], align: usize) -> bool {
+    slice.as_ptr() as usize % align == 0
+}
+
+/// Returns a V4 file (with 4096-byte sectors) holding a large stream
+/// written in one go, so that its sectors are contiguous; a small stream
+/// in the mini stream; and two large streams written in alternation, so
+/// that their sectors are interleaved.
+fn make_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V4, cursor).unwrap();
+    comp.create_stream("/contiguous")
+        .unwrap()
+        .write_all(&data(20000, 1))
+        .unwrap();
+    comp.create_stream("/small").unwrap().write_all(&data(100, 2)).unwrap();
+    let mut first = comp.create_stream("/first").unwrap();
+    let mut second = comp.create_stream("/second").unwrap();
+    for chunk in 0..4 {
+        first.write_all(&data(4096, 3 + chunk)).unwrap();
+        first.flush().unwrap();
+        second.write_all(&data(4096, 7 + chunk)).unwrap();
+        second.flush().unwrap();
+    }
+    drop((first, second));
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn interleaved(seed: u8) -> Vec<u8> {
+    (0..4).flat_map(|chunk| data(4096, seed + chunk)).collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn read_stream_aligned() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    for align in [1, 8, 64, 4096] {
+        let bytes = comp.read_stream_aligned("/contiguous", align).unwrap();
+        assert!(is_aligned(&bytes, align));
+        assert_eq!(bytes.align(), align);
+        assert_eq!(&bytes[..], &data(20000, 1)[..]);
+        let bytes = comp.read_stream_aligned("/small", align).unwrap();
+        assert!(is_aligned(&bytes, align));
+        assert_eq!(&bytes[..], &data(100, 2)[..]);
+    }
+    comp.create_stream("/empty").unwrap();
+    let bytes = comp.read_stream_aligned("/empty", 64).unwrap();
+    assert!(bytes.is_empty());
+}
+
+#[test]
+fn read_stream_aligned_errors() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let error = comp.read_stream_aligned("/contiguous", 48).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.read_stream_aligned("/", 8).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.read_stream_aligned("/missing", 8).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn stream_slice_borrows_from_aligned_file() {
+    // With the whole file in a 4096-aligned buffer, every sector starts at
+    // an aligned address, so a contiguous stream can be borrowed for any
+    // alignment up to the sector length.
+    let file = AlignedBytes::copy_from_slice(&make_file(), 4096).unwrap();
+    let mut comp = CompoundFile::open(Cursor::new(&file[..])).unwrap();
+    for align in [8, 64, 4096] {
+        let slice = comp.stream_slice_aligned("/contiguous", align).unwrap();
+        assert!(slice.is_borrowed(), "align {}", align);
+        assert!(is_aligned(&slice, align));
+        assert_eq!(&slice[..], &data(20000, 1)[..]);
+        let range = file.as_ptr_range();
+        assert!(range.contains(&slice.as_ptr()));
+    }
+}
+
+#[test]
+fn stream_slice_falls_back_to_copying() {
+    let file = AlignedBytes::copy_from_slice(&make_file(), 4096).unwrap();
+    let mut comp = CompoundFile::open(Cursor::new(&file[..])).unwrap();
+    // Mini sectors are only 64 bytes long, so a small stream is aligned to
+    // at most that much.
+    let slice = comp.stream_slice_aligned("/small", 64).unwrap();
+    assert!(slice.is_borrowed());
+    assert_eq!(&slice[..], &data(100, 2)[..]);
+    let slice = comp.stream_slice_aligned("/small", 4096).unwrap();
+    assert!(is_aligned(&slice, 4096));
+    assert_eq!(&slice[..], &data(100, 2)[..]);
+    // Interleaved streams aren't contiguous, so they must be copied
+    // whatever the alignment.
+    for (path, seed) in [("/first", 3), ("/second", 7)] {
+        for align in [8, 64, 4096] {
+            let slice = comp.stream_slice_aligned(path, align).unwrap();
+            assert!(matches!(slice, AlignedSlice::Owned(_)));
+            assert!(is_aligned(&slice, align));
+            assert_eq!(&slice[..], &interleaved(seed)[..]);
+        }
+    }
+}
+
+#[test]
+fn stream_slice_from_misaligned_file() {
+    // Offsetting the file by one byte misaligns every sector, so nothing
+    // can be borrowed (except with an alignment of one).
+    let mut buffer = vec![0u8];
+    buffer.extend(make_file());
+    let aligned = AlignedBytes::copy_from_slice(&buffer, 4096).unwrap();
+    let mut comp = CompoundFile::open(Cursor::new(&aligned[1..])).unwrap();
+    let slice = comp.stream_slice_aligned("/contiguous", 8).unwrap();
+    assert!(!slice.is_borrowed());
+    assert!(is_aligned(&slice, 8));
+    assert_eq!(&slice[..], &data(20000, 1)[..]);
+    let slice = comp.stream_slice_aligned("/contiguous", 1).unwrap();
+    assert!(slice.is_borrowed());
+    let error = comp.stream_slice_aligned("/contiguous", 0).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+//===========================================================================//
@@ -0,0 +1,135 @@
+//! Checks that opening a file whose metadata calls for a huge allocation
+//! fails with an error, rather than aborting, when the allocation fails.
+//! This is a separate test binary because it installs a global allocator.
+
+use cfb::CompoundFile;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Cursor, ErrorKind};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+//===========================================================================//
+
+/// The largest single allocation that `LimitedAllocator` will make.
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// A global allocator that fails any allocation larger than `LIMIT`.
+struct LimitedAllocator;
+
+unsafe impl GlobalAlloc for LimitedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > LIMIT.load(Ordering::SeqCst) {
+            std::ptr::null_mut()
+        } else {
+            System.alloc(layout)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: LimitedAllocator = LimitedAllocator;
+
+//===========================================================================//
+
+const SECTOR_LEN: usize = 512;
+const END_OF_CHAIN: u32 = 0xfffffffe;
+const FREE_SECTOR: u32 = 0xffffffff;
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Returns a small V3 file whose DIFAT (in the header and in
+/// `num_difat_sectors` DIFAT sectors) lists its one FAT sector over and
+/// over, so that reading the FAT calls for about 64 KiB of memory per DIFAT
+/// sector.
+fn file_with_huge_fat(num_difat_sectors: usize) -> Vec<u8> {
+    let num_sectors = num_difat_sectors + 2;
+    let mut data = vec![0u8; SECTOR_LEN * (num_sectors + 1)];
+    data[0..8]
+        .copy_from_slice(&[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1]);
+    data[24..26].copy_from_slice(&0x3eu16.to_le_bytes());
+    data[26..28].copy_from_slice(&3u16.to_le_bytes());
+    data[28..30].copy_from_slice(&0xfffeu16.to_le_bytes());
+    data[30..32].copy_from_slice(&9u16.to_le_bytes());
+    data[32..34].copy_from_slice(&6u16.to_le_bytes());
+    let num_fat_entries_listed = 109 + 127 * num_difat_sectors;
+    put_u32(&mut data, 44, num_fat_entries_listed as u32);
+    let dir_sector = num_sectors as u32 - 1;
+    put_u32(&mut data, 48, dir_sector);
+    put_u32(&mut data, 56, 4096);
+    put_u32(&mut data, 60, END_OF_CHAIN);
+    put_u32(&mut data, 68, 1);
+    put_u32(&mut data, 72, num_difat_sectors as u32);
+    // The header DIFAT entries are all zero, i.e. sector 0.
+
+    // Sector 0 is the FAT sector.
+    let fat = SECTOR_LEN;
+    put_u32(&mut data, fat, 0xfffffffd);
+    for index in 1..=num_difat_sectors {
+        put_u32(&mut data, fat + 4 * index, 0xfffffffc);
+    }
+    put_u32(&mut data, fat + 4 * dir_sector as usize, END_OF_CHAIN);
+    for index in num_sectors..SECTOR_LEN / 4 {
+        put_u32(&mut data, fat + 4 * index, FREE_SECTOR);
+    }
+
+    // Sectors 1 to num_difat_sectors are DIFAT sectors, whose entries are
+    // all zero, i.e. sector 0 again.
+    for index in 1..=num_difat_sectors {
+        let next = if index == num_difat_sectors {
+            END_OF_CHAIN
+        } else {
+            index as u32 + 1
+        };
+        put_u32(&mut data, SECTOR_LEN * (index + 1) - 4, next);
+    }
+
+    // The last sector is a directory sector with an empty root entry.
+    let dir = SECTOR_LEN * num_sectors;
+    let name: Vec<u8> = "Root Entry"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    data[dir..dir + name.len()].copy_from_slice(&name);
+    data[dir + 64..dir + 66].copy_from_slice(&22u16.to_le_bytes());
+    data[dir + 66] = 5;
+    data[dir + 67] = 1;
+    for offset in [68, 72, 76] {
+        put_u32(&mut data, dir + offset, FREE_SECTOR);
+    }
+    put_u32(&mut data, dir + 116, END_OF_CHAIN);
+    for entry in 1..4 {
+        let offset = dir + 128 * entry;
+        for field in [68, 72, 76] {
+            put_u32(&mut data, offset + field, FREE_SECTOR);
+        }
+    }
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn open_fails_cleanly_when_allocation_fails() {
+    let data = file_with_huge_fat(64);
+    assert!(data.len() < 64 * 1024);
+
+    LIMIT.store(1 << 20, Ordering::SeqCst);
+    let result = CompoundFile::open(Cursor::new(data.clone()));
+    LIMIT.store(usize::MAX, Ordering::SeqCst);
+    let error = result.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    assert!(error.to_string().contains("the FAT"), "{}", error);
+
+    // Without the limit, the file gets further (it's still malformed, since
+    // the FAT sector is listed many times, but that's not what's checked).
+    if let Err(error) = CompoundFile::open(Cursor::new(data)) {
+        assert_ne!(error.kind(), ErrorKind::OutOfMemory);
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,289 @@
+use cfb::{
+    AllocContext, ClusterMetadataFirst, CompoundFile, FirstFree,
+    SectorAllocator, SectorId, SectorPurpose, Version,
+};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+//===========================================================================//
+
+const END_OF_CHAIN: u32 = 0xfffffffe;
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn sector_len(data: &[u8]) -> usize {
+    1 << u32::from(data[30])
+}
+
+fn sector_offset(data: &[u8], sector: u32) -> usize {
+    (sector as usize + 1) * sector_len(data)
+}
+
+/// Returns the FAT sector IDs listed in the header's DIFAT (the files in
+/// these tests are too small to need DIFAT sectors).
+fn fat_sectors(data: &[u8]) -> Vec<u32> {
+    let num_fat_sectors = u32_at(data, 44) as usize;
+    (0..num_fat_sectors).map(|index| u32_at(data, 76 + 4 * index)).collect()
+}
+
+fn fat(data: &[u8]) -> Vec<u32> {
+    let mut fat = Vec::new();
+    for sector in fat_sectors(data) {
+        let offset = sector_offset(data, sector);
+        for index in 0..sector_len(data) / 4 {
+            fat.push(u32_at(data, offset + 4 * index));
+        }
+    }
+    fat
+}
+
+fn chain(fat: &[u32], start: u32) -> Vec<u32> {
+    let mut chain = Vec::new();
+    let mut next = start;
+    while next != END_OF_CHAIN {
+        chain.push(next);
+        next = fat[next as usize];
+    }
+    chain
+}
+
+/// Returns the start sector of each directory entry (stream IDs in order),
+/// together with its stream length.
+fn dir_entries(data: &[u8]) -> Vec<(u32, u64)> {
+    let mut entries = Vec::new();
+    for sector in chain(&fat(data), u32_at(data, 48)) {
+        let offset = sector_offset(data, sector);
+        for index in 0..sector_len(data) / 128 {
+            let entry = offset + 128 * index;
+            let stream_len = u32_at(data, entry + 120) as u64;
+            entries.push((u32_at(data, entry + 116), stream_len));
+        }
+    }
+    entries
+}
+
+fn stream_data(index: usize) -> Vec<u8> {
+    let len = if index % 3 == 0 { 700 } else { 5000 + 37 * index };
+    (0..len).map(|byte| (byte * 7 + index) as u8).collect()
+}
+
+fn populate<F: Read + Write + std::io::Seek>(comp: &mut CompoundFile<F>) {
+    comp.create_storage("/storage").unwrap();
+    for index in 0..120 {
+        let path = format!("/storage/stream{index}");
+        comp.create_stream(&path)
+            .unwrap()
+            .write_all(&stream_data(index))
+            .unwrap();
+    }
+    for index in (0..120).step_by(4) {
+        comp.remove_stream(format!("/storage/stream{index}")).unwrap();
+    }
+    for index in 120..150 {
+        let path = format!("/storage/stream{index}");
+        comp.create_stream(&path)
+            .unwrap()
+            .write_all(&stream_data(index))
+            .unwrap();
+    }
+}
+
+fn check_contents<F: Read + std::io::Seek>(comp: &mut CompoundFile<F>) {
+    for index in (0..150).filter(|index| index >= &120 || index % 4 != 0) {
+        let path = format!("/storage/stream{index}");
+        let mut data = Vec::new();
+        comp.open_stream(&path).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, stream_data(index), "{}", path);
+    }
+}
+
+fn build(
+    version: Version,
+    allocator: Option<Box<dyn SectorAllocator>>,
+) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    if let Some(allocator) = allocator {
+        comp.set_allocator(allocator);
+    }
+    populate(&mut comp);
+    // Pin the storage's timestamps so that builds can be compared.
+    comp.set_created_time("/storage", UNIX_EPOCH).unwrap();
+    comp.set_modified_time("/storage", UNIX_EPOCH).unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+//===========================================================================//
+
+#[test]
+fn first_free_matches_default_layout() {
+    for &version in &[Version::V3, Version::V4] {
+        let default = build(version, None);
+        let explicit = build(version, Some(Box::new(FirstFree)));
+        assert!(default == explicit, "{:?}", version);
+    }
+}
+
+#[test]
+fn cluster_metadata_first_keeps_metadata_at_start() {
+    const NUM_METADATA_SECTORS: u32 = 128;
+    let data = build(
+        Version::V3,
+        Some(Box::new(ClusterMetadataFirst::new(NUM_METADATA_SECTORS))),
+    );
+    let fat = fat(&data);
+    let mut metadata = fat_sectors(&data);
+    metadata.extend(chain(&fat, u32_at(&data, 48)));
+    metadata.extend(chain(&fat, u32_at(&data, 60)));
+    assert!(metadata.iter().all(|&sector| sector < NUM_METADATA_SECTORS));
+
+    let entries = dir_entries(&data);
+    let mut data_sectors = chain(&fat, entries[0].0);
+    for &(start, len) in &entries[1..] {
+        if len >= 4096 {
+            data_sectors.extend(chain(&fat, start));
+        }
+    }
+    assert!(data_sectors.len() > 1000);
+    assert!(data_sectors.iter().all(|&sector| sector >= NUM_METADATA_SECTORS));
+
+    // The default policy interleaves metadata with stream data.
+    let default = build(Version::V3, None);
+    let default_fat = self::fat(&default);
+    let default_dir = chain(&default_fat, u32_at(&default, 48));
+    assert!(default_dir.iter().any(|&sector| sector >= NUM_METADATA_SECTORS));
+
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    check_contents(&mut comp);
+}
+
+//===========================================================================//
+
+/// A policy that behaves like `FirstFree`, but records what it was asked.
+#[derive(Clone, Default)]
+struct Recorder {
+    purposes: Arc<Mutex<Vec<String>>>,
+    freed: Arc<Mutex<Vec<SectorId>>>,
+}
+
+impl SectorAllocator for Recorder {
+    fn alloc(&mut self, ctx: AllocContext<'_>) -> SectorId {
+        let purpose = match ctx.purpose() {
+            SectorPurpose::Stream(path) => {
+                format!("stream {}", path.display())
+            }
+            other => format!("{:?}", other),
+        };
+        self.purposes.lock().unwrap().push(purpose);
+        FirstFree.alloc(ctx)
+    }
+
+    fn free(&mut self, sector_id: SectorId) {
+        self.freed.lock().unwrap().push(sector_id);
+    }
+}
+
+#[test]
+fn policy_is_told_sector_purposes() {
+    let recorder = Recorder::default();
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.set_allocator(Box::new(recorder.clone()));
+    comp.create_storage("/foo").unwrap();
+    comp.create_stream("/foo/small").unwrap().write_all(&[1; 100]).unwrap();
+    comp.create_stream("/foo/big").unwrap().write_all(&[2; 70000]).unwrap();
+    for index in 0..10 {
+        comp.create_stream(format!("/s{index}")).unwrap();
+    }
+    let purposes = recorder.purposes.lock().unwrap().clone();
+    for expected in
+        ["MiniFat", "MiniStream", "stream /foo/big", "Fat", "Directory"]
+    {
+        assert!(
+            purposes.iter().any(|purpose| purpose == expected),
+            "{:?} not in {:?}",
+            expected,
+            purposes
+        );
+    }
+    assert!(!purposes.iter().any(|purpose| purpose == "stream /foo/small"));
+}
+
+#[test]
+fn policy_is_told_about_freed_sectors() {
+    let recorder = Recorder::default();
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.set_allocator(Box::new(recorder.clone()));
+    comp.create_stream("/big").unwrap().write_all(&[2; 10000]).unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    let big_chain = chain(&fat(&data), dir_entries(&data)[1].0);
+    assert!(recorder.freed.lock().unwrap().is_empty());
+
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    comp.set_allocator(Box::new(recorder.clone()));
+    comp.remove_stream("/big").unwrap();
+    let mut freed = recorder.freed.lock().unwrap().clone();
+    freed.sort_unstable();
+    let mut expected: Vec<SectorId> =
+        big_chain.into_iter().map(SectorId::new).collect();
+    expected.sort_unstable();
+    assert_eq!(freed, expected);
+}
+
+//===========================================================================//
+
+/// A policy that makes one particular kind of mistake.
+struct Buggy(fn(AllocContext<'_>) -> SectorId);
+
+impl SectorAllocator for Buggy {
+    fn alloc(&mut self, ctx: AllocContext<'_>) -> SectorId {
+        (self.0)(ctx)
+    }
+}
+
+fn check_buggy_policy(policy: Buggy) {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/before").unwrap().write_all(&[3; 5000]).unwrap();
+    comp.set_allocator(Box::new(policy));
+    let mut stream = comp.create_stream("/after").unwrap();
+    let error = stream
+        .write_all(&[4; 5000])
+        .and_then(|()| stream.flush())
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    drop(stream);
+
+    // The sector that was already in use must not have been clobbered.
+    comp.set_allocator(Box::new(FirstFree));
+    comp.flush().unwrap();
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let mut data = Vec::new();
+    comp.open_stream("/before").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![3; 5000]);
+}
+
+#[test]
+fn policy_choosing_used_sector_is_an_error() {
+    check_buggy_policy(Buggy(|_| SectorId::new(0)));
+}
+
+#[test]
+fn policy_growing_file_too_far_is_an_error() {
+    check_buggy_policy(Buggy(|ctx| {
+        SectorId::new(ctx.max_sector().value() + 1)
+    }));
+}
+
+//===========================================================================//
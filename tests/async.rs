@@ -0,0 +1,258 @@
+#![cfg(feature = "async")]
+
+use cfb::async_file::AsyncCompoundFile;
+use cfb::{CompoundFile, Version};
+use std::fs;
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
+
+//===========================================================================//
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+/// Builds a file with a mix of storages, mini streams, and regular streams,
+/// whose sectors are interleaved by writing the streams a chunk at a time.
+fn build_file(version: Version) -> Vec<u8> {
+    let mut comp =
+        CompoundFile::create_with_version(version, Cursor::new(Vec::new()))
+            .unwrap();
+    comp.create_storage_all("/foo/bar").unwrap();
+    comp.create_stream("/foo/small")
+        .unwrap()
+        .write_all(&data(100, 1))
+        .unwrap();
+    comp.create_stream("/foo/bar/empty").unwrap();
+    for name in ["/large1", "/large2"] {
+        comp.create_stream(name).unwrap();
+    }
+    for chunk in 0..30 {
+        for (name, seed) in [("/large1", 2), ("/large2", 3)] {
+            let mut stream = comp.open_stream(name).unwrap();
+            Seek::seek(&mut stream, SeekFrom::End(0)).unwrap();
+            stream.write_all(&data(3000, seed + chunk)).unwrap();
+        }
+    }
+    for index in 0..50 {
+        let path = format!("/foo/bar/{}", index);
+        comp.create_stream(&path)
+            .unwrap()
+            .write_all(&data(index * 37, index as u8))
+            .unwrap();
+    }
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn large_contents(seed: u8) -> Vec<u8> {
+    (0..30).flat_map(|chunk| data(3000, seed + chunk)).collect()
+}
+
+async fn read_async<F: AsyncRead + AsyncSeek + Unpin>(
+    comp: &mut AsyncCompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).await.unwrap();
+    data
+}
+
+/// A reader that counts the bytes read through it, and returns them in
+/// small pieces, alternating with `Pending`.
+struct Trickle<R> {
+    inner: R,
+    bytes_read: u64,
+    ready: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Trickle<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if !self.ready {
+            self.ready = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.ready = false;
+        let mut piece = ReadBuf::new(
+            buf.initialize_unfilled_to(buf.remaining().min(1000)),
+        );
+        let result = Pin::new(&mut self.inner).poll_read(cx, &mut piece);
+        let num_read = piece.filled().len();
+        buf.advance(num_read);
+        self.bytes_read += num_read as u64;
+        result
+    }
+}
+
+impl<R: AsyncSeek + Unpin> AsyncSeek for Trickle<R> {
+    fn start_seek(mut self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(pos)
+    }
+
+    fn poll_complete(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+//===========================================================================//
+
+#[tokio::test]
+async fn entries_match_sync_open() {
+    for version in [Version::V3, Version::V4] {
+        let bytes = build_file(version);
+        let sync =
+            CompoundFile::open_strict(Cursor::new(bytes.clone())).unwrap();
+        let comp =
+            AsyncCompoundFile::open_strict(Cursor::new(bytes)).await.unwrap();
+        assert_eq!(comp.version(), version);
+        let sync_entries: Vec<_> = sync
+            .walk()
+            .map(|entry| (entry.path().to_owned(), entry.len()))
+            .collect();
+        let entries: Vec<_> = comp
+            .walk()
+            .map(|entry| (entry.path().to_owned(), entry.len()))
+            .collect();
+        assert_eq!(entries, sync_entries);
+        let names: Vec<_> = comp
+            .read_storage("/foo")
+            .unwrap()
+            .map(|entry| entry.name().to_owned())
+            .collect();
+        assert_eq!(names, ["bar", "small"]);
+        assert!(comp.is_storage("/foo/bar"));
+        assert!(comp.is_stream("/foo/small"));
+        assert!(!comp.exists("/nope"));
+        assert_eq!(comp.entry("/large1").unwrap().len(), 90_000);
+    }
+}
+
+#[tokio::test]
+async fn read_streams() {
+    let mut comp =
+        AsyncCompoundFile::open(Cursor::new(build_file(Version::V3)))
+            .await
+            .unwrap();
+    assert_eq!(read_async(&mut comp, "/foo/small").await, data(100, 1));
+    assert_eq!(read_async(&mut comp, "/foo/bar/empty").await, b"");
+    assert_eq!(read_async(&mut comp, "/large1").await, large_contents(2));
+    assert_eq!(read_async(&mut comp, "/large2").await, large_contents(3));
+    for index in 0..50 {
+        let path = format!("/foo/bar/{}", index);
+        assert_eq!(
+            read_async(&mut comp, &path).await,
+            data(index * 37, index as u8)
+        );
+    }
+
+    let error = comp.open_stream("/foo").err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let error = comp.open_stream("/nope").err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn seek_within_stream() {
+    let mut comp =
+        AsyncCompoundFile::open(Cursor::new(build_file(Version::V3)))
+            .await
+            .unwrap();
+    let expected = large_contents(2);
+    let mut stream = comp.open_stream("/large1").unwrap();
+    assert_eq!(stream.len(), 90_000);
+    for &offset in &[0u64, 511, 4000, 89_990, 45_000, 1] {
+        assert_eq!(
+            stream.seek(SeekFrom::Start(offset)).await.unwrap(),
+            offset
+        );
+        let mut buf = [0; 10];
+        stream.read_exact(&mut buf).await.unwrap();
+        let offset = offset as usize;
+        assert_eq!(buf, expected[offset..offset + 10]);
+    }
+    assert_eq!(stream.seek(SeekFrom::End(-5)).await.unwrap(), 89_995);
+    assert_eq!(stream.seek(SeekFrom::Current(-5)).await.unwrap(), 89_990);
+    let mut tail = Vec::new();
+    stream.read_to_end(&mut tail).await.unwrap();
+    assert_eq!(tail, expected[89_990..]);
+    let error = stream.seek(SeekFrom::Start(90_001)).await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let error = stream.seek(SeekFrom::Current(-100_000)).await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn open_reads_only_metadata() {
+    // A file big enough to need DIFAT sectors.
+    let mut comp = CompoundFile::create_with_version(
+        Version::V3,
+        Cursor::new(Vec::new()),
+    )
+    .unwrap();
+    let big = data(8_000_000, 7);
+    comp.create_stream("/big").unwrap().write_all(&big).unwrap();
+    comp.create_stream("/small").unwrap().write_all(&data(10, 8)).unwrap();
+    comp.flush().unwrap();
+    let bytes = comp.into_inner().into_inner();
+
+    let reader =
+        Trickle { inner: Cursor::new(bytes), bytes_read: 0, ready: false };
+    let mut comp = AsyncCompoundFile::open_strict(reader).await.unwrap();
+    assert_eq!(comp.entry("/big").unwrap().len(), 8_000_000);
+    assert_eq!(read_async(&mut comp, "/small").await, data(10, 8));
+    let reader = comp.into_inner();
+    assert!(reader.bytes_read < 200_000, "{}", reader.bytes_read);
+
+    let mut comp = AsyncCompoundFile::open(reader).await.unwrap();
+    assert_eq!(read_async(&mut comp, "/big").await, big);
+}
+
+#[tokio::test]
+async fn tokio_file() {
+    let path = std::env::temp_dir()
+        .join(format!("cfb-async-{}.cfb", std::process::id()));
+    fs::write(&path, build_file(Version::V4)).unwrap();
+    let file = tokio::fs::File::open(&path).await.unwrap();
+    let mut comp = AsyncCompoundFile::open(file).await.unwrap();
+    // Abandon a read part-way through, then read another stream.
+    {
+        let mut stream = comp.open_stream("/large2").unwrap();
+        let mut buf = [0; 100];
+        stream.read_exact(&mut buf).await.unwrap();
+    }
+    assert_eq!(read_async(&mut comp, "/large1").await, large_contents(2));
+    assert_eq!(read_async(&mut comp, "/foo/bar/49").await, data(49 * 37, 49));
+    drop(comp);
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_files() {
+    let error =
+        AsyncCompoundFile::open(Cursor::new(Vec::new())).await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    let mut bytes = build_file(Version::V3);
+    bytes[0] = 0;
+    let error =
+        AsyncCompoundFile::open(Cursor::new(bytes)).await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn async_compound_file_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<AsyncCompoundFile<tokio::fs::File>>();
+    assert_send::<cfb::AsyncStream<'static, tokio::fs::File>>();
+}
+
+//===========================================================================//
@@ -0,0 +1,235 @@
+use cfb::{AuditOp, AuditRecord, CompoundFile, Version};
+use std::io::{Cursor, ErrorKind, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const TRAIL: &str = "/\u{5}CfbAuditTrail";
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+fn summarize(records: &[AuditRecord]) -> Vec<(AuditOp, String, u64, u64)> {
+    records
+        .iter()
+        .map(|record| {
+            (
+                record.op(),
+                record.path().to_string_lossy().into_owned(),
+                record.old_len(),
+                record.new_len(),
+            )
+        })
+        .collect()
+}
+
+fn reopen(comp: Comp) -> Comp {
+    CompoundFile::open_strict(comp.into_inner()).unwrap()
+}
+
+/// Runs a scripted editing session over two opens of the same file, and
+/// returns the resulting file.
+fn edit_session() -> Comp {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.enable_audit_trail(TRAIL).unwrap();
+    comp.create_storage("/docs").unwrap();
+    {
+        let mut stream = comp.create_stream("/docs/report").unwrap();
+        stream.write_all(&[1; 100]).unwrap();
+        stream.write_all(&[2; 5000]).unwrap();
+    }
+    comp.create_stream("/notes").unwrap().write_all(b"hello").unwrap();
+    comp.set_state_bits("/notes", 7).unwrap();
+    comp.flush().unwrap();
+
+    // A second flush with nothing new to record adds nothing.
+    comp.flush().unwrap();
+
+    let mut comp = reopen(comp);
+    comp.enable_audit_trail(TRAIL).unwrap();
+    comp.open_stream("/docs/report").unwrap().set_len(200).unwrap();
+    comp.create_stream("/notes").unwrap().write_all(b"hi").unwrap();
+    comp.set_storage_clsid("/docs", Uuid::from_u128(1)).unwrap();
+    {
+        let mut stream = comp.create_stream("/scratch").unwrap();
+        stream.write_all(b"temporary").unwrap();
+    }
+    comp.remove_stream("/scratch").unwrap();
+    comp.remove_storage_all("/docs").unwrap();
+    comp.flush().unwrap();
+    comp
+}
+
+//===========================================================================//
+
+#[test]
+fn trail_records_scripted_session() {
+    let before = SystemTime::now() - Duration::from_secs(1);
+    let mut comp = reopen(edit_session());
+    let after = SystemTime::now() + Duration::from_secs(1);
+    let records = comp.read_audit_trail(TRAIL).unwrap();
+    let s = |path: &str| path.to_string();
+    assert_eq!(
+        summarize(&records),
+        vec![
+            // First session:
+            (AuditOp::CreateStorage, s("/docs"), 0, 0),
+            (AuditOp::CreateStream, s("/docs/report"), 0, 0),
+            (AuditOp::ModifyStream, s("/docs/report"), 0, 5100),
+            (AuditOp::CreateStream, s("/notes"), 0, 0),
+            (AuditOp::ModifyStream, s("/notes"), 0, 5),
+            (AuditOp::SetMetadata, s("/notes"), 5, 5),
+            // Second session:
+            (AuditOp::ModifyStream, s("/docs/report"), 5100, 200),
+            (AuditOp::CreateStream, s("/notes"), 5, 0),
+            (AuditOp::ModifyStream, s("/notes"), 0, 2),
+            (AuditOp::SetMetadata, s("/docs"), 0, 0),
+            (AuditOp::CreateStream, s("/scratch"), 0, 0),
+            (AuditOp::ModifyStream, s("/scratch"), 0, 9),
+            (AuditOp::RemoveStream, s("/scratch"), 9, 0),
+            (AuditOp::RemoveStream, s("/docs/report"), 200, 0),
+            (AuditOp::RemoveStorage, s("/docs"), 0, 0),
+        ]
+    );
+    for record in records.iter() {
+        assert!(record.time() >= before && record.time() <= after);
+    }
+    for pair in records.windows(2) {
+        assert!(pair[0].time() <= pair[1].time());
+    }
+}
+
+#[test]
+fn trail_does_not_record_itself() {
+    let mut comp = reopen(edit_session());
+    let records = comp.read_audit_trail(TRAIL).unwrap();
+    assert!(records.iter().all(|record| record.path() != Path::new(TRAIL)));
+
+    // Writing to the audit trail stream directly, even by another spelling
+    // of its name, isn't recorded either.
+    comp.enable_audit_trail(TRAIL).unwrap();
+    let other_case = TRAIL.to_uppercase();
+    let len = comp.entry(TRAIL).unwrap().len();
+    comp.open_stream(&other_case).unwrap().set_len(len).unwrap();
+    comp.flush().unwrap();
+    assert_eq!(comp.read_audit_trail(TRAIL).unwrap(), records);
+}
+
+#[test]
+fn nothing_is_recorded_unless_enabled() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/foo").unwrap().write_all(b"foo").unwrap();
+    comp.flush().unwrap();
+    assert!(!comp.exists(TRAIL));
+    let error = comp.read_audit_trail(TRAIL).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    comp.create_storage("/dir").unwrap();
+    let error = comp.enable_audit_trail("/dir").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.enable_audit_trail("/").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn unknown_record_version_is_rejected() {
+    let mut comp = reopen(edit_session());
+    {
+        let mut stream = comp.open_stream(TRAIL).unwrap();
+        stream.seek(SeekFrom::Start(0)).unwrap();
+        stream.write_all(&[2]).unwrap();
+    }
+    let error = comp.read_audit_trail(TRAIL).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("version 2"), "{}", error);
+}
+
+#[test]
+fn trail_survives_shrinking() {
+    let mut comp = reopen(edit_session());
+    let records = comp.read_audit_trail(TRAIL).unwrap();
+    comp.shrink_directory().unwrap();
+    let len = comp.shrink_to_fit().unwrap();
+    let mut data = comp.into_inner().into_inner();
+    data.truncate(len as usize);
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert_eq!(comp.read_audit_trail(TRAIL).unwrap(), records);
+}
+
+#[test]
+#[cfg(feature = "cli")]
+fn trail_survives_copying() {
+    use cfb::tool::{extract_all, insert_all};
+    use std::fs;
+    use std::path::PathBuf;
+
+    let dir: PathBuf = std::env::temp_dir()
+        .join(format!("cfb-audit-copy-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+
+    let mut comp = reopen(edit_session());
+    let records = comp.read_audit_trail(TRAIL).unwrap();
+    extract_all(&mut comp, Path::new("/"), &dir).unwrap();
+    let cursor = Cursor::new(Vec::new());
+    let mut copy =
+        CompoundFile::create_with_version(Version::V4, cursor).unwrap();
+    insert_all(&mut copy, Path::new("/"), &dir).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    // Further edits to the copy are added to the copied trail.
+    copy.enable_audit_trail(TRAIL).unwrap();
+    copy.remove_stream("/notes").unwrap();
+    copy.flush().unwrap();
+    let mut copy = reopen(copy);
+    let copied = copy.read_audit_trail(TRAIL).unwrap();
+    assert_eq!(copied[..records.len()], records[..]);
+    assert_eq!(
+        summarize(&copied[records.len()..]),
+        vec![(AuditOp::RemoveStream, "/notes".to_string(), 2, 0)]
+    );
+}
+
+#[test]
+fn trail_records_renames() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_storage("/docs").unwrap();
+    comp.create_stream("/draft").unwrap().write_all(b"draft").unwrap();
+    comp.flush().unwrap();
+    comp.enable_audit_trail(TRAIL).unwrap();
+    {
+        let mut stream = comp.open_stream("/draft").unwrap();
+        stream.write_all(b"first").unwrap();
+        stream.flush().unwrap();
+        comp.rename("/draft", "/docs/final").unwrap();
+        stream.write_all(b"second").unwrap();
+    }
+    comp.rename("/docs", "/archive").unwrap();
+    // Renaming the audit trail itself isn't recorded.
+    comp.flush().unwrap();
+    comp.rename(TRAIL, "/old_trail").unwrap();
+    comp.flush().unwrap();
+    let mut comp = reopen(comp);
+    let s = |path: &str| path.to_string();
+    assert_eq!(
+        summarize(&comp.read_audit_trail("/old_trail").unwrap()),
+        vec![
+            (AuditOp::ModifyStream, s("/draft"), 5, 5),
+            (AuditOp::RenameFrom, s("/draft"), 5, 5),
+            (AuditOp::RenameTo, s("/docs/final"), 5, 5),
+            (AuditOp::ModifyStream, s("/docs/final"), 5, 11),
+            (AuditOp::RenameFrom, s("/docs"), 0, 0),
+            (AuditOp::RenameTo, s("/archive"), 0, 0),
+        ]
+    );
+    assert!(!comp.exists(TRAIL));
+}
+
+//===========================================================================//
@@ -0,0 +1,162 @@
+use cfb::{BackingFileShrunk, CompoundFile, Version};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+//===========================================================================//
+
+/// An in-memory file that can be truncated (or replaced) through another
+/// handle while a `CompoundFile` owns it, as if by another process.
+#[derive(Clone, Default)]
+struct SharedFile(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl SharedFile {
+    fn len(&self) -> u64 {
+        self.0.lock().unwrap().get_ref().len() as u64
+    }
+
+    fn truncate(&self, len: u64) {
+        self.0.lock().unwrap().get_mut().truncate(len as usize);
+    }
+
+    fn replace(&self, data: Vec<u8>) {
+        *self.0.lock().unwrap().get_mut() = data;
+    }
+}
+
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SharedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.lock().unwrap().seek(pos)
+    }
+}
+
+/// Creates a compound file with a 10000-byte stream ("/big") at the end of
+/// the file and a small one ("/small"), and returns it along with another
+/// handle to its backing.
+fn make_file() -> (CompoundFile<SharedFile>, SharedFile) {
+    let backing = SharedFile::default();
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, backing.clone())
+            .unwrap();
+    comp.create_stream("/small").unwrap().write_all(b"small").unwrap();
+    comp.create_stream("/big").unwrap().write_all(&[7; 10000]).unwrap();
+    comp.flush().unwrap();
+    (comp, backing)
+}
+
+fn expect_shrunk(error: io::Error, expected: u64, actual: u64) {
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    let shrunk = BackingFileShrunk::from_io_error(&error)
+        .unwrap_or_else(|| panic!("not a BackingFileShrunk: {}", error));
+    assert_eq!(shrunk.expected(), expected);
+    assert_eq!(shrunk.actual(), actual);
+}
+
+//===========================================================================//
+
+#[test]
+fn read_after_truncation_reports_shrunk() {
+    let (mut comp, backing) = make_file();
+    let len = backing.len();
+    backing.truncate(len - 2048);
+
+    // Streams in the surviving part of the file can still be read.
+    let mut data = Vec::new();
+    comp.open_stream("/small").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"small");
+
+    let mut stream = comp.open_stream("/big").unwrap();
+    let error = stream.read_to_end(&mut Vec::new()).unwrap_err();
+    expect_shrunk(error, len, len - 2048);
+    drop(stream);
+    let error = comp.read_many(&["/big"]).unwrap_err();
+    expect_shrunk(error, len, len - 2048);
+}
+
+#[test]
+fn flush_is_refused_after_truncation() {
+    let (mut comp, backing) = make_file();
+    let len = backing.len();
+    backing.truncate(len - 100);
+    let error = comp.flush().unwrap_err();
+    expect_shrunk(error, len, len - 100);
+    let error = comp.open_stream("/small").unwrap().flush().unwrap_err();
+    expect_shrunk(error, len, len - 100);
+    let error = comp.shrink_to_fit().unwrap_err();
+    expect_shrunk(error, len, len - 100);
+    assert_eq!(backing.len(), len - 100);
+
+    // Flushing stays refused even if the file grows back.
+    backing.0.lock().unwrap().get_mut().resize(len as usize, 0);
+    let error = comp.flush().unwrap_err();
+    expect_shrunk(error, len, len - 100);
+}
+
+#[test]
+fn repair_zero_fills_and_allows_flushing() {
+    let (mut comp, backing) = make_file();
+    let len = backing.len();
+    backing.truncate(len - 1000);
+    assert!(comp.flush().is_err());
+    assert_eq!(comp.repair().unwrap(), 1000);
+    assert_eq!(backing.len(), len);
+    comp.create_stream("/new").unwrap().write_all(b"new").unwrap();
+    comp.flush().unwrap();
+    assert_eq!(comp.repair().unwrap(), 0);
+
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let mut data = Vec::new();
+    comp.open_stream("/big").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data.len(), 10000);
+    // The stream's last sector has 240 unused bytes, so of the 1000 bytes
+    // cut off, the last 760 were stream data.
+    assert!(data[..9240].iter().all(|&byte| byte == 7));
+    assert!(data[9240..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn reload_picks_up_replaced_file() {
+    let (mut comp, backing) = make_file();
+    let other = {
+        let cursor = Cursor::new(Vec::new());
+        let mut other =
+            CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+        other.create_stream("/other").unwrap();
+        other.into_inner().into_inner()
+    };
+    backing.replace(other);
+    assert!(comp.flush().is_err());
+    let mut comp = comp.reload().unwrap();
+    assert!(comp.is_stream("/other"));
+    assert!(!comp.exists("/big"));
+    comp.flush().unwrap();
+}
+
+#[test]
+fn truncating_after_shrink_to_fit_is_expected() {
+    let (mut comp, backing) = make_file();
+    comp.remove_stream("/big").unwrap();
+    let len = comp.shrink_to_fit().unwrap();
+    assert!(len < backing.len());
+    backing.truncate(len);
+    comp.create_stream("/more").unwrap().write_all(&[1; 5000]).unwrap();
+    comp.flush().unwrap();
+    CompoundFile::open_strict(comp.into_inner()).unwrap();
+}
+
+//===========================================================================//
@@ -1,6 +1,6 @@
-use cfb::{CompoundFile, Entry, Version};
+use cfb::{CompoundFile, Entry, EntryKind, EntryMetadata, Version};
 use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 //===========================================================================//
@@ -95,6 +95,58 @@ fn partial_final_sector() {
     assert_eq!(actual_data, stream_data);
 }
 
+#[test]
+fn sector_size_constants() {
+    let cases = [(Version::V3, 512, 4, 128), (Version::V4, 4096, 32, 1024)];
+    for (version, sector_len, dir_entries, fat_entries) in cases {
+        let comp = CompoundFile::create_with_version(
+            version,
+            Cursor::new(Vec::new()),
+        )
+        .unwrap();
+        assert_eq!(comp.sector_len(), sector_len);
+        assert_eq!(comp.mini_sector_len(), 64);
+        assert_eq!(comp.mini_stream_cutoff(), 4096);
+        assert_eq!(comp.dir_entries_per_sector(), dir_entries);
+        assert_eq!(comp.fat_entries_per_sector(), fat_entries);
+        assert_eq!(comp.difat_header_entries(), 109);
+        assert_eq!(version.difat_entries_per_sector(), fat_entries - 1);
+    }
+}
+
+#[test]
+fn directory_with_exactly_full_sectors() {
+    for version in [Version::V3, Version::V4] {
+        let mut comp = CompoundFile::create_with_version(
+            version,
+            Cursor::new(Vec::new()),
+        )
+        .unwrap();
+        // Together with the root entry, fill exactly one directory sector.
+        let per_sector = comp.dir_entries_per_sector();
+        for index in 1..per_sector {
+            let path = format!("/s{:02}", index);
+            comp.create_stream(&path).unwrap().write_all(b"x").unwrap();
+        }
+        assert_eq!(comp.stats().unwrap().num_dir_sectors(), 1);
+        let comp =
+            CompoundFile::open_strict(comp.into_inner()).expect("full sector");
+        assert_eq!(comp.read_root_storage().count(), per_sector - 1);
+        let mut header = [0; 4];
+        let cursor = comp.into_inner();
+        header.copy_from_slice(&cursor.get_ref()[40..44]);
+        let expected = if version == Version::V4 { 1 } else { 0 };
+        assert_eq!(u32::from_le_bytes(header), expected, "{:?}", version);
+
+        // One more entry spills into a second sector.
+        let mut comp = CompoundFile::open_strict(cursor).unwrap();
+        comp.create_stream("/one_more").unwrap();
+        assert_eq!(comp.stats().unwrap().num_dir_sectors(), 2);
+        let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+        assert_eq!(comp.read_root_storage().count(), per_sector);
+    }
+}
+
 //===========================================================================//
 // Tests for directory methods:
 
@@ -107,6 +159,72 @@ fn root_entry() {
     assert!(comp.root_entry().is_root());
 }
 
+#[test]
+fn entry_lengths() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/foo").unwrap();
+    comp.create_stream("/foo/empty").unwrap();
+    comp.create_stream("/foo/small").unwrap().write_all(&[1; 100]).unwrap();
+    comp.create_stream("/big").unwrap().write_all(&[2; 5000]).unwrap();
+    let cursor = comp.into_inner();
+    let comp = CompoundFile::open_strict(cursor).unwrap();
+
+    let root = comp.root_entry();
+    assert_eq!(root.len(), 0);
+    assert!(root.is_empty());
+    assert_eq!(comp.mini_stream_len(), 128);
+
+    let storage = comp.entry("/foo").unwrap();
+    assert_eq!(storage.len(), 0);
+    assert!(storage.is_empty());
+
+    let empty = comp.entry("/foo/empty").unwrap();
+    assert_eq!(empty.len(), 0);
+    assert!(empty.is_empty());
+
+    let small = comp.entry("/foo/small").unwrap();
+    assert_eq!(small.len(), 100);
+    assert!(!small.is_empty());
+
+    let total: u64 = comp.walk().map(|entry| entry.len()).sum();
+    assert_eq!(total, 5100);
+}
+
+#[test]
+fn entry_metadata() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/foo").unwrap();
+    comp.create_stream("/foo/bar").unwrap().write_all(&[1; 100]).unwrap();
+
+    let root = comp.root_entry();
+    assert_eq!(root.file_type(), EntryKind::Root);
+    assert!(root.metadata().is_dir());
+
+    let storage = comp.entry("/foo").unwrap();
+    let metadata = storage.metadata();
+    assert_eq!(metadata, EntryMetadata::from(&storage));
+    assert_eq!(metadata.file_type(), EntryKind::Storage);
+    assert!(metadata.is_dir() && !metadata.is_file());
+    assert_eq!(metadata.len(), 0);
+    assert!(!metadata.readonly());
+    assert_eq!(metadata.created().unwrap(), storage.created());
+    assert_eq!(metadata.modified().unwrap(), storage.modified());
+
+    let stream = comp.entry("/foo/bar").unwrap();
+    let metadata = stream.metadata();
+    assert_eq!(stream.file_type(), EntryKind::Stream);
+    assert!(metadata.is_file() && !metadata.is_dir());
+    assert!(!metadata.is_symlink());
+    assert_eq!(metadata.len(), 100);
+    // Streams never record timestamps.
+    let error = metadata.modified().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    assert!(metadata.created().is_err());
+
+    let path: &Path = stream.as_ref();
+    assert_eq!(path, Path::new("/foo/bar"));
+}
+
 #[test]
 fn create_directory_tree() {
     let cursor = Cursor::new(Vec::new());
@@ -163,6 +281,87 @@ fn walk_directory_tree() {
     assert_eq!(walk_to_vec(&entries), vec![Path::new("/baz")]);
 }
 
+#[test]
+fn walk_relative_paths() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).expect("create");
+    comp.create_storage("/foo").unwrap();
+    comp.create_stream("/baz").unwrap();
+    comp.create_stream("/foo/bar").unwrap();
+    let paths: Vec<PathBuf> =
+        comp.walk_relative("/").unwrap().map(|(path, _)| path).collect();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::new(),
+            PathBuf::from("baz"),
+            PathBuf::from("foo"),
+            PathBuf::from("foo/bar")
+        ]
+    );
+    let entries: Vec<(PathBuf, Entry)> =
+        comp.walk_relative("/foo").unwrap().collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].0, PathBuf::new());
+    assert_eq!(entries[0].1.path(), Path::new("/foo"));
+    assert_eq!(entries[1].0, PathBuf::from("bar"));
+    assert_eq!(entries[1].1.path(), Path::new("/foo/bar"));
+}
+
+#[test]
+#[should_panic(expected = "Not a storage: \\\"/baz\\\"")]
+fn walk_relative_on_stream() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).expect("create");
+    comp.create_stream("/baz").unwrap();
+    let _ = comp.walk_relative("/baz").unwrap();
+}
+
+fn import_subtree<F: Read + Write + Seek>(
+    comp: &mut CompoundFile<F>,
+    mount: &str,
+) {
+    let mount = Path::new(mount);
+    comp.create_storage_all(mount).unwrap();
+    comp.create_storage(mount.join("icons")).unwrap();
+    comp.create_stream(mount.join("icons/app.ico"))
+        .unwrap()
+        .write_all(&[1; 300])
+        .unwrap();
+    comp.create_stream(mount.join("License"))
+        .unwrap()
+        .write_all(&[2; 5000])
+        .unwrap();
+    comp.create_stream(mount.join("a")).unwrap();
+}
+
+fn manifest<F>(
+    comp: &CompoundFile<F>,
+    base: &str,
+) -> Vec<(PathBuf, bool, u64)> {
+    comp.walk_relative(base)
+        .unwrap()
+        .map(|(path, entry)| (path, entry.is_stream(), entry.len()))
+        .collect()
+}
+
+#[test]
+fn walk_relative_manifests_match_across_mount_points() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).expect("create");
+    import_subtree(&mut comp, "/first");
+    import_subtree(&mut comp, "/second/nested/deeper");
+    comp.create_stream("/second/nested/unrelated").unwrap();
+    let first = manifest(&comp, "/first");
+    assert_eq!(first.len(), 5);
+    assert_eq!(first, manifest(&comp, "/second/nested/deeper"));
+    assert_ne!(first, manifest(&comp, "/second/nested"));
+
+    let mut other = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    import_subtree(&mut other, "/");
+    assert_eq!(first, manifest(&other, "/"));
+}
+
 #[test]
 #[should_panic(expected = "Not a storage: \\\"/foo\\\"")]
 fn read_storage_on_stream() {
@@ -241,6 +440,91 @@ fn path_is_storage() {
     assert!(!comp.is_storage("../../bar"));
 }
 
+fn ancestor_paths<F>(comp: &CompoundFile<F>, path: &str) -> Vec<PathBuf> {
+    comp.ancestors(path).unwrap().map(|e| e.path().to_path_buf()).collect()
+}
+
+#[test]
+fn parent_entry_and_ancestors() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).expect("create");
+    comp.create_storage_all("/obj/inner").unwrap();
+    comp.create_stream("/obj/inner/data").unwrap();
+    comp.create_stream("/top").unwrap();
+    let clsid =
+        Uuid::parse_str("00020906-0000-0000-c000-000000000046").unwrap();
+    comp.set_storage_clsid("/obj", clsid).unwrap();
+
+    let parent = comp.parent_entry("/obj/inner/data").unwrap().unwrap();
+    assert_eq!(parent.path(), Path::new("/obj/inner"));
+    assert!(parent.is_storage());
+    assert_eq!(
+        ancestor_paths(&comp, "obj/inner/data"),
+        vec![
+            PathBuf::from("/obj/inner"),
+            PathBuf::from("/obj"),
+            PathBuf::from("/")
+        ]
+    );
+    let owner = comp
+        .ancestors("/obj/inner/data")
+        .unwrap()
+        .find(|entry| !entry.clsid().is_nil())
+        .unwrap();
+    assert_eq!(owner.name(), "obj");
+    assert_eq!(*owner.clsid(), clsid);
+    assert!(comp
+        .ancestors("/obj/inner/data")
+        .unwrap()
+        .last()
+        .unwrap()
+        .is_root());
+
+    assert_eq!(ancestor_paths(&comp, "/top"), vec![PathBuf::from("/")]);
+    assert!(comp.parent_entry("/").unwrap().is_none());
+    assert_eq!(comp.ancestors("/").unwrap().count(), 0);
+    let error = comp.parent_entry("/obj/nope").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn ancestors_after_removing_and_recreating() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).expect("create");
+    // Removing "m", which has siblings on both sides, moves the entry for
+    // "f" (its predecessor) into the directory slot that "m" used.
+    comp.create_storage("/m").unwrap();
+    comp.create_storage("/f").unwrap();
+    comp.create_storage("/t").unwrap();
+    comp.create_storage("/f/g").unwrap();
+    comp.create_stream("/f/g/x").unwrap();
+    comp.create_stream("/f/y").unwrap();
+    comp.remove_storage("/m").unwrap();
+    assert_eq!(
+        ancestor_paths(&comp, "/f/g/x"),
+        vec![PathBuf::from("/f/g"), PathBuf::from("/f"), PathBuf::from("/")]
+    );
+    assert_eq!(comp.parent_entry("/f/y").unwrap().unwrap().name(), "f");
+
+    // New entries reuse the freed slot.
+    comp.create_storage("/n").unwrap();
+    comp.create_stream("/n/z").unwrap();
+    comp.remove_storage_all("/f/g").unwrap();
+    comp.create_stream("/f/g").unwrap();
+    assert_eq!(comp.parent_entry("/n/z").unwrap().unwrap().name(), "n");
+    assert_eq!(comp.parent_entry("/f/g").unwrap().unwrap().name(), "f");
+    assert_eq!(comp.parent_entry("/f/y").unwrap().unwrap().name(), "f");
+
+    // The same relationships are found after reopening the file.
+    let paths = ["/f/g", "/f/y", "/n/z", "/t"];
+    let before: Vec<_> =
+        paths.iter().map(|p| ancestor_paths(&comp, p)).collect();
+    let comp = CompoundFile::open(comp.into_inner()).unwrap();
+    let after: Vec<_> =
+        paths.iter().map(|p| ancestor_paths(&comp, p)).collect();
+    assert_eq!(before, after);
+}
+
 //===========================================================================//
 // Tests for CLSIDs:
 
@@ -359,7 +643,7 @@ fn create_storages_all() {
 }
 
 #[test]
-#[should_panic(expected = "Cannot create storage")]
+#[should_panic(expected = "PathThroughStream")]
 fn create_storage_all_with_stream_in_the_way() {
     let cursor = Cursor::new(Vec::new());
     let mut comp = CompoundFile::create(cursor).expect("create");
@@ -770,6 +1054,70 @@ fn drop_compound_file_with_stream_open() -> io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn stream_sees_length_change_from_other_handle() -> io::Result<()> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor)?;
+    comp.create_stream("/foobar")?.write_all(&[1; 100])?;
+    let mut stream1 = comp.open_stream("/foobar")?;
+    stream1.seek(SeekFrom::Start(50))?;
+    let mut stream2 = comp.open_stream("/foobar")?;
+    stream2.set_len(10)?;
+    assert_eq!(stream1.len(), 10);
+    assert_eq!(stream1.stream_position()?, 10);
+    assert_eq!(stream1.seek(SeekFrom::End(0))?, 10);
+    stream1.write_all(b"xyz")?;
+    drop(stream1);
+    assert_eq!(stream2.len(), 13);
+    let mut data = Vec::new();
+    comp.open_stream("/foobar")?.read_to_end(&mut data)?;
+    assert_eq!(&data[..10], &[1; 10]);
+    assert_eq!(&data[10..], b"xyz");
+    Ok(())
+}
+
+#[test]
+fn buffered_writes_do_not_revert_truncation() -> io::Result<()> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor)?;
+    comp.create_stream("/foobar")?.write_all(&[1; 100])?;
+    let mut stream1 = comp.open_stream("/foobar")?;
+    stream1.seek(SeekFrom::Start(50))?;
+    stream1.write_all(b"abc")?;
+    comp.open_stream("/foobar")?.set_len(10)?;
+    // Flushing the buffered write must not resurrect the truncated data;
+    // the gap is zero-filled instead.
+    stream1.flush()?;
+    assert_eq!(stream1.len(), 53);
+    let mut data = Vec::new();
+    comp.open_stream("/foobar")?.read_to_end(&mut data)?;
+    assert_eq!(&data[..10], &[1; 10]);
+    assert_eq!(&data[10..50], &[0; 40]);
+    assert_eq!(&data[50..], b"abc");
+    Ok(())
+}
+
+#[test]
+fn entry_len_matches_stream_after_flush() -> io::Result<()> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor)?;
+    comp.create_storage("/storage")?;
+    comp.create_stream("/other")?;
+    let mut stream = comp.create_stream("/foobar")?;
+    stream.write_all(&[7; 5000])?;
+    comp.touch("/other")?;
+    comp.set_storage_clsid("/storage", Uuid::from_u128(0x1234))?;
+    stream.flush()?;
+    assert_eq!(stream.len(), 5000);
+    assert_eq!(comp.entry("/foobar")?.len(), 5000);
+    stream.write_all(&[8; 100])?;
+    comp.touch("/foobar")?;
+    drop(stream);
+    assert_eq!(comp.entry("/foobar")?.len(), 5100);
+    assert_eq!(comp.entry("/storage")?.clsid(), &Uuid::from_u128(0x1234));
+    Ok(())
+}
+
 //===========================================================================//
 // Tests for asserting Send + Sync:
 
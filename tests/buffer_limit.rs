@@ -0,0 +1,141 @@
+use cfb::propset::{self, PropertySet};
+use cfb::{CompoundFile, TooLargeToBuffer, VerifyOptions, Version};
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::path::Path;
+
+//===========================================================================//
+
+const BIG_LEN: usize = 5000;
+const SMALL_LEN: usize = 100;
+
+/// Creates a V3 compound file with a regular stream "/big", a mini stream
+/// "/small", and a summary information property set, with the buffering
+/// limit set between the two stream lengths.
+fn make_comp() -> CompoundFile<Cursor<Vec<u8>>> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/big").unwrap().write_all(&[1; BIG_LEN]).unwrap();
+    comp.create_stream("/small").unwrap().write_all(&[2; SMALL_LEN]).unwrap();
+    let mut set = PropertySet::new(propset::FMTID_SUMMARY_INFO);
+    set.section_mut()
+        .set_string(propset::PID_TITLE, &"x".repeat(BIG_LEN))
+        .unwrap();
+    comp.write_property_set(propset::SUMMARY_INFO_PATH, &set).unwrap();
+    comp.set_max_buffer_len(SMALL_LEN);
+    comp
+}
+
+fn assert_too_large<T>(result: io::Result<T>, path: &str, len: u64) {
+    let error = match result {
+        Ok(_) => panic!("buffering {} wasn't refused", path),
+        Err(error) => error,
+    };
+    assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+    let too_large = TooLargeToBuffer::from_io_error(&error).unwrap();
+    assert_eq!(too_large.path(), Path::new(path));
+    assert_eq!(too_large.len(), len);
+    assert_eq!(too_large.cap(), SMALL_LEN);
+}
+
+//===========================================================================//
+
+#[test]
+fn default_limit() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    assert_eq!(comp.max_buffer_len(), 1 << 30);
+    comp.set_max_buffer_len(usize::MAX);
+    assert_eq!(comp.max_buffer_len(), usize::MAX);
+}
+
+#[test]
+fn read_stream_to_vec_is_limited() {
+    let mut comp = make_comp();
+    assert_too_large(comp.read_stream_to_vec("/big"), "/big", 5000);
+    assert_eq!(comp.read_stream_to_vec("/small").unwrap(), [2; SMALL_LEN]);
+    comp.set_max_buffer_len(usize::MAX);
+    assert_eq!(comp.read_stream_to_vec("/big").unwrap(), [1; BIG_LEN]);
+    let error = comp.read_stream_to_vec("/missing").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = comp.read_stream_to_vec("/").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn read_many_is_limited() {
+    let mut comp = make_comp();
+    assert_too_large(comp.read_many(&["/small", "/big"]), "/big", 5000);
+    let contents = comp.read_many(&["/small"]).unwrap();
+    assert_eq!(contents[0].1, [2; SMALL_LEN]);
+}
+
+#[test]
+fn read_stream_aligned_is_limited() {
+    let mut comp = make_comp();
+    assert_too_large(comp.read_stream_aligned("/big", 64), "/big", 5000);
+    assert_eq!(&comp.read_stream_aligned("/small", 64).unwrap()[..], [2; 100]);
+}
+
+#[test]
+fn read_stream_slack_is_limited() {
+    let mut comp = make_comp();
+    comp.set_max_buffer_len(100);
+    // "/big" has 120 bytes of slack, and "/small" only 28.
+    assert_too_large(comp.read_stream_slack("/big"), "/big", 120);
+    assert_eq!(comp.read_stream_slack("/small").unwrap(), [0; 28]);
+}
+
+#[test]
+fn read_property_set_is_limited() {
+    let mut comp = make_comp();
+    let len = comp.entry(propset::SUMMARY_INFO_PATH).unwrap().len();
+    assert_too_large(
+        comp.read_property_set(propset::SUMMARY_INFO_PATH),
+        propset::SUMMARY_INFO_PATH,
+        len,
+    );
+    comp.set_max_buffer_len(len as usize);
+    let set = comp.read_property_set(propset::SUMMARY_INFO_PATH).unwrap();
+    assert_eq!(set.section().string(propset::PID_TITLE).unwrap().len(), 5000);
+}
+
+#[test]
+fn digital_signature_is_limited() {
+    let mut comp = make_comp();
+    comp.create_stream("/\u{5}DigitalSignature")
+        .unwrap()
+        .write_all(&[3; 1000])
+        .unwrap();
+    assert_too_large(comp.digital_signature(), "/\u{5}DigitalSignature", 1000);
+    comp.set_max_buffer_len(1000);
+    assert_eq!(comp.digital_signature().unwrap().unwrap().len(), 1000);
+}
+
+#[test]
+fn streaming_reads_are_not_limited() {
+    let mut comp = make_comp();
+    let mut data = Vec::new();
+    comp.open_stream("/big").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, [1; BIG_LEN]);
+    let mut copy = Vec::new();
+    io::copy(&mut comp.open_stream("/big").unwrap(), &mut copy).unwrap();
+    assert_eq!(copy, data);
+    let report = comp.verify_deep(VerifyOptions::new().hash_streams(true));
+    assert!(report.is_ok());
+    assert!(report.streams().iter().all(|stream| stream.hash().is_some()));
+}
+
+#[cfg(feature = "compat")]
+#[test]
+fn compat_read_stream_is_limited() {
+    let mut comp = make_comp();
+    comp.create_storage("/dir").unwrap();
+    comp.create_stream("/dir/big").unwrap().write_all(&[4; 200]).unwrap();
+    let mut compat = cfb::compat::CompatFile::new(comp);
+    assert_too_large(compat.read_stream("/big"), "/big", 5000);
+    assert_eq!(compat.read_stream("/small").unwrap().len(), SMALL_LEN);
+    let mut storage = compat.open_storage("/dir").unwrap();
+    assert_too_large(storage.read_stream("big"), "/dir/big", 200);
+}
+
+//===========================================================================//
@@ -0,0 +1,173 @@
+use cfb::{CompoundFile, Version};
+use std::io::{Cursor, Read, Seek, Write};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+/// The logical contents shared by the files built below: (path, data) for
+/// streams, and (path, None) for storages.
+fn contents() -> Vec<(&'static str, Option<Vec<u8>>)> {
+    vec![
+        ("/Alpha", Some(data(10000, 1))),
+        ("/small", Some(data(100, 2))),
+        ("/Storage", None),
+        ("/Storage/inner", Some(data(5000, 3))),
+        ("/Storage/tiny", Some(data(7, 4))),
+        ("/Storage/Nested", None),
+        ("/Storage/Nested/deep", Some(data(70000, 5))),
+        ("/empty", Some(Vec::new())),
+        ("/zz", Some(data(4095, 6))),
+    ]
+}
+
+fn set_metadata<F: Read + Write + Seek>(comp: &mut CompoundFile<F>) {
+    let time = UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+    comp.set_storage_clsid("/", Uuid::from_u128(0x1234)).unwrap();
+    comp.set_modified_time("/", time).unwrap();
+    for path in ["/Storage", "/Storage/Nested"] {
+        comp.set_storage_clsid(path, Uuid::from_u128(0x5678)).unwrap();
+        comp.set_created_time(path, time).unwrap();
+        comp.set_modified_time(path, time).unwrap();
+    }
+    comp.set_state_bits("/small", 0xabcd).unwrap();
+}
+
+/// Builds the contents in order, writing each stream in one go.
+fn build_in_order(version: Version) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    for (path, data) in contents() {
+        match data {
+            Some(data) => {
+                comp.create_stream(path).unwrap().write_all(&data).unwrap()
+            }
+            None => comp.create_storage(path).unwrap(),
+        }
+    }
+    set_metadata(&mut comp);
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Builds the same contents in reverse order, interleaving the streams'
+/// writes, leaving behind removed objects, and resizing streams along the
+/// way, so that the physical layout is entirely different.
+fn build_with_churn(version: Version) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.create_stream("/junk").unwrap().write_all(&[9; 30000]).unwrap();
+    let mut items = contents();
+    items.reverse();
+    for (path, data) in items.iter() {
+        if data.is_none() {
+            comp.create_storage_all(path).unwrap();
+        }
+    }
+    for (path, data) in items.iter() {
+        if let Some(data) = data {
+            let mut stream = comp.create_stream(path).unwrap();
+            stream.write_all(&[0xee; 6000]).unwrap();
+            stream.set_len(0).unwrap();
+            stream.write_all(&data[..data.len() / 2]).unwrap();
+            stream.flush().unwrap();
+        }
+        comp.create_stream("/junk2").unwrap().write_all(&[8; 700]).unwrap();
+        comp.remove_stream("/junk2").unwrap();
+    }
+    for (path, data) in items.iter() {
+        if let Some(data) = data {
+            let mut stream = comp.open_stream(path).unwrap();
+            stream.seek(std::io::SeekFrom::End(0)).unwrap();
+            stream.write_all(&data[data.len() / 2..]).unwrap();
+        }
+    }
+    comp.remove_stream("/junk").unwrap();
+    set_metadata(&mut comp);
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn canonical(data: Vec<u8>) -> Vec<u8> {
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    let output = comp.rewrite_canonical(Cursor::new(Vec::new())).unwrap();
+    output.into_inner().into_inner()
+}
+
+//===========================================================================//
+
+#[test]
+fn equal_contents_give_identical_output() {
+    for version in [Version::V3, Version::V4] {
+        let first = build_in_order(version);
+        let second = build_with_churn(version);
+        assert_ne!(first, second);
+        let canonical_first = canonical(first);
+        let canonical_second = canonical(second);
+        assert!(canonical_first == canonical_second, "{:?}", version);
+        // Rewriting is idempotent.
+        assert!(canonical(canonical_first.clone()) == canonical_first);
+    }
+}
+
+#[test]
+fn output_preserves_contents() {
+    let mut source =
+        CompoundFile::open(Cursor::new(build_with_churn(Version::V3)))
+            .unwrap();
+    let output = source.rewrite_canonical(Cursor::new(Vec::new())).unwrap();
+    let mut output = CompoundFile::open_strict(output.into_inner()).unwrap();
+    assert_eq!(output.version(), Version::V3);
+    let source_paths: Vec<_> =
+        source.walk().map(|entry| entry.path().to_path_buf()).collect();
+    let output_paths: Vec<_> =
+        output.walk().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(source_paths, output_paths);
+    let entries: Vec<_> = source.walk().collect();
+    for entry in entries {
+        let copy = output.entry(entry.path()).unwrap();
+        assert_eq!(copy.metadata(), entry.metadata(), "{:?}", entry.path());
+        assert_eq!(copy.clsid(), entry.clsid(), "{:?}", entry.path());
+        assert_eq!(
+            copy.state_bits(),
+            entry.state_bits(),
+            "{:?}",
+            entry.path()
+        );
+        if entry.is_stream() {
+            let mut expected = Vec::new();
+            let mut actual = Vec::new();
+            source
+                .open_stream(entry.path())
+                .unwrap()
+                .read_to_end(&mut expected)
+                .unwrap();
+            output
+                .open_stream(entry.path())
+                .unwrap()
+                .read_to_end(&mut actual)
+                .unwrap();
+            assert_eq!(actual, expected, "{:?}", entry.path());
+        }
+    }
+}
+
+#[test]
+fn output_has_no_free_space() {
+    for version in [Version::V3, Version::V4] {
+        let data = canonical(build_with_churn(version));
+        let comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+        let stats = comp.stats().unwrap();
+        assert_eq!(stats.num_free_sectors(), 0, "{:?}", version);
+        // Only the padding at the end of the last directory sector.
+        let per_sector = version.dir_entries_per_sector() as u32;
+        assert!(stats.num_free_dir_entries() < per_sector, "{:?}", version);
+        assert_eq!(stats.num_fragments(), 0, "{:?}", version);
+    }
+}
+
+//===========================================================================//
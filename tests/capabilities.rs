@@ -0,0 +1,311 @@
+use cfb::{Capabilities, CompoundFile, SpoolPolicy, Unsupported};
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::PathBuf;
+
+//===========================================================================//
+
+/// A scratch directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "cfb-capabilities-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+fn read_stream<F: Read + io::Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Asserts that the error is an `Unsupported` for the given operation and
+/// missing capabilities.
+fn assert_unsupported(
+    error: io::Error,
+    operation: &str,
+    missing: Capabilities,
+) {
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported, "{}", error);
+    let unsupported = Unsupported::from_io_error(&error).unwrap();
+    assert_eq!(unsupported.operation(), operation);
+    assert_eq!(unsupported.missing(), missing);
+}
+
+/// The capabilities of a file opened read-only at a path.  Positional reads
+/// are only supported on Unix and Windows.
+fn read_only_file_capabilities() -> Capabilities {
+    if cfg!(any(unix, windows)) {
+        Capabilities::PERSISTENT_PATH | Capabilities::POSITIONAL_READ
+    } else {
+        Capabilities::PERSISTENT_PATH
+    }
+}
+
+/// The capabilities of a file opened read-write at a path.
+fn file_capabilities() -> Capabilities {
+    read_only_file_capabilities()
+        | Capabilities::WRITABLE
+        | Capabilities::TRUNCATABLE
+        | Capabilities::ATOMIC_SAVE
+}
+
+//===========================================================================//
+
+#[test]
+fn generic_constructors() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    assert_eq!(comp.capabilities(), Capabilities::WRITABLE);
+    assert_eq!(comp.path(), None);
+    comp.create_stream("/foo").unwrap().write_all(b"foo").unwrap();
+    comp.flush().unwrap();
+
+    // Nothing is known about a reader passed to `open`, even a writable one.
+    let mut comp = CompoundFile::open(comp.into_inner()).unwrap();
+    assert!(comp.capabilities().is_empty());
+    comp.create_stream("/bar").unwrap().write_all(b"bar").unwrap();
+    let comp = comp.reload().unwrap();
+    assert!(comp.capabilities().is_empty());
+
+    let bytes = comp.into_inner().into_inner();
+    let comp =
+        cfb::open_from_reader(bytes.as_slice(), SpoolPolicy::Memory).unwrap();
+    assert_eq!(comp.capabilities(), Capabilities::WRITABLE);
+}
+
+#[test]
+fn unsupported_operations() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream("/foo").unwrap().write_all(b"foo").unwrap();
+    let error = comp.read_stream_at("/foo", 0, &mut [0; 3]).unwrap_err();
+    assert_unsupported(error, "read_stream_at", Capabilities::POSITIONAL_READ);
+    let error = comp.flush_and_truncate().unwrap_err();
+    assert_unsupported(error, "flush_and_truncate", Capabilities::TRUNCATABLE);
+    let error = comp.save_atomic().unwrap_err();
+    assert_unsupported(error, "save_atomic", Capabilities::ATOMIC_SAVE);
+    // Nothing was changed along the way.
+    assert_eq!(read_stream(&mut comp, "/foo"), b"foo");
+}
+
+#[test]
+fn path_constructors() {
+    let dir = TempDir::new("paths");
+    let path = dir.join("test.cfb");
+    let mut comp = cfb::create(&path).unwrap();
+    assert_eq!(comp.capabilities(), file_capabilities());
+    assert_eq!(comp.path(), Some(path.as_path()));
+    comp.create_stream("/foo").unwrap().write_all(b"foo").unwrap();
+    drop(comp);
+
+    let comp = cfb::open_rw(&path).unwrap();
+    assert_eq!(comp.capabilities(), file_capabilities());
+    let comp = comp.reload().unwrap();
+    assert_eq!(comp.capabilities(), file_capabilities());
+    assert_eq!(comp.path(), Some(path.as_path()));
+    drop(comp);
+
+    let mut comp = cfb::open(&path).unwrap();
+    assert_eq!(comp.capabilities(), read_only_file_capabilities());
+    assert_eq!(read_stream(&mut comp, "/foo"), b"foo");
+    // Writes are refused up front, rather than failing in the OS.
+    let error = comp.create_storage("/bar").unwrap_err();
+    assert_unsupported(error, "write", Capabilities::WRITABLE);
+    assert!(!comp.exists("/bar"));
+    let error = comp.save_atomic().unwrap_err();
+    assert_unsupported(error, "save_atomic", Capabilities::ATOMIC_SAVE);
+    let comp = comp.reload().unwrap();
+    assert_eq!(comp.capabilities(), read_only_file_capabilities());
+}
+
+#[test]
+fn open_strict_by_path() {
+    let dir = TempDir::new("strict");
+    let path = dir.join("test.cfb");
+    let mut comp = cfb::create(&path).unwrap();
+    comp.create_stream("/foo").unwrap().write_all(b"foo").unwrap();
+    drop(comp);
+
+    let mut comp = cfb::open_strict(&path).unwrap();
+    assert_eq!(comp.capabilities(), read_only_file_capabilities());
+    assert_eq!(comp.path(), Some(path.as_path()));
+    assert_eq!(read_stream(&mut comp, "/foo"), b"foo");
+    drop(comp);
+
+    // A nonzero header CLSID is tolerated by `open`, but not by
+    // `open_strict`.
+    let mut data = fs::read(&path).unwrap();
+    data[8] = 1;
+    fs::write(&path, data).unwrap();
+    assert!(cfb::open(&path).is_ok());
+    let error = cfb::open_strict(&path).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(any(unix, windows))]
+#[test]
+fn read_stream_at() {
+    let dir = TempDir::new("read-at");
+    let mut comp = cfb::create(dir.join("test.cfb")).unwrap();
+    let small = data(1000, 1);
+    let large = data(50_000, 2);
+    comp.create_stream("/small").unwrap().write_all(&small).unwrap();
+    comp.create_stream("/large").unwrap().write_all(&large).unwrap();
+    comp.create_storage("/dir").unwrap();
+    comp.flush().unwrap();
+
+    for (path, expected) in [("/small", &small), ("/large", &large)] {
+        for &(offset, len) in
+            &[(0, 10), (60, 200), (4000, 9000), (990, 100), (49_990, 100)]
+        {
+            let mut buf = vec![0; len];
+            let num_read =
+                comp.read_stream_at(path, offset, &mut buf).unwrap();
+            let start = (offset as usize).min(expected.len());
+            let end = (start + len).min(expected.len());
+            assert_eq!(num_read, end - start, "{} at {}", path, offset);
+            assert_eq!(&buf[..num_read], &expected[start..end]);
+        }
+    }
+    let mut buf = [0; 10];
+    assert_eq!(comp.read_stream_at("/small", 5000, &mut buf).unwrap(), 0);
+    let error = comp.read_stream_at("/dir", 0, &mut buf).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let error = comp.read_stream_at("/missing", 0, &mut buf).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    // Reads only need a shared reference, so they can run concurrently.
+    let comp = &comp;
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let large = &large;
+            scope.spawn(move || {
+                for chunk in 0..10 {
+                    let offset = (thread * 10 + chunk) * 1000;
+                    let mut buf = vec![0; 1000];
+                    comp.read_stream_at("/large", offset as u64, &mut buf)
+                        .unwrap();
+                    assert_eq!(buf, large[offset..offset + 1000]);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn flush_and_truncate() {
+    let dir = TempDir::new("truncate");
+    let path = dir.join("test.cfb");
+    let mut comp = cfb::create(&path).unwrap();
+    comp.create_stream("/keep").unwrap().write_all(&data(10_000, 1)).unwrap();
+    comp.create_stream("/drop").unwrap().write_all(&data(200_000, 2)).unwrap();
+    comp.flush().unwrap();
+    let full_len = fs::metadata(&path).unwrap().len();
+    comp.remove_stream("/drop").unwrap();
+    let len = comp.flush_and_truncate().unwrap();
+    assert!(len < full_len);
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    drop(comp);
+    let mut comp =
+        CompoundFile::open_strict(fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/keep"), data(10_000, 1));
+}
+
+#[test]
+fn shrink_to_fit_truncates_when_possible() {
+    let dir = TempDir::new("shrink");
+    let path = dir.join("test.cfb");
+    let mut comp = cfb::create(&path).unwrap();
+    comp.create_stream("/keep").unwrap().write_all(&data(10_000, 1)).unwrap();
+    comp.create_stream("/drop").unwrap().write_all(&data(200_000, 2)).unwrap();
+    comp.flush().unwrap();
+    let full_len = fs::metadata(&path).unwrap().len();
+    comp.remove_stream("/drop").unwrap();
+    let len = comp.shrink_to_fit().unwrap();
+    assert!(len < full_len);
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    comp.create_stream("/more").unwrap().write_all(&data(50_000, 3)).unwrap();
+    comp.flush().unwrap();
+    drop(comp);
+    let mut comp =
+        CompoundFile::open_strict(fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/keep"), data(10_000, 1));
+    assert_eq!(read_stream(&mut comp, "/more"), data(50_000, 3));
+}
+
+#[test]
+fn save_atomic() {
+    let dir = TempDir::new("save");
+    let path = dir.join("test.cfb");
+    let mut comp = cfb::create(&path).unwrap();
+    comp.create_stream("/keep").unwrap().write_all(&data(10_000, 1)).unwrap();
+    comp.create_stream("/drop").unwrap().write_all(&data(200_000, 2)).unwrap();
+    comp.create_stream("/tail").unwrap().write_all(&data(5_000, 3)).unwrap();
+    comp.flush().unwrap();
+    let full_len = fs::metadata(&path).unwrap().len();
+    comp.remove_stream("/drop").unwrap();
+
+    // Streams must be closed first.
+    let stream = comp.open_stream("/keep").unwrap();
+    let error = comp.save_atomic().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    drop(stream);
+    let mut stream = comp.open_stream("/tail").unwrap();
+    stream.write_all(&data(5_000, 3)[..100]).unwrap();
+    let error = comp.save_atomic().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(cfb::StreamsStillOpen::from_io_error(&error).is_some());
+    drop(stream);
+
+    let len = comp.save_atomic().unwrap();
+    assert!(len < full_len);
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    assert_eq!(dir.file_names(), ["test.cfb"]);
+
+    // The file keeps working, now backed by the new file on disk.
+    assert_eq!(comp.capabilities(), file_capabilities());
+    comp.create_stream("/new").unwrap().write_all(b"new").unwrap();
+    assert_eq!(read_stream(&mut comp, "/tail"), data(5_000, 3));
+    drop(comp);
+    let mut comp =
+        CompoundFile::open_strict(fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/keep"), data(10_000, 1));
+    assert_eq!(read_stream(&mut comp, "/tail"), data(5_000, 3));
+    assert_eq!(read_stream(&mut comp, "/new"), b"new");
+    assert!(!comp.exists("/drop"));
+}
+
+//===========================================================================//
@@ -0,0 +1,611 @@
+#![cfg(feature = "cli")]
+
+use cfb::tool::{unescape_name, FileEvents, WatchStatus, Watcher};
+use cfb::CompoundFile;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// A scratch directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "cfbtool-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn make_fixture(dir: &TempDir) -> PathBuf {
+    let path = dir.path().join("fixture.cfb");
+    let mut comp = cfb::create(&path).unwrap();
+    comp.create_storage("/dir").unwrap();
+    comp.create_stream("/hello").unwrap().write_all(b"Hello, world!").unwrap();
+    comp.create_stream("/dir/big").unwrap().write_all(&[7; 10000]).unwrap();
+    comp.flush().unwrap();
+    path
+}
+
+fn cfbtool(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_cfbtool"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "cfbtool {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+fn arg(comp_path: &Path, inner: &str) -> String {
+    format!("{}:{}", comp_path.display().to_string().replace(':', "::"), inner)
+}
+
+//===========================================================================//
+
+#[test]
+fn ls_lists_storage() {
+    let dir = TempDir::new("ls");
+    let comp_path = make_fixture(&dir);
+    let output = cfbtool(&["ls", &arg(&comp_path, "/")]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "dir\nhello\n");
+    let output = cfbtool(&["ls", "-l", &arg(&comp_path, "/dir")]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("-00000000"), "{}", stdout);
+    assert!(stdout.contains("10000 B"), "{}", stdout);
+    assert!(stdout.trim_end().ends_with("big"), "{}", stdout);
+}
+
+/// Creates a file whose object names contain control characters and a
+/// right-to-left override.
+fn make_odd_names_fixture(dir: &TempDir) -> PathBuf {
+    let path = dir.path().join("odd.cfb");
+    let mut comp = cfb::create(&path).unwrap();
+    comp.create_stream("/\u{5}SummaryInformation").unwrap();
+    comp.create_storage("/bell\u{7}").unwrap();
+    let mut stream = comp.create_stream("/bell\u{7}/line\nbreak").unwrap();
+    stream.write_all(&[1; 5000]).unwrap();
+    drop(stream);
+    comp.create_stream("/\u{202e}gpj.exe").unwrap();
+    comp.flush().unwrap();
+    path
+}
+
+#[test]
+fn ls_escapes_control_characters() {
+    let dir = TempDir::new("ls-escape");
+    let comp_path = make_odd_names_fixture(&dir);
+    let output = cfbtool(&["ls", &arg(&comp_path, "/")]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "bell\\u{7}\n\\u{202e}gpj.exe\n\\u{5}SummaryInformation\n"
+    );
+    let names: Vec<String> =
+        stdout.lines().map(|line| unescape_name(line).unwrap()).collect();
+    assert_eq!(
+        names,
+        ["bell\u{7}", "\u{202e}gpj.exe", "\u{5}SummaryInformation"]
+    );
+    let output = cfbtool(&["ls", &arg(&comp_path, "/bell\u{7}")]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "line\\u{a}break\n");
+}
+
+#[test]
+fn ls_raw_prints_names_as_stored() {
+    let dir = TempDir::new("ls-raw");
+    let comp_path = make_odd_names_fixture(&dir);
+    let output = cfbtool(&["ls", "--raw", &arg(&comp_path, "/")]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "bell\u{7}\n\u{202e}gpj.exe\n\u{5}SummaryInformation\n"
+    );
+    let output = cfbtool(&["--raw", "ls", &arg(&comp_path, "/bell\u{7}")]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "line\nbreak\n");
+}
+
+#[test]
+fn du_escapes_paths() {
+    let dir = TempDir::new("du-escape");
+    let comp_path = make_odd_names_fixture(&dir);
+    let output = cfbtool(&["du", "--bytes", &arg(&comp_path, "/")]);
+    let expected = [
+        "        5000          8192        3  /",
+        "        5000          8192        1  /bell\\u{7}",
+        "",
+    ];
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected.join("\n"));
+    let output = cfbtool(&["du", "--bytes", "--raw", &arg(&comp_path, "/")]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("  /bell\u{7}\n"), "{:?}", stdout);
+}
+
+#[test]
+fn cat_prints_streams() {
+    let dir = TempDir::new("cat");
+    let comp_path = make_fixture(&dir);
+    let output = cfbtool(&[
+        "cat",
+        &arg(&comp_path, "/hello"),
+        &arg(&comp_path, "/hello"),
+    ]);
+    assert_eq!(output.stdout, b"Hello, world!Hello, world!");
+}
+
+#[test]
+fn stat_describes_one_object() {
+    let dir = TempDir::new("stat");
+    let comp_path = make_fixture(&dir);
+    let output = cfbtool(&["stat", &arg(&comp_path, "/dir/big")]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("         path: /dir/big\n"), "{}", stdout);
+    assert!(stdout.contains("\n         size: 10000\n"), "{}", stdout);
+    assert!(
+        stdout.contains("\n      storage: regular, contiguous\n"),
+        "{}",
+        stdout
+    );
+    let output = cfbtool(&["stat", "--json", &arg(&comp_path, "/dir")]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("  \"kind\": \"storage\",\n"), "{}", stdout);
+    assert!(!stdout.contains("in_mini_stream"), "{}", stdout);
+    assert!(stdout.ends_with("  \"issues\": []\n}\n"), "{}", stdout);
+}
+
+#[test]
+fn put_round_trip() {
+    let dir = TempDir::new("put");
+    let comp_path = make_fixture(&dir);
+    let data: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+    let source = dir.path().join("data.bin");
+    fs::write(&source, &data).unwrap();
+    cfbtool(&["put", source.to_str().unwrap(), &arg(&comp_path, "/dir/data")]);
+    let output = cfbtool(&["cat", &arg(&comp_path, "/dir/data")]);
+    assert_eq!(output.stdout, data);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cfbtool"))
+        .args(["put", "-", &arg(&comp_path, "/hello")])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"replaced").unwrap();
+    assert!(child.wait().unwrap().success());
+
+    let mut comp =
+        CompoundFile::open_strict(fs::File::open(&comp_path).unwrap())
+            .unwrap();
+    let mut contents = Vec::new();
+    comp.open_stream("/hello").unwrap().read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, b"replaced");
+    assert_eq!(comp.entry("/dir/data").unwrap().len(), data.len() as u64);
+}
+
+#[test]
+fn dump_and_pack_round_trip() {
+    let dir = TempDir::new("pack");
+    let comp_path = make_fixture(&dir);
+    let mut comp = cfb::open_rw(&comp_path).unwrap();
+    comp.create_stream("/dir/odd\u{1}name")
+        .unwrap()
+        .write_all(b"odd")
+        .unwrap();
+    comp.flush().unwrap();
+    drop(comp);
+    let output = Command::new(env!("CARGO_BIN_EXE_cfbtool"))
+        .args(["dump", "--all", comp_path.to_str().unwrap()])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(dir.path().join("root/dir/odd_name.dump").is_file());
+
+    let packed_path = dir.path().join("packed.cfb");
+    cfb::create(&packed_path).unwrap().flush().unwrap();
+    let root = dir.path().join("root");
+    cfbtool(&["pack", root.to_str().unwrap(), &arg(&packed_path, "/")]);
+    let output = cfbtool(&["cat", &arg(&packed_path, "/dir/odd\u{1}name")]);
+    assert_eq!(output.stdout, b"odd");
+    let output = cfbtool(&["cat", &arg(&packed_path, "/hello")]);
+    assert_eq!(output.stdout, b"Hello, world!");
+}
+
+#[test]
+fn pack_plain_directory_into_new_file() {
+    let dir = TempDir::new("pack-plain");
+    let root = dir.path().join("tree");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("sub/data.dump"), b"data").unwrap();
+    fs::write(root.join("bad:name"), b"bad").unwrap();
+
+    let packed_path = dir.path().join("new.cfb");
+    let output = cfbtool_unchecked(&[
+        "pack",
+        root.to_str().unwrap(),
+        &arg(&packed_path, "/"),
+    ]);
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("cfbtool: skipped"), "{}", stderr);
+    assert!(stderr.contains("bad:name"), "{}", stderr);
+    let output = cfbtool(&["cat", &arg(&packed_path, "/sub/data")]);
+    assert_eq!(output.stdout, b"data");
+}
+
+#[test]
+fn missing_stream_fails_cleanly() {
+    let dir = TempDir::new("missing");
+    let comp_path = make_fixture(&dir);
+    let output = Command::new(env!("CARGO_BIN_EXE_cfbtool"))
+        .args(["cat", &arg(&comp_path, "/nope")])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("cfbtool: No such stream"), "{}", stderr);
+}
+
+/// Runs cfbtool without checking that it succeeded.
+fn cfbtool_unchecked(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cfbtool")).args(args).output().unwrap()
+}
+
+fn storage_clsid(comp_path: &Path, path: &str) -> Uuid {
+    *cfb::open(comp_path).unwrap().entry(path).unwrap().clsid()
+}
+
+const CLSID_A: &str = "01234567-89ab-cdef-0123-456789abcdef";
+const CLSID_B: &str = "fedcba98-7654-3210-fedc-ba9876543210";
+
+#[test]
+fn chcls_get_set_and_clear() {
+    let dir = TempDir::new("chcls");
+    let comp_path = make_fixture(&dir);
+    let root = arg(&comp_path, "/");
+    let storage = arg(&comp_path, "/dir");
+
+    let output = cfbtool_unchecked(&["chcls", "--get", &storage]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"00000000-0000-0000-0000-000000000000\n");
+
+    cfbtool(&["chcls", CLSID_A, &storage, &root]);
+    let output = cfbtool(&["chcls", "--get", &storage, &root]);
+    let expected = format!("{}\n{}\n", CLSID_A, CLSID_A);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    assert_eq!(
+        storage_clsid(&comp_path, "/dir"),
+        Uuid::parse_str(CLSID_A).unwrap()
+    );
+
+    cfbtool(&["chcls", "--clear", &storage]);
+    assert!(storage_clsid(&comp_path, "/dir").is_nil());
+    assert!(!storage_clsid(&comp_path, "/").is_nil());
+    let output = cfbtool_unchecked(&["chcls", "--get", &root, &storage]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let output =
+        cfbtool_unchecked(&["chcls", "--get", &arg(&comp_path, "/hello")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("cfbtool: Not a storage"), "{}", stderr);
+}
+
+#[test]
+fn chcls_if_match_makes_change_conditional() {
+    let dir = TempDir::new("chcls-if-match");
+    let comp_path = make_fixture(&dir);
+    let root = arg(&comp_path, "/");
+    let storage = arg(&comp_path, "/dir");
+    let nil = Uuid::nil().to_string();
+
+    // Both storages match, so both are changed.
+    cfbtool(&["chcls", "--if-match", &nil, CLSID_A, &storage, &root]);
+    assert_eq!(
+        storage_clsid(&comp_path, "/"),
+        Uuid::parse_str(CLSID_A).unwrap()
+    );
+
+    // Running the same command again fails without changing anything.
+    let before = fs::read(&comp_path).unwrap();
+    let output = cfbtool_unchecked(&[
+        "chcls",
+        "--if-match",
+        &nil,
+        CLSID_B,
+        &storage,
+        &root,
+    ]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&format!("CLSID is {}", CLSID_A)), "{}", stderr);
+    assert_eq!(fs::read(&comp_path).unwrap(), before);
+
+    // If only one storage matches, neither is changed.
+    cfbtool(&["chcls", "--clear", &storage]);
+    let output = cfbtool_unchecked(&[
+        "chcls",
+        "--if-match",
+        CLSID_A,
+        CLSID_B,
+        &root,
+        &storage,
+    ]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(
+        storage_clsid(&comp_path, "/"),
+        Uuid::parse_str(CLSID_A).unwrap()
+    );
+
+    cfbtool(&["chcls", "--if-match", CLSID_A, "--clear", &root]);
+    assert!(storage_clsid(&comp_path, "/").is_nil());
+}
+
+#[test]
+fn sanitize_wipes_removed_data_and_times() {
+    let dir = TempDir::new("sanitize");
+    let comp_path = make_fixture(&dir);
+    let time = std::time::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    {
+        let mut comp = cfb::open_rw(&comp_path).unwrap();
+        comp.set_modified_time("/dir", time).unwrap();
+        let secret = b"REMOVEDSECRET".repeat(400);
+        comp.create_stream("/gone").unwrap().write_all(&secret).unwrap();
+        comp.remove_stream("/gone").unwrap();
+        comp.flush().unwrap();
+    }
+    let contains_secret = |path: &Path| {
+        fs::read(path).unwrap().windows(13).any(|w| w == b"REMOVEDSECRET")
+    };
+    assert!(contains_secret(&comp_path));
+
+    let path = comp_path.to_str().unwrap();
+    let output = cfbtool(&["sanitize", "--keep-times", path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("timestamps cleared: 0\n"), "{}", stdout);
+    assert!(!contains_secret(&comp_path));
+    let comp = cfb::open(&comp_path).unwrap();
+    assert_eq!(comp.entry("/dir").unwrap().modified(), time);
+
+    let output = cfbtool(&["sanitize", "--keep-properties", path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("timestamps cleared: 1\n"), "{}", stdout);
+    let mut comp =
+        CompoundFile::open_strict(fs::File::open(&comp_path).unwrap())
+            .unwrap();
+    assert_ne!(comp.entry("/dir").unwrap().modified(), time);
+    let mut data = Vec::new();
+    comp.open_stream("/dir/big").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![7; 10000]);
+}
+
+#[test]
+fn verify_deep_isolates_unreadable_stream() {
+    let dir = TempDir::new("verify");
+    let comp_path = make_fixture(&dir);
+    let path = comp_path.to_str().unwrap();
+    let output = cfbtool(&["verify", "--deep", "--hash", path]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" /dir/big\n"), "{}", stdout);
+    assert!(stdout.contains("2 streams, 10013 bytes read"), "{}", stdout);
+
+    // Point "/dir/big" at a sector past the end of the file.
+    let mut data = fs::read(&comp_path).unwrap();
+    let name: Vec<u8> =
+        "big\0".encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let offset = data.windows(8).position(|w| w == name.as_slice()).unwrap();
+    data[(offset + 116)..(offset + 120)]
+        .copy_from_slice(&1000u32.to_le_bytes());
+    fs::write(&comp_path, data).unwrap();
+    let output = cfbtool_unchecked(&["verify", "--deep", path]);
+    assert_eq!(output.status.code(), Some(5));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("FAILED /dir/big (0 of 10000"), "{}", stdout);
+    assert!(!stdout.contains("FAILED /hello"), "{}", stdout);
+    assert!(stdout.contains("2 streams, 13 bytes read"), "{}", stdout);
+}
+
+//===========================================================================//
+
+/// How long to wait for `watch` to notice a change before giving up.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Kills the child process when dropped, so that a failed test doesn't
+/// leave `cfbtool watch` running.
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Returns a channel that receives each line the child prints to stdout.
+fn stdout_lines(child: &mut Child) -> Receiver<String> {
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdout.lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Waits for the next `count` lines, and returns them sorted.
+fn next_lines(lines: &Receiver<String>, count: usize) -> Vec<String> {
+    let mut result: Vec<String> = (0..count)
+        .map(|_| lines.recv_timeout(WATCH_TIMEOUT).unwrap())
+        .collect();
+    result.sort();
+    result
+}
+
+/// Runs `cfbtool watch` with the given extra arguments, and checks that it
+/// extracts the file's streams and then re-extracts the ones that change.
+fn check_watch_reextracts(name: &str, extra_args: &[&str]) {
+    let dir = TempDir::new(name);
+    let comp_path = make_fixture(&dir);
+    let output_dir = dir.path().join("out");
+    let mut child = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_cfbtool"))
+            .args(["watch", comp_path.to_str().unwrap(), "--debounce", "50"])
+            .args(extra_args)
+            .arg("--output")
+            .arg(&output_dir)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let lines = stdout_lines(&mut child.0);
+    assert_eq!(next_lines(&lines, 2), ["A  /dir/big", "A  /hello"]);
+    let hello = output_dir.join("hello.dump");
+    assert_eq!(fs::read(&hello).unwrap(), b"Hello, world!");
+    assert_eq!(
+        fs::read(output_dir.join("dir/big.dump")).unwrap().len(),
+        10000
+    );
+
+    let mut comp = cfb::open_rw(&comp_path).unwrap();
+    comp.create_stream("/hello").unwrap().write_all(b"Hello, WORLD!").unwrap();
+    comp.create_stream("/new").unwrap().write_all(b"new").unwrap();
+    comp.remove_stream("/dir/big").unwrap();
+    comp.flush().unwrap();
+    drop(comp);
+    assert_eq!(next_lines(&lines, 3), ["A  /new", "D  /dir/big", "M  /hello"]);
+    assert_eq!(fs::read(&hello).unwrap(), b"Hello, WORLD!");
+    assert_eq!(fs::read(output_dir.join("new.dump")).unwrap(), b"new");
+    assert!(!output_dir.join("dir/big.dump").exists());
+}
+
+#[test]
+fn watch_reextracts_changed_streams() {
+    check_watch_reextracts("watch", &[]);
+}
+
+#[test]
+fn watch_reextracts_changed_streams_when_polling() {
+    check_watch_reextracts("watch-poll", &["--poll"]);
+}
+
+#[test]
+fn file_events_report_changes_to_the_file() {
+    let dir = TempDir::new("file-events");
+    let comp_path = make_fixture(&dir);
+    let events = FileEvents::new(&comp_path).unwrap();
+    assert!(!events.wait(Some(Duration::from_millis(50))));
+
+    // Replacing the file, as many programs do when saving, is noticed.
+    let temp_path = dir.path().join("replacement.tmp");
+    fs::write(&temp_path, fs::read(&comp_path).unwrap()).unwrap();
+    fs::rename(&temp_path, &comp_path).unwrap();
+    assert!(events.wait(Some(WATCH_TIMEOUT)));
+    thread::sleep(Duration::from_millis(100));
+    events.wait(Some(Duration::ZERO));
+
+    // Changes to other files in the same directory are not.
+    fs::write(dir.path().join("other.txt"), b"other").unwrap();
+    assert!(!events.wait(Some(Duration::from_millis(300))));
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&comp_path)
+        .unwrap()
+        .write_all(b"more")
+        .unwrap();
+    assert!(events.wait(Some(WATCH_TIMEOUT)));
+}
+
+#[test]
+fn watch_filter_limits_extracted_streams() {
+    let dir = TempDir::new("watch-filter");
+    let comp_path = make_fixture(&dir);
+    let output_dir = dir.path().join("out");
+    fs::create_dir(&output_dir).unwrap();
+    let mut watcher = Watcher::new(
+        &comp_path,
+        &output_dir,
+        Some("/dir/*".to_string()),
+        Duration::ZERO,
+    );
+    let changes = wait_for_extraction(&mut watcher);
+    assert_eq!(changes.added, [PathBuf::from("/dir/big")]);
+    assert!(!output_dir.join("hello.dump").exists());
+}
+
+#[test]
+fn watch_retries_after_truncated_save() {
+    let dir = TempDir::new("watch-truncated");
+    let comp_path = make_fixture(&dir);
+    let output_dir = dir.path().join("out");
+    fs::create_dir(&output_dir).unwrap();
+    let mut watcher =
+        Watcher::new(&comp_path, &output_dir, None, Duration::ZERO);
+    wait_for_extraction(&mut watcher);
+
+    // Simulate catching a save halfway through: first the file is cut
+    // short, and only later is the rest written.
+    let mut comp = cfb::open_rw(&comp_path).unwrap();
+    comp.create_stream("/hello").unwrap().write_all(b"Goodbye!").unwrap();
+    comp.flush().unwrap();
+    drop(comp);
+    let data = fs::read(&comp_path).unwrap();
+    fs::write(&comp_path, &data[..300]).unwrap();
+    let deadline = Instant::now() + WATCH_TIMEOUT;
+    loop {
+        match watcher.poll().unwrap() {
+            WatchStatus::Unreadable(_) => break,
+            WatchStatus::Extracted(changes) => panic!("{:?}", changes),
+            _ => assert!(Instant::now() < deadline),
+        }
+    }
+    assert_eq!(
+        fs::read(output_dir.join("hello.dump")).unwrap(),
+        b"Hello, world!"
+    );
+
+    fs::write(&comp_path, &data).unwrap();
+    let changes = wait_for_extraction(&mut watcher);
+    assert_eq!(changes.modified, [PathBuf::from("/hello")]);
+    assert!(changes.added.is_empty() && changes.removed.is_empty());
+    assert_eq!(fs::read(output_dir.join("hello.dump")).unwrap(), b"Goodbye!");
+}
+
+fn wait_for_extraction(watcher: &mut Watcher) -> cfb::tool::WatchChanges {
+    let deadline = Instant::now() + WATCH_TIMEOUT;
+    loop {
+        if let WatchStatus::Extracted(changes) = watcher.poll().unwrap() {
+            return changes;
+        }
+        assert!(Instant::now() < deadline, "watcher never extracted");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,96 @@
+use cfb::{ClsidPolicy, CompoundFile};
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const STORAGES: [&str; 4] = ["/a", "/a/b", "/a/b/c", "/d"];
+
+/// Builds the same small file each time, with fixed timestamps, so that the
+/// only thing that can differ between builds is the storages' CLSIDs.
+fn build(policy: ClsidPolicy) -> Vec<u8> {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.set_clsid_policy(policy);
+    comp.create_storage_all("/a/b/c").unwrap();
+    comp.create_storage("/d").unwrap();
+    comp.create_stream("/a/b/stream").unwrap().write_all(b"data").unwrap();
+    let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    for path in ["/"].iter().chain(STORAGES.iter()) {
+        comp.set_created_time(path, time).unwrap();
+        comp.set_modified_time(path, time).unwrap();
+    }
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn clsids(data: Vec<u8>) -> Vec<Uuid> {
+    let comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    STORAGES.iter().map(|path| *comp.entry(path).unwrap().clsid()).collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn null_by_default() {
+    let comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    assert!(matches!(comp.clsid_policy(), ClsidPolicy::Null));
+    let clsids = clsids(build(ClsidPolicy::Null));
+    assert!(clsids.iter().all(Uuid::is_nil));
+}
+
+#[test]
+fn derive_v5_is_reproducible() {
+    let namespace = Uuid::NAMESPACE_URL;
+    let first = build(ClsidPolicy::DeriveV5 { namespace });
+    let second = build(ClsidPolicy::DeriveV5 { namespace });
+    assert_eq!(first, second);
+    let clsids = clsids(first);
+    for (path, clsid) in STORAGES.iter().zip(clsids.iter()) {
+        assert_eq!(*clsid, Uuid::new_v5(&namespace, path.as_bytes()));
+    }
+}
+
+#[test]
+fn random_gives_distinct_clsids() {
+    let first = clsids(build(ClsidPolicy::Random));
+    let second = clsids(build(ClsidPolicy::Random));
+    for clsid in first.iter().chain(second.iter()) {
+        assert_eq!(clsid.get_version_num(), 4);
+    }
+    let mut all: Vec<Uuid> = first.into_iter().chain(second).collect();
+    all.sort();
+    all.dedup();
+    assert_eq!(all.len(), 2 * STORAGES.len());
+}
+
+#[test]
+fn custom_is_called_with_full_paths() {
+    let clsids =
+        clsids(build(ClsidPolicy::Custom(Box::new(|path: &Path| {
+            let depth = path.components().count() as u128;
+            Uuid::from_u128(depth)
+        }))));
+    // Each path's components include the root.
+    let expected: Vec<Uuid> =
+        [2, 3, 4, 2].iter().map(|&depth| Uuid::from_u128(depth)).collect();
+    assert_eq!(clsids, expected);
+}
+
+#[test]
+fn copies_keep_their_clsids() {
+    let source_clsid = Uuid::from_u128(0x1234);
+    let mut source = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    source.create_storage("/src").unwrap();
+    source.set_storage_clsid("/src", source_clsid).unwrap();
+    let mut dest = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    dest.set_clsid_policy(ClsidPolicy::Random);
+    dest.copy_storage_from(&mut source, "/src", "/dest", false).unwrap();
+    assert_eq!(*dest.entry("/dest").unwrap().clsid(), source_clsid);
+    // The policy still applies to storages created afterwards.
+    dest.create_storage("/new").unwrap();
+    assert_eq!(dest.entry("/new").unwrap().clsid().get_version_num(), 4);
+}
+
+//===========================================================================//
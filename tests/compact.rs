@@ -0,0 +1,313 @@
+use cfb::{Capabilities, CompoundFile, ValidationIssueKind, Version};
+use std::fs;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+/// Each object's path, CLSID, state bits, timestamps, and data (for
+/// streams).
+type Snapshot = Vec<(PathBuf, Uuid, u32, SystemTime, SystemTime, Vec<u8>)>;
+
+/// A scratch directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> TempDir {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "cfb-compact-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(29).wrapping_add(seed)).collect()
+}
+
+fn write_stream<F: Read + Write + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+    data: &[u8],
+) {
+    comp.create_stream(path).unwrap().write_all(data).unwrap();
+}
+
+fn snapshot<F: Read + Seek>(comp: &mut CompoundFile<F>) -> Snapshot {
+    let entries: Vec<_> = comp.walk().collect();
+    entries
+        .into_iter()
+        .map(|entry| {
+            let mut data = Vec::new();
+            if entry.is_stream() {
+                comp.open_stream(entry.path())
+                    .unwrap()
+                    .read_to_end(&mut data)
+                    .unwrap();
+            }
+            (
+                entry.path().to_path_buf(),
+                *entry.clsid(),
+                entry.state_bits(),
+                entry.created(),
+                entry.modified(),
+                data,
+            )
+        })
+        .collect()
+}
+
+/// Returns the name stored in each directory entry, by stream ID.
+fn entry_names<F: Read + Seek>(comp: &mut CompoundFile<F>) -> Vec<Vec<u8>> {
+    comp.raw_dir_entries().map(|raw| raw.unwrap().1[..64].to_vec()).collect()
+}
+
+/// Writes the file out to disk, compacts it there (which truncates it to
+/// its new length), and checks that its contents and stream IDs are
+/// unchanged, both before and after reopening it strictly, and that a copy
+/// of it can then take a large new stream.  Returns the compacted file, read
+/// back into memory, and its length.
+fn compact_and_reopen(mut comp: TestFile) -> (TestFile, u64) {
+    let before = snapshot(&mut comp);
+    let names = entry_names(&mut comp);
+    comp.flush().unwrap();
+    let dir = TempDir::new();
+    let path = dir.join("test.cfb");
+    fs::write(&path, comp.into_inner().into_inner()).unwrap();
+    let mut comp = cfb::open_rw(&path).unwrap();
+    assert!(comp.capabilities().contains(Capabilities::TRUNCATABLE));
+    let len = comp.compact().unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    assert_eq!(snapshot(&mut comp), before);
+    drop(comp);
+
+    let cursor = Cursor::new(fs::read(&path).unwrap());
+    let mut grown = CompoundFile::open_strict(cursor).unwrap();
+    write_stream(&mut grown, "/grown", &data(100_000, 9));
+    grown.flush().unwrap();
+    let mut grown = CompoundFile::open_strict(grown.into_inner()).unwrap();
+    let mut grown_data = Vec::new();
+    grown.open_stream("/grown").unwrap().read_to_end(&mut grown_data).unwrap();
+    assert_eq!(grown_data, data(100_000, 9));
+
+    let cursor = Cursor::new(fs::read(&path).unwrap());
+    let mut comp = CompoundFile::open_strict(cursor).unwrap();
+    assert_eq!(snapshot(&mut comp), before);
+    assert_eq!(entry_names(&mut comp)[..names.len()], names[..]);
+    (comp, len)
+}
+
+/// Creates a file with a mix of small and large streams in a few storages,
+/// then removes and rewrites enough of them to leave free sectors and free
+/// mini sectors scattered all over.  Returns the flushed file and its
+/// length.
+fn churned_file(version: Version) -> (TestFile, u64) {
+    let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.set_storage_clsid("/", Uuid::from_u128(1)).unwrap();
+    comp.create_storage("/a").unwrap();
+    comp.create_storage("/a/b").unwrap();
+    comp.set_storage_clsid("/a", Uuid::from_u128(2)).unwrap();
+    comp.set_state_bits("/a", 0x1234).unwrap();
+    for index in 0..40 {
+        let len = if index % 3 == 0 { 9000 + index * 100 } else { 50 * index };
+        let dir = ["/", "/a/", "/a/b/"][index % 3];
+        let path = format!("{}s{}", dir, index);
+        write_stream(&mut comp, &path, &data(len, index as u8));
+    }
+    for index in (0..40).step_by(2) {
+        let dir = ["/", "/a/", "/a/b/"][index % 3];
+        comp.remove_stream(format!("{}s{}", dir, index)).unwrap();
+    }
+    for index in (1..40).step_by(4) {
+        let dir = ["/", "/a/", "/a/b/"][index % 3];
+        let path = format!("{}s{}", dir, index);
+        let mut stream = comp.open_stream(&path).unwrap();
+        stream.set_len(10).unwrap();
+        stream.seek(SeekFrom::End(0)).unwrap();
+        stream.write_all(b"rewritten").unwrap();
+    }
+    comp.set_modified_time("/a/b", time).unwrap();
+    comp.set_created_time("/a/b", time).unwrap();
+    comp.set_state_bits("/s3", 7).unwrap();
+    comp.flush().unwrap();
+    let cursor = comp.into_inner();
+    let len = cursor.get_ref().len() as u64;
+    (CompoundFile::open(cursor).unwrap(), len)
+}
+
+//===========================================================================//
+
+#[test]
+fn compact_keeps_contents_and_shrinks_file() {
+    for version in [Version::V3, Version::V4] {
+        let (comp, old_len) = churned_file(version);
+        let estimate = comp.compact_estimate();
+        assert!(estimate > 0);
+        let stats = comp.stats().unwrap();
+        assert!(stats.num_free_sectors() > 0);
+        assert!(stats.num_free_mini_sectors() > 0);
+        assert!(stats.num_fragments() > 0);
+
+        let (comp, new_len) = compact_and_reopen(comp);
+        assert_eq!(new_len, old_len - estimate);
+        assert_eq!(comp.compact_estimate(), 0);
+        let stats = comp.stats().unwrap();
+        assert_eq!(stats.num_free_sectors(), 0);
+        assert_eq!(stats.num_free_mini_sectors(), 0);
+        assert_eq!(stats.num_fragments(), 0);
+        assert_eq!(stats.metadata_spread(), 0);
+        assert_eq!(
+            new_len,
+            (stats.num_sectors() as u64 + 1) * version.sector_len() as u64
+        );
+
+        // Compacting again changes nothing.
+        let before = comp.into_inner().into_inner();
+        let mut comp =
+            CompoundFile::open(Cursor::new(before.clone())).unwrap();
+        assert_eq!(comp.compact().unwrap(), new_len);
+        assert_eq!(comp.into_inner().into_inner(), before);
+    }
+}
+
+#[test]
+fn compacted_file_stays_usable() {
+    let (comp, _) = churned_file(Version::V3);
+    let dir = TempDir::new();
+    let path = dir.join("test.cfb");
+    fs::write(&path, comp.into_inner().into_inner()).unwrap();
+    let mut comp = cfb::open_rw(&path).unwrap();
+    comp.set_self_check(true);
+    let len = comp.compact().unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), len);
+    // Without reopening, the file can be read and written, and the changes
+    // survive a round trip.
+    write_stream(&mut comp, "/new", &data(5000, 1));
+    write_stream(&mut comp, "/big", &data(100_000, 3));
+    write_stream(&mut comp, "/a/small", &data(100, 2));
+    comp.remove_stream("/s3").unwrap();
+    let before = snapshot(&mut comp);
+    comp.flush().unwrap();
+    drop(comp);
+    let mut comp =
+        CompoundFile::open_strict(fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(snapshot(&mut comp), before);
+}
+
+#[test]
+fn compact_without_truncating() {
+    // An in-memory file can't be truncated, so compacting it leaves the old
+    // data past the new length in place, for the caller to discard.
+    let (mut comp, old_len) = churned_file(Version::V3);
+    assert!(!comp.capabilities().contains(Capabilities::TRUNCATABLE));
+    let before = snapshot(&mut comp);
+    let len = comp.compact().unwrap();
+    assert!(len < old_len);
+    let mut cursor = comp.into_inner();
+    assert_eq!(cursor.get_ref().len() as u64, old_len);
+    cursor.get_mut().truncate(len as usize);
+    let mut comp = CompoundFile::open_strict(cursor).unwrap();
+    assert_eq!(snapshot(&mut comp), before);
+    write_stream(&mut comp, "/big", &data(100_000, 3));
+    comp.flush().unwrap();
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let mut big = Vec::new();
+    comp.open_stream("/big").unwrap().read_to_end(&mut big).unwrap();
+    assert_eq!(big, data(100_000, 3));
+}
+
+#[test]
+fn untruncated_tail_is_ignored_on_reopen() {
+    let (mut comp, old_len) = churned_file(Version::V3);
+    comp.remove_stream("/s3").unwrap();
+    let len = comp.compact().unwrap();
+    assert!(len < old_len);
+    let cursor = comp.into_inner();
+    assert_eq!(cursor.get_ref().len() as u64, old_len);
+    let before = cursor.get_ref().clone();
+
+    // The stale tail is past what the compacted FAT can describe, which
+    // strict validation rejects.
+    let error = CompoundFile::open_strict(cursor).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    // Permissive validation ignores the tail, with a warning, and the file
+    // can grow over it.
+    let mut comp = CompoundFile::open(Cursor::new(before)).unwrap();
+    let kinds: Vec<_> =
+        comp.open_warnings().iter().map(|issue| issue.kind()).collect();
+    assert_eq!(kinds, vec![ValidationIssueKind::SectorsPastFat]);
+    write_stream(&mut comp, "/big", &data(100_000, 3));
+    comp.flush().unwrap();
+    let mut comp = CompoundFile::open(comp.into_inner()).unwrap();
+    let mut big = Vec::new();
+    comp.open_stream("/big").unwrap().read_to_end(&mut big).unwrap();
+    assert_eq!(big, data(100_000, 3));
+}
+
+#[test]
+fn compact_keeps_shared_chains_shared() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    write_stream(&mut comp, "/filler", &data(20000, 0));
+    comp.create_stream_dedup("/big1", &data(10000, 1)).unwrap();
+    comp.create_stream_dedup("/big2", &data(10000, 1)).unwrap();
+    comp.create_stream_dedup("/small1", &data(100, 2)).unwrap();
+    comp.create_stream_dedup("/small2", &data(100, 2)).unwrap();
+    comp.remove_stream("/filler").unwrap();
+    let (comp, len) = compact_and_reopen(comp);
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 4);
+    // Header, FAT, two directory sectors (for six entries), MiniFAT, mini
+    // stream, and one copy of the large data.
+    assert_eq!(len, 512 * (6 + 10000_u64.div_ceil(512)));
+}
+
+#[test]
+fn compact_fails_with_open_streams() {
+    let (mut comp, _) = churned_file(Version::V3);
+    let before = snapshot(&mut comp);
+    let stream = comp.open_stream("/s3").unwrap();
+    let error = comp.compact().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    drop(stream);
+    assert_eq!(snapshot(&mut comp), before);
+    compact_and_reopen(comp);
+}
+
+#[test]
+fn compact_empty_file() {
+    for version in [Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let comp = CompoundFile::create_with_version(version, cursor).unwrap();
+        assert_eq!(comp.compact_estimate(), 0);
+        let (comp, len) = compact_and_reopen(comp);
+        // Header, FAT, and directory.
+        assert_eq!(len, 3 * version.sector_len() as u64);
+        assert_eq!(comp.walk().count(), 1);
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,131 @@
+#![cfg(feature = "compat")]
+
+use cfb::compat::CompatFile;
+use cfb::CompoundFile;
+use std::io::{self, Cursor, ErrorKind, Write};
+use std::path::Path;
+
+//===========================================================================//
+
+/// Builds a small document in the shape that the other crates' examples
+/// work with: a few streams in the root, and embedded objects in nested
+/// storages.
+fn make_fixture() -> CompatFile<Cursor<Vec<u8>>> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.create_stream("/WordDocument").unwrap().write_all(&[1; 600]).unwrap();
+    comp.create_stream("/\u{5}SummaryInformation")
+        .unwrap()
+        .write_all(b"summary")
+        .unwrap();
+    comp.create_storage("/ObjectPool").unwrap();
+    comp.create_storage("/ObjectPool/_1234").unwrap();
+    comp.create_stream("/ObjectPool/_1234/\u{1}Ole")
+        .unwrap()
+        .write_all(b"embedded")
+        .unwrap();
+    comp.create_stream("/ObjectPool/_1234/CONTENTS").unwrap();
+    comp.flush().unwrap();
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    CompatFile::new(comp)
+}
+
+//===========================================================================//
+
+#[test]
+fn iterate_entries_as_results() {
+    // Iterate over every entry, propagating errors with `?`, and print a
+    // one-line summary of each.
+    fn summarize<F>(file: &CompatFile<F>) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        for entry in file.entries() {
+            let entry = entry?;
+            lines.push(format!("{} {}", entry.path().display(), entry.len()));
+        }
+        Ok(lines)
+    }
+    let file = make_fixture();
+    let lines = summarize(&file).unwrap();
+    assert_eq!(lines.len(), 7);
+    assert_eq!(lines[0], "/ 0");
+    assert!(lines.contains(&"/WordDocument 600".to_string()));
+    assert!(lines.contains(&"/ObjectPool/_1234/\u{1}Ole 8".to_string()));
+}
+
+#[test]
+fn navigate_through_storage_handles() {
+    // Walk down to an embedded object one storage at a time, then read one of
+    // its streams by name.
+    let mut file = make_fixture();
+    let mut pool = file.open_storage("/ObjectPool").unwrap();
+    assert_eq!(pool.path(), Path::new("/ObjectPool"));
+    let mut object = pool.open_storage("_1234").unwrap();
+    assert_eq!(object.path(), Path::new("/ObjectPool/_1234"));
+    assert!(object.entry().unwrap().is_storage());
+    assert_eq!(object.read_stream("\u{1}Ole").unwrap(), b"embedded");
+    let children: Vec<_> = object
+        .entries()
+        .unwrap()
+        .map(|entry| entry.map(|entry| entry.len()))
+        .collect::<io::Result<_>>()
+        .unwrap();
+    assert_eq!(children.len(), 2);
+
+    let mut root = file.root_storage();
+    assert!(root.entry().unwrap().is_root());
+    assert_eq!(root.read_stream("WordDocument").unwrap(), vec![1; 600]);
+    let mut pool = root.open_storage("ObjectPool").unwrap();
+    assert!(pool.open_storage("_1234").is_ok());
+}
+
+#[test]
+fn list_owned_names() {
+    // Collect the children's names into a `Vec<String>` that outlives the
+    // compound file.
+    let names = {
+        let mut file = make_fixture();
+        let mut names = file.list_names("/").unwrap();
+        names.sort();
+        let object = file.open_storage("/ObjectPool/_1234").unwrap();
+        let mut object_names = object.list_names().unwrap();
+        object_names.sort();
+        assert_eq!(object_names, vec!["\u{1}Ole", "CONTENTS"]);
+        names
+    };
+    assert_eq!(
+        names,
+        vec!["\u{5}SummaryInformation", "ObjectPool", "WordDocument"]
+    );
+}
+
+#[test]
+fn handle_errors_match_compound_file() {
+    let mut file = make_fixture();
+    let error = file.open_storage("/Missing").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = file.open_storage("/WordDocument").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = file.list_names("/WordDocument").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    let mut root = file.root_storage();
+    // Handles open children by name only, not by path.
+    let error = root.read_stream("ObjectPool/_1234/CONTENTS").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = root.open_stream("ObjectPool").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = root.open_stream("Missing").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn full_api_stays_reachable() {
+    let mut file = make_fixture();
+    file.inner_mut().create_stream("/Added").unwrap().write_all(b"x").unwrap();
+    file.open_stream("/Added").unwrap().write_all(b"y").unwrap();
+    assert_eq!(file.read_stream("/Added").unwrap(), b"y");
+    let comp = file.into_inner();
+    assert!(comp.is_stream("/Added"));
+}
+
+//===========================================================================//
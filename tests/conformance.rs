@@ -0,0 +1,1496 @@
+//! Conformance tests organized by clause of the [MS-CFB] specification.  Each
+//! test name starts with the clause it exercises (e.g. `s2_2_...` for section
+//! 2.2, "Compound File Header").  Tests that build files with the crate check
+//! the raw bytes that were written; tests that patch raw bytes check how the
+//! crate reads files that violate the clause.
+//!
+//! [MS-CFB]: https://msdn.microsoft.com/en-us/library/dd942138.aspx
+
+use cfb::{CompoundFile, CreateOptions, ValidationIssueKind, Version};
+use std::io::{Cursor, Read, Write};
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const HEADER_LEN: usize = 512;
+const DIR_ENTRY_LEN: usize = 128;
+const NUM_HEADER_DIFAT_ENTRIES: usize = 109;
+
+const MAX_REGULAR_SECTOR: u32 = 0xfffffffa;
+const INVALID_SECTOR: u32 = 0xfffffffb;
+const DIFAT_SECTOR: u32 = 0xfffffffc;
+const FAT_SECTOR: u32 = 0xfffffffd;
+const END_OF_CHAIN: u32 = 0xfffffffe;
+const FREE_SECTOR: u32 = 0xffffffff;
+const NO_STREAM: u32 = 0xffffffff;
+
+const OBJ_TYPE_UNALLOCATED: u8 = 0;
+const OBJ_TYPE_STORAGE: u8 = 1;
+const OBJ_TYPE_STREAM: u8 = 2;
+const OBJ_TYPE_ROOT: u8 = 5;
+
+const COLOR_RED: u8 = 0;
+const COLOR_BLACK: u8 = 1;
+
+const SIGNATURE: [u8; 8] = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+
+const SMALL_DATA: [u8; 100] = [1; 100];
+const LARGE_LEN: usize = 10000;
+
+//===========================================================================//
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn sector_len(data: &[u8]) -> usize {
+    1 << u16_at(data, 30)
+}
+
+fn sector_offset(data: &[u8], sector: u32) -> usize {
+    (sector as usize + 1) * sector_len(data)
+}
+
+fn num_sectors(data: &[u8]) -> usize {
+    data.len() / sector_len(data) - 1
+}
+
+/// Returns the DIFAT sector IDs (in chain order) and the full list of FAT
+/// sector IDs, as found by following the DIFAT from the header.
+fn difat(data: &[u8]) -> (Vec<u32>, Vec<u32>) {
+    let num_fat_sectors = u32_at(data, 44) as usize;
+    let mut fat_sectors: Vec<u32> = (0..NUM_HEADER_DIFAT_ENTRIES)
+        .map(|index| u32_at(data, 76 + 4 * index))
+        .collect();
+    let mut difat_sectors = Vec::new();
+    let mut next = u32_at(data, 68);
+    let entries_per_sector = sector_len(data) / 4 - 1;
+    while next <= MAX_REGULAR_SECTOR {
+        difat_sectors.push(next);
+        let offset = sector_offset(data, next);
+        for index in 0..entries_per_sector {
+            fat_sectors.push(u32_at(data, offset + 4 * index));
+        }
+        next = u32_at(data, offset + 4 * entries_per_sector);
+    }
+    fat_sectors.truncate(num_fat_sectors);
+    (difat_sectors, fat_sectors)
+}
+
+fn fat(data: &[u8]) -> Vec<u32> {
+    let entries_per_sector = sector_len(data) / 4;
+    let mut fat = Vec::new();
+    for sector in difat(data).1 {
+        let offset = sector_offset(data, sector);
+        for index in 0..entries_per_sector {
+            fat.push(u32_at(data, offset + 4 * index));
+        }
+    }
+    fat
+}
+
+/// Returns the file offset of the FAT entry for the given sector.
+fn fat_entry_offset(data: &[u8], sector: u32) -> usize {
+    let entries_per_sector = sector_len(data) / 4;
+    let fat_sectors = difat(data).1;
+    let fat_sector = fat_sectors[sector as usize / entries_per_sector];
+    sector_offset(data, fat_sector)
+        + 4 * (sector as usize % entries_per_sector)
+}
+
+fn minifat_entry_offset(data: &[u8], mini_sector: u32) -> usize {
+    let entries_per_sector = sector_len(data) / 4;
+    let minifat_sectors = chain(&fat(data), u32_at(data, 60));
+    let minifat_sector =
+        minifat_sectors[mini_sector as usize / entries_per_sector];
+    sector_offset(data, minifat_sector)
+        + 4 * (mini_sector as usize % entries_per_sector)
+}
+
+fn chain(fat: &[u32], start: u32) -> Vec<u32> {
+    let mut chain = Vec::new();
+    let mut next = start;
+    while next != END_OF_CHAIN {
+        assert!(next <= MAX_REGULAR_SECTOR, "bad chain pointer {:#x}", next);
+        assert!(chain.len() <= fat.len(), "chain loops");
+        chain.push(next);
+        next = fat[next as usize];
+    }
+    chain
+}
+
+/// Returns the file offsets of all directory entries, in stream ID order.
+fn dir_entry_offsets(data: &[u8]) -> Vec<usize> {
+    let entries_per_sector = sector_len(data) / DIR_ENTRY_LEN;
+    let mut offsets = Vec::new();
+    for sector in chain(&fat(data), u32_at(data, 48)) {
+        let offset = sector_offset(data, sector);
+        for index in 0..entries_per_sector {
+            offsets.push(offset + index * DIR_ENTRY_LEN);
+        }
+    }
+    offsets
+}
+
+fn entry_name(data: &[u8], offset: usize) -> String {
+    let name_len = u16_at(data, offset + 64) as usize;
+    let units: Vec<u16> = (0..name_len.saturating_sub(2) / 2)
+        .map(|index| u16_at(data, offset + 2 * index))
+        .collect();
+    String::from_utf16(&units).unwrap()
+}
+
+/// Returns the file offset of the directory entry with the given name.
+fn entry_offset(data: &[u8], name: &str) -> usize {
+    dir_entry_offsets(data)
+        .into_iter()
+        .find(|&offset| {
+            data[offset + 66] != OBJ_TYPE_UNALLOCATED
+                && entry_name(data, offset) == name
+        })
+        .unwrap_or_else(|| panic!("no entry named {:?}", name))
+}
+
+fn set_entry_name(data: &mut [u8], offset: usize, name: &str) {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    data[offset..offset + 64].fill(0);
+    for (index, unit) in units.iter().enumerate() {
+        data[offset + 2 * index..offset + 2 * index + 2]
+            .copy_from_slice(&unit.to_le_bytes());
+    }
+    let name_len = (2 * units.len() + 2) as u16;
+    data[offset + 64..offset + 66].copy_from_slice(&name_len.to_le_bytes());
+}
+
+fn minifat(data: &[u8]) -> Vec<u32> {
+    let fat = fat(data);
+    let mut minifat = Vec::new();
+    let start = u32_at(data, 60);
+    if start == END_OF_CHAIN {
+        return minifat;
+    }
+    for sector in chain(&fat, start) {
+        let offset = sector_offset(data, sector);
+        for index in 0..sector_len(data) / 4 {
+            minifat.push(u32_at(data, offset + 4 * index));
+        }
+    }
+    minifat
+}
+
+/// Builds a small file with a storage, a stream in the mini stream, a stream
+/// in regular sectors, an empty stream, and enough siblings to give the root
+/// storage a nontrivial red-black tree.
+fn sample_file(version: Version) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.create_storage("/storage").unwrap();
+    comp.set_storage_clsid("/storage", Uuid::from_u128(0x1234)).unwrap();
+    comp.create_stream("/storage/small")
+        .unwrap()
+        .write_all(&SMALL_DATA)
+        .unwrap();
+    comp.create_stream("/large").unwrap().write_all(&[2; LARGE_LEN]).unwrap();
+    comp.create_stream("/empty").unwrap();
+    for index in 0..20 {
+        let name =
+            format!("/sibling{}", "x".repeat(index % 7) + &index.to_string());
+        comp.create_stream(&name).unwrap();
+    }
+    comp.create_stream("/tiny").unwrap().write_all(&[3; 10]).unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Builds a V3 file large enough to need DIFAT sectors (more than 109 FAT
+/// sectors).
+fn file_with_difat_sectors() -> Vec<u8> {
+    let options = CreateOptions::new()
+        .version(Version::V3)
+        .expected_total_bytes(8 << 20)
+        .keep_unused_reservations(true);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_options(options, cursor).unwrap();
+    comp.create_stream("/big").unwrap().write_all(&vec![7; 8 << 20]).unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// The length of a stream that brings a new V3 file to exactly as many
+/// sectors as the 109 FAT sectors listed in the header can describe, so that
+/// the header DIFAT is full but there are no DIFAT sectors.  (The same
+/// boundary in a V4 file would take a 436 MiB file.)
+const FULL_HEADER_DIFAT_STREAM_LEN: usize =
+    (NUM_HEADER_DIFAT_ENTRIES * 128 - NUM_HEADER_DIFAT_ENTRIES - 1) * 512;
+
+fn file_with_full_header_difat() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/big")
+        .unwrap()
+        .write_all(&vec![7; FULL_HEADER_DIFAT_STREAM_LEN])
+        .unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Asserts that the file lists exactly `num_fat_sectors` FAT sectors, all
+/// in the header, that the header records no DIFAT sectors, and that the
+/// file opens strictly.
+fn assert_no_difat_sectors(data: Vec<u8>, num_fat_sectors: usize) {
+    assert_eq!(u32_at(&data, 44) as usize, num_fat_sectors);
+    assert_eq!(u32_at(&data, 68), END_OF_CHAIN);
+    assert_eq!(u32_at(&data, 72), 0);
+    let (difat_sectors, fat_sectors) = difat(&data);
+    assert!(difat_sectors.is_empty());
+    assert_eq!(fat_sectors.len(), num_fat_sectors);
+    let fat = fat(&data);
+    for &sector in fat_sectors.iter() {
+        assert_eq!(fat[sector as usize], FAT_SECTOR);
+    }
+    for index in num_fat_sectors..NUM_HEADER_DIFAT_ENTRIES {
+        assert_eq!(u32_at(&data, 76 + 4 * index), FREE_SECTOR);
+    }
+    assert!(!fat.contains(&DIFAT_SECTOR));
+    open_strict(data).unwrap();
+}
+
+/// Asserts that the file lists 110 FAT sectors, the last of them in a single
+/// DIFAT sector that is properly terminated, and that the file opens
+/// strictly.
+fn assert_one_difat_sector(data: Vec<u8>) {
+    assert_eq!(u32_at(&data, 44) as usize, NUM_HEADER_DIFAT_ENTRIES + 1);
+    assert_eq!(u32_at(&data, 72), 1);
+    let (difat_sectors, fat_sectors) = difat(&data);
+    assert_eq!(difat_sectors, vec![u32_at(&data, 68)]);
+    assert_eq!(fat_sectors.len(), NUM_HEADER_DIFAT_ENTRIES + 1);
+    let fat = fat(&data);
+    assert_eq!(fat[difat_sectors[0] as usize], DIFAT_SECTOR);
+    for &sector in fat_sectors.iter() {
+        assert_eq!(fat[sector as usize], FAT_SECTOR);
+    }
+    let offset = sector_offset(&data, difat_sectors[0]);
+    for index in 1..(sector_len(&data) / 4 - 1) {
+        assert_eq!(u32_at(&data, offset + 4 * index), FREE_SECTOR);
+    }
+    assert_eq!(u32_at(&data, offset + sector_len(&data) - 4), END_OF_CHAIN);
+    open_strict(data).unwrap();
+}
+
+fn open_strict(
+    data: Vec<u8>,
+) -> std::io::Result<CompoundFile<Cursor<Vec<u8>>>> {
+    CompoundFile::open_strict(Cursor::new(data))
+}
+
+fn open(data: Vec<u8>) -> std::io::Result<CompoundFile<Cursor<Vec<u8>>>> {
+    CompoundFile::open(Cursor::new(data))
+}
+
+/// Asserts that the data is rejected under strict validation, and returns
+/// the file opened under permissive validation.
+fn rejected_only_when_strict(data: Vec<u8>) -> CompoundFile<Cursor<Vec<u8>>> {
+    assert!(open_strict(data.clone()).is_err(), "strict open succeeded");
+    open(data).expect("permissive open failed")
+}
+
+fn assert_rejected(data: Vec<u8>) {
+    assert!(open_strict(data.clone()).is_err(), "strict open succeeded");
+    assert!(open(data).is_err(), "permissive open succeeded");
+}
+
+fn has_warning<F>(comp: &CompoundFile<F>, kind: ValidationIssueKind) -> bool {
+    comp.open_warnings().iter().any(|issue| issue.kind() == kind)
+}
+
+fn read_stream(
+    comp: &mut CompoundFile<Cursor<Vec<u8>>>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn both_versions() -> [(Version, Vec<u8>); 2] {
+    [
+        (Version::V3, sample_file(Version::V3)),
+        (Version::V4, sample_file(Version::V4)),
+    ]
+}
+
+//===========================================================================//
+// 2.1 Compound File Sector Numbers and Types
+
+#[test]
+fn s2_1_fat_uses_only_defined_sector_values() {
+    for (_, data) in both_versions() {
+        let count = num_sectors(&data) as u32;
+        for entry in fat(&data) {
+            assert!(
+                entry < count
+                    || [DIFAT_SECTOR, FAT_SECTOR, END_OF_CHAIN, FREE_SECTOR]
+                        .contains(&entry),
+                "unexpected FAT entry {:#x}",
+                entry
+            );
+        }
+    }
+}
+
+/// The special sector values that must not appear in the middle of a chain,
+/// with the names the spec gives them.
+const MID_CHAIN_VALUES: [(u32, &str); 3] = [
+    (FREE_SECTOR, "FREESECT"),
+    (FAT_SECTOR, "FATSECT"),
+    (DIFAT_SECTOR, "DIFSECT"),
+];
+
+fn error_message<T>(result: std::io::Result<T>) -> String {
+    match result {
+        Ok(_) => panic!("expected an error"),
+        Err(error) => error.to_string(),
+    }
+}
+
+#[test]
+fn s2_1_special_value_in_stream_chain_is_reported() {
+    for &(value, name) in MID_CHAIN_VALUES.iter() {
+        let mut data = sample_file(Version::V3);
+        let start = u32_at(&data, entry_offset(&data, "large") + 116);
+        let sectors = chain(&fat(&data), start);
+        let offset = fat_entry_offset(&data, sectors[1]);
+        put_u32(&mut data, offset, value);
+        let expected = format!(
+            "The stream \"/large\" is broken at position 2: found {}",
+            name
+        );
+        assert_eq!(error_message(open_strict(data.clone())), expected);
+
+        let mut comp = open(data).unwrap();
+        let issues = comp.open_warnings().to_vec();
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].kind(), ValidationIssueKind::BrokenChain);
+        assert_eq!(issues[0].message(), expected);
+        let mut contents = Vec::new();
+        let result = comp
+            .open_stream("/large")
+            .and_then(|mut stream| stream.read_to_end(&mut contents));
+        let message = error_message(result);
+        assert!(
+            message.ends_with(&format!(
+                "is broken at position 2: found {}",
+                name
+            )),
+            "{}",
+            message
+        );
+        // Other streams are unaffected.
+        assert_eq!(read_stream(&mut comp, "/storage/small"), SMALL_DATA);
+    }
+}
+
+#[test]
+fn s2_1_special_value_in_mini_chain_is_reported() {
+    for &(value, name) in MID_CHAIN_VALUES.iter() {
+        let mut data = sample_file(Version::V4);
+        let start = u32_at(&data, entry_offset(&data, "small") + 116);
+        let offset = minifat_entry_offset(&data, start);
+        put_u32(&mut data, offset, value);
+        let expected = format!(
+            "The stream \"/storage/small\" is broken at position 1: found {}",
+            name
+        );
+        assert_eq!(error_message(open_strict(data.clone())), expected);
+
+        let mut comp = open(data).unwrap();
+        assert!(has_warning(&comp, ValidationIssueKind::BrokenChain));
+        let message = error_message(comp.read_many(&["/storage/small"]));
+        assert_eq!(message, expected);
+        assert_eq!(read_stream(&mut comp, "/tiny"), [3; 10]);
+    }
+}
+
+#[test]
+fn s2_1_special_value_in_directory_chain_is_rejected() {
+    for &(value, name) in MID_CHAIN_VALUES.iter() {
+        let mut data = sample_file(Version::V3);
+        let sectors = chain(&fat(&data), u32_at(&data, 48));
+        assert!(sectors.len() > 2);
+        let offset = fat_entry_offset(&data, sectors[1]);
+        put_u32(&mut data, offset, value);
+        let expected = format!(
+            "The directory chain is broken at position 2: found {}",
+            name
+        );
+        assert_eq!(error_message(open_strict(data.clone())), expected);
+        assert_eq!(error_message(open(data)), expected);
+    }
+}
+
+#[test]
+fn s2_1_special_value_in_difat_chain_is_rejected() {
+    // FREE_SECTOR ends a DIFAT chain (see
+    // `s2_5_free_difat_terminator_is_rejected_when_strict`).
+    for &(value, name) in MID_CHAIN_VALUES[1..].iter() {
+        let mut data = file_with_difat_sectors();
+        let last = *difat(&data).0.last().unwrap();
+        let offset = sector_offset(&data, last) + sector_len(&data) - 4;
+        put_u32(&mut data, offset, value);
+        let expected = format!(
+            "The DIFAT chain is broken at position {}: found {}",
+            difat(&data).0.len(),
+            name
+        );
+        assert_eq!(error_message(open_strict(data.clone())), expected);
+        assert_eq!(error_message(open(data)), expected);
+    }
+}
+
+#[test]
+fn s2_1_reserved_sector_value_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let start = u32_at(&data, entry_offset(&data, "large") + 116);
+    let offset = fat_entry_offset(&data, start);
+    put_u32(&mut data, offset, INVALID_SECTOR);
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_1_pointer_past_end_of_file_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let start = u32_at(&data, entry_offset(&data, "large") + 116);
+    let offset = fat_entry_offset(&data, start);
+    let past_end = num_sectors(&data) as u32 + 5;
+    put_u32(&mut data, offset, past_end);
+    assert_rejected(data);
+}
+
+//===========================================================================//
+// 2.2 Compound File Header
+
+#[test]
+fn s2_2_header_signature() {
+    for (_, data) in both_versions() {
+        assert_eq!(&data[0..8], &SIGNATURE);
+    }
+}
+
+#[test]
+fn s2_2_header_signature_is_required() {
+    let mut data = sample_file(Version::V3);
+    data[3] ^= 0xff;
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_2_header_clsid_is_zero() {
+    for (_, data) in both_versions() {
+        assert_eq!(&data[8..24], &[0; 16]);
+    }
+}
+
+#[test]
+fn s2_2_nonzero_header_clsid_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    data[10] = 0x42;
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::NonzeroReservedField));
+}
+
+#[test]
+fn s2_2_minor_version() {
+    for (_, data) in both_versions() {
+        assert_eq!(u16_at(&data, 24), 0x003e);
+    }
+}
+
+#[test]
+fn s2_2_major_version() {
+    for (version, data) in both_versions() {
+        let expected = match version {
+            Version::V3 => 3,
+            Version::V4 => 4,
+        };
+        assert_eq!(u16_at(&data, 26), expected);
+    }
+}
+
+#[test]
+fn s2_2_unknown_major_version_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    data[26] = 5;
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_2_byte_order() {
+    for (_, data) in both_versions() {
+        assert_eq!(u16_at(&data, 28), 0xfffe);
+    }
+}
+
+#[test]
+fn s2_2_byte_order_is_required() {
+    let mut data = sample_file(Version::V3);
+    data[28] = 0xff;
+    data[29] = 0xfe;
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_2_sector_shift() {
+    for (version, data) in both_versions() {
+        let expected = match version {
+            Version::V3 => 9,
+            Version::V4 => 12,
+        };
+        assert_eq!(u16_at(&data, 30), expected);
+    }
+}
+
+#[test]
+fn s2_2_sector_shift_must_match_major_version() {
+    let mut data = sample_file(Version::V3);
+    data[30] = 12;
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_2_mini_sector_shift() {
+    for (_, data) in both_versions() {
+        assert_eq!(u16_at(&data, 32), 6);
+    }
+}
+
+#[test]
+fn s2_2_mini_sector_shift_is_required() {
+    let mut data = sample_file(Version::V3);
+    data[32] = 7;
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_2_reserved_bytes_are_zero() {
+    for (_, data) in both_versions() {
+        assert_eq!(&data[34..40], &[0; 6]);
+    }
+}
+
+#[test]
+fn s2_2_nonzero_reserved_bytes_are_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    data[36] = 1;
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::NonzeroReservedField));
+}
+
+#[test]
+fn s2_2_num_dir_sectors_is_zero_for_v3() {
+    let data = sample_file(Version::V3);
+    assert_eq!(u32_at(&data, 40), 0);
+}
+
+#[test]
+fn s2_2_nonzero_num_dir_sectors_for_v3_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    put_u32(&mut data, 40, 1);
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::DirSectorCountNotZero));
+}
+
+#[test]
+fn s2_2_num_dir_sectors_matches_chain_for_v4() {
+    let data = sample_file(Version::V4);
+    let dir_chain = chain(&fat(&data), u32_at(&data, 48));
+    assert_eq!(u32_at(&data, 40) as usize, dir_chain.len());
+}
+
+#[test]
+fn s2_2_num_fat_sectors_matches_difat() {
+    for data in [sample_file(Version::V3), file_with_difat_sectors()] {
+        let num_fat_sectors = u32_at(&data, 44) as usize;
+        let (_, fat_sectors) = difat(&data);
+        assert_eq!(fat_sectors.len(), num_fat_sectors);
+        assert!(fat_sectors
+            .iter()
+            .all(|&sector| sector <= MAX_REGULAR_SECTOR));
+    }
+}
+
+#[test]
+fn s2_2_first_dir_sector_holds_root_entry() {
+    for (_, data) in both_versions() {
+        let offset = sector_offset(&data, u32_at(&data, 48));
+        assert_eq!(data[offset + 66], OBJ_TYPE_ROOT);
+    }
+}
+
+#[test]
+fn s2_2_transaction_signature_is_zero() {
+    for (_, data) in both_versions() {
+        assert_eq!(u32_at(&data, 52), 0);
+    }
+}
+
+#[test]
+fn s2_2_mini_stream_cutoff() {
+    for (_, data) in both_versions() {
+        assert_eq!(u32_at(&data, 56), 4096);
+    }
+}
+
+#[test]
+fn s2_2_mini_stream_cutoff_is_required() {
+    let mut data = sample_file(Version::V3);
+    put_u32(&mut data, 56, 2048);
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_2_minifat_location_and_count() {
+    for (_, data) in both_versions() {
+        let minifat_chain = chain(&fat(&data), u32_at(&data, 60));
+        assert!(!minifat_chain.is_empty());
+        assert_eq!(u32_at(&data, 64) as usize, minifat_chain.len());
+    }
+}
+
+#[test]
+fn s2_2_minifat_location_without_mini_stream() {
+    for version in [Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_version(version, cursor).unwrap();
+        comp.create_stream("/large").unwrap().write_all(&[1; 5000]).unwrap();
+        comp.flush().unwrap();
+        let data = comp.into_inner().into_inner();
+        assert_eq!(u32_at(&data, 60), END_OF_CHAIN);
+        assert_eq!(u32_at(&data, 64), 0);
+    }
+}
+
+#[test]
+fn s2_2_difat_location_without_difat_sectors() {
+    for (_, data) in both_versions() {
+        assert_eq!(u32_at(&data, 68), END_OF_CHAIN);
+        assert_eq!(u32_at(&data, 72), 0);
+    }
+}
+
+#[test]
+fn s2_2_unused_header_difat_entries_are_free() {
+    for (_, data) in both_versions() {
+        let num_fat_sectors = u32_at(&data, 44) as usize;
+        for index in num_fat_sectors..NUM_HEADER_DIFAT_ENTRIES {
+            assert_eq!(u32_at(&data, 76 + 4 * index), FREE_SECTOR);
+        }
+    }
+}
+
+#[test]
+fn s2_2_header_difat_entry_after_free_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    put_u32(&mut data, 76 + 4 * 50, 1);
+    rejected_only_when_strict(data);
+}
+
+#[test]
+fn s2_2_v4_header_padding_is_zero() {
+    let data = sample_file(Version::V4);
+    assert!(data[HEADER_LEN..4096].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn s2_2_nonzero_v4_header_padding_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V4);
+    data[HEADER_LEN + 100] = 1;
+    rejected_only_when_strict(data);
+}
+
+#[test]
+fn s2_2_truncated_header_is_rejected() {
+    let data = sample_file(Version::V3)[..HEADER_LEN - 12].to_vec();
+    assert_rejected(data);
+}
+
+//===========================================================================//
+// 2.3 Compound File FAT Sectors
+
+#[test]
+fn s2_3_fat_sectors_are_marked() {
+    for data in [sample_file(Version::V4), file_with_difat_sectors()] {
+        let fat = fat(&data);
+        for sector in difat(&data).1 {
+            assert_eq!(fat[sector as usize], FAT_SECTOR);
+        }
+    }
+}
+
+#[test]
+fn s2_3_last_fat_sector_is_padded_with_free() {
+    for (_, data) in both_versions() {
+        let fat = fat(&data);
+        for &entry in &fat[num_sectors(&data)..] {
+            assert_eq!(entry, FREE_SECTOR);
+        }
+    }
+}
+
+#[test]
+fn s2_3_stream_chains_end_with_end_of_chain() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "large");
+        let sectors = chain(&fat(&data), u32_at(&data, offset + 116));
+        assert_eq!(sectors.len(), LARGE_LEN.div_ceil(sector_len(&data)));
+    }
+}
+
+#[test]
+fn s2_3_unmarked_fat_sector_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let fat_sector = difat(&data).1[0];
+    let offset = fat_entry_offset(&data, fat_sector);
+    put_u32(&mut data, offset, END_OF_CHAIN);
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::SectorNotMarked));
+}
+
+#[test]
+fn s2_3_chain_loop_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let start = u32_at(&data, entry_offset(&data, "large") + 116);
+    let last = *chain(&fat(&data), start).last().unwrap();
+    let offset = fat_entry_offset(&data, last);
+    put_u32(&mut data, offset, start);
+    // The cycle must be an error rather than an endless read.
+    let mut comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::BrokenChain));
+    let mut contents = Vec::new();
+    let result = comp
+        .open_stream("/large")
+        .and_then(|mut stream| stream.read_to_end(&mut contents));
+    assert!(result.is_err());
+}
+
+#[test]
+fn s2_3_sector_in_two_chains_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let start = u32_at(&data, entry_offset(&data, "large") + 116);
+    let sectors = chain(&fat(&data), start);
+    // Sector 2 of the chain is now the successor of both sectors 0 and 1.
+    let offset = fat_entry_offset(&data, sectors[0]);
+    put_u32(&mut data, offset, sectors[2]);
+    assert_rejected(data);
+}
+
+//===========================================================================//
+// 2.4 Compound File Mini FAT Sectors
+
+#[test]
+fn s2_4_minifat_unused_entries_are_free() {
+    for (_, data) in both_versions() {
+        let root_len = u64_at(&data, dir_entry_offsets(&data)[0] + 120);
+        let num_mini_sectors = (root_len / 64) as usize;
+        let minifat = minifat(&data);
+        assert!(minifat.len() >= num_mini_sectors);
+        for &entry in &minifat[num_mini_sectors..] {
+            assert_eq!(entry, FREE_SECTOR);
+        }
+    }
+}
+
+#[test]
+fn s2_4_mini_chains_end_with_end_of_chain() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "small");
+        let sectors = chain(&minifat(&data), u32_at(&data, offset + 116));
+        assert_eq!(sectors.len(), SMALL_DATA.len().div_ceil(64));
+    }
+}
+
+#[test]
+fn s2_4_minifat_longer_than_mini_stream_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let root_offset = dir_entry_offsets(&data)[0];
+    let root_len = u32_at(&data, root_offset + 120);
+    put_u32(&mut data, root_offset + 120, root_len - 64);
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::MiniFatTruncated));
+}
+
+//===========================================================================//
+// 2.5 Compound File DIFAT Sectors
+
+#[test]
+fn s2_5_difat_sectors_are_marked() {
+    let data = file_with_difat_sectors();
+    let (difat_sectors, _) = difat(&data);
+    assert!(!difat_sectors.is_empty());
+    let fat = fat(&data);
+    for sector in difat_sectors {
+        assert_eq!(fat[sector as usize], DIFAT_SECTOR);
+    }
+}
+
+#[test]
+fn s2_5_difat_header_fields_match_chain() {
+    let data = file_with_difat_sectors();
+    let (difat_sectors, _) = difat(&data);
+    assert_eq!(u32_at(&data, 68), difat_sectors[0]);
+    assert_eq!(u32_at(&data, 72) as usize, difat_sectors.len());
+}
+
+#[test]
+fn s2_5_difat_chain_ends_with_end_of_chain() {
+    let data = file_with_difat_sectors();
+    let last = *difat(&data).0.last().unwrap();
+    let offset = sector_offset(&data, last) + sector_len(&data) - 4;
+    assert_eq!(u32_at(&data, offset), END_OF_CHAIN);
+}
+
+#[test]
+fn s2_5_unused_difat_entries_are_free() {
+    let data = file_with_difat_sectors();
+    let num_fat_sectors = u32_at(&data, 44) as usize;
+    let last = *difat(&data).0.last().unwrap();
+    let entries_per_sector = sector_len(&data) / 4 - 1;
+    let used_in_last = (num_fat_sectors - NUM_HEADER_DIFAT_ENTRIES)
+        - (u32_at(&data, 72) as usize - 1) * entries_per_sector;
+    let offset = sector_offset(&data, last);
+    for index in used_in_last..entries_per_sector {
+        assert_eq!(u32_at(&data, offset + 4 * index), FREE_SECTOR);
+    }
+}
+
+#[test]
+fn s2_5_free_difat_terminator_is_rejected_when_strict() {
+    let mut data = file_with_difat_sectors();
+    let last = *difat(&data).0.last().unwrap();
+    let offset = sector_offset(&data, last) + sector_len(&data) - 4;
+    put_u32(&mut data, offset, FREE_SECTOR);
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::DifatChainTerminator));
+}
+
+#[test]
+fn s2_5_full_header_difat_needs_no_difat_sectors() {
+    let data = file_with_full_header_difat();
+    assert_eq!(num_sectors(&data), NUM_HEADER_DIFAT_ENTRIES * 128);
+    assert_no_difat_sectors(data, NUM_HEADER_DIFAT_ENTRIES);
+}
+
+#[test]
+fn s2_5_growing_past_full_header_difat() {
+    // Both a small stream (which starts the mini stream and MiniFAT) and a
+    // large one need one more sector than the full header DIFAT can cover.
+    for len in [10, 4096] {
+        let data = file_with_full_header_difat();
+        let mut comp = open_strict(data).unwrap();
+        comp.create_stream("/more").unwrap().write_all(&vec![1; len]).unwrap();
+        comp.flush().unwrap();
+        let data = comp.into_inner().into_inner();
+        assert_one_difat_sector(data.clone());
+
+        // Reopening and growing further keeps using the same DIFAT sector.
+        let mut comp = open_strict(data).unwrap();
+        comp.create_stream("/even_more")
+            .unwrap()
+            .write_all(&[2; 30000])
+            .unwrap();
+        comp.flush().unwrap();
+        assert_one_difat_sector(comp.into_inner().into_inner());
+    }
+}
+
+#[test]
+fn s2_5_shrinking_back_to_full_header_difat() {
+    let mut comp = open_strict(file_with_full_header_difat()).unwrap();
+    comp.create_stream("/more").unwrap().write_all(&[1; 4096]).unwrap();
+    comp.flush().unwrap();
+    let mut comp = open_strict(comp.into_inner().into_inner()).unwrap();
+    comp.remove_stream("/more").unwrap();
+    let len = comp.shrink_to_fit().unwrap() as usize;
+    let mut data = comp.into_inner().into_inner();
+    data.truncate(len);
+    assert_eq!(num_sectors(&data), NUM_HEADER_DIFAT_ENTRIES * 128);
+    assert_no_difat_sectors(data, NUM_HEADER_DIFAT_ENTRIES);
+}
+
+#[test]
+fn s2_5_unused_difat_sector_reservation_is_released() {
+    // Capacity hints that call for a DIFAT sector reserve one up front; if
+    // the data then fits in the header DIFAT, the DIFAT sector is released
+    // along with the unneeded FAT sectors.
+    let total = FULL_HEADER_DIFAT_STREAM_LEN as u64 + 100_000;
+    let options =
+        CreateOptions::new().version(Version::V3).expected_total_bytes(total);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_options(options, cursor).unwrap();
+    comp.create_stream("/big")
+        .unwrap()
+        .write_all(&vec![7; FULL_HEADER_DIFAT_STREAM_LEN - 4096])
+        .unwrap();
+    comp.flush().unwrap();
+    assert_no_difat_sectors(
+        comp.into_inner().into_inner(),
+        NUM_HEADER_DIFAT_ENTRIES,
+    );
+
+    // With the reservation kept, the DIFAT sector stays valid.
+    let options = CreateOptions::new()
+        .version(Version::V3)
+        .expected_total_bytes(total)
+        .keep_unused_reservations(true);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_options(options, cursor).unwrap();
+    comp.create_stream("/big")
+        .unwrap()
+        .write_all(&vec![7; FULL_HEADER_DIFAT_STREAM_LEN - 4096])
+        .unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    assert_eq!(u32_at(&data, 72), 1);
+    let last = *difat(&data).0.last().unwrap();
+    let offset = sector_offset(&data, last) + sector_len(&data) - 4;
+    assert_eq!(u32_at(&data, offset), END_OF_CHAIN);
+    open_strict(data).unwrap();
+}
+
+//===========================================================================//
+// 2.6.1 Compound File Directory Entry
+
+#[test]
+fn s2_6_1_directory_sectors_hold_whole_entries() {
+    for (_, data) in both_versions() {
+        let num_entries = dir_entry_offsets(&data).len();
+        assert_eq!(num_entries % (sector_len(&data) / DIR_ENTRY_LEN), 0);
+    }
+}
+
+#[test]
+fn s2_6_1_name_length_includes_terminator() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "large");
+        assert_eq!(u16_at(&data, offset + 64), 12);
+        assert_eq!(u16_at(&data, offset + 10), 0);
+    }
+}
+
+#[test]
+fn s2_6_1_name_is_at_most_31_characters() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    assert!(comp.create_stream(format!("/{}", "a".repeat(31))).is_ok());
+    assert!(comp.create_stream(format!("/{}", "b".repeat(32))).is_err());
+    assert!(comp.create_storage(format!("/{}", "c".repeat(32))).is_err());
+}
+
+#[test]
+fn s2_6_1_illegal_name_characters_are_rejected() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/storage").unwrap();
+    for name in ["a\\b", "a:b", "a!b"] {
+        assert!(comp.create_stream(format!("/{}", name)).is_err(), "{}", name);
+    }
+    // A slash is a path separator, so it can never end up inside a name.
+    assert!(comp.create_stream("/nonexistent/stream").is_err());
+}
+
+#[test]
+fn s2_6_1_unterminated_name_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "large");
+    data[offset + 10] = b'x';
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::NameNotTerminated));
+}
+
+#[test]
+fn s2_6_1_object_types() {
+    for (_, data) in both_versions() {
+        let offsets = dir_entry_offsets(&data);
+        assert_eq!(data[offsets[0] + 66], OBJ_TYPE_ROOT);
+        assert_eq!(
+            data[entry_offset(&data, "storage") + 66],
+            OBJ_TYPE_STORAGE
+        );
+        assert_eq!(data[entry_offset(&data, "large") + 66], OBJ_TYPE_STREAM);
+        for &offset in &offsets[1..] {
+            assert!([OBJ_TYPE_UNALLOCATED, OBJ_TYPE_STORAGE, OBJ_TYPE_STREAM]
+                .contains(&data[offset + 66]));
+        }
+    }
+}
+
+#[test]
+fn s2_6_1_invalid_object_type_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "large");
+    data[offset + 66] = 3;
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_6_1_color_flag_values() {
+    for (_, data) in both_versions() {
+        for offset in dir_entry_offsets(&data) {
+            assert!([COLOR_RED, COLOR_BLACK].contains(&data[offset + 67]));
+        }
+    }
+}
+
+#[test]
+fn s2_6_1_invalid_color_flag_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "large");
+    data[offset + 67] = 2;
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_6_1_streams_have_no_child() {
+    for (_, data) in both_versions() {
+        for name in ["large", "small", "empty", "tiny"] {
+            let offset = entry_offset(&data, name);
+            assert_eq!(u32_at(&data, offset + 76), NO_STREAM);
+        }
+    }
+}
+
+#[test]
+fn s2_6_1_stream_with_child_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "large");
+    put_u32(&mut data, offset + 76, 1);
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_6_1_sibling_ids_are_valid_or_no_stream() {
+    for (_, data) in both_versions() {
+        let offsets = dir_entry_offsets(&data);
+        for &offset in &offsets {
+            for field in [68, 72, 76] {
+                let id = u32_at(&data, offset + field);
+                assert!(id == NO_STREAM || (id as usize) < offsets.len());
+            }
+        }
+        // The root entry has no siblings.
+        assert_eq!(u32_at(&data, offsets[0] + 68), NO_STREAM);
+        assert_eq!(u32_at(&data, offsets[0] + 72), NO_STREAM);
+    }
+}
+
+#[test]
+fn s2_6_1_storage_clsid_is_written() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "storage");
+        let clsid = Uuid::from_u128(0x1234).to_bytes_le();
+        assert_eq!(&data[offset + 80..offset + 96], &clsid);
+    }
+}
+
+#[test]
+fn s2_6_1_stream_clsid_is_zero() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "large");
+        assert_eq!(&data[offset + 80..offset + 96], &[0; 16]);
+    }
+}
+
+// Files written by some old exporters have garbage here, but are otherwise
+// fine, so this is only reported, even when strict.
+#[test]
+fn s2_6_1_nonzero_stream_clsid_is_tolerated() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "large");
+    data[offset + 85] = 1;
+    let comp = open_strict(data).expect("strict open failed");
+    assert!(has_warning(&comp, ValidationIssueKind::StreamClsid));
+    assert!(comp.entry("/large").unwrap().clsid().is_nil());
+}
+
+#[test]
+fn s2_6_1_state_bits_are_written() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream("/foo").unwrap();
+    comp.set_state_bits("/foo", 0xdeadbeef).unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    assert_eq!(u32_at(&data, entry_offset(&data, "foo") + 96), 0xdeadbeef);
+}
+
+#[test]
+fn s2_6_1_stream_timestamps_are_zero() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream("/foo").unwrap();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    comp.set_modified_time("/foo", time).unwrap();
+    comp.set_created_time("/foo", time).unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    let offset = entry_offset(&data, "foo");
+    assert_eq!(&data[offset + 100..offset + 116], &[0; 16]);
+}
+
+#[test]
+fn s2_6_1_nonzero_stream_timestamp_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "large");
+    data[offset + 110] = 1;
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::StreamTimestamp));
+}
+
+#[test]
+fn s2_6_1_storage_timestamps_are_written() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/foo").unwrap();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    comp.set_created_time("/foo", time).unwrap();
+    comp.set_modified_time("/foo", time).unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    let offset = entry_offset(&data, "foo");
+    // 1_500_000_000 seconds after the Unix epoch, as a FILETIME.
+    let filetime = (1_500_000_000u64 + 11_644_473_600) * 10_000_000;
+    assert_eq!(u64_at(&data, offset + 100), filetime);
+    assert_eq!(u64_at(&data, offset + 108), filetime);
+}
+
+#[test]
+fn s2_6_1_root_creation_time_is_zero() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    comp.set_created_time("/", time).unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    assert_eq!(u64_at(&data, dir_entry_offsets(&data)[0] + 100), 0);
+}
+
+#[test]
+fn s2_6_1_nonzero_root_creation_time_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let offset = dir_entry_offsets(&data)[0];
+    data[offset + 104] = 1;
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::RootCreationTime));
+}
+
+#[test]
+fn s2_6_1_stream_size_is_limited_for_v3() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    let mut stream = comp.create_stream("/foo").unwrap();
+    assert!(stream.set_len(0x80000001).is_err());
+    assert_eq!(stream.len(), 0);
+}
+
+#[test]
+fn s2_6_1_high_stream_size_bits_are_ignored_for_v3() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "large");
+    put_u32(&mut data, offset + 124, 1);
+    let mut comp = open_strict(data).unwrap();
+    assert_eq!(comp.entry("/large").unwrap().len(), LARGE_LEN as u64);
+    assert_eq!(read_stream(&mut comp, "/large"), vec![2; LARGE_LEN]);
+}
+
+//===========================================================================//
+// 2.6.2 Root Directory Entry
+
+#[test]
+fn s2_6_2_root_entry_name() {
+    for (_, data) in both_versions() {
+        let offset = dir_entry_offsets(&data)[0];
+        assert_eq!(entry_name(&data, offset), "Root Entry");
+    }
+}
+
+#[test]
+fn s2_6_2_wrong_root_entry_name_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let offset = dir_entry_offsets(&data)[0];
+    set_entry_name(&mut data, offset, "Root Entrx");
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::RootEntryName));
+}
+
+#[test]
+fn s2_6_2_root_entry_points_to_mini_stream() {
+    for (_, data) in both_versions() {
+        let offset = dir_entry_offsets(&data)[0];
+        let root_len = u64_at(&data, offset + 120) as usize;
+        assert_eq!(root_len % 64, 0);
+        let sectors = chain(&fat(&data), u32_at(&data, offset + 116));
+        assert_eq!(sectors.len(), root_len.div_ceil(sector_len(&data)));
+    }
+}
+
+#[test]
+fn s2_6_2_root_size_not_multiple_of_mini_sector_is_rejected() {
+    let mut data = sample_file(Version::V3);
+    let offset = dir_entry_offsets(&data)[0];
+    let root_len = u32_at(&data, offset + 120);
+    put_u32(&mut data, offset + 120, root_len - 1);
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_6_2_empty_mini_stream() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    let offset = dir_entry_offsets(&data)[0];
+    assert_eq!(u32_at(&data, offset + 116), END_OF_CHAIN);
+    assert_eq!(u64_at(&data, offset + 120), 0);
+}
+
+//===========================================================================//
+// 2.6.3 Other Directory Entries
+
+#[test]
+fn s2_6_3_storage_start_sector_and_size_are_zero() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "storage");
+        assert_eq!(u32_at(&data, offset + 116), 0);
+        assert_eq!(u64_at(&data, offset + 120), 0);
+    }
+}
+
+#[test]
+fn s2_6_3_nonzero_storage_size_is_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let offset = entry_offset(&data, "storage");
+    put_u32(&mut data, offset + 120, 64);
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::StorageStreamFields));
+}
+
+#[test]
+fn s2_6_3_empty_stream_has_end_of_chain_start() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "empty");
+        assert_eq!(u32_at(&data, offset + 116), END_OF_CHAIN);
+        assert_eq!(u64_at(&data, offset + 120), 0);
+    }
+}
+
+#[test]
+fn s2_6_3_unallocated_entries_are_cleared() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/storage").unwrap();
+    comp.create_stream("/storage/foo").unwrap().write_all(b"foo").unwrap();
+    comp.create_stream("/bar").unwrap();
+    comp.remove_stream("/storage/foo").unwrap();
+    comp.remove_storage("/storage").unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    let mut num_unallocated = 0;
+    for offset in dir_entry_offsets(&data) {
+        if data[offset + 66] != OBJ_TYPE_UNALLOCATED {
+            continue;
+        }
+        num_unallocated += 1;
+        let entry = &data[offset..offset + DIR_ENTRY_LEN];
+        assert!(entry[..68].iter().all(|&byte| byte == 0));
+        assert!(entry[68..80].iter().all(|&byte| byte == 0xff));
+        assert!(entry[80..].iter().all(|&byte| byte == 0));
+    }
+    assert!(num_unallocated >= 2);
+}
+
+//===========================================================================//
+// 2.6.4 Red-Black Tree
+
+/// Compares names the way the spec requires: shorter names first, then by
+/// simple uppercase of each UTF-16 code unit.  (Only ASCII names are used in
+/// these tests, so ASCII uppercasing is enough here.)
+fn compare_names(left: &str, right: &str) -> std::cmp::Ordering {
+    let left: Vec<u16> = left.to_ascii_uppercase().encode_utf16().collect();
+    let right: Vec<u16> = right.to_ascii_uppercase().encode_utf16().collect();
+    left.len().cmp(&right.len()).then(left.cmp(&right))
+}
+
+fn in_order(data: &[u8], offsets: &[usize], id: u32, out: &mut Vec<u32>) {
+    if id == NO_STREAM {
+        return;
+    }
+    let offset = offsets[id as usize];
+    in_order(data, offsets, u32_at(data, offset + 68), out);
+    out.push(id);
+    in_order(data, offsets, u32_at(data, offset + 72), out);
+}
+
+fn check_tree(data: &[u8], offsets: &[usize], id: u32, parent_red: bool) {
+    if id == NO_STREAM {
+        return;
+    }
+    let offset = offsets[id as usize];
+    let red = data[offset + 67] == COLOR_RED;
+    assert!(!(red && parent_red), "adjacent red nodes at {}", id);
+    check_tree(data, offsets, u32_at(data, offset + 68), red);
+    check_tree(data, offsets, u32_at(data, offset + 72), red);
+}
+
+fn tree_top(data: &[u8]) -> (Vec<usize>, u32) {
+    let offsets = dir_entry_offsets(data);
+    let top = u32_at(data, offsets[0] + 76);
+    (offsets, top)
+}
+
+#[test]
+fn s2_6_4_siblings_are_sorted_by_name() {
+    for (_, data) in both_versions() {
+        let (offsets, top) = tree_top(&data);
+        let mut ids = Vec::new();
+        in_order(&data, &offsets, top, &mut ids);
+        assert!(ids.len() > 20);
+        let names: Vec<String> = ids
+            .iter()
+            .map(|&id| entry_name(&data, offsets[id as usize]))
+            .collect();
+        for pair in names.windows(2) {
+            assert_eq!(
+                compare_names(&pair[0], &pair[1]),
+                std::cmp::Ordering::Less,
+                "{:?} should sort before {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+}
+
+#[test]
+fn s2_6_4_no_adjacent_red_nodes() {
+    for (_, data) in both_versions() {
+        let (offsets, top) = tree_top(&data);
+        check_tree(&data, &offsets, top, false);
+    }
+}
+
+#[test]
+fn s2_6_4_adjacent_red_nodes_are_rejected_when_strict() {
+    let mut data = sample_file(Version::V3);
+    let (offsets, top) = tree_top(&data);
+    let top_offset = offsets[top as usize];
+    let left = u32_at(&data, top_offset + 68);
+    assert_ne!(left, NO_STREAM);
+    data[top_offset + 67] = COLOR_RED;
+    data[offsets[left as usize] + 67] = COLOR_RED;
+    let comp = rejected_only_when_strict(data);
+    assert!(has_warning(&comp, ValidationIssueKind::AdjacentRedNodes));
+}
+
+#[test]
+fn s2_6_4_misordered_siblings_are_rejected() {
+    let mut data = sample_file(Version::V3);
+    let (offsets, top) = tree_top(&data);
+    let left = u32_at(&data, offsets[top as usize] + 68);
+    assert_ne!(left, NO_STREAM);
+    // A longer name always sorts after the tree's top node.
+    set_entry_name(&mut data, offsets[left as usize], &"z".repeat(30));
+    assert_rejected(data);
+}
+
+#[test]
+fn s2_6_4_names_compare_case_insensitively() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_new_stream("/foo").unwrap();
+    assert!(comp.create_new_stream("/FOO").is_err());
+    assert!(comp.create_storage("/Foo").is_err());
+    assert!(comp.is_stream("/fOo"));
+}
+
+//===========================================================================//
+// 2.6.2 and 2.4: placement of stream data
+
+#[test]
+fn s2_6_2_small_streams_live_in_mini_stream() {
+    for (_, data) in both_versions() {
+        let offset = entry_offset(&data, "small");
+        let start = u32_at(&data, offset + 116);
+        let root_offset = dir_entry_offsets(&data)[0];
+        let root_sectors =
+            chain(&fat(&data), u32_at(&data, root_offset + 116));
+        let slen = sector_len(&data);
+        let mut contents = Vec::new();
+        for mini_sector in chain(&minifat(&data), start) {
+            let position = mini_sector as usize * 64;
+            let sector = root_sectors[position / slen];
+            let file_offset = sector_offset(&data, sector) + position % slen;
+            contents.extend_from_slice(&data[file_offset..file_offset + 64]);
+        }
+        contents.truncate(SMALL_DATA.len());
+        assert_eq!(contents, SMALL_DATA);
+    }
+}
+
+#[test]
+fn s2_6_2_streams_at_cutoff_live_in_regular_sectors() {
+    for len in [4095usize, 4096] {
+        let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        comp.create_stream("/foo").unwrap().write_all(&vec![9; len]).unwrap();
+        comp.flush().unwrap();
+        let data = comp.into_inner().into_inner();
+        let start = u32_at(&data, entry_offset(&data, "foo") + 116);
+        if len < 4096 {
+            assert_eq!(chain(&minifat(&data), start).len(), len.div_ceil(64));
+        } else {
+            assert_eq!(u32_at(&data, 60), END_OF_CHAIN);
+            let sectors = chain(&fat(&data), start);
+            assert_eq!(sectors.len(), len.div_ceil(sector_len(&data)));
+            for sector in sectors {
+                let offset = sector_offset(&data, sector);
+                assert!(data[offset..offset + 512].iter().all(|&b| b == 9));
+            }
+        }
+    }
+}
+
+//===========================================================================//
+// 2.8 Range Lock Sector
+
+#[test]
+#[ignore = "range lock sector is not reserved for files that grow past 2 GB"]
+fn s2_8_range_lock_sector_is_not_allocated() {
+    // For a V4 file larger than 2 GB, the sector covering file offsets
+    // 0x7fffff00..0x80000000 must be marked END_OF_CHAIN and never hold
+    // data.  Building such a file takes too long for the regular test run,
+    // and the allocator does not reserve that sector yet.
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V4, cursor).unwrap();
+    let chunk = vec![0u8; 1 << 20];
+    let mut stream = comp.create_stream("/big").unwrap();
+    for _ in 0..2100 {
+        stream.write_all(&chunk).unwrap();
+    }
+    drop(stream);
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    let range_lock_sector = (0x7fffff00 / 4096 - 1) as usize;
+    assert_eq!(fat(&data)[range_lock_sector], END_OF_CHAIN);
+}
+
+//===========================================================================//
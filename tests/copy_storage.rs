@@ -0,0 +1,248 @@
+use cfb::{CompoundFile, ObjectNotFound};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(13).wrapping_add(seed)).collect()
+}
+
+fn write_stream(comp: &mut TestFile, path: &str, data: &[u8]) {
+    comp.create_stream(path).unwrap().write_all(data).unwrap();
+}
+
+fn read_stream(comp: &mut TestFile, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn walk_paths(comp: &TestFile) -> Vec<PathBuf> {
+    comp.walk().map(|entry| entry.path().to_path_buf()).collect()
+}
+
+/// Returns each object's path, CLSID, state bits, and data (for streams).
+fn snapshot(comp: &mut TestFile) -> Vec<(PathBuf, Uuid, u32, Vec<u8>)> {
+    let entries: Vec<_> = comp.walk().collect();
+    entries
+        .into_iter()
+        .map(|entry| {
+            let data = if entry.is_stream() {
+                read_stream(comp, entry.path().to_str().unwrap())
+            } else {
+                Vec::new()
+            };
+            let path = entry.path().to_path_buf();
+            (path, *entry.clsid(), entry.state_bits(), data)
+        })
+        .collect()
+}
+
+/// Creates a source file with a subtree under "/src", along with a stream
+/// and a storage at the top level.
+fn make_source() -> TestFile {
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.set_storage_clsid("/", Uuid::from_u128(1)).unwrap();
+    write_stream(&mut comp, "/top", b"top");
+    comp.create_storage("/src").unwrap();
+    write_stream(&mut comp, "/src/small", &data(100, 1));
+    write_stream(&mut comp, "/src/big", &data(10000, 2));
+    comp.create_storage("/src/sub").unwrap();
+    write_stream(&mut comp, "/src/sub/inner", &data(5000, 3));
+    comp.create_storage("/src/sub/empty").unwrap();
+    comp.set_storage_clsid("/src", Uuid::from_u128(2)).unwrap();
+    comp.set_storage_clsid("/src/sub", Uuid::from_u128(3)).unwrap();
+    for path in ["/src", "/src/sub", "/src/sub/empty"] {
+        comp.set_created_time(path, time).unwrap();
+        comp.set_modified_time(path, time).unwrap();
+    }
+    comp.set_state_bits("/src", 0x11).unwrap();
+    comp.set_state_bits("/src/big", 0x22).unwrap();
+    comp
+}
+
+fn make_dest() -> TestFile {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.set_storage_clsid("/", Uuid::from_u128(9)).unwrap();
+    write_stream(&mut comp, "/existing", b"existing");
+    comp.create_storage("/dir").unwrap();
+    comp
+}
+
+/// Asserts that the subtree at `from` in `source` was copied to `to` in
+/// `dest`, with its data and metadata.
+fn assert_copied(
+    source: &mut TestFile,
+    from: &str,
+    dest: &mut TestFile,
+    to: &str,
+) {
+    let entries: Vec<_> = source.walk_relative(from).unwrap().collect();
+    for (relative, entry) in entries {
+        let path = Path::new(to).join(&relative);
+        let copy = dest.entry(&path).unwrap();
+        assert_eq!(copy.is_stream(), entry.is_stream(), "{:?}", path);
+        if relative.as_os_str().is_empty() {
+            // This may be a storage that was merged into.
+            continue;
+        }
+        assert_eq!(copy.state_bits(), entry.state_bits(), "{:?}", path);
+        if entry.is_stream() {
+            let path = path.to_str().unwrap();
+            let source_path = entry.path().to_str().unwrap();
+            assert_eq!(
+                read_stream(dest, path),
+                read_stream(source, source_path),
+                "{:?}",
+                path
+            );
+        } else {
+            assert_eq!(copy.clsid(), entry.clsid(), "{:?}", path);
+            assert_eq!(copy.created(), entry.created(), "{:?}", path);
+            assert_eq!(copy.modified(), entry.modified(), "{:?}", path);
+        }
+    }
+}
+
+//===========================================================================//
+
+#[test]
+fn copy_subtree_to_new_storage() {
+    let mut source = make_source();
+    let mut dest = make_dest();
+    dest.copy_storage_from(&mut source, "/src", "/dir/copy", false).unwrap();
+    assert_copied(&mut source, "/src", &mut dest, "/dir/copy");
+    let copy = dest.entry("/dir/copy").unwrap();
+    assert_eq!(*copy.clsid(), Uuid::from_u128(2));
+    assert_eq!(copy.state_bits(), 0x11);
+    assert_eq!(
+        walk_paths(&dest).len(),
+        3 + source.walk_storage("/src").unwrap().count()
+    );
+    assert!(!dest.exists("/top"));
+
+    // The copy survives a round trip.
+    let mut dest = CompoundFile::open_strict(dest.into_inner()).unwrap();
+    assert_copied(&mut source, "/src", &mut dest, "/dir/copy");
+}
+
+#[test]
+fn copy_root_into_sub_storage() {
+    let mut source = make_source();
+    let mut dest = make_dest();
+    dest.copy_storage_from(&mut source, "/", "/dir/whole", false).unwrap();
+    assert_copied(&mut source, "/", &mut dest, "/dir/whole");
+    // The root's CLSID carries over to the new storage.
+    let copy = dest.entry("/dir/whole").unwrap();
+    assert!(copy.is_storage() && !copy.is_root());
+    assert_eq!(*copy.clsid(), Uuid::from_u128(1));
+}
+
+#[test]
+fn copy_onto_root_merges_children() {
+    let mut source = make_source();
+    let mut dest = make_dest();
+    dest.copy_storage_from(&mut source, "/src", "/", false).unwrap();
+    assert_copied(&mut source, "/src", &mut dest, "/");
+    assert_eq!(read_stream(&mut dest, "/existing"), b"existing");
+    assert!(dest.is_storage("/dir"));
+    // The root keeps its own metadata.
+    assert_eq!(*dest.root_entry().clsid(), Uuid::from_u128(9));
+    assert_eq!(dest.root_entry().state_bits(), 0);
+}
+
+#[test]
+fn conflicts_fail_without_changing_anything() {
+    let mut source = make_source();
+    let mut dest = make_dest();
+    dest.copy_storage_from(&mut source, "/src", "/dir/copy", false).unwrap();
+    write_stream(&mut dest, "/dir/copy/big", b"changed");
+    let before = snapshot(&mut dest);
+
+    // Copying onto the same place again conflicts on every child.
+    let error = dest
+        .copy_storage_from(&mut source, "/src", "/dir/copy", false)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    // A nested conflict is found before anything else is copied.
+    let mut other = make_source();
+    other.remove_stream("/src/small").unwrap();
+    other.remove_stream("/src/big").unwrap();
+    write_stream(&mut other, "/src/new", b"new");
+    let error = dest
+        .copy_storage_from(&mut other, "/src", "/dir/copy", false)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert!(error.to_string().contains("/dir/copy/sub"), "{}", error);
+    // A stream at the destination itself conflicts too.
+    let error = dest
+        .copy_storage_from(&mut source, "/src", "/existing", false)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+
+    assert_eq!(snapshot(&mut dest), before);
+    assert!(!dest.exists("/dir/copy/new"));
+}
+
+#[test]
+fn overwrite_replaces_and_merges() {
+    let mut source = make_source();
+    let mut dest = make_dest();
+    dest.create_storage("/dir/copy").unwrap();
+    dest.set_storage_clsid("/dir/copy", Uuid::from_u128(7)).unwrap();
+    write_stream(&mut dest, "/dir/copy/big", b"old");
+    dest.create_storage("/dir/copy/small").unwrap();
+    write_stream(&mut dest, "/dir/copy/small/child", b"child");
+    write_stream(&mut dest, "/dir/copy/sub", b"not a storage");
+    write_stream(&mut dest, "/dir/copy/keep", b"keep");
+    dest.create_storage("/dir/copy/sub2").unwrap();
+
+    dest.copy_storage_from(&mut source, "/src", "/dir/copy", true).unwrap();
+    assert_copied(&mut source, "/src", &mut dest, "/dir/copy");
+    // Objects only in the destination are left alone, and so is the
+    // metadata of the storage that was merged into.
+    assert_eq!(read_stream(&mut dest, "/dir/copy/keep"), b"keep");
+    assert!(dest.is_storage("/dir/copy/sub2"));
+    assert!(!dest.exists("/dir/copy/small/child"));
+    assert_eq!(*dest.entry("/dir/copy").unwrap().clsid(), Uuid::from_u128(7));
+
+    // A stream at the destination itself is replaced by the storage.
+    dest.copy_storage_from(&mut source, "/src/sub", "/existing", true)
+        .unwrap();
+    assert_copied(&mut source, "/src/sub", &mut dest, "/existing");
+    assert_eq!(*dest.entry("/existing").unwrap().clsid(), Uuid::from_u128(3));
+
+    let dest = CompoundFile::open_strict(dest.into_inner()).unwrap();
+    assert!(dest.is_storage("/existing/empty"));
+}
+
+#[test]
+fn invalid_paths_are_rejected() {
+    let mut source = make_source();
+    let mut dest = make_dest();
+    let error =
+        dest.copy_storage_from(&mut source, "/top", "/x", false).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = dest
+        .copy_storage_from(&mut source, "/missing", "/x", false)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = dest
+        .copy_storage_from(&mut source, "/src", "/missing/x", false)
+        .unwrap_err();
+    let not_found = ObjectNotFound::from_io_error(&error).unwrap();
+    assert_eq!(not_found.nearest_existing_ancestor(), Path::new("/"));
+    let error = dest
+        .copy_storage_from(&mut source, "/src", "/existing/x", true)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    assert!(!dest.exists("/x"));
+}
+
+//===========================================================================//
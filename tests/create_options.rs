@@ -0,0 +1,157 @@
+use cfb::{CompoundFile, NewEntryOptions, StreamId, Version};
+use std::io::{Cursor, ErrorKind, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//===========================================================================//
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+fn time(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Returns every directory entry's bytes, with the color and the sibling
+/// and child links (which inserting into a tree may change) blanked out.
+fn entries_without_links(comp: &mut Comp) -> Vec<(StreamId, Vec<u8>)> {
+    comp.raw_dir_entries()
+        .map(|result| {
+            let (stream_id, mut raw) = result.unwrap();
+            raw[67..80].fill(0);
+            (stream_id, raw.to_vec())
+        })
+        .collect()
+}
+
+/// Creates an object with the given function, and checks that no other
+/// directory entry changed, apart from tree links.
+fn check_touches_one_entry<G>(comp: &mut Comp, path: &str, create: G)
+where
+    G: FnOnce(&mut Comp),
+{
+    let before = entries_without_links(comp);
+    create(comp);
+    let new_id = comp.entry(path).unwrap().stream_id();
+    let after = entries_without_links(comp);
+    assert_eq!(before.len(), after.len());
+    let changed: Vec<StreamId> = before
+        .iter()
+        .zip(after.iter())
+        .filter(|(old, new)| old != new)
+        .map(|(_, (stream_id, _))| *stream_id)
+        .collect();
+    assert_eq!(changed, vec![new_id]);
+}
+
+/// Builds a file using only explicit times, so that it should come out the
+/// same every time.
+fn build() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    let options = NewEntryOptions::new().times(time(1_000_000_000));
+    comp.create_storage_with_options("/a", &options).unwrap();
+    comp.create_storage_with_options(
+        "/a/b",
+        &NewEntryOptions::new().zero_times().state_bits(9),
+    )
+    .unwrap();
+    comp.create_stream_with_options(
+        "/a/b/data",
+        &options.clone().state_bits(1),
+    )
+    .unwrap()
+    .write_all(&[7; 5000])
+    .unwrap();
+    comp.create_stream("/a/small").unwrap().write_all(b"small").unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+//===========================================================================//
+
+#[test]
+fn storage_starts_with_given_metadata() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let options = NewEntryOptions::new()
+        .created(time(1_000))
+        .modified(time(2_000))
+        .state_bits(0x55);
+    comp.create_storage_with_options("/foo", &options).unwrap();
+    let entry = comp.entry("/foo").unwrap();
+    assert_eq!(entry.created(), time(1_000));
+    assert_eq!(entry.modified(), time(2_000));
+    assert_eq!(entry.state_bits(), 0x55);
+
+    comp.create_storage_with_options(
+        "/zero",
+        &NewEntryOptions::new().zero_times(),
+    )
+    .unwrap();
+    let raw = comp.raw_dir_entry(comp.entry("/zero").unwrap().stream_id());
+    assert_eq!(&raw.unwrap()[100..116], &[0; 16]);
+}
+
+#[test]
+fn stream_starts_with_given_state_bits() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let options = NewEntryOptions::new().times(time(1_000)).state_bits(3);
+    comp.create_stream_with_options("/foo", &options)
+        .unwrap()
+        .write_all(b"foo")
+        .unwrap();
+    let entry = comp.entry("/foo").unwrap();
+    assert_eq!(entry.state_bits(), 3);
+    // Streams' times are always zero.
+    let raw = comp.raw_dir_entry(entry.stream_id()).unwrap();
+    assert_eq!(&raw[100..116], &[0; 16]);
+
+    // Replacing a stream keeps its state bits unless they are given.
+    comp.create_stream_with_options("/foo", &NewEntryOptions::new()).unwrap();
+    assert_eq!(comp.entry("/foo").unwrap().state_bits(), 3);
+    assert_eq!(comp.entry("/foo").unwrap().len(), 0);
+    comp.create_stream_with_options("/foo", &options.state_bits(4)).unwrap();
+    assert_eq!(comp.entry("/foo").unwrap().state_bits(), 4);
+}
+
+#[test]
+fn creation_touches_exactly_one_entry() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let options = NewEntryOptions::new().times(time(5_000)).state_bits(2);
+    comp.create_storage_with_options("/parent", &options).unwrap();
+    for name in ["m", "c", "x", "a"] {
+        let path = format!("/parent/{}", name);
+        check_touches_one_entry(&mut comp, &path, |comp| {
+            comp.create_storage_with_options(&path, &options).unwrap();
+        });
+        let path = format!("/parent/{}/stream", name);
+        check_touches_one_entry(&mut comp, &path, |comp| {
+            comp.create_stream_with_options(&path, &options).unwrap();
+        });
+    }
+    assert_eq!(comp.entry("/parent").unwrap().modified(), time(5_000));
+}
+
+#[test]
+fn out_of_range_time_creates_nothing() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let too_early = UNIX_EPOCH - Duration::from_secs(400 * 365 * 86_400);
+    let options = NewEntryOptions::new().created(too_early);
+    let error =
+        comp.create_storage_with_options("/foo", &options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(!comp.exists("/foo"));
+    let result = comp.create_stream_with_options("/foo", &options);
+    let error = result.map(|_| ()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(!comp.exists("/foo"));
+}
+
+#[test]
+fn fixed_times_give_identical_bytes() {
+    let first = build();
+    std::thread::sleep(Duration::from_millis(20));
+    let second = build();
+    assert!(first == second);
+}
+
+//===========================================================================//
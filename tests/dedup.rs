@@ -0,0 +1,174 @@
+use cfb::{CompoundFile, Version};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+//===========================================================================//
+
+fn create_data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|index| (index % 251) as u8 ^ seed).collect()
+}
+
+fn read_stream<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+fn test_dedup_hit(len: usize) {
+    let data = create_data(len, 0);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_storage("/a").unwrap();
+    comp.create_stream_dedup("/a/license", &data).unwrap();
+    let before = comp.stats().unwrap();
+    comp.create_stream_dedup("/license", &data).unwrap();
+    comp.create_stream_dedup("/other", &create_data(len, 1)).unwrap();
+    let after = comp.stats().unwrap();
+    assert_eq!(after.num_shared_streams(), 2);
+    if len >= 4096 {
+        // Apart from directory entries, only the non-duplicate stream should
+        // have taken up new sectors.
+        let new_dir_sectors =
+            after.num_dir_sectors() - before.num_dir_sectors();
+        assert_eq!(
+            after.num_sectors() - before.num_sectors() - new_dir_sectors,
+            len.div_ceil(512) as u32
+        );
+    }
+
+    let cursor = comp.into_inner();
+    let mut comp = CompoundFile::open_strict(cursor).expect("open");
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 2);
+    assert_eq!(read_stream(&mut comp, "/a/license"), data);
+    assert_eq!(read_stream(&mut comp, "/license"), data);
+    assert_eq!(read_stream(&mut comp, "/other"), create_data(len, 1));
+}
+
+#[test]
+fn dedup_hit_in_mini_stream() {
+    test_dedup_hit(1000);
+}
+
+#[test]
+fn dedup_hit_in_regular_chain() {
+    test_dedup_hit(10000);
+}
+
+#[test]
+fn dedup_of_empty_stream() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream_dedup("/foo", &[]).unwrap();
+    comp.create_stream_dedup("/bar", &[]).unwrap();
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 0);
+    assert!(comp.entry("/foo").unwrap().is_empty());
+    assert!(comp.entry("/bar").unwrap().is_empty());
+}
+
+//===========================================================================//
+
+fn test_copy_on_write(len: usize) {
+    let data = create_data(len, 0);
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream_dedup("/foo", &data).unwrap();
+    comp.create_stream_dedup("/bar", &data).unwrap();
+    comp.create_stream_dedup("/baz", &data).unwrap();
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 3);
+
+    // Overwrite part of one stream:
+    {
+        let mut stream = comp.open_stream("/foo").unwrap();
+        stream.seek(SeekFrom::Start(10)).unwrap();
+        stream.write_all(b"changed").unwrap();
+    }
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 2);
+    let mut expected = data.clone();
+    expected[10..17].copy_from_slice(b"changed");
+    assert_eq!(read_stream(&mut comp, "/foo"), expected);
+    assert_eq!(read_stream(&mut comp, "/bar"), data);
+
+    // Resize another stream:
+    comp.open_stream("/bar").unwrap().set_len(len as u64 / 2).unwrap();
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 0);
+    assert_eq!(read_stream(&mut comp, "/bar"), &data[..len / 2]);
+    assert_eq!(read_stream(&mut comp, "/baz"), data);
+
+    let cursor = comp.into_inner();
+    let mut comp = CompoundFile::open_strict(cursor).expect("open");
+    assert_eq!(read_stream(&mut comp, "/foo"), expected);
+    assert_eq!(read_stream(&mut comp, "/bar"), &data[..len / 2]);
+    assert_eq!(read_stream(&mut comp, "/baz"), data);
+}
+
+#[test]
+fn copy_on_write_in_mini_stream() {
+    test_copy_on_write(1000);
+}
+
+#[test]
+fn copy_on_write_in_regular_chain() {
+    test_copy_on_write(10000);
+}
+
+#[test]
+fn copy_on_write_after_reopen() {
+    let data = create_data(5000, 0);
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream_dedup("/foo", &data).unwrap();
+    comp.create_stream_dedup("/bar", &data).unwrap();
+    let cursor = comp.into_inner();
+    // Sharing is detected from the directory when reopening the file, so it
+    // is still respected in a later session.
+    let mut comp = CompoundFile::open_strict(cursor).expect("open");
+    comp.create_stream("/foo").unwrap().write_all(b"replaced").unwrap();
+    assert_eq!(read_stream(&mut comp, "/foo"), b"replaced");
+    assert_eq!(read_stream(&mut comp, "/bar"), data);
+}
+
+//===========================================================================//
+
+fn test_refcounted_free(len: usize) {
+    let data = create_data(len, 0);
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let empty = comp.stats().unwrap();
+    comp.create_stream_dedup("/foo", &data).unwrap();
+    comp.create_stream_dedup("/bar", &data).unwrap();
+    comp.create_stream_dedup("/baz", &data).unwrap();
+
+    comp.remove_stream("/foo").unwrap();
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 2);
+    assert_eq!(read_stream(&mut comp, "/bar"), data);
+    comp.remove_stream("/bar").unwrap();
+    assert_eq!(comp.stats().unwrap().num_shared_streams(), 0);
+    assert_eq!(read_stream(&mut comp, "/baz"), data);
+    comp.remove_stream("/baz").unwrap();
+
+    let stats = comp.stats().unwrap();
+    if len >= 4096 {
+        assert_eq!(
+            stats.num_sectors() - stats.num_free_sectors(),
+            empty.num_sectors() - empty.num_free_sectors()
+        );
+    } else {
+        assert_eq!(stats.num_free_mini_sectors(), 0);
+    }
+    let cursor = comp.into_inner();
+    let comp = CompoundFile::open_strict(cursor).expect("open");
+    assert_eq!(comp.read_root_storage().count(), 0);
+}
+
+#[test]
+fn refcounted_free_in_mini_stream() {
+    test_refcounted_free(1000);
+}
+
+#[test]
+fn refcounted_free_in_regular_chain() {
+    test_refcounted_free(10000);
+}
+
+//===========================================================================//
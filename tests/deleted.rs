@@ -0,0 +1,154 @@
+use cfb::{CompoundFile, FreeEntryPolicy, SanitizeOptions, StreamId, Version};
+use std::io::{Cursor, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//===========================================================================//
+
+fn time(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Creates a V3 file with the tree:
+///
+/// ```text
+/// /
+/// ├── empty/
+/// ├── full/
+/// │   ├── inner  (10 bytes)
+/// │   └── sub/
+/// └── stream     (100 bytes)
+/// ```
+///
+/// where every storage has distinct creation and modification times.
+fn make_file() -> CompoundFile<Cursor<Vec<u8>>> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/stream").unwrap().write_all(&[1; 100]).unwrap();
+    comp.create_storage("/empty").unwrap();
+    comp.create_storage("/full").unwrap();
+    comp.create_stream("/full/inner").unwrap().write_all(&[2; 10]).unwrap();
+    comp.create_storage("/full/sub").unwrap();
+    for (index, path) in ["/empty", "/full", "/full/sub"].iter().enumerate() {
+        let index = index as u64;
+        comp.set_created_time(path, time(1_000_000 + index)).unwrap();
+        comp.set_modified_time(path, time(2_000_000 + index)).unwrap();
+    }
+    comp
+}
+
+fn deleted_names<F>(comp: &CompoundFile<F>) -> Vec<String> {
+    let mut names: Vec<String> = comp
+        .deleted_entries()
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Returns true if every unallocated directory entry in the file is all
+/// zeros, apart from its sibling and child fields.
+fn unallocated_entries_are_scrubbed(
+    comp: &mut CompoundFile<Cursor<Vec<u8>>>,
+) -> bool {
+    let mut scrubbed = [0u8; 128];
+    scrubbed[68..80].fill(0xff);
+    comp.raw_dir_entries()
+        .map(|item| item.unwrap().1)
+        .filter(|raw| raw[66] == 0)
+        .all(|raw| raw[..] == scrubbed[..])
+}
+
+//===========================================================================//
+
+#[test]
+fn default_policy_scrubs_removed_entries() {
+    let mut comp = make_file();
+    assert_eq!(comp.free_entry_policy(), FreeEntryPolicy::Scrub);
+    comp.remove_stream("/stream").unwrap();
+    comp.remove_storage("/empty").unwrap();
+    comp.remove_storage_all("/full").unwrap();
+    assert!(comp.deleted_entries().is_empty());
+    assert!(unallocated_entries_are_scrubbed(&mut comp));
+
+    let cursor = comp.into_inner();
+    let mut comp = CompoundFile::open_strict(cursor).unwrap();
+    assert!(comp.deleted_entries().is_empty());
+    assert!(unallocated_entries_are_scrubbed(&mut comp));
+}
+
+#[test]
+fn preserve_policy_keeps_names_and_times() {
+    let mut comp = make_file();
+    comp.set_free_entry_policy(FreeEntryPolicy::Preserve);
+    comp.remove_stream("/stream").unwrap();
+    comp.remove_storage("/empty").unwrap();
+    assert_eq!(deleted_names(&comp), vec!["empty", "stream"]);
+    let empty = comp
+        .deleted_entries()
+        .into_iter()
+        .find(|entry| entry.name() == "empty")
+        .unwrap();
+    assert_eq!(empty.created(), time(1_000_000));
+    assert_eq!(empty.modified(), time(2_000_000));
+    assert!(!comp.exists("/stream"));
+    assert!(!comp.exists("/empty"));
+    assert!(!unallocated_entries_are_scrubbed(&mut comp));
+
+    // The preserved entries survive reopening the file, even strictly.
+    let cursor = comp.into_inner();
+    let comp = CompoundFile::open_strict(cursor).unwrap();
+    assert!(comp.open_warnings().is_empty());
+    assert_eq!(deleted_names(&comp), vec!["empty", "stream"]);
+    let names: Vec<String> =
+        comp.walk().map(|entry| entry.name().to_string()).collect();
+    assert_eq!(names, vec!["Root Entry", "full", "sub", "inner"]);
+}
+
+#[test]
+fn preserve_policy_applies_to_recursive_removal() {
+    let mut comp = make_file();
+    comp.set_free_entry_policy(FreeEntryPolicy::Preserve);
+    comp.remove_storage_all("/full").unwrap();
+    assert_eq!(deleted_names(&comp), vec!["full", "inner", "sub"]);
+    let sub = comp
+        .deleted_entries()
+        .into_iter()
+        .find(|entry| entry.name() == "sub")
+        .unwrap();
+    assert_eq!(sub.created(), time(1_000_002));
+    assert_eq!(sub.modified(), time(2_000_002));
+}
+
+#[test]
+fn policy_applies_only_to_later_removals() {
+    let mut comp = make_file();
+    comp.remove_stream("/stream").unwrap();
+    comp.set_free_entry_policy(FreeEntryPolicy::Preserve);
+    comp.remove_storage("/empty").unwrap();
+    comp.set_free_entry_policy(FreeEntryPolicy::Scrub);
+    comp.remove_stream("/full/inner").unwrap();
+    assert_eq!(deleted_names(&comp), vec!["empty"]);
+}
+
+#[test]
+fn preserved_entries_are_lost_when_reused_or_sanitized() {
+    let mut comp = make_file();
+    comp.set_free_entry_policy(FreeEntryPolicy::Preserve);
+    comp.remove_stream("/stream").unwrap();
+    comp.remove_storage("/empty").unwrap();
+    let mut ids: Vec<StreamId> =
+        comp.deleted_entries().iter().map(|entry| entry.stream_id()).collect();
+    ids.sort();
+    comp.create_stream("/new").unwrap();
+    let remaining: Vec<StreamId> =
+        comp.deleted_entries().iter().map(|entry| entry.stream_id()).collect();
+    assert_eq!(remaining, vec![ids[1]]);
+
+    comp.sanitize(SanitizeOptions::new()).unwrap();
+    assert!(comp.deleted_entries().is_empty());
+    assert!(unallocated_entries_are_scrubbed(&mut comp));
+}
+
+//===========================================================================//
@@ -0,0 +1,183 @@
+use cfb::{CompoundFile, VerifyOptions, Version};
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+/// The smallest possible compound file: a header, one FAT sector, and one
+/// directory sector holding only the root entry.
+fn minimal_len(version: Version) -> usize {
+    3 * version.sector_len()
+}
+
+fn create_empty(version: Version) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Adds an empty MiniFAT sector (all `FREESECT`) to an empty file, as some
+/// other implementations write.
+fn with_empty_minifat(version: Version, mut data: Vec<u8>) -> Vec<u8> {
+    let sector_len = version.sector_len();
+    assert_eq!(data.len(), minimal_len(version));
+    // The new sector is sector 2, after the FAT and directory sectors.
+    data[60..64].copy_from_slice(&2u32.to_le_bytes());
+    data[64..68].copy_from_slice(&1u32.to_le_bytes());
+    let fat_entry = sector_len + 2 * 4;
+    data[fat_entry..fat_entry + 4]
+        .copy_from_slice(&0xfffffffeu32.to_le_bytes());
+    data.resize(data.len() + sector_len, 0xff);
+    data
+}
+
+fn assert_empty(comp: &mut TestFile) {
+    assert!(comp.open_warnings().is_empty(), "{:?}", comp.open_warnings());
+    let paths: Vec<_> =
+        comp.walk().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(paths, vec![Path::new("/").to_path_buf()]);
+    assert_eq!(comp.read_root_storage().count(), 0);
+    assert_eq!(comp.walk_storage("/").unwrap().count(), 1);
+    assert_eq!(comp.walk_relative("/").unwrap().count(), 1);
+    assert!(comp.root_entry().is_root());
+    assert_eq!(comp.root_entry().len(), 0);
+    assert_eq!(comp.mini_stream_len(), 0);
+    let stats = comp.stats().unwrap();
+    assert_eq!(stats.num_fragments(), 0);
+    assert_eq!(stats.num_free_mini_sectors(), 0);
+    assert_eq!(stats.num_shared_streams(), 0);
+    assert!(comp.stale_chains().is_empty());
+    assert!(comp.deleted_entries().is_empty());
+    let report = comp.verify_deep(VerifyOptions::new().hash_streams(true));
+    assert!(report.is_ok());
+    assert!(report.streams().is_empty());
+    assert!(report.structure_problems().is_empty());
+    assert_eq!(report.bytes_read(), 0);
+}
+
+//===========================================================================//
+
+#[test]
+fn empty_file_has_minimal_size() {
+    for version in [Version::V3, Version::V4] {
+        let data = create_empty(version);
+        assert_eq!(data.len(), minimal_len(version));
+        let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+        assert_eq!(comp.version(), version);
+        assert_empty(&mut comp);
+        let stats = comp.stats().unwrap();
+        assert_eq!(stats.num_sectors(), 2);
+        assert_eq!(stats.num_free_sectors(), 0);
+        assert_eq!(stats.num_fat_sectors(), 1);
+        assert_eq!(stats.num_difat_sectors(), 0);
+        assert_eq!(stats.num_dir_sectors(), 1);
+        assert_eq!(stats.num_minifat_sectors(), 0);
+        assert_eq!(stats.num_mini_stream_sectors(), 0);
+        assert_eq!(
+            stats.num_free_dir_entries() as usize,
+            version.dir_entries_per_sector() - 1
+        );
+        assert_eq!(
+            comp.raw_dir_entries().count(),
+            version.dir_entries_per_sector()
+        );
+    }
+}
+
+#[test]
+fn new_empty_file_matches_reopened_file() {
+    for version in [Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_version(version, cursor).unwrap();
+        let stats = comp.stats().unwrap();
+        let raw_entries: Vec<_> =
+            comp.raw_dir_entries().map(Result::unwrap).collect();
+        let mut reopened =
+            CompoundFile::open_strict(comp.into_inner()).unwrap();
+        assert_eq!(reopened.stats().unwrap(), stats);
+        let reopened_entries: Vec<_> =
+            reopened.raw_dir_entries().map(Result::unwrap).collect();
+        assert_eq!(reopened_entries, raw_entries);
+    }
+}
+
+#[test]
+fn empty_file_maintenance_is_a_no_op() {
+    for version in [Version::V3, Version::V4] {
+        let data = create_empty(version);
+        let mut comp =
+            CompoundFile::open_strict(Cursor::new(data.clone())).unwrap();
+        assert_eq!(comp.compact_estimate(), 0);
+        assert_eq!(comp.compact().unwrap(), data.len() as u64);
+        assert_eq!(comp.shrink_to_fit().unwrap(), data.len() as u64);
+        assert_eq!(comp.shrink_directory().unwrap(), 0);
+        assert_eq!(comp.repair().unwrap(), 0);
+        comp.flush().unwrap();
+        assert_empty(&mut comp);
+        assert_eq!(comp.into_inner().into_inner(), data);
+
+        let mut comp =
+            CompoundFile::open_strict(Cursor::new(data.clone())).unwrap();
+        let copy = comp.rewrite_canonical(Cursor::new(Vec::new())).unwrap();
+        assert_eq!(copy.into_inner().into_inner(), data);
+    }
+}
+
+#[test]
+fn convert_empty_file_between_versions() {
+    for (from, to) in [(Version::V3, Version::V4), (Version::V4, Version::V3)]
+    {
+        let mut source =
+            CompoundFile::open_strict(Cursor::new(create_empty(from)))
+                .unwrap();
+        let cursor = Cursor::new(Vec::new());
+        let mut dest = CompoundFile::create_with_version(to, cursor).unwrap();
+        dest.copy_storage_from(&mut source, "/", "/", false).unwrap();
+        dest.flush().unwrap();
+        let data = dest.into_inner().into_inner();
+        assert_eq!(data, create_empty(to));
+        let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+        assert_eq!(comp.version(), to);
+        assert_empty(&mut comp);
+    }
+}
+
+#[test]
+fn read_empty_file_with_empty_minifat() {
+    for version in [Version::V3, Version::V4] {
+        let data = with_empty_minifat(version, create_empty(version));
+        let mut comp =
+            CompoundFile::open_strict(Cursor::new(data.clone())).unwrap();
+        assert_empty(&mut comp);
+        let stats = comp.stats().unwrap();
+        assert_eq!(stats.num_minifat_sectors(), 1);
+        assert_eq!(stats.num_free_sectors(), 0);
+        let mut comp = CompoundFile::open(Cursor::new(data.clone())).unwrap();
+        assert_empty(&mut comp);
+
+        // The file can be written to, using the existing MiniFAT sector.
+        comp.create_stream("/small").unwrap().write_all(b"small").unwrap();
+        comp.flush().unwrap();
+        let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+        assert_eq!(comp.stats().unwrap().num_minifat_sectors(), 1);
+        let mut data = Vec::new();
+        comp.open_stream("/small").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"small");
+
+        // Compacting drops the MiniFAT sector if it isn't needed.
+        let data = with_empty_minifat(version, create_empty(version));
+        let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+        assert_eq!(comp.compact_estimate(), version.sector_len() as u64);
+        let len = comp.compact().unwrap();
+        assert_eq!(len, minimal_len(version) as u64);
+        let mut data = comp.into_inner().into_inner();
+        data.truncate(len as usize);
+        assert_eq!(data, create_empty(version));
+    }
+}
+
+//===========================================================================//
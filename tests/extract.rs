@@ -0,0 +1,240 @@
+//! Tests for extracting compound files into local directories and packing
+//! them back, checking that object names and metadata survive even when they
+//! can't be used as-is for local file names.
+
+#![cfg(feature = "cli")]
+
+use cfb::tool::{extract_all, insert_all, MANIFEST_FILE_NAME};
+use cfb::{CompoundFile, Version};
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// A scratch directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "cfb-extract-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+/// Names that filesystems mangle: the same letter precomposed (NFC) and
+/// decomposed (NFD), control characters, characters Windows forbids, a
+/// Windows device name, and two names (`K` and the Kelvin sign) that CFB
+/// considers distinct but that fold to the same lowercase.
+const AWKWARD_NAMES: &[&str] = &[
+    "caf\u{e9}",
+    "cafe\u{301}",
+    "tab\there",
+    "bell\u{7}",
+    "a*b?c<d>\"e|",
+    "CON",
+    "trailing.",
+    "K",
+    "\u{212a}",
+];
+
+fn make_fixture() -> Comp {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V4, cursor).unwrap();
+    let time = UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+    for (index, name) in AWKWARD_NAMES.iter().enumerate() {
+        let storage = format!("/{}", name);
+        comp.create_storage(&storage).unwrap();
+        comp.set_storage_clsid(&storage, Uuid::from_u128(index as u128 + 1))
+            .unwrap();
+        comp.set_state_bits(&storage, index as u32 * 3).unwrap();
+        comp.set_created_time(&storage, time).unwrap();
+        comp.set_modified_time(&storage, time + Duration::from_nanos(100))
+            .unwrap();
+        for (other, inner) in AWKWARD_NAMES.iter().enumerate() {
+            let stream = format!("{}/{}", storage, inner);
+            let data = format!("{} in {}", inner, name).into_bytes();
+            comp.create_stream(&stream).unwrap().write_all(&data).unwrap();
+            comp.set_state_bits(&stream, other as u32).unwrap();
+        }
+    }
+    // A storage whose local directory would differ only in case from the
+    // local file of a stream.
+    comp.create_storage("/MIXED.dump").unwrap();
+    comp.create_stream("/mixed").unwrap().write_all(b"mixed").unwrap();
+    comp.set_storage_clsid("/", Uuid::from_u128(0xabc)).unwrap();
+    comp.set_modified_time("/", time).unwrap();
+    comp
+}
+
+/// Returns a description of every object in the file, with its exact name,
+/// metadata, and (for streams) contents.
+fn describe(comp: &mut Comp) -> Vec<String> {
+    let entries: Vec<_> = comp.walk().collect();
+    let mut lines = Vec::new();
+    for entry in entries {
+        let mut line = format!(
+            "{:?} {} {} {:?} {:?}",
+            entry.path(),
+            entry.clsid(),
+            entry.state_bits(),
+            entry.created(),
+            entry.modified()
+        );
+        if entry.is_stream() {
+            let mut data = Vec::new();
+            comp.open_stream(entry.path())
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            line.push_str(&format!(" {:?}", data));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn empty_file() -> Comp {
+    let cursor = Cursor::new(Vec::new());
+    CompoundFile::create_with_version(Version::V4, cursor).unwrap()
+}
+
+//===========================================================================//
+
+#[test]
+fn awkward_names_round_trip() {
+    let dir = TempDir::new("awkward");
+    let mut comp = make_fixture();
+    let written = extract_all(&mut comp, Path::new("/"), dir.path()).unwrap();
+    assert_eq!(written.len(), AWKWARD_NAMES.len() * AWKWARD_NAMES.len() + 1);
+    assert!(dir.path().join(MANIFEST_FILE_NAME).is_file());
+
+    let mut packed = empty_file();
+    insert_all(&mut packed, Path::new("/"), dir.path()).unwrap();
+    assert_eq!(describe(&mut packed), describe(&mut comp));
+}
+
+/// Returns the names of the files and directories in a local directory.
+fn local_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+fn assert_distinct_ignoring_case(names: &[String]) {
+    let mut folded: Vec<String> =
+        names.iter().map(|name| name.to_lowercase()).collect();
+    folded.sort();
+    folded.dedup();
+    assert_eq!(folded.len(), names.len(), "{:?}", names);
+}
+
+#[test]
+fn local_names_are_portable_and_distinct() {
+    let dir = TempDir::new("local-names");
+    let mut comp = make_fixture();
+    extract_all(&mut comp, Path::new("/"), dir.path()).unwrap();
+    let names = local_names(dir.path());
+    assert_distinct_ignoring_case(&names);
+    assert!(names.contains(&"mixed.dump".to_string()), "{:?}", names);
+    assert!(names.contains(&"MIXED.dump~2".to_string()), "{:?}", names);
+    assert!(names.contains(&"\u{212a}~2".to_string()), "{:?}", names);
+
+    let names = local_names(&dir.path().join("K"));
+    assert_distinct_ignoring_case(&names);
+    for name in names.iter() {
+        assert!(
+            !name.chars().any(|chr| chr.is_control()
+                || "\\:*?\"<>|".contains(chr)
+                || chr == '\u{301}'),
+            "{:?}",
+            name
+        );
+    }
+    assert!(names.contains(&"_CON.dump".to_string()), "{:?}", names);
+    assert!(names.contains(&"trailing._.dump".to_string()), "{:?}", names);
+}
+
+#[test]
+fn pack_into_subtree() {
+    let dir = TempDir::new("subtree");
+    let mut comp = make_fixture();
+    extract_all(&mut comp, Path::new("/cafe\u{301}"), dir.path()).unwrap();
+
+    let mut packed = empty_file();
+    packed.create_storage("/copy").unwrap();
+    insert_all(&mut packed, Path::new("/copy"), dir.path()).unwrap();
+    let entry = packed.entry("/copy").unwrap();
+    assert_eq!(*entry.clsid(), Uuid::from_u128(2));
+    assert_eq!(entry.state_bits(), 3);
+    let mut data = String::new();
+    packed
+        .open_stream("/copy/caf\u{e9}")
+        .unwrap()
+        .read_to_string(&mut data)
+        .unwrap();
+    assert_eq!(data, "caf\u{e9} in cafe\u{301}");
+    assert!(packed.is_stream("/copy/cafe\u{301}"));
+    assert!(packed.is_stream("/copy/K"));
+    assert!(packed.is_stream("/copy/\u{212a}"));
+}
+
+#[test]
+fn pack_without_manifest_uses_local_names() {
+    let dir = TempDir::new("no-manifest");
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub").join("data.dump"), b"dumped").unwrap();
+    fs::write(dir.path().join("notes.txt"), b"plain").unwrap();
+
+    let mut packed = empty_file();
+    let mut written =
+        insert_all(&mut packed, Path::new("/"), dir.path()).unwrap();
+    written.sort();
+    assert_eq!(
+        written,
+        vec![PathBuf::from("/notes.txt"), PathBuf::from("/sub/data")]
+    );
+    assert!(packed.is_storage("/sub"));
+}
+
+#[test]
+fn manifest_cannot_escape_input_dir() {
+    let dir = TempDir::new("escape");
+    fs::write(
+        dir.path().join(MANIFEST_FILE_NAME),
+        "{\"version\": 1, \"entries\": [{\"local\": \"../secret.dump\", \
+         \"names\": [\"secret\"], \"type\": \"stream\", \"state_bits\": 0}]}",
+    )
+    .unwrap();
+    let mut packed = empty_file();
+    let error =
+        insert_all(&mut packed, Path::new("/"), dir.path()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(!packed.exists("/secret"));
+}
+
+//===========================================================================//
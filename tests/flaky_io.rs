@@ -0,0 +1,320 @@
+//! Runs the test fixtures through an underlying file that returns at most one
+//! byte per read or write, and fails with `ErrorKind::Interrupted` at
+//! random, and checks that everything comes out exactly as it does with a
+//! well-behaved `Cursor`: the same open errors and warnings, the same tree,
+//! the same stream contents, the same validation issues and verification
+//! results, and byte-for-byte the same file after writing.
+
+use cfb::{CompoundFile, VerifyOptions, Version};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const NUM_SEEDS: u64 = 3;
+
+/// A file that is as unhelpful as `Read` and `Write` allow.
+struct FlakyIo {
+    inner: Cursor<Vec<u8>>,
+    rng: Pcg32,
+    num_interrupts: u64,
+    /// If set, reads claim that the file ends at this offset, although it
+    /// doesn't.
+    eof_at: Option<u64>,
+}
+
+impl FlakyIo {
+    fn new(data: Vec<u8>, seed: u64) -> FlakyIo {
+        FlakyIo {
+            inner: Cursor::new(data),
+            rng: Pcg32::seed_from_u64(seed),
+            num_interrupts: 0,
+            eof_at: None,
+        }
+    }
+
+    fn interrupt(&mut self) -> io::Result<()> {
+        if self.rng.gen_ratio(1, 3) {
+            self.num_interrupts += 1;
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "flaky"));
+        }
+        Ok(())
+    }
+}
+
+impl Read for FlakyIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt()?;
+        if self.eof_at.is_some_and(|eof| self.inner.position() >= eof) {
+            return Ok(0);
+        }
+        let len = buf.len().min(1);
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+impl Write for FlakyIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.interrupt()?;
+        let len = buf.len().min(1);
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interrupt()?;
+        self.inner.flush()
+    }
+}
+
+impl Seek for FlakyIo {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+//===========================================================================//
+
+fn fixture_dir(dir: &str) -> Vec<(String, Vec<u8>)> {
+    let mut fixtures: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            (path.display().to_string(), fs::read(&path).unwrap())
+        })
+        .collect();
+    fixtures.sort();
+    fixtures
+}
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31) ^ seed).collect()
+}
+
+/// Applies the same sequence of changes to any compound file.
+fn populate<F: Read + Write + Seek>(comp: &mut CompoundFile<F>) {
+    comp.set_storage_clsid("/", Uuid::from_u128(0x1234)).unwrap();
+    comp.create_storage("/sub").unwrap();
+    comp.create_storage("/sub/deeper").unwrap();
+    comp.set_state_bits("/sub", 0xabcd).unwrap();
+    comp.create_stream("/small").unwrap().write_all(&data(100, 1)).unwrap();
+    comp.create_stream("/sub/large")
+        .unwrap()
+        .write_all(&data(20_000, 2))
+        .unwrap();
+    comp.create_stream("/sub/deeper/edge")
+        .unwrap()
+        .write_all(&data(4096, 3))
+        .unwrap();
+    comp.create_stream("/empty").unwrap();
+    comp.create_stream("/doomed").unwrap().write_all(&data(9000, 4)).unwrap();
+    comp.flush().unwrap();
+    let mut stream = comp.open_stream("/sub/large").unwrap();
+    stream.seek(SeekFrom::Start(5000)).unwrap();
+    stream.write_all(&data(700, 5)).unwrap();
+    stream.set_len(12_345).unwrap();
+    drop(stream);
+    comp.open_stream("/small").unwrap().set_len(5000).unwrap();
+    comp.remove_stream("/doomed").unwrap();
+    comp.rename("/sub/deeper/edge", "/edge").unwrap();
+    fix_timestamps(comp);
+    comp.flush().unwrap();
+}
+
+/// Sets every storage's timestamps to a fixed time, so that files written
+/// at different times can be compared byte for byte.
+fn fix_timestamps<F: Read + Write + Seek>(comp: &mut CompoundFile<F>) {
+    let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    let storages: Vec<_> = comp
+        .walk()
+        .filter(|entry| entry.is_storage())
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    for path in storages {
+        comp.set_modified_time(&path, time).unwrap();
+        if path != Path::new("/") {
+            comp.set_created_time(&path, time).unwrap();
+        }
+    }
+}
+
+fn generated_file(version: Version) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    populate(&mut comp);
+    comp.into_inner().into_inner()
+}
+
+/// Returns every fixture to run through both kinds of file: the fuzzed
+/// files from the other tests, freshly generated files of each version,
+/// and copies of those cut short at various points.
+fn fixtures() -> Vec<(String, Vec<u8>)> {
+    let mut fixtures = fixture_dir("tests/infinite_loops_fuzzed");
+    fixtures.extend(fixture_dir("tests/panics_fuzzed"));
+    for version in [Version::V3, Version::V4] {
+        let data = generated_file(version);
+        let name = format!("generated {:?}", version);
+        for cut in [100, 512, 1000, data.len() / 2, data.len() - 1] {
+            let truncated = data[..cut].to_vec();
+            fixtures.push((format!("{} cut to {}", name, cut), truncated));
+        }
+        fixtures.push((name, data));
+    }
+    fixtures
+}
+
+//===========================================================================//
+
+/// Describes everything that reading the file reports, as lines of text.
+fn describe<F: Read + Seek>(inner: F) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut comp = match CompoundFile::open(inner) {
+        Ok(comp) => comp,
+        Err(error) => {
+            lines.push(format!("open: {:?}: {}", error.kind(), error));
+            return lines;
+        }
+    };
+    lines.push(format!("open warnings: {:?}", comp.open_warnings()));
+    lines.push(format!("validate: {:?}", comp.validate()));
+    let entries: Vec<_> = comp.walk().collect();
+    for entry in entries {
+        lines.push(format!(
+            "{:?} stream={} len={} clsid={} state={:x}",
+            entry.path(),
+            entry.is_stream(),
+            entry.len(),
+            entry.clsid(),
+            entry.state_bits()
+        ));
+        if entry.is_stream() {
+            match comp.read_stream_to_vec(entry.path()) {
+                Ok(contents) => lines.push(format!("  = {:?}", contents)),
+                Err(error) => {
+                    lines.push(format!("  ! {:?}: {}", error.kind(), error))
+                }
+            }
+        }
+    }
+    let report = comp.verify_deep(VerifyOptions::new().hash_streams(true));
+    lines.push(format!("structure: {:?}", report.structure_problems()));
+    for stream in report.streams() {
+        lines.push(format!(
+            "verify {:?}: {} of {} bytes, hash {:?}, error {:?}",
+            stream.path(),
+            stream.bytes_read(),
+            stream.len(),
+            stream.hash(),
+            stream.error().map(|error| error.to_string())
+        ));
+    }
+    lines
+}
+
+fn describe_recovered<F: Read + Seek>(inner: F) -> Vec<String> {
+    match CompoundFile::open_recover(inner) {
+        Ok((comp, warnings)) => {
+            let paths: Vec<_> =
+                comp.walk().map(|entry| entry.path().to_path_buf()).collect();
+            vec![format!("{:?}", warnings), format!("{:?}", paths)]
+        }
+        Err(error) => vec![format!("{:?}: {}", error.kind(), error)],
+    }
+}
+
+//===========================================================================//
+
+#[test]
+fn reads_match_well_behaved_reader() {
+    for (name, data) in fixtures() {
+        let expected = describe(Cursor::new(data.clone()));
+        let expected_recovered = describe_recovered(Cursor::new(data.clone()));
+        for seed in 0..NUM_SEEDS {
+            let actual = describe(FlakyIo::new(data.clone(), seed));
+            assert_eq!(actual, expected, "{} (seed {})", name, seed);
+            let recovered =
+                describe_recovered(FlakyIo::new(data.clone(), seed));
+            assert_eq!(
+                recovered, expected_recovered,
+                "{} (seed {})",
+                name, seed
+            );
+        }
+    }
+}
+
+#[test]
+fn end_of_file_errors_say_whether_file_really_ends() {
+    let data = generated_file(Version::V3);
+    let flaky = FlakyIo::new(data[..1000].to_vec(), 0);
+    let error = CompoundFile::open(flaky).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(
+        error.to_string(),
+        "Unexpected end of file: needed 24 more bytes at offset 1000, but \
+         the file is only 1000 bytes long"
+    );
+
+    let mut flaky = FlakyIo::new(data.clone(), 0);
+    flaky.eof_at = Some(1000);
+    let error = CompoundFile::open(flaky).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "Underlying reader returned end-of-file at offset 1000 with 24 \
+             bytes still to read, although the file is {} bytes long",
+            data.len()
+        )
+    );
+}
+
+#[test]
+fn writes_match_well_behaved_writer() {
+    for version in [Version::V3, Version::V4] {
+        let expected = generated_file(version);
+        for seed in 0..NUM_SEEDS {
+            let flaky = FlakyIo::new(Vec::new(), seed);
+            let mut comp =
+                CompoundFile::create_with_version(version, flaky).unwrap();
+            populate(&mut comp);
+            let flaky = comp.into_inner();
+            assert!(flaky.num_interrupts > 0);
+            let actual = flaky.inner.into_inner();
+            assert!(actual == expected, "{:?} (seed {})", version, seed);
+        }
+    }
+}
+
+#[test]
+fn modifying_existing_file_matches_well_behaved_writer() {
+    fn modify<F: Read + Write + Seek>(comp: &mut CompoundFile<F>) {
+        comp.open_stream("/sub/large")
+            .unwrap()
+            .write_all(&data(3000, 6))
+            .unwrap();
+        comp.create_stream("/new").unwrap().write_all(&data(6000, 7)).unwrap();
+        comp.remove_storage_all("/sub").unwrap();
+        fix_timestamps(comp);
+        comp.flush().unwrap();
+        comp.compact().unwrap();
+    }
+
+    let original = generated_file(Version::V3);
+    let mut comp = CompoundFile::open(Cursor::new(original.clone())).unwrap();
+    modify(&mut comp);
+    let expected = comp.into_inner().into_inner();
+    for seed in 0..NUM_SEEDS {
+        let flaky = FlakyIo::new(original.clone(), seed);
+        let mut comp = CompoundFile::open(flaky).unwrap();
+        modify(&mut comp);
+        let actual = comp.into_inner().inner.into_inner();
+        assert!(actual == expected, "seed {}", seed);
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,131 @@
+use cfb::{CompoundFile, Version};
+use rawcfb::named_dir_entry_offset;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+mod rawcfb;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+/// Overwrites the declared length of the named stream.
+fn set_declared_len(data: &mut [u8], name: &str, len: u64) {
+    let offset = named_dir_entry_offset(data, name) + 120;
+    data[offset..offset + 8].copy_from_slice(&len.to_le_bytes());
+}
+
+/// Returns a file whose streams have meaningful bytes hidden past their
+/// declared lengths: "mini" lives in the mini stream, and "big" in regular
+/// sectors.
+fn hidden_data_file() -> (TestFile, Vec<u8>, Vec<u8>) {
+    let mini: Vec<u8> =
+        [b"public".as_slice(), &[b'm'; 44], b"SECRET", &[b's'; 144]].concat();
+    let big: Vec<u8> =
+        [vec![b'b'; 5000], b"HIDDEN".to_vec(), vec![b'h'; 4994]].concat();
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/mini").unwrap().write_all(&mini).unwrap();
+    comp.create_stream("/big").unwrap().write_all(&big).unwrap();
+    comp.create_storage("/dir").unwrap();
+    comp.flush().unwrap();
+    let mut data = comp.into_inner().into_inner();
+    set_declared_len(&mut data, "mini", 50);
+    set_declared_len(&mut data, "big", 5000);
+    let comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    (comp, mini, big)
+}
+
+fn read_all(comp: &mut TestFile, path: &str, full_chain: bool) -> Vec<u8> {
+    let mut stream = if full_chain {
+        comp.open_stream_full_chain(path).unwrap()
+    } else {
+        comp.open_stream(path).unwrap()
+    };
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+    assert_eq!(data.len() as u64, stream.len());
+    data
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+//===========================================================================//
+
+#[test]
+fn recover_data_past_declared_length() {
+    let (mut comp, mini, big) = hidden_data_file();
+
+    let entry = comp.entry("/mini").unwrap();
+    assert_eq!(entry.len(), 50);
+    assert_eq!(entry.readable_len(), 50);
+    assert_eq!(entry.chain_len(), 256);
+    let entry = comp.entry("/big").unwrap();
+    assert_eq!(entry.len(), 5000);
+    assert_eq!(entry.chain_len(), 10_240);
+    assert_eq!(comp.entry("/dir").unwrap().chain_len(), 0);
+
+    // The normal API stops at the declared length.
+    let data = read_all(&mut comp, "/mini", false);
+    assert_eq!(data, mini[..50]);
+    assert!(!contains(&data, b"SECRET"));
+    let data = read_all(&mut comp, "/big", false);
+    assert_eq!(data, big[..5000]);
+    assert!(!contains(&data, b"HIDDEN"));
+
+    // The full chain includes the slack, with the hidden data in it.
+    let data = read_all(&mut comp, "/mini", true);
+    assert_eq!(data.len(), 256);
+    assert_eq!(data[..200], mini[..]);
+    assert!(contains(&data, b"SECRET"));
+    let data = read_all(&mut comp, "/big", true);
+    assert_eq!(data.len(), 10_240);
+    assert_eq!(data[..10_000], big[..]);
+    assert!(contains(&data, b"HIDDEN"));
+}
+
+#[test]
+fn full_chain_stream_seeks_and_is_read_only() {
+    let (mut comp, mini, _) = hidden_data_file();
+    let mut stream = comp.open_stream_full_chain("/mini").unwrap();
+    assert_eq!(stream.seek(SeekFrom::Start(50)).unwrap(), 50);
+    let mut buf = [0; 6];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"SECRET");
+    assert_eq!(stream.seek(SeekFrom::End(0)).unwrap(), 256);
+
+    assert_eq!(
+        stream.write(b"x").unwrap_err().kind(),
+        ErrorKind::PermissionDenied
+    );
+    assert_eq!(
+        stream.set_len(10).unwrap_err().kind(),
+        ErrorKind::PermissionDenied
+    );
+    drop(stream);
+    assert_eq!(comp.entry("/mini").unwrap().len(), 50);
+    assert_eq!(read_all(&mut comp, "/mini", true)[..200], mini[..]);
+
+    let error = comp.open_stream_full_chain("/dir").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.open_stream_full_chain("/nope").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn well_formed_streams_have_only_rounding_slack() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream("/empty").unwrap();
+    comp.create_stream("/small").unwrap().write_all(&[1; 65]).unwrap();
+    let entry = comp.entry("/empty").unwrap();
+    assert_eq!((entry.len(), entry.chain_len()), (0, 0));
+    let entry = comp.entry("/small").unwrap();
+    assert_eq!((entry.len(), entry.chain_len()), (65, 128));
+    let data = read_all(&mut comp, "/small", true);
+    assert_eq!(data[..65], [1; 65]);
+    assert_eq!(read_all(&mut comp, "/empty", true), b"");
+}
+
+//===========================================================================//
@@ -0,0 +1,159 @@
+use cfb::{
+    CompoundFile, StreamId, TooLargeToBuffer, ValidationIssueKind, Version,
+};
+use rawcfb::dir_entry_offset;
+use std::io::{Cursor, Write};
+
+mod rawcfb;
+
+//===========================================================================//
+
+const MARKER: &[u8] = b"<<HIDDEN MARKER>>";
+
+/// Returns `len` bytes of filler with `MARKER` at the given offset.
+fn data_with_marker(len: usize, marker_offset: usize) -> Vec<u8> {
+    let mut data = vec![b'.'; len];
+    data[marker_offset..(marker_offset + MARKER.len())]
+        .copy_from_slice(MARKER);
+    data
+}
+
+fn issue_kinds<F>(comp: &CompoundFile<F>) -> Vec<ValidationIssueKind> {
+    comp.open_warnings().iter().map(|issue| issue.kind()).collect()
+}
+
+/// Creates a V3 file with a stream "/stream" of `len` bytes with `MARKER` at
+/// `marker_offset`, then shrinks the stream's declared length to `new_len`
+/// without touching its chain.
+fn make_oversized(len: usize, marker_offset: usize, new_len: u64) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    let data = data_with_marker(len, marker_offset);
+    comp.create_stream("/stream").unwrap().write_all(&data).unwrap();
+    let mut data = comp.into_inner().into_inner();
+    let offset = dir_entry_offset(&data, 1);
+    assert_eq!(&data[offset..(offset + 4)], b"s\0t\0");
+    data[(offset + 120)..(offset + 128)]
+        .copy_from_slice(&new_len.to_le_bytes());
+    data
+}
+
+/// Creates a V3 file with streams "/keep" (entry 1) and "/gone" (entry 2),
+/// the latter `len` bytes long with `MARKER` at `marker_offset`.  Then
+/// deletes "/gone" by unlinking it from the tree and marking its entry
+/// unallocated, but leaves its starting sector, length, and chain in place.
+fn make_stale(len: usize, marker_offset: usize) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/keep").unwrap().write_all(b"kept").unwrap();
+    let data = data_with_marker(len, marker_offset);
+    comp.create_stream("/gone").unwrap().write_all(&data).unwrap();
+    let mut data = comp.into_inner().into_inner();
+    let keep = dir_entry_offset(&data, 1);
+    let gone = dir_entry_offset(&data, 2);
+    assert_eq!(data[(keep + 68)..(keep + 72)], 2u32.to_le_bytes());
+    data[(keep + 68)..(keep + 72)].copy_from_slice(&[0xff; 4]);
+    assert_eq!(data[gone + 66], 2);
+    data[gone + 66] = 0;
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn well_formed_file_has_no_hidden_chains() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/big").unwrap().write_all(&[1; 5000]).unwrap();
+    comp.create_stream("/small").unwrap().write_all(&[2; 100]).unwrap();
+    comp.create_stream("/removed").unwrap().write_all(&[3; 5000]).unwrap();
+    comp.remove_stream("/removed").unwrap();
+    let cursor = comp.into_inner();
+    let mut comp = CompoundFile::open_strict(cursor).unwrap();
+    assert_eq!(issue_kinds(&comp), vec![]);
+    assert!(comp.stale_chains().is_empty());
+    assert_eq!(comp.read_stream_slack("/big").unwrap(), vec![0; 120]);
+    assert_eq!(comp.read_stream_slack("/small").unwrap(), vec![0; 28]);
+}
+
+#[test]
+fn recover_marker_from_oversized_chain() {
+    // 10 sectors of data, but a declared length that needs only 8.
+    let data = make_oversized(5000, 4500, 4096);
+    let mut comp = CompoundFile::open(Cursor::new(data.clone())).unwrap();
+    assert_eq!(issue_kinds(&comp), vec![ValidationIssueKind::ChainSlack]);
+    assert_eq!(comp.entry("/stream").unwrap().len(), 4096);
+    let slack = comp.read_stream_slack("/stream").unwrap();
+    assert_eq!(slack.len(), 1024);
+    assert_eq!(&slack[404..(404 + MARKER.len())], MARKER);
+
+    // Hidden data isn't a reason to reject the file outright.
+    let comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert_eq!(issue_kinds(&comp), vec![ValidationIssueKind::ChainSlack]);
+}
+
+#[test]
+fn recover_marker_from_oversized_mini_chain() {
+    // 4 mini sectors of data, but a declared length that needs only 2.
+    let data = make_oversized(250, 200, 100);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert_eq!(issue_kinds(&comp), vec![ValidationIssueKind::ChainSlack]);
+    let slack = comp.read_stream_slack("/stream").unwrap();
+    assert_eq!(slack.len(), 156);
+    assert_eq!(&slack[100..(100 + MARKER.len())], MARKER);
+}
+
+#[test]
+fn recover_marker_from_stale_chain() {
+    let data = make_stale(5000, 4900);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert!(issue_kinds(&comp).contains(&ValidationIssueKind::StaleChain));
+    assert!(!comp.exists("/gone"));
+    assert_eq!(comp.stale_chains(), vec![StreamId::new(2)]);
+    let chain = comp.read_stale_chain(StreamId::new(2)).unwrap();
+    assert_eq!(chain.len(), 5120);
+    assert_eq!(&chain[4900..(4900 + MARKER.len())], MARKER);
+
+    let error = comp.read_stale_chain(StreamId::new(1)).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+
+    // Reading the chain is subject to the buffering limit.
+    comp.set_max_buffer_len(5119);
+    let error = comp.read_stale_chain(StreamId::new(2)).unwrap_err();
+    let too_large = TooLargeToBuffer::from_io_error(&error).unwrap();
+    assert_eq!(too_large.path(), std::path::Path::new(""));
+    assert_eq!(too_large.len(), 5120);
+}
+
+#[test]
+fn recover_marker_from_stale_mini_chain() {
+    let data = make_stale(100, 70);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert!(issue_kinds(&comp).contains(&ValidationIssueKind::StaleChain));
+    assert_eq!(comp.stale_chains(), vec![StreamId::new(2)]);
+    let chain = comp.read_stale_chain(StreamId::new(2)).unwrap();
+    assert_eq!(chain.len(), 128);
+    assert_eq!(&chain[70..(70 + MARKER.len())], MARKER);
+}
+
+#[test]
+fn stale_chain_reused_by_live_stream_is_not_reported() {
+    let data = make_stale(5000, 4900);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    // Point the live stream at the same chain; the old entry's reference is
+    // then no longer the only one.
+    let start_sector =
+        comp.raw_dir_entry(StreamId::new(2)).unwrap()[116..120].to_vec();
+    let mut data = comp.into_inner().into_inner();
+    let keep = dir_entry_offset(&data, 1);
+    data[(keep + 116)..(keep + 120)].copy_from_slice(&start_sector);
+    data[(keep + 120)..(keep + 128)].copy_from_slice(&5000u64.to_le_bytes());
+    let comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert!(!issue_kinds(&comp).contains(&ValidationIssueKind::StaleChain));
+    assert!(comp.stale_chains().is_empty());
+}
+
+//===========================================================================//
@@ -0,0 +1,73 @@
+use cfb::{consts, CompoundFile, ObjType, SectorId, StreamId, Version};
+use std::io::{Cursor, Write};
+
+//===========================================================================//
+
+#[test]
+fn constants_match_spec() {
+    // MS-CFB section 2.1.
+    assert_eq!(consts::MAX_REGULAR_SECTOR, 0xfffffffa);
+    assert_eq!(consts::DIFAT_SECTOR, 0xfffffffc);
+    assert_eq!(consts::FAT_SECTOR, 0xfffffffd);
+    assert_eq!(consts::END_OF_CHAIN, 0xfffffffe);
+    assert_eq!(consts::FREE_SECTOR, 0xffffffff);
+    assert_eq!(consts::MAX_REGULAR_STREAM_ID, 0xfffffffa);
+    assert_eq!(consts::NO_STREAM, 0xffffffff);
+    // MS-CFB section 2.6.1.
+    assert_eq!(consts::OBJ_TYPE_UNALLOCATED, 0x00);
+    assert_eq!(consts::OBJ_TYPE_STORAGE, 0x01);
+    assert_eq!(consts::OBJ_TYPE_STREAM, 0x02);
+    assert_eq!(consts::OBJ_TYPE_ROOT, 0x05);
+    assert_eq!(consts::COLOR_RED, 0x00);
+    assert_eq!(consts::COLOR_BLACK, 0x01);
+}
+
+#[test]
+fn newtype_constants_match_spec() {
+    assert_eq!(SectorId::MAX_REGULAR.value(), consts::MAX_REGULAR_SECTOR);
+    assert_eq!(SectorId::DIFAT.value(), consts::DIFAT_SECTOR);
+    assert_eq!(SectorId::FAT.value(), consts::FAT_SECTOR);
+    assert_eq!(SectorId::END_OF_CHAIN.value(), consts::END_OF_CHAIN);
+    assert_eq!(SectorId::FREE.value(), consts::FREE_SECTOR);
+    assert_eq!(StreamId::ROOT.value(), 0);
+    assert_eq!(StreamId::MAX_REGULAR.value(), consts::MAX_REGULAR_STREAM_ID);
+    assert_eq!(StreamId::NONE.value(), consts::NO_STREAM);
+    assert_eq!(ObjType::Storage.as_byte(), consts::OBJ_TYPE_STORAGE);
+    assert_eq!(ObjType::from_byte(consts::OBJ_TYPE_ROOT), Some(ObjType::Root));
+}
+
+#[test]
+fn fat_dump_is_readable() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/foo").unwrap().write_all(&[1; 1024]).unwrap();
+    comp.flush().unwrap();
+    let fat = comp.raw_fat();
+    // Sector 0 is the FAT itself, sector 1 the directory, and sector 2 the
+    // MiniFAT; the 1024-byte stream is below the mini stream cutoff, so it
+    // fills the two-sector mini stream.
+    assert_eq!(fat[0], SectorId::FAT);
+    assert_eq!(
+        format!("{:?}", fat),
+        "[FAT, END_OF_CHAIN, END_OF_CHAIN, 4, END_OF_CHAIN]"
+    );
+}
+
+#[test]
+fn raw_ids_render_by_name() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.create_storage("/foo").unwrap();
+    let raw = comp.raw_dir_entry(StreamId::ROOT).unwrap();
+    let sibling = |offset: usize| {
+        let bytes =
+            [raw[offset], raw[offset + 1], raw[offset + 2], raw[offset + 3]];
+        StreamId::new(u32::from_le_bytes(bytes))
+    };
+    assert_eq!(format!("{:?}", (sibling(68), sibling(72))), "(NONE, NONE)");
+    assert_eq!(sibling(76).to_string(), "1");
+    assert_eq!(ObjType::from_byte(raw[66]), Some(ObjType::Root));
+}
+
+//===========================================================================//
@@ -0,0 +1,282 @@
+use cfb::{CompoundFile, ImportOptions};
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+//===========================================================================//
+
+/// A scratch directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "cfb-import-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn write(&self, name: &str, data: &[u8]) {
+        let path = self.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn new_file() -> CompoundFile<Cursor<Vec<u8>>> {
+    CompoundFile::create(Cursor::new(Vec::new())).unwrap()
+}
+
+fn read_stream<F: Read + io::Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn paths(paths: &[PathBuf]) -> Vec<&str> {
+    paths.iter().map(|path| path.to_str().unwrap()).collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn import_tree() {
+    let dir = TempDir::new("tree");
+    dir.write("src/hello.txt", b"Hello, world!");
+    dir.write("src/sub/big", &[7; 100_000]);
+    dir.write("src/sub/deeper/empty", b"");
+    fs::create_dir(dir.join("src/empty_dir")).unwrap();
+
+    let mut comp = new_file();
+    let report = comp
+        .create_storage_from_dir(dir.join("src"), "/a/b", ImportOptions::new())
+        .unwrap();
+    assert!(report.is_complete());
+    assert_eq!(
+        paths(report.created()),
+        [
+            "/a/b",
+            "/a/b/empty_dir",
+            "/a/b/hello.txt",
+            "/a/b/sub",
+            "/a/b/sub/big",
+            "/a/b/sub/deeper",
+            "/a/b/sub/deeper/empty",
+        ]
+    );
+    assert_eq!(report.num_bytes(), 100_013);
+    assert_eq!(read_stream(&mut comp, "/a/b/hello.txt"), b"Hello, world!");
+    assert_eq!(read_stream(&mut comp, "/a/b/sub/big"), vec![7; 100_000]);
+    assert!(comp.is_storage("/a/b/empty_dir"));
+    assert_eq!(comp.entry("/a/b/sub/deeper/empty").unwrap().len(), 0);
+
+    // Importing again merges into the existing storages, but doesn't
+    // replace existing streams.
+    dir.write("src/sub/new", b"new");
+    let report = comp
+        .create_storage_from_dir(dir.join("src"), "/a/b", ImportOptions::new())
+        .unwrap();
+    assert_eq!(paths(report.created()), ["/a/b/sub/new"]);
+    assert_eq!(report.failures().len(), 3);
+    for failure in report.failures() {
+        assert_eq!(failure.error().kind(), io::ErrorKind::AlreadyExists);
+    }
+    assert_eq!(failure_names(&report), ["big", "empty", "hello.txt"]);
+}
+
+fn failure_names(report: &cfb::ImportReport) -> Vec<String> {
+    let mut names: Vec<String> = report
+        .failures()
+        .iter()
+        .map(|failure| {
+            failure.local_path().file_name().unwrap().to_str().unwrap().into()
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn invalid_names_are_reported_per_entry() {
+    let dir = TempDir::new("names");
+    dir.write("src/ok", b"ok");
+    dir.write("src/a:b", b"colon");
+    dir.write("src/bang!", b"bang");
+    dir.write("src/back\\slash", b"backslash");
+    dir.write(&format!("src/{}", "x".repeat(32)), b"long");
+    dir.write(&format!("src/{}", "y".repeat(31)), b"longest");
+    dir.write("src/bad:dir/inside", b"never imported");
+
+    let mut comp = new_file();
+    let report = comp
+        .create_storage_from_dir(dir.join("src"), "/", ImportOptions::new())
+        .unwrap();
+    assert!(!report.is_complete());
+    assert_eq!(
+        failure_names(&report),
+        ["a:b", "back\\slash", "bad:dir", "bang!", &"x".repeat(32)]
+    );
+    for failure in report.failures() {
+        assert_eq!(failure.error().kind(), io::ErrorKind::InvalidInput);
+        assert!(failure.to_string().contains("Object name"), "{}", failure);
+    }
+    let names: Vec<String> =
+        comp.read_root_storage().map(|entry| entry.name().into()).collect();
+    assert_eq!(names, ["ok", &"y".repeat(31)]);
+}
+
+#[test]
+fn map_names() {
+    let dir = TempDir::new("map");
+    dir.write("src/Table.dump", b"table");
+    dir.write("src/dir.dump/data.dump", b"data");
+    dir.write("src/a:b", b"renamed");
+    let options = ImportOptions::new().map_names(|name, is_storage| {
+        let name = name.replace(':', "_");
+        match name.strip_suffix(".dump") {
+            Some(stem) if !is_storage => stem.to_string(),
+            _ => name,
+        }
+    });
+    let mut comp = new_file();
+    let report =
+        comp.create_storage_from_dir(dir.join("src"), "/", options).unwrap();
+    assert!(report.is_complete());
+    assert_eq!(read_stream(&mut comp, "/Table"), b"table");
+    assert_eq!(read_stream(&mut comp, "/dir.dump/data"), b"data");
+    assert_eq!(read_stream(&mut comp, "/a_b"), b"renamed");
+}
+
+#[test]
+fn preserve_times() {
+    let dir = TempDir::new("times");
+    dir.write("src/sub/file", b"data");
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    for path in ["src/sub", "src"] {
+        let file = fs::File::open(dir.join(path)).unwrap();
+        file.set_modified(time).unwrap();
+    }
+
+    let mut comp = new_file();
+    comp.create_storage_from_dir(
+        dir.join("src"),
+        "/plain",
+        ImportOptions::new(),
+    )
+    .unwrap();
+    assert_ne!(comp.entry("/plain/sub").unwrap().modified(), time);
+    let options = ImportOptions::new().preserve_times(true);
+    comp.create_storage_from_dir(dir.join("src"), "/kept", options).unwrap();
+    assert_eq!(comp.entry("/kept").unwrap().modified(), time);
+    assert_eq!(comp.entry("/kept/sub").unwrap().modified(), time);
+    // Streams keep their zero timestamps.
+    let zero = UNIX_EPOCH - Duration::from_secs(11_644_473_600);
+    assert_eq!(comp.entry("/kept/sub/file").unwrap().modified(), zero);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new("symlinks");
+    dir.write("target/file", b"target");
+    dir.write("src/real", b"real");
+    symlink(dir.join("target"), dir.join("src/linked_dir")).unwrap();
+    symlink(dir.join("target/file"), dir.join("src/linked_file")).unwrap();
+    symlink(dir.join("src"), dir.join("src/loop")).unwrap();
+    symlink(dir.join("nowhere"), dir.join("src/dangling")).unwrap();
+
+    let mut comp = new_file();
+    let report = comp
+        .create_storage_from_dir(dir.join("src"), "/", ImportOptions::new())
+        .unwrap();
+    assert!(report.is_complete());
+    let skipped: Vec<_> = report
+        .skipped()
+        .iter()
+        .map(|path| path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(skipped, ["dangling", "linked_dir", "linked_file", "loop"]);
+    assert_eq!(paths(report.created()), ["/real"]);
+
+    let mut comp = new_file();
+    let options = ImportOptions::new().follow_symlinks(true);
+    let report =
+        comp.create_storage_from_dir(dir.join("src"), "/", options).unwrap();
+    assert!(report.skipped().is_empty());
+    assert_eq!(failure_names(&report), ["dangling", "loop"]);
+    assert_eq!(read_stream(&mut comp, "/linked_dir/file"), b"target");
+    assert_eq!(read_stream(&mut comp, "/linked_file"), b"target");
+    assert_eq!(read_stream(&mut comp, "/real"), b"real");
+}
+
+#[test]
+fn create_from_dir() {
+    let dir = TempDir::new("create");
+    dir.write("src/sub/stream", b"contents");
+    let path = dir.join("out.cfb");
+    let report =
+        cfb::create_from_dir(dir.join("src"), &path, ImportOptions::new())
+            .unwrap();
+    assert_eq!(paths(report.created()), ["/sub", "/sub/stream"]);
+    let mut comp =
+        CompoundFile::open_strict(fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/sub/stream"), b"contents");
+
+    let error = cfb::create_from_dir(
+        dir.join("src/sub/stream"),
+        dir.join("other.cfb"),
+        ImportOptions::new(),
+    )
+    .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(!dir.join("other.cfb").exists());
+}
+
+#[test]
+fn destination_must_be_a_storage() {
+    let dir = TempDir::new("dest");
+    dir.write("src/file", b"data");
+    let mut comp = new_file();
+    comp.create_stream("/stream").unwrap().write_all(b"x").unwrap();
+    let error = comp
+        .create_storage_from_dir(
+            dir.join("src"),
+            "/stream",
+            ImportOptions::new(),
+        )
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let error = comp
+        .create_storage_from_dir(
+            dir.join("missing"),
+            "/",
+            ImportOptions::new(),
+        )
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    let names: Vec<_> =
+        comp.walk().map(|entry| entry.path().to_owned()).collect();
+    assert_eq!(names, [Path::new("/"), Path::new("/stream")]);
+}
+
+//===========================================================================//
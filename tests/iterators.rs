@@ -0,0 +1,196 @@
+//! Tests that the iterators returned by `CompoundFile` stay finished once
+//! they end, stop after yielding an error, and give correct size hints.
+
+use cfb::{CompoundFile, StreamId, Version};
+use std::cell::Cell;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::iter::FusedIterator;
+use std::rc::Rc;
+
+//===========================================================================//
+
+/// A cursor whose reads start failing once its shared flag is set.
+struct FlakyFile {
+    inner: Cursor<Vec<u8>>,
+    failing: Rc<Cell<bool>>,
+}
+
+impl Read for FlakyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.failing.get() {
+            return Err(io::Error::other("flaky read"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for FlakyFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Returns a V3 file with the tree:
+///
+/// ```text
+/// /
+/// ├── big      (5000 bytes)
+/// ├── foo/
+/// │   ├── bar  (3 bytes)
+/// │   └── baz/
+/// └── quux     (3 bytes)
+/// ```
+fn make_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/big").unwrap().write_all(&[7; 5000]).unwrap();
+    comp.create_storage("/foo").unwrap();
+    comp.create_stream("/foo/bar").unwrap().write_all(b"bar").unwrap();
+    comp.create_storage("/foo/baz").unwrap();
+    comp.create_stream("/quux").unwrap().write_all(b"qux").unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Steps through the iterator made by `make`, checking before each step that
+/// its size hint brackets the number of items left (as counted from another
+/// iterator made the same way), and then that it keeps returning `None` once
+/// finished.  Returns the number of items.
+fn check_fused<I, M>(make: M) -> usize
+where
+    I: FusedIterator,
+    M: Fn() -> I,
+{
+    let total = make().count();
+    let mut iter = make();
+    for remaining in (0..=total).rev() {
+        let (lower, upper) = iter.size_hint();
+        assert!(lower <= remaining, "lower {} > {}", lower, remaining);
+        assert!(
+            upper.map_or(true, |upper| remaining <= upper),
+            "upper {:?} < {}",
+            upper,
+            remaining
+        );
+        assert_eq!(iter.next().is_some(), remaining > 0);
+    }
+    for _ in 0..3 {
+        assert!(iter.next().is_none());
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+    }
+    total
+}
+
+//===========================================================================//
+
+#[test]
+fn entry_iterators_are_fused() {
+    let comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    assert_eq!(check_fused(|| comp.walk()), 6);
+    assert_eq!(check_fused(|| comp.iter_entries_physical()), 6);
+    assert_eq!(check_fused(|| comp.walk_storage("/foo").unwrap()), 3);
+    assert_eq!(check_fused(|| comp.walk_relative("/foo").unwrap()), 3);
+    assert_eq!(check_fused(|| comp.read_root_storage()), 3);
+    assert_eq!(check_fused(|| comp.read_storage("/foo").unwrap()), 2);
+    assert_eq!(check_fused(|| comp.read_storage("/foo/baz").unwrap()), 0);
+}
+
+#[test]
+fn entry_iterators_yield_no_duplicates_when_polled_past_the_end() {
+    let comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let mut entries = comp.read_root_storage();
+    let mut names = Vec::new();
+    for _ in 0..6 {
+        if let Some(entry) = entries.next() {
+            names.push(entry.name().to_string());
+        }
+    }
+    assert_eq!(names, vec!["big", "foo", "quux"]);
+}
+
+#[test]
+fn hidden_temporaries_relax_lower_bound() {
+    // Rename an extra stream to a temporary name of the same length, as if
+    // an interrupted operation had left it behind.
+    let temporary = format!("{}0", cfb::TEMPORARY_NAME_PREFIX);
+    let placeholder = "x".repeat(temporary.len());
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    comp.create_stream(format!("/{}", placeholder)).unwrap();
+    let mut data = comp.into_inner().into_inner();
+    let utf16 = |name: &str| -> Vec<u8> {
+        name.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    };
+    let placeholder = utf16(&placeholder);
+    let offset = data
+        .windows(placeholder.len())
+        .position(|window| window == placeholder.as_slice())
+        .unwrap();
+    data[offset..(offset + placeholder.len())]
+        .copy_from_slice(&utf16(&temporary));
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert_eq!(comp.read_root_storage().count(), 3);
+    assert_eq!(check_fused(|| comp.read_root_storage()), 3);
+    comp.set_show_temporaries(true);
+    assert_eq!(check_fused(|| comp.read_root_storage()), 4);
+}
+
+#[test]
+fn ancestors_iterator_is_exact() {
+    let comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    assert_eq!(check_fused(|| comp.ancestors("/foo/baz").unwrap()), 2);
+    let ancestors = comp.ancestors("/foo/bar").unwrap();
+    assert_eq!(ancestors.len(), 2);
+    let paths: Vec<_> =
+        ancestors.rev().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(paths, vec![std::path::Path::new("/"), "/foo".as_ref()]);
+}
+
+#[test]
+fn raw_dir_entries_is_fused() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let total = comp.raw_dir_entries().count();
+    assert_eq!(total, 8);
+    let mut entries = comp.raw_dir_entries();
+    assert_eq!(entries.size_hint(), (0, Some(8)));
+    assert_eq!(entries.by_ref().count(), 8);
+    assert!(entries.next().is_none());
+    assert_eq!(entries.size_hint(), (0, Some(0)));
+}
+
+#[test]
+fn raw_dir_entries_stops_after_error() {
+    let failing = Rc::new(Cell::new(false));
+    let file = FlakyFile {
+        inner: Cursor::new(make_file()),
+        failing: failing.clone(),
+    };
+    let mut comp = CompoundFile::open(file).unwrap();
+    let mut entries = comp.raw_dir_entries();
+    let (stream_id, _) = entries.next().unwrap().unwrap();
+    assert_eq!(stream_id, StreamId::ROOT);
+    failing.set(true);
+    assert!(entries.next().unwrap().is_err());
+    // Even once reads work again, the iterator stays finished.
+    failing.set(false);
+    for _ in 0..3 {
+        assert!(entries.next().is_none());
+    }
+}
+
+#[test]
+fn signature_content_iter_is_fused() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let mut iter = comp.signature_content_iter();
+    let mut count = 0;
+    for item in iter.by_ref() {
+        item.unwrap();
+        count += 1;
+    }
+    // Three streams, plus the CLSIDs of the three storages.
+    assert_eq!(count, 6);
+    for _ in 0..3 {
+        assert!(iter.next().is_none());
+    }
+}
+
+//===========================================================================//
@@ -1,8 +1,12 @@
-use cfb::CompoundFile;
+use cfb::{CompoundFile, Version};
 use rand::prelude::{Rng, SeedableRng};
 use rand_pcg::Pcg32;
-use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
+use tracer::Tracer;
+
+mod tracer;
 
 /// Regression test for https://github.com/mdsteele/rust-cfb/issues/12.
 #[test]
@@ -37,3 +41,183 @@ fn large_file_issue_12() {
     let cursor = comp.into_inner();
     let _comp = CompoundFile::open_strict(cursor).expect("re-open");
 }
+
+/// Exercises allocation in a file with many sectors, where freed sectors,
+/// mini sectors, and directory entries get reused over and over.  Each
+/// allocation should find a free slot without scanning the whole FAT,
+/// MiniFAT, or directory.
+#[test]
+fn alternating_remove_and_create_on_large_file() {
+    fn stream_path(index: usize, big: bool) -> String {
+        let kind = if big { "big" } else { "small" };
+        format!("/dir{}/{}{}", index % 50, kind, index)
+    }
+
+    let tracer = Tracer::new();
+    let trace = tracer.trace();
+    let mut comp = CompoundFile::create_with_version(Version::V4, tracer)
+        .expect("create");
+    let big_data = vec![0x22; 40_000];
+    let small_data = vec![0x33; 100];
+    for index in 0..50 {
+        comp.create_storage(format!("/dir{}", index)).unwrap();
+    }
+    for index in 0..2000 {
+        let mut stream = comp.create_stream(stream_path(index, true)).unwrap();
+        stream.write_all(&big_data).unwrap();
+        let mut stream =
+            comp.create_stream(stream_path(index, false)).unwrap();
+        stream.write_all(&small_data).unwrap();
+    }
+    let stats = comp.stats().unwrap();
+    assert!(stats.num_sectors() > 20_000);
+
+    let mut io_per_thousand = Vec::new();
+    trace.clear();
+    for op in 0..10_000 {
+        let index = (op * 7919) % 2000;
+        let big = op % 2 == 0;
+        let path = stream_path(index, big);
+        let data = if big { &big_data } else { &small_data };
+        comp.remove_stream(&path).unwrap();
+        comp.create_stream(&path).unwrap().write_all(data).unwrap();
+        if op % 1000 == 999 {
+            io_per_thousand.push(trace.num_reads() + trace.num_writes());
+            trace.clear();
+        }
+    }
+    // Each remove and create touches only the sectors and directory entries
+    // of the stream itself, plus the FAT, MiniFAT, and directory sectors that
+    // describe them, however large the file is.
+    let least = *io_per_thousand.iter().min().unwrap();
+    let most = *io_per_thousand.iter().max().unwrap();
+    assert!(most < 40 * 1000, "I/O per thousand: {:?}", io_per_thousand);
+    assert!(most < 2 * least, "I/O per thousand: {:?}", io_per_thousand);
+
+    // Every freed slot was reused, so the file did not grow.
+    let after = comp.stats().unwrap();
+    assert_eq!(after.num_sectors(), stats.num_sectors());
+    assert_eq!(after.num_free_sectors(), stats.num_free_sectors());
+    assert_eq!(after.num_free_mini_sectors(), 0);
+    assert_eq!(after.num_free_dir_entries(), stats.num_free_dir_entries());
+
+    let tracer = comp.into_inner();
+    let _comp = CompoundFile::open_strict(tracer).expect("re-open");
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Returns the stream ID of each allocated directory entry other than the
+/// root, keyed by name, by reading the raw directory chain.
+fn stream_ids(data: &[u8]) -> BTreeMap<String, u32> {
+    let sector_len = 1usize << data[30];
+    let offset = |sector: u32| (sector as usize + 1) * sector_len;
+    let num_fat_sectors = u32_at(data, 44) as usize;
+    let mut fat = Vec::new();
+    for index in 0..num_fat_sectors {
+        let start = offset(u32_at(data, 76 + 4 * index));
+        fat.extend((0..sector_len / 4).map(|i| u32_at(data, start + 4 * i)));
+    }
+    let mut ids = BTreeMap::new();
+    let mut stream_id = 0;
+    let mut sector = u32_at(data, 48);
+    while sector != 0xfffffffe {
+        for index in 0..sector_len / 128 {
+            let entry = offset(sector) + 128 * index;
+            let name_len =
+                u16::from_le_bytes([data[entry + 64], data[entry + 65]]);
+            if stream_id != 0 && name_len > 0 {
+                let name: Vec<u16> = (0..(name_len as usize / 2 - 1))
+                    .map(|i| {
+                        u16::from_le_bytes([
+                            data[entry + 2 * i],
+                            data[entry + 2 * i + 1],
+                        ])
+                    })
+                    .collect();
+                ids.insert(String::from_utf16(&name).unwrap(), stream_id);
+            }
+            stream_id += 1;
+        }
+        sector = fat[sector as usize];
+    }
+    ids
+}
+
+/// Creates 10,000 streams and removes all but 100 of them, which leaves a
+/// long tail of unallocated directory entries for `shrink_directory` to free.
+#[test]
+fn shrink_directory_after_churn() {
+    // Spread the streams over several storages, to keep each storage's
+    // sibling tree small.
+    fn stream_path(index: usize) -> String {
+        format!("/dir{}/s{}", index % 100, index)
+    }
+
+    for &version in &[Version::V3, Version::V4] {
+        let entries_per_sector = version.sector_len() / 128;
+        let cursor = Cursor::new(Vec::new());
+        let mut comp = CompoundFile::create_with_version(version, cursor)
+            .expect("create");
+        for index in 0..100 {
+            comp.create_storage(format!("/dir{}", index)).unwrap();
+        }
+        for index in 0..10_000 {
+            let mut stream = comp.create_stream(stream_path(index)).unwrap();
+            stream.write_all(format!("data{}", index).as_bytes()).unwrap();
+        }
+        let survives = |index: usize| index < 1000 && index % 10 == 0;
+        for index in (0..9999).filter(|&index| !survives(index)) {
+            comp.remove_stream(stream_path(index)).unwrap();
+        }
+        let num_dir_sectors = comp.stats().unwrap().num_dir_sectors();
+        // The last entry is still live, so nothing can be freed yet.
+        assert_eq!(comp.shrink_directory().unwrap(), 0);
+        assert_eq!(comp.stats().unwrap().num_dir_sectors(), num_dir_sectors);
+
+        comp.remove_stream(stream_path(9999)).unwrap();
+        comp.flush().unwrap();
+        let cursor = comp.into_inner();
+        let before = stream_ids(cursor.get_ref());
+        assert_eq!(before.len(), 200);
+        let mut comp = CompoundFile::open(cursor).expect("open");
+        let max_id = *before.values().max().unwrap() as usize;
+        let released = comp.shrink_directory().unwrap();
+        let expected = (max_id + 1).div_ceil(entries_per_sector) as u32;
+        assert_eq!(released, num_dir_sectors - expected, "{:?}", version);
+        let stats = comp.stats().unwrap();
+        assert_eq!(stats.num_dir_sectors(), expected);
+        assert_eq!(comp.shrink_directory().unwrap(), 0);
+
+        // New entries reuse the free entries that remain, below the new end
+        // of the directory.
+        let num_free = stats.num_free_dir_entries();
+        assert!(num_free > 0);
+        comp.create_stream("/new").unwrap().write_all(b"new").unwrap();
+        let after = comp.stats().unwrap();
+        assert_eq!(after.num_free_dir_entries(), num_free - 1);
+        assert_eq!(after.num_dir_sectors(), expected);
+
+        comp.flush().unwrap();
+        let data = comp.into_inner().into_inner();
+        let after = stream_ids(&data);
+        assert!((after["new"] as usize) <= max_id);
+        for (name, stream_id) in before.iter() {
+            assert_eq!(after[name], *stream_id, "{}", name);
+        }
+        let mut comp =
+            CompoundFile::open_strict(Cursor::new(data)).expect("re-open");
+        for index in (0..10_000).filter(|&index| survives(index)) {
+            let mut contents = String::new();
+            comp.open_stream(stream_path(index))
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, format!("data{}", index));
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use cfb::{CompoundFile, CreateOptions, Version};
+use std::io::{Cursor, Read, Write};
+
+//===========================================================================//
+
+const NUM_STREAMS: usize = 200;
+const LARGE_STREAM_LEN: usize = 5000;
+const SMALL_STREAM_LEN: usize = 300;
+
+fn stream_data(index: usize) -> Vec<u8> {
+    let len = if index % 2 == 0 { LARGE_STREAM_LEN } else { SMALL_STREAM_LEN };
+    vec![(index % 251) as u8; len]
+}
+
+fn total_bytes() -> u64 {
+    (0..NUM_STREAMS).map(|index| stream_data(index).len() as u64).sum()
+}
+
+fn small_bytes() -> u64 {
+    // Each small stream takes up a whole number of 64-byte mini sectors.
+    (NUM_STREAMS / 2 * SMALL_STREAM_LEN.div_ceil(64) * 64) as u64
+}
+
+fn populate(comp: &mut CompoundFile<Cursor<Vec<u8>>>) {
+    for index in 0..NUM_STREAMS {
+        let path = format!("/stream{index}");
+        comp.create_stream(&path)
+            .unwrap()
+            .write_all(&stream_data(index))
+            .unwrap();
+    }
+}
+
+fn check_contents(comp: &mut CompoundFile<Cursor<Vec<u8>>>) {
+    for index in 0..NUM_STREAMS {
+        let path = format!("/stream{index}");
+        let mut data = Vec::new();
+        comp.open_stream(&path).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, stream_data(index));
+    }
+}
+
+fn hinted_options(version: Version) -> CreateOptions {
+    CreateOptions::new()
+        .version(version)
+        .expected_streams(NUM_STREAMS as u64)
+        .expected_total_bytes(total_bytes())
+        .expected_small_stream_bytes(small_bytes())
+}
+
+//===========================================================================//
+
+#[test]
+fn hinted_build_is_not_fragmented() {
+    for &version in &[Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let mut comp = CompoundFile::create_with_version(version, cursor)
+            .expect("create");
+        populate(&mut comp);
+        comp.flush().unwrap();
+        let baseline = comp.stats().unwrap();
+
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_options(hinted_options(version), cursor)
+                .expect("create");
+        populate(&mut comp);
+        comp.flush().unwrap();
+        let hinted = comp.stats().unwrap();
+
+        assert_eq!(hinted.num_fragments(), 0);
+        assert_eq!(hinted.metadata_spread(), 0);
+        assert!(baseline.num_fragments() > 0);
+        assert!(baseline.metadata_spread() > 0);
+
+        let mut comp =
+            CompoundFile::open_strict(comp.into_inner()).expect("open");
+        check_contents(&mut comp);
+    }
+}
+
+#[test]
+fn unused_reservations_are_released_on_flush() {
+    let options = CreateOptions::new()
+        .version(Version::V3)
+        .expected_streams(1000)
+        .expected_total_bytes(10 << 20)
+        .expected_small_stream_bytes(1 << 20);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_options(options, cursor).expect("create");
+    let reserved = comp.stats().unwrap();
+    assert!(reserved.num_fat_sectors() > 1);
+    assert_eq!(reserved.num_dir_sectors(), 251);
+    assert_eq!(reserved.num_minifat_sectors(), 128);
+    assert_eq!(reserved.num_mini_stream_sectors(), 2048);
+
+    comp.create_stream("/foo").unwrap().write_all(&[1; 100]).unwrap();
+    comp.flush().unwrap();
+    let released = comp.stats().unwrap();
+    // The released sectors are still part of the file (as free sectors), so
+    // only the FAT sectors covering nothing but the end of the file can be
+    // released.
+    assert!(released.num_fat_sectors() < reserved.num_fat_sectors());
+    assert_eq!(
+        released.num_fat_sectors(),
+        released.num_sectors().div_ceil(128)
+    );
+    assert_eq!(released.num_dir_sectors(), 1);
+    assert_eq!(released.num_minifat_sectors(), 1);
+    assert_eq!(released.num_mini_stream_sectors(), 1);
+
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).expect("open");
+    let mut data = Vec::new();
+    comp.open_stream("/foo").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![1; 100]);
+}
+
+#[test]
+fn unused_reservations_can_be_kept() {
+    let options = hinted_options(Version::V4).keep_unused_reservations(true);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_options(options, cursor).expect("create");
+    let reserved = comp.stats().unwrap();
+    comp.flush().unwrap();
+    assert_eq!(comp.stats().unwrap(), reserved);
+
+    // The reserved (but empty) file should still be valid, and the
+    // reservations should still be usable after reopening it.
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).expect("open");
+    assert_eq!(comp.stats().unwrap(), reserved);
+    populate(&mut comp);
+    comp.flush().unwrap();
+    let stats = comp.stats().unwrap();
+    assert_eq!(stats.num_fragments(), 0);
+    assert_eq!(stats.metadata_spread(), 0);
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).expect("open");
+    check_contents(&mut comp);
+}
+
+#[test]
+fn huge_hint_reserves_difat_sectors() {
+    let options = CreateOptions::new()
+        .version(Version::V3)
+        .expected_total_bytes(64 << 20)
+        .keep_unused_reservations(true);
+    let cursor = Cursor::new(Vec::new());
+    let comp =
+        CompoundFile::create_with_options(options, cursor).expect("create");
+    let stats = comp.stats().unwrap();
+    assert!(stats.num_fat_sectors() > 109);
+    assert!(stats.num_difat_sectors() > 0);
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).expect("open");
+    comp.create_stream("/foo").unwrap().write_all(&[7; 10000]).unwrap();
+    comp.flush().unwrap();
+
+    let options = CreateOptions::new()
+        .version(Version::V3)
+        .expected_total_bytes(64 << 20);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_options(options, cursor).expect("create");
+    comp.create_stream("/foo").unwrap().write_all(&[7; 10000]).unwrap();
+    comp.flush().unwrap();
+    let stats = comp.stats().unwrap();
+    assert_eq!(stats.num_fat_sectors(), stats.num_sectors().div_ceil(128));
+    assert_eq!(stats.num_difat_sectors(), 0);
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).expect("open");
+    let mut data = Vec::new();
+    comp.open_stream("/foo").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![7; 10000]);
+}
+
+//===========================================================================//
+
+/// Builds a file with the standard contents, then (optionally) adds and
+/// removes a huge stream, and finally shrinks the file and returns it,
+/// truncated to its new length.
+fn build_and_shrink(version: Version, huge_len: usize) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(version, cursor).expect("create");
+    populate(&mut comp);
+    if huge_len > 0 {
+        let mut stream = comp.create_stream("/huge").unwrap();
+        for _ in 0..(huge_len / 65536) {
+            stream.write_all(&[0xaa; 65536]).unwrap();
+        }
+        drop(stream);
+        comp.remove_stream("/huge").unwrap();
+    }
+    let len = comp.shrink_to_fit().unwrap();
+    let mut data = comp.into_inner().into_inner();
+    assert!(len <= data.len() as u64);
+    data.truncate(len as usize);
+    data
+}
+
+#[test]
+fn shrink_releases_fat_and_difat_sectors() {
+    // In a V3 file, 8 MB of data needs more FAT sectors than fit in the
+    // header's DIFAT, so this exercises DIFAT sectors as well.
+    for &(version, huge_len) in
+        &[(Version::V3, 8 << 20), (Version::V4, 12 << 20)]
+    {
+        let expected = build_and_shrink(version, 0);
+        let shrunk = build_and_shrink(version, huge_len);
+        assert_eq!(shrunk.len(), expected.len());
+        let expected = CompoundFile::open_strict(Cursor::new(expected))
+            .expect("open")
+            .stats()
+            .unwrap();
+        let mut comp =
+            CompoundFile::open_strict(Cursor::new(shrunk)).expect("open");
+        assert_eq!(comp.stats().unwrap(), expected);
+        check_contents(&mut comp);
+    }
+}
+
+#[test]
+fn shrunk_file_can_grow_again() {
+    let data = build_and_shrink(Version::V3, 8 << 20);
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).expect("open");
+    comp.create_stream("/again").unwrap().write_all(&[7; 100_000]).unwrap();
+    comp.flush().unwrap();
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).expect("open");
+    check_contents(&mut comp);
+    let mut data = Vec::new();
+    comp.open_stream("/again").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![7; 100_000]);
+}
+
+#[test]
+fn shrink_keeps_sectors_still_in_use() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/first").unwrap().write_all(&[1; 2 << 20]).unwrap();
+    comp.create_stream("/last").unwrap().write_all(&[2; 10000]).unwrap();
+    comp.remove_stream("/first").unwrap();
+    let len = comp.stats().unwrap().num_sectors() as u64 * 512 + 512;
+    // The free space is in the middle of the file, so nothing can be
+    // released.
+    assert_eq!(comp.shrink_to_fit().unwrap(), len);
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).expect("open");
+    let mut data = Vec::new();
+    comp.open_stream("/last").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, vec![2; 10000]);
+}
+
+//===========================================================================//
@@ -1,4 +1,5 @@
-use cfb::CompoundFile;
+use cfb::{CompoundFile, StreamId, ValidationIssueKind, Version};
+use rawcfb::named_dir_entry_offset;
 use std::{
     fs::read_dir,
     io::{Cursor, Read, Seek, SeekFrom, Write},
@@ -8,6 +9,8 @@ use std::{
     time::Duration,
 };
 
+mod rawcfb;
+
 // Run function on another thread, panic if it takes too long
 fn panic_after<T, F>(d: Duration, f: F) -> T
 where
@@ -195,7 +198,10 @@ fn sector_panic_pr_24() {
 }
 
 #[test]
-#[should_panic(expected = "next_id (4294967293) is invalid")]
+#[should_panic(
+    expected = "The chain starting at sector 0 is broken at position 1: \
+                found FATSECT"
+)]
 fn alloc_panic_pr_24() {
     let mut cfb = cfb::open("tests/panics_fuzzed/alloc_panic").unwrap();
     cfb.walk()
@@ -213,7 +219,10 @@ fn alloc_panic_pr_24() {
 }
 
 #[test]
-#[should_panic(expected = "next_id (4294967295) is invalid")]
+#[should_panic(
+    expected = "The chain starting at mini sector 1 is broken at position 2: \
+                found FREESECT"
+)]
 fn minialloc_panic_pr_24() {
     let mut cfb = cfb::open("tests/panics_fuzzed/minialloc_panic").unwrap();
     cfb.walk()
@@ -456,12 +465,10 @@ fn open_strict_difat_terminate_freesect() {
     CompoundFile::open_strict(difat_terminate_in_freesect()).unwrap();
 }
 
-/// Regression test for https://github.com/mdsteele/rust-cfb/issues/52.
-#[test]
-#[should_panic(
-    expected = "Directory chain includes at least 2 sectors which is greater than header num_dir_sectors 1"
-)]
-fn invalid_num_dir_sectors_issue_52() {
+/// Returns a V4 file whose header claims fewer directory sectors than the
+/// directory chain actually has (see
+/// https://github.com/mdsteele/rust-cfb/issues/52).
+fn too_few_num_dir_sectors() -> Cursor<Vec<u8>> {
     // Create a CFB file with 2 sectors for the directory.
     let cursor = Cursor::new(Vec::new());
     let mut comp = CompoundFile::create(cursor).unwrap();
@@ -479,7 +486,207 @@ fn invalid_num_dir_sectors_issue_52() {
     cursor.seek(SeekFrom::Start(40)).unwrap();
     cursor.write_all(&1u32.to_le_bytes()).unwrap();
     cursor.flush().unwrap();
+    cursor
+}
 
+/// Regression test for https://github.com/mdsteele/rust-cfb/issues/52.
+#[test]
+#[should_panic(
+    expected = "Directory chain includes at least 2 sectors which is greater than header num_dir_sectors 1"
+)]
+fn invalid_num_dir_sectors_issue_52() {
     // Read the file back in.
-    CompoundFile::open_strict(cursor).unwrap();
+    CompoundFile::open_strict(too_few_num_dir_sectors()).unwrap();
+}
+
+//===========================================================================//
+
+fn warning_kinds<F>(comp: &CompoundFile<F>) -> Vec<ValidationIssueKind> {
+    comp.open_warnings().iter().map(|issue| issue.kind()).collect()
+}
+
+/// Returns a small, valid V3 file containing a single stream named "foo".
+fn valid_v3_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/foo").unwrap().write_all(b"foobar").unwrap();
+    comp.into_inner().into_inner()
+}
+
+#[test]
+fn no_open_warnings_for_valid_file() {
+    let data = valid_v3_file();
+    let comp = CompoundFile::open(Cursor::new(data.clone())).unwrap();
+    assert!(comp.open_warnings().is_empty());
+    let comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert!(comp.open_warnings().is_empty());
+    let comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    assert!(comp.open_warnings().is_empty());
+}
+
+#[test]
+fn open_warnings_difat_terminate_freesect() {
+    let comp = CompoundFile::open(difat_terminate_in_freesect()).unwrap();
+    assert!(warning_kinds(&comp)
+        .contains(&ValidationIssueKind::DifatChainTerminator));
+}
+
+#[test]
+fn open_warnings_num_dir_sectors() {
+    let comp = CompoundFile::open(too_few_num_dir_sectors()).unwrap();
+    assert_eq!(
+        warning_kinds(&comp),
+        vec![ValidationIssueKind::DirSectorCount]
+    );
+    assert!(comp.open_warnings()[0].message().contains("1 sectors"));
+}
+
+#[test]
+fn open_warnings_header_fields() {
+    let mut data = valid_v3_file();
+    data[34] = 1; // Reserved
+    data[40] = 1; // Number of Directory Sectors (must be zero for V3)
+    let comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert_eq!(
+        warning_kinds(&comp),
+        vec![
+            ValidationIssueKind::NonzeroReservedField,
+            ValidationIssueKind::DirSectorCountNotZero,
+        ]
+    );
+}
+
+#[test]
+fn open_warnings_stream_fields() {
+    let mut data = valid_v3_file();
+    let offset = named_dir_entry_offset(&data, "foo");
+    data[offset + 80] = 1; // CLSID
+    data[offset + 100] = 1; // Creation Time
+    let comp = CompoundFile::open(Cursor::new(data.clone())).unwrap();
+    assert_eq!(
+        warning_kinds(&comp),
+        vec![
+            ValidationIssueKind::StreamClsid,
+            ValidationIssueKind::StreamTimestamp,
+        ]
+    );
+    assert!(CompoundFile::open_strict(Cursor::new(data)).is_err());
+}
+
+//===========================================================================//
+
+/// Returns a V3 file whose storage "/b" has a child ID of `child`, alongside
+/// other storages and streams that should remain readable.
+fn dangling_child_file(child: u32) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_storage("/a").unwrap();
+    comp.create_stream("/a/x").unwrap().write_all(b"xyzzy").unwrap();
+    comp.create_storage("/b").unwrap();
+    comp.create_stream("/b/y").unwrap().write_all(b"plugh").unwrap();
+    comp.create_stream("/c").unwrap().write_all(b"hello").unwrap();
+    comp.create_storage("/d").unwrap();
+    let mut data = comp.into_inner().into_inner();
+    let offset = named_dir_entry_offset(&data, "b") + 76;
+    data[offset..offset + 4].copy_from_slice(&child.to_le_bytes());
+    data
+}
+
+fn check_dangling_child(child: u32) {
+    let data = dangling_child_file(child);
+    assert!(CompoundFile::open_strict(Cursor::new(data.clone())).is_err());
+
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert_eq!(warning_kinds(&comp), vec![ValidationIssueKind::DanglingChild]);
+    let message = comp.open_warnings()[0].message();
+    assert!(message.contains("\"/b\""), "{}", message);
+    assert!(message.contains(&child.to_string()), "{}", message);
+    let target_id = comp.open_warnings()[0].target_id();
+    assert_eq!(target_id, Some(StreamId::from(child)));
+
+    assert!(comp.is_storage("/b"));
+    assert_eq!(comp.read_storage("/b").unwrap().count(), 0);
+    assert!(!comp.exists("/b/y"));
+    let mut names: Vec<String> =
+        comp.walk().map(|entry| entry.path().display().to_string()).collect();
+    names.sort();
+    assert_eq!(names, vec!["/", "/a", "/a/x", "/b", "/c", "/d"]);
+    let mut data = Vec::new();
+    comp.open_stream("/a/x").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"xyzzy");
+    let mut data = Vec::new();
+    comp.open_stream("/c").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"hello");
+}
+
+#[test]
+fn dangling_child_unallocated_entry() {
+    check_dangling_child(7);
+}
+
+#[test]
+fn dangling_child_out_of_range() {
+    check_dangling_child(1000);
+}
+
+//===========================================================================//
+
+/// Returns a V3 file with twelve streams in the root, whose directory chain
+/// has been cut short after its first two sectors (holding entries 0 to 7)
+/// without fixing up the links to the entries that were cut off.
+fn truncated_directory_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    for index in 0..12 {
+        let path = format!("/s{:02}", index);
+        comp.create_stream(&path).unwrap().write_all(b"data").unwrap();
+    }
+    let mut data = comp.into_inner().into_inner();
+    let u32_at = |data: &[u8], offset: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    };
+    let fat_offset = (u32_at(&data, 76) as usize + 1) * 512;
+    let first_dir_sector = u32_at(&data, 48) as usize;
+    let second_dir_sector =
+        u32_at(&data, fat_offset + 4 * first_dir_sector) as usize;
+    let offset = fat_offset + 4 * second_dir_sector;
+    data[offset..offset + 4].copy_from_slice(&0xfffffffeu32.to_le_bytes());
+    data
+}
+
+#[test]
+fn dangling_sibling_past_end_of_directory() {
+    let data = truncated_directory_file();
+    assert!(CompoundFile::open_strict(Cursor::new(data.clone())).is_err());
+
+    // Entry 4 ("/s03") is at the top of the root's tree, and its right
+    // sibling (entry 8, "/s07") was cut off along with the rest of the
+    // branch under it, including entries 5 to 7.  The rest still enumerate.
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert_eq!(
+        warning_kinds(&comp),
+        vec![ValidationIssueKind::DanglingSibling]
+    );
+    let issue = &comp.open_warnings()[0];
+    assert_eq!(issue.stream_id(), Some(StreamId::from(4)));
+    assert_eq!(issue.target_id(), Some(StreamId::from(8)));
+    assert!(issue.message().contains("\"/s03\""), "{}", issue.message());
+    assert!(comp
+        .validate()
+        .iter()
+        .any(|issue| issue.kind() == ValidationIssueKind::DanglingSibling));
+    let names: Vec<String> = comp
+        .read_storage("/")
+        .unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["s00", "s01", "s02", "s03"]);
+    let mut data = Vec::new();
+    comp.open_stream("/s02").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"data");
 }
@@ -0,0 +1,163 @@
+use cfb::{CompoundFile, MergeOptions, Severity, Version};
+use std::io::{Cursor, ErrorKind, Write};
+use uuid::Uuid;
+
+//===========================================================================//
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+const NUM_SOURCES: usize = 50;
+const SHARED_LEN: u64 = 2000 + 20_000 + 16_000;
+const UNIQUE_LEN: usize = 4000;
+
+fn data(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) ^ seed) as u8)
+        .collect()
+}
+
+/// The streams of each generated source, as (path, contents) pairs.  All
+/// but the body are the same in every source, making up 90% of the data.
+fn source_streams(index: usize) -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("/Header", data(2000, 1)),
+        ("/Data/Template", data(20_000, 2)),
+        ("/Data/Styles", data(16_000, 3)),
+        ("/Data/Body", data(UNIQUE_LEN, 1000 + index as u32)),
+    ]
+}
+
+fn make_source(index: usize) -> Comp {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.set_storage_clsid("/", Uuid::from_u128(index as u128)).unwrap();
+    comp.create_storage("/Data").unwrap();
+    comp.set_state_bits("/Data", 7).unwrap();
+    for (path, contents) in source_streams(index) {
+        comp.create_stream(path).unwrap().write_all(&contents).unwrap();
+    }
+    comp
+}
+
+fn sources() -> impl Iterator<Item = (String, Comp)> {
+    (0..NUM_SOURCES)
+        .map(|index| (format!("report{}", index), make_source(index)))
+}
+
+fn merged(options: MergeOptions) -> (Comp, cfb::MergeReport) {
+    let cursor = Cursor::new(Vec::new());
+    let mut dest = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    let report = cfb::merge(sources(), &mut dest, options).unwrap();
+    dest.flush().unwrap();
+    (dest, report)
+}
+
+//===========================================================================//
+
+#[test]
+fn merge_dedups_shared_streams() {
+    let (dest, report) = merged(MergeOptions::new());
+    assert_eq!(report.sources().len(), NUM_SOURCES);
+    for (index, source) in report.sources().iter().enumerate() {
+        assert_eq!(source.key(), format!("report{}", index));
+        assert_eq!(
+            source.path(),
+            std::path::Path::new(&format!("/report{}", index))
+        );
+        assert_eq!(source.num_storages(), 1);
+        assert_eq!(source.num_streams(), 4);
+        assert_eq!(source.num_bytes(), SHARED_LEN + UNIQUE_LEN as u64);
+        if index == 0 {
+            assert_eq!(source.num_shared_streams(), 0);
+            assert_eq!(source.num_bytes_saved(), 0);
+        } else {
+            assert_eq!(source.num_shared_streams(), 3);
+            assert_eq!(source.num_bytes_saved(), SHARED_LEN);
+        }
+    }
+    assert_eq!(report.num_streams(), 4 * NUM_SOURCES as u64);
+    assert_eq!(
+        report.num_bytes_saved(),
+        (NUM_SOURCES as u64 - 1) * SHARED_LEN
+    );
+
+    // The container holds one copy of the shared data, plus each body, plus
+    // some overhead, and is far smaller than it would be without dedup.
+    let len = dest.into_inner().into_inner().len() as u64;
+    let (plain, plain_report) = merged(MergeOptions::new().dedup(false));
+    assert_eq!(plain_report.num_bytes_saved(), 0);
+    let plain_len = plain.into_inner().into_inner().len() as u64;
+    assert!(plain_len > NUM_SOURCES as u64 * SHARED_LEN);
+    assert!(
+        len < SHARED_LEN + NUM_SOURCES as u64 * (UNIQUE_LEN as u64 + 2048)
+    );
+    assert!(len * 5 < plain_len, "{} vs {} bytes", len, plain_len);
+}
+
+#[test]
+fn merged_sources_round_trip() {
+    let (dest, _) = merged(MergeOptions::new());
+    let dest = CompoundFile::open_strict(dest.into_inner()).unwrap();
+    assert!(dest
+        .validate()
+        .iter()
+        .all(|issue| issue.severity() == Severity::Info));
+    for index in 0..NUM_SOURCES {
+        let key = format!("/report{}", index);
+        let root = dest.entry(&key).unwrap();
+        assert_eq!(root.clsid(), &Uuid::from_u128(index as u128));
+        let mut out =
+            dest.export_storage_as_cfb(&key, Cursor::new(Vec::new())).unwrap();
+        let original = make_source(index);
+        let expected: Vec<_> =
+            original.walk().map(|e| e.path().to_path_buf()).collect();
+        let actual: Vec<_> =
+            out.walk().map(|e| e.path().to_path_buf()).collect();
+        assert_eq!(actual, expected);
+        assert_eq!(out.entry("/Data").unwrap().state_bits(), 7);
+        for (path, contents) in source_streams(index) {
+            assert!(out.read_stream_to_vec(path).unwrap() == contents);
+        }
+    }
+}
+
+#[test]
+fn merged_stream_can_be_changed_independently() {
+    let (mut dest, _) = merged(MergeOptions::new());
+    dest.open_stream("/report3/Data/Template")
+        .unwrap()
+        .write_all(b"changed")
+        .unwrap();
+    let template = dest.read_stream_to_vec("/report4/Data/Template").unwrap();
+    assert!(template == data(20_000, 2));
+    let changed = dest.read_stream_to_vec("/report3/Data/Template").unwrap();
+    assert_eq!(&changed[..7], b"changed");
+}
+
+#[test]
+fn conflicting_keys_are_rejected() {
+    let (mut dest, _) = merged(MergeOptions::new());
+    let again = vec![("report7".to_string(), make_source(0))];
+    let error = cfb::merge(again, &mut dest, MergeOptions::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(dest.read_storage("/report7/Data").unwrap().count(), 3);
+
+    // Sources before the conflicting one are kept.
+    let sources = vec![
+        ("new".to_string(), make_source(1)),
+        ("new".to_string(), make_source(2)),
+    ];
+    let error =
+        cfb::merge(sources, &mut dest, MergeOptions::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(dest.entry("/new").unwrap().clsid(), &Uuid::from_u128(1));
+
+    let sources = vec![("a/b".to_string(), make_source(1))];
+    let error =
+        cfb::merge(sources, &mut dest, MergeOptions::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+//===========================================================================//
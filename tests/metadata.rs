@@ -0,0 +1,381 @@
+use cfb::{CompoundFile, CreateOptions, MetadataFields, Version};
+use std::io::{Cursor, ErrorKind, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const CLSID: Uuid = Uuid::from_bytes(*b"ABCDEFGHIJKLMNOP");
+const STATE_BITS: u32 = 0x1234_5678;
+
+fn created() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+}
+
+fn modified() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_500_000_000)
+}
+
+/// The CFB epoch, which a zero timestamp reads back as.
+fn cfb_epoch() -> SystemTime {
+    UNIX_EPOCH - Duration::from_secs(11_644_473_600)
+}
+
+fn make_file() -> CompoundFile<Cursor<Vec<u8>>> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.create_storage("/src").unwrap();
+    comp.create_storage("/dst").unwrap();
+    comp.create_stream("/stream").unwrap();
+    comp.set_storage_clsid("/src", CLSID).unwrap();
+    comp.set_state_bits("/src", STATE_BITS).unwrap();
+    comp.set_created_time("/src", created()).unwrap();
+    comp.set_modified_time("/src", modified()).unwrap();
+    comp
+}
+
+//===========================================================================//
+
+#[test]
+fn copy_each_field() {
+    let fields = [
+        MetadataFields::CLSID,
+        MetadataFields::STATE_BITS,
+        MetadataFields::CREATED,
+        MetadataFields::MODIFIED,
+    ];
+    for &field in fields.iter() {
+        let mut comp = make_file();
+        let before = comp.entry("/dst").unwrap();
+        comp.copy_metadata("/src", "/dst", field).unwrap();
+        let after = comp.entry("/dst").unwrap();
+        let expect = |selected: MetadataFields| field.contains(selected);
+        if expect(MetadataFields::CLSID) {
+            assert_eq!(after.clsid(), &CLSID);
+        } else {
+            assert_eq!(after.clsid(), before.clsid());
+        }
+        if expect(MetadataFields::STATE_BITS) {
+            assert_eq!(after.state_bits(), STATE_BITS);
+        } else {
+            assert_eq!(after.state_bits(), before.state_bits());
+        }
+        if expect(MetadataFields::CREATED) {
+            assert_eq!(after.created(), created());
+        } else {
+            assert_eq!(after.created(), before.created());
+        }
+        if expect(MetadataFields::MODIFIED) {
+            assert_eq!(after.modified(), modified());
+        } else {
+            assert_eq!(after.modified(), before.modified());
+        }
+    }
+}
+
+#[test]
+fn copy_all_fields_survives_reopen() {
+    let mut comp = make_file();
+    comp.copy_metadata("/src", "/dst", MetadataFields::ALL).unwrap();
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let entry = comp.entry("/dst").unwrap();
+    assert_eq!(entry.clsid(), &CLSID);
+    assert_eq!(entry.state_bits(), STATE_BITS);
+    assert_eq!(entry.created(), created());
+    assert_eq!(entry.modified(), modified());
+    // Nothing else about the target changes.
+    assert!(entry.is_storage());
+    assert_eq!(entry.name(), "dst");
+}
+
+#[test]
+fn copy_to_root_and_stream_follows_setter_rules() {
+    let mut comp = make_file();
+    let root_created = comp.root_entry().created();
+    comp.copy_metadata("/src", "/", MetadataFields::ALL).unwrap();
+    let root = comp.root_entry();
+    assert_eq!(root.clsid(), &CLSID);
+    assert_eq!(root.state_bits(), STATE_BITS);
+    assert_eq!(root.created(), root_created);
+    assert_eq!(root.modified(), modified());
+
+    let before = comp.entry("/stream").unwrap();
+    let stream_fields = MetadataFields::STATE_BITS | MetadataFields::TIMES;
+    comp.copy_metadata("/src", "/stream", stream_fields).unwrap();
+    let stream = comp.entry("/stream").unwrap();
+    assert_eq!(stream.state_bits(), STATE_BITS);
+    assert_eq!(stream.created(), before.created());
+    assert_eq!(stream.modified(), before.modified());
+}
+
+#[test]
+fn copy_clsid_between_kinds_fails() {
+    let mut comp = make_file();
+    let before = comp.entry("/stream").unwrap();
+    let error = comp
+        .copy_metadata("/src", "/stream", MetadataFields::ALL)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    // Nothing was copied, not even the compatible fields.
+    assert_eq!(
+        comp.entry("/stream").unwrap().state_bits(),
+        before.state_bits()
+    );
+
+    let error = comp
+        .copy_metadata("/stream", "/dst", MetadataFields::CLSID)
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(comp.entry("/dst").unwrap().clsid().is_nil());
+}
+
+#[test]
+fn copy_from_missing_object_fails() {
+    let mut comp = make_file();
+    let fields = MetadataFields::STATE_BITS;
+    let error = comp.copy_metadata("/nope", "/dst", fields).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = comp.copy_metadata("/src", "/nope", fields).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn copy_is_idempotent() {
+    let mut comp = make_file();
+    comp.copy_metadata("/src", "/dst", MetadataFields::ALL).unwrap();
+    let once = comp.entry("/dst").unwrap().metadata();
+    comp.copy_metadata("/src", "/dst", MetadataFields::ALL).unwrap();
+    assert_eq!(comp.entry("/dst").unwrap().metadata(), once);
+    // Copying an object onto itself changes nothing.
+    let source = comp.entry("/src").unwrap().metadata();
+    comp.copy_metadata("/src", "/src", MetadataFields::ALL).unwrap();
+    assert_eq!(comp.entry("/src").unwrap().metadata(), source);
+    // Neither does copying no fields at all.
+    comp.set_state_bits("/dst", 7).unwrap();
+    comp.copy_metadata("/src", "/dst", MetadataFields::empty()).unwrap();
+    assert_eq!(comp.entry("/dst").unwrap().state_bits(), 7);
+}
+
+#[test]
+fn copy_from_entry_of_another_file() {
+    let source_comp = make_file();
+    let source = source_comp.entry("/src").unwrap();
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.create_storage("/dst").unwrap();
+    comp.copy_metadata_from_entry(&source, "/dst", MetadataFields::ALL)
+        .unwrap();
+    let entry = comp.entry("/dst").unwrap();
+    assert_eq!(entry.clsid(), &CLSID);
+    assert_eq!(entry.state_bits(), STATE_BITS);
+    assert_eq!(entry.created(), created());
+    assert_eq!(entry.modified(), modified());
+}
+
+#[test]
+fn timestamps_round_trip() {
+    let mut comp = make_file();
+    let precise = UNIX_EPOCH + Duration::new(1_234_567_890, 123_456_789);
+    let epoch = UNIX_EPOCH - Duration::from_secs(11_644_473_600);
+    comp.set_created_time("/dst", epoch).unwrap();
+    comp.set_modified_time("/dst", precise).unwrap();
+    // Writing to streams doesn't touch any timestamps.
+    let mut stream = comp.create_stream("/src/data").unwrap();
+    stream.write_all(&[1; 10000]).unwrap();
+    drop(stream);
+    comp.flush().unwrap();
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let entry = comp.entry("/src").unwrap();
+    assert_eq!(entry.created(), created());
+    assert_eq!(entry.modified(), modified());
+    let entry = comp.entry("/dst").unwrap();
+    assert_eq!(entry.created(), epoch);
+    // Precision finer than 100 nanoseconds is truncated.
+    assert_eq!(entry.modified(), precise - Duration::from_nanos(89));
+    // Streams keep zero timestamps, as the spec requires.
+    let entry = comp.entry("/src/data").unwrap();
+    assert_eq!(entry.created(), epoch);
+    assert_eq!(entry.modified(), epoch);
+}
+
+#[test]
+fn out_of_range_timestamps_are_rejected() {
+    let mut comp = make_file();
+    let too_early = UNIX_EPOCH - Duration::from_secs(11_644_473_601);
+    let error = comp.set_created_time("/src", too_early).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.set_modified_time("/src", too_early).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    // The largest timestamp is about 58,000 years after the epoch.
+    if let Some(too_late) =
+        UNIX_EPOCH.checked_add(Duration::from_secs(60_000 * 366 * 86_400))
+    {
+        let error = comp.set_modified_time("/src", too_late).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+    let entry = comp.entry("/src").unwrap();
+    assert_eq!(entry.created(), created());
+    assert_eq!(entry.modified(), modified());
+}
+
+#[test]
+fn replace_returns_previous_values() {
+    let mut comp = make_file();
+    let other = Uuid::from_bytes(*b"0123456789abcdef");
+    assert_eq!(comp.replace_storage_clsid("/src", other).unwrap(), CLSID);
+    assert_eq!(
+        comp.replace_storage_clsid("/src", Uuid::nil()).unwrap(),
+        other
+    );
+    assert_eq!(
+        comp.replace_storage_clsid("/src", CLSID).unwrap(),
+        Uuid::nil()
+    );
+    assert_eq!(comp.replace_state_bits("/src", 1).unwrap(), STATE_BITS);
+    assert_eq!(comp.replace_state_bits("/src", 2).unwrap(), 1);
+    assert_eq!(comp.replace_state_bits("/stream", 3).unwrap(), 0);
+    assert_eq!(comp.replace_state_bits("/stream", 4).unwrap(), 3);
+    assert_eq!(
+        comp.replace_created_time("/src", modified()).unwrap(),
+        created()
+    );
+    assert_eq!(
+        comp.replace_created_time("/src", created()).unwrap(),
+        modified()
+    );
+    assert_eq!(
+        comp.replace_modified_time("/src", created()).unwrap(),
+        modified()
+    );
+    assert_eq!(
+        comp.replace_modified_time("/src", modified()).unwrap(),
+        created()
+    );
+    let entry = comp.entry("/src").unwrap();
+    assert_eq!(entry.clsid(), &CLSID);
+    assert_eq!(entry.state_bits(), 2);
+    assert_eq!(entry.created(), created());
+    assert_eq!(entry.modified(), modified());
+
+    // Where the setters have no effect, the previous value is still the
+    // current one.
+    let epoch = comp.entry("/stream").unwrap().modified();
+    assert_eq!(
+        comp.replace_modified_time("/stream", modified()).unwrap(),
+        epoch
+    );
+    assert_eq!(
+        comp.replace_modified_time("/stream", modified()).unwrap(),
+        epoch
+    );
+    let root_created = comp.entry("/").unwrap().created();
+    assert_eq!(
+        comp.replace_created_time("/", created()).unwrap(),
+        root_created
+    );
+    assert_eq!(
+        comp.replace_created_time("/", created()).unwrap(),
+        root_created
+    );
+
+    comp.flush().unwrap();
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let entry = comp.entry("/src").unwrap();
+    assert_eq!(entry.clsid(), &CLSID);
+    assert_eq!(entry.state_bits(), 2);
+    assert_eq!(comp.entry("/stream").unwrap().state_bits(), 4);
+}
+
+#[test]
+fn failed_replace_changes_nothing() {
+    let mut comp = make_file();
+    let before = comp.entry("/src").unwrap();
+    let error = comp.replace_storage_clsid("/stream", CLSID).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.replace_storage_clsid("/missing", CLSID).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = comp.replace_state_bits("/missing", 1).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let too_early = UNIX_EPOCH - Duration::from_secs(11_644_473_601);
+    let error = comp.replace_created_time("/src", too_early).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.replace_modified_time("/src", too_early).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error =
+        comp.replace_modified_time("/missing", modified()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let after = comp.entry("/src").unwrap();
+    assert_eq!(after.clsid(), before.clsid());
+    assert_eq!(after.state_bits(), before.state_bits());
+    assert_eq!(after.created(), before.created());
+    assert_eq!(after.modified(), before.modified());
+    assert_eq!(comp.entry("/stream").unwrap().clsid(), &Uuid::nil());
+}
+
+#[test]
+fn new_files_have_zero_root_times() {
+    for version in [Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_version(version, cursor).unwrap();
+        comp.create_storage("/dir").unwrap();
+        comp.create_stream("/dir/data").unwrap().write_all(b"data").unwrap();
+        comp.flush().unwrap();
+        let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+        let root = comp.root_entry();
+        assert_eq!(root.created(), cfb_epoch());
+        assert_eq!(root.modified(), cfb_epoch());
+        assert!(comp.validate().is_empty());
+        // Storages other than the root still get real times.
+        assert!(comp.entry("/dir").unwrap().created() > UNIX_EPOCH);
+    }
+}
+
+#[test]
+fn stamp_root_modified_time() {
+    let before = SystemTime::now() - Duration::from_secs(1);
+    let options = CreateOptions::new().stamp_root_modified_time(true);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_options(options, cursor).unwrap();
+    comp.create_stream("/data").unwrap().write_all(b"data").unwrap();
+    comp.flush().unwrap();
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let root = comp.root_entry();
+    assert_eq!(root.created(), cfb_epoch());
+    assert!(root.modified() > before);
+    assert!(root.modified() <= SystemTime::now());
+    assert!(comp.validate().is_empty());
+}
+
+#[test]
+fn existing_root_times_survive_unrelated_flush() {
+    let mut comp = make_file();
+    comp.set_modified_time("/", modified()).unwrap();
+    comp.flush().unwrap();
+    // Also give the root a (nonstandard) creation time, which can't be set
+    // through the API, by patching the root directory entry directly.
+    let mut data = comp.into_inner().into_inner();
+    let name: Vec<u8> =
+        "Root Entry".encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+    let root_offset =
+        data.windows(name.len()).position(|window| window == name).unwrap();
+    let created_offset = root_offset + 100;
+    let raw_created = 0x01d0_0000_0000_0000u64.to_le_bytes();
+    data[created_offset..created_offset + 8].copy_from_slice(&raw_created);
+
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let root_created = comp.root_entry().created();
+    assert_ne!(root_created, cfb_epoch());
+    comp.create_stream("/src/new").unwrap().write_all(&[7; 5000]).unwrap();
+    comp.set_state_bits("/dst", 1).unwrap();
+    comp.flush().unwrap();
+
+    let data = comp.into_inner().into_inner();
+    assert_eq!(data[created_offset..created_offset + 8], raw_created);
+    let comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let root = comp.root_entry();
+    assert_eq!(root.created(), root_created);
+    assert_eq!(root.modified(), modified());
+}
+
+//===========================================================================//
@@ -0,0 +1,176 @@
+#![cfg(feature = "metrics")]
+
+use cfb::metrics::{AtomicMetrics, Op, OpTotals};
+use cfb::{CompoundFile, Version};
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+//===========================================================================//
+
+const DATA_LEN: usize = 100_000;
+
+fn data() -> Vec<u8> {
+    (0..DATA_LEN).map(|index| (index % 251) as u8).collect()
+}
+
+fn write_file(metrics: &Arc<AtomicMetrics>) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.set_metrics_sink(metrics.clone());
+    comp.create_stream("/data").unwrap().write_all(&data()).unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn assert_no_less(later: OpTotals, earlier: OpTotals) {
+    assert!(later.count >= earlier.count);
+    assert!(later.duration >= earlier.duration);
+    assert!(later.bytes >= earlier.bytes);
+}
+
+//===========================================================================//
+
+#[test]
+fn writing_reports_writes_and_flush() {
+    let metrics = Arc::new(AtomicMetrics::new());
+    let file = write_file(&metrics);
+
+    let flush = metrics.totals(Op::Flush);
+    assert_eq!(flush.count, 1);
+    assert!(flush.duration > Duration::ZERO);
+    assert_eq!(flush.bytes, 0);
+
+    // Every byte of stream data is written, along with some metadata
+    // (directory entries, FAT entries, and the header).  New sectors are
+    // zeroed as they are allocated, before the data goes in, so at most
+    // twice the file is written.
+    let writes = metrics.totals(Op::WriteSectors);
+    assert!(writes.count > 0);
+    assert!(writes.bytes >= DATA_LEN as u64, "{:?}", writes);
+    assert!(writes.bytes <= 2 * file.len() as u64, "{:?}", writes);
+
+    // A newly created file was never opened.
+    assert_eq!(metrics.totals(Op::Open).count, 0);
+    assert_eq!(metrics.totals(Op::Validate).count, 0);
+}
+
+#[test]
+fn opening_is_reported_once_sink_is_installed() {
+    let file = write_file(&Arc::new(AtomicMetrics::new()));
+    let mut comp = CompoundFile::open(Cursor::new(file.clone())).unwrap();
+    let metrics = Arc::new(AtomicMetrics::new());
+    comp.set_metrics_sink(metrics.clone());
+
+    let open = metrics.totals(Op::Open);
+    assert_eq!(open.count, 1);
+    assert_eq!(open.bytes, file.len() as u64);
+    let validate = metrics.totals(Op::Validate);
+    assert_eq!(validate.count, 1);
+    assert!(validate.duration <= open.duration);
+
+    // Installing another sink doesn't report the open again.
+    let other = Arc::new(AtomicMetrics::new());
+    comp.set_metrics_sink(other.clone());
+    assert_eq!(other.totals(Op::Open).count, 0);
+    comp.flush().unwrap();
+    assert_eq!(other.totals(Op::Flush).count, 1);
+    assert_eq!(metrics.totals(Op::Flush).count, 0);
+}
+
+#[test]
+fn reading_reports_exact_stream_bytes() {
+    let file = write_file(&Arc::new(AtomicMetrics::new()));
+    let mut comp = CompoundFile::open(Cursor::new(file)).unwrap();
+    let metrics = Arc::new(AtomicMetrics::new());
+    comp.set_metrics_sink(metrics.clone());
+
+    let mut stream = comp.open_stream("/data").unwrap();
+    let open_stream = metrics.totals(Op::OpenStream);
+    assert_eq!(open_stream.count, 1);
+    assert_eq!(open_stream.bytes, DATA_LEN as u64);
+    assert_eq!(metrics.totals(Op::ReadSectors).count, 0);
+
+    let mut half = vec![0; DATA_LEN / 2];
+    stream.read_exact(&mut half).unwrap();
+    let first = metrics.totals(Op::ReadSectors);
+    assert!(first.count > 0);
+    assert!(first.bytes >= half.len() as u64);
+
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    let second = metrics.totals(Op::ReadSectors);
+    assert_no_less(second, first);
+    assert_eq!(second.bytes, DATA_LEN as u64);
+    half.extend(rest);
+    assert_eq!(half, data());
+    assert_eq!(metrics.totals(Op::WriteSectors), OpTotals::default());
+}
+
+/// Writes `data()` to two streams at once, in interleaved chunks, and
+/// returns the file along with the most data ever left buffered after a
+/// write.
+fn write_two_streams(
+    metrics: &Arc<AtomicMetrics>,
+    budget: Option<u64>,
+) -> (Vec<u8>, u64) {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.set_metrics_sink(metrics.clone());
+    if let Some(budget) = budget {
+        comp.set_dirty_budget(budget);
+    }
+    let mut streams = [
+        comp.create_stream("/first").unwrap(),
+        comp.create_stream("/second").unwrap(),
+    ];
+    let mut peak = 0;
+    for chunk in data().chunks(700) {
+        for stream in streams.iter_mut() {
+            stream.write_all(chunk).unwrap();
+            peak = peak.max(comp.dirty_bytes());
+        }
+    }
+    drop(streams);
+    assert_eq!(comp.dirty_bytes(), 0);
+    comp.flush().unwrap();
+    (comp.into_inner().into_inner(), peak)
+}
+
+#[test]
+fn dirty_budget_bounds_buffered_writes() {
+    let metrics = Arc::new(AtomicMetrics::new());
+    let (_, peak) = write_two_streams(&metrics, None);
+    assert!(peak > 3000, "{}", peak);
+    assert_eq!(metrics.totals(Op::EarlyFlush), OpTotals::default());
+
+    let metrics = Arc::new(AtomicMetrics::new());
+    let (file, peak) = write_two_streams(&metrics, Some(3000));
+    assert!(peak <= 3000, "{}", peak);
+    let early = metrics.totals(Op::EarlyFlush);
+    assert!(early.count > 0);
+    assert!(early.bytes <= 2 * DATA_LEN as u64, "{:?}", early);
+
+    let mut comp = CompoundFile::open(Cursor::new(file)).unwrap();
+    for path in ["/first", "/second"] {
+        let mut stream = comp.open_stream(path).unwrap();
+        let mut contents = Vec::new();
+        stream.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, data(), "{}", path);
+    }
+}
+
+#[test]
+fn metrics_can_be_reset() {
+    let metrics = Arc::new(AtomicMetrics::new());
+    write_file(&metrics);
+    assert!(metrics.totals(Op::WriteSectors).count > 0);
+    metrics.reset();
+    for op in Op::ALL {
+        assert_eq!(metrics.totals(op), OpTotals::default(), "{:?}", op);
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,156 @@
+//! Tests for reading files whose header declares a mini sector size other
+//! than the standard 64 bytes.
+
+use cfb::{CompoundFile, StreamId, ValidationIssueKind, Version};
+use rawcfb::{dir_entry_offset, sector_offset, u32_at};
+use std::io::{Cursor, Read, Write};
+
+mod rawcfb;
+
+//===========================================================================//
+
+const END_OF_CHAIN: u32 = 0xfffffffe;
+const FREE_SECTOR: u32 = 0xffffffff;
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..(offset + 4)].copy_from_slice(&value.to_le_bytes());
+}
+
+fn contents_a() -> Vec<u8> {
+    (0..100).collect()
+}
+
+fn contents_b() -> Vec<u8> {
+    (0..200).map(|i| 255 - i as u8).collect()
+}
+
+/// Returns a V3 file with streams "/a" (100 bytes) and "/b" (200 bytes),
+/// byte-patched to use 128-byte mini sectors.  The mini stream's contents
+/// are left in place: "/a" occupies 128-byte mini sector 0, and "/b" (which
+/// starts at byte 128) occupies mini sectors 1 and 2.
+fn make_shift_7_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/a").unwrap().write_all(&contents_a()).unwrap();
+    comp.create_stream("/b").unwrap().write_all(&contents_b()).unwrap();
+    let mut data = comp.into_inner().into_inner();
+
+    assert_eq!(data[32], 6);
+    data[32] = 7;
+    let root = dir_entry_offset(&data, 0);
+    assert_eq!(u32_at(&data, root + 120), 384);
+    let b = dir_entry_offset(&data, 2);
+    assert_eq!(&data[b..(b + 2)], b"b\0");
+    assert_eq!(u32_at(&data, b + 116), 2);
+    put_u32(&mut data, b + 116, 1);
+    let minifat = sector_offset(u32_at(&data, 60));
+    let entries = [END_OF_CHAIN, 2, END_OF_CHAIN];
+    for index in 0..128 {
+        let entry = entries.get(index).copied().unwrap_or(FREE_SECTOR);
+        put_u32(&mut data, minifat + 4 * index, entry);
+    }
+    data
+}
+
+fn read_stream<F: Read + std::io::Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn read_file_with_128_byte_mini_sectors() {
+    let data = make_shift_7_file();
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    let kinds: Vec<ValidationIssueKind> =
+        comp.open_warnings().iter().map(|issue| issue.kind()).collect();
+    assert_eq!(kinds, vec![ValidationIssueKind::NonstandardMiniSectorShift]);
+    assert_eq!(comp.mini_sector_len(), 128);
+    assert_eq!(comp.version().mini_sector_len(), 64);
+    assert_eq!(read_stream(&mut comp, "/a"), contents_a());
+    assert_eq!(read_stream(&mut comp, "/b"), contents_b());
+    let mut many = comp.read_many(&["/b", "/a"]).unwrap();
+    assert_eq!(many.remove(0).1, contents_b());
+    assert_eq!(many.remove(0).1, contents_a());
+}
+
+#[test]
+fn modify_file_with_128_byte_mini_sectors() {
+    let data = make_shift_7_file();
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let contents_c = vec![9; 300];
+    comp.create_stream("/c").unwrap().write_all(&contents_c).unwrap();
+    comp.remove_stream("/a").unwrap();
+    comp.flush().unwrap();
+    let data = comp.into_inner().into_inner();
+    // The existing file keeps its own mini sector size.
+    assert_eq!(data[32], 7);
+
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert_eq!(comp.mini_sector_len(), 128);
+    assert_eq!(read_stream(&mut comp, "/b"), contents_b());
+    assert_eq!(read_stream(&mut comp, "/c"), contents_c);
+    // "/c" was appended as three more 128-byte mini sectors, and the one
+    // "/a" used is now free.
+    let root = comp.raw_dir_entry(StreamId::ROOT).unwrap();
+    assert_eq!(&root[120..128], &768u64.to_le_bytes());
+    assert_eq!(comp.stats().unwrap().num_free_mini_sectors(), 1);
+}
+
+#[test]
+fn new_files_use_64_byte_mini_sectors() {
+    for version in [Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let comp = CompoundFile::create_with_version(version, cursor).unwrap();
+        assert_eq!(comp.mini_sector_len(), 64);
+        let data = comp.into_inner().into_inner();
+        assert_eq!(&data[32..34], &[6, 0]);
+    }
+}
+
+#[test]
+fn reject_mini_sectors_larger_than_sectors() {
+    let mut data = make_shift_7_file();
+    data[32] = 10;
+    let error = CompoundFile::open(Cursor::new(data)).unwrap_err();
+    assert!(error.to_string().contains("Unsupported mini sector shift"));
+}
+
+#[test]
+fn mini_stream_code_has_no_hardcoded_mini_sector_len() {
+    // Everything that deals with mini sectors of an open file must use the
+    // length given by its header rather than the standard constant.
+    let sources = [
+        ("minialloc.rs", include_str!("../src/internal/minialloc.rs")),
+        ("minichain.rs", include_str!("../src/internal/minichain.rs")),
+        ("directory.rs", include_str!("../src/internal/directory.rs")),
+    ];
+    for (name, source) in sources {
+        let code = source.split("#[cfg(test)]").next().unwrap();
+        for (number, line) in code.lines().enumerate() {
+            let line = line.split("//").next().unwrap();
+            if line.contains("seek_within_header") {
+                continue;
+            }
+            assert!(
+                !line.contains("MINI_SECTOR_LEN")
+                    && !line.contains("MINI_SECTOR_SHIFT")
+                    && !line
+                        .split(|c: char| !c.is_alphanumeric())
+                        .any(|word| word == "64" || word == "0x40"),
+                "{}:{}: {}",
+                name,
+                number + 1,
+                line
+            );
+        }
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,466 @@
+//! A deliberately simple, read-only CFB parser written directly from the
+//! MS-CFB specification.  It shares no code with the `cfb` crate, so that
+//! round-trip tests through it can catch bugs where the crate reads and
+//! writes some field in the same wrong way.
+//!
+//! Everything is read into memory up front, and any deviation from the spec
+//! is reported as an `Err` describing the problem.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+const MAGIC: [u8; 8] = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+const MAXREGSECT: u32 = 0xfffffffa;
+const DIFSECT: u32 = 0xfffffffc;
+const FATSECT: u32 = 0xfffffffd;
+const ENDOFCHAIN: u32 = 0xfffffffe;
+const FREESECT: u32 = 0xffffffff;
+const NOSTREAM: u32 = 0xffffffff;
+const MINI_SECTOR_SIZE: usize = 64;
+const MINI_STREAM_CUTOFF: u64 = 4096;
+
+//===========================================================================//
+
+/// An object in the parsed file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Node {
+    Storage { clsid: [u8; 16], state_bits: u32 },
+    Stream { data: Vec<u8>, state_bits: u32 },
+}
+
+/// A parsed compound file.
+#[derive(Debug)]
+pub struct File {
+    pub major_version: u16,
+    /// Every object in the file other than the root, keyed by its path
+    /// (e.g. `"/foo/bar"`).
+    pub nodes: BTreeMap<String, Node>,
+    pub root_clsid: [u8; 16],
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+//===========================================================================//
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+struct DirEntry {
+    name: String,
+    object_type: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    clsid: [u8; 16],
+    state_bits: u32,
+    start: u32,
+    size: u64,
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    sector_size: usize,
+    fat: Vec<u32>,
+    /// Sectors already claimed by some chain, to detect cross-linking.
+    used: BTreeSet<u32>,
+}
+
+impl<'a> Parser<'a> {
+    fn sector(&self, id: u32) -> Result<&'a [u8]> {
+        let start = (id as usize + 1) * self.sector_size;
+        let end = start + self.sector_size;
+        if id > MAXREGSECT || end > self.data.len() {
+            return Err(format!("sector {} is out of range", id));
+        }
+        Ok(&self.data[start..end])
+    }
+
+    fn claim(&mut self, id: u32) -> Result<()> {
+        if !self.used.insert(id) {
+            return Err(format!("sector {} is used twice", id));
+        }
+        Ok(())
+    }
+
+    /// Follows a chain through the FAT, returning its sector IDs.
+    fn chain(&mut self, start: u32) -> Result<Vec<u32>> {
+        let mut ids = Vec::new();
+        let mut id = start;
+        while id != ENDOFCHAIN {
+            if id as usize >= self.fat.len() {
+                return Err(format!("chain refers to sector {}", id));
+            }
+            if ids.len() > self.fat.len() {
+                return Err("loop in FAT chain".to_string());
+            }
+            self.claim(id)?;
+            ids.push(id);
+            id = self.fat[id as usize];
+        }
+        Ok(ids)
+    }
+
+    fn read_chain(&mut self, start: u32) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for id in self.chain(start)? {
+            out.extend_from_slice(self.sector(id)?);
+        }
+        Ok(out)
+    }
+}
+
+//===========================================================================//
+
+fn parse_dir_entry(raw: &[u8]) -> Result<DirEntry> {
+    let name_len = u16_at(raw, 64) as usize;
+    let object_type = raw[66];
+    let name = if object_type == 0 {
+        String::new()
+    } else {
+        if !(2..=64).contains(&name_len) || name_len % 2 != 0 {
+            return Err(format!("bad name length {}", name_len));
+        }
+        let units: Vec<u16> =
+            (0..name_len / 2).map(|index| u16_at(raw, index * 2)).collect();
+        if units.last() != Some(&0) {
+            return Err("name is not null-terminated".to_string());
+        }
+        String::from_utf16(&units[..units.len() - 1])
+            .map_err(|_| "name is not valid UTF-16".to_string())?
+    };
+    if raw[67] > 1 {
+        return Err(format!("bad color {}", raw[67]));
+    }
+    let mut clsid = [0u8; 16];
+    clsid.copy_from_slice(&raw[80..96]);
+    Ok(DirEntry {
+        name,
+        object_type,
+        left: u32_at(raw, 68),
+        right: u32_at(raw, 72),
+        child: u32_at(raw, 76),
+        clsid,
+        state_bits: u32_at(raw, 96),
+        start: u32_at(raw, 116),
+        size: u64_at(raw, 120),
+    })
+}
+
+/// Compares two names the way the spec orders siblings: shorter names first,
+/// then by simple uppercase code point.
+fn compare_names(a: &str, b: &str) -> std::cmp::Ordering {
+    let upper = |name: &str| -> Vec<u32> {
+        name.encode_utf16()
+            .map(|unit| {
+                char::from_u32(unit as u32)
+                    .map(|ch| {
+                        let mut upper = ch.to_uppercase();
+                        match (upper.next(), upper.next()) {
+                            (Some(up), None) if (up as u32) < 0x10000 => {
+                                up as u32
+                            }
+                            _ => unit as u32,
+                        }
+                    })
+                    .unwrap_or(unit as u32)
+            })
+            .collect()
+    };
+    let len_a = a.encode_utf16().count();
+    let len_b = b.encode_utf16().count();
+    len_a.cmp(&len_b).then_with(|| upper(a).cmp(&upper(b)))
+}
+
+/// Parses a complete compound file.
+pub fn parse(data: &[u8]) -> Result<File> {
+    if data.len() < 512 {
+        return Err("file is shorter than the header".to_string());
+    }
+    if data[0..8] != MAGIC {
+        return Err("bad signature".to_string());
+    }
+    if data[8..24] != [0; 16] {
+        return Err("header CLSID is not zero".to_string());
+    }
+    let major_version = u16_at(data, 26);
+    let sector_shift = u16_at(data, 30);
+    match (major_version, sector_shift) {
+        (3, 9) | (4, 12) => {}
+        _ => {
+            return Err(format!(
+                "version {} with sector shift {}",
+                major_version, sector_shift
+            ))
+        }
+    }
+    if u16_at(data, 28) != 0xfffe {
+        return Err("bad byte order mark".to_string());
+    }
+    if u16_at(data, 32) != 6 {
+        return Err("bad mini sector shift".to_string());
+    }
+    if data[34..40] != [0; 6] {
+        return Err("reserved header bytes are not zero".to_string());
+    }
+    let num_dir_sectors = u32_at(data, 40);
+    if major_version == 3 && num_dir_sectors != 0 {
+        return Err("version 3 file has nonzero directory count".to_string());
+    }
+    let num_fat_sectors = u32_at(data, 44);
+    let first_dir_sector = u32_at(data, 48);
+    if u32_at(data, 56) != MINI_STREAM_CUTOFF as u32 {
+        return Err("bad mini stream cutoff".to_string());
+    }
+    let first_minifat_sector = u32_at(data, 60);
+    let num_minifat_sectors = u32_at(data, 64);
+    let first_difat_sector = u32_at(data, 68);
+    let num_difat_sectors = u32_at(data, 72);
+    let sector_size = 1usize << sector_shift;
+    if major_version == 4 && data.len() >= 4096 && data[512..4096] != [0; 3584]
+    {
+        return Err("version 4 header padding is not zero".to_string());
+    }
+
+    let mut parser =
+        Parser { data, sector_size, fat: Vec::new(), used: BTreeSet::new() };
+
+    // Gather the DIFAT: 109 entries in the header, then a chain of DIFAT
+    // sectors (each ending with the ID of the next one).
+    let mut fat_sector_ids: Vec<u32> =
+        (0..109).map(|index| u32_at(data, 76 + 4 * index)).collect();
+    let mut difat_sector_ids = Vec::new();
+    let mut difat_sector = first_difat_sector;
+    while difat_sector != ENDOFCHAIN && difat_sector != FREESECT {
+        if difat_sector_ids.len() as u32 >= num_difat_sectors {
+            return Err("DIFAT chain is longer than declared".to_string());
+        }
+        difat_sector_ids.push(difat_sector);
+        let sector = parser.sector(difat_sector)?;
+        let per_sector = sector_size / 4 - 1;
+        fat_sector_ids
+            .extend((0..per_sector).map(|index| u32_at(sector, 4 * index)));
+        difat_sector = u32_at(sector, 4 * per_sector);
+    }
+    if difat_sector_ids.len() as u32 != num_difat_sectors {
+        return Err("DIFAT chain is shorter than declared".to_string());
+    }
+    let (used_ids, unused_ids) =
+        fat_sector_ids.split_at(num_fat_sectors as usize);
+    if unused_ids.iter().any(|&id| id != FREESECT) {
+        return Err("unused DIFAT entries are not FREESECT".to_string());
+    }
+    for &id in used_ids {
+        parser.fat.extend(
+            parser.sector(id)?.chunks(4).map(|chunk| u32_at(chunk, 0)),
+        );
+    }
+    for &id in used_ids {
+        if parser.fat.get(id as usize) != Some(&FATSECT) {
+            return Err(format!("FAT sector {} is not marked FATSECT", id));
+        }
+        parser.claim(id)?;
+    }
+    for &id in &difat_sector_ids {
+        if parser.fat.get(id as usize) != Some(&DIFSECT) {
+            return Err(format!("DIFAT sector {} is not marked DIFSECT", id));
+        }
+        parser.claim(id)?;
+    }
+    let num_sectors = (data.len() - 512).div_ceil(sector_size);
+    for (id, &next) in parser.fat.iter().enumerate().skip(num_sectors) {
+        if next != FREESECT {
+            return Err(format!("FAT entry {} past end of file is used", id));
+        }
+    }
+
+    // Read the directory.
+    let dir_sectors = parser.chain(first_dir_sector)?;
+    if major_version == 4 && dir_sectors.len() as u32 != num_dir_sectors {
+        return Err("directory sector count is wrong".to_string());
+    }
+    let mut dir_data = Vec::new();
+    for &id in &dir_sectors {
+        dir_data.extend_from_slice(parser.sector(id)?);
+    }
+    let entries = dir_data
+        .chunks(128)
+        .map(parse_dir_entry)
+        .collect::<Result<Vec<DirEntry>>>()?;
+    let root = entries.first().ok_or("directory is empty")?;
+    if root.object_type != 5 || root.name != "Root Entry" {
+        return Err("first directory entry is not the root".to_string());
+    }
+
+    // Read the MiniFAT and the mini stream.
+    let minifat_data = parser.read_chain(first_minifat_sector)?;
+    if minifat_data.len() / sector_size != num_minifat_sectors as usize {
+        return Err("MiniFAT sector count is wrong".to_string());
+    }
+    let minifat: Vec<u32> =
+        minifat_data.chunks(4).map(|chunk| u32_at(chunk, 0)).collect();
+    let mini_stream = parser.read_chain(root.start)?;
+    if (root.size as usize) > mini_stream.len() {
+        return Err("mini stream is longer than its chain".to_string());
+    }
+    let mut used_mini_sectors = BTreeSet::new();
+
+    // Walk the tree, reading stream data along the way.
+    let mut nodes = BTreeMap::new();
+    let mut visited = BTreeSet::new();
+    let mut storages = vec![(String::new(), root.child)];
+    while let Some((parent_path, child)) = storages.pop() {
+        // In-order traversal of this storage's sibling tree.
+        let mut siblings = Vec::new();
+        let mut stack = Vec::new();
+        let mut current = child;
+        loop {
+            while current != NOSTREAM {
+                if !visited.insert(current) {
+                    return Err(format!("entry {} reached twice", current));
+                }
+                let entry = entries
+                    .get(current as usize)
+                    .ok_or(format!("entry {} is out of range", current))?;
+                stack.push(current);
+                current = entry.left;
+            }
+            match stack.pop() {
+                Some(id) => {
+                    siblings.push(id);
+                    current = entries[id as usize].right;
+                }
+                None => break,
+            }
+        }
+        for pair in siblings.windows(2) {
+            let (a, b) =
+                (&entries[pair[0] as usize], &entries[pair[1] as usize]);
+            if compare_names(&a.name, &b.name) != std::cmp::Ordering::Less {
+                return Err(format!(
+                    "siblings {:?} and {:?} are out of order",
+                    a.name, b.name
+                ));
+            }
+        }
+        for id in siblings {
+            let entry = &entries[id as usize];
+            let path = format!("{}/{}", parent_path, entry.name);
+            match entry.object_type {
+                1 => {
+                    if entry.start != 0 && entry.start != ENDOFCHAIN
+                        || entry.size != 0
+                    {
+                        return Err(format!("storage {} has data", path));
+                    }
+                    storages.push((path.clone(), entry.child));
+                    nodes.insert(
+                        path,
+                        Node::Storage {
+                            clsid: entry.clsid,
+                            state_bits: entry.state_bits,
+                        },
+                    );
+                }
+                2 => {
+                    if entry.child != NOSTREAM {
+                        return Err(format!("stream {} has children", path));
+                    }
+                    if entry.clsid != [0; 16] {
+                        return Err(format!("stream {} has a CLSID", path));
+                    }
+                    let size = if major_version == 3 {
+                        if entry.size >> 32 != 0 {
+                            return Err(format!("stream {} is too big", path));
+                        }
+                        entry.size & 0xffffffff
+                    } else {
+                        entry.size
+                    };
+                    let mut data = if size == 0 {
+                        Vec::new()
+                    } else if size < MINI_STREAM_CUTOFF {
+                        let mut out = Vec::new();
+                        let mut id = entry.start;
+                        while id != ENDOFCHAIN {
+                            if id as usize >= minifat.len()
+                                || !used_mini_sectors.insert(id)
+                            {
+                                return Err(format!(
+                                    "bad mini sector {} in {}",
+                                    id, path
+                                ));
+                            }
+                            let offset = id as usize * MINI_SECTOR_SIZE;
+                            let end = offset + MINI_SECTOR_SIZE;
+                            if end > root.size as usize {
+                                return Err(format!(
+                                    "mini sector {} is past the end of the \
+                                     mini stream",
+                                    id
+                                ));
+                            }
+                            out.extend_from_slice(&mini_stream[offset..end]);
+                            id = minifat[id as usize];
+                        }
+                        out
+                    } else {
+                        parser.read_chain(entry.start)?
+                    };
+                    if (data.len() as u64) < size {
+                        return Err(format!("stream {} is truncated", path));
+                    }
+                    if (data.len() as u64) - size
+                        >= if size < MINI_STREAM_CUTOFF {
+                            MINI_SECTOR_SIZE as u64
+                        } else {
+                            sector_size as u64
+                        }
+                    {
+                        return Err(format!(
+                            "stream {} chain is too long",
+                            path
+                        ));
+                    }
+                    data.truncate(size as usize);
+                    nodes.insert(
+                        path,
+                        Node::Stream { data, state_bits: entry.state_bits },
+                    );
+                }
+                other => {
+                    return Err(format!(
+                        "entry {} has object type {}",
+                        path, other
+                    ))
+                }
+            }
+        }
+    }
+
+    // Every allocated directory entry must be reachable from the root, and
+    // every allocated sector must belong to some chain.
+    for (id, entry) in entries.iter().enumerate().skip(1) {
+        if entry.object_type != 0 && !visited.contains(&(id as u32)) {
+            return Err(format!("entry {} is unreachable", id));
+        }
+    }
+    for (id, &next) in parser.fat.iter().enumerate() {
+        if next != FREESECT && !parser.used.contains(&(id as u32)) {
+            return Err(format!("sector {} is allocated but unused", id));
+        }
+    }
+
+    Ok(File { major_version, nodes, root_clsid: root.clsid })
+}
@@ -0,0 +1,369 @@
+#![cfg(feature = "msi")]
+
+use cfb::msi::{
+    decode_chars, decode_into, decode_name, encode_into, encode_name, MsiArch,
+    MsiOptions, MsiSkeleton, DATABASE_CLSID, SUMMARY_INFO_STREAM_NAME,
+};
+use cfb::CompoundFile;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Cursor, ErrorKind, Read};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const PACKAGE_CODE: Uuid =
+    Uuid::from_u128(0x0123abcd_4567_89ef_0123_456789abcdef);
+
+const ALPHABET: &str =
+    "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz._";
+
+/// A small deterministic pseudo-random number generator (xorshift64), so
+/// that the fuzz-style tests are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, bound: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as u32
+    }
+}
+
+/// A `fmt::Write` that fails once it has been given `limit` characters.
+struct LimitedWriter {
+    output: String,
+    limit: usize,
+}
+
+impl fmt::Write for LimitedWriter {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for chr in string.chars() {
+            if self.output.chars().count() == self.limit {
+                return Err(fmt::Error);
+            }
+            self.output.push(chr);
+        }
+        Ok(())
+    }
+}
+
+/// A property value from a property set, as parsed by `properties`.
+#[derive(Debug, PartialEq)]
+enum Value {
+    I2(i16),
+    I4(i32),
+    Str(String),
+    FileTime(u64),
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Parses a property set stream holding a single property set, checking its
+/// header, and returns the format ID and properties.
+fn properties(data: &[u8]) -> ([u8; 16], BTreeMap<u32, Value>) {
+    assert_eq!(u16_at(data, 0), 0xfffe);
+    assert_eq!(&data[8..24], &[0; 16]);
+    assert_eq!(u32_at(data, 24), 1);
+    let mut fmtid = [0; 16];
+    fmtid.copy_from_slice(&data[28..44]);
+    let start = u32_at(data, 44) as usize;
+    assert_eq!(start, 48);
+    let set = &data[start..];
+    assert_eq!(u32_at(set, 0) as usize, set.len());
+    let mut properties = BTreeMap::new();
+    for index in 0..u32_at(set, 4) as usize {
+        let pid = u32_at(set, 8 + 8 * index);
+        let offset = u32_at(set, 12 + 8 * index) as usize;
+        assert_eq!(offset % 4, 0);
+        let value = match u32_at(set, offset) {
+            2 => Value::I2(u16_at(set, offset + 4) as i16),
+            3 => Value::I4(u32_at(set, offset + 4) as i32),
+            30 => {
+                let len = u32_at(set, offset + 4) as usize;
+                let bytes = &set[offset + 8..offset + 8 + len];
+                assert_eq!(bytes.last(), Some(&0));
+                let string = &bytes[..len - 1];
+                Value::Str(String::from_utf8(string.to_vec()).unwrap())
+            }
+            64 => {
+                let low = u32_at(set, offset + 4) as u64;
+                let high = u32_at(set, offset + 8) as u64;
+                Value::FileTime(low | (high << 32))
+            }
+            other => panic!("unexpected property type {}", other),
+        };
+        properties.insert(pid, value);
+    }
+    (fmtid, properties)
+}
+
+fn summary_info(
+    comp: &mut CompoundFile<Cursor<Vec<u8>>>,
+) -> BTreeMap<u32, Value> {
+    let mut data = Vec::new();
+    comp.open_stream(format!("/{}", SUMMARY_INFO_STREAM_NAME))
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    let (fmtid, properties) = self::properties(&data);
+    let expected =
+        Uuid::parse_str("f29f85e0-4ff9-1068-ab91-08002b27b3d9").unwrap();
+    assert_eq!(fmtid, expected.to_bytes_le());
+    properties
+}
+
+fn create(options: MsiOptions) -> CompoundFile<Cursor<Vec<u8>>> {
+    let comp = MsiSkeleton::create(Cursor::new(Vec::new()), options).unwrap();
+    let cursor = comp.into_inner();
+    CompoundFile::open_strict(cursor).unwrap()
+}
+
+//===========================================================================//
+
+#[test]
+fn skeleton_has_database_structure() {
+    let mut comp = create(MsiOptions::new(MsiArch::X64, PACKAGE_CODE));
+    assert_eq!(
+        *comp.root_entry().clsid(),
+        Uuid::parse_str("000c1084-0000-0000-c000-000000000046").unwrap()
+    );
+    assert_eq!(*comp.root_entry().clsid(), DATABASE_CLSID);
+    assert_eq!(comp.root_entry().state_bits(), 0);
+
+    let mut names: Vec<String> =
+        comp.read_root_storage().map(|e| e.name().to_string()).collect();
+    names.sort();
+    let mut expected: Vec<String> =
+        ["_StringPool", "_StringData", "_Tables", "_Columns"]
+            .iter()
+            .map(|name| encode_name(name, true).unwrap())
+            .collect();
+    expected.push(SUMMARY_INFO_STREAM_NAME.to_string());
+    expected.sort();
+    assert_eq!(names, expected);
+    assert!(names.iter().all(|name| name.starts_with('\u{4840}')
+        || name == SUMMARY_INFO_STREAM_NAME));
+
+    let mut pool = Vec::new();
+    let path = format!("/{}", encode_name("_StringPool", true).unwrap());
+    comp.open_stream(path).unwrap().read_to_end(&mut pool).unwrap();
+    assert_eq!(pool, 1252u32.to_le_bytes());
+    for name in ["_StringData", "_Tables", "_Columns"] {
+        let path = format!("/{}", encode_name(name, true).unwrap());
+        assert!(comp.entry(path).unwrap().is_empty(), "{}", name);
+    }
+}
+
+#[test]
+fn summary_info_has_required_properties() {
+    let created = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let options = MsiOptions::new(MsiArch::X64, PACKAGE_CODE)
+        .languages(vec![1033, 1031])
+        .title("Installation Database")
+        .author("Example Corp")
+        .creating_application("cfb")
+        .created(created);
+    let mut comp = create(options);
+    let properties = summary_info(&mut comp);
+    assert_eq!(properties[&1], Value::I2(1252));
+    assert_eq!(properties[&2], Value::Str("Installation Database".into()));
+    assert_eq!(properties[&4], Value::Str("Example Corp".into()));
+    assert_eq!(properties[&7], Value::Str("x64;1033,1031".into()));
+    assert_eq!(
+        properties[&9],
+        Value::Str("{0123ABCD-4567-89EF-0123-456789ABCDEF}".into())
+    );
+    let filetime = 116_444_736_000_000_000 + 1_600_000_000 * 10_000_000;
+    assert_eq!(properties[&12], Value::FileTime(filetime));
+    assert_eq!(properties[&13], Value::FileTime(filetime));
+    assert_eq!(properties[&14], Value::I4(200));
+    assert_eq!(properties[&15], Value::I4(0));
+    assert_eq!(properties[&18], Value::Str("cfb".into()));
+    assert!(!properties.contains_key(&3));
+}
+
+#[test]
+fn page_count_and_template_encode_architecture() {
+    let cases = [
+        (MsiArch::Intel, "Intel;1033", 200),
+        (MsiArch::X64, "x64;1033", 200),
+        (MsiArch::Intel64, "Intel64;1033", 200),
+        (MsiArch::Arm64, "Arm64;1033", 500),
+    ];
+    for &(arch, template, page_count) in cases.iter() {
+        let mut comp = create(MsiOptions::new(arch, PACKAGE_CODE));
+        let properties = summary_info(&mut comp);
+        assert_eq!(properties[&7], Value::Str(template.into()));
+        assert_eq!(properties[&14], Value::I4(page_count));
+    }
+}
+
+#[test]
+fn codepage_applies_to_pool_and_strings() {
+    let options = MsiOptions::new(MsiArch::Intel, PACKAGE_CODE)
+        .codepage(65001)
+        .title("Caf\u{e9}");
+    let mut comp = create(options);
+    assert_eq!(summary_info(&mut comp)[&2], Value::Str("Caf\u{e9}".into()));
+    let mut pool = Vec::new();
+    let path = format!("/{}", encode_name("_StringPool", true).unwrap());
+    comp.open_stream(path).unwrap().read_to_end(&mut pool).unwrap();
+    assert_eq!(pool, 65001u32.to_le_bytes());
+
+    let options =
+        MsiOptions::new(MsiArch::Intel, PACKAGE_CODE).title("Caf\u{e9}");
+    let error =
+        MsiSkeleton::create(Cursor::new(Vec::new()), options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn encode_real_stream_names() {
+    assert_eq!(
+        encode_name("_StringPool", true).unwrap(),
+        "\u{4840}\u{3f3f}\u{4577}\u{446c}\u{3e6a}\u{44b2}\u{482f}"
+    );
+    assert_eq!(
+        encode_name("_Columns", true).unwrap(),
+        "\u{4840}\u{3b3f}\u{43f2}\u{4438}\u{45b1}"
+    );
+    for &(name, is_table) in &[
+        ("_StringPool", true),
+        ("File", true),
+        ("Binary", true),
+        ("Binary.foo", false),
+        ("Icon.app_1.ico", false),
+        ("x", false),
+        ("", true),
+    ] {
+        let encoded = encode_name(name, is_table).unwrap();
+        assert_eq!(decode_name(&encoded), (name.to_string(), is_table));
+    }
+    assert_eq!(
+        decode_name(SUMMARY_INFO_STREAM_NAME),
+        (SUMMARY_INFO_STREAM_NAME.to_string(), false)
+    );
+}
+
+#[test]
+fn encode_rejects_unencodable_names() {
+    for name in ["!_StringPool", "Binary foo", "Caf\u{e9}", "a/b"] {
+        let error = encode_name(name, false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput, "{:?}", name);
+    }
+}
+
+#[test]
+fn random_names_round_trip() {
+    let alphabet: Vec<char> = ALPHABET.chars().collect();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut encoded = String::new();
+    let mut decoded = String::new();
+    for _ in 0..2000 {
+        let len = rng.next(40) as usize;
+        let name: String = (0..len)
+            .map(|_| alphabet[rng.next(alphabet.len() as u32) as usize])
+            .collect();
+        let is_table = rng.next(2) == 0;
+        encoded.clear();
+        encode_into(&name, is_table, &mut encoded).unwrap();
+        assert_eq!(encoded, encode_name(&name, is_table).unwrap());
+        assert_eq!(
+            encoded.chars().count(),
+            is_table as usize + len.div_ceil(2)
+        );
+        assert_eq!(decode_name(&encoded), (name.clone(), is_table));
+        decoded.clear();
+        assert_eq!(decode_into(&encoded, &mut decoded), Ok(is_table));
+        assert_eq!(decoded, name);
+        let chars = decode_chars(&encoded);
+        assert_eq!(chars.is_table(), is_table);
+        assert_eq!(chars.collect::<String>(), name);
+    }
+}
+
+#[test]
+fn decode_arbitrary_names_without_panicking() {
+    // Characters at and around the edges of the encoded ranges, around the
+    // surrogate gap, and the table prefix (which only counts at the start).
+    let interesting: Vec<char> = [
+        0x37ff, 0x3800, 0x3801, 0x3fff, 0x47ff, 0x4800, 0x483f, 0x4840,
+        0x4841, 0xd7ff, 0xe000, 0xfffd, 0xffff, 0x10000, 0x10ffff, 0x0, 0x5,
+        0x2f, 0x7f,
+    ]
+    .iter()
+    .map(|&value| char::from_u32(value).unwrap())
+    .collect();
+    let mut rng = Rng(0x0123_4567_89ab_cdef);
+    let mut decoded = String::new();
+    for _ in 0..5000 {
+        let len = rng.next(20) as usize;
+        let name: String = (0..len)
+            .map(|_| match rng.next(3) {
+                0 => interesting[rng.next(interesting.len() as u32) as usize],
+                1 => char::from_u32(0x3800 + rng.next(0x1100)).unwrap(),
+                _ => loop {
+                    if let Some(chr) = char::from_u32(rng.next(0x110000)) {
+                        break chr;
+                    }
+                },
+            })
+            .collect();
+        let (expected, is_table) = decode_name(&name);
+        assert_eq!(is_table, name.starts_with('\u{4840}'), "{:?}", name);
+        decoded.clear();
+        assert_eq!(decode_into(&name, &mut decoded), Ok(is_table));
+        assert_eq!(decoded, expected);
+        let chars = decode_chars(&name);
+        let (min, max) = chars.size_hint();
+        let count = expected.chars().count();
+        assert!(min <= count && count <= max.unwrap(), "{:?}", name);
+        assert_eq!(chars.collect::<String>(), expected);
+        let num_input = name.chars().count() - is_table as usize;
+        assert!(num_input <= count && count <= 2 * num_input);
+    }
+}
+
+#[test]
+fn decode_and_encode_into_propagate_write_errors() {
+    let encoded = encode_name("_StringPool", true).unwrap();
+    let mut out = LimitedWriter { output: String::new(), limit: 4 };
+    assert_eq!(decode_into(&encoded, &mut out), Err(fmt::Error));
+    assert_eq!(out.output, "_Str");
+    let mut out = LimitedWriter { output: String::new(), limit: 3 };
+    let error = encode_into("_StringPool", true, &mut out).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Other);
+    // Names that can't be encoded are rejected before anything is written.
+    let mut out = String::new();
+    let error = encode_into("_String Pool", true, &mut out).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(out.is_empty());
+}
+
+#[test]
+fn open_msi_stream_by_readable_name() {
+    let mut comp = create(MsiOptions::new(MsiArch::X64, PACKAGE_CODE));
+    let mut pool = Vec::new();
+    comp.open_msi_stream("_StringPool", true)
+        .unwrap()
+        .read_to_end(&mut pool)
+        .unwrap();
+    assert_eq!(pool, 1252u32.to_le_bytes());
+    let result = comp.open_msi_stream("_StringPool", false);
+    assert_eq!(result.err().unwrap().kind(), ErrorKind::NotFound);
+    let result = comp.open_msi_stream("!_StringPool", true);
+    assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidInput);
+}
+
+//===========================================================================//
@@ -0,0 +1,259 @@
+use cfb::{CompoundFile, Version};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+//===========================================================================//
+
+/// A wrapper around a cursor that records the offset and length of every
+/// write made to it, and whose contents can be looked at while the
+/// compound file is using it.
+struct TracingWriter {
+    inner: Rc<RefCell<Cursor<Vec<u8>>>>,
+    writes: Rc<RefCell<Vec<(u64, usize)>>>,
+}
+
+impl Read for TracingWriter {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().read(buf)
+    }
+}
+
+impl Write for TracingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        let offset = inner.position();
+        let num_bytes = inner.write(buf)?;
+        self.writes.borrow_mut().push((offset, num_bytes));
+        Ok(num_bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.borrow_mut().flush()
+    }
+}
+
+impl Seek for TracingWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.borrow_mut().seek(pos)
+    }
+}
+
+type Comp = CompoundFile<TracingWriter>;
+
+struct Traced {
+    comp: Comp,
+    file: Rc<RefCell<Cursor<Vec<u8>>>>,
+    writes: Rc<RefCell<Vec<(u64, usize)>>>,
+}
+
+impl Traced {
+    fn contents(&self) -> Vec<u8> {
+        self.file.borrow().get_ref().clone()
+    }
+}
+
+const SECTOR_LEN: u64 = 512;
+const MINI_SECTOR_LEN: u64 = 64;
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(7) ^ seed).collect()
+}
+
+/// Builds a version 3 file with a stream in the mini stream, a stream in
+/// regular sectors, and other streams interleaved with them, and opens it
+/// with a tracing writer.
+fn make_file() -> Traced {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/mini").unwrap();
+    let mini_data = data(300, 1);
+    for index in 0..3 {
+        let mut mini = comp.open_stream("/mini").unwrap();
+        mini.seek(SeekFrom::End(0)).unwrap();
+        mini.write_all(&mini_data[index * 100..][..100]).unwrap();
+        drop(mini);
+        let path = format!("/other{}", index);
+        comp.create_stream(&path).unwrap().write_all(&[9; 100]).unwrap();
+    }
+    comp.create_stream("/big").unwrap().write_all(&data(5000, 2)).unwrap();
+    comp.flush().unwrap();
+    let file = Rc::new(RefCell::new(comp.into_inner()));
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let inner = TracingWriter { inner: file.clone(), writes: writes.clone() };
+    Traced { comp: CompoundFile::open_strict(inner).unwrap(), file, writes }
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Follows a chain in the given FAT (or MiniFAT).
+fn chain(fat: &[u32], start: u32) -> Vec<u32> {
+    let mut sectors = Vec::new();
+    let mut sector = start;
+    while (sector as usize) < fat.len() {
+        sectors.push(sector);
+        sector = fat[sector as usize];
+    }
+    sectors
+}
+
+/// Returns the file offset of every byte of the stream at the given path,
+/// worked out from the raw structures of the file.
+fn byte_offsets(traced: &mut Traced, path: &str) -> Vec<u64> {
+    let file = traced.contents();
+    let comp = &mut traced.comp;
+    let fat: Vec<u32> =
+        comp.raw_fat().iter().map(|sector| sector.value()).collect();
+    let sector_offset = |sector: u32| (sector as u64 + 1) * SECTOR_LEN;
+    let entry = comp.entry(path).unwrap();
+    let raw = comp.raw_dir_entry(entry.stream_id()).unwrap();
+    let start = le_u32(&raw, 116);
+    let len = entry.len();
+    if len >= 4096 {
+        let sectors = chain(&fat, start);
+        return (0..len)
+            .map(|i| {
+                sector_offset(sectors[(i / SECTOR_LEN) as usize])
+                    + i % SECTOR_LEN
+            })
+            .collect();
+    }
+    let mut minifat = Vec::new();
+    for sector in chain(&fat, le_u32(&file, 60)) {
+        let at = sector_offset(sector) as usize;
+        for index in 0..(SECTOR_LEN as usize / 4) {
+            minifat.push(le_u32(&file, at + 4 * index));
+        }
+    }
+    let root = comp.raw_dir_entry(comp.root_entry().stream_id()).unwrap();
+    let mini_stream = chain(&fat, le_u32(&root, 116));
+    let mini_sectors = chain(&minifat, start);
+    (0..len)
+        .map(|i| {
+            let mini_offset = mini_sectors[(i / MINI_SECTOR_LEN) as usize]
+                as u64
+                * MINI_SECTOR_LEN
+                + i % MINI_SECTOR_LEN;
+            sector_offset(mini_stream[(mini_offset / SECTOR_LEN) as usize])
+                + mini_offset % SECTOR_LEN
+        })
+        .collect()
+}
+
+/// Patches the stream at the given path, and checks that exactly the
+/// patched bytes were written, one write per (mini) sector, and that
+/// nothing else about the file changed.  Returns the sectors written.
+fn check_patch(path: &str, offset: u64, len: usize) -> BTreeSet<u64> {
+    let mut traced = make_file();
+    let offsets = byte_offsets(&mut traced, path);
+    let before = traced.contents();
+    let mut expected = traced.comp.read_stream_to_vec(path).unwrap();
+    let patch: Vec<u8> =
+        expected[offset as usize..][..len].iter().map(|byte| !byte).collect();
+    expected[offset as usize..][..len].copy_from_slice(&patch);
+    let fat = traced.comp.raw_fat();
+    let raw = traced
+        .comp
+        .raw_dir_entry(traced.comp.entry(path).unwrap().stream_id());
+
+    traced.writes.borrow_mut().clear();
+    traced.comp.patch_stream(path, offset, &patch).unwrap();
+    let writes = traced.writes.borrow().clone();
+
+    let written: BTreeSet<u64> = writes
+        .iter()
+        .flat_map(|&(at, num_bytes)| at..at + num_bytes as u64)
+        .collect();
+    let patched: BTreeSet<u64> =
+        offsets[offset as usize..][..len].iter().copied().collect();
+    assert_eq!(written, patched);
+    let unit =
+        if expected.len() >= 4096 { SECTOR_LEN } else { MINI_SECTOR_LEN };
+    let units: BTreeSet<u64> = patched.iter().map(|at| at / unit).collect();
+    assert_eq!(writes.len(), units.len());
+
+    let after = traced.contents();
+    for (at, (old, new)) in before.iter().zip(after.iter()).enumerate() {
+        if !patched.contains(&(at as u64)) {
+            assert_eq!(old, new, "byte {} changed", at);
+        }
+    }
+    assert_eq!(before.len(), after.len());
+    assert_eq!(traced.comp.raw_fat(), fat);
+    assert_eq!(
+        traced
+            .comp
+            .raw_dir_entry(traced.comp.entry(path).unwrap().stream_id())
+            .unwrap(),
+        raw.unwrap()
+    );
+    assert_eq!(traced.comp.read_stream_to_vec(path).unwrap(), expected);
+    patched.iter().map(|at| at / SECTOR_LEN - 1).collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn patch_regular_stream_across_sector_boundary() {
+    let mut traced = make_file();
+    let offsets = byte_offsets(&mut traced, "/big");
+    let sectors = check_patch("/big", 1000, 50);
+    let expected: BTreeSet<u64> =
+        [offsets[1000], offsets[1049]].iter().map(|at| at / 512 - 1).collect();
+    assert_eq!(expected.len(), 2);
+    assert_eq!(sectors, expected);
+
+    let sectors = check_patch("/big", 0, 5000);
+    assert_eq!(sectors.len(), 10);
+    let sectors = check_patch("/big", 4999, 1);
+    assert_eq!(sectors.len(), 1);
+}
+
+#[test]
+fn patch_mini_stream_across_mini_sector_boundary() {
+    // The stream was written in three parts, so its mini sectors are not
+    // all adjacent.
+    let mut traced = make_file();
+    let offsets = byte_offsets(&mut traced, "/mini");
+    assert_ne!(offsets[127] + 1, offsets[128]);
+    check_patch("/mini", 120, 16);
+    check_patch("/mini", 60, 10);
+    check_patch("/mini", 0, 300);
+    check_patch("/mini", 299, 1);
+}
+
+#[test]
+fn patch_errors_leave_file_unchanged() {
+    let Traced { mut comp, writes, .. } = make_file();
+    writes.borrow_mut().clear();
+    let error = comp.patch_stream("/mini", 290, &[0; 11]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.patch_stream("/big", u64::MAX, &[0; 2]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.patch_stream("/", 0, &[0]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error = comp.patch_stream("/missing", 0, &[0]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    comp.patch_stream("/big", 5000, &[]).unwrap();
+    assert!(writes.borrow().is_empty());
+    assert_eq!(comp.entry("/mini").unwrap().len(), 300);
+}
+
+#[test]
+fn patch_refuses_shared_chain() {
+    let mut comp = make_file().comp;
+    let contents = data(5000, 2);
+    comp.create_stream_dedup("/first", &contents).unwrap();
+    comp.create_stream_dedup("/copy", &contents).unwrap();
+    let error = comp.patch_stream("/copy", 0, b"changed").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(comp.read_stream_to_vec("/first").unwrap() == contents);
+    assert!(comp.read_stream_to_vec("/copy").unwrap() == contents);
+}
+
+//===========================================================================//
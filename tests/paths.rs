@@ -0,0 +1,234 @@
+use cfb::{CompoundFile, EntryKind, ObjectNotFound, PathThroughStream};
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+/// Every public method that resolves a path, each called in a way that
+/// would succeed if the path named a suitable object.
+#[allow(clippy::type_complexity)]
+fn methods() -> Vec<(&'static str, fn(&mut TestFile, &str) -> io::Result<()>)>
+{
+    vec![
+        ("entry", |comp, path| comp.entry(path).map(drop)),
+        ("parent_entry", |comp, path| comp.parent_entry(path).map(drop)),
+        ("ancestors", |comp, path| comp.ancestors(path).map(drop)),
+        ("read_storage", |comp, path| comp.read_storage(path).map(drop)),
+        ("walk_storage", |comp, path| comp.walk_storage(path).map(drop)),
+        ("walk_relative", |comp, path| comp.walk_relative(path).map(drop)),
+        ("open_stream", |comp, path| comp.open_stream(path).map(drop)),
+        ("read_many", |comp, path| comp.read_many(&[path]).map(drop)),
+        ("create_stream", |comp, path| comp.create_stream(path).map(drop)),
+        ("create_new_stream", |comp, path| {
+            comp.create_new_stream(path).map(drop)
+        }),
+        ("create_stream_dedup", |comp, path| {
+            comp.create_stream_dedup(path, b"data")
+        }),
+        ("create_storage", |comp, path| comp.create_storage(path)),
+        ("create_storage_all", |comp, path| comp.create_storage_all(path)),
+        ("remove_stream", |comp, path| comp.remove_stream(path)),
+        ("remove_storage", |comp, path| comp.remove_storage(path)),
+        ("remove_storage_all", |comp, path| comp.remove_storage_all(path)),
+        ("set_storage_clsid", |comp, path| {
+            comp.set_storage_clsid(path, Uuid::nil())
+        }),
+        ("set_state_bits", |comp, path| comp.set_state_bits(path, 1)),
+        ("touch", |comp, path| comp.touch(path)),
+    ]
+}
+
+/// Creates a file with a stream at the top level ("/top") and one inside a
+/// storage ("/dir/s"), alongside an empty storage ("/dir/sub").
+fn make_file() -> TestFile {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream("/top").unwrap().write_all(b"top").unwrap();
+    comp.create_storage("/dir").unwrap();
+    comp.create_stream("/dir/s").unwrap().write_all(b"s").unwrap();
+    comp.create_storage("/dir/sub").unwrap();
+    comp
+}
+
+/// Normalizes a path the way the methods above do.
+fn comp_path(path: &str) -> PathBuf {
+    let trimmed = path.trim_start_matches('/').trim_end_matches('/');
+    Path::new("/").join(trimmed)
+}
+
+fn walk_paths(comp: &TestFile) -> Vec<PathBuf> {
+    comp.walk().map(|entry| entry.path().to_path_buf()).collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn paths_through_streams_fail_at_first_stream() {
+    // (requested path, normalized path, offending stream)
+    let cases = [
+        ("/top/x", "/top/x", "/top"),
+        ("top/x", "/top/x", "/top"),
+        ("/top/x/y", "/top/x/y", "/top"),
+        ("/top/x/", "/top/x", "/top"),
+        ("/TOP/x", "/TOP/x", "/TOP"),
+        ("/dir/s/x", "/dir/s/x", "/dir/s"),
+        ("/dir/./s/x/y/", "/dir/s/x/y", "/dir/s"),
+        ("/dir/sub/../s/x", "/dir/s/x", "/dir/s"),
+    ];
+    for (method, call) in methods() {
+        for &(requested, path, stream_path) in cases.iter() {
+            let mut comp = make_file();
+            let before = walk_paths(&comp);
+            let error = match call(&mut comp, requested) {
+                Ok(()) => panic!("{}({:?}) succeeded", method, requested),
+                Err(error) => error,
+            };
+            assert_eq!(
+                error.kind(),
+                io::ErrorKind::NotFound,
+                "{}({:?}): {}",
+                method,
+                requested,
+                error
+            );
+            let through = PathThroughStream::from_io_error(&error)
+                .unwrap_or_else(|| {
+                    panic!("{}({:?}): {}", method, requested, error)
+                });
+            assert_eq!(through.path(), Path::new(path), "{}", method);
+            assert_eq!(through.stream_path(), Path::new(stream_path));
+            assert_eq!(
+                walk_paths(&comp),
+                before,
+                "{} changed the file",
+                method
+            );
+            assert!(!comp.exists(requested));
+            assert!(!comp.is_stream(requested));
+            assert!(!comp.is_storage(requested));
+        }
+    }
+}
+
+#[test]
+fn missing_paths_are_not_reported_as_through_streams() {
+    for (method, call) in methods() {
+        for &requested in ["/missing/x", "/dir/missing/x/y"].iter() {
+            let mut comp = make_file();
+            let Err(error) = call(&mut comp, requested) else {
+                // Only create_storage_all creates missing parents.
+                assert_eq!(method, "create_storage_all");
+                continue;
+            };
+            assert_eq!(error.kind(), io::ErrorKind::NotFound, "{}", method);
+            assert!(PathThroughStream::from_io_error(&error).is_none());
+        }
+    }
+}
+
+#[test]
+fn missing_paths_report_nearest_existing_ancestor() {
+    // (requested path, nearest existing ancestor, its kind)
+    let cases = [
+        ("/missing", "/", EntryKind::Root),
+        ("/missing/x/y", "/", EntryKind::Root),
+        ("/dir/missing", "/dir", EntryKind::Storage),
+        ("dir/missing/x/", "/dir", EntryKind::Storage),
+        ("/dir/sub/missing/x", "/dir/sub", EntryKind::Storage),
+        ("/DIR/SUB/missing", "/DIR/SUB", EntryKind::Storage),
+    ];
+    for (method, call) in methods() {
+        let creates = method.starts_with("create");
+        for &(requested, ancestor, kind) in cases.iter() {
+            let mut comp = make_file();
+            let Err(error) = call(&mut comp, requested) else {
+                // Creating succeeds if only the object itself is missing,
+                // and create_storage_all creates missing parents.
+                assert!(creates, "{}({:?}) succeeded", method, requested);
+                continue;
+            };
+            assert_eq!(error.kind(), io::ErrorKind::NotFound, "{}", method);
+            let not_found = ObjectNotFound::from_io_error(&error)
+                .unwrap_or_else(|| {
+                    panic!("{}({:?}): {}", method, requested, error)
+                });
+            let normalized = comp_path(requested);
+            // Creating methods look up the parent of the new object.
+            let missing = if creates {
+                normalized.parent().unwrap()
+            } else {
+                normalized.as_path()
+            };
+            assert_eq!(not_found.missing(), missing, "{}", method);
+            assert_eq!(
+                not_found.nearest_existing_ancestor(),
+                Path::new(ancestor),
+                "{}({:?})",
+                method,
+                requested
+            );
+            assert_eq!(not_found.ancestor_kind(), kind, "{}", method);
+            assert_eq!(
+                not_found.parent_exists(),
+                missing.parent() == Some(Path::new(ancestor))
+            );
+        }
+    }
+}
+
+#[test]
+fn missing_stream_in_existing_storage_is_distinguishable() {
+    let mut comp = make_file();
+    let error = comp.open_stream("/dir/missing").err().unwrap();
+    let not_found = ObjectNotFound::from_io_error(&error).unwrap();
+    assert!(not_found.parent_exists());
+    assert_eq!(error.to_string(), "No such stream: \"/dir/missing\"");
+
+    let error = comp.open_stream("/gone/missing").err().unwrap();
+    let not_found = ObjectNotFound::from_io_error(&error).unwrap();
+    assert!(!not_found.parent_exists());
+    assert_eq!(not_found.nearest_existing_ancestor(), Path::new("/"));
+    assert_eq!(not_found.ancestor_kind(), EntryKind::Root);
+    let message = error.to_string();
+    assert!(message.contains("\"/gone/missing\""), "{}", message);
+    assert!(message.contains("only \"/\" exists"), "{}", message);
+
+    let error = comp.create_stream("/gone/missing").err().unwrap();
+    let not_found = ObjectNotFound::from_io_error(&error).unwrap();
+    assert_eq!(not_found.missing(), Path::new("/gone"));
+    assert!(not_found.parent_exists());
+}
+
+#[test]
+fn trailing_separators_are_ignored() {
+    let mut comp = make_file();
+    assert!(comp.entry("dir/").unwrap().is_storage());
+    assert_eq!(comp.entry("dir/").unwrap().path(), Path::new("/dir"));
+    assert!(comp.is_storage("/dir/sub/"));
+    assert_eq!(comp.read_storage("/dir/").unwrap().count(), 2);
+    assert_eq!(comp.walk_storage("dir//").unwrap().count(), 3);
+    assert!(comp.entry("/top/").unwrap().is_stream());
+    assert!(comp.is_stream("/dir/s/"));
+    comp.open_stream("/dir/s/").unwrap();
+
+    comp.create_storage("/new/").unwrap();
+    assert!(comp.is_storage("/new"));
+    comp.create_stream("/new/stream/").unwrap();
+    assert!(comp.is_stream("/new/stream"));
+    comp.remove_stream("/new/stream/").unwrap();
+    comp.remove_storage("new/").unwrap();
+    assert!(!comp.exists("/new"));
+}
+
+#[test]
+fn stream_error_message_names_both_paths() {
+    let comp = make_file();
+    let error = comp.entry("/dir/s/x").unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains("/dir/s/x"), "{}", message);
+    assert!(message.contains("\"/dir/s\""), "{}", message);
+}
+
+//===========================================================================//
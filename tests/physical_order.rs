@@ -0,0 +1,179 @@
+use cfb::{CompoundFile, Entry, SectorId, Version};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::PathBuf;
+use tracer::Tracer;
+
+mod tracer;
+
+//===========================================================================//
+
+/// Builds a file whose directory spans several sectors, with entries
+/// created in an order unrelated to the tree's, and with holes left by
+/// removed entries.
+fn make_file(version: Version) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(version, cursor).expect("create");
+    for index in 0..20 {
+        comp.create_storage(format!("/dir{}", index % 4)).ok();
+        let path = format!("/dir{}/stream{}", index % 4, 19 - index);
+        comp.create_stream(&path)
+            .unwrap()
+            .write_all(&[index as u8; 100])
+            .unwrap();
+        comp.create_storage(format!("/dir{}/sub{}", index % 4, index))
+            .unwrap();
+    }
+    for index in (0..20).step_by(3) {
+        comp.remove_storage(format!("/dir{}/sub{}", index % 4, index))
+            .unwrap();
+    }
+    comp.create_stream("/dir1/sub5/deep").unwrap().write_all(b"deep").unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn keyed(entries: impl Iterator<Item = Entry>) -> BTreeMap<u32, Entry> {
+    let mut map = BTreeMap::new();
+    for entry in entries {
+        let stream_id = entry.stream_id().value();
+        assert!(map.insert(stream_id, entry).is_none(), "duplicate entry");
+    }
+    map
+}
+
+fn check_same_entries_as_walk<F: Read + Seek>(comp: &CompoundFile<F>) {
+    let walked = keyed(comp.walk());
+    let physical = keyed(comp.iter_entries_physical());
+    assert_eq!(
+        physical.keys().collect::<Vec<_>>(),
+        walked.keys().collect::<Vec<_>>()
+    );
+    for (stream_id, entry) in physical.iter() {
+        let other = &walked[stream_id];
+        assert_eq!(entry.path(), other.path());
+        assert_eq!(entry.is_stream(), other.is_stream());
+        assert_eq!(entry.len(), other.len());
+        assert_eq!(entry.clsid(), other.clsid());
+        assert_eq!(entry.modified(), other.modified());
+    }
+}
+
+/// Returns the byte range of each sector in the directory chain.
+fn dir_sector_ranges(data: &[u8]) -> Vec<(u64, u64)> {
+    let comp = CompoundFile::open(Cursor::new(data.to_vec())).unwrap();
+    let sector_len = comp.version().sector_len() as u64;
+    let fat = comp.raw_fat();
+    let first = u32::from_le_bytes(data[48..52].try_into().unwrap());
+    let mut ranges = Vec::new();
+    let mut sector = SectorId::new(first);
+    while sector != SectorId::END_OF_CHAIN {
+        let start = (sector.value() as u64 + 1) * sector_len;
+        ranges.push((start, start + sector_len));
+        sector = fat[sector.value() as usize];
+    }
+    ranges
+}
+
+//===========================================================================//
+
+#[test]
+fn yields_walk_entries_in_stream_id_order() {
+    let data = make_file(Version::V3);
+    let comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let entries: Vec<Entry> = comp.iter_entries_physical().collect();
+    assert!(entries[0].is_root());
+    let ids: Vec<_> = entries.iter().map(|entry| entry.stream_id()).collect();
+    let mut sorted = ids.clone();
+    sorted.sort();
+    assert_eq!(ids, sorted);
+    // The holes left by removed storages aren't yielded.
+    assert!(ids.windows(2).any(|pair| pair[1].value() > pair[0].value() + 1));
+    assert!(comp.exists("/dir1/sub5/deep"));
+}
+
+#[test]
+fn same_entries_as_walk() {
+    for version in [Version::V3, Version::V4] {
+        let data = make_file(version);
+        let comp = CompoundFile::open(Cursor::new(data)).unwrap();
+        check_same_entries_as_walk(&comp);
+    }
+    let cursor = Cursor::new(Vec::new());
+    let comp = CompoundFile::create(cursor).unwrap();
+    check_same_entries_as_walk(&comp);
+    assert_eq!(comp.iter_entries_physical().count(), 1);
+    for dir in ["tests/infinite_loops_fuzzed", "tests/panics_fuzzed"] {
+        for entry in fs::read_dir(dir).unwrap() {
+            let data = fs::read(entry.unwrap().path()).unwrap();
+            if let Ok(comp) = CompoundFile::open(Cursor::new(data)) {
+                check_same_entries_as_walk(&comp);
+            }
+        }
+    }
+}
+
+#[test]
+fn temporaries_are_hidden_unless_shown() {
+    let temp_name = format!("{}abc", cfb::TEMPORARY_NAME_PREFIX);
+    let placeholder = "x".repeat(temp_name.chars().count());
+    let cursor = Cursor::new(make_file(Version::V3));
+    let mut comp = CompoundFile::open(cursor).unwrap();
+    comp.create_storage(format!("/dir1/{}", placeholder)).unwrap();
+    let path = format!("/dir1/{}/data", placeholder);
+    comp.create_stream(&path).unwrap().write_all(b"data").unwrap();
+    comp.flush().unwrap();
+
+    // Give the storage a temporary name by patching its directory entry, as
+    // if an interrupted operation had left it behind.
+    let mut data = comp.into_inner().into_inner();
+    let utf16 = |name: &str| -> Vec<u8> {
+        name.encode_utf16().flat_map(|c| c.to_le_bytes()).collect()
+    };
+    let (placeholder, temp_name) = (utf16(&placeholder), utf16(&temp_name));
+    let offset = data
+        .windows(placeholder.len())
+        .position(|window| window == placeholder)
+        .unwrap();
+    data[offset..offset + temp_name.len()].copy_from_slice(&temp_name);
+
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    check_same_entries_as_walk(&comp);
+    let hidden = comp.iter_entries_physical().count();
+    comp.set_show_temporaries(true);
+    check_same_entries_as_walk(&comp);
+    assert_eq!(comp.iter_entries_physical().count(), hidden + 2);
+}
+
+#[test]
+fn manifest_reads_each_directory_sector_once() {
+    let data = make_file(Version::V3);
+    let dir_sectors = dir_sector_ranges(&data);
+    assert!(dir_sectors.len() > 2);
+    let tracer = Tracer::with_data(data);
+    let trace = tracer.trace();
+    let comp = CompoundFile::open(tracer).unwrap();
+    let manifest: Vec<(PathBuf, bool, u64)> = comp
+        .iter_entries_physical()
+        .map(|entry| {
+            (entry.path().to_path_buf(), entry.is_stream(), entry.len())
+        })
+        .collect();
+    assert_eq!(manifest.len(), comp.walk().count());
+    for (start, end) in dir_sectors {
+        let num_bytes: u64 = trace
+            .reads()
+            .iter()
+            .map(|&(offset, len)| {
+                let read_end = offset + len as u64;
+                read_end.min(end).saturating_sub(offset.max(start))
+            })
+            .sum();
+        assert_eq!(num_bytes, end - start, "sector at {}", start);
+    }
+}
+
+//===========================================================================//
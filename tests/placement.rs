@@ -0,0 +1,75 @@
+use cfb::{CompoundFile, Version};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use tracer::Tracer;
+
+mod tracer;
+
+//===========================================================================//
+
+#[test]
+fn small_stream_is_placed_in_mini_stream() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/foo").unwrap().write_all(&[1; 4095]).unwrap();
+    assert_eq!(comp.mini_stream_len(), 4096);
+    assert_eq!(comp.stats().unwrap().num_mini_stream_sectors(), 8);
+}
+
+#[test]
+fn large_stream_is_placed_in_regular_sectors() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/foo").unwrap().write_all(&[1; 4096]).unwrap();
+    assert_eq!(comp.mini_stream_len(), 0);
+    assert_eq!(comp.stats().unwrap().num_mini_stream_sectors(), 0);
+}
+
+#[test]
+fn sequential_writes_skip_the_mini_stream() {
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, Tracer::new()).unwrap();
+    {
+        let mut stream = comp.create_stream("/foo").unwrap();
+        for _ in 0..10 {
+            stream.write_all(&[0xab; 1000]).unwrap();
+        }
+    }
+    assert_eq!(comp.mini_stream_len(), 0);
+    let tracer = comp.into_inner();
+    assert_eq!(tracer.trace().bytes_written_of(0xab), 10000);
+}
+
+#[test]
+fn promotion_copies_data_once() {
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, Tracer::new()).unwrap();
+    let mut stream = comp.create_stream("/foo").unwrap();
+    stream.write_all(&[0xab; 1000]).unwrap();
+    stream.flush().unwrap();
+    drop(stream);
+    assert_eq!(comp.mini_stream_len(), 1024);
+    let tracer = comp.into_inner();
+    assert_eq!(tracer.trace().bytes_written_of(0xab), 1000);
+    tracer.trace().clear();
+
+    let mut comp = CompoundFile::open_strict(tracer).unwrap();
+    {
+        let mut stream = comp.open_stream("/foo").unwrap();
+        stream.seek(SeekFrom::End(0)).unwrap();
+        stream.write_all(&[0xcd; 9000]).unwrap();
+    }
+    assert_eq!(comp.entry("/foo").unwrap().len(), 10000);
+    let tracer = comp.into_inner();
+    assert_eq!(tracer.trace().bytes_written_of(0xab), 1000);
+    assert_eq!(tracer.trace().bytes_written_of(0xcd), 9000);
+
+    let mut comp = CompoundFile::open_strict(tracer).unwrap();
+    let mut data = Vec::new();
+    comp.open_stream("/foo").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(&data[..1000], &[0xab; 1000][..]);
+    assert_eq!(&data[1000..], &[0xcd; 9000][..]);
+}
+
+//===========================================================================//
@@ -0,0 +1,607 @@
+use cfb::propset::{
+    self, PropertySet, PropertyValue, CODEPAGE_UTF16, CODEPAGE_UTF8,
+    FMTID_SUMMARY_INFO, PID_APP_NAME, PID_AUTHOR, PID_CODEPAGE,
+    PID_CREATE_TIME, PID_DICTIONARY, PID_EDIT_TIME, PID_LAST_PRINTED,
+    PID_PAGE_COUNT, PID_SECURITY, PID_THUMBNAIL, PID_TITLE, SUMMARY_INFO_PATH,
+};
+use std::convert::TryInto;
+use std::io::{Cursor, ErrorKind};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// Assembles a property set stream with a single summary information
+/// section holding the given (property ID, value bytes) pairs, in the order
+/// given.
+fn property_set_stream(properties: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    multi_section_stream(&[(FMTID_SUMMARY_INFO, properties.to_vec())])
+}
+
+/// A section to assemble: a format ID, and (property ID, value bytes) pairs.
+type SectionSpec = (Uuid, Vec<(u32, Vec<u8>)>);
+
+/// Assembles a property set stream with the given sections.
+fn multi_section_stream(sections: &[SectionSpec]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&0xfffe_u16.to_le_bytes());
+    data.extend_from_slice(&0_u16.to_le_bytes());
+    data.extend_from_slice(&0x0002_0005_u32.to_le_bytes());
+    data.extend_from_slice(&[0; 16]);
+    data.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+    let mut body = Vec::new();
+    let header_len = 28 + 20 * sections.len();
+    for (fmtid, properties) in sections {
+        data.extend_from_slice(&fmtid.to_bytes_le());
+        data.extend_from_slice(
+            &((header_len + body.len()) as u32).to_le_bytes(),
+        );
+        let table_len = 8 + 8 * properties.len();
+        let mut table = Vec::new();
+        let mut values = Vec::new();
+        for (pid, value) in properties {
+            table.extend_from_slice(&pid.to_le_bytes());
+            table.extend_from_slice(
+                &((table_len + values.len()) as u32).to_le_bytes(),
+            );
+            values.extend_from_slice(value);
+        }
+        body.extend_from_slice(
+            &((table_len + values.len()) as u32).to_le_bytes(),
+        );
+        body.extend_from_slice(&(properties.len() as u32).to_le_bytes());
+        body.extend_from_slice(&table);
+        body.extend_from_slice(&values);
+    }
+    data.extend_from_slice(&body);
+    data
+}
+
+fn typed(type_code: u32, bytes: &[u8]) -> Vec<u8> {
+    let mut value = type_code.to_le_bytes().to_vec();
+    value.extend_from_slice(bytes);
+    value
+}
+
+/// A `VT_LPSTR` whose length counts the null terminator and the padding
+/// after it, as Word writes them.
+fn padded_lpstr(bytes: &[u8]) -> Vec<u8> {
+    let len = (bytes.len() + 4) & !3;
+    let mut value = typed(30, &(len as u32).to_le_bytes());
+    value.extend_from_slice(bytes);
+    value.resize(8 + len, 0);
+    value
+}
+
+/// A summary information stream like one written by Word: the table isn't
+/// in property ID order, strings are padded, and there is a thumbnail of a
+/// type that isn't otherwise understood.
+fn word_summary_info() -> Vec<u8> {
+    let mut thumbnail = typed(71, &12_u32.to_le_bytes());
+    thumbnail.extend_from_slice(&(-1_i32).to_le_bytes());
+    thumbnail.extend_from_slice(&3_u32.to_le_bytes());
+    thumbnail.extend_from_slice(&[1, 2, 3, 4]);
+    property_set_stream(&[
+        (PID_CODEPAGE, typed(2, &[0xe4, 0x04, 0, 0])),
+        (PID_TITLE, padded_lpstr(b"\x93Quoted\x94 title")),
+        (PID_AUTHOR, padded_lpstr(b"Jos\xe9")),
+        (PID_APP_NAME, padded_lpstr(b"Microsoft Office Word")),
+        (PID_EDIT_TIME, typed(64, &6_000_000_000_u64.to_le_bytes())),
+        (PID_LAST_PRINTED, typed(64, &[0; 8])),
+        (PID_CREATE_TIME, typed(64, &0x01d5_0000_0000_0000_u64.to_le_bytes())),
+        (PID_PAGE_COUNT, typed(3, &3_i32.to_le_bytes())),
+        (PID_THUMBNAIL, thumbnail),
+        (PID_SECURITY, typed(3, &0_i32.to_le_bytes())),
+    ])
+}
+
+/// A dictionary giving the given properties the given names (in the
+/// section's code page), laid out as Word writes them.
+fn dictionary(entries: &[(u32, &[u8])], utf16: bool) -> Vec<u8> {
+    let unit = if utf16 { 2 } else { 1 };
+    let mut value = (entries.len() as u32).to_le_bytes().to_vec();
+    for &(pid, name) in entries {
+        value.extend_from_slice(&pid.to_le_bytes());
+        let len = name.len() / unit + 1;
+        value.extend_from_slice(&(len as u32).to_le_bytes());
+        value.extend_from_slice(name);
+        value.resize(value.len() + unit, 0);
+        while utf16 && value.len() % 4 != 0 {
+            value.push(0);
+        }
+    }
+    while value.len() % 4 != 0 {
+        value.push(0);
+    }
+    value
+}
+
+/// A document summary information stream like one written by Word for a
+/// document with custom properties: a string, a number, a boolean, a date,
+/// and a vector of strings (whose type isn't otherwise understood).
+fn word_doc_summary_info() -> Vec<u8> {
+    let mut tags = typed(0x101e, &2_u32.to_le_bytes());
+    tags.extend_from_slice(&padded_lpstr(b"draft")[4..]);
+    tags.extend_from_slice(&padded_lpstr(b"internal")[4..]);
+    multi_section_stream(&[
+        (
+            propset::FMTID_DOC_SUMMARY_INFO,
+            vec![
+                (PID_CODEPAGE, typed(2, &[0xe4, 0x04, 0, 0])),
+                (15, padded_lpstr(b"Contoso")),
+                (23, typed(3, &0x000c_0000_i32.to_le_bytes())),
+            ],
+        ),
+        (
+            propset::FMTID_USER_DEFINED_PROPERTIES,
+            vec![
+                (
+                    PID_DICTIONARY,
+                    dictionary(
+                        &[
+                            (2, b"Department"),
+                            (3, b"Budget"),
+                            (4, b"Reviewed"),
+                            (5, b"Due date"),
+                            (6, b"Tags"),
+                            (7, b"Caf\xe9"),
+                        ],
+                        false,
+                    ),
+                ),
+                (PID_CODEPAGE, typed(2, &[0xe4, 0x04, 0, 0])),
+                (2, padded_lpstr(b"Finance")),
+                (3, typed(3, &125_000_i32.to_le_bytes())),
+                (4, typed(11, &[0xff, 0xff, 0, 0])),
+                (5, typed(64, &0x01d9_0000_0000_0000_u64.to_le_bytes())),
+                (6, tags),
+                (7, padded_lpstr(b"cr\xe8me")),
+            ],
+        ),
+    ])
+}
+
+/// Returns the bytes of the given section of a property set stream.
+fn section_bytes(data: &[u8], index: usize) -> &[u8] {
+    let entry = 28 + 20 * index;
+    let offset =
+        u32::from_le_bytes(data[entry + 16..entry + 20].try_into().unwrap())
+            as usize;
+    let len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+        as usize;
+    &data[offset..offset + len]
+}
+
+fn write_to_vec(set: &PropertySet) -> Vec<u8> {
+    let mut data = Vec::new();
+    set.write_to(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn word_summary_info_round_trip() {
+    let data = word_summary_info();
+    let set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    assert_eq!(set.fmtid(), FMTID_SUMMARY_INFO);
+    assert_eq!(set.system_identifier(), 0x0002_0005);
+    let section = set.section();
+    assert_eq!(section.codepage(), Some(1252));
+    assert_eq!(
+        section.string(PID_TITLE).as_deref(),
+        Some("\u{201c}Quoted\u{201d} title")
+    );
+    assert_eq!(section.string(PID_AUTHOR).as_deref(), Some("Jos\u{e9}"));
+    assert_eq!(
+        section.string(PID_APP_NAME).as_deref(),
+        Some("Microsoft Office Word")
+    );
+    assert_eq!(
+        section.get(PID_EDIT_TIME),
+        Some(&PropertyValue::FileTime(6_000_000_000))
+    );
+    assert_eq!(section.time(PID_LAST_PRINTED), None);
+    assert!(section.time(PID_CREATE_TIME).is_some());
+    assert_eq!(section.i32(PID_PAGE_COUNT), Some(3));
+    assert!(matches!(
+        section.get(PID_THUMBNAIL),
+        Some(PropertyValue::Other(bytes)) if bytes.len() == 20
+    ));
+    // The table order is kept, rather than sorted by property ID.
+    let pids: Vec<u32> =
+        section.properties().iter().map(|&(pid, _)| pid).collect();
+    assert_eq!(pids, [1, 2, 4, 18, 10, 11, 12, 14, 17, 19]);
+    assert_eq!(write_to_vec(&set), data);
+}
+
+#[test]
+fn edit_and_round_trip() {
+    let data = word_summary_info();
+    let mut set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    let created = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let section = set.section_mut();
+    section.set_string(PID_TITLE, "Caf\u{e9} \u{20ac}5").unwrap();
+    section.set_string(PID_AUTHOR, "Someone else").unwrap();
+    section.set_time(PID_CREATE_TIME, created).unwrap();
+    section.set_i32(PID_PAGE_COUNT, 7);
+    assert!(section.remove(PID_SECURITY).is_some());
+    assert_eq!(
+        section.get(PID_TITLE),
+        Some(&PropertyValue::LpStr(b"Caf\xe9 \x805".to_vec()))
+    );
+    let reread =
+        PropertySet::read_from(&mut write_to_vec(&set).as_slice()).unwrap();
+    assert_eq!(reread, set);
+    let section = reread.section();
+    assert_eq!(
+        section.string(PID_TITLE).as_deref(),
+        Some("Caf\u{e9} \u{20ac}5")
+    );
+    assert_eq!(section.time(PID_CREATE_TIME), Some(created));
+    assert_eq!(section.i32(PID_PAGE_COUNT), Some(7));
+    assert_eq!(section.get(PID_SECURITY), None);
+    // Unchanged properties keep their place in the table.
+    let pids: Vec<u32> =
+        section.properties().iter().map(|&(pid, _)| pid).collect();
+    assert_eq!(pids, [1, 2, 4, 18, 10, 11, 12, 14, 17]);
+}
+
+#[test]
+fn codepage_conversion() {
+    let mut set = PropertySet::new(FMTID_SUMMARY_INFO);
+    let section = set.section_mut();
+    assert_eq!(section.codepage(), Some(1252));
+    section.set_string(PID_TITLE, "na\u{ef}ve").unwrap();
+    let error = section.set_string(PID_AUTHOR, "\u{3b1}\u{3b2}").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(section.get(PID_AUTHOR), None);
+
+    // Switching to UTF-8 converts existing strings.
+    section.set_codepage(CODEPAGE_UTF8).unwrap();
+    assert_eq!(section.get(PID_CODEPAGE), Some(&PropertyValue::I2(-535)));
+    assert_eq!(
+        section.get(PID_TITLE),
+        Some(&PropertyValue::LpStr("na\u{ef}ve".as_bytes().to_vec()))
+    );
+    section.set_string(PID_AUTHOR, "\u{3b1}\u{3b2}").unwrap();
+
+    // Switching back fails, changing nothing, since the author can't be
+    // represented.
+    let error = section.set_codepage(1252).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(section.codepage(), Some(CODEPAGE_UTF8));
+    assert_eq!(section.string(PID_TITLE).as_deref(), Some("na\u{ef}ve"));
+
+    // In UTF-16, strings are stored as UTF-16 with a two-byte terminator.
+    section.set_codepage(CODEPAGE_UTF16).unwrap();
+    let data = write_to_vec(&set);
+    let set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    let section = set.section();
+    assert_eq!(section.string(PID_TITLE).as_deref(), Some("na\u{ef}ve"));
+    assert_eq!(section.string(PID_AUTHOR).as_deref(), Some("\u{3b1}\u{3b2}"));
+    assert_eq!(
+        section.get(PID_AUTHOR),
+        Some(&PropertyValue::LpStr(vec![0xb1, 0x03, 0xb2, 0x03]))
+    );
+}
+
+#[test]
+fn unsupported_codepage_decodes_lossily() {
+    let data = property_set_stream(&[
+        (PID_CODEPAGE, typed(2, &[0xa4, 0x03, 0, 0])),
+        (PID_TITLE, padded_lpstr(b"abc")),
+        (PID_AUTHOR, padded_lpstr(b"\x93\xfa\x96\x7b")),
+    ]);
+    let mut set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    let section = set.section_mut();
+    assert_eq!(section.codepage(), Some(932));
+    assert_eq!(section.string(PID_TITLE).as_deref(), Some("abc"));
+    assert_eq!(
+        section.string(PID_AUTHOR).as_deref(),
+        Some("\u{fffd}\u{fffd}\u{fffd}{")
+    );
+    assert!(section.set_string(PID_TITLE, "\u{65e5}").is_err());
+    assert!(section.set_codepage(CODEPAGE_UTF8).is_err());
+    assert_eq!(write_to_vec(&set), data);
+}
+
+#[test]
+fn read_and_write_in_compound_file() {
+    let mut comp = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let error = comp.read_property_set(SUMMARY_INFO_PATH).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    let mut set = PropertySet::new(FMTID_SUMMARY_INFO);
+    set.section_mut().set_string(PID_TITLE, "First").unwrap();
+    comp.write_property_set(SUMMARY_INFO_PATH, &set).unwrap();
+    set.section_mut().set_string(PID_TITLE, "Second").unwrap();
+    set.section_mut().set_i32(PID_PAGE_COUNT, 2);
+    comp.write_property_set(SUMMARY_INFO_PATH, &set).unwrap();
+
+    let mut comp = cfb::CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let reread = comp.read_property_set(SUMMARY_INFO_PATH).unwrap();
+    assert_eq!(reread, set);
+    assert_eq!(
+        comp.entry(SUMMARY_INFO_PATH).unwrap().len(),
+        write_to_vec(&set).len() as u64
+    );
+    let error = comp.write_property_set("/missing/props", &set).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn multiple_sections() {
+    let mut set = PropertySet::new(propset::FMTID_DOC_SUMMARY_INFO);
+    let user =
+        set.section_by_fmtid_mut(propset::FMTID_USER_DEFINED_PROPERTIES);
+    user.set(2, PropertyValue::LpWStr("custom".to_string()));
+    user.set_bool(3, true);
+    user.set(4, PropertyValue::Clsid(Uuid::from_u128(0x1234)));
+    user.set(5, PropertyValue::R8(2.5));
+    let data = write_to_vec(&set);
+    let reread = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    assert_eq!(reread, set);
+    assert_eq!(reread.sections().len(), 2);
+    let user = reread
+        .section_by_fmtid(propset::FMTID_USER_DEFINED_PROPERTIES)
+        .unwrap();
+    assert_eq!(user.string(2).as_deref(), Some("custom"));
+    assert_eq!(user.bool(3), Some(true));
+}
+
+#[test]
+fn read_custom_properties_by_name() {
+    let data = word_doc_summary_info();
+    let set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    assert_eq!(set.sections().len(), 2);
+    assert!(set.section().names().is_empty());
+    let user =
+        set.section_by_fmtid(propset::FMTID_USER_DEFINED_PROPERTIES).unwrap();
+    let names: Vec<(u32, String)> = user.names();
+    assert_eq!(
+        names,
+        [
+            (2, "Department".to_string()),
+            (3, "Budget".to_string()),
+            (4, "Reviewed".to_string()),
+            (5, "Due date".to_string()),
+            (6, "Tags".to_string()),
+            (7, "Caf\u{e9}".to_string()),
+        ]
+    );
+    assert_eq!(
+        user.get_by_name("Department"),
+        Some(&PropertyValue::LpStr(b"Finance".to_vec()))
+    );
+    assert_eq!(user.pid_by_name("department"), Some(2));
+    assert_eq!(user.string(2).as_deref(), Some("Finance"));
+    assert_eq!(user.get_by_name("Budget"), Some(&PropertyValue::I4(125_000)));
+    assert_eq!(user.get_by_name("REVIEWED"), Some(&PropertyValue::Bool(true)));
+    assert_eq!(
+        user.get_by_name("Due date"),
+        Some(&PropertyValue::FileTime(0x01d9_0000_0000_0000))
+    );
+    assert!(matches!(
+        user.get_by_name("Tags"),
+        Some(PropertyValue::Other(bytes)) if bytes.len() == 36
+    ));
+    assert_eq!(user.pid_by_name("caf\u{c9}"), Some(7));
+    assert_eq!(user.string(7).as_deref(), Some("cr\u{e8}me"));
+    assert_eq!(user.name(6).as_deref(), Some("Tags"));
+    assert_eq!(user.get_by_name("Missing"), None);
+    assert_eq!(write_to_vec(&set), data);
+}
+
+#[test]
+fn modify_one_custom_property() {
+    let data = word_doc_summary_info();
+    let mut set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    let user =
+        set.section_by_fmtid_mut(propset::FMTID_USER_DEFINED_PROPERTIES);
+    let old = user.set_by_name("budget", PropertyValue::I4(-1)).unwrap();
+    assert_eq!(old, Some(PropertyValue::I4(125_000)));
+    // Only the four bytes of the value change.
+    let written = write_to_vec(&set);
+    assert_eq!(written.len(), data.len());
+    let changed: Vec<usize> =
+        (0..data.len()).filter(|&i| written[i] != data[i]).collect();
+    let value_offset = data.len() - section_bytes(&data, 1).len()
+        + section_bytes(&data, 1)
+            .windows(4)
+            .position(|window| window == 125_000_i32.to_le_bytes())
+            .unwrap();
+    assert_eq!(changed, (value_offset..value_offset + 4).collect::<Vec<_>>());
+
+    // Changing the length of a value moves the values after it, but leaves
+    // the first section untouched.
+    let user =
+        set.section_by_fmtid_mut(propset::FMTID_USER_DEFINED_PROPERTIES);
+    user.set_string(2, "Research and development").unwrap();
+    let written = write_to_vec(&set);
+    assert_eq!(section_bytes(&written, 0), section_bytes(&data, 0));
+    let reread = PropertySet::read_from(&mut written.as_slice()).unwrap();
+    assert_eq!(reread, set);
+    let user = reread
+        .section_by_fmtid(propset::FMTID_USER_DEFINED_PROPERTIES)
+        .unwrap();
+    assert_eq!(
+        user.get_by_name("Department"),
+        Some(&PropertyValue::LpStr(b"Research and development".to_vec()))
+    );
+    assert_eq!(user.get_by_name("Budget"), Some(&PropertyValue::I4(-1)));
+    assert_eq!(user.string(7).as_deref(), Some("cr\u{e8}me"));
+}
+
+#[test]
+fn add_and_remove_custom_properties() {
+    let data = word_doc_summary_info();
+    let mut set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    let user =
+        set.section_by_fmtid_mut(propset::FMTID_USER_DEFINED_PROPERTIES);
+    let old = user.set_by_name("Owner", PropertyValue::I4(42)).unwrap();
+    assert_eq!(old, None);
+    assert_eq!(user.pid_by_name("Owner"), Some(8));
+    let error = user.set_name(3, "Owner").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    let error = user.set_name(9, "\u{3b1}").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(user.remove_by_name("Tags").map(|_| ()), Some(()));
+    assert_eq!(user.pid_by_name("Tags"), None);
+    assert_eq!(user.get(6), None);
+
+    let written = write_to_vec(&set);
+    assert_eq!(section_bytes(&written, 0), section_bytes(&data, 0));
+    let reread = PropertySet::read_from(&mut written.as_slice()).unwrap();
+    assert_eq!(reread, set);
+    let user = reread
+        .section_by_fmtid(propset::FMTID_USER_DEFINED_PROPERTIES)
+        .unwrap();
+    assert_eq!(user.get_by_name("Owner"), Some(&PropertyValue::I4(42)));
+    assert_eq!(user.names().len(), 6);
+
+    // A new section gets a dictionary at the start of its table.
+    let mut set = PropertySet::new(propset::FMTID_DOC_SUMMARY_INFO);
+    let user =
+        set.section_by_fmtid_mut(propset::FMTID_USER_DEFINED_PROPERTIES);
+    user.set_by_name("Reviewed", PropertyValue::Bool(false)).unwrap();
+    let pids: Vec<u32> =
+        user.properties().iter().map(|&(pid, _)| pid).collect();
+    assert_eq!(pids, [PID_DICTIONARY, PID_CODEPAGE, 2]);
+    let reread =
+        PropertySet::read_from(&mut write_to_vec(&set).as_slice()).unwrap();
+    assert_eq!(reread, set);
+}
+
+#[test]
+fn utf16_dictionary() {
+    let name: Vec<u8> = "\u{3b1}bc"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    let data = multi_section_stream(&[(
+        propset::FMTID_USER_DEFINED_PROPERTIES,
+        vec![
+            (PID_CODEPAGE, typed(2, &[0xb0, 0x04, 0, 0])),
+            (PID_DICTIONARY, dictionary(&[(2, &name), (3, b"x\0")], true)),
+            (2, typed(3, &7_i32.to_le_bytes())),
+            (3, typed(11, &[0, 0, 0, 0])),
+        ],
+    )]);
+    let mut set = PropertySet::read_from(&mut data.as_slice()).unwrap();
+    let section = set.section_mut();
+    assert_eq!(
+        section.names(),
+        [(2, "\u{3b1}bc".to_string()), (3, "x".to_string())]
+    );
+    assert_eq!(section.get_by_name("\u{391}BC"), Some(&PropertyValue::I4(7)));
+    assert_eq!(write_to_vec(&set), data);
+
+    // Changing the code page converts the names.
+    let section = set.section_mut();
+    section.set_codepage(CODEPAGE_UTF8).unwrap();
+    assert_eq!(
+        section.get(PID_DICTIONARY),
+        Some(&PropertyValue::Dictionary(vec![
+            (2, "\u{3b1}bc".as_bytes().to_vec()),
+            (3, b"x".to_vec()),
+        ]))
+    );
+    let reread =
+        PropertySet::read_from(&mut write_to_vec(&set).as_slice()).unwrap();
+    assert_eq!(reread, set);
+    assert_eq!(reread.section().pid_by_name("X"), Some(3));
+}
+
+#[test]
+fn malformed_dictionary() {
+    let data = word_doc_summary_info();
+    // The first dictionary entry's length runs past the end of the section.
+    let mut bad = data.clone();
+    let section = data.len() - section_bytes(&data, 1).len();
+    let dictionary_offset = section
+        + u32::from_le_bytes(
+            data[section + 12..section + 16].try_into().unwrap(),
+        ) as usize;
+    bad[dictionary_offset + 8..dictionary_offset + 12]
+        .copy_from_slice(&10_000_u32.to_le_bytes());
+    let error = PropertySet::read_from(&mut bad.as_slice()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    // So does its entry count.
+    let mut bad = data.clone();
+    bad[dictionary_offset..dictionary_offset + 4]
+        .copy_from_slice(&u32::MAX.to_le_bytes());
+    let error = PropertySet::read_from(&mut bad.as_slice()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn malformed_streams() {
+    let valid = word_summary_info();
+    let parse = |data: &[u8]| {
+        PropertySet::read_from(&mut &data[..]).unwrap_err().kind()
+    };
+    // Too short for a header.
+    assert_eq!(parse(&valid[..20]), ErrorKind::InvalidData);
+    // Wrong byte order mark.
+    let mut data = valid.clone();
+    data[0] = 0xff;
+    assert_eq!(parse(&data), ErrorKind::InvalidData);
+    // No sections.
+    let mut data = valid.clone();
+    data[24..28].copy_from_slice(&0_u32.to_le_bytes());
+    assert_eq!(parse(&data), ErrorKind::InvalidData);
+    // Section past the end of the stream.
+    let mut data = valid.clone();
+    data[44..48].copy_from_slice(&10_000_u32.to_le_bytes());
+    assert_eq!(parse(&data), ErrorKind::InvalidData);
+    // Section longer than the stream.
+    assert_eq!(parse(&valid[..valid.len() - 4]), ErrorKind::InvalidData);
+    // Property count too large for the section.
+    let mut data = valid.clone();
+    data[52..56].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(parse(&data), ErrorKind::InvalidData);
+    // Property offset past the end of the section.
+    let mut data = valid.clone();
+    data[60..64].copy_from_slice(&10_000_u32.to_le_bytes());
+    assert_eq!(parse(&data), ErrorKind::InvalidData);
+    // String length running past the end of the section.
+    let mut data = valid.clone();
+    let title_offset =
+        48 + u32::from_le_bytes(data[68..72].try_into().unwrap()) as usize;
+    data[title_offset + 4..title_offset + 8]
+        .copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(parse(&data), ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "msi")]
+#[test]
+fn msi_summary_info_round_trip() {
+    use cfb::msi::{MsiArch, MsiOptions, MsiSkeleton};
+
+    let options = MsiOptions::new(MsiArch::X64, Uuid::from_u128(0xabcdef))
+        .title("Installation Database")
+        .author("Somebody")
+        .created(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+    let mut comp =
+        MsiSkeleton::create(Cursor::new(Vec::new()), options).unwrap();
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(
+        &mut comp.open_stream(SUMMARY_INFO_PATH).unwrap(),
+        &mut data,
+    )
+    .unwrap();
+    let set = comp.read_property_set(SUMMARY_INFO_PATH).unwrap();
+    let section = set.section();
+    assert_eq!(
+        section.string(PID_TITLE).as_deref(),
+        Some("Installation Database")
+    );
+    assert_eq!(
+        section.string(propset::PID_TEMPLATE).as_deref(),
+        Some("x64;1033")
+    );
+    assert_eq!(write_to_vec(&set), data);
+}
+
+//===========================================================================//
@@ -0,0 +1,73 @@
+use cfb::{CompoundFile, StreamId, ValidationIssueKind, Version};
+use rawcfb::dir_entry_offset;
+use std::io::{self, Cursor, Write};
+
+mod rawcfb;
+
+//===========================================================================//
+
+/// Creates a V3 file with streams "/a" (entry 1) and "/c" (entry 2), then
+/// hides data where a parser won't look, and corrupts the unallocated entry
+/// 3 beyond parsing.
+fn make_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/a").unwrap().write_all(b"alpha").unwrap();
+    comp.create_stream("/c").unwrap().write_all(b"gamma").unwrap();
+    let mut data = comp.into_inner().into_inner();
+
+    // Stash bytes after the terminator of "/a"'s name, and in the high half
+    // of its stream size (which version 3 ignores).
+    let a = dir_entry_offset(&data, 1);
+    assert_eq!(&data[a..(a + 4)], b"a\0\0\0");
+    data[(a + 10)..(a + 16)].copy_from_slice(b"HIDDEN");
+    data[(a + 124)..(a + 128)].copy_from_slice(b"\xde\xad\xbe\xef");
+
+    // Give the unallocated entry an invalid name length and object type.
+    let unallocated = dir_entry_offset(&data, 3);
+    assert_eq!(data[unallocated + 66], 0);
+    data[unallocated..(unallocated + 128)].copy_from_slice(&[0xee; 128]);
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn iterate_raw_entries_of_corrupt_directory() {
+    let data = make_file();
+    assert!(CompoundFile::open_strict(Cursor::new(data.clone())).is_err());
+    let mut comp = CompoundFile::open(Cursor::new(data.clone())).unwrap();
+    let issues: Vec<ValidationIssueKind> =
+        comp.open_warnings().iter().map(|issue| issue.kind()).collect();
+    assert!(issues.contains(&ValidationIssueKind::UnparseableDirEntry));
+    assert_eq!(comp.entry("/a").unwrap().len(), 5);
+    assert!(comp.is_stream("/c"));
+
+    let entries: Vec<(StreamId, [u8; 128])> =
+        comp.raw_dir_entries().collect::<io::Result<_>>().unwrap();
+    assert_eq!(entries.len(), 4);
+    for (index, (stream_id, raw)) in entries.iter().enumerate() {
+        assert_eq!(stream_id.value() as usize, index);
+        let offset = dir_entry_offset(&data, index);
+        assert_eq!(raw[..], data[offset..(offset + 128)]);
+        assert_eq!(comp.raw_dir_entry(*stream_id).unwrap(), *raw);
+    }
+    assert_eq!(&entries[1].1[10..16], b"HIDDEN");
+    assert_eq!(entries[3].1, [0xee; 128]);
+
+    let error = comp.raw_dir_entry(StreamId::new(4)).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn raw_entries_reflect_changes() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    // The unparseable entry is free, so it gets reused.
+    comp.create_stream("/b").unwrap();
+    let raw = comp.raw_dir_entry(StreamId::new(3)).unwrap();
+    assert_eq!(&raw[..6], b"b\0\0\0\0\0");
+    assert_eq!(comp.raw_dir_entries().count(), 4);
+}
+
+//===========================================================================//
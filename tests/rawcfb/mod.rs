@@ -0,0 +1,55 @@
+//! Helpers for tests that inspect or patch the raw bytes of a V3 compound
+//! file (with 512-byte sectors) directly, rather than through the `cfb`
+//! crate.  Each test crate uses only some of them.
+
+#![allow(dead_code)]
+
+const SECTOR_LEN: usize = 512;
+const DIR_ENTRY_LEN: usize = 128;
+
+//===========================================================================//
+
+/// Reads the little-endian `u32` at the given offset.
+pub fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..(offset + 4)]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Returns the offset of the given sector.
+pub fn sector_offset(sector_id: u32) -> usize {
+    (sector_id as usize + 1) * SECTOR_LEN
+}
+
+/// Returns the offset of the FAT entry for the given sector, which must be
+/// described by one of the FAT sectors listed in the header.
+pub fn fat_entry_offset(data: &[u8], sector_id: u32) -> usize {
+    let entries_per_sector = (SECTOR_LEN / 4) as u32;
+    let index = (sector_id / entries_per_sector) as usize;
+    assert!(index < 109, "sector {} is past the header's DIFAT", sector_id);
+    let fat_sector = u32_at(data, 76 + 4 * index);
+    sector_offset(fat_sector) + (sector_id % entries_per_sector) as usize * 4
+}
+
+/// Returns the offset of the given directory entry, following the directory
+/// chain through the FAT if the entry is past the first directory sector.
+pub fn dir_entry_offset(data: &[u8], stream_id: usize) -> usize {
+    let entries_per_sector = SECTOR_LEN / DIR_ENTRY_LEN;
+    let mut sector_id = u32_at(data, 48);
+    for _ in 0..(stream_id / entries_per_sector) {
+        sector_id = u32_at(data, fat_entry_offset(data, sector_id));
+    }
+    sector_offset(sector_id) + (stream_id % entries_per_sector) * DIR_ENTRY_LEN
+}
+
+/// Returns the offset of the directory entry with the given name.  This scans
+/// the whole file rather than following the directory chain, so it still
+/// works after the FAT has been damaged.
+pub fn named_dir_entry_offset(data: &[u8], name: &str) -> usize {
+    let name: Vec<u8> =
+        name.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+    (0..data.len())
+        .step_by(DIR_ENTRY_LEN)
+        .find(|&i| data[i..].starts_with(&name))
+        .unwrap()
+}
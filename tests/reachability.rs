@@ -0,0 +1,255 @@
+use cfb::{CompoundFile, Reachability, Version};
+use rawcfb::dir_entry_offset;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+
+mod rawcfb;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(41).wrapping_add(seed)).collect()
+}
+
+fn write_stream(comp: &mut TestFile, path: &str, data: &[u8]) {
+    comp.create_stream(path).unwrap().write_all(data).unwrap();
+}
+
+/// Computes the file's reachability and checks that its categories add up.
+fn reachability(comp: &mut TestFile) -> Reachability {
+    let reach = comp.reachability().unwrap();
+    assert_eq!(
+        reach.reachable_bytes()
+            + reach.slack_bytes()
+            + reach.orphaned_bytes()
+            + reach.free_bytes()
+            + reach.metadata_bytes(),
+        reach.total_bytes(),
+        "{:?}",
+        reach
+    );
+    assert_eq!(
+        reach.file_len() + reach.missing_bytes() - reach.excess_bytes(),
+        reach.total_bytes()
+    );
+    let score = reach.integrity_score();
+    assert!((0.0..=1.0).contains(&score), "{}", score);
+    reach
+}
+
+fn total_stream_len(comp: &TestFile) -> u64 {
+    comp.walk()
+        .filter(|entry| entry.is_stream())
+        .map(|entry| entry.len())
+        .sum()
+}
+
+/// Creates a file with a mix of small and large streams, then removes and
+/// shrinks enough of them to leave free sectors and free mini sectors
+/// scattered all over.
+fn churned_file(version: Version) -> TestFile {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.create_storage("/a").unwrap();
+    for index in 0..30 {
+        let len = if index % 3 == 0 { 7000 + index * 100 } else { 60 * index };
+        let dir = if index % 2 == 0 { "/" } else { "/a/" };
+        let path = format!("{}s{}", dir, index);
+        write_stream(&mut comp, &path, &data(len, index as u8));
+    }
+    for index in (0..30).step_by(4) {
+        let dir = if index % 2 == 0 { "/" } else { "/a/" };
+        comp.remove_stream(format!("{}s{}", dir, index)).unwrap();
+    }
+    for index in (1..30).step_by(4) {
+        let dir = if index % 2 == 0 { "/" } else { "/a/" };
+        let path = format!("{}s{}", dir, index);
+        comp.open_stream(&path).unwrap().set_len(5).unwrap();
+    }
+    comp.flush().unwrap();
+    CompoundFile::open(comp.into_inner()).unwrap()
+}
+
+/// Creates a V3 file with streams "/keep" (entry 1) and "/gone" (entry 2),
+/// the latter `len` bytes long.  Then deletes "/gone" by unlinking it from
+/// the tree and marking its entry unallocated, but leaves its chain
+/// allocated.
+fn make_stale(len: usize) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    write_stream(&mut comp, "/keep", b"kept");
+    write_stream(&mut comp, "/gone", &data(len, 0));
+    let mut data = comp.into_inner().into_inner();
+    let keep = dir_entry_offset(&data, 1);
+    let gone = dir_entry_offset(&data, 2);
+    assert_eq!(data[(keep + 68)..(keep + 72)], 2u32.to_le_bytes());
+    data[(keep + 68)..(keep + 72)].copy_from_slice(&[0xff; 4]);
+    assert_eq!(data[gone + 66], 2);
+    data[gone + 66] = 0;
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn clean_file_is_fully_accounted_for() {
+    for version in [Version::V3, Version::V4] {
+        let sector_len = version.sector_len() as u64;
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_version(version, cursor).unwrap();
+        write_stream(&mut comp, "/big", &data(5000, 1));
+        write_stream(&mut comp, "/small", &data(100, 2));
+        comp.flush().unwrap();
+        let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+        let reach = reachability(&mut comp);
+        assert_eq!(reach.reachable_bytes(), 5100);
+        assert_eq!(reach.orphaned_bytes(), 0);
+        assert_eq!(reach.missing_bytes(), 0);
+        assert_eq!(reach.excess_bytes(), 0);
+        assert_eq!(reach.total_bytes(), reach.file_len());
+        assert_eq!(reach.integrity_score(), 1.0);
+        // The big stream's last sector, the small stream's last mini sector,
+        // and the rest of the mini stream's only sector.
+        let big_slack = 5000_u64.div_ceil(sector_len) * sector_len - 5000;
+        assert_eq!(reach.slack_bytes(), big_slack + 28 + (sector_len - 128));
+        let stats = comp.stats().unwrap();
+        assert_eq!(
+            reach.free_bytes(),
+            stats.num_free_sectors() as u64 * sector_len
+        );
+    }
+}
+
+#[test]
+fn empty_file_is_all_metadata() {
+    for version in [Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_version(version, cursor).unwrap();
+        let reach = reachability(&mut comp);
+        assert_eq!(reach.metadata_bytes(), reach.total_bytes());
+        assert_eq!(reach.total_bytes(), 3 * version.sector_len() as u64);
+        assert_eq!(reach.integrity_score(), 1.0);
+    }
+}
+
+#[test]
+fn fragmented_file_is_fully_accounted_for() {
+    for version in [Version::V3, Version::V4] {
+        let mut comp = churned_file(version);
+        let stats = comp.stats().unwrap();
+        assert!(stats.num_fragments() > 0);
+        assert!(stats.num_free_mini_sectors() > 0);
+        let reach = reachability(&mut comp);
+        assert_eq!(reach.reachable_bytes(), total_stream_len(&comp));
+        assert_eq!(reach.orphaned_bytes(), 0);
+        assert_eq!(reach.missing_bytes(), 0);
+        assert!(reach.free_bytes() > 0);
+        assert!(reach.slack_bytes() > 0);
+
+        // Compacting frees up space at the end of the file without
+        // truncating it, leaving excess bytes past the FAT-described length.
+        let old_len = reach.file_len();
+        let new_len = comp.compact().unwrap();
+        let compacted = reachability(&mut comp);
+        assert_eq!(compacted.total_bytes(), new_len);
+        assert_eq!(compacted.excess_bytes(), old_len - new_len);
+        assert_eq!(compacted.free_bytes(), 0);
+        assert_eq!(compacted.reachable_bytes(), reach.reachable_bytes());
+        assert_eq!(compacted.integrity_score(), 1.0);
+    }
+}
+
+#[test]
+fn shared_chains_are_counted_once() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream_dedup("/big1", &data(5000, 1)).unwrap();
+    comp.create_stream_dedup("/big2", &data(5000, 1)).unwrap();
+    comp.create_stream_dedup("/small1", &data(100, 2)).unwrap();
+    comp.create_stream_dedup("/small2", &data(100, 2)).unwrap();
+    comp.flush().unwrap();
+    let reach = reachability(&mut comp);
+    assert_eq!(reach.reachable_bytes(), 5100);
+    assert_eq!(reach.orphaned_bytes(), 0);
+}
+
+#[test]
+fn truncated_file_reports_missing_bytes() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    write_stream(&mut comp, "/small", &data(100, 1));
+    // Written last, so that the file ends with this stream's data.
+    write_stream(&mut comp, "/big", &data(5000, 2));
+    comp.flush().unwrap();
+    let mut data = comp.into_inner().into_inner();
+    let full_len = data.len() as u64;
+    data.truncate(data.len() - 100);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let reach = reachability(&mut comp);
+    assert_eq!(reach.total_bytes(), full_len);
+    assert_eq!(reach.file_len(), full_len - 100);
+    assert_eq!(reach.missing_bytes(), 100);
+    assert_eq!(reach.excess_bytes(), 0);
+    assert_eq!(reach.reachable_bytes(), 5100);
+    assert!(reach.integrity_score() < 1.0);
+}
+
+#[test]
+fn file_with_trailing_data_is_fully_accounted_for() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    write_stream(&mut comp, "/big", &data(5000, 1));
+    comp.flush().unwrap();
+    let mut cursor = comp.into_inner();
+    let old_len = cursor.seek(SeekFrom::End(0)).unwrap();
+    cursor.write_all(&[0xaa; 1024]).unwrap();
+    let mut comp = CompoundFile::open(cursor).unwrap();
+    let reach = reachability(&mut comp);
+    assert_eq!(reach.total_bytes(), old_len + 1024);
+    assert_eq!(reach.free_bytes(), 1024);
+    assert_eq!(reach.integrity_score(), 1.0);
+}
+
+#[test]
+fn stale_chains_are_orphaned() {
+    let mut comp = CompoundFile::open(Cursor::new(make_stale(5000))).unwrap();
+    assert_eq!(comp.stale_chains().len(), 1);
+    let reach = reachability(&mut comp);
+    assert_eq!(reach.reachable_bytes(), 4);
+    assert_eq!(reach.orphaned_bytes(), 10 * 512);
+    assert!(reach.integrity_score() < 1.0);
+
+    let mut comp = CompoundFile::open(Cursor::new(make_stale(200))).unwrap();
+    assert_eq!(comp.stale_chains().len(), 1);
+    let reach = reachability(&mut comp);
+    assert_eq!(reach.reachable_bytes(), 4);
+    assert_eq!(reach.orphaned_bytes(), 4 * 64);
+}
+
+#[test]
+fn unlinked_stream_is_orphaned() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    write_stream(&mut comp, "/big", &data(5000, 1));
+    let mut data = comp.into_inner().into_inner();
+    // Unlink the only stream from the root storage, leaving its entry
+    // allocated.
+    let root = dir_entry_offset(&data, 0);
+    assert_eq!(data[(root + 76)..(root + 80)], 1u32.to_le_bytes());
+    data[(root + 76)..(root + 80)].copy_from_slice(&[0xff; 4]);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert_eq!(comp.walk().count(), 1);
+    let reach = reachability(&mut comp);
+    assert_eq!(reach.reachable_bytes(), 0);
+    assert_eq!(reach.orphaned_bytes(), 10 * 512);
+}
+
+//===========================================================================//
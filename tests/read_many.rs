@@ -0,0 +1,141 @@
+use cfb::{CompoundFile, Version};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use tracer::Tracer;
+
+mod tracer;
+
+//===========================================================================//
+
+fn stream_data(index: usize) -> Vec<u8> {
+    let len = match index % 3 {
+        0 => 100 + index,
+        1 => 5000 + 7 * index,
+        _ => 20_000 + index,
+    };
+    (0..len).map(|i| (i * 31 + index) as u8).collect()
+}
+
+/// Builds a file whose streams are written a chunk at a time, round-robin,
+/// so that their chains are interleaved throughout the file.
+fn interleaved_file(version: Version, num_streams: usize) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(version, cursor).expect("create");
+    comp.create_storage("/dir").unwrap();
+    let mut streams: Vec<_> = (0..num_streams)
+        .map(|index| {
+            let path = format!("/dir/stream{}", index);
+            (comp.create_stream(&path).unwrap(), stream_data(index))
+        })
+        .collect();
+    let mut offset = 0;
+    while streams.iter().any(|(_, data)| offset < data.len()) {
+        for (stream, data) in streams.iter_mut() {
+            // Crossing the mini stream cutoff all at once keeps each stream
+            // in the right place from the start.
+            let chunk = if offset == 0 { data.len().min(4096) } else { 512 };
+            if offset < data.len() && (offset == 0 || offset >= 4096) {
+                let end = (offset + chunk).min(data.len());
+                stream.write_all(&data[offset..end]).unwrap();
+            }
+        }
+        offset = if offset == 0 { 4096 } else { offset + 512 };
+    }
+    drop(streams);
+    comp.into_inner().into_inner()
+}
+
+fn paths(indices: &[usize]) -> Vec<PathBuf> {
+    indices
+        .iter()
+        .map(|index| PathBuf::from(format!("/dir/stream{}", index)))
+        .collect()
+}
+
+//===========================================================================//
+
+fn check_read_many(version: Version) {
+    let num_streams = 60;
+    let data = interleaved_file(version, num_streams);
+    let tracer = Tracer::with_data(data);
+    let trace = tracer.trace();
+    let mut comp = CompoundFile::open(tracer).expect("open");
+
+    // Ask for the streams in a scrambled order.
+    let indices: Vec<usize> =
+        (0..num_streams).map(|i| (i * 37) % num_streams).collect();
+    trace.clear();
+    let results = comp.read_many(&paths(&indices)).unwrap();
+    let reads = trace.reads();
+
+    assert_eq!(results.len(), num_streams);
+    for (&index, (path, contents)) in indices.iter().zip(&results) {
+        assert_eq!(path, &PathBuf::from(format!("/dir/stream{}", index)));
+        assert!(*contents == stream_data(index), "{:?} differs", path);
+    }
+    // Every read should start at or after the end of the previous one.
+    for pair in reads.windows(2) {
+        assert!(pair[1].0 >= pair[0].0 + pair[0].1 as u64, "{:?}", pair);
+    }
+    // Adjacent sectors should have been merged into much fewer reads than
+    // there are sectors.
+    let sector_len = version.sector_len() as u64;
+    let total_len: u64 = reads.iter().map(|&(_, len)| len as u64).sum();
+    assert!((reads.len() as u64) < total_len / sector_len / 4);
+
+    // The results should match reading each stream individually.
+    for (path, contents) in &results {
+        let mut expected = Vec::new();
+        comp.open_stream(path).unwrap().read_to_end(&mut expected).unwrap();
+        assert!(*contents == expected, "{:?} differs", path);
+    }
+}
+
+#[test]
+fn read_many_v3() {
+    check_read_many(Version::V3);
+}
+
+#[test]
+fn read_many_v4() {
+    check_read_many(Version::V4);
+}
+
+#[test]
+fn read_many_repeated_and_shared_streams() {
+    let data = stream_data(2);
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream_dedup("/foo", &data).unwrap();
+    comp.create_stream_dedup("/bar", &data).unwrap();
+    comp.create_stream("/empty").unwrap();
+    let results = comp.read_many(&["/foo", "/bar", "/empty", "/foo"]).unwrap();
+    let paths: Vec<_> = results.iter().map(|(path, _)| path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("/foo"),
+            PathBuf::from("/bar"),
+            PathBuf::from("/empty"),
+            PathBuf::from("/foo"),
+        ]
+    );
+    assert_eq!(results[0].1, data);
+    assert_eq!(results[1].1, data);
+    assert!(results[2].1.is_empty());
+    assert_eq!(results[3].1, data);
+    assert!(comp.read_many::<&str>(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn read_many_errors() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/storage").unwrap();
+    comp.create_stream("/stream").unwrap();
+    let error = comp.read_many(&["/stream", "/missing"]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = comp.read_many(&["/stream", "/storage"]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+//===========================================================================//
@@ -0,0 +1,151 @@
+//! Tests for streams whose declared length is more than their chain holds.
+//! Every allocation in this test binary is capped, so that a reader that
+//! sizes a buffer by the declared length fails instead of exhausting memory.
+
+use cfb::{CompoundFile, StreamId, ValidationIssueKind, Version};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Cursor, Read, Write};
+
+//===========================================================================//
+
+/// The largest single allocation that this test binary permits.
+const ALLOCATION_CAP: usize = 64 << 20;
+
+struct CappedAllocator;
+
+unsafe impl GlobalAlloc for CappedAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > ALLOCATION_CAP {
+            return std::ptr::null_mut();
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.size() > ALLOCATION_CAP {
+            return std::ptr::null_mut();
+        }
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        if new_size > ALLOCATION_CAP {
+            return std::ptr::null_mut();
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CappedAllocator = CappedAllocator;
+
+//===========================================================================//
+
+const DECLARED_LEN: u64 = 8 << 30;
+
+fn issue_kinds<F>(comp: &CompoundFile<F>) -> Vec<ValidationIssueKind> {
+    comp.open_warnings().iter().map(|issue| issue.kind()).collect()
+}
+
+fn read_stream<F: Read + std::io::Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Creates a file with a stream "/stream" holding `data`, then changes the
+/// stream's declared length to `new_len` without touching its chain.
+fn make_short(version: Version, data: &[u8], new_len: u64) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.create_stream("/keep").unwrap().write_all(b"kept").unwrap();
+    comp.create_stream("/stream").unwrap().write_all(data).unwrap();
+    comp.flush().unwrap();
+    let raw = comp.raw_dir_entry(StreamId::new(2)).unwrap();
+    let mut data = comp.into_inner().into_inner();
+    let offset = data
+        .windows(raw.len())
+        .position(|window| window == &raw[..])
+        .expect("directory entry");
+    data[(offset + 120)..(offset + 128)]
+        .copy_from_slice(&new_len.to_le_bytes());
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn read_to_end_stops_at_end_of_chain() {
+    // Three 4096-byte sectors, declared as 8 GiB.
+    let contents: Vec<u8> = (0..12288).map(|i| (i % 251) as u8).collect();
+    let data = make_short(Version::V4, &contents, DECLARED_LEN);
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert_eq!(
+        issue_kinds(&comp),
+        vec![ValidationIssueKind::StreamLongerThanChain]
+    );
+    let entry = comp.entry("/stream").unwrap();
+    assert_eq!(entry.len(), DECLARED_LEN);
+    assert_eq!(entry.readable_len(), 12288);
+    assert_eq!(read_stream(&mut comp, "/stream"), contents);
+    let contents_many = comp.read_many(&["/stream", "/keep"]).unwrap();
+    assert_eq!(contents_many[0].1, contents);
+    assert_eq!(contents_many[1].1, b"kept");
+    // Well-formed streams are unaffected.
+    let keep = comp.entry("/keep").unwrap();
+    assert_eq!(keep.readable_len(), keep.len());
+    assert_eq!(comp.root_entry().readable_len(), 0);
+}
+
+#[test]
+fn short_mini_stream() {
+    // One 64-byte mini sector, declared as 4000 bytes (still a mini
+    // stream).
+    let data = make_short(Version::V3, &[7; 64], 4000);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    assert_eq!(
+        issue_kinds(&comp),
+        vec![ValidationIssueKind::StreamLongerThanChain]
+    );
+    assert_eq!(comp.entry("/stream").unwrap().readable_len(), 64);
+    assert_eq!(read_stream(&mut comp, "/stream"), vec![7; 64]);
+}
+
+#[test]
+fn rewritten_stream_is_readable_in_full() {
+    let data = make_short(Version::V4, &[1; 12288], DECLARED_LEN);
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    comp.remove_stream("/stream").unwrap();
+    comp.create_stream("/stream").unwrap().write_all(&[2; 20000]).unwrap();
+    let entry = comp.entry("/stream").unwrap();
+    assert_eq!(entry.len(), 20000);
+    assert_eq!(entry.readable_len(), 20000);
+    assert_eq!(read_stream(&mut comp, "/stream"), vec![2; 20000]);
+}
+
+#[test]
+fn well_formed_file_has_no_short_streams() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.create_stream("/big").unwrap().write_all(&[1; 5000]).unwrap();
+    comp.create_stream("/small").unwrap().write_all(&[2; 100]).unwrap();
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    assert_eq!(issue_kinds(&comp), vec![]);
+    for entry in comp.walk() {
+        assert_eq!(entry.readable_len(), entry.len());
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,223 @@
+use cfb::{
+    CompoundFile, RecoveryWarning, RecoveryWarningKind, ValidationIssueKind,
+    Version,
+};
+use rawcfb::{dir_entry_offset, fat_entry_offset, u32_at};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::path::Path;
+
+mod rawcfb;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(43).wrapping_add(seed)).collect()
+}
+
+fn write_stream(comp: &mut TestFile, path: &str, data: &[u8]) {
+    comp.create_stream(path).unwrap().write_all(data).unwrap();
+}
+
+fn read_stream(comp: &mut TestFile, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn create(streams: &[(&str, usize)]) -> TestFile {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    for (index, &(path, len)) in streams.iter().enumerate() {
+        write_stream(&mut comp, path, &data(len, index as u8));
+    }
+    comp.flush().unwrap();
+    comp
+}
+
+fn kinds(warnings: &[RecoveryWarning]) -> Vec<RecoveryWarningKind> {
+    warnings.iter().map(RecoveryWarning::kind).collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn undamaged_file_recovers_without_warnings() {
+    let comp = create(&[("/small", 100), ("/big", 5000)]);
+    let (mut comp, warnings) =
+        CompoundFile::open_recover(comp.into_inner()).unwrap();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert_eq!(read_stream(&mut comp, "/small"), data(100, 0));
+    assert_eq!(read_stream(&mut comp, "/big"), data(5000, 1));
+}
+
+#[test]
+fn recovered_file_is_read_only() {
+    let comp = create(&[("/small", 100), ("/big", 5000)]);
+    let (mut comp, _) = CompoundFile::open_recover(comp.into_inner()).unwrap();
+    let error = comp.create_stream("/new").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    let error = comp.create_storage("/dir").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    let mut stream = comp.open_stream("/big").unwrap();
+    // The write itself may only be buffered, but can't be flushed.
+    let error = stream
+        .write_all(b"changed")
+        .and_then(|()| stream.flush())
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    drop(stream);
+    assert_eq!(read_stream(&mut comp, "/big"), data(5000, 1));
+}
+
+#[test]
+fn truncated_file() {
+    // Written last, so that the file ends with this stream's bytes.
+    let comp = create(&[("/small", 100), ("/big", 5000)]);
+    let mut bytes = comp.into_inner().into_inner();
+    bytes.truncate(bytes.len() - 1000);
+    assert!(CompoundFile::open(Cursor::new(bytes.clone())).is_err());
+
+    let (mut comp, warnings) =
+        CompoundFile::open_recover(Cursor::new(bytes)).unwrap();
+    assert!(kinds(&warnings).contains(&RecoveryWarningKind::UnreadableSector));
+    let truncated = warnings
+        .iter()
+        .find(|warning| warning.kind() == RecoveryWarningKind::StreamTruncated)
+        .unwrap();
+    assert_eq!(truncated.path(), Some(Path::new("/big")));
+    // Only whole sectors are salvaged.
+    let big = comp.entry("/big").unwrap();
+    assert_eq!(big.len(), 8 * 512);
+    assert_eq!(comp.open_stream("/big").unwrap().len(), 8 * 512);
+    assert_eq!(read_stream(&mut comp, "/big"), data(5000, 1)[..8 * 512]);
+    assert_eq!(read_stream(&mut comp, "/small"), data(100, 0));
+}
+
+#[test]
+fn looped_fat_chain() {
+    let comp = create(&[("/big", 5000), ("/other", 5000)]);
+    let mut bytes = comp.into_inner().into_inner();
+    let big = dir_entry_offset(&bytes, 1);
+    let start_sector = u32_at(&bytes, big + 116);
+    // Make the fifth sector of "/big" loop back to its first.
+    let mut sector_id = start_sector;
+    for _ in 0..4 {
+        sector_id = u32_at(&bytes, fat_entry_offset(&bytes, sector_id));
+    }
+    let offset = fat_entry_offset(&bytes, sector_id);
+    bytes[offset..(offset + 4)].copy_from_slice(&start_sector.to_le_bytes());
+    assert!(CompoundFile::open_strict(Cursor::new(bytes.clone())).is_err());
+
+    let (mut comp, warnings) =
+        CompoundFile::open_recover(Cursor::new(bytes)).unwrap();
+    assert_eq!(
+        kinds(&warnings),
+        vec![
+            RecoveryWarningKind::ChainLoop,
+            RecoveryWarningKind::StreamTruncated
+        ]
+    );
+    let warning = &warnings[0];
+    assert_eq!(warning.path(), Some(Path::new("/big")));
+    assert_eq!(warning.stream_id().unwrap().value(), 1);
+    assert_eq!(warning.sector_id().unwrap().value(), sector_id);
+    assert_eq!(comp.entry("/big").unwrap().len(), 5 * 512);
+    assert_eq!(read_stream(&mut comp, "/big"), data(5000, 0)[..5 * 512]);
+    assert_eq!(read_stream(&mut comp, "/other"), data(5000, 1));
+}
+
+#[test]
+fn garbage_directory_sector() {
+    // Eight entries (including the root) fill two directory sectors.
+    let names = ["/a", "/b", "/c", "/d", "/e", "/f", "/g"];
+    let streams: Vec<_> = names.iter().map(|&name| (name, 100)).collect();
+    let comp = create(&streams);
+    let mut bytes = comp.into_inner().into_inner();
+    let second_sector = dir_entry_offset(&bytes, 4);
+    bytes[second_sector..(second_sector + 512)].fill(0xab);
+    assert!(CompoundFile::open(Cursor::new(bytes.clone())).is_err());
+
+    let (mut comp, warnings) =
+        CompoundFile::open_recover(Cursor::new(bytes)).unwrap();
+    let unreadable: Vec<_> = warnings
+        .iter()
+        .filter(|warning| {
+            warning.kind() == RecoveryWarningKind::UnreadableDirEntry
+        })
+        .map(|warning| warning.stream_id().unwrap().value())
+        .collect();
+    assert_eq!(unreadable, vec![4, 5, 6, 7]);
+    assert!(kinds(&warnings).contains(&RecoveryWarningKind::BrokenTreeLink));
+    // Whatever is still reachable can be read.
+    let paths: Vec<_> = comp
+        .walk()
+        .filter(|entry| entry.is_stream())
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let name = path.to_str().unwrap();
+        let index = names.iter().position(|&n| n == name).unwrap();
+        assert_eq!(read_stream(&mut comp, name), data(100, index as u8));
+    }
+}
+
+#[test]
+fn broken_sibling_pointer() {
+    let names = ["/a", "/b", "/c", "/d", "/e"];
+    let streams: Vec<_> = names.iter().map(|&name| (name, 100)).collect();
+    let comp = create(&streams);
+    let mut bytes = comp.into_inner().into_inner();
+    // Find an entry with a sibling, and point that sibling out of range.
+    let (stream_id, link) = (1..=names.len())
+        .flat_map(|stream_id| [(stream_id, 68), (stream_id, 72)])
+        .find(|&(stream_id, link)| {
+            let offset = dir_entry_offset(&bytes, stream_id) + link;
+            u32_at(&bytes, offset) != u32::MAX
+        })
+        .unwrap();
+    let offset = dir_entry_offset(&bytes, stream_id) + link;
+    bytes[offset..(offset + 4)].copy_from_slice(&200u32.to_le_bytes());
+    assert!(CompoundFile::open_strict(Cursor::new(bytes.clone())).is_err());
+    // Permissive opening skips the broken branch, too.
+    let comp = CompoundFile::open(Cursor::new(bytes.clone())).unwrap();
+    let issue_kinds: Vec<ValidationIssueKind> =
+        comp.open_warnings().iter().map(|issue| issue.kind()).collect();
+    assert_eq!(issue_kinds, vec![ValidationIssueKind::DanglingSibling]);
+
+    let (mut comp, warnings) =
+        CompoundFile::open_recover(Cursor::new(bytes)).unwrap();
+    assert_eq!(kinds(&warnings), vec![RecoveryWarningKind::BrokenTreeLink]);
+    let warning = &warnings[0];
+    assert_eq!(warning.stream_id().unwrap().value(), stream_id as u32);
+    let name = names[stream_id - 1];
+    assert_eq!(warning.path(), Some(Path::new(name)));
+    assert!(warning.to_string().contains("200"), "{}", warning);
+    // The entry itself is still reachable, along with anything not behind
+    // the broken link.
+    assert_eq!(read_stream(&mut comp, name), data(100, stream_id as u8 - 1));
+    let num_streams = comp.walk().filter(|entry| entry.is_stream()).count();
+    assert!(num_streams < names.len());
+}
+
+#[test]
+fn missing_fat_sector() {
+    let comp = create(&[("/big", 5000)]);
+    let mut bytes = comp.into_inner().into_inner();
+    // Claim a second FAT sector, far past the end of the file.
+    bytes[44..48].copy_from_slice(&2u32.to_le_bytes());
+    bytes[80..84].copy_from_slice(&5000u32.to_le_bytes());
+    assert!(CompoundFile::open(Cursor::new(bytes.clone())).is_err());
+
+    let (mut comp, warnings) =
+        CompoundFile::open_recover(Cursor::new(bytes)).unwrap();
+    assert_eq!(kinds(&warnings), vec![RecoveryWarningKind::FatSectorMissing]);
+    assert_eq!(warnings[0].sector_id().unwrap().value(), 5000);
+    assert_eq!(warnings[0].path(), None);
+    assert_eq!(read_stream(&mut comp, "/big"), data(5000, 0));
+}
+
+//===========================================================================//
@@ -0,0 +1,149 @@
+//! Stress tests for repeatedly removing and recreating objects under the
+//! same names, which reuses the freed directory entries.
+
+use cfb::{CompoundFile, FreeEntryPolicy, Version};
+use rand::prelude::{IteratorRandom, Rng, SeedableRng, SliceRandom};
+use rand_pcg::Pcg32;
+use std::collections::BTreeMap;
+use std::io::{Cursor, ErrorKind, Read, Write};
+
+//===========================================================================//
+
+const NAMES: &[&str] = &["alpha", "beta", "gamma", "delta", "epsilon"];
+
+fn random_data(rng: &mut Pcg32) -> Vec<u8> {
+    // Straddle the mini stream cutoff of 4096 bytes.
+    let len = rng.gen_range(3000..5200);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+fn read_stream<F: Read + std::io::Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn stress(version: Version, policy: FreeEntryPolicy, seed: &[u8; 16]) {
+    let mut rng = Pcg32::from_seed(*seed);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.set_free_entry_policy(policy);
+    let mut model = BTreeMap::<String, Vec<u8>>::new();
+    for iteration in 0..1000 {
+        let path = format!("/{}", NAMES.choose(&mut rng).unwrap());
+        let data = random_data(&mut rng);
+        // A handle to another stream, opened before the removal, must keep
+        // working even though the tree gets relinked around it.
+        let other =
+            model.keys().filter(|&other| *other != path).choose(&mut rng);
+        let other = other.cloned();
+        let mut other_handle =
+            other.as_ref().map(|other| comp.open_stream(other).unwrap());
+        // A handle to the removed stream, on the other hand, must not write
+        // its buffered data into the stream recreated in its place.
+        let mut stale_handle = None;
+        if model.remove(&path).is_some() {
+            if rng.gen_bool(0.25) {
+                let mut stream = comp.open_stream(&path).unwrap();
+                stream.write_all(b"stale").unwrap();
+                stale_handle = Some(stream);
+            }
+            comp.remove_stream(&path).unwrap();
+        }
+        // Write the data in two pieces, so that the stream grows across the
+        // mini stream cutoff within one handle.
+        let split = rng.gen_range(0..=data.len());
+        let mut stream = comp.create_new_stream(&path).unwrap();
+        stream.write_all(&data[..split]).unwrap();
+        stream.write_all(&data[split..]).unwrap();
+        drop(stream);
+        if rng.gen_bool(0.5) {
+            comp.remove_stream(&path).unwrap();
+            comp.create_stream(&path).unwrap().write_all(&data).unwrap();
+        }
+        if let Some(mut stream) = stale_handle {
+            let error = stream.flush().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+        }
+        if let (Some(other), Some(handle)) = (&other, other_handle.as_mut()) {
+            let mut contents = Vec::new();
+            handle.read_to_end(&mut contents).unwrap();
+            assert_eq!(&contents, &model[other], "{}", other);
+        }
+        drop(other_handle);
+        model.insert(path, data);
+        if iteration % 17 == 0 {
+            comp.flush().unwrap();
+        }
+    }
+    comp.flush().unwrap();
+
+    let cursor = comp.into_inner();
+    let mut comp = CompoundFile::open_strict(cursor).unwrap();
+    let mut paths: Vec<String> = comp
+        .read_root_storage()
+        .map(|entry| entry.path().to_string_lossy().into_owned())
+        .collect();
+    paths.sort();
+    assert_eq!(paths, model.keys().cloned().collect::<Vec<_>>());
+    for (path, data) in model.iter() {
+        assert_eq!(&read_stream(&mut comp, path), data, "{}", path);
+    }
+}
+
+//===========================================================================//
+
+#[test]
+fn stale_handle_does_not_write_into_recreated_stream() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    let mut stale = comp.create_stream("/foo").unwrap();
+    stale.write_all(b"old data").unwrap();
+    comp.remove_stream("/foo").unwrap();
+    comp.create_stream("/foo").unwrap().write_all(b"new").unwrap();
+    assert_eq!(stale.flush().unwrap_err().kind(), ErrorKind::NotFound);
+    drop(stale);
+    assert_eq!(read_stream(&mut comp, "/foo"), b"new");
+}
+
+#[test]
+fn removal_keeps_other_handles_attached() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    // "/m" ends up with two children in the sibling tree, so removing it
+    // moves its predecessor, "/d", into its place.
+    for path in ["/m", "/c", "/x", "/d"] {
+        comp.create_stream(path).unwrap().write_all(path.as_bytes()).unwrap();
+    }
+    let mut handle = comp.open_stream("/d").unwrap();
+    comp.remove_stream("/m").unwrap();
+    handle.write_all(b"DDDD").unwrap();
+    handle.flush().unwrap();
+    drop(handle);
+    assert_eq!(read_stream(&mut comp, "/c"), b"/c");
+    assert_eq!(read_stream(&mut comp, "/x"), b"/x");
+    assert_eq!(read_stream(&mut comp, "/d"), b"DDDD");
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/d"), b"DDDD");
+    assert!(!comp.exists("/m"));
+}
+
+#[test]
+fn recreate_same_names_v3() {
+    stress(Version::V3, FreeEntryPolicy::Scrub, b"recreate-seed-v3");
+}
+
+#[test]
+fn recreate_same_names_v4() {
+    stress(Version::V4, FreeEntryPolicy::Scrub, b"recreate-seed-v4");
+}
+
+#[test]
+fn recreate_same_names_preserving_entries() {
+    stress(Version::V3, FreeEntryPolicy::Preserve, b"recreate-presrve");
+}
+
+//===========================================================================//
@@ -0,0 +1,416 @@
+use cfb::{CompoundFile, ObjectNotFound, VerifyOptions, Version};
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(17).wrapping_add(seed)).collect()
+}
+
+fn write_stream(comp: &mut TestFile, path: &str, data: &[u8]) {
+    comp.create_stream(path).unwrap().write_all(data).unwrap();
+}
+
+fn read_stream(comp: &mut TestFile, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn walk_paths(comp: &TestFile) -> Vec<PathBuf> {
+    comp.walk().map(|entry| entry.path().to_path_buf()).collect()
+}
+
+fn child_names(comp: &TestFile, path: &str) -> Vec<String> {
+    comp.read_storage(path)
+        .unwrap()
+        .map(|entry| entry.name().to_string())
+        .collect()
+}
+
+fn reopen(comp: TestFile) -> TestFile {
+    CompoundFile::open_strict(comp.into_inner()).unwrap()
+}
+
+/// Creates a file with a small stream, a large stream, and a storage with a
+/// couple of levels of children, with self-checking turned on.
+fn make_file() -> TestFile {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.set_self_check(true);
+    write_stream(&mut comp, "/small", &data(100, 1));
+    write_stream(&mut comp, "/large", &data(10000, 2));
+    comp.create_storage_all("/dir/sub").unwrap();
+    write_stream(&mut comp, "/dir/inner", &data(5000, 3));
+    write_stream(&mut comp, "/dir/sub/leaf", &data(50, 4));
+    comp
+}
+
+//===========================================================================//
+
+#[test]
+fn rename_stream_keeps_data_and_metadata() {
+    let mut comp = make_file();
+    comp.set_state_bits("/large", 0x55).unwrap();
+    comp.rename("/large", "/big").unwrap();
+    comp.rename("/small", "/dir/sub/tiny").unwrap();
+    assert!(!comp.exists("/large"));
+    assert!(!comp.exists("/small"));
+    for _ in 0..2 {
+        let entry = comp.entry("/big").unwrap();
+        assert_eq!(entry.name(), "big");
+        assert_eq!(entry.state_bits(), 0x55);
+        assert_eq!(read_stream(&mut comp, "/big"), data(10000, 2));
+        assert_eq!(read_stream(&mut comp, "/dir/sub/tiny"), data(100, 1));
+        comp.flush().unwrap();
+        comp = reopen(comp);
+    }
+}
+
+#[test]
+fn rename_storage_moves_its_subtree() {
+    let mut comp = make_file();
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    comp.set_storage_clsid("/dir", Uuid::from_u128(7)).unwrap();
+    comp.set_created_time("/dir", time).unwrap();
+    comp.set_modified_time("/dir", time).unwrap();
+    comp.create_storage("/other").unwrap();
+    comp.rename("/dir", "/other/moved").unwrap();
+    let mut paths = walk_paths(&comp);
+    paths.sort();
+    let expected = [
+        "/",
+        "/large",
+        "/other",
+        "/other/moved",
+        "/other/moved/inner",
+        "/other/moved/sub",
+        "/other/moved/sub/leaf",
+        "/small",
+    ];
+    assert_eq!(paths, expected.iter().map(PathBuf::from).collect::<Vec<_>>());
+    let mut comp = reopen(comp);
+    let entry = comp.entry("/other/moved").unwrap();
+    assert_eq!(*entry.clsid(), Uuid::from_u128(7));
+    assert_eq!(entry.created(), time);
+    assert_eq!(entry.modified(), time);
+    assert_eq!(read_stream(&mut comp, "/other/moved/inner"), data(5000, 3));
+    assert_eq!(read_stream(&mut comp, "/other/moved/sub/leaf"), data(50, 4));
+}
+
+#[test]
+fn renamed_entries_are_kept_in_order() {
+    let mut comp = make_file();
+    for name in ["bb", "a", "ccc", "DDDD", "e"] {
+        write_stream(&mut comp, &format!("/dir/sub/{}", name), b"x");
+    }
+    // Names are ordered by length first, then case-insensitively.
+    comp.rename("/dir/sub/ccc", "/dir/sub/Z").unwrap();
+    comp.rename("/dir/sub/a", "/dir/sub/zzzzz").unwrap();
+    comp.rename("/dir/sub/DDDD", "/dir/sub/dddd").unwrap();
+    comp.rename("/dir/sub/leaf", "/dir/sub/B").unwrap();
+    let expected = ["B", "e", "Z", "bb", "dddd", "zzzzz"];
+    assert_eq!(child_names(&comp, "/dir/sub"), expected);
+    let mut comp = reopen(comp);
+    assert_eq!(child_names(&comp, "/dir/sub"), expected);
+    // Lookups are case-insensitive, and find the new names.
+    assert_eq!(read_stream(&mut comp, "/dir/sub/DDDD"), b"x");
+    assert_eq!(read_stream(&mut comp, "/dir/sub/b"), data(50, 4));
+}
+
+#[test]
+fn rename_to_same_name_or_case() {
+    let mut comp = make_file();
+    comp.rename("/small", "/small").unwrap();
+    comp.rename("/dir", "/DIR").unwrap();
+    assert_eq!(comp.entry("/dir").unwrap().name(), "DIR");
+    let comp = reopen(comp);
+    assert_eq!(child_names(&comp, "/"), ["DIR", "large", "small"]);
+}
+
+#[test]
+fn open_streams_survive_rename() {
+    let mut comp = make_file();
+    let mut inner = comp.open_stream("/dir/inner").unwrap();
+    let mut leaf = comp.open_stream("/dir/sub/leaf").unwrap();
+    inner.seek(SeekFrom::Start(4000)).unwrap();
+    comp.rename("/dir", "/renamed").unwrap();
+    comp.rename("/renamed/sub/leaf", "/leaf").unwrap();
+    inner.write_all(&data(2000, 5)).unwrap();
+    leaf.seek(SeekFrom::End(0)).unwrap();
+    leaf.write_all(b"more").unwrap();
+    drop(inner);
+    drop(leaf);
+    comp.flush().unwrap();
+    let mut comp = reopen(comp);
+    let mut expected = data(5000, 3)[..4000].to_vec();
+    expected.extend_from_slice(&data(2000, 5));
+    assert_eq!(read_stream(&mut comp, "/renamed/inner"), expected);
+    let mut expected = data(50, 4);
+    expected.extend_from_slice(b"more");
+    assert_eq!(read_stream(&mut comp, "/leaf"), expected);
+}
+
+#[test]
+fn invalid_renames_change_nothing() {
+    let mut comp = make_file();
+    let before = walk_paths(&comp);
+    let invalid_input = [
+        ("/", "/x"),
+        ("/small", "/"),
+        ("/small", "/abcdefghijklmnopqrstuvwxyz123456"),
+        ("/small", "/a:b"),
+        ("/small", "/a!b"),
+        ("/small", "/dir/a\\b"),
+        ("/small", "/large/x"),
+        ("/dir", "/dir/sub/dir"),
+        ("/dir", "/dir/x"),
+        ("/small", "/\u{1}cfb.tmp.00000000"),
+    ];
+    for (from, to) in invalid_input {
+        let error = comp.rename(from, to).unwrap_err();
+        assert!(
+            matches!(
+                error.kind(),
+                ErrorKind::InvalidInput | ErrorKind::NotFound
+            ),
+            "{} -> {}: {}",
+            from,
+            to,
+            error
+        );
+    }
+    for (from, to) in [("/small", "/LARGE"), ("/dir/inner", "/dir/SUB")] {
+        let error = comp.rename(from, to).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists, "{}", error);
+    }
+    let error = comp.rename("/missing", "/x").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = comp.rename("/small", "/missing/x").unwrap_err();
+    let not_found = ObjectNotFound::from_io_error(&error).unwrap();
+    assert_eq!(not_found.missing(), Path::new("/missing"));
+    // A name of exactly 31 code units is fine.
+    comp.rename("/small", "/abcdefghijklmnopqrstuvwxyz12345").unwrap();
+    comp.rename("/abcdefghijklmnopqrstuvwxyz12345", "/small").unwrap();
+    assert_eq!(walk_paths(&comp), before);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Missing,
+    Stream,
+    EmptyStorage,
+    FullStorage,
+}
+
+/// Creates an object of the given kind at the given path, with contents
+/// and metadata derived from `seed`.
+fn make_object(comp: &mut TestFile, path: &str, kind: Kind, seed: u8) {
+    match kind {
+        Kind::Missing => return,
+        Kind::Stream => write_stream(comp, path, &data(3000, seed)),
+        Kind::EmptyStorage | Kind::FullStorage => {
+            comp.create_storage(path).unwrap();
+            comp.set_storage_clsid(path, Uuid::from_u128(seed as u128))
+                .unwrap();
+        }
+    }
+    comp.set_state_bits(path, seed as u32).unwrap();
+    if kind == Kind::FullStorage {
+        write_stream(comp, &format!("{}/big", path), &data(9000, seed));
+        comp.create_storage(format!("{}/sub", path)).unwrap();
+        write_stream(comp, &format!("{}/sub/small", path), &data(10, seed));
+    }
+}
+
+#[test]
+fn rename_matrix() {
+    let kinds = [Kind::Stream, Kind::EmptyStorage, Kind::FullStorage];
+    let targets = [Kind::Missing, Kind::Stream, Kind::EmptyStorage];
+    let targets = targets.iter().chain(&[Kind::FullStorage]);
+    for &source in &kinds {
+        for &target in targets.clone() {
+            for mode in 0..3 {
+                let mut comp = make_file();
+                make_object(&mut comp, "/dir/source", source, 1);
+                make_object(&mut comp, "/target", target, 2);
+                let result = match mode {
+                    0 => comp.rename("/dir/source", "/target"),
+                    1 => comp.rename_overwrite("/dir/source", "/target"),
+                    _ => comp.rename_overwrite_all("/dir/source", "/target"),
+                };
+                let is_stream = |kind| kind == Kind::Stream;
+                let expected = if target == Kind::Missing {
+                    None
+                } else if mode == 0 {
+                    Some(ErrorKind::AlreadyExists)
+                } else if is_stream(source) != is_stream(target)
+                    || (target == Kind::FullStorage && mode == 1)
+                {
+                    Some(ErrorKind::InvalidInput)
+                } else {
+                    None
+                };
+                let case = (source, target, mode);
+                assert_eq!(
+                    result.as_ref().err().map(|error| error.kind()),
+                    expected,
+                    "{:?}: {:?}",
+                    case,
+                    result
+                );
+
+                // Check what's left at both paths, after a round trip.
+                let mut comp = reopen(comp);
+                let (at_target, at_source, seed) = if expected.is_none() {
+                    (source, Kind::Missing, 1)
+                } else {
+                    (target, source, 2)
+                };
+                assert_eq!(
+                    comp.exists("/dir/source"),
+                    at_source != Kind::Missing,
+                    "{:?}",
+                    case
+                );
+                let entry = comp.entry("/target");
+                if at_target == Kind::Missing {
+                    assert!(entry.is_err(), "{:?}", case);
+                    continue;
+                }
+                let entry = entry.unwrap();
+                assert_eq!(entry.state_bits(), seed as u32, "{:?}", case);
+                if at_target == Kind::Stream {
+                    assert_eq!(
+                        read_stream(&mut comp, "/target"),
+                        data(3000, seed),
+                        "{:?}",
+                        case
+                    );
+                    continue;
+                }
+                assert_eq!(*entry.clsid(), Uuid::from_u128(seed as u128));
+                let expected_children: &[&str] =
+                    if at_target == Kind::FullStorage {
+                        &["big", "sub"]
+                    } else {
+                        &[]
+                    };
+                assert_eq!(
+                    child_names(&comp, "/target"),
+                    expected_children,
+                    "{:?}",
+                    case
+                );
+                if at_target == Kind::FullStorage {
+                    assert_eq!(
+                        read_stream(&mut comp, "/target/sub/small"),
+                        data(10, seed)
+                    );
+                }
+                // Nothing was leaked or lost.
+                let stats = comp.stats().unwrap();
+                assert_eq!(stats.num_shared_streams(), 0, "{:?}", case);
+                assert!(comp.verify_deep(VerifyOptions::new()).is_ok());
+            }
+        }
+    }
+}
+
+#[test]
+fn overwrite_frees_replaced_data() {
+    let mut comp = make_file();
+    let num_free = comp.stats().unwrap().num_free_sectors();
+    comp.rename_overwrite("/small", "/large").unwrap();
+    // The large stream's sectors are free now, and the small stream's data
+    // is in use under the new name.
+    let stats = comp.stats().unwrap();
+    assert_eq!(stats.num_free_sectors(), num_free + 10000_u32.div_ceil(512));
+    assert_eq!(stats.num_shared_streams(), 0);
+    let mut comp = reopen(comp);
+    assert_eq!(read_stream(&mut comp, "/large"), data(100, 1));
+    assert!(!comp.exists("/small"));
+}
+
+#[test]
+fn overwrite_keeps_case_of_new_name() {
+    let mut comp = make_file();
+    comp.rename_overwrite("/small", "/LARGE").unwrap();
+    assert_eq!(child_names(&comp, "/"), ["dir", "LARGE"]);
+    comp.create_storage("/x").unwrap();
+    write_stream(&mut comp, "/x/old", b"old");
+    comp.rename_overwrite_all("/dir/sub", "/X").unwrap();
+    assert_eq!(child_names(&comp, "/"), ["X", "dir", "LARGE"]);
+    let mut comp = reopen(comp);
+    assert_eq!(child_names(&comp, "/x"), ["leaf"]);
+    assert_eq!(read_stream(&mut comp, "/x/leaf"), data(50, 4));
+}
+
+#[test]
+fn overwrite_handles_open_streams() {
+    let mut comp = make_file();
+    let mut source = comp.open_stream("/small").unwrap();
+    let mut replaced = comp.open_stream("/large").unwrap();
+    let mut below = comp.open_stream("/dir/sub/leaf").unwrap();
+    comp.create_storage("/other").unwrap();
+    comp.rename_overwrite("/small", "/large").unwrap();
+    comp.rename_overwrite_all("/dir/sub", "/other").unwrap();
+    // Streams for the replaced objects are dead, but a stream inside the
+    // moved storage keeps working.
+    assert!(source.write_all(b"x").and_then(|_| source.flush()).is_err());
+    assert!(replaced.read(&mut [0; 10]).is_err());
+    below.seek(SeekFrom::End(0)).unwrap();
+    below.write_all(b"more").unwrap();
+    drop((source, replaced, below));
+    comp.flush().unwrap();
+    let mut comp = reopen(comp);
+    assert_eq!(read_stream(&mut comp, "/large"), data(100, 1));
+    let mut expected = data(50, 4);
+    expected.extend_from_slice(b"more");
+    assert_eq!(read_stream(&mut comp, "/other/leaf"), expected);
+}
+
+#[test]
+fn overwrite_rejects_replacing_an_ancestor() {
+    let mut comp = make_file();
+    let before = walk_paths(&comp);
+    let error = comp.rename_overwrite_all("/dir/sub", "/dir").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let error =
+        comp.rename_overwrite_all("/dir/sub/leaf", "/dir").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(walk_paths(&comp), before);
+}
+
+#[test]
+fn rename_after_remove_never_duplicates_names() {
+    // Removing an entry frees its directory slot, and creating another
+    // object reuses it; renaming onto a name that's still taken must fail
+    // however the slots have been shuffled.
+    let mut comp = make_file();
+    write_stream(&mut comp, "/a", b"a");
+    write_stream(&mut comp, "/b", b"b");
+    comp.remove_stream("/a").unwrap();
+    write_stream(&mut comp, "/c", b"c");
+    let error = comp.rename("/c", "/b").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    comp.rename("/c", "/a").unwrap();
+    comp.remove_stream("/b").unwrap();
+    write_stream(&mut comp, "/d", b"d");
+    let error = comp.rename("/d", "/A").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    comp.rename_overwrite("/d", "/A").unwrap();
+    comp.flush().unwrap();
+    let mut comp = reopen(comp);
+    let names = child_names(&comp, "/");
+    assert_eq!(names, ["A", "dir", "large", "small"]);
+    assert_eq!(read_stream(&mut comp, "/a"), b"d");
+}
+
+//===========================================================================//
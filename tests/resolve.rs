@@ -0,0 +1,99 @@
+use cfb::{CompoundFile, ObjectNotFound};
+use std::io::{Cursor, ErrorKind, Read, Write};
+
+//===========================================================================//
+
+fn make_comp() -> CompoundFile<Cursor<Vec<u8>>> {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage_all("/foo/bar").unwrap();
+    comp.create_stream("/foo/bar/baz").unwrap().write_all(b"baz").unwrap();
+    comp.create_stream("/foo/qux").unwrap().write_all(b"qux").unwrap();
+    comp
+}
+
+fn read_resolved(
+    comp: &mut CompoundFile<Cursor<Vec<u8>>>,
+    resolved: &cfb::ResolvedPath,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream_resolved(resolved)
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn resolve_and_reopen() {
+    let mut comp = make_comp();
+    let resolved = comp.resolve("/FOO/bar/baz").unwrap();
+    assert_eq!(resolved.path(), "/FOO/bar/baz");
+    assert_eq!(read_resolved(&mut comp, &resolved), b"baz");
+    let entry = comp.entry_resolved(&resolved).unwrap();
+    assert_eq!(entry.path(), "/FOO/bar/baz");
+    assert_eq!(entry.name(), "baz");
+
+    let root = comp.resolve("/").unwrap();
+    assert!(comp.entry_resolved(&root).unwrap().is_root());
+    let storage = comp.resolve("/foo/bar").unwrap();
+    let error = comp.open_stream_resolved(&storage).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn resolve_missing_path_fails() {
+    let comp = make_comp();
+    let error = comp.resolve("/foo/nope").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    assert!(ObjectNotFound::from_io_error(&error).is_some());
+}
+
+#[test]
+fn resolved_path_survives_unrelated_changes() {
+    let mut comp = make_comp();
+    let resolved = comp.resolve("/foo/qux").unwrap();
+    for index in 0..20 {
+        comp.create_stream(format!("/foo/new{}", index)).unwrap();
+    }
+    comp.remove_storage_all("/foo/bar").unwrap();
+    assert_eq!(read_resolved(&mut comp, &resolved), b"qux");
+}
+
+#[test]
+fn rename_falls_back_to_full_resolution() {
+    let mut comp = make_comp();
+    let resolved = comp.resolve("/foo/bar/baz").unwrap();
+    comp.rename("/foo/bar/baz", "/foo/bar/old").unwrap();
+    let error = comp.open_stream_resolved(&resolved).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+
+    // A new object at the old path is found, even though it has a
+    // different stream ID.
+    comp.create_stream("/foo/bar/baz").unwrap().write_all(b"new").unwrap();
+    assert_eq!(read_resolved(&mut comp, &resolved), b"new");
+
+    // Renaming a storage along the path is caught, too.
+    comp.rename("/foo", "/moved").unwrap();
+    let error = comp.entry_resolved(&resolved).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    comp.rename("/moved", "/foo").unwrap();
+    assert_eq!(read_resolved(&mut comp, &resolved), b"new");
+}
+
+#[test]
+fn resolved_path_in_differently_shaped_file() {
+    let mut comp = make_comp();
+    let resolved = comp.resolve("/foo/qux").unwrap();
+    let mut other = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    other.create_storage("/foo").unwrap();
+    other.create_stream("/foo/aaa").unwrap();
+    other.create_stream("/foo/zzz").unwrap();
+    other.create_stream("/foo/qux").unwrap().write_all(b"other").unwrap();
+    assert_eq!(read_resolved(&mut other, &resolved), b"other");
+    other.remove_stream("/foo/qux").unwrap();
+    let error = other.entry_resolved(&resolved).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    assert_eq!(read_resolved(&mut comp, &resolved), b"qux");
+}
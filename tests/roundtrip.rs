@@ -0,0 +1,237 @@
+//! Property-based round-trip tests: random files are built through the public
+//! API, then read back with the independent parser in `minicfb`, and both the
+//! directory tree and the stream contents are compared against a model of
+//! what was written.
+
+use cfb::{CompoundFile, Version};
+use minicfb::Node;
+use rand::prelude::{Rng, SeedableRng, SliceRandom};
+use rand_pcg::Pcg32;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use uuid::Uuid;
+
+mod minicfb;
+
+//===========================================================================//
+
+const NAME_CHARS: &[char] = &[
+    'a', 'b', 'c', 'A', 'B', 'x', 'Y', 'z', '0', '7', '_', ' ', 'é', 'Ω', 'ж',
+];
+
+/// Generates a random valid name that doesn't collide (case-insensitively)
+/// with any existing child of `parent` in the model.
+fn random_name(
+    rng: &mut Pcg32,
+    model: &BTreeMap<String, Node>,
+    parent: &str,
+) -> String {
+    loop {
+        let len = rng.gen_range(1..=31);
+        let name: String =
+            (0..len).map(|_| *NAME_CHARS.choose(rng).unwrap()).collect();
+        let upper = format!("{}/{}", parent, name).to_uppercase();
+        if !model.keys().any(|path| path.to_uppercase() == upper) {
+            return name;
+        }
+    }
+}
+
+fn random_data(rng: &mut Pcg32) -> Vec<u8> {
+    let len = match rng.gen_range(0..10) {
+        0 => 0,
+        1..=4 => rng.gen_range(1..4096),
+        5 => rng.gen_range(4090..4100),
+        _ => rng.gen_range(4096..40_000),
+    };
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+fn storages(model: &BTreeMap<String, Node>) -> Vec<String> {
+    let mut storages = vec![String::new()];
+    for (path, node) in model {
+        if let Node::Storage { .. } = node {
+            storages.push(path.clone());
+        }
+    }
+    storages
+}
+
+fn streams(model: &BTreeMap<String, Node>) -> Vec<String> {
+    model
+        .iter()
+        .filter(|(_, node)| matches!(node, Node::Stream { .. }))
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Applies one random operation both to the compound file and to the model.
+fn random_op<F: Read + Write + Seek>(
+    rng: &mut Pcg32,
+    comp: &mut CompoundFile<F>,
+    model: &mut BTreeMap<String, Node>,
+) {
+    let streams = streams(model);
+    match rng.gen_range(0..10) {
+        0 | 1 => {
+            let parent = storages(model).choose(rng).unwrap().clone();
+            let path =
+                format!("{}/{}", parent, random_name(rng, model, &parent));
+            comp.create_storage(&path).unwrap();
+            model
+                .insert(path, Node::Storage { clsid: [0; 16], state_bits: 0 });
+        }
+        2..=4 => {
+            let parent = storages(model).choose(rng).unwrap().clone();
+            let path =
+                format!("{}/{}", parent, random_name(rng, model, &parent));
+            let data = random_data(rng);
+            comp.create_stream(&path).unwrap().write_all(&data).unwrap();
+            model.insert(path, Node::Stream { data, state_bits: 0 });
+        }
+        5 if !streams.is_empty() => {
+            // Overwrite or append to an existing stream at a random offset.
+            let path = streams.choose(rng).unwrap();
+            let extra = random_data(rng);
+            if let Some(Node::Stream { data, .. }) = model.get_mut(path) {
+                let offset = rng.gen_range(0..=data.len());
+                let mut stream = comp.open_stream(path).unwrap();
+                stream.seek(SeekFrom::Start(offset as u64)).unwrap();
+                stream.write_all(&extra).unwrap();
+                let end = offset + extra.len();
+                if end > data.len() {
+                    data.resize(end, 0);
+                }
+                data[offset..end].copy_from_slice(&extra);
+            }
+        }
+        6 if !streams.is_empty() => {
+            let path = streams.choose(rng).unwrap();
+            let new_len = random_data(rng).len();
+            comp.open_stream(path).unwrap().set_len(new_len as u64).unwrap();
+            if let Some(Node::Stream { data, .. }) = model.get_mut(path) {
+                data.resize(new_len, 0);
+            }
+        }
+        7 if !streams.is_empty() => {
+            let path = streams.choose(rng).unwrap().clone();
+            comp.remove_stream(&path).unwrap();
+            model.remove(&path);
+        }
+        8 => {
+            let storages = storages(model);
+            let path = storages.choose(rng).unwrap().clone();
+            if path.is_empty() {
+                return;
+            }
+            comp.remove_storage_all(&path).unwrap();
+            let prefix = format!("{}/", path);
+            model.retain(|other, _| {
+                *other != path && !other.starts_with(&prefix)
+            });
+        }
+        _ => {
+            let paths: Vec<String> = model.keys().cloned().collect();
+            if let Some(path) = paths.choose(rng) {
+                let bits: u32 = rng.gen();
+                comp.set_state_bits(path, bits).unwrap();
+                match model.get_mut(path).unwrap() {
+                    Node::Storage { clsid, state_bits } => {
+                        *state_bits = bits;
+                        let new_clsid: [u8; 16] = rng.gen();
+                        comp.set_storage_clsid(
+                            path,
+                            Uuid::from_bytes_le(new_clsid),
+                        )
+                        .unwrap();
+                        *clsid = new_clsid;
+                    }
+                    Node::Stream { state_bits, .. } => *state_bits = bits,
+                }
+            }
+        }
+    }
+}
+
+fn check_round_trip(seed: u64, version: Version, num_ops: usize) {
+    let mut rng = Pcg32::seed_from_u64(seed);
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.set_self_check(true);
+    let mut model = BTreeMap::new();
+    for _ in 0..num_ops {
+        random_op(&mut rng, &mut comp, &mut model);
+    }
+    comp.flush().unwrap();
+    let bytes = comp.into_inner().into_inner();
+
+    let parsed = minicfb::parse(&bytes).unwrap_or_else(|error| {
+        panic!("seed {} ({:?}): independent parser: {}", seed, version, error)
+    });
+    let expected_version = match version {
+        Version::V3 => 3,
+        Version::V4 => 4,
+    };
+    assert_eq!(parsed.major_version, expected_version, "seed {}", seed);
+    assert_eq!(parsed.root_clsid, [0; 16], "seed {}", seed);
+    assert_eq!(
+        parsed.nodes.keys().collect::<Vec<_>>(),
+        model.keys().collect::<Vec<_>>(),
+        "seed {}",
+        seed
+    );
+    for (path, node) in &model {
+        assert!(
+            parsed.nodes[path] == *node,
+            "seed {}: {} differs",
+            seed,
+            path
+        );
+    }
+
+    // The crate should of course also agree with the model.
+    let mut comp = CompoundFile::open_strict(Cursor::new(bytes)).unwrap();
+    for (path, node) in &model {
+        let entry = comp.entry(path).unwrap();
+        match node {
+            Node::Storage { clsid, state_bits } => {
+                assert!(entry.is_storage());
+                assert_eq!(entry.clsid(), &Uuid::from_bytes_le(*clsid));
+                assert_eq!(entry.state_bits(), *state_bits);
+            }
+            Node::Stream { data, state_bits } => {
+                assert_eq!(entry.state_bits(), *state_bits);
+                let mut actual = Vec::new();
+                comp.open_stream(path)
+                    .unwrap()
+                    .read_to_end(&mut actual)
+                    .unwrap();
+                assert!(actual == *data, "seed {}: {} differs", seed, path);
+            }
+        }
+    }
+}
+
+//===========================================================================//
+
+#[test]
+fn round_trip_v3() {
+    for seed in 0..40 {
+        check_round_trip(seed, Version::V3, 60);
+    }
+}
+
+#[test]
+fn round_trip_v4() {
+    for seed in 1000..1040 {
+        check_round_trip(seed, Version::V4, 60);
+    }
+}
+
+#[test]
+fn round_trip_empty_files() {
+    check_round_trip(0, Version::V3, 0);
+    check_round_trip(0, Version::V4, 0);
+}
+
+//===========================================================================//
@@ -0,0 +1,287 @@
+use cfb::{CompoundFile, SanitizeOptions, Version};
+use std::io::{Cursor, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const SUMMARY_INFO: &str = "/\u{5}SummaryInformation";
+const DOC_SUMMARY_INFO: &str = "/\u{5}DocumentSummaryInformation";
+const SUMMARY_INFO_FMTID: Uuid =
+    Uuid::from_u128(0xf29f85e0_4ff9_1068_ab91_08002b27b3d9);
+const DOC_SUMMARY_INFO_FMTID: Uuid =
+    Uuid::from_u128(0xd5cdd502_2e9c_101b_9397_08002b2cf9ae);
+const DOC_CLSID: Uuid =
+    Uuid::from_u128(0x00020906_0000_0000_c000_000000000046);
+const CREATE_TIME: u64 = 0x01d5_7e3a_1234_5678;
+
+enum Value<'a> {
+    Str(&'a str),
+    WideStr(&'a str),
+    FileTime(u64),
+}
+
+/// Serializes a property set stream with a single property set.
+fn property_set(fmtid: Uuid, properties: &[(u32, Value)]) -> Vec<u8> {
+    let table_len = 8 + 8 * properties.len();
+    let mut table = Vec::new();
+    let mut values = Vec::new();
+    for (pid, value) in properties.iter() {
+        table.extend_from_slice(&pid.to_le_bytes());
+        table.extend_from_slice(
+            &((table_len + values.len()) as u32).to_le_bytes(),
+        );
+        match value {
+            Value::Str(string) => {
+                values.extend_from_slice(&30u32.to_le_bytes());
+                values.extend_from_slice(
+                    &(string.len() as u32 + 1).to_le_bytes(),
+                );
+                values.extend_from_slice(string.as_bytes());
+                values.push(0);
+            }
+            Value::WideStr(string) => {
+                values.extend_from_slice(&31u32.to_le_bytes());
+                let chars: Vec<u16> = string.encode_utf16().collect();
+                values.extend_from_slice(
+                    &(chars.len() as u32 + 1).to_le_bytes(),
+                );
+                for chr in chars {
+                    values.extend_from_slice(&chr.to_le_bytes());
+                }
+                values.extend_from_slice(&[0, 0]);
+            }
+            Value::FileTime(time) => {
+                values.extend_from_slice(&64u32.to_le_bytes());
+                values.extend_from_slice(&time.to_le_bytes());
+            }
+        }
+        while values.len() % 4 != 0 {
+            values.push(0);
+        }
+    }
+    let mut data = Vec::new();
+    data.extend_from_slice(&0xfffeu16.to_le_bytes());
+    data.extend_from_slice(&[0; 22]);
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&fmtid.to_bytes_le());
+    data.extend_from_slice(&48u32.to_le_bytes());
+    data.extend_from_slice(&((table_len + values.len()) as u32).to_le_bytes());
+    data.extend_from_slice(&(properties.len() as u32).to_le_bytes());
+    data.extend_from_slice(&table);
+    data.extend_from_slice(&values);
+    data
+}
+
+fn utf16(string: &str) -> Vec<u8> {
+    string.encode_utf16().flat_map(|chr| chr.to_le_bytes()).collect()
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+/// The private data planted in the file made by `make_file`, none of which
+/// should survive sanitizing.
+fn secrets() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("author", b"AliceAuthorName".to_vec()),
+        ("last author", utf16("BobEditorName")),
+        ("company", b"SecretCorpName".to_vec()),
+        ("creation time", CREATE_TIME.to_le_bytes().to_vec()),
+        ("removed stream", b"DELETEDSECRET".to_vec()),
+        ("removed mini stream", b"MINISECRET".to_vec()),
+        ("truncated stream", b"SLACKSECRET".to_vec()),
+        ("truncated mini stream", b"MINISLACK".to_vec()),
+    ]
+}
+
+fn repeat(marker: &[u8], len: usize) -> Vec<u8> {
+    marker.iter().copied().cycle().take(len).collect()
+}
+
+/// Creates a compound file containing content streams, property sets
+/// identifying the author, storage timestamps, leftover data from removed
+/// and truncated streams, and a nonzero transaction signature.
+fn make_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_storage("/Doc").unwrap();
+    comp.set_storage_clsid("/Doc", DOC_CLSID).unwrap();
+    comp.create_stream("/Doc/Content")
+        .unwrap()
+        .write_all(&repeat(b"content", 6000))
+        .unwrap();
+    comp.create_stream("/small")
+        .unwrap()
+        .write_all(&repeat(b"small", 100))
+        .unwrap();
+    let summary = property_set(
+        SUMMARY_INFO_FMTID,
+        &[
+            (2, Value::Str("KeepTitle")),
+            (4, Value::Str("AliceAuthorName")),
+            (8, Value::WideStr("BobEditorName")),
+            (12, Value::FileTime(CREATE_TIME)),
+        ],
+    );
+    comp.create_stream(SUMMARY_INFO).unwrap().write_all(&summary).unwrap();
+    let doc_summary = property_set(
+        DOC_SUMMARY_INFO_FMTID,
+        &[(15, Value::Str("SecretCorpName"))],
+    );
+    comp.create_stream(DOC_SUMMARY_INFO)
+        .unwrap()
+        .write_all(&doc_summary)
+        .unwrap();
+
+    {
+        let mut stream = comp.create_stream("/Trunc").unwrap();
+        stream.write_all(&repeat(b"kept", 5000)).unwrap();
+        stream.write_all(&repeat(b"SLACKSECRET", 1000)).unwrap();
+        stream.set_len(5000).unwrap();
+    }
+    {
+        let mut stream = comp.create_stream("/minitrunc").unwrap();
+        stream.write_all(&repeat(b"kept", 100)).unwrap();
+        stream.write_all(&repeat(b"MINISLACK", 100)).unwrap();
+        stream.set_len(100).unwrap();
+    }
+
+    comp.create_stream("/Deleted")
+        .unwrap()
+        .write_all(&repeat(b"DELETEDSECRET", 5000))
+        .unwrap();
+    comp.remove_stream("/Deleted").unwrap();
+    comp.create_stream("/gone")
+        .unwrap()
+        .write_all(&repeat(b"MINISECRET", 200))
+        .unwrap();
+    comp.remove_stream("/gone").unwrap();
+
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    comp.set_modified_time("/Doc", time).unwrap();
+    comp.set_modified_time("/", time).unwrap();
+    comp.flush().unwrap();
+    let mut data = comp.into_inner().into_inner();
+    data[52..56].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+    data
+}
+
+/// Returns the contents of every stream other than the property sets.
+fn content_streams(comp: &mut CompoundFile<Cursor<Vec<u8>>>) -> Vec<Vec<u8>> {
+    let paths: Vec<PathBuf> = comp
+        .walk()
+        .filter(|entry| {
+            entry.is_stream() && !entry.name().starts_with('\u{5}')
+        })
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    assert_eq!(paths.len(), 4);
+    paths
+        .iter()
+        .map(|path| {
+            let mut data = Vec::new();
+            comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+            data
+        })
+        .collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn sanitize_strips_private_data() {
+    let data = make_file();
+    for (what, secret) in secrets() {
+        assert!(contains(&data, &secret), "no {} before sanitizing", what);
+    }
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let contents = content_streams(&mut comp);
+
+    let report = comp.sanitize(SanitizeOptions::new()).unwrap();
+    assert_eq!(report.num_properties_scrubbed(), 4);
+    assert!(report.removed_property_sets().is_empty());
+    assert_eq!(report.num_times_cleared(), 2);
+    assert_eq!(report.num_clsids_cleared(), 0);
+    assert!(report.num_bytes_wiped() > 0);
+    assert!(report.header_reset());
+
+    let data = comp.into_inner().into_inner();
+    for (what, secret) in secrets() {
+        assert!(!contains(&data, &secret), "{} survived sanitizing", what);
+    }
+    assert_eq!(data[52..56], [0, 0, 0, 0]);
+    assert!(contains(&data, b"KeepTitle"));
+
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert_eq!(content_streams(&mut comp), contents);
+    let zero = comp.entry("/Doc").unwrap().created();
+    for entry in comp.walk() {
+        assert_eq!(entry.created(), zero);
+        assert_eq!(entry.modified(), zero);
+    }
+    assert_eq!(*comp.entry("/Doc").unwrap().clsid(), DOC_CLSID);
+
+    // Sanitizing again finds nothing more to change.
+    let report = comp.sanitize(SanitizeOptions::new()).unwrap();
+    assert_eq!(report.num_properties_scrubbed(), 0);
+    assert_eq!(report.num_times_cleared(), 0);
+    assert!(!report.header_reset());
+}
+
+#[test]
+fn sanitize_keeps_what_options_ask_for() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let contents = content_streams(&mut comp);
+    let options = SanitizeOptions::new()
+        .keep_times(true)
+        .keep_properties(true)
+        .clear_clsids(true);
+    let report = comp.sanitize(options).unwrap();
+    assert_eq!(report.num_properties_scrubbed(), 0);
+    assert_eq!(report.num_times_cleared(), 0);
+    assert_eq!(report.num_clsids_cleared(), 1);
+
+    let data = comp.into_inner().into_inner();
+    assert!(contains(&data, b"AliceAuthorName"));
+    assert!(contains(&data, &CREATE_TIME.to_le_bytes()));
+    assert!(!contains(&data, b"DELETEDSECRET"));
+    assert!(!contains(&data, b"SLACKSECRET"));
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert_eq!(content_streams(&mut comp), contents);
+    assert!(comp.entry("/Doc").unwrap().clsid().is_nil());
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    assert_eq!(comp.entry("/Doc").unwrap().modified(), time);
+}
+
+#[test]
+fn sanitize_keeping_free_space() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let options = SanitizeOptions::new().keep_free_space(true);
+    let report = comp.sanitize(options).unwrap();
+    assert_eq!(report.num_bytes_wiped(), 0);
+    let data = comp.into_inner().into_inner();
+    assert!(contains(&data, b"DELETEDSECRET"));
+    assert!(!contains(&data, b"AliceAuthorName"));
+}
+
+#[test]
+fn sanitize_removes_unparseable_property_sets() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_storage("/Obj").unwrap();
+    let path = "/Obj/\u{5}SummaryInformation";
+    comp.create_stream(path).unwrap().write_all(b"AliceAuthorName").unwrap();
+    let report = comp.sanitize(SanitizeOptions::new()).unwrap();
+    assert_eq!(report.removed_property_sets(), &[PathBuf::from(path)]);
+    assert!(!comp.exists(path));
+    let data = comp.into_inner().into_inner();
+    assert!(!contains(&data, b"AliceAuthorName"));
+    CompoundFile::open_strict(Cursor::new(data)).unwrap();
+}
+
+//===========================================================================//
@@ -0,0 +1,235 @@
+//! Tests for scanning a local directory of compound files.
+
+use cfb::{scan_dir, CompoundFile, ScanOptions, ScanOutcome, ScanResult};
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+//===========================================================================//
+
+/// A scratch directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "cfb-scan-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Returns a compound file with two identical streams and one other stream
+/// of the given length.
+fn compound_file(big_len: usize) -> Vec<u8> {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/dir").unwrap();
+    comp.create_stream("/dir/a").unwrap().write_all(b"same").unwrap();
+    comp.create_stream("/b").unwrap().write_all(b"same").unwrap();
+    comp.create_stream("/big").unwrap().write_all(&vec![1; big_len]).unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Fills a directory with good, corrupt, and non-compound files:
+///
+/// ```text
+/// big.msi          a good compound file of about 100 KB
+/// corrupt.msi      a compound file cut off just after its header
+/// empty.doc        an empty file
+/// good.msi         a good compound file
+/// notes.txt        a text file
+/// sub/nested.doc   a good compound file
+/// ```
+fn make_dir(name: &str) -> TempDir {
+    let dir = TempDir::new(name);
+    let good = compound_file(10);
+    fs::write(dir.path().join("big.msi"), compound_file(100_000)).unwrap();
+    fs::write(dir.path().join("corrupt.msi"), &good[..600]).unwrap();
+    fs::write(dir.path().join("empty.doc"), b"").unwrap();
+    fs::write(dir.path().join("good.msi"), &good).unwrap();
+    fs::write(dir.path().join("notes.txt"), b"not a compound file").unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub/nested.doc"), &good).unwrap();
+    dir
+}
+
+fn names(dir: &TempDir, results: &[ScanResult]) -> Vec<String> {
+    results
+        .iter()
+        .map(|result| {
+            let relative = result.path().strip_prefix(dir.path()).unwrap();
+            relative.to_string_lossy().replace('\\', "/")
+        })
+        .collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn one_bad_file_does_not_stop_the_scan() {
+    let dir = make_dir("isolation");
+    let results: Vec<ScanResult> =
+        scan_dir(dir.path(), ScanOptions::new()).collect();
+    assert_eq!(
+        names(&dir, &results),
+        ["big.msi", "corrupt.msi", "empty.doc", "good.msi", "notes.txt"]
+    );
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1].outcome(), ScanOutcome::Failed(_)));
+    assert!(matches!(results[2].outcome(), ScanOutcome::NotCompoundFile));
+    assert!(results[3].is_ok());
+    assert!(matches!(results[4].outcome(), ScanOutcome::NotCompoundFile));
+
+    let paths: Vec<&Path> = results[3]
+        .manifest()
+        .iter()
+        .map(|entry| entry.entry().path())
+        .collect();
+    assert_eq!(paths, ["/", "/b", "/big", "/dir", "/dir/a"].map(Path::new));
+    assert!(results[3].warnings().is_empty());
+    assert!(results[3].manifest().iter().all(|entry| entry.hash().is_none()));
+}
+
+#[test]
+fn recursive_scan_with_filter_and_hashes() {
+    let dir = make_dir("recursive");
+    let options = ScanOptions::new()
+        .recursive(true)
+        .file_name("*.doc")
+        .hash_streams(true);
+    let results: Vec<ScanResult> = scan_dir(dir.path(), options).collect();
+    assert_eq!(names(&dir, &results), ["empty.doc", "sub/nested.doc"]);
+    let manifest = results[1].manifest();
+    let hash = |path: &str| {
+        let entry = manifest
+            .iter()
+            .find(|entry| entry.entry().path() == Path::new(path))
+            .unwrap();
+        entry.hash()
+    };
+    assert_eq!(hash("/"), None);
+    assert_eq!(hash("/dir"), None);
+    assert!(hash("/b").is_some());
+    assert_eq!(hash("/b"), hash("/dir/a"));
+    assert_ne!(hash("/b"), hash("/big"));
+}
+
+#[test]
+fn size_and_time_limits() {
+    let dir = make_dir("limits");
+    let options = ScanOptions::new().file_name("*.msi").max_file_size(50_000);
+    let results: Vec<ScanResult> = scan_dir(dir.path(), options).collect();
+    assert_eq!(names(&dir, &results), ["big.msi", "corrupt.msi", "good.msi"]);
+    assert!(matches!(results[0].outcome(), ScanOutcome::TooLarge(len)
+                     if *len > 100_000));
+    assert!(results[2].is_ok());
+
+    let options = ScanOptions::new()
+        .file_name("good.msi")
+        .hash_streams(true)
+        .timeout(Duration::ZERO);
+    let results: Vec<ScanResult> = scan_dir(dir.path(), options).collect();
+    assert_eq!(results.len(), 1);
+    assert!(matches!(results[0].outcome(), ScanOutcome::TimedOut));
+}
+
+#[test]
+fn cancelling_stops_the_scan() {
+    let dir = make_dir("cancel");
+    let flag = Arc::new(AtomicBool::new(false));
+    let options = ScanOptions::new().cancel_flag(flag.clone());
+    let mut scan = scan_dir(dir.path(), options);
+    assert!(scan.next().unwrap().is_ok());
+    flag.store(true, Ordering::Relaxed);
+    assert!(scan.next().is_none());
+    assert!(scan.next().is_none());
+}
+
+#[test]
+fn parallel_scan_matches_serial_scan() {
+    let dir = make_dir("parallel");
+    for index in 0..20 {
+        let data = compound_file(index * 1000);
+        fs::write(dir.path().join(format!("sub/more{:02}.doc", index)), data)
+            .unwrap();
+    }
+    let summarize = |results: Vec<ScanResult>| {
+        let names = names(&dir, &results);
+        let outcomes: Vec<String> = results
+            .iter()
+            .map(|result| match result.outcome() {
+                ScanOutcome::Failed(_) => "Failed".to_string(),
+                outcome => format!("{:?}", outcome),
+            })
+            .collect();
+        let hashes: Vec<Vec<Option<u64>>> = results
+            .iter()
+            .map(|result| {
+                result.manifest().iter().map(|entry| entry.hash()).collect()
+            })
+            .collect();
+        (names, outcomes, hashes)
+    };
+    let options = ScanOptions::new().recursive(true).hash_streams(true);
+    let serial = summarize(scan_dir(dir.path(), options.clone()).collect());
+    assert_eq!(serial.0.len(), 26);
+    for threads in [0, 1, 2, 3, 8] {
+        let options = options.clone().threads(threads);
+        let parallel = summarize(scan_dir(dir.path(), options).collect());
+        assert_eq!(parallel, serial, "threads = {}", threads);
+    }
+}
+
+#[test]
+fn unreadable_directory_is_reported() {
+    let dir = make_dir("missing");
+    let missing = dir.path().join("no-such-dir");
+    let results: Vec<ScanResult> =
+        scan_dir(&missing, ScanOptions::new()).collect();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].path(), missing);
+    assert!(matches!(results[0].outcome(), ScanOutcome::Failed(_)));
+}
+
+#[test]
+fn finished_scan_stays_finished() {
+    let dir = make_dir("fused");
+    let options = ScanOptions::new().recursive(true);
+    let mut scan = scan_dir(dir.path(), options);
+    // Nothing is known until the top directory has been listed.
+    assert_eq!(scan.size_hint(), (0, None));
+    let mut count = 0;
+    while let Some(result) = scan.next() {
+        count += 1;
+        let (_, upper) = scan.size_hint();
+        assert!(
+            upper.map_or(true, |upper| upper <= 6 - count),
+            "{:?}",
+            result
+        );
+    }
+    assert_eq!(count, 6);
+    for _ in 0..3 {
+        assert_eq!(scan.size_hint(), (0, Some(0)));
+        assert!(scan.next().is_none());
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,115 @@
+use cfb::{CompoundFile, SanitizeOptions, Version};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+//===========================================================================//
+
+fn read_stream<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Exercises every kind of mutation, with self-checking enabled throughout.
+fn exercise(version: Version) {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.set_self_check(true);
+    comp.create_storage_all("/a/b/c").unwrap();
+    comp.create_storage("/d").unwrap();
+    comp.set_storage_clsid("/d", uuid::Uuid::from_u128(7)).unwrap();
+    for index in 0..40 {
+        let path = format!("/a/b/s{}", index);
+        let len = 100 * index * index;
+        comp.create_stream(&path).unwrap().write_all(&vec![1; len]).unwrap();
+    }
+    // Grow a stream out of the mini stream, then shrink it back in.
+    let mut stream = comp.open_stream("/a/b/s3").unwrap();
+    stream.set_len(6000).unwrap();
+    stream.seek(SeekFrom::Start(5900)).unwrap();
+    stream.write_all(&[2; 100]).unwrap();
+    stream.set_len(50).unwrap();
+    drop(stream);
+    comp.create_stream_dedup("/d/x", &[3; 5000]).unwrap();
+    comp.create_stream_dedup("/d/y", &[3; 5000]).unwrap();
+    comp.open_stream("/d/y").unwrap().write_all(b"unshared").unwrap();
+    comp.replace_stream("/a/b/s10", &mut &[4u8; 300][..]).unwrap();
+    comp.set_digital_signature(Some(b"signature")).unwrap();
+    comp.set_state_bits("/a", 5).unwrap();
+    comp.touch("/d").unwrap();
+    for index in (0..40).step_by(3) {
+        comp.remove_stream(format!("/a/b/s{}", index)).unwrap();
+    }
+    comp.remove_storage("/a/b/c").unwrap();
+    comp.flush().unwrap();
+    comp.sanitize(SanitizeOptions::new()).unwrap();
+    comp.remove_storage_all("/a").unwrap();
+    comp.shrink_directory().unwrap();
+    let len = comp.shrink_to_fit().unwrap();
+    assert_eq!(read_stream(&mut comp, "/d/x"), vec![3; 5000]);
+
+    let mut data = comp.into_inner().into_inner();
+    data.truncate(len as usize);
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    comp.set_self_check(true);
+    assert_eq!(&read_stream(&mut comp, "/d/y")[..8], b"unshared");
+    comp.remove_storage_all("/").unwrap();
+    comp.flush().unwrap();
+}
+
+//===========================================================================//
+
+#[test]
+fn consistent_operations_pass_v3() {
+    exercise(Version::V3);
+}
+
+#[test]
+fn consistent_operations_pass_v4() {
+    exercise(Version::V4);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(
+    expected = "Self-check failed after enabling self-checks: 1 sectors are \
+                allocated but not in any chain"
+)]
+fn inconsistent_file_fails_when_enabled() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_stream("/old").unwrap().write_all(&[1; 5000]).unwrap();
+    comp.create_stream("/new").unwrap().write_all(&[2; 5000]).unwrap();
+    comp.remove_stream("/old").unwrap();
+    comp.flush().unwrap();
+    let mut data = comp.into_inner().into_inner();
+    // Mark one of the sectors freed by removing the old stream as allocated,
+    // leaking it.
+    let fat_sector =
+        u32::from_le_bytes([data[76], data[77], data[78], data[79]]);
+    let fat_offset = 512 * (fat_sector as usize + 1);
+    let num_sectors = data.len() / 512 - 1;
+    let offset = (0..num_sectors)
+        .map(|sector| fat_offset + 4 * sector)
+        .find(|&offset| data[offset..offset + 4] == [0xff; 4])
+        .unwrap();
+    data[offset..offset + 4].copy_from_slice(&0xfffffffeu32.to_le_bytes());
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    comp.set_self_check(true);
+}
+
+#[test]
+fn disabling_stops_checks() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.set_self_check(true);
+    comp.create_stream("/foo").unwrap().write_all(b"foo").unwrap();
+    comp.set_self_check(false);
+    comp.create_stream("/bar").unwrap().write_all(b"bar").unwrap();
+    assert_eq!(read_stream(&mut comp, "/foo"), b"foo");
+}
+
+//===========================================================================//
@@ -98,3 +98,35 @@ fn resize_huge_to_large() {
 }
 
 //===========================================================================//
+
+fn test_shrink_then_grow(initial_len: usize, shrunk_len: usize) {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/stale").unwrap().write_all(&[0xff; 1000]).unwrap();
+    comp.remove_stream("/stale").unwrap();
+    let data = create_data(initial_len);
+    let mut stream = comp.create_stream("/foobar").unwrap();
+    stream.write_all(&data).unwrap();
+    stream.set_len(shrunk_len as u64).unwrap();
+    stream.set_len(initial_len as u64).unwrap();
+    // Neither the old contents nor the freed stream should show through.
+    let mut stream = comp.open_stream("/foobar").unwrap();
+    let mut actual_data = Vec::new();
+    stream.read_to_end(&mut actual_data).unwrap();
+    assert_eq!(actual_data[..shrunk_len], data[..shrunk_len]);
+    assert_eq!(actual_data[shrunk_len..], vec![0u8; initial_len - shrunk_len]);
+}
+
+#[test]
+fn regrow_small_stream_is_zero_filled() {
+    test_shrink_then_grow(2000, 100);
+    test_shrink_then_grow(2000, 0);
+}
+
+#[test]
+fn regrow_large_stream_is_zero_filled() {
+    test_shrink_then_grow(10000, 5000);
+}
+
+//===========================================================================//
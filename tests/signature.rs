@@ -0,0 +1,121 @@
+//! Tests for enumerating the content covered by an Authenticode signature.
+
+use cfb::CompoundFile;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+//===========================================================================//
+
+const ROOT_CLSID: Uuid =
+    Uuid::from_u128(0x000c1084_0000_0000_c000_000000000046);
+const STORAGE_CLSID: Uuid =
+    Uuid::from_u128(0x01234567_89ab_cdef_0123_456789abcdef);
+
+/// Creates a file whose objects hash in a different order than compound
+/// files sort them in, along with signature streams that must be skipped.
+fn make_file() -> CompoundFile<Cursor<Vec<u8>>> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.set_storage_clsid("/", ROOT_CLSID).unwrap();
+    comp.create_stream("/b").unwrap().write_all(b"<b>").unwrap();
+    comp.create_stream("/A").unwrap().write_all(b"<A>").unwrap();
+    comp.create_storage("/AA").unwrap();
+    comp.set_storage_clsid("/AA", STORAGE_CLSID).unwrap();
+    comp.create_stream("/AA/z").unwrap().write_all(b"<z>").unwrap();
+    comp.create_stream("/AA/\u{4100}").unwrap().write_all(b"<4100>").unwrap();
+    // Only the signature streams in the root storage are excluded.
+    comp.create_stream("/AA/\u{5}DigitalSignature")
+        .unwrap()
+        .write_all(b"<nested>")
+        .unwrap();
+    comp.create_storage("/AA/empty").unwrap();
+    comp.set_digital_signature(Some(b"SIGNATURE")).unwrap();
+    comp.set_msi_digital_signature_ex(Some(b"EXTENDED")).unwrap();
+    comp
+}
+
+fn signature_content(
+    comp: &mut CompoundFile<Cursor<Vec<u8>>>,
+) -> (Vec<PathBuf>, Vec<u8>) {
+    let mut paths = Vec::new();
+    let mut data = Vec::new();
+    let items: Vec<_> = comp.signature_content_iter().collect();
+    for item in items {
+        let (path, mut content) = item.unwrap();
+        let start = data.len();
+        content.read_to_end(&mut data).unwrap();
+        assert_eq!(content.is_stream(), comp.is_stream(&path));
+        if !content.is_stream() {
+            assert_eq!(data.len() - start, 16);
+        }
+        paths.push(path);
+    }
+    (paths, data)
+}
+
+//===========================================================================//
+
+#[test]
+fn signature_content_order() {
+    let mut comp = make_file();
+    let (paths, data) = signature_content(&mut comp);
+    assert_eq!(
+        paths,
+        [
+            "/A",
+            "/AA/\u{4100}",
+            "/AA/\u{5}DigitalSignature",
+            "/AA/empty",
+            "/AA/z",
+            "/AA",
+            "/b",
+            "/",
+        ]
+        .map(PathBuf::from)
+    );
+    let mut expected = Vec::new();
+    expected.extend_from_slice(b"<A>");
+    expected.extend_from_slice(b"<4100>");
+    expected.extend_from_slice(b"<nested>");
+    expected.extend_from_slice(&[0; 16]);
+    expected.extend_from_slice(b"<z>");
+    expected.extend_from_slice(&STORAGE_CLSID.to_bytes_le());
+    expected.extend_from_slice(b"<b>");
+    expected.extend_from_slice(&ROOT_CLSID.to_bytes_le());
+    assert_eq!(data, expected);
+
+    // Re-signing doesn't change the signed content.
+    comp.set_digital_signature(Some(b"OTHER SIGNATURE")).unwrap();
+    comp.set_msi_digital_signature_ex(None).unwrap();
+    assert_eq!(signature_content(&mut comp).1, expected);
+}
+
+#[test]
+fn read_and_write_signature_streams() {
+    let mut comp = make_file();
+    assert_eq!(comp.digital_signature().unwrap().unwrap(), b"SIGNATURE");
+    assert_eq!(comp.msi_digital_signature_ex().unwrap().unwrap(), b"EXTENDED");
+
+    comp.set_digital_signature(Some(b"NEW")).unwrap();
+    comp.set_msi_digital_signature_ex(None).unwrap();
+    assert_eq!(comp.digital_signature().unwrap().unwrap(), b"NEW");
+    assert_eq!(comp.msi_digital_signature_ex().unwrap(), None);
+    assert!(!comp.exists(Path::new("/\u{5}MsiDigitalSignatureEx")));
+
+    comp.set_digital_signature(None).unwrap();
+    comp.set_digital_signature(None).unwrap();
+    assert_eq!(comp.digital_signature().unwrap(), None);
+    assert!(comp.is_stream("/AA/\u{5}DigitalSignature"));
+}
+
+#[test]
+fn unsigned_empty_file() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    assert_eq!(comp.digital_signature().unwrap(), None);
+    let (paths, data) = signature_content(&mut comp);
+    assert_eq!(paths, [PathBuf::from("/")]);
+    assert_eq!(data, [0; 16]);
+}
+
+//===========================================================================//
@@ -0,0 +1,322 @@
+use cfb::{CompoundFile, CreateOptions, FileTooLarge, Version};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+//===========================================================================//
+
+const CHUNK_LEN: usize = 4096;
+
+/// The contents of an in-memory file that only stores the chunks that have
+/// had nonzero data written to them, so that a file of nearly 2 GiB that is
+/// mostly zeros can be simulated cheaply.
+#[derive(Default)]
+struct SparseData {
+    chunks: BTreeMap<u64, Vec<u8>>,
+    len: u64,
+    position: u64,
+}
+
+impl Read for SparseData {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        let chunk_index = self.position / CHUNK_LEN as u64;
+        let offset = (self.position % CHUNK_LEN as u64) as usize;
+        let num_bytes =
+            buf.len().min(CHUNK_LEN - offset).min(remaining as usize);
+        match self.chunks.get(&chunk_index) {
+            Some(chunk) => {
+                buf[..num_bytes]
+                    .copy_from_slice(&chunk[offset..offset + num_bytes]);
+            }
+            None => buf[..num_bytes].fill(0),
+        }
+        self.position += num_bytes as u64;
+        Ok(num_bytes)
+    }
+}
+
+impl Write for SparseData {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk_index = self.position / CHUNK_LEN as u64;
+        let offset = (self.position % CHUNK_LEN as u64) as usize;
+        let num_bytes = buf.len().min(CHUNK_LEN - offset);
+        let data = &buf[..num_bytes];
+        if let Some(chunk) = self.chunks.get_mut(&chunk_index) {
+            chunk[offset..offset + num_bytes].copy_from_slice(data);
+        } else if data.iter().any(|&byte| byte != 0) {
+            let mut chunk = vec![0; CHUNK_LEN];
+            chunk[offset..offset + num_bytes].copy_from_slice(data);
+            self.chunks.insert(chunk_index, chunk);
+        }
+        self.position += num_bytes as u64;
+        self.len = self.len.max(self.position);
+        Ok(num_bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SparseData {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(delta) => (self.len as i64 + delta) as u64,
+            SeekFrom::Current(delta) => (self.position as i64 + delta) as u64,
+        };
+        Ok(self.position)
+    }
+}
+
+/// A handle to a `SparseData` that can be shared with a `CompoundFile`, so
+/// that the length of the file can be checked without reopening it (which,
+/// for a file this large, is slow).
+#[derive(Clone, Default)]
+struct SparseFile(Arc<Mutex<SparseData>>);
+
+impl SparseFile {
+    fn len(&self) -> u64 {
+        self.0.lock().unwrap().len
+    }
+}
+
+impl fmt::Debug for SparseFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SparseFile({} bytes)", self.len())
+    }
+}
+
+impl Read for SparseFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for SparseFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SparseFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.lock().unwrap().seek(pos)
+    }
+}
+
+//===========================================================================//
+
+const MAX_V3_SECTORS: u32 = 4194303;
+const END_OF_CHAIN: u32 = 0xfffffffe;
+const FREE_SECTOR: u32 = 0xffffffff;
+const LAST_SECTOR_MARKER: &[u8] = b"last sector";
+
+fn put_u32s(file: &mut SparseFile, offset: u64, values: &[u32]) {
+    let bytes: Vec<u8> =
+        values.iter().flat_map(|value| value.to_le_bytes()).collect();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(&bytes).unwrap();
+}
+
+fn sector_offset(sector_id: u32) -> u64 {
+    (sector_id as u64 + 1) * 512
+}
+
+/// Builds a V3 file with `num_sectors` sectors: `num_fat_sectors` FAT
+/// sectors, then as many DIFAT sectors as those need, then one directory
+/// sector, then the data of a single stream, "/big", filling the rest of the
+/// file.  The last sector of "/big" starts with `LAST_SECTOR_MARKER`; the
+/// rest of its data is zeros.
+fn make_near_limit_file(num_sectors: u32, num_fat_sectors: u32) -> SparseFile {
+    let num_difat_sectors = num_fat_sectors.saturating_sub(109).div_ceil(127);
+    let dir_sector = num_fat_sectors + num_difat_sectors;
+    let first_data_sector = dir_sector + 1;
+    assert!(num_fat_sectors as u64 * 128 >= num_sectors as u64);
+    let mut file = SparseFile::default();
+
+    // Header:
+    let mut header = Vec::new();
+    header
+        .extend_from_slice(&[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1]);
+    header.extend_from_slice(&[0; 16]); // CLSID
+    header.extend_from_slice(&[0x3e, 0, 3, 0, 0xfe, 0xff, 9, 0, 6, 0]);
+    header.extend_from_slice(&[0; 6]); // reserved
+    file.write_all(&header).unwrap();
+    let difat_start =
+        if num_difat_sectors > 0 { num_fat_sectors } else { END_OF_CHAIN };
+    put_u32s(
+        &mut file,
+        40,
+        &[
+            0, // number of directory sectors (always zero in V3)
+            num_fat_sectors,
+            dir_sector,
+            0,    // transaction signature
+            4096, // mini stream cutoff
+            END_OF_CHAIN,
+            0,
+            difat_start,
+            num_difat_sectors,
+        ],
+    );
+    let mut difat: Vec<u32> = (0..num_fat_sectors).collect();
+    difat.resize(109 + num_difat_sectors as usize * 127, FREE_SECTOR);
+    put_u32s(&mut file, 76, &difat[..109]);
+
+    // FAT:
+    let mut fat = vec![0xfffffffd; num_fat_sectors as usize];
+    fat.resize(dir_sector as usize, 0xfffffffc);
+    fat.push(END_OF_CHAIN);
+    fat.extend(first_data_sector + 1..num_sectors);
+    fat.push(END_OF_CHAIN);
+    fat.resize(num_fat_sectors as usize * 128, FREE_SECTOR);
+    put_u32s(&mut file, sector_offset(0), &fat);
+
+    // DIFAT sectors:
+    for index in 0..num_difat_sectors {
+        let start = 109 + index as usize * 127;
+        let mut sector = difat[start..start + 127].to_vec();
+        let next = if index + 1 < num_difat_sectors {
+            num_fat_sectors + index + 1
+        } else {
+            END_OF_CHAIN
+        };
+        sector.push(next);
+        put_u32s(&mut file, sector_offset(num_fat_sectors + index), &sector);
+    }
+
+    // Directory:
+    let big_len = (num_sectors - first_data_sector) as u64 * 512;
+    let mut dir = Vec::new();
+    for (name, obj_type, child, start, len) in [
+        ("Root Entry", 5u8, 1u32, END_OF_CHAIN, 0u64),
+        ("big", 2, 0xffffffff, first_data_sector, big_len),
+    ] {
+        let mut entry = [0u8; 128];
+        let name: Vec<u16> = name.encode_utf16().collect();
+        for (index, unit) in name.iter().enumerate() {
+            entry[2 * index..2 * index + 2]
+                .copy_from_slice(&unit.to_le_bytes());
+        }
+        let name_len = (2 * name.len() + 2) as u16;
+        entry[64..66].copy_from_slice(&name_len.to_le_bytes());
+        entry[66] = obj_type;
+        entry[67] = 1; // black
+        entry[68..72].copy_from_slice(&0xffffffffu32.to_le_bytes());
+        entry[72..76].copy_from_slice(&0xffffffffu32.to_le_bytes());
+        entry[76..80].copy_from_slice(&child.to_le_bytes());
+        entry[116..120].copy_from_slice(&start.to_le_bytes());
+        entry[120..128].copy_from_slice(&len.to_le_bytes());
+        dir.extend_from_slice(&entry);
+    }
+    dir.resize(512, 0);
+    file.seek(SeekFrom::Start(sector_offset(dir_sector))).unwrap();
+    file.write_all(&dir).unwrap();
+
+    // The data is all zeros, except for a marker in the last sector, which
+    // also gives the file its full length.
+    let mut last_sector = vec![0; 512];
+    last_sector[..LAST_SECTOR_MARKER.len()]
+        .copy_from_slice(LAST_SECTOR_MARKER);
+    file.seek(SeekFrom::Start(sector_offset(num_sectors - 1))).unwrap();
+    file.write_all(&last_sector).unwrap();
+    assert_eq!(file.len(), sector_offset(num_sectors));
+    file
+}
+
+fn expect_too_large(error: io::Error) {
+    let too_large = FileTooLarge::from_io_error(&error)
+        .unwrap_or_else(|| panic!("not FileTooLarge: {}", error));
+    assert_eq!(too_large.version(), Version::V3);
+    assert_eq!(too_large.max_len(), 1 << 31);
+    assert!(too_large.required() > 1 << 31);
+    assert!(error.to_string().contains("Version::V4"), "{}", error);
+}
+
+fn read_tail<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut stream = comp.open_stream(path).unwrap();
+    stream.seek(SeekFrom::End(-512)).unwrap();
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn grow_to_exactly_limit_then_refuse() {
+    let file = make_near_limit_file(MAX_V3_SECTORS - 8, 32768);
+    let mut comp = CompoundFile::open(file.clone()).unwrap();
+    let big_len = comp.entry("/big").unwrap().len();
+    assert_eq!(big_len, (MAX_V3_SECTORS as u64 - 8 - 32768 - 258 - 1) * 512);
+    assert!(big_len > 2_000_000_000);
+
+    // Filling the last eight sectors exactly is fine.
+    comp.create_stream("/tail").unwrap().write_all(&[7; 4096]).unwrap();
+    comp.flush().unwrap();
+    assert_eq!(file.len(), 1 << 31);
+
+    // But not a byte more.
+    let mut stream = comp.open_stream("/tail").unwrap();
+    stream.seek(SeekFrom::End(0)).unwrap();
+    stream.write_all(&[8]).unwrap();
+    expect_too_large(stream.flush().unwrap_err());
+    drop(stream);
+    assert_eq!(comp.entry("/tail").unwrap().len(), 4096);
+
+    // The directory sector has room for one more entry, but adding another
+    // directory sector would cross the limit.
+    comp.create_storage("/full").unwrap();
+    expect_too_large(comp.create_storage("/over").unwrap_err());
+    assert!(!comp.exists("/over"));
+    comp.flush().unwrap();
+    assert_eq!(file.len(), 1 << 31);
+
+    // Everything written before the limit was reached reads back.
+    drop(comp);
+    let mut comp = CompoundFile::open_strict(file).unwrap();
+    assert!(comp.open_warnings().is_empty());
+    assert!(comp.is_storage("/full"));
+    assert_eq!(read_tail(&mut comp, "/tail"), vec![7; 512]);
+    assert_eq!(&read_tail(&mut comp, "/big")[..11], LAST_SECTOR_MARKER);
+}
+
+#[test]
+fn fat_growth_at_limit() {
+    // The FAT is exactly full, so the next sector needs a new FAT sector,
+    // which leaves room for only 126 more data sectors.
+    let file = make_near_limit_file(MAX_V3_SECTORS - 127, 32767);
+    let mut comp = CompoundFile::open(file.clone()).unwrap();
+    comp.create_stream("/tail").unwrap().write_all(&[7; 126 * 512]).unwrap();
+    comp.flush().unwrap();
+    assert_eq!(file.len(), 1 << 31);
+    let mut stream = comp.open_stream("/tail").unwrap();
+    stream.seek(SeekFrom::End(0)).unwrap();
+    stream.write_all(&[8]).unwrap();
+    expect_too_large(stream.flush().unwrap_err());
+    drop(stream);
+    comp.flush().unwrap();
+    assert_eq!(file.len(), 1 << 31);
+}
+
+#[test]
+fn capacity_hints_past_limit() {
+    let options = CreateOptions::new()
+        .version(Version::V3)
+        .expected_total_bytes(3 << 30);
+    let result =
+        CompoundFile::create_with_options(options, SparseFile::default());
+    expect_too_large(result.unwrap_err());
+}
+
+//===========================================================================//
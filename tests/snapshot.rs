@@ -0,0 +1,287 @@
+use cfb::{
+    CompoundFile, EntryKind, ExtraEntries, MetadataSnapshot, MissingEntries,
+    RestorePolicy, Version,
+};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::Hasher;
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+fn time(secs: u64) -> std::time::SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn make_file() -> Comp {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.set_storage_clsid("/", Uuid::from_u128(1)).unwrap();
+    comp.create_storage("/docs").unwrap();
+    comp.set_storage_clsid("/docs", Uuid::from_u128(2)).unwrap();
+    comp.set_state_bits("/docs", 0x20).unwrap();
+    comp.set_created_time("/docs", time(1_000_000)).unwrap();
+    comp.set_modified_time("/docs", time(2_000_000)).unwrap();
+    comp.create_storage("/docs/old").unwrap();
+    comp.set_modified_time("/docs/old", time(3_000_000)).unwrap();
+    comp.create_stream("/docs/old/notes")
+        .unwrap()
+        .write_all(&[5; 300])
+        .unwrap();
+    comp.create_stream("/docs/report").unwrap().write_all(&[7; 9000]).unwrap();
+    comp.set_state_bits("/docs/report", 3).unwrap();
+    comp.create_stream("/config").unwrap().write_all(b"key=value").unwrap();
+    comp.set_modified_time("/", time(4_000_000)).unwrap();
+    comp.flush().unwrap();
+    comp
+}
+
+/// Returns a description of every object's metadata, keyed by path.
+fn metadata(comp: &Comp) -> BTreeMap<PathBuf, String> {
+    comp.walk()
+        .map(|entry| {
+            let description = format!(
+                "{:?} {} {:x} {:?} {:?}",
+                entry.file_type(),
+                entry.clsid(),
+                entry.state_bits(),
+                entry.created(),
+                entry.modified()
+            );
+            (entry.path().to_path_buf(), description)
+        })
+        .collect()
+}
+
+/// Returns a hash of the contents of every stream, keyed by path.
+fn stream_hashes(comp: &mut Comp) -> BTreeMap<PathBuf, (u64, u64)> {
+    let paths: Vec<PathBuf> = comp
+        .walk()
+        .filter(|entry| entry.is_stream())
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    paths
+        .into_iter()
+        .map(|path| {
+            let data = comp.read_stream_to_vec(&path).unwrap();
+            let mut hasher = DefaultHasher::new();
+            hasher.write(&data);
+            (path, (data.len() as u64, hasher.finish()))
+        })
+        .collect()
+}
+
+/// Makes metadata edits, a rename, a removal, and an addition.
+fn transform(comp: &mut Comp) {
+    comp.set_storage_clsid("/", Uuid::from_u128(100)).unwrap();
+    comp.set_state_bits("/docs", 0).unwrap();
+    comp.set_modified_time("/docs", time(9_000_000)).unwrap();
+    comp.set_state_bits("/docs/report", 0xffff).unwrap();
+    comp.rename("/docs/old", "/docs/archive").unwrap();
+    comp.remove_stream("/config").unwrap();
+    comp.create_stream("/extra").unwrap().write_all(b"new").unwrap();
+}
+
+//===========================================================================//
+
+#[test]
+fn snapshot_lists_every_object() {
+    let comp = make_file();
+    let snapshot = comp.export_metadata();
+    let paths: Vec<&Path> =
+        snapshot.entries().iter().map(|entry| entry.path()).collect();
+    let walked: Vec<PathBuf> =
+        comp.walk().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(paths, walked);
+    let docs = snapshot.get("/docs").unwrap();
+    assert_eq!(docs.kind(), EntryKind::Storage);
+    assert_eq!(docs.clsid(), &Uuid::from_u128(2));
+    assert_eq!(docs.state_bits(), 0x20);
+    assert_eq!(docs.created(), time(1_000_000));
+    assert_eq!(docs.modified(), time(2_000_000));
+    let report = snapshot.get("/docs/report").unwrap();
+    assert_eq!(report.kind(), EntryKind::Stream);
+    assert_eq!(report.len(), 9000);
+    assert_eq!(snapshot.get("/").unwrap().kind(), EntryKind::Root);
+    assert!(snapshot.get("/nothing").is_none());
+}
+
+#[test]
+fn restoring_unchanged_file_changes_nothing() {
+    let comp = make_file();
+    let snapshot = comp.export_metadata();
+    let before = comp.into_inner().into_inner();
+    let mut comp = CompoundFile::open(Cursor::new(before.clone())).unwrap();
+    let report =
+        comp.restore_metadata(&snapshot, RestorePolicy::new()).unwrap();
+    assert!(report.is_unchanged());
+    assert!(report.skipped().is_empty());
+    comp.flush().unwrap();
+    assert!(comp.into_inner().into_inner() == before);
+}
+
+#[test]
+fn default_policy_fails_without_changes() {
+    let mut comp = make_file();
+    let snapshot = comp.export_metadata();
+    transform(&mut comp);
+    let expected = metadata(&comp);
+
+    let error =
+        comp.restore_metadata(&snapshot, RestorePolicy::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    assert_eq!(metadata(&comp), expected);
+
+    let policy = RestorePolicy::new().missing(MissingEntries::Skip);
+    let error = comp.restore_metadata(&snapshot, policy).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(metadata(&comp), expected);
+}
+
+#[test]
+fn restore_skipping_missing_and_extra() {
+    let mut comp = make_file();
+    let snapshot = comp.export_metadata();
+    transform(&mut comp);
+    let hashes = stream_hashes(&mut comp);
+
+    let policy = RestorePolicy::new()
+        .missing(MissingEntries::Skip)
+        .extra(ExtraEntries::Skip);
+    let report = comp.restore_metadata(&snapshot, policy).unwrap();
+    let updated: Vec<&str> =
+        report.updated().iter().map(|p| p.to_str().unwrap()).collect();
+    assert_eq!(updated, vec!["/", "/docs", "/docs/report"]);
+    let skipped: Vec<&str> =
+        report.skipped().iter().map(|p| p.to_str().unwrap()).collect();
+    assert_eq!(
+        skipped,
+        vec![
+            "/docs/archive",
+            "/docs/archive/notes",
+            "/extra",
+            "/docs/old",
+            "/docs/old/notes",
+            "/config",
+        ]
+    );
+    assert!(report.recreated().is_empty());
+    assert!(report.removed().is_empty());
+
+    let root = comp.root_entry();
+    assert_eq!(root.clsid(), &Uuid::from_u128(1));
+    assert_eq!(root.modified(), time(4_000_000));
+    let docs = comp.entry("/docs").unwrap();
+    assert_eq!(docs.state_bits(), 0x20);
+    assert_eq!(docs.modified(), time(2_000_000));
+    assert_eq!(comp.entry("/docs/report").unwrap().state_bits(), 3);
+    assert!(comp.is_storage("/docs/archive"));
+    assert!(!comp.exists("/config"));
+    assert_eq!(stream_hashes(&mut comp), hashes);
+}
+
+#[test]
+fn restore_recreating_missing_and_removing_extra() {
+    let mut comp = make_file();
+    let original = metadata(&comp);
+    let original_hashes = stream_hashes(&mut comp);
+    let snapshot = comp.export_metadata();
+    transform(&mut comp);
+
+    let policy = RestorePolicy::new()
+        .missing(MissingEntries::RecreateEmpty)
+        .extra(ExtraEntries::Remove);
+    let report = comp.restore_metadata(&snapshot, policy).unwrap();
+    let removed: Vec<&str> =
+        report.removed().iter().map(|p| p.to_str().unwrap()).collect();
+    assert_eq!(removed, vec!["/docs/archive", "/extra"]);
+    let recreated: Vec<&str> =
+        report.recreated().iter().map(|p| p.to_str().unwrap()).collect();
+    assert_eq!(recreated, vec!["/docs/old", "/docs/old/notes", "/config"]);
+    assert!(report.skipped().is_empty());
+
+    // The tree and every object's metadata are back as they were.  Streams
+    // that survived keep their contents, and recreated ones are empty.
+    assert_eq!(metadata(&comp), original);
+    let hashes = stream_hashes(&mut comp);
+    assert_eq!(
+        hashes[Path::new("/docs/report")],
+        original_hashes[Path::new("/docs/report")]
+    );
+    assert_eq!(hashes[Path::new("/config")].0, 0);
+    assert_eq!(hashes[Path::new("/docs/old/notes")].0, 0);
+
+    comp.flush().unwrap();
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    assert_eq!(metadata(&comp), original);
+}
+
+#[test]
+fn restore_replaces_object_of_other_kind() {
+    let mut comp = make_file();
+    let snapshot = comp.export_metadata();
+    comp.remove_stream("/config").unwrap();
+    comp.create_storage("/config").unwrap();
+    comp.create_stream("/config/inner").unwrap();
+
+    let policy = RestorePolicy::new()
+        .missing(MissingEntries::RecreateEmpty)
+        .extra(ExtraEntries::Skip);
+    let error = comp.restore_metadata(&snapshot, policy).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert!(comp.is_storage("/config"));
+
+    let policy = RestorePolicy::new()
+        .missing(MissingEntries::RecreateEmpty)
+        .extra(ExtraEntries::Remove);
+    let report = comp.restore_metadata(&snapshot, policy).unwrap();
+    assert_eq!(report.removed(), &[PathBuf::from("/config")]);
+    assert_eq!(report.recreated(), &[PathBuf::from("/config")]);
+    assert!(comp.is_stream("/config"));
+}
+
+#[test]
+fn restore_writes_back_exact_dir_entry() {
+    let mut comp = make_file();
+    let snapshot = comp.export_metadata();
+    let raw_before =
+        comp.raw_dir_entry(comp.entry("/config").unwrap().stream_id());
+    let mut comp = CompoundFile::open(comp.into_inner()).unwrap();
+    comp.set_state_bits("/config", 99).unwrap();
+    let policy = RestorePolicy::new();
+    let report = comp.restore_metadata(&snapshot, policy).unwrap();
+    assert_eq!(report.updated(), &[PathBuf::from("/config")]);
+    let stream_id = comp.entry("/config").unwrap().stream_id();
+    assert_eq!(comp.raw_dir_entry(stream_id).unwrap(), raw_before.unwrap());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn snapshot_round_trips_through_serde() {
+    let comp = make_file();
+    let snapshot = comp.export_metadata();
+    let json = serde_json::to_string(&snapshot).unwrap();
+    let parsed: MetadataSnapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, snapshot);
+}
+
+#[test]
+fn empty_snapshot_with_skips_changes_nothing() {
+    let mut comp = make_file();
+    let expected = metadata(&comp);
+    let policy = RestorePolicy::new()
+        .missing(MissingEntries::Skip)
+        .extra(ExtraEntries::Skip);
+    let report =
+        comp.restore_metadata(&MetadataSnapshot::default(), policy).unwrap();
+    assert!(report.is_unchanged());
+    assert_eq!(metadata(&comp), expected);
+}
+
+//===========================================================================//
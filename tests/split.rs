@@ -0,0 +1,322 @@
+use cfb::{CompoundFile, Entry, SplitOptions, Version};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// An in-memory file that the test can still read once `split` is done
+/// with it.
+#[derive(Clone, Default)]
+struct SharedFile(Rc<RefCell<Cursor<Vec<u8>>>>);
+
+impl SharedFile {
+    fn data(&self) -> Vec<u8> {
+        self.0.borrow().get_ref().clone()
+    }
+}
+
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SharedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.borrow_mut().seek(pos)
+    }
+}
+
+const SUMMARY_INFO: &str = "\u{5}SummaryInformation";
+
+fn time(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Creates a V3 file with the tree:
+///
+/// ```text
+/// /
+/// ├── \x05SummaryInformation  (48 bytes)
+/// ├── Attach1/
+/// │   ├── Contents  (5000 bytes)
+/// │   └── Nested/
+/// │       └── Small  (10 bytes)
+/// ├── Attach2/
+/// │   └── \x05SummaryInformation  (3 bytes)
+/// └── Loose  (20 bytes)
+/// ```
+///
+/// with CLSIDs, state bits, and timestamps set throughout.
+fn make_container() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    let summary = format!("/{}", SUMMARY_INFO);
+    comp.create_stream(&summary).unwrap().write_all(&[5; 48]).unwrap();
+    comp.create_storage("/Attach1").unwrap();
+    comp.create_stream("/Attach1/Contents")
+        .unwrap()
+        .write_all(&[1; 5000])
+        .unwrap();
+    comp.create_storage("/Attach1/Nested").unwrap();
+    comp.create_stream("/Attach1/Nested/Small")
+        .unwrap()
+        .write_all(b"0123456789")
+        .unwrap();
+    comp.create_storage("/Attach2").unwrap();
+    comp.create_stream(format!("/Attach2/{}", SUMMARY_INFO))
+        .unwrap()
+        .write_all(b"own")
+        .unwrap();
+    comp.create_stream("/Loose").unwrap().write_all(&[7; 20]).unwrap();
+    comp.set_storage_clsid("/", Uuid::from_u128(0x10)).unwrap();
+    comp.set_state_bits("/", 0x100).unwrap();
+    comp.set_modified_time("/", time(3_000_000)).unwrap();
+    let storages = ["/Attach1", "/Attach1/Nested", "/Attach2"];
+    for (index, path) in storages.iter().enumerate() {
+        let index = index as u64;
+        comp.set_storage_clsid(path, Uuid::from_u128(0x20 + index as u128))
+            .unwrap();
+        comp.set_state_bits(path, 0x200 + index as u32).unwrap();
+        comp.set_created_time(path, time(1_000_000 + index)).unwrap();
+        comp.set_modified_time(path, time(2_000_000 + index)).unwrap();
+    }
+    comp.set_state_bits("/Attach1/Contents", 0x300).unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Everything about an object that splitting should keep, other than its
+/// path.
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    is_stream: bool,
+    clsid: Uuid,
+    state_bits: u32,
+    created: Option<SystemTime>,
+    modified: SystemTime,
+    contents: Vec<u8>,
+}
+
+/// Returns a snapshot of everything within the storage at `path`, keyed by
+/// path relative to it.  The storage itself is keyed by "/", and its
+/// creation time is left out, since a root entry can't have one.
+fn snapshot_subtree<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> BTreeMap<PathBuf, Snapshot> {
+    let entries: Vec<Entry> = comp.walk_storage(path).unwrap().collect();
+    let mut snapshot = BTreeMap::new();
+    for entry in entries {
+        let relative = entry.path().strip_prefix(path).unwrap();
+        let mut contents = Vec::new();
+        if entry.is_stream() {
+            comp.open_stream(entry.path())
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+        }
+        let is_top = relative == Path::new("");
+        snapshot.insert(
+            Path::new("/").join(relative),
+            Snapshot {
+                is_stream: entry.is_stream(),
+                clsid: *entry.clsid(),
+                state_bits: entry.state_bits(),
+                created: if is_top { None } else { Some(entry.created()) },
+                modified: entry.modified(),
+                contents,
+            },
+        );
+    }
+    snapshot
+}
+
+/// Splits the container, returning the report and the data written to each
+/// output, keyed by the name of the entry it was requested for.
+fn split_container(
+    options: SplitOptions,
+) -> (cfb::SplitReport, BTreeMap<String, Vec<u8>>) {
+    let mut comp = CompoundFile::open(Cursor::new(make_container())).unwrap();
+    let mut outputs = Vec::<(String, SharedFile)>::new();
+    let report = comp
+        .split(options, |entry| {
+            let file = SharedFile::default();
+            outputs.push((entry.name().to_string(), file.clone()));
+            Ok(file)
+        })
+        .unwrap();
+    let outputs =
+        outputs.into_iter().map(|(name, file)| (name, file.data())).collect();
+    (report, outputs)
+}
+
+//===========================================================================//
+
+#[test]
+fn split_round_trips_each_storage() {
+    let (report, outputs) = split_container(SplitOptions::new());
+    assert_eq!(
+        report.storages(),
+        &[PathBuf::from("/Attach1"), PathBuf::from("/Attach2")]
+    );
+    let names: Vec<&String> = outputs.keys().collect();
+    assert_eq!(names, vec!["Attach1", "Attach2"]);
+    assert!(!report.top_level_streams_written());
+    assert_eq!(
+        report.top_level_streams(),
+        &[
+            PathBuf::from("/Loose"),
+            PathBuf::from(format!("/{}", SUMMARY_INFO))
+        ]
+    );
+    assert_eq!(report.num_streams(), 3);
+    assert_eq!(report.num_bytes(), 5013);
+
+    let mut source =
+        CompoundFile::open(Cursor::new(make_container())).unwrap();
+    for (name, data) in outputs {
+        let mut split = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+        assert_eq!(split.version(), Version::V3);
+        assert!(split.open_warnings().is_empty());
+        assert_eq!(
+            snapshot_subtree(&mut split, "/"),
+            snapshot_subtree(&mut source, &format!("/{}", name)),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn split_can_copy_summary_information() {
+    let options = SplitOptions::new().summary_information(true);
+    let (_, outputs) = split_container(options);
+    let summary = format!("/{}", SUMMARY_INFO);
+    let read_summary = |data: &Vec<u8>| {
+        let mut comp = CompoundFile::open(Cursor::new(data.clone())).unwrap();
+        let mut contents = Vec::new();
+        comp.open_stream(&summary)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    };
+    assert_eq!(read_summary(&outputs["Attach1"]), vec![5; 48]);
+    // A storage's own summary information is left alone.
+    assert_eq!(read_summary(&outputs["Attach2"]), b"own");
+}
+
+#[test]
+fn split_can_collect_top_level_streams() {
+    let options = SplitOptions::new().top_level_streams(true);
+    let (report, outputs) = split_container(options);
+    assert!(report.top_level_streams_written());
+    assert_eq!(report.num_streams(), 5);
+    let names: Vec<&String> = outputs.keys().collect();
+    assert_eq!(names, vec!["Attach1", "Attach2", "Root Entry"]);
+    let data = outputs["Root Entry"].clone();
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    let root = comp.root_entry();
+    assert_eq!(*root.clsid(), Uuid::from_u128(0x10));
+    assert_eq!(root.state_bits(), 0x100);
+    assert_eq!(root.modified(), time(3_000_000));
+    let paths: Vec<PathBuf> =
+        comp.walk().map(|entry| entry.path().to_path_buf()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("/"),
+            PathBuf::from("/Loose"),
+            PathBuf::from(format!("/{}", SUMMARY_INFO)),
+        ]
+    );
+    let mut contents = Vec::new();
+    comp.open_stream("/Loose").unwrap().read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, vec![7; 20]);
+}
+
+#[test]
+fn split_stops_at_output_error() {
+    let mut comp = CompoundFile::open(Cursor::new(make_container())).unwrap();
+    let mut calls = 0;
+    let result = comp.split(SplitOptions::new(), |_| {
+        calls += 1;
+        if calls == 2 {
+            return Err(io::Error::other("no space left"));
+        }
+        Ok(SharedFile::default())
+    });
+    assert_eq!(result.unwrap_err().to_string(), "no space left");
+    assert_eq!(calls, 2);
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_free_function_still_splits() {
+    let (report, outputs) = split_container(SplitOptions::new());
+    let mut comp = CompoundFile::open(Cursor::new(make_container())).unwrap();
+    let mut names = Vec::new();
+    let old_report = cfb::split(&mut comp, SplitOptions::new(), |entry| {
+        names.push(entry.name().to_string());
+        Ok(SharedFile::default())
+    })
+    .unwrap();
+    assert_eq!(old_report.storages(), report.storages());
+    assert_eq!(names, outputs.keys().cloned().collect::<Vec<_>>());
+}
+
+#[test]
+fn export_rejects_streams() {
+    let comp = CompoundFile::open(Cursor::new(make_container())).unwrap();
+    let error = comp
+        .export_storage_as_cfb("/Loose", Cursor::new(Vec::new()))
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn export_from_several_threads_at_once() {
+    let comp = CompoundFile::open(Cursor::new(make_container())).unwrap();
+    let exports: Vec<Vec<u8>> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    let cursor = Cursor::new(Vec::new());
+                    let copy = comp.export_storage_as_cfb("/", cursor);
+                    copy.unwrap().into_inner().into_inner()
+                })
+            })
+            .collect();
+        threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+    });
+    let mut copy =
+        CompoundFile::open(Cursor::new(exports[0].clone())).unwrap();
+    assert_eq!(
+        copy.walk()
+            .map(|entry| entry.path().to_path_buf())
+            .collect::<Vec<_>>(),
+        comp.walk()
+            .map(|entry| entry.path().to_path_buf())
+            .collect::<Vec<_>>()
+    );
+    assert!(!copy.read_stream_to_vec("/Loose").unwrap().is_empty());
+    assert!(exports.iter().all(|export| export == &exports[0]));
+}
+
+//===========================================================================//
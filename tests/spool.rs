@@ -0,0 +1,94 @@
+use cfb::{CompoundFile, SpoolPolicy};
+use std::io::{self, Cursor, Read, Write};
+
+//===========================================================================//
+
+/// A reader that only implements `Read`, and hands out its data in small,
+/// irregular pieces like a pipe or socket would.
+struct PipeReader {
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunk = 1 + self.position % 1000;
+        let end = (self.position + chunk.min(buf.len())).min(self.data.len());
+        let len = end - self.position;
+        buf[..len].copy_from_slice(&self.data[self.position..end]);
+        self.position = end;
+        Ok(len)
+    }
+}
+
+fn stream_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn make_compound_file() -> PipeReader {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.create_stream("/small")
+        .unwrap()
+        .write_all(&stream_data(100))
+        .unwrap();
+    comp.create_storage("/dir").unwrap();
+    let mut stream = comp.create_stream("/dir/big").unwrap();
+    stream.write_all(&stream_data(50_000)).unwrap();
+    drop(stream);
+    let data = comp.into_inner().into_inner();
+    PipeReader { data, position: 0 }
+}
+
+fn check_contents<F: Read + io::Seek>(comp: &mut CompoundFile<F>) {
+    let mut data = Vec::new();
+    comp.open_stream("/small").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, stream_data(100));
+    data.clear();
+    comp.open_stream("/dir/big").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, stream_data(50_000));
+}
+
+//===========================================================================//
+
+#[test]
+fn open_from_reader_in_memory() {
+    let reader = make_compound_file();
+    let mut comp = cfb::open_from_reader(reader, SpoolPolicy::Memory).unwrap();
+    check_contents(&mut comp);
+    assert!(!comp.into_inner().is_temp_file());
+}
+
+#[test]
+fn open_from_reader_below_threshold_stays_in_memory() {
+    let reader = make_compound_file();
+    let policy = SpoolPolicy::TempFile { threshold: 1 << 20 };
+    let mut comp = cfb::open_from_reader(reader, policy).unwrap();
+    check_contents(&mut comp);
+    assert!(!comp.into_inner().is_temp_file());
+}
+
+#[test]
+fn open_from_reader_migrates_to_temp_file() {
+    let reader = make_compound_file();
+    assert!(reader.data.len() > 4096);
+    let policy = SpoolPolicy::TempFile { threshold: 4096 };
+    let mut comp = cfb::open_from_reader(reader, policy).unwrap();
+    check_contents(&mut comp);
+    assert!(comp.into_inner().is_temp_file());
+}
+
+#[test]
+fn spooled_compound_file_is_writable() {
+    let reader = make_compound_file();
+    let policy = SpoolPolicy::TempFile { threshold: 4096 };
+    let mut comp = cfb::open_from_reader(reader, policy).unwrap();
+    comp.create_stream("/new").unwrap().write_all(b"hello").unwrap();
+    comp.flush().unwrap();
+    let mut data = Vec::new();
+    comp.open_stream("/new").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"hello");
+    check_contents(&mut comp);
+}
+
+//===========================================================================//
@@ -0,0 +1,133 @@
+use cfb::{CompoundFile, Severity, ValidationIssueKind};
+use std::convert::TryInto;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use uuid::Uuid;
+
+//===========================================================================//
+
+/// A version 3 file, like those written by some old exporters, whose three
+/// streams ("/Objects/Contents" in the mini stream, "/Data" in regular
+/// sectors, and "/Summary" in the second directory sector) have garbage in
+/// their CLSID fields.  The storage "/Objects" has a real CLSID.
+const FIXTURE: &str = "tests/old_exporters/nonzero_stream_clsid";
+
+const STREAMS: [(&str, [u8; 16]); 3] = [
+    (
+        "/Objects/Contents",
+        [
+            0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b,
+            0x1c, 0x1d, 0x1e, 0x1f, 0x20,
+        ],
+    ),
+    ("/Data", [0xcc; 16]),
+    (
+        "/Summary",
+        [
+            0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe, 0xef, 0xde, 0xad, 0xbe,
+            0xef, 0xde, 0xad, 0xbe, 0xef,
+        ],
+    ),
+];
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+fn fixture() -> Vec<u8> {
+    std::fs::read(FIXTURE).unwrap()
+}
+
+/// Returns the raw CLSID field of the object at the given path.
+fn raw_clsid(comp: &mut Comp, path: &str) -> [u8; 16] {
+    let stream_id = comp.entry(path).unwrap().stream_id();
+    let raw = comp.raw_dir_entry(stream_id);
+    raw.unwrap()[80..96].try_into().unwrap()
+}
+
+fn read_stream(comp: &mut Comp, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn nonzero_stream_clsid_is_reported_under_both_validations() {
+    let permissive = CompoundFile::open(Cursor::new(fixture())).unwrap();
+    let strict = CompoundFile::open_strict(Cursor::new(fixture())).unwrap();
+    for comp in [&permissive, &strict] {
+        let warnings = comp.open_warnings();
+        assert_eq!(warnings.len(), STREAMS.len());
+        assert!(warnings
+            .iter()
+            .all(|issue| issue.kind() == ValidationIssueKind::StreamClsid));
+        let issues = comp.validate();
+        let mut paths: Vec<&Path> =
+            issues.iter().map(|issue| issue.path().unwrap()).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("/Data"),
+                Path::new("/Objects/Contents"),
+                Path::new("/Summary"),
+            ]
+        );
+        assert!(issues.iter().all(|issue| issue.severity() == Severity::Info));
+    }
+}
+
+#[test]
+fn stream_clsid_reads_as_nil_but_is_kept() {
+    let mut comp = CompoundFile::open(Cursor::new(fixture())).unwrap();
+    for (path, garbage) in STREAMS {
+        assert!(comp.entry(path).unwrap().clsid().is_nil());
+        assert_eq!(raw_clsid(&mut comp, path), garbage);
+    }
+    assert_eq!(
+        comp.entry("/Objects").unwrap().clsid(),
+        &Uuid::from_u128(0x0002_0906_0000_0000_c000_0000_0000_0046)
+    );
+    assert_eq!(
+        read_stream(&mut comp, "/Objects/Contents"),
+        (0..200).map(|i| i as u8).collect::<Vec<u8>>()
+    );
+    assert_eq!(
+        read_stream(&mut comp, "/Data"),
+        (0..6000u32).map(|i| (i * 7) as u8).collect::<Vec<u8>>()
+    );
+    assert_eq!(read_stream(&mut comp, "/Summary"), b"summary");
+}
+
+#[test]
+fn round_trip_keeps_file_byte_for_byte() {
+    let original = fixture();
+    let mut comp = CompoundFile::open(Cursor::new(original.clone())).unwrap();
+    comp.flush().unwrap();
+    assert!(comp.into_inner().into_inner() == original);
+
+    // Rewriting the streams' directory entries keeps the bytes too.
+    let mut comp = CompoundFile::open(Cursor::new(original.clone())).unwrap();
+    for (path, _) in STREAMS {
+        comp.set_state_bits(path, 1).unwrap();
+        comp.set_state_bits(path, 0).unwrap();
+    }
+    comp.flush().unwrap();
+    assert!(comp.into_inner().into_inner() == original);
+}
+
+#[test]
+fn writing_streams_keeps_their_clsid_bytes() {
+    let mut comp = CompoundFile::open(Cursor::new(fixture())).unwrap();
+    comp.open_stream("/Summary").unwrap().write_all(b"SUMMARY!").unwrap();
+    comp.open_stream("/Data").unwrap().write_all(&[0; 100]).unwrap();
+    comp.create_stream("/New").unwrap().write_all(b"new").unwrap();
+    comp.flush().unwrap();
+    let mut comp = CompoundFile::open(comp.into_inner()).unwrap();
+    for (path, garbage) in STREAMS {
+        assert_eq!(raw_clsid(&mut comp, path), garbage);
+    }
+    assert_eq!(raw_clsid(&mut comp, "/New"), [0; 16]);
+}
+
+//===========================================================================//
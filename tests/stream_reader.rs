@@ -0,0 +1,132 @@
+use cfb::CompoundFile;
+use std::io::{BufRead, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(37).wrapping_add(seed)).collect()
+}
+
+/// Creates a file with a small stream (in the mini stream) and a large one
+/// (in regular sectors), interleaved so that neither is contiguous.
+fn make_file() -> TestFile {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let mut small = comp.create_stream("/small").unwrap();
+    let mut large = comp.create_stream("/large").unwrap();
+    for chunk in 0..10 {
+        small.write_all(&data(300, chunk)[..]).unwrap();
+        small.flush().unwrap();
+        large.write_all(&data(5000, chunk)[..]).unwrap();
+        large.flush().unwrap();
+    }
+    // Shrinking the small stream moves it into the mini stream.
+    small.set_len(3000).unwrap();
+    drop(small);
+    drop(large);
+    comp.flush().unwrap();
+    CompoundFile::open(comp.into_inner()).unwrap()
+}
+
+fn read_all(comp: &mut TestFile, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn interleaved_readers_match_sequential_reads() {
+    let mut comp = make_file();
+    let small = read_all(&mut comp, "/small");
+    let large = read_all(&mut comp, "/large");
+    assert!((small.len() as u64) < comp.mini_stream_cutoff());
+    assert!((large.len() as u64) >= comp.mini_stream_cutoff());
+
+    let comp = &comp;
+    let mut readers = [
+        comp.open_stream_reader("/small").unwrap(),
+        comp.open_stream_reader("/large").unwrap(),
+        comp.open_stream_reader("/large").unwrap(),
+    ];
+    assert_eq!(readers[0].len(), small.len() as u64);
+    assert_eq!(readers[1].len(), large.len() as u64);
+    let mut results = [Vec::new(), Vec::new(), Vec::new()];
+    // Read in chunks of varying sizes, round robin, so that no reader's
+    // buffer lines up with any other's.
+    let mut chunk_len = 1;
+    loop {
+        let mut done = true;
+        for (reader, result) in readers.iter_mut().zip(results.iter_mut()) {
+            let mut chunk = vec![0; chunk_len];
+            let num_read = reader.read(&mut chunk).unwrap();
+            result.extend_from_slice(&chunk[..num_read]);
+            done &= num_read == 0;
+        }
+        if done {
+            break;
+        }
+        chunk_len = chunk_len * 7 % 1013 + 1;
+    }
+    assert_eq!(results[0], small);
+    assert_eq!(results[1], large);
+    assert_eq!(results[2], large);
+}
+
+#[test]
+fn readers_seek_independently() {
+    let mut comp = make_file();
+    let small = read_all(&mut comp, "/small");
+    let large = read_all(&mut comp, "/large");
+    let comp = &comp;
+    // Look up offsets in one stream, and then read at those offsets in
+    // another, as when following an index into a data stream.
+    let mut index = comp.open_stream_reader("/small").unwrap();
+    let mut table = comp.open_stream_reader("/large").unwrap();
+    let mut other = comp.open_stream_reader("/large").unwrap();
+    other.seek(SeekFrom::End(-10)).unwrap();
+    for position in [2999, 0, 1500, 7] {
+        index.seek(SeekFrom::Start(position)).unwrap();
+        let mut byte = [0u8];
+        index.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], small[position as usize]);
+        let offset = byte[0] as u64 * 150;
+        table.seek(SeekFrom::Start(offset)).unwrap();
+        let mut chunk = [0u8; 100];
+        table.read_exact(&mut chunk).unwrap();
+        assert_eq!(&chunk[..], &large[offset as usize..][..100]);
+    }
+    let mut tail = Vec::new();
+    other.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &large[large.len() - 10..]);
+    assert_eq!(index.fill_buf().unwrap(), &small[8..]);
+}
+
+#[test]
+fn readers_can_be_opened_while_walking() {
+    let mut comp = make_file();
+    let small = read_all(&mut comp, "/small");
+    let large = read_all(&mut comp, "/large");
+    let mut total = 0;
+    for entry in comp.walk().filter(|entry| entry.is_stream()) {
+        let mut reader = comp.open_stream_reader(entry.path()).unwrap();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len() as u64, entry.len());
+        total += data.len();
+    }
+    assert_eq!(total, small.len() + large.len());
+}
+
+#[test]
+fn open_stream_reader_errors() {
+    let comp = make_file();
+    let error = comp.open_stream_reader("/missing").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = comp.open_stream_reader("/").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+//===========================================================================//
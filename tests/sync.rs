@@ -0,0 +1,197 @@
+use cfb::{CompoundFile, SyncOptions, SyncReport, Version};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use tracer::Tracer;
+
+mod tracer;
+
+//===========================================================================//
+
+/// A scratch directory that is deleted when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "cfb-sync-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    fn write(&self, relative: &str, data: &[u8]) {
+        let path = self.0.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+type TestFile = CompoundFile<Tracer>;
+
+fn create() -> TestFile {
+    CompoundFile::create_with_version(Version::V3, Tracer::new()).unwrap()
+}
+
+/// Syncs, flushes, and reopens the file, returning the report and the number
+/// of writes that were made.
+fn sync(
+    comp: TestFile,
+    dir: &TempDir,
+    options: SyncOptions,
+) -> (TestFile, SyncReport, usize) {
+    let mut comp = comp;
+    let report = comp.sync_from_dir(dir.path(), "/res", options).unwrap();
+    comp.flush().unwrap();
+    let tracer = comp.into_inner();
+    let num_writes = tracer.trace().num_writes();
+    tracer.trace().clear();
+    let comp = CompoundFile::open_strict(tracer).unwrap();
+    (comp, report, num_writes)
+}
+
+fn read_stream(comp: &mut TestFile, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn paths(paths: &[&str]) -> Vec<PathBuf> {
+    paths.iter().map(PathBuf::from).collect()
+}
+
+//===========================================================================//
+
+#[test]
+fn successive_syncs() {
+    let dir = TempDir::new("successive");
+    dir.write("a.txt", b"alpha");
+    dir.write("big.bin", &[7; 5000]);
+    dir.write("sub/c.txt", b"gamma");
+    let options = SyncOptions::new().remove_missing(true);
+
+    // Initial sync: everything is created.
+    let (comp, report, num_writes) = sync(create(), &dir, options.clone());
+    let created = paths(&[
+        "/res",
+        "/res/a.txt",
+        "/res/big.bin",
+        "/res/sub",
+        "/res/sub/c.txt",
+    ]);
+    assert_eq!(report.created(), created);
+    assert!(report.updated().is_empty());
+    assert!(report.removed().is_empty(), "{:?}", report);
+    assert_eq!(report.num_unchanged(), 0);
+    assert!(num_writes > 0);
+
+    // No-op sync: nothing changes, and nothing is written.
+    let (mut comp, report, num_writes) = sync(comp, &dir, options.clone());
+    assert!(report.is_unchanged(), "{:?}", report);
+    assert_eq!(report.num_unchanged(), 3);
+    assert_eq!(num_writes, 0);
+    assert_eq!(read_stream(&mut comp, "/res/a.txt"), b"alpha");
+
+    // One file changed and one deleted.
+    dir.write("a.txt", b"alpha, revised");
+    fs::remove_file(dir.path().join("sub/c.txt")).unwrap();
+    let (mut comp, report, num_writes) = sync(comp, &dir, options.clone());
+    assert!(report.created().is_empty());
+    assert_eq!(report.updated(), paths(&["/res/a.txt"]));
+    assert_eq!(report.removed(), paths(&["/res/sub/c.txt"]));
+    assert_eq!(report.num_unchanged(), 1);
+    assert!(num_writes > 0);
+    assert_eq!(read_stream(&mut comp, "/res/a.txt"), b"alpha, revised");
+    assert_eq!(read_stream(&mut comp, "/res/big.bin"), vec![7; 5000]);
+    assert!(!comp.exists("/res/sub/c.txt"));
+    assert!(comp.is_storage("/res/sub"));
+
+    // And once that's synced, it's a no-op again.
+    let (_, report, num_writes) = sync(comp, &dir, options);
+    assert!(report.is_unchanged(), "{:?}", report);
+    assert_eq!(num_writes, 0);
+}
+
+#[test]
+fn missing_files_are_kept_by_default() {
+    let dir = TempDir::new("keep");
+    dir.write("a", b"alpha");
+    dir.write("b", b"beta");
+    let (comp, _, _) = sync(create(), &dir, SyncOptions::new());
+    fs::remove_file(dir.path().join("b")).unwrap();
+    let (mut comp, report, num_writes) = sync(comp, &dir, SyncOptions::new());
+    assert!(report.is_unchanged(), "{:?}", report);
+    assert_eq!(num_writes, 0);
+    assert_eq!(read_stream(&mut comp, "/res/b"), b"beta");
+}
+
+#[test]
+fn compare_contents_catches_same_size_changes() {
+    let dir = TempDir::new("contents");
+    dir.write("a", b"alpha");
+    dir.write("b", b"beta");
+    let options = SyncOptions::new().compare_contents(true);
+    let (comp, _, _) = sync(create(), &dir, options.clone());
+    let (comp, report, num_writes) = sync(comp, &dir, options.clone());
+    assert_eq!(report.num_unchanged(), 2);
+    assert_eq!(num_writes, 0);
+    dir.write("a", b"ALPHA");
+    let (mut comp, report, _) = sync(comp, &dir, options);
+    assert_eq!(report.updated(), paths(&["/res/a"]));
+    assert_eq!(report.num_unchanged(), 1);
+    assert_eq!(read_stream(&mut comp, "/res/a"), b"ALPHA");
+}
+
+#[test]
+fn file_replacing_directory() {
+    let dir = TempDir::new("replace");
+    dir.write("x/inner", b"inner");
+    let options = SyncOptions::new();
+    let (comp, _, _) = sync(create(), &dir, options.clone());
+    fs::remove_dir_all(dir.path().join("x")).unwrap();
+    dir.write("x", b"now a file");
+    let (mut comp, report, _) = sync(comp, &dir, options);
+    assert_eq!(report.removed(), paths(&["/res/x"]));
+    assert_eq!(report.created(), paths(&["/res/x"]));
+    assert_eq!(read_stream(&mut comp, "/res/x"), b"now a file");
+}
+
+#[test]
+fn existing_names_are_matched_case_insensitively() {
+    let dir = TempDir::new("case");
+    dir.write("readme", b"hello");
+    let mut comp = create();
+    comp.create_storage("/res").unwrap();
+    comp.create_stream("/res/README").unwrap().write_all(b"old").unwrap();
+    let options = SyncOptions::new().remove_missing(true);
+    let (mut comp, report, _) = sync(comp, &dir, options);
+    assert_eq!(report.updated(), paths(&["/res/README"]));
+    assert!(report.removed().is_empty());
+    assert_eq!(read_stream(&mut comp, "/res/README"), b"hello");
+}
+
+#[test]
+fn sync_into_stream_fails() {
+    let dir = TempDir::new("stream");
+    let mut comp = create();
+    comp.create_stream("/res").unwrap();
+    let error = comp
+        .sync_from_dir(dir.path(), "/res", SyncOptions::new())
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+//===========================================================================//
@@ -0,0 +1,234 @@
+use cfb::{CompoundFile, ValidationIssueKind, TEMPORARY_NAME_PREFIX};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+//===========================================================================//
+
+/// An in-memory file that starts failing all writes after a given number of
+/// them have succeeded, as if the process had crashed at that point.  The
+/// bytes written so far remain readable through another handle.
+#[derive(Clone)]
+struct CrashingFile {
+    data: Arc<Mutex<Cursor<Vec<u8>>>>,
+    writes_left: Arc<Mutex<usize>>,
+    crashed: Arc<AtomicBool>,
+}
+
+impl CrashingFile {
+    fn new(data: Vec<u8>, writes_left: usize) -> CrashingFile {
+        CrashingFile {
+            data: Arc::new(Mutex::new(Cursor::new(data))),
+            writes_left: Arc::new(Mutex::new(writes_left)),
+            crashed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn has_crashed(&self) -> bool {
+        self.crashed.load(Ordering::Relaxed)
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        self.data.lock().unwrap().get_ref().clone()
+    }
+}
+
+impl Read for CrashingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for CrashingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut writes_left = self.writes_left.lock().unwrap();
+        if *writes_left == 0 {
+            self.crashed.store(true, Ordering::Relaxed);
+            return Err(io::Error::other("simulated crash"));
+        }
+        *writes_left -= 1;
+        self.data.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for CrashingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.data.lock().unwrap().seek(pos)
+    }
+}
+
+const OLD_DATA: &[u8] = b"old contents";
+
+fn new_data() -> Vec<u8> {
+    // Big enough to move the stream out of the mini stream.
+    (0..10000).map(|i| (i % 251) as u8).collect()
+}
+
+/// Creates a file with a stream ("/dir/target") to be replaced, next to
+/// another stream ("/dir/other").
+fn make_file() -> Vec<u8> {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage("/dir").unwrap();
+    comp.create_stream("/dir/target").unwrap().write_all(OLD_DATA).unwrap();
+    comp.create_stream("/dir/other").unwrap().write_all(b"other").unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn read_stream<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+fn walk_paths<F>(comp: &CompoundFile<F>) -> Vec<PathBuf> {
+    comp.walk().map(|entry| entry.path().to_path_buf()).collect()
+}
+
+fn leaked_temporaries<F>(comp: &CompoundFile<F>) -> usize {
+    comp.open_warnings()
+        .iter()
+        .filter(|issue| issue.kind() == ValidationIssueKind::LeakedTemporary)
+        .count()
+}
+
+//===========================================================================//
+
+#[test]
+fn replace_stream() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let len =
+        comp.replace_stream("/dir/target", &mut &new_data()[..]).unwrap();
+    assert_eq!(len, new_data().len() as u64);
+    assert_eq!(read_stream(&mut comp, "/dir/target"), new_data());
+    assert_eq!(
+        walk_paths(&comp),
+        ["/", "/dir", "/dir/other", "/dir/target"].map(PathBuf::from)
+    );
+    let len = comp.replace_stream("/dir/target", &mut &OLD_DATA[..]).unwrap();
+    assert_eq!(len, OLD_DATA.len() as u64);
+    comp.flush().unwrap();
+
+    let mut comp = CompoundFile::open(comp.into_inner()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/dir/target"), OLD_DATA);
+    assert_eq!(read_stream(&mut comp, "/dir/other"), b"other");
+    assert!(comp.open_warnings().is_empty());
+}
+
+#[test]
+fn replace_stream_requires_existing_stream() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let error =
+        comp.replace_stream("/dir/missing", &mut &OLD_DATA[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    let error = comp.replace_stream("/dir", &mut &OLD_DATA[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn temporary_names_are_reserved() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let name = format!("/dir/{}0", TEMPORARY_NAME_PREFIX);
+    let error = comp.create_stream(&name).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let error = comp.create_storage(&name).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(!comp.exists(&name));
+}
+
+#[test]
+fn crash_during_replace_stream() {
+    let original = make_file();
+    let mut saw_leak = false;
+    for budget in 0.. {
+        let backing = CrashingFile::new(original.clone(), budget);
+        let mut comp = CompoundFile::open(backing.clone()).unwrap();
+        let result = comp.replace_stream("/dir/target", &mut &new_data()[..]);
+        let crashed = backing.has_crashed();
+        assert_eq!(result.is_err(), crashed, "budget {}", budget);
+        drop(comp);
+
+        // Whatever happened, the stream holds either its old or its new
+        // contents, and nothing else has changed.
+        let mut comp = CompoundFile::open(Cursor::new(backing.contents()))
+            .unwrap_or_else(|error| panic!("budget {}: {}", budget, error));
+        let data = read_stream(&mut comp, "/dir/target");
+        assert!(
+            data == OLD_DATA || data == new_data(),
+            "budget {}: stream has {} bytes",
+            budget,
+            data.len()
+        );
+        assert_eq!(read_stream(&mut comp, "/dir/other"), b"other");
+        assert_eq!(
+            walk_paths(&comp),
+            ["/", "/dir", "/dir/other", "/dir/target"].map(PathBuf::from),
+            "budget {}",
+            budget
+        );
+
+        let leaked = leaked_temporaries(&comp);
+        if leaked > 0 {
+            saw_leak = true;
+            assert_eq!(comp.read_storage("/dir").unwrap().count(), 2);
+            comp.set_show_temporaries(true);
+            let temporaries: Vec<PathBuf> = comp
+                .read_storage("/dir")
+                .unwrap()
+                .map(|entry| entry.path().to_path_buf())
+                .filter(|path| !Path::new("/dir/other").eq(path))
+                .filter(|path| !Path::new("/dir/target").eq(path))
+                .collect();
+            assert_eq!(temporaries.len(), leaked, "budget {}", budget);
+            comp.set_show_temporaries(false);
+        }
+
+        // Flushing removes any leaked temporaries for good.
+        comp.flush().unwrap();
+        let mut comp = CompoundFile::open(comp.into_inner()).unwrap();
+        assert_eq!(leaked_temporaries(&comp), 0, "budget {}", budget);
+        comp.set_show_temporaries(true);
+        assert_eq!(walk_paths(&comp).len(), 4, "budget {}", budget);
+        assert_eq!(read_stream(&mut comp, "/dir/target"), data);
+
+        if !crashed {
+            assert_eq!(data, new_data());
+            break;
+        }
+    }
+    assert!(saw_leak);
+}
+
+#[test]
+fn storage_with_leaked_temporary_can_be_removed() {
+    // Crash just after creating the temporary stream.
+    let mut budget = 0;
+    let contents = loop {
+        let backing = CrashingFile::new(make_file(), budget);
+        let mut comp = CompoundFile::open(backing.clone()).unwrap();
+        let _ = comp.replace_stream("/dir/target", &mut &new_data()[..]);
+        drop(comp);
+        let comp =
+            CompoundFile::open(Cursor::new(backing.contents())).unwrap();
+        if leaked_temporaries(&comp) > 0 {
+            break backing.contents();
+        }
+        budget += 1;
+    };
+    let mut comp = CompoundFile::open(Cursor::new(contents)).unwrap();
+    comp.remove_stream("/dir/target").unwrap();
+    comp.remove_stream("/dir/other").unwrap();
+    comp.remove_storage("/dir").unwrap();
+    assert_eq!(walk_paths(&comp), [PathBuf::from("/")]);
+    comp.set_show_temporaries(true);
+    assert_eq!(walk_paths(&comp), [PathBuf::from("/")]);
+}
+
+//===========================================================================//
@@ -0,0 +1,216 @@
+use cfb::{CompoundFile, Version};
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::time::Instant;
+use tracer::Tracer;
+
+mod tracer;
+
+//===========================================================================//
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+fn read_stream<F: Read + Seek>(
+    comp: &mut CompoundFile<F>,
+    path: &str,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn large_write_makes_few_writes() {
+    let comp =
+        CompoundFile::create_with_version(Version::V4, Tracer::new()).unwrap();
+    let tracer = comp.into_inner();
+    tracer.trace().clear();
+    let mut comp = CompoundFile::open(tracer).unwrap();
+    let expected = data(1 << 20, 1);
+    comp.create_stream("/big").unwrap().write_all(&expected).unwrap();
+    comp.flush().unwrap();
+    // Writing 256 sectors one at a time would take well over a thousand
+    // writes; in bulk, it's the zero-fill, a few FAT and directory updates,
+    // and the data itself.
+    let tracer = comp.into_inner();
+    let num_writes = tracer.trace().num_writes();
+    assert!(num_writes < 40, "{} writes", num_writes);
+    let mut comp = CompoundFile::open_strict(tracer).unwrap();
+    assert_eq!(read_stream(&mut comp, "/big"), expected);
+}
+
+#[test]
+fn interleaved_writes_round_trip() {
+    // Alternating between two streams keeps each stream's chain from being
+    // contiguous, so writes have to be split up at the gaps.
+    for version in [Version::V3, Version::V4] {
+        let cursor = Cursor::new(Vec::new());
+        let mut comp =
+            CompoundFile::create_with_version(version, cursor).unwrap();
+        let one = data(300_000, 1);
+        let two = data(300_000, 2);
+        let mut stream1 = comp.create_stream("/one").unwrap();
+        let mut stream2 = comp.create_stream("/two").unwrap();
+        for (chunk1, chunk2) in one.chunks(9000).zip(two.chunks(9000)) {
+            stream1.write_all(chunk1).unwrap();
+            stream1.flush().unwrap();
+            stream2.write_all(chunk2).unwrap();
+            stream2.flush().unwrap();
+        }
+        drop((stream1, stream2));
+        let cursor = comp.into_inner();
+        let mut comp = CompoundFile::open_strict(cursor).unwrap();
+        assert_eq!(read_stream(&mut comp, "/one"), one);
+        assert_eq!(read_stream(&mut comp, "/two"), two);
+    }
+}
+
+#[test]
+fn set_len_then_overwrite() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    let mut stream = comp.create_stream("/foo").unwrap();
+    stream.set_len(200_000).unwrap();
+    stream.seek(SeekFrom::Start(50_000)).unwrap();
+    let middle = data(100_000, 3);
+    stream.write_all(&middle).unwrap();
+    drop(stream);
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    let mut expected = vec![0; 200_000];
+    expected[50_000..150_000].copy_from_slice(&middle);
+    assert_eq!(read_stream(&mut comp, "/foo"), expected);
+}
+
+#[test]
+fn growing_out_of_mini_stream() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    let small = data(3000, 4);
+    let large = data(20_000, 5);
+    comp.create_stream("/other").unwrap().write_all(&small).unwrap();
+    let mut stream = comp.create_stream("/foo").unwrap();
+    stream.write_all(&small).unwrap();
+    stream.flush().unwrap();
+    stream.write_all(&large).unwrap();
+    drop(stream);
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    assert_eq!(
+        read_stream(&mut comp, "/foo"),
+        [small.clone(), large].concat()
+    );
+    assert_eq!(read_stream(&mut comp, "/other"), small);
+}
+
+#[test]
+fn many_creates_make_bounded_writes() {
+    let tracer = Tracer::new();
+    let trace = tracer.trace();
+    let mut comp = CompoundFile::create(tracer).unwrap();
+    let mut writes_per_thousand = Vec::new();
+    for thousand in 0..5 {
+        let before = trace.num_writes();
+        for index in 0..1000 {
+            comp.create_stream(format!("/s{}", thousand * 1000 + index))
+                .unwrap();
+        }
+        writes_per_thousand.push(trace.num_writes() - before);
+    }
+    // Each create only touches the new entry and the few entries that get
+    // relinked or recolored around it, however large the tree has grown.
+    let first = writes_per_thousand[0];
+    let last = writes_per_thousand[4];
+    assert!(
+        last < 2 * first,
+        "writes per thousand: {:?}",
+        writes_per_thousand
+    );
+    // Directory entries are written through as they change, so flushing
+    // rewrites no directory sectors.
+    let before = trace.num_writes();
+    comp.flush().unwrap();
+    let flush_writes = trace.num_writes() - before;
+    assert!(flush_writes < 10, "{} writes", flush_writes);
+    let comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    assert_eq!(comp.read_storage("/").unwrap().count(), 5000);
+}
+
+/// Run with `cargo test --release --test throughput -- --ignored` to check
+/// that creates don't slow down as the root storage fills up.  This compares
+/// wall-clock times, so it's too noisy to run by default; the writes made
+/// per create are checked deterministically by
+/// `many_creates_make_bounded_writes`.
+#[test]
+#[ignore]
+fn create_time_stays_flat() {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    let mut times = Vec::new();
+    for thousand in 0..20 {
+        let start = Instant::now();
+        for index in 0..1000 {
+            comp.create_stream(format!("/s{}", thousand * 1000 + index))
+                .unwrap();
+        }
+        times.push(start.elapsed());
+    }
+    // With a balanced tree and a cached directory chain, the last thousand
+    // creates cost about the same as the first thousand; before, they took
+    // hundreds of times longer.  The bound is loose to allow for noisy
+    // machines.
+    let first = times[0].max(times[1]);
+    let last = times[19];
+    assert!(last < 8 * first, "first {:?}, last {:?}", first, last);
+}
+
+/// Run with `cargo test --release --test throughput -- --ignored
+/// --nocapture` to time bulk writes to a file-backed compound file.
+#[test]
+#[ignore]
+fn bench_bulk_writes() {
+    let len = 128 << 20;
+    let expected = data(len, 6);
+    for version in [Version::V3, Version::V4] {
+        let path = std::env::temp_dir()
+            .join(format!("cfb-throughput-{}.cfb", std::process::id()));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mut comp =
+            CompoundFile::create_with_version(version, file).unwrap();
+        let start = Instant::now();
+        let mut stream = comp.create_stream("/copy").unwrap();
+        io::copy(&mut &expected[..], &mut stream).unwrap();
+        stream.flush().unwrap();
+        drop(stream);
+        println!(
+            "{:?}: io::copy of {} bytes: {:?}",
+            version,
+            len,
+            start.elapsed()
+        );
+        let start = Instant::now();
+        let mut stream = comp.create_stream("/set_len").unwrap();
+        stream.set_len(len as u64).unwrap();
+        stream.flush().unwrap();
+        drop(stream);
+        println!(
+            "{:?}: set_len to {} bytes: {:?}",
+            version,
+            len,
+            start.elapsed()
+        );
+        drop(comp);
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+//===========================================================================//
@@ -0,0 +1,197 @@
+use cfb::{CompoundFile, TouchOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//===========================================================================//
+
+fn time(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// A file whose storages all have known, distinct times:
+///
+/// ```text
+/// /a/b/c/stream
+/// /a/b/d
+/// /a/e
+/// /f
+/// ```
+fn make_file() -> CompoundFile<Cursor<Vec<u8>>> {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_storage_all("/a/b/c").unwrap();
+    comp.create_storage("/a/b/d").unwrap();
+    comp.create_storage("/a/e").unwrap();
+    comp.create_storage("/f").unwrap();
+    comp.create_stream("/a/b/c/stream").unwrap().write_all(b"data").unwrap();
+    for (index, path) in ["/", "/a", "/a/b", "/a/b/c", "/a/b/d", "/a/e", "/f"]
+        .iter()
+        .enumerate()
+    {
+        comp.set_modified_time(path, time(1_000 + index as u64)).unwrap();
+    }
+    comp
+}
+
+/// The CFB epoch, which is how a zero timestamp reads back.
+fn zero() -> SystemTime {
+    UNIX_EPOCH - Duration::from_secs(11_644_473_600)
+}
+
+fn modified(comp: &CompoundFile<Cursor<Vec<u8>>>, path: &str) -> SystemTime {
+    comp.entry(path).unwrap().modified()
+}
+
+fn created(comp: &CompoundFile<Cursor<Vec<u8>>>, path: &str) -> SystemTime {
+    comp.entry(path).unwrap().created()
+}
+
+/// A writer that counts the write calls made to it.
+struct CountWrites {
+    inner: Cursor<Vec<u8>>,
+    writes: usize,
+}
+
+impl Read for CountWrites {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for CountWrites {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes += 1;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for CountWrites {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+//===========================================================================//
+
+#[test]
+fn touch_sets_only_modified_time() {
+    let mut comp = make_file();
+    comp.create_storage("/new").unwrap();
+    comp.set_created_time("/new", zero()).unwrap();
+    let before = SystemTime::now();
+    comp.touch("/new").unwrap();
+    assert!(modified(&comp, "/new") >= before - Duration::from_secs(1));
+    // A zero created time is not filled in.
+    assert_eq!(created(&comp, "/new"), zero());
+    // Nothing else is touched.
+    assert_eq!(modified(&comp, "/"), time(1_000));
+    assert_eq!(modified(&comp, "/f"), time(1_006));
+    // Streams keep their zero timestamps.
+    comp.touch("/a/b/c/stream").unwrap();
+    assert_eq!(modified(&comp, "/a/b/c/stream"), zero());
+    assert_eq!(modified(&comp, "/a/b/c"), time(1_003));
+}
+
+#[test]
+fn touch_ancestors() {
+    let mut comp = make_file();
+    let options = TouchOptions::new().modified(time(5_000)).ancestors(true);
+    comp.touch_with("/a/b/c/stream", options).unwrap();
+    for path in ["/", "/a", "/a/b", "/a/b/c"] {
+        assert_eq!(modified(&comp, path), time(5_000), "{}", path);
+    }
+    assert_eq!(modified(&comp, "/a/b/c/stream"), zero());
+    assert_eq!(modified(&comp, "/a/b/d"), time(1_004));
+    assert_eq!(modified(&comp, "/a/e"), time(1_005));
+    assert_eq!(modified(&comp, "/f"), time(1_006));
+}
+
+#[test]
+fn touch_recursive() {
+    let mut comp = make_file();
+    let options = TouchOptions::new().modified(time(6_000)).recursive(true);
+    comp.touch_with("/a/b", options).unwrap();
+    for path in ["/a/b", "/a/b/c", "/a/b/d"] {
+        assert_eq!(modified(&comp, path), time(6_000), "{}", path);
+    }
+    assert_eq!(modified(&comp, "/a/b/c/stream"), zero());
+    assert_eq!(modified(&comp, "/"), time(1_000));
+    assert_eq!(modified(&comp, "/a"), time(1_001));
+    assert_eq!(modified(&comp, "/a/e"), time(1_005));
+
+    // Both at once, from the root, touches everything.
+    let options = TouchOptions::new()
+        .modified(time(7_000))
+        .recursive(true)
+        .ancestors(true);
+    comp.touch_with("/", options).unwrap();
+    for entry in comp.walk() {
+        if entry.is_storage() || entry.is_root() {
+            assert_eq!(entry.modified(), time(7_000), "{:?}", entry.path());
+        }
+    }
+}
+
+#[test]
+fn touch_explicit_created_time() {
+    let mut comp = make_file();
+    let f_created = created(&comp, "/f");
+    let options = TouchOptions::new()
+        .modified(time(8_000))
+        .created(time(9_000))
+        .ancestors(true);
+    comp.touch_with("/a/e", options).unwrap();
+    for path in ["/a/e", "/a"] {
+        assert_eq!(modified(&comp, path), time(8_000), "{}", path);
+        assert_eq!(created(&comp, path), time(9_000), "{}", path);
+    }
+    // The root's created time must stay zero.
+    assert_eq!(modified(&comp, "/"), time(8_000));
+    assert_eq!(created(&comp, "/"), zero());
+    assert_eq!(created(&comp, "/f"), f_created);
+
+    // The times survive a reopen.
+    let mut comp = CompoundFile::open(comp.into_inner()).unwrap();
+    assert_eq!(created(&comp, "/a/e"), time(9_000));
+    assert_eq!(modified(&comp, "/a"), time(8_000));
+
+    // An out-of-range time changes nothing.
+    let too_early = UNIX_EPOCH - Duration::from_secs(400 * 365 * 86_400);
+    let options = TouchOptions::new().modified(time(1)).created(too_early);
+    let error = comp.touch_with("/a", options).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(modified(&comp, "/a"), time(8_000));
+    let error = comp.touch_with("/missing", TouchOptions::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn recursive_touch_batches_directory_writes() {
+    let inner = CountWrites { inner: Cursor::new(Vec::new()), writes: 0 };
+    let mut comp = CompoundFile::create(inner).unwrap();
+    comp.create_storage("/top").unwrap();
+    for index in 0..1000 {
+        comp.create_storage(format!("/top/{}", index)).unwrap();
+    }
+    comp.flush().unwrap();
+    let mut inner = comp.into_inner();
+    inner.writes = 0;
+    let mut comp = CompoundFile::open(inner).unwrap();
+    let options = TouchOptions::new().modified(time(10_000)).recursive(true);
+    comp.touch_with("/top", options).unwrap();
+    comp.flush().unwrap();
+    assert_eq!(modified_in(&comp, "/top/999"), time(10_000));
+    // 1001 entries fill 32 directory sectors (of 32 entries each), which
+    // are each written once.
+    let writes = comp.into_inner().writes;
+    assert!(writes <= 40, "{} writes", writes);
+}
+
+fn modified_in<F>(comp: &CompoundFile<F>, path: &str) -> SystemTime {
+    comp.entry(path).unwrap().modified()
+}
+
+//===========================================================================//
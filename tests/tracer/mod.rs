@@ -0,0 +1,114 @@
+//! A cursor wrapper for tests that check how much (and where) the `cfb`
+//! crate reads from and writes to its underlying file.  Each test crate uses
+//! only some of it.
+
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+//===========================================================================//
+
+/// The reads and writes made through a [`Tracer`], as `(offset, length)`
+/// pairs and `(offset, data)` pairs.  Cloning a `Trace` gives another handle
+/// to the same log, so that it can be checked while a `CompoundFile` owns
+/// the tracer.
+#[derive(Clone, Default)]
+pub struct Trace(Rc<RefCell<Log>>);
+
+#[derive(Default)]
+struct Log {
+    reads: Vec<(u64, usize)>,
+    writes: Vec<(u64, Vec<u8>)>,
+}
+
+impl Trace {
+    /// Returns the offset and length of each read so far, in order.
+    pub fn reads(&self) -> Vec<(u64, usize)> {
+        self.0.borrow().reads.clone()
+    }
+
+    pub fn num_reads(&self) -> usize {
+        self.0.borrow().reads.len()
+    }
+
+    pub fn num_writes(&self) -> usize {
+        self.0.borrow().writes.len()
+    }
+
+    /// Returns the total length of all writes so far that consisted entirely
+    /// of the given byte value.
+    pub fn bytes_written_of(&self, value: u8) -> usize {
+        self.0
+            .borrow()
+            .writes
+            .iter()
+            .filter(|(_, data)| data.iter().all(|&byte| byte == value))
+            .map(|(_, data)| data.len())
+            .sum()
+    }
+
+    /// Forgets all reads and writes so far.
+    pub fn clear(&self) {
+        let mut log = self.0.borrow_mut();
+        log.reads.clear();
+        log.writes.clear();
+    }
+}
+
+//===========================================================================//
+
+/// A wrapper around an in-memory cursor that records every read and write
+/// made through it in a [`Trace`].
+#[derive(Default)]
+pub struct Tracer {
+    inner: Cursor<Vec<u8>>,
+    trace: Trace,
+}
+
+impl Tracer {
+    /// Returns a tracer over an empty file.
+    pub fn new() -> Tracer {
+        Tracer::default()
+    }
+
+    /// Returns a tracer over a file with the given contents.
+    pub fn with_data(data: Vec<u8>) -> Tracer {
+        Tracer { inner: Cursor::new(data), trace: Trace::default() }
+    }
+
+    /// Returns a handle to this tracer's log.
+    pub fn trace(&self) -> Trace {
+        self.trace.clone()
+    }
+}
+
+impl Read for Tracer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let offset = self.inner.position();
+        let num_bytes = self.inner.read(buf)?;
+        self.trace.0.borrow_mut().reads.push((offset, num_bytes));
+        Ok(num_bytes)
+    }
+}
+
+impl Write for Tracer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let offset = self.inner.position();
+        let num_bytes = self.inner.write(buf)?;
+        let data = buf[..num_bytes].to_vec();
+        self.trace.0.borrow_mut().writes.push((offset, data));
+        Ok(num_bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for Tracer {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
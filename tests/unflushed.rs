@@ -0,0 +1,126 @@
+use cfb::{CompoundFile, StreamsStillOpen};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+//===========================================================================//
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+fn make_file() -> Comp {
+    let mut comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    comp.create_stream("/first").unwrap().write_all(b"first").unwrap();
+    comp.create_stream("/second").unwrap().write_all(b"second").unwrap();
+    comp.flush().unwrap();
+    comp
+}
+
+fn assert_streams_still_open(error: std::io::Error, expected: &[&str]) {
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let still_open = StreamsStillOpen::from_io_error(&error).unwrap();
+    let expected: Vec<PathBuf> = expected.iter().map(PathBuf::from).collect();
+    assert_eq!(still_open.paths(), expected.as_slice());
+}
+
+fn read_stream(comp: &mut Comp, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+//===========================================================================//
+
+#[test]
+fn flush_refuses_unflushed_handles() {
+    let mut comp = make_file();
+    let mut first = comp.open_stream("/first").unwrap();
+    first.write_all(b"FIRST").unwrap();
+    let mut second = comp.open_stream("/second").unwrap();
+    second.write_all(b"SECOND").unwrap();
+    assert_eq!(
+        comp.unflushed_streams(),
+        vec![PathBuf::from("/first"), PathBuf::from("/second")]
+    );
+    let error = comp.flush().unwrap_err();
+    assert_streams_still_open(error, &["/first", "/second"]);
+
+    first.flush().unwrap();
+    let error = comp.flush().unwrap_err();
+    assert_streams_still_open(error, &["/second"]);
+    drop(second);
+    assert!(comp.unflushed_streams().is_empty());
+    comp.flush().unwrap();
+    // The flushed handle is still open, but no longer holds anything back.
+    drop(first);
+
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/first"), b"FIRST");
+    assert_eq!(read_stream(&mut comp, "/second"), b"SECOND");
+}
+
+#[test]
+fn clean_handles_do_not_block_flush() {
+    let mut comp = make_file();
+    let mut stream = comp.open_stream("/first").unwrap();
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).unwrap();
+    assert!(comp.unflushed_streams().is_empty());
+    comp.create_stream("/third").unwrap();
+    comp.flush().unwrap();
+    drop(stream);
+}
+
+#[test]
+fn handles_to_removed_streams_do_not_block_flush() {
+    let mut comp = make_file();
+    let mut stream = comp.open_stream("/first").unwrap();
+    stream.write_all(b"lost").unwrap();
+    comp.remove_stream("/first").unwrap();
+    assert!(comp.unflushed_streams().is_empty());
+    comp.flush().unwrap();
+    drop(stream);
+    assert!(!comp.exists("/first"));
+}
+
+#[test]
+fn compact_refuses_unflushed_handles() {
+    let mut comp = make_file();
+    let mut stream = comp.open_stream("/second").unwrap();
+    stream.write_all(b"more").unwrap();
+    let error = comp.compact().unwrap_err();
+    assert_streams_still_open(error, &["/second"]);
+    drop(stream);
+    comp.compact().unwrap();
+    assert_eq!(read_stream(&mut comp, "/second"), b"morend");
+}
+
+#[test]
+fn unflushed_streams_warns_before_into_inner() {
+    let mut comp = make_file();
+    let mut stream = comp.open_stream("/first").unwrap();
+    stream.write_all(b"gone").unwrap();
+    assert_eq!(comp.unflushed_streams(), vec![PathBuf::from("/first")]);
+    // The handle outlives the compound file, so its write never reaches the
+    // returned file.
+    let cursor = comp.into_inner();
+    drop(stream);
+    let mut comp = CompoundFile::open_strict(cursor).unwrap();
+    assert_eq!(read_stream(&mut comp, "/first"), b"first");
+}
+
+#[test]
+fn leaked_handle_blocks_flush_until_stream_removed() {
+    let mut comp = make_file();
+    let mut stream = comp.open_stream("/first").unwrap();
+    stream.write_all(b"leaked").unwrap();
+    std::mem::forget(stream);
+    let error = comp.flush().unwrap_err();
+    assert_streams_still_open(error, &["/first"]);
+    let error = comp.flush().unwrap_err();
+    assert_streams_still_open(error, &["/first"]);
+    comp.remove_stream("/first").unwrap();
+    comp.flush().unwrap();
+    let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+    assert_eq!(read_stream(&mut comp, "/second"), b"second");
+}
+
+//===========================================================================//
@@ -0,0 +1,230 @@
+use cfb::{
+    CompoundFile, Severity, ValidationIssue, ValidationIssueKind, Version,
+};
+use rawcfb::{fat_entry_offset, named_dir_entry_offset, u32_at};
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+mod rawcfb;
+
+//===========================================================================//
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(29).wrapping_add(seed)).collect()
+}
+
+/// Creates a V3 file with the given streams and returns its bytes.
+fn create(streams: &[(&str, usize)]) -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    for (index, &(path, len)) in streams.iter().enumerate() {
+        let mut stream = comp.create_stream(path).unwrap();
+        stream.write_all(&data(len, index as u8)).unwrap();
+    }
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+fn validate(data: &[u8]) -> Vec<ValidationIssue> {
+    CompoundFile::open(Cursor::new(data.to_vec())).unwrap().validate()
+}
+
+fn kinds(issues: &[ValidationIssue]) -> Vec<ValidationIssueKind> {
+    issues.iter().map(ValidationIssue::kind).collect()
+}
+
+fn worst(issues: &[ValidationIssue]) -> Option<Severity> {
+    issues.iter().map(ValidationIssue::severity).max()
+}
+
+//===========================================================================//
+
+#[test]
+fn valid_file_has_no_issues() {
+    let data = create(&[("/small", 100), ("/big", 5000)]);
+    assert!(validate(&data).is_empty());
+    let comp = CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+    assert!(comp.validate().is_empty());
+}
+
+#[test]
+fn strict_violations_are_warnings() {
+    let mut data = create(&[("/foo", 100)]);
+    data[34] = 1; // Reserved
+    let offset = named_dir_entry_offset(&data, "foo");
+    data[offset + 80] = 1; // CLSID
+    data[offset + 100] = 1; // Creation Time
+    assert!(CompoundFile::open_strict(Cursor::new(data.clone())).is_err());
+
+    let issues = validate(&data);
+    assert_eq!(
+        kinds(&issues),
+        vec![
+            ValidationIssueKind::NonzeroReservedField,
+            ValidationIssueKind::StreamClsid,
+            ValidationIssueKind::StreamTimestamp,
+        ]
+    );
+    assert_eq!(worst(&issues), Some(Severity::Warning));
+    assert_eq!(issues[0].path(), None);
+    assert_eq!(issues[0].stream_id(), None);
+    for issue in &issues[1..] {
+        assert_eq!(issue.path(), Some(Path::new("/foo")));
+        assert_eq!(issue.stream_id().unwrap().value(), 1);
+    }
+}
+
+#[test]
+fn adjacent_red_nodes() {
+    let mut data = create(&[("/a", 10), ("/b", 10), ("/c", 10)]);
+    for name in ["a", "b", "c"] {
+        let offset = named_dir_entry_offset(&data, name);
+        data[offset + 67] = 0; // Red
+    }
+    assert!(CompoundFile::open_strict(Cursor::new(data.clone())).is_err());
+    let issues = validate(&data);
+    assert!(!issues.is_empty());
+    for issue in issues.iter() {
+        assert_eq!(issue.kind(), ValidationIssueKind::AdjacentRedNodes);
+        assert_eq!(issue.severity(), Severity::Warning);
+        assert!(issue.path().is_some());
+    }
+}
+
+#[test]
+fn dangling_child_is_an_error() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp =
+        CompoundFile::create_with_version(Version::V3, cursor).unwrap();
+    comp.create_storage("/a").unwrap();
+    comp.create_stream("/a/x").unwrap().write_all(b"xyzzy").unwrap();
+    comp.create_storage("/b").unwrap();
+    comp.create_stream("/b/y").unwrap().write_all(b"plugh").unwrap();
+    let mut data = comp.into_inner().into_inner();
+    let offset = named_dir_entry_offset(&data, "b") + 76;
+    data[offset..(offset + 4)].copy_from_slice(&1000u32.to_le_bytes());
+    assert!(CompoundFile::open_strict(Cursor::new(data.clone())).is_err());
+
+    let issues = validate(&data);
+    assert_eq!(
+        kinds(&issues),
+        vec![
+            ValidationIssueKind::DanglingChild,
+            ValidationIssueKind::UnreachableEntry,
+        ]
+    );
+    assert_eq!(worst(&issues), Some(Severity::Error));
+    assert_eq!(issues[0].path(), Some(Path::new("/b")));
+    // The unreachable entry has no path, but can still be identified.
+    assert_eq!(issues[1].path(), None);
+    assert!(issues[1].stream_id().is_some());
+    assert!(issues[1].message().contains("\"y\""), "{}", issues[1]);
+}
+
+#[test]
+fn stale_chain_is_orphaned() {
+    let mut data = create(&[("/keep", 4), ("/gone", 5000)]);
+    let keep = named_dir_entry_offset(&data, "keep");
+    let gone = named_dir_entry_offset(&data, "gone");
+    let start_sector = u32_at(&data, gone + 116);
+    // Delete "/gone" without freeing its chain.
+    assert_eq!(u32_at(&data, keep + 68), 2);
+    data[(keep + 68)..(keep + 72)].copy_from_slice(&[0xff; 4]);
+    data[gone + 66] = 0;
+    CompoundFile::open_strict(Cursor::new(data.clone())).unwrap();
+
+    let issues = validate(&data);
+    assert_eq!(
+        kinds(&issues),
+        vec![
+            ValidationIssueKind::OrphanedSectors,
+            ValidationIssueKind::StaleChain
+        ]
+    );
+    assert_eq!(issues[0].severity(), Severity::Warning);
+    assert_eq!(issues[0].sector_id().unwrap().value(), start_sector);
+    assert!(issues[0].message().starts_with("10 sectors"), "{}", issues[0]);
+    assert_eq!(issues[1].severity(), Severity::Info);
+    assert_eq!(issues[1].stream_id().unwrap().value(), 2);
+    assert_eq!(issues[1].sector_id().unwrap().value(), start_sector);
+}
+
+#[test]
+fn cross_linked_sectors_are_errors() {
+    let mut data = create(&[("/a", 5000), ("/b", 5000)]);
+    let a = named_dir_entry_offset(&data, "a");
+    let b = named_dir_entry_offset(&data, "b");
+    // Point "/b" into the middle of the chain of "/a", leaving the rest of
+    // the chain of "/b" orphaned.
+    let a_start = u32_at(&data, a + 116);
+    let a_second = u32_at(&data, fat_entry_offset(&data, a_start));
+    data[(b + 116)..(b + 120)].copy_from_slice(&a_second.to_le_bytes());
+    data[(b + 120)..(b + 124)].copy_from_slice(&4500u32.to_le_bytes());
+    // Opening doesn't follow chains far enough to notice.
+    CompoundFile::open_strict(Cursor::new(data.clone())).unwrap();
+
+    let issues = validate(&data);
+    let crossed: Vec<_> = issues
+        .iter()
+        .filter(|issue| issue.kind() == ValidationIssueKind::CrossLinkedSector)
+        .collect();
+    assert_eq!(crossed.len(), 9, "{:?}", issues);
+    assert_eq!(crossed[0].sector_id().unwrap().value(), a_second);
+    assert!(kinds(&issues).contains(&ValidationIssueKind::OrphanedSectors));
+    assert_eq!(worst(&issues), Some(Severity::Error));
+}
+
+#[test]
+fn shared_chains_are_info() {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create(cursor).unwrap();
+    comp.create_stream_dedup("/one", &data(5000, 1)).unwrap();
+    comp.create_stream_dedup("/two", &data(5000, 1)).unwrap();
+    comp.flush().unwrap();
+    let issues = comp.validate();
+    assert_eq!(
+        kinds(&issues),
+        vec![
+            ValidationIssueKind::SharedChain,
+            ValidationIssueKind::SharedChain
+        ]
+    );
+    assert_eq!(worst(&issues), Some(Severity::Info));
+    let mut paths: Vec<_> =
+        issues.iter().map(|issue| issue.path().unwrap()).collect();
+    paths.sort();
+    assert_eq!(paths, vec![Path::new("/one"), Path::new("/two")]);
+    CompoundFile::open_strict(comp.into_inner()).unwrap();
+}
+
+#[test]
+fn validate_reflects_later_changes() {
+    let bytes = create(&[("/a", 100)]);
+    let mut comp = CompoundFile::open(Cursor::new(bytes)).unwrap();
+    comp.create_stream_dedup("/b", &data(5000, 0)).unwrap();
+    comp.create_stream_dedup("/c", &data(5000, 0)).unwrap();
+    assert_eq!(
+        kinds(&comp.validate()),
+        vec![
+            ValidationIssueKind::SharedChain,
+            ValidationIssueKind::SharedChain
+        ]
+    );
+    comp.remove_stream("/b").unwrap();
+    assert!(comp.validate().is_empty(), "{:?}", comp.validate());
+}
+
+#[test]
+fn severity_is_ordered() {
+    assert!(Severity::Info < Severity::Warning);
+    assert!(Severity::Warning < Severity::Error);
+    assert_eq!(ValidationIssueKind::ChainSlack.severity(), Severity::Info);
+    assert_eq!(ValidationIssueKind::BrokenChain.severity(), Severity::Error);
+    assert_eq!(
+        ValidationIssueKind::DifatZeroPadding.severity(),
+        Severity::Warning
+    );
+}
+
+//===========================================================================//
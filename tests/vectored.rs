@@ -0,0 +1,276 @@
+use cfb::{CompoundFile, Version};
+use std::cell::Cell;
+use std::io::{
+    self, Cursor, IoSlice, IoSliceMut, Read, Seek, SeekFrom, Write,
+};
+use std::rc::Rc;
+
+//===========================================================================//
+
+/// A wrapper around a cursor that counts the reads and writes made to it,
+/// and that can be made to accept only a few bytes per write.  The counts are
+/// shared so that they can be checked while a `CompoundFile` owns the file.
+struct TracingFile {
+    inner: Cursor<Vec<u8>>,
+    num_reads: Rc<Cell<usize>>,
+    num_writes: Rc<Cell<usize>>,
+    max_write: usize,
+}
+
+impl TracingFile {
+    fn new(max_write: usize) -> TracingFile {
+        TracingFile {
+            inner: Cursor::new(Vec::new()),
+            num_reads: Rc::new(Cell::new(0)),
+            num_writes: Rc::new(Cell::new(0)),
+            max_write,
+        }
+    }
+}
+
+impl Read for TracingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.num_reads.set(self.num_reads.get() + 1);
+        self.inner.read(buf)
+    }
+}
+
+impl Write for TracingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.num_writes.set(self.num_writes.get() + 1);
+        let len = buf.len().min(self.max_write);
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for TracingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+}
+
+/// Creates an empty compound file, returning it along with its read and
+/// write counters.
+fn make_traced_comp(
+    max_write: usize,
+) -> (CompoundFile<TracingFile>, Rc<Cell<usize>>, Rc<Cell<usize>>) {
+    let file = TracingFile::new(max_write);
+    let num_reads = Rc::clone(&file.num_reads);
+    let num_writes = Rc::clone(&file.num_writes);
+    let comp = CompoundFile::create_with_version(Version::V3, file).unwrap();
+    (comp, num_reads, num_writes)
+}
+
+fn make_comp(max_write: usize) -> CompoundFile<TracingFile> {
+    make_traced_comp(max_write).0
+}
+
+/// Writes `prefix` to a new stream, then the given pieces, either one at a
+/// time or as a single vectored write, and returns the stream's contents.
+fn write_pieces(
+    comp: &mut CompoundFile<TracingFile>,
+    prefix: &[u8],
+    pieces: &[Vec<u8>],
+    vectored: bool,
+) -> Vec<u8> {
+    let mut stream = comp.create_stream("/stream").unwrap();
+    stream.write_all(prefix).unwrap();
+    stream.flush().unwrap();
+    if vectored {
+        let slices: Vec<IoSlice> =
+            pieces.iter().map(|piece| IoSlice::new(piece)).collect();
+        let total: usize = pieces.iter().map(|piece| piece.len()).sum();
+        assert_eq!(stream.write_vectored(&slices).unwrap(), total);
+    } else {
+        for piece in pieces {
+            stream.write_all(piece).unwrap();
+        }
+    }
+    drop(stream);
+    let mut contents = Vec::new();
+    comp.open_stream("/stream").unwrap().read_to_end(&mut contents).unwrap();
+    assert!(comp.validate().is_empty());
+    contents
+}
+
+fn assert_vectored_write_matches(
+    max_write: usize,
+    prefix: &[u8],
+    pieces: &[Vec<u8>],
+) {
+    let scalar =
+        write_pieces(&mut make_comp(max_write), prefix, pieces, false);
+    let vectored =
+        write_pieces(&mut make_comp(max_write), prefix, pieces, true);
+    assert_eq!(vectored.len(), scalar.len());
+    assert!(vectored == scalar, "vectored write differs from scalar write");
+}
+
+//===========================================================================//
+
+#[test]
+fn vectored_write_matches_scalar() {
+    let cases: &[(usize, &[usize])] = &[
+        (0, &[10, 0, 20, 0, 0, 30]),
+        (0, &[3000, 2000, 0, 5000]),
+        (0, &[0, 9000, 0, 1, 10000]),
+        (100, &[4000, 4000, 4000, 4000]),
+        (8000, &[50; 200]),
+    ];
+    for &(prefix_len, lens) in cases {
+        let prefix = data(prefix_len, 7);
+        let pieces: Vec<Vec<u8>> = lens
+            .iter()
+            .enumerate()
+            .map(|(index, &len)| data(len, index as u8))
+            .collect();
+        assert_vectored_write_matches(usize::MAX, &prefix, &pieces);
+    }
+}
+
+#[test]
+fn vectored_write_across_mini_stream_cutoff() {
+    // The stream starts out in the mini stream, and the write takes it past
+    // the cutoff partway through the slices.
+    let prefix = data(3000, 1);
+    let pieces = vec![data(500, 2), Vec::new(), data(1000, 3), data(8000, 4)];
+    assert_vectored_write_matches(usize::MAX, &prefix, &pieces);
+    let mut comp = make_comp(usize::MAX);
+    write_pieces(&mut comp, &prefix, &pieces, true);
+    assert_eq!(comp.entry("/stream").unwrap().len(), 12500);
+    assert_eq!(comp.entry("/stream").unwrap().chain_len(), 12800);
+}
+
+#[test]
+fn vectored_write_with_short_backing_writes() {
+    let pieces = vec![data(5000, 1), Vec::new(), data(7000, 2), data(3, 3)];
+    assert_vectored_write_matches(100, &data(2000, 9), &pieces);
+    assert_vectored_write_matches(1, &[], &pieces);
+}
+
+#[test]
+fn vectored_write_of_nothing() {
+    let mut comp = make_comp(usize::MAX);
+    let mut stream = comp.create_stream("/stream").unwrap();
+    let slices = [IoSlice::new(&[]), IoSlice::new(&[])];
+    assert_eq!(stream.write_vectored(&slices).unwrap(), 0);
+    assert_eq!(stream.write_vectored(&[]).unwrap(), 0);
+    assert_eq!(stream.len(), 0);
+}
+
+#[test]
+fn vectored_write_makes_fewer_writes() {
+    let pieces: Vec<Vec<u8>> =
+        (0..64).map(|index| data(4096, index as u8)).collect();
+    let slices: Vec<IoSlice> =
+        pieces.iter().map(|piece| IoSlice::new(piece)).collect();
+    let mut num_writes = Vec::new();
+    for vectored in [false, true] {
+        let (mut comp, _, counter) = make_traced_comp(usize::MAX);
+        let mut stream = comp.create_stream("/stream").unwrap();
+        counter.set(0);
+        if vectored {
+            assert_eq!(stream.write_vectored(&slices).unwrap(), 1 << 18);
+        } else {
+            for piece in pieces.iter() {
+                stream.write_all(piece).unwrap();
+            }
+        }
+        stream.flush().unwrap();
+        num_writes.push(counter.get());
+    }
+    assert!(
+        num_writes[1] < num_writes[0],
+        "vectored: {} writes, scalar: {} writes",
+        num_writes[1],
+        num_writes[0]
+    );
+}
+
+#[test]
+fn vectored_read_matches_scalar() {
+    let mut comp = make_comp(usize::MAX);
+    let expected = data(100_000, 5);
+    comp.create_stream("/stream").unwrap().write_all(&expected).unwrap();
+    let lens: &[usize] = &[0, 10, 5000, 0, 20_000, 1, 30_000];
+    let total: usize = lens.iter().sum();
+    // Start partway through the stream, so the read spans the end of it.
+    let start = expected.len() - total + 1000;
+    let mut pieces: Vec<Vec<u8>> =
+        lens.iter().map(|&len| vec![0; len]).collect();
+    let mut stream = comp.open_stream("/stream").unwrap();
+    stream.seek(SeekFrom::Start(start as u64)).unwrap();
+    let mut slices: Vec<IoSliceMut> =
+        pieces.iter_mut().map(|piece| IoSliceMut::new(piece)).collect();
+    let num_bytes = stream.read_vectored(&mut slices).unwrap();
+    assert_eq!(num_bytes, total - 1000);
+    let actual: Vec<u8> = pieces.concat();
+    assert!(actual[..num_bytes] == expected[start..]);
+    assert_eq!(stream.stream_position().unwrap(), expected.len() as u64);
+    let mut slices = [IoSliceMut::new(&mut pieces[2])];
+    assert_eq!(stream.read_vectored(&mut slices).unwrap(), 0);
+}
+
+#[test]
+fn vectored_read_after_buffered_read() {
+    let mut comp = make_comp(usize::MAX);
+    let expected = data(50_000, 6);
+    comp.create_stream("/stream").unwrap().write_all(&expected).unwrap();
+    let mut stream = comp.open_stream("/stream").unwrap();
+    let mut first = [0u8; 100];
+    stream.read_exact(&mut first).unwrap();
+    let mut actual = first.to_vec();
+    while actual.len() < expected.len() {
+        let mut a = vec![0u8; 7000];
+        let mut b = vec![0u8; 3000];
+        let mut slices = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        let num_bytes = stream.read_vectored(&mut slices).unwrap();
+        assert!(num_bytes > 0);
+        let mut both = [a, b].concat();
+        both.truncate(num_bytes);
+        actual.extend_from_slice(&both);
+    }
+    assert!(actual == expected);
+}
+
+#[test]
+fn vectored_read_makes_fewer_reads() {
+    let (mut comp, counter, _) = make_traced_comp(usize::MAX);
+    comp.create_stream("/stream")
+        .unwrap()
+        .write_all(&data(1 << 18, 8))
+        .unwrap();
+    let mut num_reads = Vec::new();
+    for vectored in [false, true] {
+        let mut pieces: Vec<Vec<u8>> = vec![vec![0; 4096]; 64];
+        let mut stream = comp.open_stream("/stream").unwrap();
+        counter.set(0);
+        if vectored {
+            let mut slices: Vec<IoSliceMut> = pieces
+                .iter_mut()
+                .map(|piece| IoSliceMut::new(piece))
+                .collect();
+            assert_eq!(stream.read_vectored(&mut slices).unwrap(), 1 << 18);
+        } else {
+            for piece in pieces.iter_mut() {
+                stream.read_exact(piece).unwrap();
+            }
+        }
+        num_reads.push(counter.get());
+        assert!(pieces.concat() == data(1 << 18, 8));
+    }
+    assert!(
+        num_reads[1] < num_reads[0],
+        "vectored: {} reads, scalar: {} reads",
+        num_reads[1],
+        num_reads[0]
+    );
+}
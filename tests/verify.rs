@@ -0,0 +1,321 @@
+use cfb::{CompoundFile, ResumeToken, VerifyOptions, VerifyReport, Version};
+use rawcfb::dir_entry_offset;
+use std::cell::RefCell;
+use std::io::{Cursor, ErrorKind, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+mod rawcfb;
+
+//===========================================================================//
+
+/// Creates a V3 file with a mix of large and small streams, two of which
+/// ("/a" and "/dir/same") have identical contents.
+fn make_file() -> Vec<u8> {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(Version::V3, cursor)
+        .expect("create");
+    comp.create_stream("/a").unwrap().write_all(&[1; 6000]).unwrap();
+    comp.create_stream("/b").unwrap().write_all(&[2; 100]).unwrap();
+    comp.create_stream("/c").unwrap().write_all(&[3; 5000]).unwrap();
+    comp.create_storage("/dir").unwrap();
+    comp.create_stream("/dir/same").unwrap().write_all(&[1; 6000]).unwrap();
+    comp.create_stream("/dir/empty").unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Like `make_file`, but with a stream long enough to take several chunks
+/// to verify.
+fn make_big_file() -> Vec<u8> {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    comp.create_stream("/big").unwrap().write_all(&data).unwrap();
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Returns everything about each stream in a report apart from its error.
+fn summarize(
+    report: &VerifyReport,
+) -> Vec<(PathBuf, u64, u64, Option<u64>, bool)> {
+    report
+        .streams()
+        .iter()
+        .map(|stream| {
+            (
+                stream.path().to_path_buf(),
+                stream.len(),
+                stream.bytes_read(),
+                stream.hash(),
+                stream.error().is_none(),
+            )
+        })
+        .collect()
+}
+
+/// Verifies the file, stopping once the given function returns true for a
+/// checkpoint token, and then carries on from the token, checking that the
+/// two runs together match an uninterrupted one.  Returns the token.
+fn check_resume<G>(data: Vec<u8>, stop_at: G) -> ResumeToken
+where
+    G: Fn(&ResumeToken) -> bool + 'static,
+{
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let whole = comp.verify_deep(VerifyOptions::new().hash_streams(true));
+    assert!(whole.is_ok());
+    assert!(whole.resume_token().is_none());
+
+    let first = comp.verify_deep(
+        VerifyOptions::new().hash_streams(true).checkpoint(move |token| {
+            if stop_at(token) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }),
+    );
+    assert!(first.is_cancelled());
+    let token = first.resume_token().unwrap().clone();
+
+    let total = whole.bytes_read();
+    let skipped: u64 = first.streams().iter().map(|s| s.len()).sum();
+    let remaining = total - skipped;
+    let second = comp
+        .verify_deep_from(
+            &token,
+            VerifyOptions::new().hash_streams(true).progress(
+                move |done, total_len| {
+                    // Skipped streams count as done.
+                    assert!(done > total - remaining);
+                    assert_eq!(total_len, total);
+                    true
+                },
+            ),
+        )
+        .unwrap();
+    assert!(second.is_ok());
+    assert!(second.resume_token().is_none());
+
+    let mut combined = summarize(&first);
+    combined.extend(summarize(&second));
+    assert_eq!(combined, summarize(&whole));
+    assert_eq!(skipped + second.bytes_read(), whole.bytes_read());
+    token
+}
+
+//===========================================================================//
+
+#[test]
+fn verify_good_file() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let report = comp.verify_deep(VerifyOptions::new().hash_streams(true));
+    assert!(report.is_ok(), "{:?}", report);
+    assert!(report.structure_problems().is_empty());
+    assert_eq!(report.streams().len(), 5);
+    assert_eq!(report.bytes_read(), 17100);
+    assert_eq!(report.failed_streams().count(), 0);
+    for stream in report.streams() {
+        assert_eq!(stream.bytes_read(), stream.len());
+        assert!(stream.hash().is_some());
+    }
+    let hash = |path: &str| {
+        let stream =
+            report.streams().iter().find(|s| s.path() == Path::new(path));
+        stream.unwrap().hash().unwrap()
+    };
+    assert_eq!(hash("/a"), hash("/dir/same"));
+    assert_ne!(hash("/a"), hash("/c"));
+}
+
+#[test]
+fn unreadable_stream_is_isolated() {
+    let mut data = make_file();
+    // Point "/c" (entry 3) at a sector past the end of the file.
+    let offset = dir_entry_offset(&data, 3);
+    assert_eq!(&data[offset..(offset + 4)], b"c\0\0\0");
+    data[(offset + 116)..(offset + 120)]
+        .copy_from_slice(&1000u32.to_le_bytes());
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let report = comp.verify_deep(VerifyOptions::new().hash_streams(true));
+    assert!(!report.is_ok());
+    assert_eq!(report.streams().len(), 5);
+    let failed: Vec<&Path> =
+        report.failed_streams().map(|stream| stream.path()).collect();
+    assert_eq!(failed, vec![Path::new("/c")]);
+    let c = report.failed_streams().next().unwrap();
+    assert_eq!(c.bytes_read(), 0);
+    assert!(c.hash().is_none());
+    assert!(c.error().is_some());
+    // Every other stream was still read in full.
+    assert_eq!(report.bytes_read(), 12100);
+    // The orphaned chain that "/c" used to point at is reported too.
+    let problems = report.structure_problems();
+    assert!(problems.iter().any(|problem| problem.contains("1000")));
+    assert!(problems.iter().any(|problem| problem.contains("not in any")));
+}
+
+#[test]
+fn truncated_stream_is_reported() {
+    let mut data = make_file();
+    // Claim "/a" (entry 1) is longer than its chain.
+    let offset = dir_entry_offset(&data, 1);
+    data[(offset + 120)..(offset + 128)]
+        .copy_from_slice(&6200u64.to_le_bytes());
+    let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+    let report = comp.verify_deep(VerifyOptions::new());
+    let failed: Vec<&Path> =
+        report.failed_streams().map(|stream| stream.path()).collect();
+    assert_eq!(failed, vec![Path::new("/a")]);
+    let a = report.failed_streams().next().unwrap();
+    assert_eq!(a.len(), 6200);
+    assert!(a.bytes_read() < 6200);
+    assert!(a.error().is_some());
+}
+
+#[test]
+fn progress_can_cancel() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let mut calls = Vec::new();
+    let report =
+        comp.verify_deep(VerifyOptions::new().progress(move |done, total| {
+            calls.push(done);
+            assert_eq!(total, 17100);
+            calls.len() < 2
+        }));
+    assert!(report.is_cancelled());
+    assert!(!report.is_ok());
+    assert_eq!(report.streams().len(), 1);
+    assert_eq!(report.bytes_read(), 6100);
+}
+
+//===========================================================================//
+
+#[test]
+fn resume_after_each_number_of_streams() {
+    for num_done in 1..5 {
+        let token = check_resume(make_file(), move |token| {
+            token.offset() == 0 && {
+                // Each finished stream gives one token with a zero offset,
+                // so count them by their paths' position in walk order.
+                let paths = ["/a", "/b", "/c", "/dir/empty", "/dir/same"];
+                let last = token.last_done().unwrap();
+                paths.iter().position(|&p| Path::new(p) == last).unwrap() + 1
+                    == num_done
+            }
+        });
+        assert_eq!(token.offset(), 0);
+    }
+}
+
+#[test]
+fn resume_from_middle_of_stream() {
+    // "/big" comes right after "/c" in walk order.
+    let token = check_resume(make_big_file(), |token| {
+        token.last_done() == Some(Path::new("/c")) && token.offset() >= 100_000
+    });
+    assert_eq!(token.last_done(), Some(Path::new("/c")));
+    assert!(token.offset() >= 100_000 && token.offset() < 300_000);
+}
+
+#[test]
+fn resume_from_start() {
+    let token = check_resume(make_big_file(), |token| token.offset() > 0);
+    assert_eq!(token.last_done(), None);
+}
+
+#[test]
+fn progress_cancel_gives_resume_token() {
+    let mut comp = CompoundFile::open(Cursor::new(make_big_file())).unwrap();
+    let report =
+        comp.verify_deep(VerifyOptions::new().progress(|done, _| done < 6000));
+    let token = report.resume_token().unwrap();
+    assert_eq!(token.last_done(), None);
+    assert_eq!(token.offset(), 6000);
+    let resumed = comp.verify_deep_from(token, VerifyOptions::new()).unwrap();
+    assert_eq!(resumed.streams().len(), 6);
+}
+
+#[test]
+fn checkpoints_are_reported_in_order() {
+    let mut comp = CompoundFile::open(Cursor::new(make_big_file())).unwrap();
+    let tokens = Rc::new(RefCell::new(Vec::new()));
+    let seen = tokens.clone();
+    let report = comp.verify_deep(VerifyOptions::new().checkpoint(move |t| {
+        seen.borrow_mut().push(t.clone());
+        ControlFlow::Continue(())
+    }));
+    assert!(report.is_ok());
+    let tokens = tokens.borrow();
+    let finished: Vec<&Path> = tokens
+        .iter()
+        .filter(|token| token.offset() == 0)
+        .map(|token| token.last_done().unwrap())
+        .collect();
+    let walked: Vec<&Path> =
+        report.streams().iter().map(|stream| stream.path()).collect();
+    assert_eq!(finished, walked);
+    // Within each stream, the offsets count up to its length, and the
+    // stream is then reported as done.
+    let mut offset = 0;
+    for token in tokens.iter() {
+        if token.offset() == 0 {
+            let path = token.last_done().unwrap();
+            assert_eq!(offset, comp.entry(path).unwrap().len());
+        } else {
+            assert!(token.offset() > offset);
+        }
+        offset = token.offset();
+    }
+}
+
+#[test]
+fn changed_file_invalidates_token() {
+    let mut comp = CompoundFile::open(Cursor::new(make_file())).unwrap();
+    let report = comp.verify_deep(VerifyOptions::new().checkpoint(|token| {
+        if token.offset() == 0 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }));
+    let token = report.resume_token().unwrap().clone();
+    assert_eq!(token.last_done(), Some(Path::new("/a")));
+
+    // Overwriting data in place doesn't change the file's structure.
+    comp.open_stream("/c").unwrap().write_all(&[4; 100]).unwrap();
+    comp.verify_deep_from(&token, VerifyOptions::new()).unwrap();
+
+    // Growing a stream does.
+    comp.open_stream("/b").unwrap().write_all(&[5; 200]).unwrap();
+    let error =
+        comp.verify_deep_from(&token, VerifyOptions::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    // So does reopening a different file.
+    let mut other = CompoundFile::open(Cursor::new(make_big_file())).unwrap();
+    let error =
+        other.verify_deep_from(&token, VerifyOptions::new()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn resume_token_round_trips_through_serde() {
+    let mut comp = CompoundFile::open(Cursor::new(make_big_file())).unwrap();
+    let report = comp.verify_deep(VerifyOptions::new().checkpoint(|token| {
+        if token.offset() > 0x10000 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }));
+    let token = report.resume_token().unwrap();
+    let json = serde_json::to_string(token).unwrap();
+    let parsed: ResumeToken = serde_json::from_str(&json).unwrap();
+    assert_eq!(&parsed, token);
+    let resumed = comp.verify_deep_from(&parsed, VerifyOptions::new());
+    assert!(resumed.unwrap().is_ok());
+}
+
+//===========================================================================//
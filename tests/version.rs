@@ -0,0 +1,80 @@
+use cfb::{CompoundFile, RequiresVersion4, Version};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+//===========================================================================//
+
+fn create(version: Version) -> CompoundFile<Cursor<Vec<u8>>> {
+    CompoundFile::create_with_version(version, Cursor::new(Vec::new()))
+        .unwrap()
+}
+
+//===========================================================================//
+
+#[test]
+fn version_predicates() {
+    let v3 = Version::V3;
+    assert!(!v3.is_version_4());
+    assert!(!v3.supports_large_streams());
+    assert!(!v3.requires_dir_sector_count());
+    assert_eq!(v3.sector_shift(), 9);
+    assert_eq!(v3.stream_len_mask(), 0xffffffff);
+    let v4 = Version::V4;
+    assert!(v4.is_version_4());
+    assert!(v4.supports_large_streams());
+    assert!(v4.requires_dir_sector_count());
+    assert_eq!(v4.sector_shift(), 12);
+    assert_eq!(v4.stream_len_mask(), u64::MAX);
+}
+
+#[test]
+fn compound_file_predicates_match_version() {
+    for version in [Version::V3, Version::V4] {
+        let comp = create(version);
+        let comp = CompoundFile::open(comp.into_inner()).unwrap();
+        assert_eq!(comp.version(), version);
+        assert_eq!(comp.is_version_4(), version.is_version_4());
+        assert_eq!(comp.sector_shift(), version.sector_shift());
+        assert_eq!(1 << comp.sector_shift(), comp.sector_len());
+        assert_eq!(
+            comp.supports_large_streams(),
+            version.supports_large_streams()
+        );
+        assert_eq!(
+            comp.requires_dir_sector_count(),
+            version.requires_dir_sector_count()
+        );
+    }
+}
+
+#[test]
+fn dir_sector_count_only_written_when_required() {
+    for version in [Version::V3, Version::V4] {
+        let mut comp = create(version);
+        for index in 0..40 {
+            comp.create_stream(format!("/s{}", index)).unwrap();
+        }
+        comp.flush().unwrap();
+        let mut cursor = comp.into_inner();
+        cursor.seek(SeekFrom::Start(40)).unwrap();
+        let mut field = [0u8; 4];
+        cursor.read_exact(&mut field).unwrap();
+        let num_dir_sectors = u32::from_le_bytes(field);
+        if version.requires_dir_sector_count() {
+            assert_eq!(num_dir_sectors, 2);
+        } else {
+            assert_eq!(num_dir_sectors, 0);
+        }
+    }
+}
+
+#[test]
+fn large_stream_in_v3_requires_version_4() {
+    let mut comp = create(Version::V3);
+    comp.create_stream("/foo").unwrap();
+    let mut stream = comp.open_stream("/foo").unwrap();
+    let error = stream.set_len(0x80000001).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    let requires = RequiresVersion4::from_io_error(&error).unwrap();
+    assert_eq!(requires.capability(), "streams longer than 2 GiB");
+    assert_eq!(stream.len(), 0);
+}
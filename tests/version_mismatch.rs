@@ -0,0 +1,133 @@
+use cfb::{CompoundFile, Severity, ValidationIssueKind, Version};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+//===========================================================================//
+
+/// A version 3 file, byte-patched so that its header says version 4, as one
+/// library briefly wrote them.
+const V4_WITH_SHIFT_9: &str =
+    "tests/old_exporters/v4_header_with_512_byte_sectors";
+
+/// A version 4 file, byte-patched so that its header says version 3.
+const V3_WITH_SHIFT_12: &str =
+    "tests/old_exporters/v3_header_with_4096_byte_sectors";
+
+/// Each fixture, the version that its header declares, and the version that
+/// its sector shift belongs to.
+const FIXTURES: [(&str, u16, Version); 2] =
+    [(V4_WITH_SHIFT_9, 4, Version::V3), (V3_WITH_SHIFT_12, 3, Version::V4)];
+
+type Comp = CompoundFile<Cursor<Vec<u8>>>;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_stream(comp: &mut Comp, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Checks that the compound file has the fixtures' objects and contents.
+fn check_contents(comp: &mut Comp) {
+    let paths: Vec<PathBuf> =
+        comp.walk().map(|entry| entry.path().to_path_buf()).collect();
+    let expected = ["/", "/Data", "/Objects", "/Objects/Contents", "/Summary"];
+    assert_eq!(paths, expected.iter().map(PathBuf::from).collect::<Vec<_>>());
+    assert!(comp.is_storage("/Objects"));
+    assert_eq!(
+        read_stream(comp, "/Objects/Contents"),
+        (0..200).map(|i| i as u8).collect::<Vec<u8>>()
+    );
+    assert_eq!(
+        read_stream(comp, "/Data"),
+        (0..6000u32).map(|i| (i * 7) as u8).collect::<Vec<u8>>()
+    );
+    assert_eq!(read_stream(comp, "/Summary"), b"summary");
+}
+
+//===========================================================================//
+
+#[test]
+fn strict_rejects_mismatch() {
+    for (path, declared, actual) in FIXTURES {
+        let data = std::fs::read(path).unwrap();
+        let error = CompoundFile::open_strict(Cursor::new(data)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let expected = format!(
+            "Incorrect sector shift for CFB version {} (expected {}, found \
+             {}, which is the sector shift for version {})",
+            declared,
+            Version::from_number(declared).unwrap().sector_shift(),
+            actual.sector_shift(),
+            actual.number()
+        );
+        assert_eq!(error.to_string(), expected);
+    }
+}
+
+#[test]
+fn permissive_trusts_sector_shift() {
+    for (path, declared, actual) in FIXTURES {
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(u16_at(&data, 26), declared);
+        let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+        assert_eq!(comp.version(), actual);
+        assert_eq!(comp.sector_len(), actual.sector_len());
+        let kinds: Vec<ValidationIssueKind> =
+            comp.open_warnings().iter().map(|issue| issue.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![ValidationIssueKind::VersionSectorShiftMismatch]
+        );
+        let issues = comp.validate();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity(), Severity::Warning);
+        check_contents(&mut comp);
+    }
+}
+
+#[test]
+fn flush_rewrites_consistent_header() {
+    for (path, _, actual) in FIXTURES {
+        let original = std::fs::read(path).unwrap();
+        let mut comp =
+            CompoundFile::open(Cursor::new(original.clone())).unwrap();
+        comp.flush().unwrap();
+        let data = comp.into_inner().into_inner();
+        assert_eq!(u16_at(&data, 26), actual.number());
+        assert_eq!(u16_at(&data, 30), actual.sector_shift());
+        // Nothing but the version field needed to change.
+        let changed: Vec<usize> = (0..data.len())
+            .filter(|&index| data[index] != original[index])
+            .collect();
+        assert_eq!(changed, vec![26]);
+
+        let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+        assert!(comp.open_warnings().is_empty());
+        assert_eq!(comp.version(), actual);
+        check_contents(&mut comp);
+    }
+}
+
+#[test]
+fn modified_file_gets_consistent_header() {
+    for (path, _, actual) in FIXTURES {
+        let data = std::fs::read(path).unwrap();
+        let mut comp = CompoundFile::open(Cursor::new(data)).unwrap();
+        // Enough new entries to need another directory sector.
+        for index in 0..40 {
+            let path = format!("/Objects/new{}", index);
+            comp.create_stream(&path).unwrap().write_all(b"new").unwrap();
+        }
+        comp.flush().unwrap();
+        let mut comp = CompoundFile::open_strict(comp.into_inner()).unwrap();
+        assert_eq!(comp.version(), actual);
+        assert_eq!(read_stream(&mut comp, "/Objects/new39"), b"new");
+        assert_eq!(read_stream(&mut comp, "/Summary"), b"summary");
+    }
+}
+
+//===========================================================================//
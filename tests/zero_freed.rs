@@ -0,0 +1,135 @@
+use cfb::{CompoundFile, FreeEntryPolicy, Version};
+use std::io::{Cursor, Read, Write};
+
+//===========================================================================//
+
+type TestFile = CompoundFile<Cursor<Vec<u8>>>;
+
+const MARKER: &[u8] = b"<<TOP-SECRET-MARKER>>";
+
+/// Returns `len` bytes of stream data with the marker repeated throughout.
+fn marked(len: usize) -> Vec<u8> {
+    MARKER.iter().copied().cycle().take(len).collect()
+}
+
+fn utf16(string: &str) -> Vec<u8> {
+    string.encode_utf16().flat_map(|chr| chr.to_le_bytes()).collect()
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+fn create(version: Version, zero_freed: bool) -> TestFile {
+    let cursor = Cursor::new(Vec::new());
+    let mut comp = CompoundFile::create_with_version(version, cursor).unwrap();
+    comp.set_zero_freed_sectors(zero_freed);
+    assert_eq!(comp.zero_freed_sectors(), zero_freed);
+    comp
+}
+
+fn write_stream(comp: &mut TestFile, path: &str, data: &[u8]) {
+    comp.create_stream(path).unwrap().write_all(data).unwrap();
+}
+
+fn read_stream(comp: &mut TestFile, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    comp.open_stream(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Flushes the file and returns the raw bytes of the underlying buffer.
+fn raw_bytes(mut comp: TestFile) -> Vec<u8> {
+    comp.flush().unwrap();
+    comp.into_inner().into_inner()
+}
+
+/// Writes a marked stream in the mini stream and one in regular sectors
+/// (alongside a stream that is kept), then removes the marked ones.
+fn remove_marked_streams(zero_freed: bool) -> Vec<u8> {
+    let mut comp = create(Version::V3, zero_freed);
+    write_stream(&mut comp, "/keep", &[1; 100]);
+    write_stream(&mut comp, "/mini", &marked(1000));
+    write_stream(&mut comp, "/big", &marked(20_000));
+    write_stream(&mut comp, "/after", &[2; 100]);
+    comp.remove_stream("/mini").unwrap();
+    comp.remove_stream("/big").unwrap();
+    assert_eq!(read_stream(&mut comp, "/keep"), [1; 100]);
+    assert_eq!(read_stream(&mut comp, "/after"), [2; 100]);
+    raw_bytes(comp)
+}
+
+//===========================================================================//
+
+#[test]
+fn removed_streams_leave_no_trace() {
+    // Without the option, the removed data is still in the file.
+    assert!(contains(&remove_marked_streams(false), MARKER));
+    let data = remove_marked_streams(true);
+    assert!(!contains(&data, MARKER));
+    assert!(!contains(&data, &utf16("mini")));
+    assert!(!contains(&data, &utf16("big")));
+    let mut comp = CompoundFile::open_strict(Cursor::new(data)).unwrap();
+    assert_eq!(read_stream(&mut comp, "/keep"), [1; 100]);
+}
+
+#[test]
+fn truncated_streams_leave_no_trace() {
+    for version in [Version::V3, Version::V4] {
+        let mut comp = create(version, true);
+        let mut data = vec![7; 10_000];
+        data.extend_from_slice(&marked(10_000));
+        write_stream(&mut comp, "/big", &data);
+        let mut mini = vec![8; 100];
+        mini.extend_from_slice(&marked(200));
+        write_stream(&mut comp, "/mini", &mini);
+        let mut moved = vec![9; 1000];
+        moved.extend_from_slice(&marked(8000));
+        write_stream(&mut comp, "/moved", &moved);
+
+        // Shrink within regular sectors, within the mini stream, and from
+        // regular sectors into the mini stream, each cutting part-way
+        // through a (mini) sector.
+        comp.open_stream("/big").unwrap().set_len(10_000).unwrap();
+        comp.open_stream("/mini").unwrap().set_len(100).unwrap();
+        comp.open_stream("/moved").unwrap().set_len(1000).unwrap();
+        assert_eq!(read_stream(&mut comp, "/big"), [7; 10_000]);
+        assert_eq!(read_stream(&mut comp, "/mini"), [8; 100]);
+        assert_eq!(read_stream(&mut comp, "/moved"), [9; 1000]);
+        assert!(!contains(&raw_bytes(comp), MARKER), "{:?}", version);
+    }
+}
+
+#[test]
+fn removed_storages_leave_no_trace() {
+    let mut comp = create(Version::V3, true);
+    // Zeroing freed space overrides a policy of preserving removed entries.
+    comp.set_free_entry_policy(FreeEntryPolicy::Preserve);
+    comp.create_storage_all("/outer/inner").unwrap();
+    write_stream(&mut comp, "/outer/mini", &marked(500));
+    write_stream(&mut comp, "/outer/inner/big", &marked(9000));
+    comp.create_storage("/empty_dir").unwrap();
+    write_stream(&mut comp, "/keep", &[1; 10]);
+    comp.remove_storage_all("/outer").unwrap();
+    comp.remove_storage("/empty_dir").unwrap();
+    assert!(comp.deleted_entries().is_empty());
+    let data = raw_bytes(comp);
+    assert!(!contains(&data, MARKER));
+    for name in ["outer", "inner", "mini", "big", "empty_dir"] {
+        assert!(!contains(&data, &utf16(name)), "{}", name);
+    }
+}
+
+#[test]
+fn setting_applies_only_to_later_frees() {
+    let mut comp = create(Version::V3, false);
+    write_stream(&mut comp, "/early", &marked(5000));
+    comp.remove_stream("/early").unwrap();
+    comp.set_zero_freed_sectors(true);
+    write_stream(&mut comp, "/late", &[3; 100]);
+    comp.remove_stream("/late").unwrap();
+    // The sectors freed earlier aren't wiped until they are reused.
+    assert!(contains(&raw_bytes(comp), MARKER));
+}
+
+//===========================================================================//